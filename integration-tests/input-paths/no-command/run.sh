@@ -0,0 +1,14 @@
+#!/usr/bin/env bash
+set -euxo pipefail
+
+mkdir -p foo/bar
+echo 'Hello, World!' > foo/bar/baz.txt
+echo 'echo Hello, World!' > foo/qux.sh
+chmod +x foo/qux.sh
+"$TOAST" --read-local-cache false --write-local-cache false list_staged > output.txt
+"$TOAST" --read-local-cache false --write-local-cache false list_staged_reference \
+  > reference.txt
+diff output.txt reference.txt
+grep '\-rwxrwxrwx .* root root .* qux\.sh' output.txt
+rm output.txt reference.txt
+rm -r foo
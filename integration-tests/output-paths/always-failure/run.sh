@@ -0,0 +1,11 @@
+#!/usr/bin/env bash
+set -euxo pipefail
+
+if "$TOAST" --read-local-cache false --write-local-cache false; then
+  exit 1
+fi
+
+test ! -e foo.txt
+grep 'Building' build.log
+
+rm build.log
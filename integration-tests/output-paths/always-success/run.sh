@@ -0,0 +1,9 @@
+#!/usr/bin/env bash
+set -euxo pipefail
+
+"$TOAST" --read-local-cache false --write-local-cache false
+
+test -f foo.txt
+grep 'Building' build.log
+
+rm foo.txt build.log
@@ -2,7 +2,7 @@ use {
     crate::{
         failure,
         failure::Failure,
-        toastfile::{command, location, user, Task, Toastfile},
+        toastfile::{command, gpus, location, user, Task, Toastfile},
     },
     sha2::{Digest, Sha256},
     std::{
@@ -21,7 +21,10 @@ use std::os::unix::ffi::OsStrExt;
 use std::os::windows::ffi::OsStrExt;
 
 // Bump this if we need to invalidate all existing caches for some reason.
-const CACHE_VERSION: usize = 0;
+const CACHE_VERSION: usize = 1;
+
+// The tags of the images in the cache start with this [tag:cache_tag_prefix].
+pub const TAG_PREFIX: &str = "toast-";
 
 // This trait is implemented by things we can take a cryptographic hash of, such as strings and
 // paths.
@@ -101,7 +104,8 @@ pub fn hash_read<R: Read>(input: &mut R) -> Result<String, Failure> {
 }
 
 // Determine the image name for a task based on the name of the image for the previous task in the
-// schedule (or the base image, if this is the first task).
+// schedule (or the base image, if this is the first task). The `architecture` is that of the Docker
+// daemon. The `secrets` of the task are deliberately left out [ref:secrets_nand_commit].
 pub fn image_name(
     previous_image: &str,
     docker_repo: &str,
@@ -109,19 +113,32 @@ pub fn image_name(
     task: &Task,
     input_files_hash: &str,
     environment: &HashMap<String, String>,
+    architecture: &str,
 ) -> String {
     // Compute the command for this task.
     let command = command(toastfile, task);
 
-    // If there are no environment variables, no input paths, and no command to run, we can just use
+    // If there are no environment variables, no input paths, and no commands to run, we can just use
     // the image from the previous task.
-    if task.environment.is_empty() && task.input_paths.is_empty() && command.is_empty() {
+    if task.environment.is_empty()
+        && task.input_paths.is_empty()
+        && command.is_empty()
+        && task.exec_after_start.is_empty()
+    {
         return previous_image.to_owned();
     }
 
     // Start with a hash of the cache version.
     let mut cache_key: String = format!("{CACHE_VERSION}").crypto_hash();
 
+    // Incorporate the architecture, unless the task opted out. Without this, machines with different
+    // architectures would share cache entries, since a multi-architecture base image has the same
+    // name on all of them. A task which opts out can still get a different key on each architecture
+    // from the previous image, if that image came from a task which didn't opt out.
+    if !task.arch_independent_cache {
+        cache_key = combine(&cache_key, architecture);
+    }
+
     // Incorporate the previous image.
     cache_key = combine(&cache_key, previous_image);
 
@@ -150,24 +167,157 @@ pub fn image_name(
     // Incorporate the command.
     cache_key = combine(&cache_key, &command);
 
+    // Incorporate the commands run after the container starts, if there are any. Skipping this when
+    // there are none keeps the cache keys of existing tasks unchanged.
+    for exec_command in &task.exec_after_start {
+        cache_key = combine(&cache_key, exec_command);
+    }
+
+    // Incorporate the GPU request, if there is one, since the outputs may depend on the hardware
+    // (e.g., compiled kernels). Skipping this when there is none keeps the cache keys of existing
+    // tasks unchanged.
+    if let Some(gpus) = gpus(task) {
+        cache_key = combine(&cache_key, &gpus);
+    }
+
     // We add this "toast-" prefix because Docker has a rule that tags cannot be 64-byte hexadecimal
     // strings. See this for more details: https://github.com/moby/moby/issues/20972
-    format!("{docker_repo}:toast-{cache_key}")
+    format!("{docker_repo}:{TAG_PREFIX}{cache_key}")
+}
+
+// Combine a list of things into a single hash. The guarantees are the same as those of `combine`,
+// but the order of the list matters.
+fn combine_list<T: CryptoHash>(items: &[T]) -> String {
+    items
+        .iter()
+        .fold(String::new(), |acc, item| combine(&acc, item))
+}
+
+// Compute a hash of the definition of a task with the defaults applied, so tools can tell whether a
+// task changed without understanding YAML. Unlike the cache key, this doesn't depend on the files,
+// the environment, or the other tasks. It only changes when `CACHE_VERSION` does or when one of the
+// following changes, combined in this order:
+//   1. The location
+//   2. The user
+//   3. The command, including the command prefix
+//   4. The names of the environment variables (in sorted order) and their defaults
+//   5. The `input_paths`
+//   6. The `excluded_input_paths`
+//   7. The `output_paths`
+//   8. The `output_paths_on_failure`
+//   9. The `output_paths_always`
+//  10. The `mount_paths` (as `host_path:container_path`)
+//  11. Whether the mounts are read-only
+//  12. The `ports` (as the user wrote them)
+// Fields must only be added to the end of this list, so the existing hashes remain stable.
+pub fn task_hash(toastfile: &Toastfile, task: &Task) -> String {
+    // Start with a hash of the cache version.
+    let mut hash: String = format!("{CACHE_VERSION}").crypto_hash();
+
+    // Incorporate the location, user, and command.
+    hash = combine(&hash, &location(toastfile, task));
+    hash = combine(&hash, &user(toastfile, task));
+    hash = combine(&hash, &command(toastfile, task));
+
+    // Incorporate the environment variables. A variable without a default is distinguished from one
+    // with an empty default.
+    let mut environment_hash = String::new();
+    let mut variables = task.environment.keys().collect::<Vec<_>>();
+    variables.sort();
+    for variable in variables {
+        environment_hash = combine(&environment_hash, variable);
+        environment_hash = match &task.environment[variable] {
+            Some(default) => combine(&combine(&environment_hash, "default"), default),
+            None => combine(&environment_hash, "required"),
+        };
+    }
+    hash = combine(&hash, &environment_hash);
+
+    // Incorporate the paths.
+    for paths in [
+        &task.input_paths,
+        &task.excluded_input_paths,
+        &task.output_paths,
+        &task.output_paths_on_failure,
+        &task.output_paths_always,
+    ] {
+        hash = combine(&hash, &combine_list(paths));
+    }
+
+    // Incorporate whether ignored input files are skipped. This is only done when they are, so that
+    // the hashes of existing tasks don't change.
+    if task.respect_gitignore {
+        hash = combine(&hash, "respect_gitignore");
+    }
+
+    // Incorporate the mounts. The host paths are hashed as strings so the hash doesn't depend on the
+    // platform.
+    hash = combine(
+        &hash,
+        &combine_list(
+            &task
+                .mount_paths
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+        ),
+    );
+    hash = combine(&hash, &task.mount_readonly.to_string());
+
+    // Incorporate the volumes. This is only done when there are any, so that the hashes of existing
+    // tasks don't change.
+    if !task.volumes.is_empty() {
+        hash = combine(
+            &hash,
+            &combine_list(
+                &task
+                    .volumes
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>(),
+            ),
+        );
+    }
+
+    // Incorporate whether the root filesystem is read-only. This is only done when it is, so that the
+    // hashes of existing tasks don't change.
+    if task.read_only_rootfs {
+        hash = combine(&hash, "read_only_rootfs");
+    }
+
+    // Incorporate the ports, as the user wrote them.
+    combine(
+        &hash,
+        &combine_list(
+            &task
+                .ports
+                .iter()
+                .map(|port| port.spec.clone())
+                .collect::<Vec<_>>(),
+        ),
+    )
 }
 
 #[cfg(test)]
 mod tests {
     use {
         crate::{
-            cache::{combine, hash_read, image_name, CryptoHash},
+            cache::{combine, hash_read, image_name, task_hash, CryptoHash},
+            tar::UNHASHED,
+            toastfile,
             toastfile::{Task, Toastfile, DEFAULT_LOCATION, DEFAULT_USER},
         },
-        std::{collections::HashMap, path::Path},
+        std::{
+            collections::{BTreeMap, HashMap},
+            fs,
+            path::Path,
+        },
+        tempfile::tempdir,
         typed_path::UnixPath,
     };
 
     fn toastfile_with_task(foo_task: Task) -> Toastfile {
-        let mut tasks = HashMap::new();
+        let mut tasks = BTreeMap::new();
         tasks.insert("foo".to_owned(), foo_task);
 
         Toastfile {
@@ -176,12 +326,18 @@ mod tests {
             location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
             command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
             tasks,
         }
     }
 
     fn toastfile_with_tasks(foo_task: Task, bar_task: Task) -> Toastfile {
-        let mut tasks = HashMap::new();
+        let mut tasks = BTreeMap::new();
         tasks.insert("foo".to_owned(), foo_task);
         tasks.insert("bar".to_owned(), bar_task);
 
@@ -191,6 +347,12 @@ mod tests {
             location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
             command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
             tasks,
         }
     }
@@ -282,19 +444,47 @@ mod tests {
             description: None,
             dependencies: vec![],
             cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
             environment,
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
             input_paths: vec![],
             excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
             mount_paths: vec![],
             mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
             ports: vec![],
+            gpus: None,
+            image: None,
             location: None,
             user: None,
             command: String::new(),
+            command_file: None,
             command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
             extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         };
 
         let toastfile = toastfile_with_task(task);
@@ -312,6 +502,7 @@ mod tests {
                 &toastfile.tasks["foo"],
                 input_files_hash,
                 &full_environment,
+                "amd64",
             ),
         );
     }
@@ -328,19 +519,47 @@ mod tests {
             description: None,
             dependencies: vec![],
             cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
             environment,
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
             input_paths: vec![UnixPath::new("flob").to_owned()],
             excluded_input_paths: vec![UnixPath::new("thud").to_owned()],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
             mount_paths: vec![],
             mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
             ports: vec![],
+            gpus: None,
+            image: None,
             location: None,
             user: None,
             command: "echo wibble".to_owned(),
+            command_file: None,
             command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
             extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         };
 
         let toastfile = toastfile_with_task(task);
@@ -358,6 +577,7 @@ mod tests {
                 &toastfile.tasks["foo"],
                 input_files_hash,
                 &full_environment,
+                "amd64",
             ),
             image_name(
                 previous_image,
@@ -366,6 +586,7 @@ mod tests {
                 &toastfile.tasks["foo"],
                 input_files_hash,
                 &full_environment,
+                "amd64",
             ),
         );
     }
@@ -380,19 +601,47 @@ mod tests {
             description: None,
             dependencies: vec![],
             cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
             environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
             input_paths: vec![],
             excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
             mount_paths: vec![],
             mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
             ports: vec![],
+            gpus: None,
+            image: None,
             location: None,
             user: None,
             command: "echo wibble".to_owned(),
+            command_file: None,
             command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
             extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         };
 
         let toastfile = toastfile_with_task(task);
@@ -409,6 +658,7 @@ mod tests {
                 &toastfile.tasks["foo"],
                 input_files_hash,
                 &full_environment,
+                "amd64",
             ),
             image_name(
                 previous_image2,
@@ -417,11 +667,13 @@ mod tests {
                 &toastfile.tasks["foo"],
                 input_files_hash,
                 &full_environment,
+                "amd64",
             ),
         );
     }
 
     #[test]
+    #[allow(clippy::too_many_lines)]
     fn image_name_environment_order() {
         let previous_image = "corge";
         let docker_repo = "toast";
@@ -438,38 +690,94 @@ mod tests {
             description: None,
             dependencies: vec![],
             cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
             environment: environment1,
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
             input_paths: vec![],
             excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
             mount_paths: vec![],
             mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
             ports: vec![],
+            gpus: None,
+            image: None,
             location: None,
             user: None,
             command: "echo wibble".to_owned(),
+            command_file: None,
             command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
             extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         };
 
         let task2 = Task {
             description: None,
             dependencies: vec![],
             cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
             environment: environment2,
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
             input_paths: vec![],
             excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
             mount_paths: vec![],
             mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
             ports: vec![],
+            gpus: None,
+            image: None,
             location: None,
             user: None,
             command: "echo wibble".to_owned(),
+            command_file: None,
             command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
             extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         };
 
         let toastfile = toastfile_with_tasks(task1, task2);
@@ -488,6 +796,7 @@ mod tests {
                 &toastfile.tasks["foo"],
                 input_files_hash,
                 &full_environment,
+                "amd64",
             ),
             image_name(
                 previous_image,
@@ -496,11 +805,13 @@ mod tests {
                 &toastfile.tasks["bar"],
                 input_files_hash,
                 &full_environment,
+                "amd64",
             ),
         );
     }
 
     #[test]
+    #[allow(clippy::too_many_lines)]
     fn image_name_environment_keys() {
         let previous_image = "corge";
         let docker_repo = "toast";
@@ -515,38 +826,94 @@ mod tests {
             description: None,
             dependencies: vec![],
             cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
             environment: environment1,
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
             input_paths: vec![],
             excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
             mount_paths: vec![],
             mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
             ports: vec![],
+            gpus: None,
+            image: None,
             location: None,
             user: None,
             command: "echo wibble".to_owned(),
+            command_file: None,
             command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
             extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         };
 
         let task2 = Task {
             description: None,
             dependencies: vec![],
             cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
             environment: environment2,
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
             input_paths: vec![],
             excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
             mount_paths: vec![],
             mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
             ports: vec![],
+            gpus: None,
+            image: None,
             location: None,
             user: None,
             command: "echo wibble".to_owned(),
+            command_file: None,
             command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
             extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         };
 
         let toastfile = toastfile_with_tasks(task1, task2);
@@ -565,6 +932,7 @@ mod tests {
                 &toastfile.tasks["foo"],
                 input_files_hash,
                 &full_environment,
+                "amd64",
             ),
             image_name(
                 previous_image,
@@ -573,6 +941,7 @@ mod tests {
                 &toastfile.tasks["bar"],
                 input_files_hash,
                 &full_environment,
+                "amd64",
             ),
         );
     }
@@ -589,19 +958,47 @@ mod tests {
             description: None,
             dependencies: vec![],
             cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
             environment,
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
             input_paths: vec![],
             excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
             mount_paths: vec![],
             mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
             ports: vec![],
+            gpus: None,
+            image: None,
             location: None,
             user: None,
             command: "echo wibble".to_owned(),
+            command_file: None,
             command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
             extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         };
 
         let toastfile = toastfile_with_task(task);
@@ -621,6 +1018,7 @@ mod tests {
                 &toastfile.tasks["foo"],
                 input_files_hash,
                 &full_environment1,
+                "amd64",
             ),
             image_name(
                 previous_image,
@@ -629,6 +1027,7 @@ mod tests {
                 &toastfile.tasks["foo"],
                 input_files_hash,
                 &full_environment2,
+                "amd64",
             ),
         );
     }
@@ -642,19 +1041,47 @@ mod tests {
             description: None,
             dependencies: vec![],
             cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
             environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
             input_paths: vec![UnixPath::new("flob").to_owned()],
             excluded_input_paths: vec![UnixPath::new("thud").to_owned()],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
             mount_paths: vec![],
             mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
             ports: vec![],
+            gpus: None,
+            image: None,
             location: None,
             user: None,
             command: "echo wibble".to_owned(),
+            command_file: None,
             command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
             extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         };
 
         let toastfile = toastfile_with_task(task);
@@ -672,6 +1099,7 @@ mod tests {
                 &toastfile.tasks["foo"],
                 input_files_hash1,
                 &full_environment,
+                "amd64",
             ),
             image_name(
                 previous_image,
@@ -680,11 +1108,96 @@ mod tests {
                 &toastfile.tasks["foo"],
                 input_files_hash2,
                 &full_environment,
+                "amd64",
             ),
         );
     }
 
     #[test]
+    fn image_name_unhashed_input_files() {
+        let previous_image = "corge";
+        let docker_repo = "toast";
+
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: "echo foo".to_owned(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let toastfile = toastfile_with_task(task);
+
+        let full_environment = HashMap::new();
+
+        // The image name for a task whose inputs weren't hashed must not collide with a real cache
+        // entry, even for a task with no inputs.
+        let unhashed = image_name(
+            previous_image,
+            docker_repo,
+            &toastfile,
+            &toastfile.tasks["foo"],
+            UNHASHED,
+            &full_environment,
+            "amd64",
+        );
+        for input_files_hash in ["", &"grault".crypto_hash()] {
+            assert_ne!(
+                unhashed,
+                image_name(
+                    previous_image,
+                    docker_repo,
+                    &toastfile,
+                    &toastfile.tasks["foo"],
+                    input_files_hash,
+                    &full_environment,
+                    "amd64",
+                ),
+            );
+        }
+    }
+
+    #[test]
+    #[allow(clippy::too_many_lines)]
     fn image_name_location() {
         let previous_image = "corge";
         let docker_repo = "toast";
@@ -693,38 +1206,94 @@ mod tests {
             description: None,
             dependencies: vec![],
             cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
             environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
             input_paths: vec![],
             excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
             mount_paths: vec![],
             mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
             ports: vec![],
+            gpus: None,
+            image: None,
             location: Some(UnixPath::new("/foo").to_owned()),
             user: None,
             command: "echo wibble".to_owned(),
+            command_file: None,
             command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
             extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         };
 
         let task2 = Task {
             description: None,
             dependencies: vec![],
             cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
             environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
             input_paths: vec![],
             excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
             mount_paths: vec![],
             mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
             ports: vec![],
+            gpus: None,
+            image: None,
             location: Some(UnixPath::new("/bar").to_owned()),
             user: None,
             command: "echo wibble".to_owned(),
+            command_file: None,
             command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
             extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         };
 
         let toastfile = toastfile_with_tasks(task1, task2);
@@ -741,6 +1310,7 @@ mod tests {
                 &toastfile.tasks["foo"],
                 input_files_hash,
                 &full_environment,
+                "amd64",
             ),
             image_name(
                 previous_image,
@@ -749,11 +1319,13 @@ mod tests {
                 &toastfile.tasks["bar"],
                 input_files_hash,
                 &full_environment,
+                "amd64",
             ),
         );
     }
 
     #[test]
+    #[allow(clippy::too_many_lines)]
     fn image_name_user() {
         let previous_image = "corge";
         let docker_repo = "toast";
@@ -762,38 +1334,94 @@ mod tests {
             description: None,
             dependencies: vec![],
             cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
             environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
             input_paths: vec![],
             excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
             mount_paths: vec![],
             mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
             ports: vec![],
+            gpus: None,
+            image: None,
             location: None,
             user: Some("foo".to_owned()),
             command: "echo wibble".to_owned(),
+            command_file: None,
             command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
             extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         };
 
         let task2 = Task {
             description: None,
             dependencies: vec![],
             cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
             environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
             input_paths: vec![],
             excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
             mount_paths: vec![],
             mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
             ports: vec![],
+            gpus: None,
+            image: None,
             location: None,
             user: Some("bar".to_owned()),
             command: "echo wibble".to_owned(),
+            command_file: None,
             command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
             extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         };
 
         let toastfile = toastfile_with_tasks(task1, task2);
@@ -810,6 +1438,7 @@ mod tests {
                 &toastfile.tasks["foo"],
                 input_files_hash,
                 &full_environment,
+                "amd64",
             ),
             image_name(
                 previous_image,
@@ -818,11 +1447,13 @@ mod tests {
                 &toastfile.tasks["bar"],
                 input_files_hash,
                 &full_environment,
+                "amd64",
             ),
         );
     }
 
     #[test]
+    #[allow(clippy::too_many_lines)]
     fn image_name_command() {
         let previous_image = "corge";
         let docker_repo = "toast";
@@ -831,38 +1462,94 @@ mod tests {
             description: None,
             dependencies: vec![],
             cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
             environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
             input_paths: vec![],
             excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
             mount_paths: vec![],
             mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
             ports: vec![],
+            gpus: None,
+            image: None,
             location: None,
             user: None,
             command: "echo foo".to_owned(),
+            command_file: None,
             command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
             extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         };
 
         let task2 = Task {
             description: None,
             dependencies: vec![],
             cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
             environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
             input_paths: vec![],
             excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
             mount_paths: vec![],
             mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
             ports: vec![],
+            gpus: None,
+            image: None,
             location: None,
             user: None,
             command: "echo bar".to_owned(),
+            command_file: None,
             command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
             extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         };
 
         let toastfile = toastfile_with_tasks(task1, task2);
@@ -879,6 +1566,7 @@ mod tests {
                 &toastfile.tasks["foo"],
                 input_files_hash,
                 &full_environment,
+                "amd64",
             ),
             image_name(
                 previous_image,
@@ -887,7 +1575,693 @@ mod tests {
                 &toastfile.tasks["bar"],
                 input_files_hash,
                 &full_environment,
+                "amd64",
             ),
         );
     }
+
+    #[test]
+    #[allow(clippy::too_many_lines)]
+    fn image_name_exec_after_start() {
+        let previous_image = "corge";
+        let docker_repo = "toast";
+
+        let task1 = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: "echo foo".to_owned(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec!["echo bar".to_owned()],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let task2 = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: "echo foo".to_owned(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec!["echo baz".to_owned()],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let toastfile = toastfile_with_tasks(task1, task2);
+
+        let input_files_hash = "grault";
+
+        let full_environment = HashMap::new();
+
+        assert_ne!(
+            image_name(
+                previous_image,
+                docker_repo,
+                &toastfile,
+                &toastfile.tasks["foo"],
+                input_files_hash,
+                &full_environment,
+                "amd64",
+            ),
+            image_name(
+                previous_image,
+                docker_repo,
+                &toastfile,
+                &toastfile.tasks["bar"],
+                input_files_hash,
+                &full_environment,
+                "amd64",
+            ),
+        );
+    }
+
+    #[test]
+    #[allow(clippy::too_many_lines)]
+    fn image_name_gpus() {
+        let previous_image = "corge";
+        let docker_repo = "toast";
+
+        let task1 = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: "echo foo".to_owned(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let task2 = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: Some("all".to_owned()),
+            image: None,
+            location: None,
+            user: None,
+            command: "echo foo".to_owned(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let toastfile = toastfile_with_tasks(task1, task2);
+
+        let input_files_hash = "grault";
+
+        let full_environment = HashMap::new();
+
+        assert_ne!(
+            image_name(
+                previous_image,
+                docker_repo,
+                &toastfile,
+                &toastfile.tasks["foo"],
+                input_files_hash,
+                &full_environment,
+                "amd64",
+            ),
+            image_name(
+                previous_image,
+                docker_repo,
+                &toastfile,
+                &toastfile.tasks["bar"],
+                input_files_hash,
+                &full_environment,
+                "amd64",
+            ),
+        );
+    }
+
+    #[test]
+    fn image_name_architecture() {
+        let previous_image = "corge";
+        let docker_repo = "toast";
+
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: "echo foo".to_owned(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let toastfile = toastfile_with_task(task);
+
+        let input_files_hash = "grault";
+
+        let full_environment = HashMap::new();
+
+        assert_ne!(
+            image_name(
+                previous_image,
+                docker_repo,
+                &toastfile,
+                &toastfile.tasks["foo"],
+                input_files_hash,
+                &full_environment,
+                "amd64",
+            ),
+            image_name(
+                previous_image,
+                docker_repo,
+                &toastfile,
+                &toastfile.tasks["foo"],
+                input_files_hash,
+                &full_environment,
+                "arm64",
+            ),
+        );
+    }
+
+    #[test]
+    fn image_name_arch_independent_cache() {
+        let previous_image = "corge";
+        let docker_repo = "toast";
+
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: true,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: "echo foo".to_owned(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let toastfile = toastfile_with_task(task);
+
+        let input_files_hash = "grault";
+
+        let full_environment = HashMap::new();
+
+        assert_eq!(
+            image_name(
+                previous_image,
+                docker_repo,
+                &toastfile,
+                &toastfile.tasks["foo"],
+                input_files_hash,
+                &full_environment,
+                "amd64",
+            ),
+            image_name(
+                previous_image,
+                docker_repo,
+                &toastfile,
+                &toastfile.tasks["foo"],
+                input_files_hash,
+                &full_environment,
+                "arm64",
+            ),
+        );
+    }
+
+    // A toastfile with a task that uses most of the fields covered by `task_hash`
+    const TASK_HASH_TOASTFILE: &str = r"
+image: encom:os-12
+command_prefix: set -eu
+tasks:
+  foo:
+    environment:
+      AMBIENCE: null
+      BAR: baz
+    input_paths:
+      - src
+    excluded_input_paths:
+      - src/target
+    output_paths:
+      - out
+    location: /code
+    user: flynn
+    command: make
+";
+
+    #[test]
+    fn image_name_command_prefix() {
+        let toastfile = toastfile::parse(TASK_HASH_TOASTFILE).unwrap();
+        let mut top_level_prefix = toastfile::parse(TASK_HASH_TOASTFILE).unwrap();
+        top_level_prefix.command_prefix = "set -eux".to_owned();
+        let mut task_prefix = toastfile::parse(TASK_HASH_TOASTFILE).unwrap();
+        task_prefix.tasks.get_mut("foo").unwrap().command_prefix = Some("set -eux".to_owned());
+
+        let full_environment = HashMap::from([
+            ("AMBIENCE".to_owned(), "loud".to_owned()),
+            ("BAR".to_owned(), "baz".to_owned()),
+        ]);
+        let name = |toastfile: &Toastfile| {
+            image_name(
+                "corge",
+                "toast",
+                toastfile,
+                &toastfile.tasks["foo"],
+                "grault",
+                &full_environment,
+                "amd64",
+            )
+        };
+
+        // The cache key covers the command as it's actually run, prefix included.
+        assert_ne!(name(&toastfile), name(&top_level_prefix));
+        assert_ne!(name(&toastfile), name(&task_prefix));
+        assert_eq!(name(&top_level_prefix), name(&task_prefix));
+    }
+
+    #[test]
+    fn task_hash_pinned() {
+        let toastfile = toastfile::parse(TASK_HASH_TOASTFILE).unwrap();
+
+        // If this changes, the hashes of existing tasks have changed. That should only happen when
+        // `CACHE_VERSION` is bumped.
+        assert_eq!(
+            task_hash(&toastfile, &toastfile.tasks["foo"]),
+            "5ab10c94efaed3a49dec435e0b47596579354107c6e2abb6ea779651bcb15967",
+        );
+    }
+
+    #[test]
+    fn task_hash_formatting_insensitive() {
+        let reformatted = r"
+tasks:
+  foo:
+    command: 'make'
+    user: flynn
+    location: '/code'
+    output_paths: [out]
+    excluded_input_paths: [src/target]
+    input_paths: [src]
+    environment: { BAR: baz, AMBIENCE: ~ }
+command_prefix: 'set -eu'
+image: encom:os-12
+";
+        let toastfile = toastfile::parse(TASK_HASH_TOASTFILE).unwrap();
+        let reformatted_toastfile = toastfile::parse(reformatted).unwrap();
+
+        assert_eq!(
+            task_hash(&toastfile, &toastfile.tasks["foo"]),
+            task_hash(&reformatted_toastfile, &reformatted_toastfile.tasks["foo"]),
+        );
+    }
+
+    #[test]
+    fn task_hash_applies_defaults() {
+        let explicit = toastfile::parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    location: /scratch
+    user: root
+",
+        )
+        .unwrap();
+        let implicit = toastfile::parse(
+            r"
+image: encom:os-12
+tasks:
+  foo: {}
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            task_hash(&explicit, &explicit.tasks["foo"]),
+            task_hash(&implicit, &implicit.tasks["foo"]),
+        );
+    }
+
+    #[test]
+    fn task_hash_command_prefix() {
+        let toastfile = toastfile::parse(TASK_HASH_TOASTFILE).unwrap();
+        let mut other_toastfile = toastfile::parse(TASK_HASH_TOASTFILE).unwrap();
+        other_toastfile.command_prefix = "set -euo pipefail".to_owned();
+
+        assert_ne!(
+            task_hash(&toastfile, &toastfile.tasks["foo"]),
+            task_hash(&other_toastfile, &other_toastfile.tasks["foo"]),
+        );
+    }
+
+    #[test]
+    fn task_hash_environment_default() {
+        let toastfile = toastfile::parse(TASK_HASH_TOASTFILE).unwrap();
+        let mut task = toastfile.tasks["foo"].clone();
+        task.environment
+            .insert("AMBIENCE".to_owned(), Some(String::new()));
+
+        assert_ne!(
+            task_hash(&toastfile, &toastfile.tasks["foo"]),
+            task_hash(&toastfile, &task),
+        );
+    }
+
+    #[test]
+    fn task_hash_shared_environment() {
+        let per_task = toastfile::parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    environment:
+      CI: 'true'
+      RUSTFLAGS: null
+    command: make
+",
+        )
+        .unwrap();
+        let shared = toastfile::parse(
+            r"
+image: encom:os-12
+environment:
+  CI: 'true'
+  RUSTFLAGS: null
+tasks:
+  foo:
+    command: make
+",
+        )
+        .unwrap();
+        let environment = HashMap::from([
+            ("CI".to_owned(), "true".to_owned()),
+            ("RUSTFLAGS".to_owned(), "-D warnings".to_owned()),
+        ]);
+
+        assert_eq!(
+            task_hash(&per_task, &per_task.tasks["foo"]),
+            task_hash(&shared, &shared.tasks["foo"]),
+        );
+        assert_eq!(
+            image_name(
+                "encom:os-12",
+                "toast",
+                &per_task,
+                &per_task.tasks["foo"],
+                "",
+                &environment,
+                "amd64",
+            ),
+            image_name(
+                "encom:os-12",
+                "toast",
+                &shared,
+                &shared.tasks["foo"],
+                "",
+                &environment,
+                "amd64",
+            ),
+        );
+    }
+
+    #[test]
+    fn task_hash_paths() {
+        let toastfile = toastfile::parse(TASK_HASH_TOASTFILE).unwrap();
+        let mut task = toastfile.tasks["foo"].clone();
+
+        // Moving a path from one list to another changes the hash.
+        task.output_paths_always = task.output_paths.clone();
+        task.output_paths.clear();
+
+        assert_ne!(
+            task_hash(&toastfile, &toastfile.tasks["foo"]),
+            task_hash(&toastfile, &task),
+        );
+    }
+
+    #[test]
+    fn task_hash_respect_gitignore() {
+        let toastfile = toastfile::parse(TASK_HASH_TOASTFILE).unwrap();
+        let mut task = toastfile.tasks["foo"].clone();
+        task.respect_gitignore = true;
+
+        assert_ne!(
+            task_hash(&toastfile, &toastfile.tasks["foo"]),
+            task_hash(&toastfile, &task),
+        );
+    }
+
+    #[test]
+    fn task_hash_read_only_rootfs() {
+        let toastfile = toastfile::parse(TASK_HASH_TOASTFILE).unwrap();
+        let mut task = toastfile.tasks["foo"].clone();
+        task.read_only_rootfs = true;
+
+        assert_ne!(
+            task_hash(&toastfile, &toastfile.tasks["foo"]),
+            task_hash(&toastfile, &task),
+        );
+    }
+
+    #[test]
+    fn task_hash_ignores_other_tasks() {
+        let toastfile = toastfile::parse(TASK_HASH_TOASTFILE).unwrap();
+        let mut other_toastfile = toastfile::parse(TASK_HASH_TOASTFILE).unwrap();
+        other_toastfile.image = "encom:os-13".to_owned();
+        let task = other_toastfile.tasks["foo"].clone();
+        other_toastfile.tasks.insert("bar".to_owned(), task);
+
+        assert_eq!(
+            task_hash(&toastfile, &toastfile.tasks["foo"]),
+            task_hash(&other_toastfile, &other_toastfile.tasks["foo"]),
+        );
+    }
+
+    #[test]
+    fn task_hash_command_file() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("build.sh"), "make").unwrap();
+        fs::write(dir.path().join("other.sh"), "make").unwrap();
+        fs::write(dir.path().join("test.sh"), "make test").unwrap();
+
+        let hash = |command_file: &str| {
+            let mut toastfile = toastfile::parse(&format!(
+                "image: encom:os-12\ntasks:\n  foo:\n    command_file: {command_file}\n",
+            ))
+            .unwrap();
+            toastfile::load_command_files(&mut toastfile, dir.path()).unwrap();
+            task_hash(&toastfile, &toastfile.tasks["foo"])
+        };
+
+        // The hash depends on the contents of the file, not its path.
+        assert_eq!(hash("build.sh"), hash("other.sh"));
+        assert_ne!(hash("build.sh"), hash("test.sh"));
+    }
 }
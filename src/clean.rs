@@ -0,0 +1,248 @@
+use {
+    crate::{failure, failure::Failure, format::CodeStr},
+    std::{
+        fs::{remove_dir_all, remove_file, rename, symlink_metadata},
+        path::{Path, PathBuf},
+        time::{SystemTime, UNIX_EPOCH},
+    },
+    typed_path::{unix::UnixComponent, UnixPath},
+};
+
+// Lexically resolve a relative path into its normal components, or return `None` if the path
+// escapes its base directory or refers to the base directory itself.
+fn contained_components(path: &UnixPath) -> Option<Vec<String>> {
+    let mut components = vec![];
+
+    for component in path.components() {
+        match component {
+            UnixComponent::Normal(name) => {
+                components.push(String::from_utf8_lossy(name).into_owned());
+            }
+            UnixComponent::ParentDir => {
+                components.pop()?;
+            }
+            UnixComponent::CurDir => {}
+            UnixComponent::RootDir => return None,
+        }
+    }
+
+    if components.is_empty() {
+        None
+    } else {
+        Some(components)
+    }
+}
+
+// Determine the host path that would be removed to clean up an output path, making sure it lies
+// strictly within `base_dir`. Every intermediate directory is checked to ensure it's not a symbolic
+// link, since that could cause a path outside `base_dir` to be removed. The final component may be
+// a symbolic link, since removing it only removes the link.
+fn cleanup_target(base_dir: &Path, path: &UnixPath) -> Result<PathBuf, Failure> {
+    let components = contained_components(path).ok_or_else(|| {
+        Failure::User(
+            format!(
+                "Refusing to clean output path {}, since it's not contained in {}.",
+                path.to_string_lossy().code_str(),
+                base_dir.to_string_lossy().code_str(),
+            ),
+            None,
+        )
+    })?;
+
+    let mut target = base_dir.to_owned();
+    for (i, component) in components.iter().enumerate() {
+        target.push(component);
+
+        if i + 1 < components.len() {
+            if let Ok(metadata) = symlink_metadata(&target) {
+                if metadata.file_type().is_symlink() {
+                    return Err(Failure::User(
+                        format!(
+                            "Refusing to clean output path {}, since {} is a symbolic link.",
+                            path.to_string_lossy().code_str(),
+                            target.to_string_lossy().code_str(),
+                        ),
+                        None,
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(target)
+}
+
+// Remove (or move aside, if `backup` is `true`) the host destinations of some output paths so the
+// host reflects exactly what the task produces. All paths are relative to `base_dir`.
+pub fn clean_output_paths<P: AsRef<UnixPath>>(
+    base_dir: &Path,
+    paths: &[P],
+    backup: bool,
+) -> Result<(), Failure> {
+    // Resolve all the targets before removing anything, so a bad path doesn't leave the cleanup
+    // half done.
+    let mut targets = vec![];
+    for path in paths {
+        let target = cleanup_target(base_dir, path.as_ref())?;
+        if symlink_metadata(&target).is_ok() {
+            targets.push(target);
+        }
+    }
+
+    // Tell the user what's about to be removed.
+    for target in &targets {
+        if backup {
+            info!(
+                "Moving aside previous output {}\u{2026}",
+                target.to_string_lossy().code_str(),
+            );
+        } else {
+            info!(
+                "Removing previous output {}\u{2026}",
+                target.to_string_lossy().code_str(),
+            );
+        }
+    }
+
+    // Remove or move aside each target.
+    for target in targets {
+        if backup {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs());
+            let mut backup_path = target.clone().into_os_string();
+            backup_path.push(format!(".toast-backup-{timestamp}"));
+
+            rename(&target, &backup_path).map_err(failure::system(format!(
+                "Unable to move {} to {}.",
+                target.to_string_lossy().code_str(),
+                backup_path.to_string_lossy().code_str(),
+            )))?;
+        } else if symlink_metadata(&target).is_ok_and(|metadata| metadata.is_dir()) {
+            remove_dir_all(&target).map_err(failure::system(format!(
+                "Unable to remove directory {}.",
+                target.to_string_lossy().code_str(),
+            )))?;
+        } else {
+            remove_file(&target).map_err(failure::system(format!(
+                "Unable to remove file {}.",
+                target.to_string_lossy().code_str(),
+            )))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::clean::{clean_output_paths, cleanup_target, contained_components},
+        std::fs::{create_dir, write},
+        tempfile::tempdir,
+        typed_path::UnixPath,
+    };
+
+    #[test]
+    fn contained_components_simple() {
+        assert_eq!(
+            contained_components(UnixPath::new("foo/bar")),
+            Some(vec!["foo".to_owned(), "bar".to_owned()]),
+        );
+    }
+
+    #[test]
+    fn contained_components_dots() {
+        assert_eq!(
+            contained_components(UnixPath::new("./foo/../bar/./baz")),
+            Some(vec!["bar".to_owned(), "baz".to_owned()]),
+        );
+    }
+
+    #[test]
+    fn contained_components_base_dir() {
+        assert_eq!(contained_components(UnixPath::new(".")), None);
+        assert_eq!(contained_components(UnixPath::new("foo/..")), None);
+    }
+
+    #[test]
+    fn contained_components_escape() {
+        assert_eq!(contained_components(UnixPath::new("../foo")), None);
+        assert_eq!(contained_components(UnixPath::new("foo/../../bar")), None);
+    }
+
+    #[test]
+    fn contained_components_absolute() {
+        assert_eq!(contained_components(UnixPath::new("/foo")), None);
+    }
+
+    #[test]
+    fn cleanup_target_ok() {
+        let dir = tempdir().unwrap();
+
+        assert_eq!(
+            cleanup_target(dir.path(), UnixPath::new("foo/bar")).unwrap(),
+            dir.path().join("foo").join("bar"),
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cleanup_target_symlink_escape() {
+        let outside = tempdir().unwrap();
+        let dir = tempdir().unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("foo")).unwrap();
+
+        assert!(cleanup_target(dir.path(), UnixPath::new("foo/bar")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cleanup_target_final_symlink() {
+        let outside = tempdir().unwrap();
+        let dir = tempdir().unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("foo")).unwrap();
+
+        assert!(cleanup_target(dir.path(), UnixPath::new("foo")).is_ok());
+    }
+
+    #[test]
+    fn clean_output_paths_remove() {
+        let dir = tempdir().unwrap();
+        create_dir(dir.path().join("foo")).unwrap();
+        write(dir.path().join("foo").join("bar.txt"), "bar").unwrap();
+        write(dir.path().join("baz.txt"), "baz").unwrap();
+
+        clean_output_paths(
+            dir.path(),
+            &[
+                UnixPath::new("foo"),
+                UnixPath::new("baz.txt"),
+                UnixPath::new("qux"),
+            ],
+            false,
+        )
+        .unwrap();
+
+        assert!(!dir.path().join("foo").exists());
+        assert!(!dir.path().join("baz.txt").exists());
+    }
+
+    #[test]
+    fn clean_output_paths_backup() {
+        let dir = tempdir().unwrap();
+        write(dir.path().join("foo.txt"), "foo").unwrap();
+
+        clean_output_paths(dir.path(), &[UnixPath::new("foo.txt")], true).unwrap();
+
+        assert!(!dir.path().join("foo.txt").exists());
+        assert_eq!(dir.path().read_dir().unwrap().count(), 1);
+    }
+
+    #[test]
+    fn clean_output_paths_escape() {
+        let dir = tempdir().unwrap();
+
+        assert!(clean_output_paths(dir.path(), &[UnixPath::new("..")], false).is_err());
+    }
+}
@@ -0,0 +1,268 @@
+use {
+    crate::{docker, failure::Failure, format::CodeStr},
+    std::sync::{atomic::AtomicBool, Arc, Mutex},
+};
+
+// Something Toast would normally get rid of when it's no longer needed
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Action {
+    StopContainer(String),
+    DeleteContainer(String),
+    DeleteImage(String),
+}
+
+impl Action {
+    // Describe the action for the user, e.g., "delete image toast/toast:abc".
+    fn describe(&self) -> String {
+        match self {
+            Self::StopContainer(container) => format!("stop container {}", container.code_str()),
+            Self::DeleteContainer(container) => {
+                format!("delete container {}", container.code_str())
+            }
+            Self::DeleteImage(image) => format!("delete image {}", image.code_str()),
+        }
+    }
+
+    // The command the user can run to perform the action themselves
+    fn command(&self, docker_cli: &str) -> String {
+        match self {
+            Self::StopContainer(container) => format!("{docker_cli} container stop {container}"),
+            Self::DeleteContainer(container) => {
+                format!("{docker_cli} container rm --force {container}")
+            }
+            Self::DeleteImage(image) => format!("{docker_cli} image rm --force {image}"),
+        }
+    }
+}
+
+// The function which actually performs an action. It can be replaced for testing.
+pub type Performer = dyn Fn(&Action, &Arc<AtomicBool>) -> Result<(), Failure> + Send + Sync;
+
+// This struct is responsible for deleting the containers and images that Toast creates, and for
+// stopping containers when the user interrupts Toast. With `--no-cleanup`, it only remembers what
+// it would have done, so the resources can be inspected after the run.
+pub struct Cleanup {
+    docker_cli: String,
+    enabled: bool,
+    perform: Box<Performer>,
+
+    // The actions which were skipped, along with the Docker CLI for each one. This is shared with
+    // the cleanups for other Docker CLIs, so everything left behind is reported together.
+    skipped: Arc<Mutex<Vec<(String, Action)>>>,
+}
+
+impl Cleanup {
+    // Clean up with the Docker CLI, unless `enabled` is `false`.
+    pub fn new(docker_cli: &str, enabled: bool) -> Self {
+        let perform_docker_cli = docker_cli.to_owned();
+
+        Self::with_performer(
+            docker_cli,
+            enabled,
+            Box::new(move |action, interrupted| match action {
+                Action::StopContainer(container) => {
+                    docker::stop_container(&perform_docker_cli, container, interrupted)
+                }
+                Action::DeleteContainer(container) => {
+                    docker::delete_container(&perform_docker_cli, container, interrupted)
+                }
+                Action::DeleteImage(image) => {
+                    docker::delete_image(&perform_docker_cli, image, interrupted)
+                }
+            }),
+        )
+    }
+
+    // Clean up with a custom function for performing the actions.
+    pub fn with_performer(docker_cli: &str, enabled: bool, perform: Box<Performer>) -> Self {
+        Self {
+            docker_cli: docker_cli.to_owned(),
+            enabled,
+            perform,
+            skipped: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    // Clean up with a different Docker CLI (e.g., for a task which overrides it).
+    pub fn for_docker_cli(self: &Arc<Self>, docker_cli: &str) -> Arc<Self> {
+        if docker_cli == self.docker_cli {
+            return self.clone();
+        }
+
+        let mut cleanup = Self::new(docker_cli, self.enabled);
+        cleanup.skipped = self.skipped.clone();
+        Arc::new(cleanup)
+    }
+
+    // Whether resources are actually being cleaned up
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // Stop a container.
+    pub fn stop_container(
+        &self,
+        container: &str,
+        interrupted: &Arc<AtomicBool>,
+    ) -> Result<(), Failure> {
+        self.run(Action::StopContainer(container.to_owned()), interrupted)
+    }
+
+    // Delete a container.
+    pub fn delete_container(
+        &self,
+        container: &str,
+        interrupted: &Arc<AtomicBool>,
+    ) -> Result<(), Failure> {
+        self.run(Action::DeleteContainer(container.to_owned()), interrupted)
+    }
+
+    // Delete an image.
+    pub fn delete_image(&self, image: &str, interrupted: &Arc<AtomicBool>) -> Result<(), Failure> {
+        self.run(Action::DeleteImage(image.to_owned()), interrupted)
+    }
+
+    // Perform an action, or just remember it if cleanup is disabled.
+    fn run(&self, action: Action, interrupted: &Arc<AtomicBool>) -> Result<(), Failure> {
+        if self.enabled {
+            (self.perform)(&action, interrupted)
+        } else {
+            debug!(
+                "Cleanup is disabled. Would have done: {}.",
+                action.describe()
+            );
+
+            // The `unwrap` will only fail if a panic already occurred.
+            self.skipped
+                .lock()
+                .unwrap()
+                .push((self.docker_cli.clone(), action));
+
+            Ok(())
+        }
+    }
+
+    // Tell the user what was left behind and how to get rid of it.
+    pub fn report(&self) {
+        // The `unwrap` will only fail if a panic already occurred.
+        let skipped = self.skipped.lock().unwrap();
+
+        // A container that would have been stopped is also listed for deletion, so there's no need
+        // to mention stopping it separately.
+        let leaked = skipped
+            .iter()
+            .filter(|(_, action)| !matches!(action, Action::StopContainer(_)))
+            .collect::<Vec<_>>();
+
+        if !leaked.is_empty() {
+            warn!("The following resources were not cleaned up:");
+            for (docker_cli, action) in leaked {
+                warn!(
+                    "  To {}, run: {}",
+                    action.describe(),
+                    action.command(docker_cli).code_str(),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::cleanup::{Action, Cleanup},
+        std::sync::{atomic::AtomicBool, Arc, Mutex},
+    };
+
+    // Construct a cleanup which records the actions it performs.
+    fn recording_cleanup(enabled: bool) -> (Cleanup, Arc<Mutex<Vec<Action>>>) {
+        let performed = Arc::new(Mutex::new(vec![]));
+        let recorder = performed.clone();
+
+        (
+            Cleanup::with_performer(
+                "docker",
+                enabled,
+                Box::new(move |action, _| {
+                    recorder.lock().unwrap().push(action.clone());
+                    Ok(())
+                }),
+            ),
+            performed,
+        )
+    }
+
+    #[test]
+    fn cleanup_enabled() {
+        let (cleanup, performed) = recording_cleanup(true);
+        let interrupted = Arc::new(AtomicBool::new(false));
+
+        cleanup.stop_container("foo", &interrupted).unwrap();
+        cleanup.delete_container("foo", &interrupted).unwrap();
+        cleanup.delete_image("toast:bar", &interrupted).unwrap();
+
+        assert_eq!(
+            *performed.lock().unwrap(),
+            vec![
+                Action::StopContainer("foo".to_owned()),
+                Action::DeleteContainer("foo".to_owned()),
+                Action::DeleteImage("toast:bar".to_owned()),
+            ],
+        );
+        assert!(cleanup.skipped.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn cleanup_disabled() {
+        let (cleanup, performed) = recording_cleanup(false);
+        let interrupted = Arc::new(AtomicBool::new(false));
+
+        cleanup.stop_container("foo", &interrupted).unwrap();
+        cleanup.delete_container("foo", &interrupted).unwrap();
+        cleanup.delete_image("toast:bar", &interrupted).unwrap();
+
+        assert!(performed.lock().unwrap().is_empty());
+        assert_eq!(cleanup.skipped.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn cleanup_for_docker_cli() {
+        let (cleanup, _) = recording_cleanup(false);
+        let cleanup = Arc::new(cleanup);
+        let interrupted = Arc::new(AtomicBool::new(false));
+
+        assert!(Arc::ptr_eq(&cleanup.for_docker_cli("docker"), &cleanup));
+
+        let podman_cleanup = cleanup.for_docker_cli("podman");
+        cleanup.delete_image("toast:bar", &interrupted).unwrap();
+        podman_cleanup
+            .delete_image("toast:baz", &interrupted)
+            .unwrap();
+
+        assert_eq!(
+            *cleanup.skipped.lock().unwrap(),
+            vec![
+                (
+                    "docker".to_owned(),
+                    Action::DeleteImage("toast:bar".to_owned())
+                ),
+                (
+                    "podman".to_owned(),
+                    Action::DeleteImage("toast:baz".to_owned())
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn action_command() {
+        assert_eq!(
+            Action::DeleteImage("toast:bar".to_owned()).command("podman"),
+            "podman image rm --force toast:bar",
+        );
+        assert_eq!(
+            Action::DeleteContainer("foo".to_owned()).command("docker"),
+            "docker container rm --force foo",
+        );
+    }
+}
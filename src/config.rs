@@ -1,11 +1,19 @@
 use {
-    crate::{failure, failure::Failure},
-    serde::Deserialize,
+    crate::{failure, failure::Failure, format::CodeStr, registry::LoginMode, units},
+    serde::{de::Error, Deserialize, Deserializer},
+    std::collections::HashMap,
 };
 
 pub const REPO_DEFAULT: &str = "toast";
 pub const EMPTY_CONFIG: &str = "{}";
 const DOCKER_CLI_DEFAULT: &str = "docker";
+const SSH_CLI_DEFAULT: &str = "ssh";
+const SSH_CACHE_TIMEOUT_DEFAULT: u64 = 30;
+const INPUT_SIZE_WARNING_BYTES_DEFAULT: u64 = 1_000_000_000;
+const INPUT_SIZE_WARNING_FILES_DEFAULT: u64 = 50_000;
+
+// The environment variable which tells the Docker CLI where to find its configuration
+const DOCKER_CONFIG_ENV_VAR: &str = "DOCKER_CONFIG";
 
 // A program configuration
 #[derive(Debug, Deserialize, Eq, PartialEq)]
@@ -15,9 +23,20 @@ pub struct Config {
     #[serde(default = "default_docker_cli")]
     pub docker_cli: String,
 
+    #[serde(default)]
+    pub docker_config_dir: Option<String>,
+
+    // Must not set `DOCKER_CONFIG` if `docker_config_dir` is set [ref:docker_env_valid]
+    #[serde(default)]
+    pub docker_env: HashMap<String, String>,
+
     #[serde(default = "default_docker_repo")]
     pub docker_repo: String,
 
+    // In seconds, where zero means not to wait
+    #[serde(default)]
+    pub wait_for_docker: u64,
+
     #[serde(default = "default_read_local_cache")]
     pub read_local_cache: bool,
 
@@ -29,6 +48,59 @@ pub struct Config {
 
     #[serde(default = "default_write_remote_cache")]
     pub write_remote_cache: bool,
+
+    #[serde(default)]
+    pub confirm_public_push: bool,
+
+    // Must only contain options [ref:extra_image_arguments_valid]
+    #[serde(default)]
+    pub extra_pull_arguments: Vec<String>,
+
+    // Must only contain options [ref:extra_image_arguments_valid]
+    #[serde(default)]
+    pub extra_push_arguments: Vec<String>,
+
+    #[serde(default)]
+    pub ssh_cache_host: Option<String>,
+
+    #[serde(default = "default_write_ssh_cache")]
+    pub write_ssh_cache: bool,
+
+    // In seconds
+    #[serde(default = "default_ssh_cache_timeout")]
+    pub ssh_cache_timeout: u64,
+
+    #[serde(default = "default_ssh_cli")]
+    pub ssh_cli: String,
+
+    #[serde(default)]
+    pub registry_login_command: Vec<String>,
+
+    #[serde(default)]
+    pub registry_login_mode: LoginMode,
+
+    // Must be present if `registry_login_mode` is `token` [ref:registry_user_for_token]
+    #[serde(default)]
+    pub registry_user: Option<String>,
+
+    #[serde(default = "default_input_size_warning_bytes")]
+    #[serde(deserialize_with = "deserialize_bytes")]
+    pub input_size_warning_bytes: u64,
+
+    #[serde(default = "default_input_size_warning_files")]
+    pub input_size_warning_files: u64,
+
+    #[serde(default)]
+    pub require_pinned_image: bool,
+
+    #[serde(default)]
+    pub resolve_image_digest: bool,
+
+    #[serde(default)]
+    pub prefix_output: bool,
+
+    #[serde(default)]
+    pub check_for_updates: bool,
 }
 
 fn default_docker_cli() -> String {
@@ -55,24 +127,178 @@ fn default_write_remote_cache() -> bool {
     false
 }
 
+fn default_write_ssh_cache() -> bool {
+    true
+}
+
+fn default_ssh_cache_timeout() -> u64 {
+    SSH_CACHE_TIMEOUT_DEFAULT
+}
+
+fn default_ssh_cli() -> String {
+    SSH_CLI_DEFAULT.to_owned()
+}
+
+fn default_input_size_warning_bytes() -> u64 {
+    INPUT_SIZE_WARNING_BYTES_DEFAULT
+}
+
+fn default_input_size_warning_files() -> u64 {
+    INPUT_SIZE_WARNING_FILES_DEFAULT
+}
+
+// A size can be given as a number of bytes or as a string with a unit (e.g., `5GB`).
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Size {
+    Bytes(u64),
+    WithUnit(String),
+}
+
+fn deserialize_bytes<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Size::deserialize(deserializer)? {
+        Size::Bytes(bytes) => Ok(bytes),
+        Size::WithUnit(size) => units::parse_bytes(&size, "The size").map_err(D::Error::custom),
+    }
+}
+
 // Parse a program configuration.
 pub fn parse(config: &str) -> Result<Config, Failure> {
-    serde_yaml::from_str(config).map_err(failure::user("Syntax error."))
+    let config: Config = serde_yaml::from_str(config).map_err(failure::user("Syntax error."))?;
+
+    // Make sure the extra arguments for pulling and pushing images are valid.
+    check_extra_image_arguments("extra_pull_arguments", &config.extra_pull_arguments)?;
+    check_extra_image_arguments("extra_push_arguments", &config.extra_push_arguments)?;
+
+    // Make sure `docker_config_dir` and `docker_env` don't disagree about where the Docker
+    // configuration lives [tag:docker_env_valid].
+    if config.docker_config_dir.is_some() && config.docker_env.contains_key(DOCKER_CONFIG_ENV_VAR) {
+        return Err(Failure::User(
+            format!(
+                "{} must not set {} when {} is set.",
+                "docker_env".code_str(),
+                DOCKER_CONFIG_ENV_VAR.code_str(),
+                "docker_config_dir".code_str(),
+            ),
+            None,
+        ));
+    }
+
+    // Make sure there is a user to log in as if the login command prints a token
+    // [tag:registry_user_for_token].
+    if !config.registry_login_command.is_empty()
+        && config.registry_login_mode == LoginMode::Token
+        && config.registry_user.is_none()
+    {
+        return Err(Failure::User(
+            format!(
+                "{} must be set when {} is {}.",
+                "registry_user".code_str(),
+                "registry_login_mode".code_str(),
+                "token".code_str(),
+            ),
+            None,
+        ));
+    }
+
+    Ok(config)
+}
+
+// Compute the environment variables to set for every Docker CLI process, sorted by name.
+pub fn docker_environment(config: &Config) -> Vec<(String, String)> {
+    let mut environment = config
+        .docker_env
+        .iter()
+        .map(|(variable, value)| (variable.clone(), value.clone()))
+        .collect::<Vec<_>>();
+
+    if let Some(docker_config_dir) = &config.docker_config_dir {
+        // [ref:docker_env_valid]
+        environment.push((DOCKER_CONFIG_ENV_VAR.to_owned(), docker_config_dir.clone()));
+    }
+
+    environment.sort();
+    environment
+}
+
+// Check that extra arguments for `docker image pull` or `docker image push` only consist of options
+// (and their values), since Toast provides the image itself [tag:extra_image_arguments_valid].
+fn check_extra_image_arguments(field: &str, args: &[String]) -> Result<(), Failure> {
+    let mut expecting_value = false;
+
+    for arg in args {
+        if matches!(arg.as_ref(), "-h" | "--help" | "--") {
+            return Err(Failure::User(
+                format!("{} must not contain {}.", field.code_str(), arg.code_str()),
+                None,
+            ));
+        }
+
+        if arg.starts_with('-') {
+            // An option without an inline value (e.g., `--platform`) may be followed by its value.
+            expecting_value = !arg.contains('=');
+        } else if expecting_value {
+            // This is the value of the previous option.
+            expecting_value = false;
+        } else {
+            return Err(Failure::User(
+                format!(
+                    "{} must only contain options, but {} looks like an image. Toast provides \
+                     the image automatically.",
+                    field.code_str(),
+                    arg.code_str(),
+                ),
+                None,
+            ));
+        }
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::config::{parse, Config, DOCKER_CLI_DEFAULT, EMPTY_CONFIG};
+    use {
+        crate::config::{
+            docker_environment, parse, Config, DOCKER_CLI_DEFAULT, EMPTY_CONFIG,
+            INPUT_SIZE_WARNING_BYTES_DEFAULT, INPUT_SIZE_WARNING_FILES_DEFAULT,
+            SSH_CACHE_TIMEOUT_DEFAULT, SSH_CLI_DEFAULT,
+        },
+        crate::registry::LoginMode,
+        std::collections::HashMap,
+    };
 
     #[test]
     fn parse_empty() {
         let result = Config {
             docker_cli: DOCKER_CLI_DEFAULT.to_owned(),
+            docker_config_dir: None,
+            docker_env: HashMap::new(),
             docker_repo: "toast".to_owned(),
+            wait_for_docker: 0,
             read_local_cache: true,
             write_local_cache: true,
             read_remote_cache: false,
             write_remote_cache: false,
+            confirm_public_push: false,
+            extra_pull_arguments: vec![],
+            extra_push_arguments: vec![],
+            ssh_cache_host: None,
+            write_ssh_cache: true,
+            ssh_cache_timeout: SSH_CACHE_TIMEOUT_DEFAULT,
+            ssh_cli: SSH_CLI_DEFAULT.to_owned(),
+            registry_login_command: vec![],
+            registry_login_mode: LoginMode::Command,
+            registry_user: None,
+            input_size_warning_bytes: INPUT_SIZE_WARNING_BYTES_DEFAULT,
+            input_size_warning_files: INPUT_SIZE_WARNING_FILES_DEFAULT,
+            require_pinned_image: false,
+            resolve_image_digest: false,
+            prefix_output: false,
+            check_for_updates: false,
         };
 
         assert_eq!(parse(EMPTY_CONFIG).unwrap(), result);
@@ -82,23 +308,181 @@ mod tests {
     fn parse_nonempty() {
         let config = r"
 docker_cli: podman
+docker_config_dir: /etc/toast/docker
+docker_env:
+  DOCKER_HOST: unix:///run/podman/podman.sock
 docker_repo: foo
+wait_for_docker: 60
 read_local_cache: false
 write_local_cache: false
 read_remote_cache: true
 write_remote_cache: true
+confirm_public_push: true
+extra_pull_arguments:
+  - --platform
+  - linux/amd64
+extra_push_arguments:
+  - --disable-content-trust=false
+ssh_cache_host: builder@cache.example.com
+write_ssh_cache: false
+ssh_cache_timeout: 5
+ssh_cli: /usr/local/bin/ssh
+registry_login_command:
+  - mint-token
+  - --scope=cache
+registry_login_mode: token
+registry_user: flynn
+input_size_warning_bytes: 5000000000
+input_size_warning_files: 100000
+require_pinned_image: true
+resolve_image_digest: true
+prefix_output: true
+check_for_updates: true
     "
         .trim();
 
         let result = Config {
             docker_cli: "podman".to_owned(),
+            docker_config_dir: Some("/etc/toast/docker".to_owned()),
+            docker_env: HashMap::from([(
+                "DOCKER_HOST".to_owned(),
+                "unix:///run/podman/podman.sock".to_owned(),
+            )]),
             docker_repo: "foo".to_owned(),
+            wait_for_docker: 60,
             read_local_cache: false,
             write_local_cache: false,
             read_remote_cache: true,
             write_remote_cache: true,
+            confirm_public_push: true,
+            extra_pull_arguments: vec!["--platform".to_owned(), "linux/amd64".to_owned()],
+            extra_push_arguments: vec!["--disable-content-trust=false".to_owned()],
+            ssh_cache_host: Some("builder@cache.example.com".to_owned()),
+            write_ssh_cache: false,
+            ssh_cache_timeout: 5,
+            ssh_cli: "/usr/local/bin/ssh".to_owned(),
+            registry_login_command: vec!["mint-token".to_owned(), "--scope=cache".to_owned()],
+            registry_login_mode: LoginMode::Token,
+            registry_user: Some("flynn".to_owned()),
+            input_size_warning_bytes: 5_000_000_000,
+            input_size_warning_files: 100_000,
+            require_pinned_image: true,
+            resolve_image_digest: true,
+            prefix_output: true,
+            check_for_updates: true,
         };
 
         assert_eq!(parse(config).unwrap(), result);
     }
+
+    #[test]
+    fn parse_extra_pull_arguments_image() {
+        let config = r"
+extra_pull_arguments:
+  - --platform=linux/amd64
+  - encom:os-12
+    "
+        .trim();
+
+        let result = parse(config);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("encom:os-12"));
+    }
+
+    #[test]
+    fn parse_input_size_warning_bytes_with_unit() {
+        let config = r"
+input_size_warning_bytes: 5GB
+    "
+        .trim();
+
+        assert_eq!(
+            parse(config).unwrap().input_size_warning_bytes,
+            5_000_000_000
+        );
+    }
+
+    #[test]
+    fn parse_input_size_warning_bytes_invalid() {
+        let config = r"
+input_size_warning_bytes: 5 gigs
+    "
+        .trim();
+
+        let result = parse(config);
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("isn't a unit of size"));
+    }
+
+    #[test]
+    fn parse_extra_push_arguments_help() {
+        let config = r"
+extra_push_arguments:
+  - --help
+    "
+        .trim();
+
+        let result = parse(config);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("--help"));
+    }
+
+    #[test]
+    fn parse_registry_login_token_without_user() {
+        let config = r"
+registry_login_command:
+  - mint-token
+registry_login_mode: token
+    "
+        .trim();
+
+        let result = parse(config);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("registry_user"));
+    }
+
+    #[test]
+    fn parse_docker_env_docker_config() {
+        let config = r"
+docker_config_dir: /etc/toast/docker
+docker_env:
+  DOCKER_CONFIG: /root/.docker
+    "
+        .trim();
+
+        let result = parse(config);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("DOCKER_CONFIG"));
+    }
+
+    #[test]
+    fn docker_environment_empty() {
+        assert_eq!(docker_environment(&parse(EMPTY_CONFIG).unwrap()), vec![]);
+    }
+
+    #[test]
+    fn docker_environment_nonempty() {
+        let config = r"
+docker_config_dir: /etc/toast/docker
+docker_env:
+  HTTPS_PROXY: http://proxy.example.com
+  BUILDKIT_PROGRESS: plain
+    "
+        .trim();
+
+        assert_eq!(
+            docker_environment(&parse(config).unwrap()),
+            vec![
+                ("BUILDKIT_PROGRESS".to_owned(), "plain".to_owned()),
+                ("DOCKER_CONFIG".to_owned(), "/etc/toast/docker".to_owned()),
+                (
+                    "HTTPS_PROXY".to_owned(),
+                    "http://proxy.example.com".to_owned(),
+                ),
+            ],
+        );
+    }
 }
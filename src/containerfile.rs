@@ -0,0 +1,312 @@
+use {
+    crate::{
+        format::CodeStr,
+        toastfile::{command, location, user, Task, Toastfile},
+    },
+    std::fmt::Write as _,
+};
+
+// Render the tasks in a schedule as a Dockerfile, for `--containerfile`. This is a best-effort
+// translation: it doesn't know about the cache, output files, or anything else Toast does outside
+// the container. Tasks which mount paths or volumes or publish ports are left out (as comments),
+// since a Dockerfile can't do any of those.
+pub fn render(toastfile: &Toastfile, schedule: &[&str]) -> String {
+    // The heredocs in `RUN` instructions require this syntax.
+    let mut output = String::from("# syntax=docker/dockerfile:1\n");
+    output.push_str(
+        "# Generated by `toast --containerfile`. Input paths are relative to the directory\n",
+    );
+    output.push_str("# containing the toastfile, so use that directory as the build context.\n");
+    let _ = write!(output, "\nFROM {}\n", toastfile.image);
+
+    for task_name in schedule {
+        let task = &toastfile.tasks[*task_name]; // [ref:tasks_valid]
+
+        output.push('\n');
+        match &task.description {
+            Some(description) => {
+                let _ = writeln!(output, "# Task {}: {}", task_name.code_str(), description);
+            }
+            None => {
+                let _ = writeln!(output, "# Task {}", task_name.code_str());
+            }
+        }
+
+        let instructions = instructions(toastfile, task);
+        let mut reasons = vec![];
+        if !task.mount_paths.is_empty() {
+            reasons.push("mounts paths from the host");
+        }
+        if !task.volumes.is_empty() {
+            reasons.push("mounts volumes");
+        }
+        if !task.ports.is_empty() {
+            reasons.push("publishes ports");
+        }
+
+        if reasons.is_empty() {
+            output.push_str(&instructions);
+        } else {
+            let _ = writeln!(
+                output,
+                "# This task {}, which can't be represented in a Dockerfile:",
+                reasons.join(" and "),
+            );
+            for line in instructions.lines() {
+                let _ = writeln!(output, "# {line}");
+            }
+        }
+    }
+
+    output
+}
+
+// Render the instructions for a single task.
+fn instructions(toastfile: &Toastfile, task: &Task) -> String {
+    let mut output = String::new();
+    let location = location(toastfile, task);
+
+    // A task with its own image doesn't start from the previous task.
+    if let Some(image) = &task.image {
+        let _ = writeln!(output, "FROM {image}");
+    }
+
+    // The values of the environment variables are build arguments, since they come from the host.
+    // The defaults of secret variables are left out, so the containerfile can be shared
+    // [ref:secrets_masked].
+    let mut variables = task.environment.iter().collect::<Vec<_>>();
+    variables.sort_unstable();
+    for (variable, default) in variables {
+        match default {
+            Some(_) if task.secret_variables.contains(variable) => {
+                let _ = writeln!(output, "# The default of {variable} is secret.");
+                let _ = writeln!(output, "ARG {variable}");
+            }
+            Some(default) => {
+                let _ = writeln!(output, "ARG {variable}={}", quote_value(default));
+            }
+            None => {
+                let _ = writeln!(output, "ARG {variable}");
+            }
+        }
+        let _ = writeln!(output, "ENV {variable}=${variable}");
+    }
+
+    let _ = writeln!(output, "WORKDIR {}", location.to_string_lossy());
+
+    for input_path in &task.input_paths {
+        let _ = writeln!(
+            output,
+            "COPY [{}, {}]",
+            quote_json(&input_path.to_string_lossy()),
+            quote_json(&location.join(input_path).to_string_lossy()),
+        );
+    }
+    if !task.excluded_input_paths.is_empty() {
+        let _ = writeln!(
+            output,
+            "# Exclude these paths from the inputs (e.g., with `.dockerignore`): {}",
+            task.excluded_input_paths
+                .iter()
+                .map(|path| path.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
+    }
+
+    let _ = writeln!(output, "USER {}", user(toastfile, task));
+
+    let command = command(toastfile, task);
+    let command = command.trim_end_matches('\n');
+    if !command.is_empty() {
+        // Choose a delimiter for the heredoc which doesn't appear in the command.
+        let mut delimiter = "EOF".to_owned();
+        while command.lines().any(|line| line == delimiter) {
+            delimiter.push('_');
+        }
+
+        let _ = writeln!(output, "RUN <<\"{delimiter}\"\n{command}\n{delimiter}");
+    }
+
+    output
+}
+
+// Quote a string for a JSON array, as in the exec form of `COPY`.
+fn quote_json(string: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in string.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Quote the value of an `ARG` instruction. Dollar signs are escaped so they aren't substituted.
+fn quote_value(value: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' || c == '$' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{containerfile::render, schedule::compute, toastfile::parse};
+
+    const TOASTFILE: &str = r#"
+image: encom:os-12
+command_prefix: set -eu
+tasks:
+  install_deps:
+    description: Install the dependencies.
+    input_paths:
+      - package.json
+    user: flynn
+    command: npm install
+  build:
+    dependencies:
+      - install_deps
+    environment:
+      MODE: release
+      TOKEN: null
+      GREETING: say "$hi"
+    input_paths:
+      - src
+      - my file.txt
+    excluded_input_paths:
+      - src/target
+    location: /code
+    command: |
+      make
+      cat > version.txt <<EOF
+      1.0
+      EOF
+  serve:
+    dependencies:
+      - build
+    cache: false
+    ports:
+      - 3000:80
+    command: make serve
+  release:
+    dependencies:
+      - build
+    image: encom:os-13
+    command_prefix: ''
+"#;
+
+    #[test]
+    fn render_chain() {
+        let toastfile = parse(TOASTFILE).unwrap();
+
+        assert_eq!(
+            render(&toastfile, &compute(&toastfile, &["build"])),
+            r#"# syntax=docker/dockerfile:1
+# Generated by `toast --containerfile`. Input paths are relative to the directory
+# containing the toastfile, so use that directory as the build context.
+
+FROM encom:os-12
+
+# Task `install_deps`: Install the dependencies.
+WORKDIR /scratch
+COPY ["package.json", "/scratch/package.json"]
+USER flynn
+RUN <<"EOF"
+set -eu
+npm install
+EOF
+
+# Task `build`
+ARG GREETING="say \"\$hi\""
+ENV GREETING=$GREETING
+ARG MODE="release"
+ENV MODE=$MODE
+ARG TOKEN
+ENV TOKEN=$TOKEN
+WORKDIR /code
+COPY ["src", "/code/src"]
+COPY ["my file.txt", "/code/my file.txt"]
+# Exclude these paths from the inputs (e.g., with `.dockerignore`): src/target
+USER root
+RUN <<"EOF_"
+set -eu
+make
+cat > version.txt <<EOF
+1.0
+EOF
+EOF_
+"#,
+        );
+    }
+
+    #[test]
+    fn render_ports() {
+        let toastfile = parse(TOASTFILE).unwrap();
+        let rendered = render(&toastfile, &["serve"]);
+
+        assert!(rendered.ends_with(
+            r#"
+# Task `serve`
+# This task publishes ports, which can't be represented in a Dockerfile:
+# WORKDIR /scratch
+# USER root
+# RUN <<"EOF"
+# set -eu
+# make serve
+# EOF
+"#,
+        ));
+    }
+
+    #[test]
+    fn render_secret() {
+        let input = r"
+image: encom:os-12
+tasks:
+  deploy:
+    environment:
+      API_KEY: abc123
+    secret_variables:
+      - API_KEY
+";
+        let toastfile = parse(input).unwrap();
+        let rendered = render(&toastfile, &["deploy"]);
+
+        assert!(rendered.ends_with(
+            r"
+# Task `deploy`
+# The default of API_KEY is secret.
+ARG API_KEY
+ENV API_KEY=$API_KEY
+WORKDIR /scratch
+USER root
+",
+        ));
+        assert!(!rendered.contains("abc123"));
+    }
+
+    #[test]
+    fn render_task_image() {
+        let toastfile = parse(TOASTFILE).unwrap();
+        let rendered = render(&toastfile, &["release"]);
+
+        assert!(rendered.ends_with(
+            r"
+FROM encom:os-12
+
+# Task `release`
+FROM encom:os-13
+WORKDIR /scratch
+USER root
+",
+        ));
+    }
+}
@@ -0,0 +1,120 @@
+use {
+    crate::format::CodeStr,
+    std::{collections::BTreeMap, fmt::Write as _},
+};
+
+// The most paths to list when reporting that a task isn't deterministic
+const MAX_REPORTED_PATHS: usize = 20;
+
+// Find the paths which differ between two container fingerprints (see
+// `docker::container_fingerprint`), in sorted order. A path which only one of the runs touched
+// counts as a difference.
+pub fn compare(first: &BTreeMap<String, String>, second: &BTreeMap<String, String>) -> Vec<String> {
+    let mut paths = first
+        .iter()
+        .filter(|(path, hash)| second.get(*path) != Some(*hash))
+        .map(|(path, _)| path.clone())
+        .chain(
+            second
+                .keys()
+                .filter(|path| !first.contains_key(*path))
+                .cloned(),
+        )
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths
+}
+
+// Describe the paths which differ between two runs of a task.
+pub fn report(task: &str, paths: &[String]) -> String {
+    let mut message = format!(
+        "Task {} is not deterministic. Running it twice produced different contents for {} \
+         path{}:",
+        task.code_str(),
+        paths.len(),
+        if paths.len() == 1 { "" } else { "s" },
+    );
+    for path in paths.iter().take(MAX_REPORTED_PATHS) {
+        let _ = write!(message, "\n  {}", path.code_str());
+    }
+    if paths.len() > MAX_REPORTED_PATHS {
+        let _ = write!(
+            message,
+            "\n  \u{2026}and {} more.",
+            paths.len() - MAX_REPORTED_PATHS,
+        );
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::determinism::{compare, report, MAX_REPORTED_PATHS},
+        std::collections::BTreeMap,
+    };
+
+    fn fingerprint(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(path, hash)| ((*path).to_owned(), (*hash).to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn compare_identical() {
+        let first = fingerprint(&[("/scratch/foo.txt", "abc"), ("/tmp/bar.txt", "deleted")]);
+
+        assert!(compare(&first, &first.clone()).is_empty());
+    }
+
+    #[test]
+    fn compare_empty() {
+        assert!(compare(&BTreeMap::new(), &BTreeMap::new()).is_empty());
+    }
+
+    #[test]
+    fn compare_changed_contents() {
+        let first = fingerprint(&[("/scratch/foo.txt", "abc"), ("/scratch/bar.txt", "def")]);
+        let second = fingerprint(&[("/scratch/foo.txt", "xyz"), ("/scratch/bar.txt", "def")]);
+
+        assert_eq!(
+            compare(&first, &second),
+            vec!["/scratch/foo.txt".to_owned()]
+        );
+    }
+
+    #[test]
+    fn compare_added_and_removed_paths() {
+        let first = fingerprint(&[("/scratch/foo.txt", "abc"), ("/tmp/1234", "def")]);
+        let second = fingerprint(&[("/scratch/foo.txt", "abc"), ("/tmp/5678", "def")]);
+
+        assert_eq!(
+            compare(&first, &second),
+            vec!["/tmp/1234".to_owned(), "/tmp/5678".to_owned()],
+        );
+    }
+
+    #[test]
+    fn report_one_path() {
+        assert_eq!(
+            report("build", &["/scratch/foo.txt".to_owned()]),
+            "Task `build` is not deterministic. Running it twice produced different contents for \
+             1 path:\n  `/scratch/foo.txt`",
+        );
+    }
+
+    #[test]
+    fn report_truncates() {
+        let paths = (0..MAX_REPORTED_PATHS + 3)
+            .map(|i| format!("/scratch/{i}"))
+            .collect::<Vec<_>>();
+
+        let message = report("build", &paths);
+
+        assert!(message.contains(&format!("{} paths:", MAX_REPORTED_PATHS + 3)));
+        assert!(message.contains("`/scratch/0`"));
+        assert!(!message.contains(&format!("`/scratch/{MAX_REPORTED_PATHS}`")));
+        assert!(message.ends_with("\u{2026}and 3 more."));
+    }
+}
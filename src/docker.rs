@@ -1,56 +1,173 @@
 use {
-    crate::{failure, failure::Failure, format::CodeStr, spinner::spin, toastfile::MappingPath},
+    crate::{
+        cache,
+        cache::CryptoHash,
+        failure,
+        failure::{CommandExit, Failure},
+        format,
+        format::CodeStr,
+        spinner::spin,
+        stats::Sample,
+        tail::Tail,
+        toastfile::{mount_target, output_host_path, MappingPath, Port, Volume},
+        units,
+    },
+    serde::Deserialize,
     std::{
-        collections::HashMap,
+        collections::{BTreeMap, HashMap},
         env::current_dir,
-        fs::{copy, create_dir_all, rename, symlink_metadata, Metadata},
+        fmt::Write as _,
+        fs::{
+            copy, create_dir_all, hard_link, remove_dir_all, remove_file, rename, symlink_metadata,
+            Metadata,
+        },
         io,
-        io::Read,
-        path::Path,
-        process::{ChildStdin, Command, Stdio},
+        io::{Read, Write},
+        mem::drop,
+        path::{Path, PathBuf},
         string::ToString,
         sync::{
             atomic::{AtomicBool, Ordering},
-            Arc,
+            mpsc::{channel, RecvTimeoutError, Sender},
+            Arc, Mutex,
         },
+        thread,
+        thread::{sleep, JoinHandle},
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
     },
     tempfile::tempdir,
-    typed_path::{TryAsRef, UnixPath, UnixPathBuf},
+    typed_path::{unix::UnixComponent, TryAsRef, UnixPath, UnixPathBuf},
     walkdir::WalkDir,
 };
 
 #[cfg(unix)]
-use std::fs::read_link;
+use std::{
+    fs::{read_link, set_permissions},
+    os::unix::fs::MetadataExt,
+};
+
+mod cli;
+mod errors;
+
+pub use cli::{command, set_environment, DockerCli, Outcome, Process};
+
+#[cfg(unix)]
+pub use cli::resume_children;
+
+#[cfg(test)]
+pub use cli::FakeCli;
 
-// Query whether an image exists locally.
+// The label on volumes which Docker creates for the `volumes` of a task, so `toast prune
+// --prune-volumes` can find them. Its value is the repository the task uses for the cache, so only
+// the volumes of the project at hand are found [tag:volume_label].
+pub const VOLUME_LABEL: &str = "toast.repo";
+
+// How often to report that output is being suppressed due to `max_log_lines`
+const SUPPRESSED_NOTICE_INTERVAL: Duration = Duration::from_secs(10);
+
+// Copying a file at least this large out of a container is logged, since it can take a while
+const LARGE_COPY_THRESHOLD_BYTES: u64 = 1_000_000_000;
+
+// How often to check on a command running in the background
+const BACKGROUND_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// How long to wait before checking on the Docker daemon again, at first and at most
+const DAEMON_POLL_INITIAL_DELAY: Duration = Duration::from_millis(100);
+const DAEMON_POLL_MAX_DELAY: Duration = Duration::from_secs(1);
+
+// How often to report that we're still waiting for the Docker daemon
+const DAEMON_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+// Query whether an image exists locally. Failures other than the image not existing (e.g., the
+// Docker daemon being unreachable) are reported as errors rather than being treated as a cache
+// miss.
 pub fn image_exists(
-    docker_cli: &str,
+    docker_cli: &(impl DockerCli + ?Sized),
     image: &str,
     interrupted: &Arc<AtomicBool>,
 ) -> Result<bool, Failure> {
     debug!("Checking existence of image {}\u{2026}", image.code_str());
 
-    match run_quiet(
+    let output = run_quiet_output(
         docker_cli,
         "Checking existence of image\u{2026}",
-        "The image doesn't exist.",
+        "Unable to check the existence of the image.",
         &vec!["image", "inspect", image]
             .into_iter()
             .map(std::borrow::ToOwned::to_owned)
             .collect::<Vec<_>>(),
-        false,
         interrupted,
-    ) {
-        Ok(_) => Ok(true),
-        Err(Failure::Interrupted) => Err(Failure::Interrupted),
-        Err(Failure::System(_, _) | Failure::User(_, _)) => Ok(false),
+    )?;
+
+    if output.success() {
+        return Ok(true);
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if errors::is_image_not_found(&stderr) {
+        Ok(false)
+    } else if errors::is_daemon_unreachable(&stderr) {
+        Err(Failure::System(
+            format!("Unable to reach the Docker daemon.\n{stderr}"),
+            None,
+        ))
+    } else {
+        Err(Failure::System(
+            format!("Unable to check the existence of the image.\n{stderr}"),
+            None,
+        ))
+    }
+}
+
+// Query whether an image exists in its registry, without pulling it. As with `image_exists`, failures
+// other than the image not existing (e.g., being unable to log in to the registry) are reported as
+// errors rather than being treated as a cache miss.
+pub fn remote_image_exists(
+    docker_cli: &(impl DockerCli + ?Sized),
+    image: &str,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<bool, Failure> {
+    debug!(
+        "Checking existence of image {} in the registry\u{2026}",
+        image.code_str(),
+    );
+
+    let output = run_quiet_output(
+        docker_cli,
+        "Checking existence of image in the registry\u{2026}",
+        "Unable to check the existence of the image in the registry.",
+        &vec!["manifest", "inspect", image]
+            .into_iter()
+            .map(std::borrow::ToOwned::to_owned)
+            .collect::<Vec<_>>(),
+        interrupted,
+    )?;
+
+    if output.success() {
+        return Ok(true);
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if errors::is_manifest_not_found(&stderr) {
+        Ok(false)
+    } else if errors::is_daemon_unreachable(&stderr) {
+        Err(Failure::System(
+            format!("Unable to reach the Docker daemon.\n{stderr}"),
+            None,
+        ))
+    } else {
+        Err(Failure::System(
+            format!("Unable to check the existence of the image in the registry.\n{stderr}"),
+            None,
+        ))
     }
 }
 
 // Push an image.
 pub fn push_image(
-    docker_cli: &str,
+    docker_cli: &(impl DockerCli + ?Sized),
     image: &str,
+    extra_args: &[String],
     interrupted: &Arc<AtomicBool>,
 ) -> Result<(), Failure> {
     debug!("Pushing image {}\u{2026}", image.code_str());
@@ -59,41 +176,330 @@ pub fn push_image(
         docker_cli,
         "Pushing image\u{2026}",
         "Unable to push image.",
-        &vec!["image", "push", image]
-            .into_iter()
-            .map(std::borrow::ToOwned::to_owned)
-            .collect::<Vec<_>>(),
+        &image_args("push", image, extra_args),
         false,
         interrupted,
     )
     .map(|_| ())
 }
 
-// Pull an image.
-pub fn pull_image(
-    docker_cli: &str,
+// Why an image is needed
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ImagePurpose {
+    // The base image from the toastfile, which the first task runs on
+    BaseImage,
+
+    // An image produced by an earlier task, which the next task runs on
+    Context,
+
+    // An image which may let us skip a task if it exists
+    CacheEntry,
+
+    // The image given by `--shell-image`
+    Shell,
+}
+
+impl ImagePurpose {
+    // A short description for log messages
+    fn description(self) -> &'static str {
+        match self {
+            Self::BaseImage => "base image",
+            Self::Context => "image from the previous task",
+            Self::CacheEntry => "cached image",
+            Self::Shell => "image for the shell",
+        }
+    }
+}
+
+// When to pull an image from the registry
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PullPolicy {
+    // Only use the image if it already exists locally.
+    Never,
+
+    // Pull the image only if it doesn't exist locally.
+    IfMissing,
+
+    // Pull the image even if it exists locally.
+    Always,
+}
+
+// Whether an image is available locally, and if so, whether it had to be pulled
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Availability {
+    Local,
+    Pulled,
+    Missing,
+}
+
+impl Availability {
+    pub fn available(self) -> bool {
+        self != Self::Missing
+    }
+}
+
+// Make sure an image is available locally, pulling it according to the policy. Returns whether the
+// image is available and how it got there. For a cache entry, a failed pull just means the image
+// isn't in the remote cache. For anything else, the image is required, so a failed pull is an
+// error.
+pub fn ensure_image(
+    docker_cli: &(impl DockerCli + ?Sized),
     image: &str,
+    purpose: ImagePurpose,
+    policy: PullPolicy,
+    extra_pull_args: &[String],
     interrupted: &Arc<AtomicBool>,
-) -> Result<(), Failure> {
-    debug!("Pulling image {}\u{2026}", image.code_str());
+) -> Result<Availability, Failure> {
+    // Check for the image locally, unless we're going to pull it regardless.
+    if policy != PullPolicy::Always && image_exists(docker_cli, image, interrupted)? {
+        debug!(
+            "Found the {} {} locally.",
+            purpose.description(),
+            image.code_str(),
+        );
+        return Ok(Availability::Local);
+    }
 
-    run_quiet(
+    if policy == PullPolicy::Never {
+        debug!(
+            "The {} {} doesn't exist locally.",
+            purpose.description(),
+            image.code_str(),
+        );
+        return Ok(Availability::Missing);
+    }
+
+    // Pull the image.
+    debug!(
+        "Pulling the {} {}\u{2026}",
+        purpose.description(),
+        image.code_str(),
+    );
+    match run_quiet(
         docker_cli,
-        "Pulling image\u{2026}",
-        "Unable to pull image.",
-        &vec!["image", "pull", image]
-            .into_iter()
-            .map(std::borrow::ToOwned::to_owned)
-            .collect::<Vec<_>>(),
+        &format!("Pulling {}\u{2026}", purpose.description()),
+        &format!("Unable to pull the {}.", purpose.description()),
+        &image_args("pull", image, extra_pull_args),
         false,
         interrupted,
-    )
-    .map(|_| ())
+    ) {
+        Ok(_) => Ok(Availability::Pulled),
+
+        // If the pull failed because the user killed the child process (e.g., by hitting CTRL+C),
+        // that's not a cache miss.
+        Err(e) if purpose != ImagePurpose::CacheEntry || interrupted.load(Ordering::SeqCst) => {
+            Err(e)
+        }
+
+        Err(_) => {
+            debug!(
+                "The {} {} isn't in the remote cache.",
+                purpose.description(),
+                image.code_str(),
+            );
+            Ok(Availability::Missing)
+        }
+    }
+}
+
+// A pull of an image which runs in the background while Toast does other work (e.g., reading input
+// files). Errors are held until the pull is joined, so they're only reported if the image is
+// actually needed. Dropping the pull without joining it cancels it.
+pub struct BackgroundPull {
+    image: String,
+    cancelled: Arc<AtomicBool>,
+    interrupted: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<(), String>>>,
+}
+
+impl BackgroundPull {
+    // Start pulling an image, unless it already exists locally and `force` is `false`. The child
+    // processes are killed if the user interrupts the program.
+    pub fn start(
+        docker_cli: &str,
+        image: &str,
+        extra_args: &[String],
+        force: bool,
+        interrupted: &Arc<AtomicBool>,
+    ) -> Self {
+        debug!(
+            "Pulling image {} in the background\u{2026}",
+            image.code_str()
+        );
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let docker_cli = docker_cli.to_owned();
+        let inspect_args = vec!["image".to_owned(), "inspect".to_owned(), image.to_owned()];
+        let pull_args = image_args("pull", image, extra_args);
+        let thread_cancelled = cancelled.clone();
+        let thread_interrupted = interrupted.clone();
+        let thread = thread::spawn(move || {
+            let interrupted = thread_interrupted;
+            // Skip the pull if the image is already here. If the check fails for some other reason,
+            // the pull will surface the problem.
+            if !force
+                && run_background(&docker_cli, &inspect_args, &thread_cancelled, &interrupted)?.0
+            {
+                return Ok(());
+            }
+
+            let (success, stderr) =
+                run_background(&docker_cli, &pull_args, &thread_cancelled, &interrupted)?;
+            if success {
+                Ok(())
+            } else {
+                Err(format!("Unable to pull image.\n{stderr}"))
+            }
+        });
+
+        Self {
+            image: image.to_owned(),
+            cancelled,
+            interrupted: interrupted.clone(),
+            thread: Some(thread),
+        }
+    }
+
+    // The image being pulled
+    pub fn image(&self) -> &str {
+        &self.image
+    }
+
+    // Wait for the pull to finish and return its result.
+    pub fn join(mut self) -> Result<(), Failure> {
+        debug!(
+            "Waiting for the background pull of image {}\u{2026}",
+            self.image.code_str(),
+        );
+
+        let _guard = spin("Pulling image\u{2026}");
+
+        // The first `unwrap` is safe since the thread is only taken here or when the pull is
+        // dropped. The second will only fail if the thread panicked.
+        self.thread
+            .take()
+            .unwrap()
+            .join()
+            .unwrap()
+            .map_err(|error| {
+                if self.interrupted.load(Ordering::SeqCst) {
+                    Failure::Interrupted
+                } else {
+                    Failure::System(error, None)
+                }
+            })
+    }
+
+    // Stop the pull if it's still running, since the image turned out not to be needed.
+    pub fn cancel(self) {
+        drop(self);
+    }
+}
+
+impl Drop for BackgroundPull {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.cancelled.store(true, Ordering::SeqCst);
+
+            // Wait for the child processes to be killed, so they don't outlive the program. The
+            // result doesn't matter, since the image isn't needed.
+            let _ = thread.join();
+        }
+    }
+}
+
+// Run a command without a spinner, since other work is happening in the foreground. Returns whether
+// the command succeeded along with its standard error stream. The child process is killed if
+// `cancelled` or `interrupted` is set. Errors are returned as messages, since they're sent across
+// threads.
+fn run_background(
+    docker_cli: &(impl DockerCli + ?Sized),
+    args: &[String],
+    cancelled: &Arc<AtomicBool>,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(bool, String), String> {
+    let mut child = docker_cli.spawn(args, false).map_err(|error| {
+        format!("Unable to run Docker. Perhaps you don't have Docker installed.\n{error}")
+    })?;
+
+    loop {
+        if let Some(outcome) = child
+            .try_wait()
+            .map_err(|error| format!("Unable to wait for child process.\n{error}"))?
+        {
+            return Ok((
+                outcome.success(),
+                String::from_utf8_lossy(&outcome.stderr).into_owned(),
+            ));
+        }
+
+        if interrupted.load(Ordering::SeqCst) || cancelled.load(Ordering::SeqCst) {
+            child.kill();
+            return Err("The background pull was stopped.".to_owned());
+        }
+
+        sleep(BACKGROUND_POLL_INTERVAL);
+    }
+}
+
+// Run a command without consulting `interrupted`, killing it if it's still running at the
+// `deadline`. This is for work which is done after the user has already interrupted the program.
+fn run_until(
+    docker_cli: &(impl DockerCli + ?Sized),
+    error: &str,
+    args: &[String],
+    deadline: Instant,
+) -> Result<(), Failure> {
+    let mut child = docker_cli
+        .spawn(args, false)
+        .map_err(failure::system(format!(
+            "{error} Perhaps you don't have Docker installed.",
+        )))?;
+
+    match wait_until(&mut *child, deadline)
+        .map_err(failure::system("Unable to wait for child process."))?
+    {
+        Some(outcome) if outcome.success() => Ok(()),
+        Some(outcome) => Err(Failure::User(
+            format!("{}\n{}", error, String::from_utf8_lossy(&outcome.stderr)),
+            None,
+        )),
+        None => Err(Failure::User(
+            format!("{error} It didn't finish in time."),
+            None,
+        )),
+    }
+}
+
+// Wait for a process to finish, killing it if the `deadline` passes first. In that case, `None` is
+// returned.
+fn wait_until(process: &mut dyn Process, deadline: Instant) -> io::Result<Option<Outcome>> {
+    loop {
+        if let Some(outcome) = process.try_wait()? {
+            return Ok(Some(outcome));
+        }
+
+        if Instant::now() >= deadline {
+            process.kill();
+            return Ok(None);
+        }
+
+        sleep(BACKGROUND_POLL_INTERVAL);
+    }
+}
+
+// This function returns arguments for `docker image pull` or `docker image push`. The user-provided
+// arguments come before the image, since Docker expects options to precede positional arguments.
+fn image_args(subcommand: &str, image: &str, extra_args: &[String]) -> Vec<String> {
+    let mut args = vec!["image".to_owned(), subcommand.to_owned()];
+    args.extend_from_slice(extra_args);
+    args.push(image.to_owned());
+    args
 }
 
 // Delete an image.
 pub fn delete_image(
-    docker_cli: &str,
+    docker_cli: &(impl DockerCli + ?Sized),
     image: &str,
     interrupted: &Arc<AtomicBool>,
 ) -> Result<(), Failure> {
@@ -113,16 +519,280 @@ pub fn delete_image(
     .map(|_| ())
 }
 
+// List the images in a repository, as `repository:tag`. Images without a tag are omitted.
+pub fn list_images(
+    docker_cli: &(impl DockerCli + ?Sized),
+    repo: &str,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<Vec<String>, Failure> {
+    debug!("Listing the images in {}\u{2026}", repo.code_str());
+
+    let images = run_quiet(
+        docker_cli,
+        "Listing images\u{2026}",
+        "Unable to list images.",
+        &vec!["image", "ls", "--format", "{{.Repository}}:{{.Tag}}", repo]
+            .into_iter()
+            .map(std::borrow::ToOwned::to_owned)
+            .collect::<Vec<_>>(),
+        false,
+        interrupted,
+    )?;
+
+    Ok(images
+        .lines()
+        .map(str::trim)
+        .filter(|image| !image.is_empty() && !image.ends_with(":<none>"))
+        .map(ToOwned::to_owned)
+        .collect())
+}
+
+// Delete a volume.
+pub fn delete_volume(
+    docker_cli: &(impl DockerCli + ?Sized),
+    volume: &str,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    debug!("Deleting volume {}\u{2026}", volume.code_str());
+
+    run_quiet(
+        docker_cli,
+        "Deleting volume\u{2026}",
+        "Unable to delete volume.",
+        &vec!["volume", "rm", volume]
+            .into_iter()
+            .map(std::borrow::ToOwned::to_owned)
+            .collect::<Vec<_>>(),
+        false,
+        interrupted,
+    )
+    .map(|_| ())
+}
+
+// List the volumes which Docker created for the `volumes` of tasks which use the given repository
+// [ref:volume_label]. Volumes the user created themselves aren't included, even if tasks use them.
+pub fn list_volumes(
+    docker_cli: &(impl DockerCli + ?Sized),
+    docker_repo: &str,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<Vec<String>, Failure> {
+    debug!("Listing the volumes for {}\u{2026}", docker_repo.code_str());
+
+    let volumes = run_quiet(
+        docker_cli,
+        "Listing volumes\u{2026}",
+        "Unable to list volumes.",
+        &[
+            "volume".to_owned(),
+            "ls".to_owned(),
+            "--filter".to_owned(),
+            format!("label={VOLUME_LABEL}={docker_repo}"),
+            "--format".to_owned(),
+            "{{.Name}}".to_owned(),
+        ],
+        false,
+        interrupted,
+    )?;
+
+    Ok(volumes
+        .lines()
+        .map(str::trim)
+        .filter(|volume| !volume.is_empty())
+        .map(ToOwned::to_owned)
+        .collect())
+}
+
+// Determine the digest of a local image, as a reference like `node@sha256:…`. Returns `None` if the
+// image has no digest (e.g., because it was built locally rather than pulled from a registry). If
+// the image was pulled from more than one repository, the digest for its own repository is used.
+pub fn image_digest(
+    docker_cli: &(impl DockerCli + ?Sized),
+    image: &str,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<Option<String>, Failure> {
+    debug!("Resolving the digest of image {}\u{2026}", image.code_str());
+
+    let digests = run_quiet(
+        docker_cli,
+        "Resolving the digest of the image\u{2026}",
+        "Unable to determine the digest of the image.",
+        &vec![
+            "image",
+            "inspect",
+            "--format",
+            "{{range .RepoDigests}}{{println .}}{{end}}",
+            image,
+        ]
+        .into_iter()
+        .map(std::borrow::ToOwned::to_owned)
+        .collect::<Vec<_>>(),
+        false,
+        interrupted,
+    )?;
+
+    // Strip the tag, if there is one. A colon before the last slash belongs to a registry port.
+    let repository = match image.rsplit_once(':') {
+        Some((repository, tag)) if !tag.contains('/') => repository,
+        _ => image,
+    };
+
+    let digests = digests
+        .lines()
+        .map(str::trim)
+        .filter(|digest| !digest.is_empty())
+        .collect::<Vec<_>>();
+
+    Ok(digests
+        .iter()
+        .find(|digest| {
+            digest
+                .split_once('@')
+                .is_some_and(|(digest_repository, _)| digest_repository == repository)
+        })
+        .or_else(|| digests.first())
+        .map(|digest| (*digest).to_owned()))
+}
+
+// Determine when an image was created.
+pub fn image_created(
+    docker_cli: &(impl DockerCli + ?Sized),
+    image: &str,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<SystemTime, Failure> {
+    debug!(
+        "Checking when image {} was created\u{2026}",
+        image.code_str()
+    );
+
+    let created = run_quiet(
+        docker_cli,
+        "Checking the age of the image\u{2026}",
+        "Unable to determine when the image was created.",
+        &vec!["image", "inspect", "--format", "{{.Created}}", image]
+            .into_iter()
+            .map(std::borrow::ToOwned::to_owned)
+            .collect::<Vec<_>>(),
+        false,
+        interrupted,
+    )?;
+
+    parse_timestamp(created.trim()).ok_or_else(|| {
+        Failure::System(
+            format!(
+                "Unable to parse the creation time {} of image {}.",
+                created.trim().code_str(),
+                image.code_str(),
+            ),
+            None,
+        )
+    })
+}
+
+// Determine the size of an image in bytes. This is the uncompressed size, so it overstates how much
+// data is actually transferred when the image is pushed or pulled.
+pub fn image_size(
+    docker_cli: &(impl DockerCli + ?Sized),
+    image: &str,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<u64, Failure> {
+    debug!("Checking the size of image {}\u{2026}", image.code_str());
+
+    let size = run_quiet(
+        docker_cli,
+        "Checking the size of the image\u{2026}",
+        "Unable to determine the size of the image.",
+        &vec!["image", "inspect", "--format", "{{.Size}}", image]
+            .into_iter()
+            .map(std::borrow::ToOwned::to_owned)
+            .collect::<Vec<_>>(),
+        false,
+        interrupted,
+    )?;
+
+    size.trim().parse().map_err(|_| {
+        Failure::System(
+            format!(
+                "Unable to parse the size {} of image {}.",
+                size.trim().code_str(),
+                image.code_str(),
+            ),
+            None,
+        )
+    })
+}
+
+// Parse an RFC 3339 timestamp, as printed by Docker (e.g., `2024-05-01T12:34:56.123456789Z`).
+// Fractional seconds are ignored.
+fn parse_timestamp(timestamp: &str) -> Option<SystemTime> {
+    let number = |digits: &str| {
+        if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            None
+        } else {
+            digits.parse::<i64>().ok()
+        }
+    };
+
+    let (date, time) = timestamp.split_once(['T', 't', ' '])?;
+
+    // Parse the date.
+    let mut date_parts = date.splitn(3, '-');
+    let year = number(date_parts.next()?)?;
+    let month = number(date_parts.next()?)?;
+    let day = number(date_parts.next()?)?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Split off the time zone offset.
+    let (time, offset) = if let Some(time) = time.strip_suffix(['Z', 'z']) {
+        (time, 0)
+    } else {
+        let sign_index = time.rfind(['+', '-'])?;
+        let (time, offset) = time.split_at(sign_index);
+        let sign = if offset.starts_with('-') { -1 } else { 1 };
+        let (hours, minutes) = offset[1..].split_once(':')?;
+        (time, sign * (number(hours)? * 3600 + number(minutes)? * 60))
+    };
+
+    // Parse the time, ignoring fractional seconds.
+    let time = time.split_once('.').map_or(time, |(time, _)| time);
+    let mut time_parts = time.splitn(3, ':');
+    let hours = number(time_parts.next()?)?;
+    let minutes = number(time_parts.next()?)?;
+    let seconds = number(time_parts.next()?)?;
+    if hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+
+    // Count the days since the Unix epoch, using the algorithm from Howard Hinnant's
+    // `days_from_civil` (http://howardhinnant.github.io/date_algorithms.html).
+    let shifted_year = if month <= 2 { year - 1 } else { year };
+    let era = shifted_year.div_euclid(400);
+    let year_of_era = shifted_year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let since_epoch = days * 86_400 + hours * 3600 + minutes * 60 + seconds - offset;
+    u64::try_from(since_epoch)
+        .ok()
+        .map(|since_epoch| UNIX_EPOCH + Duration::from_secs(since_epoch))
+}
+
 // Create a container and return its ID.
 #[allow(clippy::too_many_arguments)]
 pub fn create_container(
-    docker_cli: &str,
+    docker_cli: &(impl DockerCli + ?Sized),
     image: &str,
     source_dir: &Path,
     environment: &HashMap<String, String>,
     mount_paths: &[MappingPath],
     mount_readonly: bool,
-    ports: &[String],
+    volumes: &[Volume],
+    docker_repo: &str,
+    read_only_rootfs: bool,
+    ports: &[Port],
+    gpus: Option<&str>,
     location: &UnixPath,
     user: &str,
     command: &str,
@@ -142,7 +812,11 @@ pub fn create_container(
         location,
         mount_paths,
         mount_readonly,
+        volumes,
+        docker_repo,
+        read_only_rootfs,
         ports,
+        gpus,
         extra_args,
     )?);
 
@@ -153,22 +827,41 @@ pub fn create_container(
             .collect::<Vec<_>>(),
     );
 
-    Ok(run_quiet(
+    let output = run_quiet_output(
         docker_cli,
         "Creating container\u{2026}",
         "Unable to create container.",
         &args,
-        false,
         interrupted,
-    )?
-    .trim()
-    .to_owned())
+    )?;
+
+    if output.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        // A common reason for failing to create a container with GPUs is that the NVIDIA Container
+        // Toolkit isn't installed, and Docker's error message doesn't make that obvious.
+        Err(Failure::System(
+            if gpus.is_some() && errors::is_gpu_runtime_missing(&stderr) {
+                format!(
+                    "Unable to create container.\n{stderr}\nThe task requests GPUs, but Docker \
+                     doesn't seem to have a GPU runtime. Perhaps you need to install the NVIDIA \
+                     Container Toolkit.",
+                )
+            } else {
+                format!("Unable to create container.\n{stderr}")
+            },
+            None,
+        ))
+    }
 }
 
-// Copy files into a container.
+// Copy files into a container. The paths in the archive are relative to `destination_acr`.
 pub fn copy_into_container<R: Read>(
-    docker_cli: &str,
+    docker_cli: &(impl DockerCli + ?Sized),
     container: &str,
+    destination_acr: &UnixPath,
     mut tar: R,
     interrupted: &Arc<AtomicBool>,
 ) -> Result<(), Failure> {
@@ -185,15 +878,55 @@ pub fn copy_into_container<R: Read>(
             "container".to_owned(),
             "cp".to_owned(),
             "-".to_owned(),
-            format!("{container}:/"),
+            format!("{container}:{}", destination_acr.to_string_lossy()),
         ],
         false,
-        |mut stdin| {
-            io::copy(&mut tar, &mut stdin)
-                .map_err(failure::system("Unable to copy files into the container."))?;
-
-            Ok(())
-        },
+        &mut tar,
+        interrupted,
+    )
+    .map(|_| ())
+}
+
+// The command a container runs for `copy_via_exec`, so it stays up while the input files are copied
+// in and the task's command runs via `docker exec`. Stopping the container ends it.
+pub const KEEP_ALIVE_COMMAND: &str = "while true; do sleep 3600; done";
+
+// Copy files into a running container by piping them to `tar` in it, rather than with `docker
+// container cp`. This works with images (e.g., based on BusyBox) and engines whose handling of
+// `docker container cp` differs. The paths in the archive are relative to `destination_acr`.
+pub fn copy_into_container_via_exec<R: Read>(
+    docker_cli: &(impl DockerCli + ?Sized),
+    container: &str,
+    destination_acr: &UnixPath,
+    mut tar: R,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    debug!(
+        "Copying files into container {} via {}\u{2026}",
+        container.code_str(),
+        "tar".code_str(),
+    );
+
+    run_quiet_stdin(
+        docker_cli,
+        "Copying files into container\u{2026}",
+        "Unable to copy files into the container.",
+        &[
+            "container".to_owned(),
+            "exec".to_owned(),
+            "--interactive".to_owned(),
+            "--user".to_owned(),
+            "root".to_owned(),
+            container.to_owned(),
+            "tar".to_owned(),
+            "-x".to_owned(),
+            "-f".to_owned(),
+            "-".to_owned(),
+            "-C".to_owned(),
+            destination_acr.to_string_lossy().into_owned(),
+        ],
+        false,
+        &mut tar,
         interrupted,
     )
     .map(|_| ())
@@ -240,7 +973,20 @@ fn rename_or_copy_file_or_symlink(
                 None,
             ));
         } else {
-            // It's a file. Copy it to the destination.
+            // It's a file. Copy it to the destination. The `rename` only fails across filesystems,
+            // so reflinks (which only work within a filesystem) can't help here. But `copy` already
+            // uses the fastest mechanism the platform offers, such as `copy_file_range` on Linux
+            // (which keeps the data in the kernel) and `fclonefileat`/`fcopyfile` on macOS.
+            if metadata.len() >= LARGE_COPY_THRESHOLD_BYTES {
+                info!(
+                    "Copying {} ({}) to {}, since it's on a different filesystem. This may take a \
+                    while\u{2026}",
+                    source_path.to_string_lossy().code_str(),
+                    units::format_bytes(metadata.len()),
+                    destination_path.to_string_lossy().code_str(),
+                );
+            }
+
             copy(source_path, destination_path).map_err(failure::system(format!(
                 "Unable to move or copy file {} to destination {}.",
                 source_path.to_string_lossy().code_str(),
@@ -253,15 +999,462 @@ fn rename_or_copy_file_or_symlink(
     Ok(())
 }
 
-// Copy files from a container.
+// Identify the file behind the given metadata if it has other hard links, so the links can be
+// recreated at the destination. Returns `None` for anything else, including on Windows.
+#[cfg(unix)]
+fn hard_link_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    (metadata.is_file() && metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(windows)]
+#[allow(clippy::unnecessary_wraps)]
+fn hard_link_id(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+// Move or copy a file or symlink into place with `rename_or_copy_file_or_symlink`. If it's a hard
+// link to a file which was already moved into place (according to `hard_links`), link to that
+// file instead. Otherwise a copy would break the link and take up the space twice.
+fn place_file_or_symlink(
+    source_path: &Path,
+    destination_path: &Path,
+    metadata: &Metadata,
+    hard_links: &mut HashMap<(u64, u64), PathBuf>,
+) -> Result<(), Failure> {
+    let id = hard_link_id(metadata);
+
+    // Check if another link to this file was already moved into place.
+    if let Some(existing_path) = id.and_then(|id| hard_links.get(&id)) {
+        match hard_link(existing_path, destination_path) {
+            Ok(()) => return Ok(()),
+            Err(error) => debug!(
+                "Unable to link {} to {}. Moving it instead. Details: {}",
+                destination_path.to_string_lossy().code_str(),
+                existing_path.to_string_lossy().code_str(),
+                error,
+            ),
+        }
+    }
+
+    rename_or_copy_file_or_symlink(source_path, destination_path, metadata)?;
+
+    // Remember where this file went, so its other links can point to it.
+    if let Some(id) = id {
+        hard_links
+            .entry(id)
+            .or_insert_with(|| destination_path.to_owned());
+    }
+
+    Ok(())
+}
+
+// Give a file or directory on the host the permissions that its counterpart had in the container.
+// A `rename` or `copy` preserves the mode of a file, but directories are created according to the
+// umask. This must not be called for symbolic links, since it would affect their targets instead.
+#[cfg_attr(windows, allow(unused_variables))]
+fn propagate_permissions(path: &Path, metadata: &Metadata) -> Result<(), Failure> {
+    #[cfg(unix)]
+    set_permissions(path, metadata.permissions()).map_err(failure::system(format!(
+        "Unable to set the permissions of {}.",
+        path.to_string_lossy().code_str(),
+    )))?;
+
+    #[cfg(windows)]
+    debug!(
+        "Skipping setting the permissions of {} on Windows.",
+        path.to_string_lossy().code_str(),
+    );
+
+    Ok(())
+}
+
+// Determine where an output path is located in the container and where it should be copied to on
+// the host. An absolute path is used as is in the container.
+pub fn output_path_mapping(
+    path: &UnixPath,
+    source_dir: &UnixPath,
+    destination_dir: &Path,
+) -> Result<(UnixPathBuf, PathBuf), Failure> {
+    let source = source_dir.join(path);
+    let host_path = output_host_path(path);
+    let destination = destination_dir.join(host_path.try_as_ref().ok_or_else(|| {
+        Failure::User(
+            format!("Invalid path {}", path.to_string_lossy().code_str()),
+            None,
+        )
+    })?);
+
+    Ok((source, destination))
+}
+
+// The paths written on the host while copying files out of a container, so what a copy which fails
+// midway leaves behind can be cleaned up [tag:partial_copy_cleanup]
+#[derive(Default)]
+struct WrittenPaths {
+    // Paths which didn't exist before the copy, in the order they were written
+    created: Vec<PathBuf>,
+
+    // Files which were replaced. Their old contents are gone, so they can only be reported.
+    overwritten: Vec<PathBuf>,
+}
+
+impl WrittenPaths {
+    // Record that a directory is about to be created, along with any missing ancestors. Only the
+    // outermost missing one is recorded, since removing it removes the rest.
+    fn directory(&mut self, path: &Path) {
+        let outermost = path
+            .ancestors()
+            .take_while(|ancestor| {
+                !ancestor.as_os_str().is_empty() && symlink_metadata(ancestor).is_err()
+            })
+            .last();
+
+        if let Some(outermost) = outermost {
+            self.created.push(outermost.to_owned());
+        }
+    }
+
+    // Record that a file or symbolic link is about to be written.
+    fn file(&mut self, path: &Path) {
+        if symlink_metadata(path).is_ok() {
+            self.overwritten.push(path.to_owned());
+        } else {
+            self.created.push(path.to_owned());
+        }
+    }
+
+    // Remove the paths which were created, most recent first, and mention the files which were
+    // overwritten in the failure. Problems with removing the paths are only logged, since the
+    // failure is what the user needs to know about.
+    fn clean_up(&self, failure: Failure) -> Failure {
+        for path in self.created.iter().rev() {
+            let result = match symlink_metadata(path) {
+                Ok(metadata) if metadata.is_dir() => remove_dir_all(path),
+                Ok(_) => remove_file(path),
+                Err(_) => continue,
+            };
+
+            if let Err(error) = result {
+                debug!(
+                    "Unable to remove {}. Details: {}",
+                    path.to_string_lossy().code_str(),
+                    error,
+                );
+            }
+        }
+
+        if self.overwritten.is_empty() {
+            return failure;
+        }
+
+        let message = format!(
+            "Unable to copy all the files from the container, so the outputs may be a mix of old \
+             and new files. These were already overwritten: {}.",
+            format::series(
+                &self
+                    .overwritten
+                    .iter()
+                    .map(|path| path.to_string_lossy().code_str().to_string())
+                    .collect::<Vec<_>>(),
+            ),
+        );
+
+        match failure {
+            Failure::System(_, _) => Failure::System(message, Some(Box::new(failure))),
+            Failure::User(_, _) => Failure::User(message, Some(Box::new(failure))),
+            Failure::Interrupted => {
+                warn!("{}", message);
+                Failure::Interrupted
+            }
+        }
+    }
+}
+
+// A symbolic link which was copied out of a container
+struct ExtractedSymlink {
+    // The absolute path of the symbolic link in the container
+    path_acr: UnixPathBuf,
+
+    // Where the symbolic link was written on the host
+    destination: PathBuf,
+
+    // The target of the symbolic link
+    target: UnixPathBuf,
+}
+
+// Lexically normalize an absolute path in the container, resolving `.` and `..` components.
+fn normalize_acr(path: &UnixPath) -> UnixPathBuf {
+    let mut normalized = UnixPathBuf::from("/");
+    for component in path.components() {
+        match component {
+            UnixComponent::Normal(name) => normalized.push(name),
+            UnixComponent::ParentDir => {
+                normalized.pop();
+            }
+            UnixComponent::CurDir | UnixComponent::RootDir => {}
+        }
+    }
+    normalized
+}
+
+// Determine the absolute path in the container that a symbolic link points to.
+fn symlink_target_acr(path_acr: &UnixPath, target: &UnixPath) -> UnixPathBuf {
+    if target.is_absolute() {
+        normalize_acr(target)
+    } else {
+        // The `unwrap` is safe because a symbolic link can't be the root directory.
+        normalize_acr(&path_acr.parent().unwrap().join(target))
+    }
+}
+
+// Determine whether a symbolic link points to something that was extracted along with it. The
+// `extracted_acr` paths are the absolute paths in the container which were copied to the host.
+fn symlink_target_extracted(
+    path_acr: &UnixPath,
+    target: &UnixPath,
+    extracted_acr: &[UnixPathBuf],
+) -> bool {
+    let target_acr = symlink_target_acr(path_acr, target);
+    extracted_acr
+        .iter()
+        .any(|extracted| target_acr.starts_with(normalize_acr(extracted)))
+}
+
+// Convert a relative host path into a relative container path.
+fn host_to_unix_path(path: &Path) -> UnixPathBuf {
+    let mut result = UnixPathBuf::new();
+    for component in path.components() {
+        result.push(component.as_os_str().to_string_lossy().as_bytes());
+    }
+    result
+}
+
+// Move a file, symlink, or directory which was copied out of a container from a temporary location
+// to its final destination. Any symbolic links encountered are recorded in `symlinks`.
+fn move_into_place(
+    intermediate: &Path,
+    destination: &Path,
+    source_acr: &UnixPath,
+    symlinks: &mut Vec<ExtractedSymlink>,
+    written: &mut WrittenPaths,
+) -> Result<(), Failure> {
+    // Fetch filesystem metadata for `intermediate`.
+    let intermediate_metadata =
+        symlink_metadata(intermediate).map_err(failure::system(format!(
+            "Unable to fetch filesystem metadata for {}.",
+            intermediate.to_string_lossy().code_str(),
+        )))?;
+
+    // Determine what we got from the container.
+    if intermediate_metadata.is_dir() {
+        // The permissions of the directories are set after everything is moved into place, since a
+        // directory might not be writable.
+        let mut directories = vec![];
+
+        // Where the files with multiple hard links were moved to, by device and inode
+        let mut hard_links = HashMap::new();
+
+        // It's a directory. Traverse it.
+        for entry in WalkDir::new(intermediate) {
+            // If we run into an error traversing the filesystem, report it.
+            let entry = entry.map_err(failure::system(format!(
+                "Unable to traverse directory {}.",
+                intermediate.to_string_lossy().code_str(),
+            )))?;
+
+            // Fetch the metadata for this entry.
+            let entry_metadata = entry.metadata().map_err(failure::system(format!(
+                "Unable to fetch filesystem metadata for {}.",
+                entry.path().to_string_lossy().code_str(),
+            )))?;
+
+            // Figure out what needs to go where. The `unwrap` is safe because `entry` is
+            // guaranteed to be inside `intermediate` (or equal to it).
+            let entry_source_path = entry.path();
+            let entry_relative_path = entry_source_path.strip_prefix(intermediate).unwrap();
+            let entry_destination_path = destination.join(entry_relative_path);
+
+            // Check if the entry is a file or a directory.
+            if entry.file_type().is_dir() {
+                // It's a directory. Create a directory at the destination.
+                written.directory(&entry_destination_path);
+                create_dir_all(&entry_destination_path).map_err(failure::system(format!(
+                    "Unable to create directory {}.",
+                    entry_destination_path.to_string_lossy().code_str(),
+                )))?;
+                directories.push((entry_destination_path, entry_metadata));
+            } else {
+                // If it's a symlink, remember where it points.
+                record_symlink(
+                    entry_source_path,
+                    &entry_metadata,
+                    &source_acr.join(host_to_unix_path(entry_relative_path)),
+                    &entry_destination_path,
+                    symlinks,
+                );
+
+                // It's a file or symlink. Move or copy it to the destination.
+                written.file(&entry_destination_path);
+                place_file_or_symlink(
+                    entry_source_path,
+                    &entry_destination_path,
+                    &entry_metadata,
+                    &mut hard_links,
+                )?;
+                if !entry_metadata.file_type().is_symlink() {
+                    propagate_permissions(&entry_destination_path, &entry_metadata)?;
+                }
+            }
+        }
+
+        // Set the permissions of the directories, starting with the innermost ones.
+        for (path, metadata) in directories.iter().rev() {
+            propagate_permissions(path, metadata)?;
+        }
+    } else {
+        // It's a file or symlink. Determine the destination directory. The `unwrap` is safe
+        // because the root of the filesystem cannot be a file or symlink.
+        let destination_parent = destination.parent().unwrap().to_owned();
+
+        // Make sure the destination directory exists.
+        written.directory(&destination_parent);
+        create_dir_all(&destination_parent).map_err(failure::system(format!(
+            "Unable to create directory {}.",
+            destination_parent.to_string_lossy().code_str(),
+        )))?;
+
+        // If it's a symlink, remember where it points.
+        record_symlink(
+            intermediate,
+            &intermediate_metadata,
+            source_acr,
+            destination,
+            symlinks,
+        );
+
+        // Move or copy it to the destination.
+        written.file(destination);
+        rename_or_copy_file_or_symlink(intermediate, destination, &intermediate_metadata)?;
+        if !intermediate_metadata.file_type().is_symlink() {
+            propagate_permissions(destination, &intermediate_metadata)?;
+        }
+    }
+
+    Ok(())
+}
+
+// If a path is a symbolic link, record where it points.
+fn record_symlink(
+    path: &Path,
+    metadata: &Metadata,
+    path_acr: &UnixPath,
+    destination: &Path,
+    symlinks: &mut Vec<ExtractedSymlink>,
+) {
+    if metadata.file_type().is_symlink() {
+        if let Ok(target) = std::fs::read_link(path) {
+            symlinks.push(ExtractedSymlink {
+                path_acr: normalize_acr(path_acr),
+                destination: destination.to_owned(),
+                target: host_to_unix_path(&target),
+            });
+        }
+    }
+}
+
+// Copy files from a container. Symbolic links which point outside the extracted paths would dangle
+// on the host. If `resolve_symlinks` is `true`, such links are replaced with a copy of what they
+// point to in the container. Otherwise, a warning is logged for each of them.
+#[allow(clippy::too_many_arguments)]
 pub fn copy_from_container(
-    docker_cli: &str,
+    docker_cli: &(impl DockerCli + ?Sized),
     container: &str,
     paths: &[UnixPathBuf],
     source_dir: &UnixPath,
     destination_dir: &Path,
+    resolve_symlinks: bool,
     interrupted: &Arc<AtomicBool>,
 ) -> Result<(), Failure> {
+    copy_paths_from_container(
+        container,
+        paths,
+        source_dir,
+        destination_dir,
+        resolve_symlinks,
+        |error, args| {
+            run_quiet(
+                docker_cli,
+                "Copying files from the container\u{2026}",
+                error,
+                args,
+                true,
+                interrupted,
+            )
+            .map(|_| ())
+        },
+    )
+}
+
+// Like `copy_from_container`, but for when the user has already interrupted the program. Waiting
+// for each copy to finish would make quitting hang if the copy gets stuck, so any copy which is
+// still running at the `deadline` is killed and reported as a failure.
+pub fn copy_from_container_until(
+    docker_cli: &(impl DockerCli + ?Sized),
+    container: &str,
+    paths: &[UnixPathBuf],
+    source_dir: &UnixPath,
+    destination_dir: &Path,
+    resolve_symlinks: bool,
+    deadline: Instant,
+) -> Result<(), Failure> {
+    copy_paths_from_container(
+        container,
+        paths,
+        source_dir,
+        destination_dir,
+        resolve_symlinks,
+        |error, args| run_until(docker_cli, error, args, deadline),
+    )
+}
+
+// Copy files from a container, using `run_cp` to run each `docker container cp` command. It takes
+// the error message to use if the command fails, along with the arguments. If the copy fails
+// midway, the files it created are removed [ref:partial_copy_cleanup].
+fn copy_paths_from_container(
+    container: &str,
+    paths: &[UnixPathBuf],
+    source_dir: &UnixPath,
+    destination_dir: &Path,
+    resolve_symlinks: bool,
+    run_cp: impl FnMut(&str, &[String]) -> Result<(), Failure>,
+) -> Result<(), Failure> {
+    let mut written = WrittenPaths::default();
+
+    copy_paths_from_container_tracked(
+        container,
+        paths,
+        source_dir,
+        destination_dir,
+        resolve_symlinks,
+        run_cp,
+        &mut written,
+    )
+    .map_err(|failure| written.clean_up(failure))
+}
+
+// Like `copy_paths_from_container`, but keeping track of the paths it writes in `written`.
+fn copy_paths_from_container_tracked(
+    container: &str,
+    paths: &[UnixPathBuf],
+    source_dir: &UnixPath,
+    destination_dir: &Path,
+    resolve_symlinks: bool,
+    mut run_cp: impl FnMut(&str, &[String]) -> Result<(), Failure>,
+    written: &mut WrittenPaths,
+) -> Result<(), Failure> {
+    // This will accumulate the symbolic links that were extracted.
+    let mut symlinks = vec![];
+
     // Copy each path from the container to the host.
     for path in paths {
         debug!(
@@ -282,19 +1475,11 @@ pub fn copy_from_container(
             tempdir().map_err(failure::system("Unable to create temporary directory."))?;
 
         // Figure out what needs to go where.
-        let source = source_dir.join(path);
+        let (source, destination) = output_path_mapping(path, source_dir, destination_dir)?;
         let intermediate = temp_dir.path().join("data");
-        let destination = destination_dir.join(path.try_as_ref().ok_or_else(|| {
-            Failure::User(
-                format!("Invalid path {}", path.to_string_lossy().code_str()),
-                None,
-            )
-        })?);
 
         // Get the path from the container.
-        run_quiet(
-            docker_cli,
-            "Copying files from the container\u{2026}",
+        run_cp(
             "Unable to copy files from the container.",
             &[
                 "container".to_owned(),
@@ -302,501 +1487,3623 @@ pub fn copy_from_container(
                 format!("{}:{}", container, source.to_string_lossy()),
                 intermediate.to_string_lossy().into_owned(),
             ],
-            true,
-            interrupted,
-        )
-        .map(|_| ())?;
+        )?;
 
-        // Fetch filesystem metadata for `input_path`.
-        let intermediate_metadata =
-            symlink_metadata(&intermediate).map_err(failure::system(format!(
-                "Unable to fetch filesystem metadata for {}.",
-                intermediate.to_string_lossy().code_str(),
-            )))?;
+        // Move the files to their final destination.
+        move_into_place(&intermediate, &destination, &source, &mut symlinks, written)?;
+    }
 
-        // Determine what we got from the container.
-        if intermediate_metadata.is_dir() {
-            // It's a directory. Traverse it.
-            for entry in WalkDir::new(&intermediate) {
-                // If we run into an error traversing the filesystem, report it.
-                let entry = entry.map_err(failure::system(format!(
-                    "Unable to traverse directory {}.",
-                    intermediate.to_string_lossy().code_str(),
-                )))?;
+    // Check for symbolic links which point outside the extracted paths.
+    let extracted_acr = paths
+        .iter()
+        .map(|path| source_dir.join(path))
+        .collect::<Vec<_>>();
+    for symlink in symlinks {
+        if symlink_target_extracted(&symlink.path_acr, &symlink.target, &extracted_acr) {
+            continue;
+        }
 
-                // Fetch the metadata for this entry.
-                let entry_metadata = entry.metadata().map_err(failure::system(format!(
-                    "Unable to fetch filesystem metadata for {}.",
-                    entry.path().to_string_lossy().code_str(),
-                )))?;
+        let target_acr = symlink_target_acr(&symlink.path_acr, &symlink.target);
 
-                // Figure out what needs to go where. The `unwrap` is safe because `entry` is
-                // guaranteed to be inside `intermediate` (or equal to it).
-                let entry_source_path = entry.path();
-                let entry_destination_path =
-                    destination.join(entry_source_path.strip_prefix(&intermediate).unwrap());
-
-                // Check if the entry is a file or a directory.
-                if entry.file_type().is_dir() {
-                    // It's a directory. Create a directory at the destination.
-                    create_dir_all(&entry_destination_path).map_err(failure::system(format!(
-                        "Unable to create directory {}.",
-                        entry_destination_path.to_string_lossy().code_str(),
-                    )))?;
-                } else {
-                    // It's a file or symlink. Move or copy it to the destination.
-                    rename_or_copy_file_or_symlink(
-                        entry_source_path,
-                        &entry_destination_path,
-                        &entry_metadata,
-                    )?;
-                }
-            }
-        } else {
-            // It's a file or symlink. Determine the destination directory. The `unwrap` is safe
-            // because the root of the filesystem cannot be a file or symlink.
-            let destination_parent = destination.parent().unwrap().to_owned();
-
-            // Make sure the destination directory exists.
-            create_dir_all(&destination_parent).map_err(failure::system(format!(
-                "Unable to create directory {}.",
-                destination_parent.to_string_lossy().code_str(),
-            )))?;
+        if resolve_symlinks {
+            debug!(
+                "Replacing symbolic link {} with a copy of {}\u{2026}",
+                symlink.destination.to_string_lossy().code_str(),
+                target_acr.to_string_lossy().code_str(),
+            );
 
-            // Move or copy it to the destination.
-            rename_or_copy_file_or_symlink(&intermediate, &destination, &intermediate_metadata)?;
+            // Fetch what the symbolic link points to from the container.
+            let temp_dir =
+                tempdir().map_err(failure::system("Unable to create temporary directory."))?;
+            let intermediate = temp_dir.path().join("data");
+            run_cp(
+                "Unable to copy the target of a symbolic link from the container.",
+                &[
+                    "container".to_owned(),
+                    "cp".to_owned(),
+                    "--follow-link".to_owned(),
+                    format!("{}:{}", container, target_acr.to_string_lossy()),
+                    intermediate.to_string_lossy().into_owned(),
+                ],
+            )?;
+
+            // Replace the symbolic link with the copy.
+            std::fs::remove_file(&symlink.destination).map_err(failure::system(format!(
+                "Unable to remove symbolic link {}.",
+                symlink.destination.to_string_lossy().code_str(),
+            )))?;
+            move_into_place(
+                &intermediate,
+                &symlink.destination,
+                &target_acr,
+                &mut vec![],
+                written,
+            )?;
+        } else {
+            warn!(
+                "The symbolic link {} points to {}, which was not copied out of the container. \
+                 Set {} to copy it instead.",
+                symlink.destination.to_string_lossy().code_str(),
+                symlink.target.to_string_lossy().code_str(),
+                "resolve_output_symlinks: true".code_str(),
+            );
         }
     }
 
     Ok(())
 }
 
-// Start a container.
+// Start a container. If `max_log_lines` is provided, only that many lines of output at the end are
+// displayed.
 pub fn start_container(
-    docker_cli: &str,
+    docker_cli: &(impl DockerCli + ?Sized),
     container: &str,
+    max_log_lines: Option<usize>,
+    output_prefix: Option<&str>,
     interrupted: &Arc<AtomicBool>,
 ) -> Result<(), Failure> {
     debug!("Starting container {}\u{2026}", container.code_str());
 
-    run_loud(
+    run_attached(
         docker_cli,
         "Unable to start container.",
         &vec!["container", "start", "--attach", container]
             .into_iter()
             .map(std::borrow::ToOwned::to_owned)
             .collect::<Vec<_>>(),
-        true,
+        max_log_lines,
+        output_prefix,
         interrupted,
     )
 }
 
-// Stop a container.
-pub fn stop_container(
-    docker_cli: &str,
+// Run a command in a running container via `docker exec` and wait for it to finish, just as
+// `start_container` runs the main command of a container. This is how the command of a task with
+// `copy_via_exec` runs.
+#[allow(clippy::too_many_arguments)]
+pub fn exec_in_container(
+    docker_cli: &(impl DockerCli + ?Sized),
     container: &str,
+    environment: &HashMap<String, String>,
+    location: &UnixPath,
+    user: &str,
+    command: &str,
+    max_log_lines: Option<usize>,
+    output_prefix: Option<&str>,
     interrupted: &Arc<AtomicBool>,
 ) -> Result<(), Failure> {
-    debug!("Stopping container {}\u{2026}", container.code_str());
+    debug!(
+        "Running the command in container {}\u{2026}",
+        container.code_str(),
+    );
 
-    run_quiet(
+    run_attached(
         docker_cli,
-        "Stopping container\u{2026}",
-        "Unable to stop container.",
-        &vec!["container", "stop", container]
-            .into_iter()
-            .map(std::borrow::ToOwned::to_owned)
-            .collect::<Vec<_>>(),
-        false,
+        "The command failed.",
+        &exec_args(container, environment, location, user, command),
+        max_log_lines,
+        output_prefix,
         interrupted,
     )
-    .map(|_| ())
 }
 
-// Commit a container to an image.
-pub fn commit_container(
-    docker_cli: &str,
+// Run a command whose output is the output of a task. If `max_log_lines` is provided, only that
+// many lines of output at the end are displayed. Each line is preceded by `output_prefix`, if
+// provided.
+fn run_attached(
+    docker_cli: &(impl DockerCli + ?Sized),
+    error: &str,
+    args: &[String],
+    max_log_lines: Option<usize>,
+    output_prefix: Option<&str>,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    if let Some(max_log_lines) = max_log_lines {
+        run_tail(
+            docker_cli,
+            error,
+            args,
+            max_log_lines,
+            output_prefix.unwrap_or(""),
+            true,
+            interrupted,
+        )
+    } else if let Some(output_prefix) = output_prefix {
+        run_prefixed(docker_cli, error, args, output_prefix, true, interrupted)
+    } else {
+        run_loud(docker_cli, error, args, true, interrupted)
+    }
+}
+
+// Start a container in the background, without waiting for it to finish.
+pub fn start_container_in_background(
+    docker_cli: &(impl DockerCli + ?Sized),
     container: &str,
-    image: &str,
     interrupted: &Arc<AtomicBool>,
 ) -> Result<(), Failure> {
     debug!(
-        "Committing container {} to image {}\u{2026}",
+        "Starting container {} in the background\u{2026}",
         container.code_str(),
-        image.code_str(),
     );
 
     run_quiet(
         docker_cli,
-        "Committing container\u{2026}",
-        "Unable to commit container.",
-        &vec!["container", "commit", container, image]
+        "Starting container\u{2026}",
+        "Unable to start container.",
+        &vec!["container", "start", container]
             .into_iter()
             .map(std::borrow::ToOwned::to_owned)
             .collect::<Vec<_>>(),
-        false,
+        true,
         interrupted,
     )
     .map(|_| ())
 }
 
-// Delete a container.
-pub fn delete_container(
-    docker_cli: &str,
+// Start a container in the background, run some commands in it once it's up, and then wait for it
+// to finish. The output of the container is streamed to the terminal in the meantime. If any of the
+// commands fail, the container is stopped and an error is returned.
+#[allow(clippy::too_many_arguments)]
+pub fn start_container_with_exec(
+    docker_cli: &(impl DockerCli + ?Sized),
     container: &str,
+    commands: &[String],
+    environment: &HashMap<String, String>,
+    location: &UnixPath,
+    user: &str,
     interrupted: &Arc<AtomicBool>,
 ) -> Result<(), Failure> {
-    debug!("Deleting container {}\u{2026}", container.code_str());
+    start_container_in_background(docker_cli, container, interrupted)?;
 
-    run_quiet(
-        docker_cli,
-        "Deleting container\u{2026}",
-        "Unable to delete container.",
-        &vec!["container", "rm", "--force", container]
-            .into_iter()
-            .map(std::borrow::ToOwned::to_owned)
-            .collect::<Vec<_>>(),
-        false,
-        interrupted,
-    )
-    .map(|_| ())
+    // Stream the output of the container. This process exits on its own when the container stops.
+    let mut logs = docker_cli
+        .spawn(
+            &vec!["container", "logs", "--follow", container]
+                .into_iter()
+                .map(std::borrow::ToOwned::to_owned)
+                .collect::<Vec<_>>(),
+            true,
+        )
+        .map_err(failure::system(
+            "Unable to stream container output. Perhaps you don't have Docker installed.",
+        ))?;
+
+    // Wait for the log streaming process when we're done.
+    defer! {{
+        drop(logs.wait());
+    }}
+
+    // Run each command in the container.
+    for exec_command in commands {
+        debug!(
+            "Running {} in container {}\u{2026}",
+            exec_command.code_str(),
+            container.code_str(),
+        );
+
+        if let Err(e) = run_loud(
+            docker_cli,
+            "A command in the running container failed.",
+            &exec_args(container, environment, location, user, exec_command),
+            true,
+            interrupted,
+        ) {
+            if let Err(e) = stop_container(docker_cli, container, interrupted) {
+                error!("{}", e);
+            }
+
+            return Err(e);
+        }
+    }
+
+    // Wait for the container to finish and determine whether it succeeded.
+    let exit_code = run_quiet(
+        docker_cli,
+        "Waiting for container\u{2026}",
+        "Unable to wait for container.",
+        &vec!["container", "wait", container]
+            .into_iter()
+            .map(std::borrow::ToOwned::to_owned)
+            .collect::<Vec<_>>(),
+        false,
+        interrupted,
+    )?;
+
+    if exit_code.trim() == "0" {
+        Ok(())
+    } else {
+        Err(Failure::User("Unable to start container.".to_owned(), None))
+    }
 }
 
-// Run an interactive shell.
-#[allow(clippy::too_many_arguments)]
-pub fn spawn_shell(
-    docker_cli: &str,
-    image: &str,
-    source_dir: &Path,
+// This function returns arguments for `docker exec`. The command is run via `/bin/su` as the given
+// user, just like the main command of the container.
+fn exec_args(
+    container: &str,
     environment: &HashMap<String, String>,
     location: &UnixPath,
-    mount_paths: &[MappingPath],
-    mount_readonly: bool,
-    ports: &[String],
     user: &str,
-    extra_args: &[String],
-    interrupted: &Arc<AtomicBool>,
-) -> Result<(), Failure> {
-    debug!(
-        "Spawning an interactive shell for image {}\u{2026}",
-        image.code_str(),
-    );
-
-    let mut args = vec!["container", "run", "--rm", "--interactive", "--tty"]
+    command: &str,
+) -> Vec<String> {
+    let mut args = vec!["container", "exec", "--user", "root", "--workdir"]
         .into_iter()
         .map(std::borrow::ToOwned::to_owned)
         .collect::<Vec<_>>();
 
-    args.extend(container_args(
-        source_dir,
-        environment,
-        location,
-        mount_paths,
-        mount_readonly,
-        ports,
-        extra_args,
-    )?);
+    args.push(location.to_string_lossy().into_owned());
+
+    // Sort the variables to make the arguments deterministic.
+    let mut variables = environment.iter().collect::<Vec<_>>();
+    variables.sort();
+    args.extend(
+        variables
+            .into_iter()
+            .flat_map(|(variable, value)| vec!["--env".to_owned(), format!("{variable}={value}")]),
+    );
 
     args.extend(
-        vec![image, "/bin/su", user]
+        vec![container, "/bin/su", "-c", command, user]
             .into_iter()
             .map(std::borrow::ToOwned::to_owned)
             .collect::<Vec<_>>(),
     );
 
-    run_attach(
-        docker_cli,
-        "The shell exited with a failure.",
-        &args,
-        true,
-        interrupted,
-    )
+    args
 }
 
-// This function returns arguments for `docker create` or `docker run`.
-fn container_args(
-    source_dir: &Path,
-    environment: &HashMap<String, String>,
-    location: &UnixPath,
-    mount_paths: &[MappingPath],
-    mount_readonly: bool,
-    ports: &[String],
-    extra_args: &[String],
-) -> Result<Vec<String>, Failure> {
-    // Why `--init`? (1) PID 1 is supposed to reap orphaned zombie processes, otherwise they can
-    // accumulate. Bash does this, but we run `/bin/sh` in the container, which may or may not be
-    // Bash. So `--init` runs Tini (https://github.com/krallin/tini) as PID 1, which properly reaps
-    // orphaned zombies. (2) PID 1 also does not exhibit the default behavior (crashing) for signals
-    // like SIGINT and SIGTERM. However, PID 1 can still handle these signals by explicitly trapping
-    // them. Tini traps these signals and forwards them to the child process. Then the default
-    // signal handling behavior of the child process (in our case, `/bin/sh`) works normally.
-    let mut args = vec!["--init".to_owned()];
+// This struct stops a container from a background thread if it's still running after a timeout.
+pub struct Watchdog {
+    done: Sender<()>,
+    thread: JoinHandle<bool>,
+}
 
-    // Run as the `root` user. We always run `/bin/su` in the container, which switches to the user
-    // specified in the toastfile. We want to run `/bin/su` as root so it can switch users without
-    // requiring a password. Most Docker images already use `root` as the default user, but not
-    // all.
-    args.extend(vec!["--user".to_owned(), "root".to_owned()]);
+impl Watchdog {
+    // Start watching a container. The timeout starts now.
+    pub fn start(docker_cli: &str, container: &str, timeout: Duration) -> Self {
+        let (done, finished) = channel();
 
-    // Environment
-    args.extend(
-        environment.iter().flat_map(|(variable, value)| {
-            vec!["--env".to_owned(), format!("{}={}", variable, value)]
-        }),
-    );
+        let docker_cli = docker_cli.to_owned();
+        let container = container.to_owned();
+        let thread = thread::spawn(move || match finished.recv_timeout(timeout) {
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => false,
+            Err(RecvTimeoutError::Timeout) => {
+                warn!(
+                    "Stopping container {} after {}\u{2026}",
+                    container.code_str(),
+                    units::format_duration(timeout),
+                );
 
-    // Location
-    args.extend(vec![
-        "--workdir".to_owned(),
-        location.to_string_lossy().into_owned(),
-    ]);
+                // The user didn't interrupt anything, so this gets its own flag.
+                if let Err(e) =
+                    stop_container(&*docker_cli, &container, &Arc::new(AtomicBool::new(false)))
+                {
+                    error!("{}", e);
+                }
 
-    // For bind mounts, Docker requires the host path to be absolute. We can't
-    // use `std::fs::canonicalize` here, since on Windows that generates an
-    // extended-length path (e.g., `\\?\C:\Users\...`) which Docker doesn't
-    // understand.
-    let absolute_source_dir = current_dir()
-        .map_err(failure::user(
-            "Unable to determine the current working directory.",
-        ))?
-        .join(source_dir);
+                true
+            }
+        });
 
-    // Mount paths
-    args.extend(mount_paths.iter().flat_map(|mount_path| {
-        // [ref:mount_paths_no_commas]
-        vec![
-            "--mount".to_owned(),
-            if mount_readonly {
-                format!(
-                    "type=bind,source={},target={},readonly",
-                    absolute_source_dir
-                        .join(&mount_path.host_path)
-                        .to_string_lossy(),
-                    location.join(&mount_path.container_path).to_string_lossy(),
-                )
-            } else {
-                format!(
-                    "type=bind,source={},target={}",
-                    absolute_source_dir
-                        .join(&mount_path.host_path)
-                        .to_string_lossy(),
-                    location.join(&mount_path.container_path).to_string_lossy(),
-                )
-            },
-        ]
-    }));
+        Self { done, thread }
+    }
 
-    // Ports
-    args.extend(
-        ports
-            .iter()
-            .flat_map(|port| {
-                vec!["--publish", port]
-                    .into_iter()
-                    .map(std::borrow::ToOwned::to_owned)
-                    .collect::<Vec<_>>()
-            })
+    // Stop watching the container and report whether the timeout expired (in which case the
+    // container was stopped).
+    pub fn finish(self) -> bool {
+        let _ = self.done.send(());
+
+        // The `unwrap` will only fail if a panic already occurred.
+        self.thread.join().unwrap()
+    }
+}
+
+// Stop a container.
+pub fn stop_container(
+    docker_cli: &(impl DockerCli + ?Sized),
+    container: &str,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    debug!("Stopping container {}\u{2026}", container.code_str());
+
+    run_quiet(
+        docker_cli,
+        "Stopping container\u{2026}",
+        "Unable to stop container.",
+        &vec!["container", "stop", container]
+            .into_iter()
+            .map(std::borrow::ToOwned::to_owned)
             .collect::<Vec<_>>(),
-    );
+        false,
+        interrupted,
+    )
+    .map(|_| ())
+}
 
-    // User-provided arguments
-    args.extend_from_slice(extra_args);
+// Pause a container, freezing all of its processes.
+pub fn pause_container(
+    docker_cli: &(impl DockerCli + ?Sized),
+    container: &str,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    debug!("Pausing container {}\u{2026}", container.code_str());
 
-    Ok(args)
+    run_quiet(
+        docker_cli,
+        "Pausing container\u{2026}",
+        "Unable to pause container.",
+        &vec!["container", "pause", container]
+            .into_iter()
+            .map(std::borrow::ToOwned::to_owned)
+            .collect::<Vec<_>>(),
+        false,
+        interrupted,
+    )
+    .map(|_| ())
 }
 
-// Run a command and return its standard output.
-fn run_quiet(
-    docker_cli: &str,
-    spinner_message: &str,
-    error: &str,
-    args: &[String],
-    user_command: bool,
+// Unpause a container which was paused with `pause_container`.
+pub fn unpause_container(
+    docker_cli: &(impl DockerCli + ?Sized),
+    container: &str,
     interrupted: &Arc<AtomicBool>,
-) -> Result<String, Failure> {
-    // Render a spinner animation and clear it when we're done.
-    let _guard = spin(spinner_message);
+) -> Result<(), Failure> {
+    debug!("Unpausing container {}\u{2026}", container.code_str());
 
-    // This is used to determine whether the user interrupted the program during the execution of
-    // the child process.
-    let was_interrupted = interrupted.load(Ordering::SeqCst);
+    run_quiet(
+        docker_cli,
+        "Unpausing container\u{2026}",
+        "Unable to unpause container.",
+        &vec!["container", "unpause", container]
+            .into_iter()
+            .map(std::borrow::ToOwned::to_owned)
+            .collect::<Vec<_>>(),
+        false,
+        interrupted,
+    )
+    .map(|_| ())
+}
 
-    // Run the child process.
-    let child = command(docker_cli, args)
-        .output()
-        .map_err(failure::system(format!(
-            "{error} Perhaps you don't have Docker installed.",
-        )))?;
+// The fields we need from `docker container stats --format '{{json .}}'`
+#[derive(Deserialize)]
+struct StatsOutput {
+    #[serde(rename = "CPUPerc")]
+    cpu_percent: String,
 
-    // Handle the result.
-    if child.status.success() {
-        Ok(String::from_utf8_lossy(&child.stdout).to_string())
+    #[serde(rename = "MemUsage")]
+    memory_usage: String,
+}
+
+// Parse a size as reported by `docker container stats` (e.g., `3.5MiB` or `12kB`) into bytes.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn parse_stats_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number = number.parse::<f64>().ok()?;
+    let multiplier = match unit.trim() {
+        "B" | "" => 1_f64,
+        "kB" | "KB" => 1e3_f64,
+        "MB" => 1e6_f64,
+        "GB" => 1e9_f64,
+        "TB" => 1e12_f64,
+        "KiB" => 1024_f64,
+        "MiB" => 1024_f64.powi(2),
+        "GiB" => 1024_f64.powi(3),
+        "TiB" => 1024_f64.powi(4),
+        _ => return None,
+    };
+
+    Some((number * multiplier).round() as u64)
+}
+
+// Parse a line of output from `docker container stats --format '{{json .}}'`.
+fn parse_stats_output(output: &str) -> Option<Sample> {
+    // JSON is a subset of YAML, so we can reuse the YAML parser here.
+    let output = serde_yaml::from_str::<StatsOutput>(output.trim()).ok()?;
+
+    // Memory usage is reported as the usage and the limit separated by a slash.
+    let memory_bytes = parse_stats_size(output.memory_usage.split('/').next()?)?;
+    let cpu_percent = output
+        .cpu_percent
+        .trim()
+        .trim_end_matches('%')
+        .parse::<f64>()
+        .ok()?;
+
+    Some(Sample {
+        memory_bytes,
+        cpu_percent,
+    })
+}
+
+// Take a single reading of the resources used by a running container. This is meant to be called
+// from a background thread, so it doesn't render a spinner or report errors. It returns `None` if
+// the container isn't running or the output couldn't be understood.
+pub fn container_stats(docker_cli: &(impl DockerCli + ?Sized), container: &str) -> Option<Sample> {
+    let output = docker_cli
+        .run_quiet(&[
+            "container".to_owned(),
+            "stats".to_owned(),
+            "--no-stream".to_owned(),
+            "--format".to_owned(),
+            "{{json .}}".to_owned(),
+            container.to_owned(),
+        ])
+        .ok()?;
+
+    if output.success() {
+        parse_stats_output(&String::from_utf8_lossy(&output.stdout))
     } else {
-        Err(
-            if child.status.code().is_none()
-                || (!was_interrupted && interrupted.load(Ordering::SeqCst))
-            {
-                interrupted.store(true, Ordering::SeqCst);
-                Failure::Interrupted
-            } else if user_command {
-                Failure::User(
-                    format!("{}\n{}", error, String::from_utf8_lossy(&child.stderr)),
-                    None,
-                )
-            } else {
-                Failure::System(
-                    format!("{}\n{}", error, String::from_utf8_lossy(&child.stderr)),
-                    None,
-                )
-            },
-        )
+        None
     }
 }
 
-// Run a command and return its standard output. Accepts a closure which receives a pipe to the
-// standard input stream of the child process.
-fn run_quiet_stdin<W: FnOnce(&mut ChildStdin) -> Result<(), Failure>>(
-    docker_cli: &str,
-    spinner_message: &str,
-    error: &str,
-    args: &[String],
-    user_command: bool,
-    writer: W,
+// Query the architecture of the Docker daemon (e.g., `amd64`), which determines the variant of a
+// multi-architecture image that gets pulled.
+pub fn server_architecture(
+    docker_cli: &(impl DockerCli + ?Sized),
     interrupted: &Arc<AtomicBool>,
 ) -> Result<String, Failure> {
-    // Render a spinner animation and clear it when we're done.
-    let _guard = spin(spinner_message);
+    debug!("Checking the architecture of the Docker daemon\u{2026}");
 
-    // This is used to determine whether the user interrupted the program during the execution of
-    // the child process.
-    let was_interrupted = interrupted.load(Ordering::SeqCst);
+    let architecture = run_quiet(
+        docker_cli,
+        "Checking the architecture of the Docker daemon\u{2026}",
+        "Unable to determine the architecture of the Docker daemon.",
+        &vec!["version", "--format", "{{.Server.Arch}}"]
+            .into_iter()
+            .map(std::borrow::ToOwned::to_owned)
+            .collect::<Vec<_>>(),
+        false,
+        interrupted,
+    )?
+    .trim()
+    .to_owned();
 
-    // Run the child process.
-    let mut child = command(docker_cli, args)
-        .stdin(Stdio::piped()) // [tag:run_quiet_stdin_piped]
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(failure::system(format!(
-            "{error} Perhaps you don't have Docker installed.",
-        )))?;
+    if architecture.is_empty() {
+        return Err(Failure::System(
+            "Unable to determine the architecture of the Docker daemon.".to_owned(),
+            None,
+        ));
+    }
 
-    // Pipe data to the child's standard input stream.
-    writer(child.stdin.as_mut().unwrap())?; // [ref:run_quiet_stdin_piped]
+    Ok(architecture)
+}
 
-    // Wait for the child to terminate.
-    let output = child.wait_with_output().map_err(failure::system(format!(
-        "{error} Perhaps you don't have Docker installed.",
-    )))?;
+// Wait for the Docker daemon to respond, for environments where it starts concurrently with Toast
+// (e.g., CI). Only failures which indicate that the daemon can't be reached yet are retried.
+pub fn wait_for_daemon(
+    docker_cli: &(impl DockerCli + ?Sized),
+    timeout: Duration,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    debug!("Waiting for the Docker daemon to respond\u{2026}");
 
-    // Handle the result.
-    if output.status.success() {
-        Ok(String::from_utf8_lossy(&output.stdout).to_string())
-    } else {
-        Err(
-            if output.status.code().is_none()
-                || (!was_interrupted && interrupted.load(Ordering::SeqCst))
-            {
-                interrupted.store(true, Ordering::SeqCst);
-                Failure::Interrupted
-            } else if user_command {
-                Failure::User(
-                    format!("{}\n{}", error, String::from_utf8_lossy(&output.stderr)),
-                    None,
-                )
-            } else {
+    let _guard = spin("Waiting for the Docker daemon\u{2026}");
+
+    poll_daemon(timeout, interrupted, Instant::now, sleep, || {
+        let output = docker_cli
+            .run_quiet(
+                &vec!["version", "--format", "{{.Server.Version}}"]
+                    .into_iter()
+                    .map(std::borrow::ToOwned::to_owned)
+                    .collect::<Vec<_>>(),
+            )
+            .map_err(failure::system(
+                "Unable to reach the Docker daemon. Perhaps you don't have Docker installed.",
+            ))?;
+
+        if output.success() {
+            return Ok(None);
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        if errors::is_daemon_unreachable(&stderr) {
+            Ok(Some(stderr))
+        } else {
+            Err(Failure::System(
+                format!("Unable to reach the Docker daemon.\n{stderr}"),
+                None,
+            ))
+        }
+    })
+}
+
+// Call `probe` with exponential backoff until it reports that the daemon is ready (`Ok(None)`), it
+// fails permanently, the deadline passes, or the user interrupts the program. While the daemon is
+// unreachable, `probe` returns the error it printed. The clock and `sleep` are parameters so the
+// loop can be tested without waiting.
+fn poll_daemon(
+    timeout: Duration,
+    interrupted: &Arc<AtomicBool>,
+    mut now: impl FnMut() -> Instant,
+    mut sleep: impl FnMut(Duration),
+    mut probe: impl FnMut() -> Result<Option<String>, Failure>,
+) -> Result<(), Failure> {
+    let start = now();
+    let deadline = start + timeout;
+    let mut delay = DAEMON_POLL_INITIAL_DELAY;
+    let mut next_progress = start + DAEMON_PROGRESS_INTERVAL;
+
+    loop {
+        if interrupted.load(Ordering::SeqCst) {
+            return Err(Failure::Interrupted);
+        }
+
+        let Some(error) = probe()? else {
+            return Ok(());
+        };
+
+        let current = now();
+        if current >= deadline {
+            return Err(Failure::System(
+                format!(
+                    "Timed out after {} waiting for the Docker daemon to respond. Make sure it's \
+                        running, or allow it more time to start.\n{}",
+                    units::format_duration(timeout),
+                    error.trim_end(),
+                ),
+                None,
+            ));
+        }
+
+        if current >= next_progress {
+            info!(
+                "Still waiting for the Docker daemon to respond ({} elapsed)\u{2026}",
+                units::format_duration(current - start),
+            );
+            next_progress = current + DAEMON_PROGRESS_INTERVAL;
+        }
+
+        sleep(delay.min(deadline - current));
+        delay = (delay * 2).min(DAEMON_POLL_MAX_DELAY);
+    }
+}
+
+// Log in to a registry (or Docker Hub, if `registry` is `None`) with a token. The token is passed via
+// standard input so it doesn't show up in the list of processes or the logs.
+pub fn login(
+    docker_cli: &(impl DockerCli + ?Sized),
+    registry: Option<&str>,
+    user: &str,
+    token: &str,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    debug!(
+        "Logging in to {} as {}\u{2026}",
+        registry.unwrap_or("Docker Hub").code_str(),
+        user.code_str(),
+    );
+
+    let mut args = vec!["login", "--username", user, "--password-stdin"];
+    args.extend(registry);
+
+    run_quiet_stdin(
+        docker_cli,
+        "Logging in to the registry\u{2026}",
+        "Unable to log in to the registry.",
+        &args
+            .into_iter()
+            .map(std::borrow::ToOwned::to_owned)
+            .collect::<Vec<_>>(),
+        true,
+        &mut token.as_bytes(),
+        interrupted,
+    )
+    .map(|_| ())
+}
+
+// Commit a container to an image.
+pub fn commit_container(
+    docker_cli: &(impl DockerCli + ?Sized),
+    container: &str,
+    image: &str,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    debug!(
+        "Committing container {} to image {}\u{2026}",
+        container.code_str(),
+        image.code_str(),
+    );
+
+    run_quiet(
+        docker_cli,
+        "Committing container\u{2026}",
+        "Unable to commit container.",
+        &vec!["container", "commit", container, image]
+            .into_iter()
+            .map(std::borrow::ToOwned::to_owned)
+            .collect::<Vec<_>>(),
+        false,
+        interrupted,
+    )
+    .map(|_| ())
+}
+
+// The kind of change to a path in the filesystem of a container, as reported by
+// `docker container diff`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Change {
+    Added,
+    Changed,
+    Deleted,
+}
+
+// Parse the output of `docker container diff`, which has lines like `A /foo/bar.txt`.
+fn parse_diff_output(output: &str) -> Result<Vec<(Change, String)>, Failure> {
+    output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (kind, path) = line.split_once(' ').ok_or_else(|| {
                 Failure::System(
-                    format!("{}\n{}", error, String::from_utf8_lossy(&output.stderr)),
+                    format!(
+                        "Unable to parse the container diff line {}.",
+                        line.code_str()
+                    ),
                     None,
                 )
-            },
-        )
-    }
+            })?;
+            let change = match kind {
+                "A" => Change::Added,
+                "C" => Change::Changed,
+                "D" => Change::Deleted,
+                _ => {
+                    return Err(Failure::System(
+                        format!(
+                            "Unknown kind of change in container diff line {}.",
+                            line.code_str()
+                        ),
+                        None,
+                    ))
+                }
+            };
+            Ok((change, path.to_owned()))
+        })
+        .collect()
 }
 
-// Run a command and inherit standard output and error streams.
-fn run_loud(
-    docker_cli: &str,
-    error: &str,
-    args: &[String],
-    user_command: bool,
+// List the paths which were added, changed, or deleted in a container relative to its image.
+pub fn container_diff(
+    docker_cli: &(impl DockerCli + ?Sized),
+    container: &str,
     interrupted: &Arc<AtomicBool>,
-) -> Result<(), Failure> {
-    // This is used to determine whether the user interrupted the program during the execution of
-    // the child process.
-    let was_interrupted = interrupted.load(Ordering::SeqCst);
+) -> Result<Vec<(Change, String)>, Failure> {
+    debug!(
+        "Comparing container {} to its image\u{2026}",
+        container.code_str(),
+    );
 
-    // Run the child process.
-    let mut child = command(docker_cli, args)
-        .stdin(Stdio::null())
-        .spawn()
-        .map_err(failure::system(format!(
-            "{error} Perhaps you don't have Docker installed.",
-        )))?;
+    parse_diff_output(&run_quiet(
+        docker_cli,
+        "Comparing the container to its image\u{2026}",
+        "Unable to compare the container to its image.",
+        &vec!["container", "diff", container]
+            .into_iter()
+            .map(std::borrow::ToOwned::to_owned)
+            .collect::<Vec<_>>(),
+        false,
+        interrupted,
+    )?)
+}
 
-    // Wait for the child to terminate.
-    let status = child.wait().map_err(failure::system(format!(
-        "{error} Perhaps you don't have Docker installed.",
-    )))?;
+// Describe the changes in a container, grouped by kind. Only the first `limit` changes are listed.
+pub fn describe_diff(changes: &[(Change, String)], limit: usize) -> String {
+    let shown = &changes[..changes.len().min(limit)];
+    let mut description = String::new();
+    for (kind, heading) in [
+        (Change::Added, "Added"),
+        (Change::Changed, "Changed"),
+        (Change::Deleted, "Deleted"),
+    ] {
+        let paths = shown
+            .iter()
+            .filter(|(change, _)| *change == kind)
+            .map(|(_, path)| path)
+            .collect::<Vec<_>>();
+        if !paths.is_empty() {
+            let _ = write!(description, "\n  {heading}:");
+            for path in paths {
+                let _ = write!(description, "\n    {path}");
+            }
+        }
+    }
+    if changes.len() > shown.len() {
+        let _ = write!(
+            description,
+            "\n  \u{2026}and {} more.",
+            changes.len() - shown.len(),
+        );
+    }
+    description
+}
 
-    // Handle the result.
-    if status.success() {
-        Ok(())
-    } else {
-        Err(
-            if status.code().is_none() || (!was_interrupted && interrupted.load(Ordering::SeqCst)) {
-                interrupted.store(true, Ordering::SeqCst);
-                Failure::Interrupted
-            } else if user_command {
-                Failure::User(error.to_owned(), None)
-            } else {
-                Failure::System(error.to_owned(), None)
-            },
-        )
+// The marker for a deleted path in a fingerprint. Real hashes are hexadecimal, so this can't be
+// confused with one.
+const DELETED_MARKER: &str = "deleted";
+
+// Compute a hash of each entry in an exported container filesystem whose path (made absolute) is in
+// `paths`. The hash covers the type, mode, and contents of the entry, but not its modification time
+// or owner.
+fn hash_export(
+    export: &[u8],
+    paths: &BTreeMap<String, String>,
+) -> Result<BTreeMap<String, String>, Failure> {
+    let mut hashes = BTreeMap::new();
+    let mut archive = tar::Archive::new(export);
+    for entry in archive
+        .entries()
+        .map_err(failure::system("Unable to read the exported container."))?
+    {
+        let mut entry = entry.map_err(failure::system("Unable to read the exported container."))?;
+        let path = format!(
+            "/{}",
+            String::from_utf8_lossy(&entry.path_bytes()).trim_matches('/'),
+        );
+        if !paths.contains_key(&path) {
+            continue;
+        }
+
+        let header = entry.header();
+        let mode = header.mode().unwrap_or(0);
+        let contents_hash = if header.entry_type().is_dir() {
+            "directory".crypto_hash()
+        } else if let Some(target) = entry
+            .link_name_bytes()
+            .filter(|_| header.entry_type().is_symlink())
+        {
+            cache::combine("symlink", &*String::from_utf8_lossy(&target))
+        } else {
+            cache::hash_read(&mut entry)?
+        };
+        hashes.insert(path, cache::combine(&format!("{mode:o}"), &contents_hash));
     }
+
+    Ok(hashes)
 }
 
-// Run a command and inherit standard input, output, and error streams.
-fn run_attach(
-    docker_cli: &str,
-    error: &str,
-    args: &[String],
-    user_command: bool,
+// Compute a fingerprint of how the filesystem of a container differs from its image: a map from
+// each added, changed, or deleted path to a hash of its contents (see `hash_export`). This exports
+// the whole filesystem of the container, so it's expensive.
+pub fn container_fingerprint(
+    docker_cli: &(impl DockerCli + ?Sized),
+    container: &str,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<BTreeMap<String, String>, Failure> {
+    debug!("Fingerprinting container {}\u{2026}", container.code_str());
+
+    // Find out which paths changed.
+    let mut fingerprint = container_diff(docker_cli, container, interrupted)?
+        .into_iter()
+        .map(|(change, path)| {
+            (
+                path,
+                if change == Change::Deleted {
+                    DELETED_MARKER.to_owned()
+                } else {
+                    String::new()
+                },
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    // Hash the contents of the paths which weren't deleted.
+    let export = run_quiet_output(
+        docker_cli,
+        "Exporting the container\u{2026}",
+        "Unable to export the container.",
+        &vec!["container", "export", container]
+            .into_iter()
+            .map(std::borrow::ToOwned::to_owned)
+            .collect::<Vec<_>>(),
+        interrupted,
+    )?;
+    if !export.success() {
+        return Err(Failure::System(
+            format!(
+                "Unable to export the container.\n{}",
+                String::from_utf8_lossy(&export.stderr),
+            ),
+            None,
+        ));
+    }
+    fingerprint.extend(hash_export(&export.stdout, &fingerprint)?);
+
+    Ok(fingerprint)
+}
+
+// Delete a container.
+pub fn delete_container(
+    docker_cli: &(impl DockerCli + ?Sized),
+    container: &str,
     interrupted: &Arc<AtomicBool>,
 ) -> Result<(), Failure> {
-    // This is used to determine whether the user interrupted the program during the execution of
-    // the child process.
-    let was_interrupted = interrupted.load(Ordering::SeqCst);
+    debug!("Deleting container {}\u{2026}", container.code_str());
 
-    // Run the child process.
-    let child = command(docker_cli, args)
-        .status()
-        .map_err(failure::system(format!(
-            "{error} Perhaps you don't have Docker installed.",
-        )))?;
+    run_quiet(
+        docker_cli,
+        "Deleting container\u{2026}",
+        "Unable to delete container.",
+        &vec!["container", "rm", "--force", "--volumes", container]
+            .into_iter()
+            .map(std::borrow::ToOwned::to_owned)
+            .collect::<Vec<_>>(),
+        false,
+        interrupted,
+    )
+    .map(|_| ())
+}
 
-    // Handle the result.
-    if child.success() {
-        Ok(())
+// Run an interactive shell.
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_shell(
+    docker_cli: &(impl DockerCli + ?Sized),
+    image: &str,
+    source_dir: &Path,
+    environment: &HashMap<String, String>,
+    location: &UnixPath,
+    mount_paths: &[MappingPath],
+    mount_readonly: bool,
+    volumes: &[Volume],
+    docker_repo: &str,
+    ports: &[Port],
+    gpus: Option<&str>,
+    user: &str,
+    command_prefix: &str,
+    extra_args: &[String],
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    debug!(
+        "Spawning an interactive shell for image {}\u{2026}",
+        image.code_str(),
+    );
+
+    let mut args = vec!["container", "run", "--rm", "--interactive", "--tty"]
+        .into_iter()
+        .map(std::borrow::ToOwned::to_owned)
+        .collect::<Vec<_>>();
+
+    args.extend(container_args(
+        source_dir,
+        environment,
+        location,
+        mount_paths,
+        mount_readonly,
+        volumes,
+        docker_repo,
+        false,
+        ports,
+        gpus,
+        extra_args,
+    )?);
+
+    args.push(image.to_owned());
+    args.extend(shell_args(command_prefix, user));
+
+    run_attach(
+        docker_cli,
+        "The shell exited with a failure.",
+        &args,
+        true,
+        interrupted,
+    )
+}
+
+// This function returns the command which starts an interactive shell as the given user. If there is
+// a command prefix, the shell is started by a script which runs the prefix first, so any environment
+// it sets up is available in the shell. The prefix is followed by a newline rather than a semicolon,
+// just like for tasks [ref:command_prefix_newline], so a trailing comment in the prefix can't hide
+// the rest of the script. The arguments are passed to Docker directly, so no quoting is needed.
+fn shell_args(command_prefix: &str, user: &str) -> Vec<String> {
+    if command_prefix.is_empty() {
+        vec!["/bin/su".to_owned(), user.to_owned()]
     } else {
-        Err(
-            if child.code().is_none() || (!was_interrupted && interrupted.load(Ordering::SeqCst)) {
-                interrupted.store(true, Ordering::SeqCst);
-                Failure::Interrupted
-            } else if user_command {
-                Failure::User(error.to_owned(), None)
-            } else {
-                Failure::System(error.to_owned(), None)
-            },
-        )
+        vec![
+            "/bin/su".to_owned(),
+            "-c".to_owned(),
+            format!("{command_prefix}\nexec \"${{SHELL:-/bin/sh}}\" -i"),
+            user.to_owned(),
+        ]
     }
 }
 
-// Construct a Docker `Command` from an array of arguments.
-fn command(docker_cli: &str, args: &[String]) -> Command {
-    let mut command = Command::new(docker_cli);
-    for arg in args {
-        command.arg(arg);
+// This function returns arguments for `docker create` or `docker run`.
+#[allow(clippy::too_many_arguments)]
+fn container_args(
+    source_dir: &Path,
+    environment: &HashMap<String, String>,
+    location: &UnixPath,
+    mount_paths: &[MappingPath],
+    mount_readonly: bool,
+    volumes: &[Volume],
+    docker_repo: &str,
+    read_only_rootfs: bool,
+    ports: &[Port],
+    gpus: Option<&str>,
+    extra_args: &[String],
+) -> Result<Vec<String>, Failure> {
+    // Why `--init`? (1) PID 1 is supposed to reap orphaned zombie processes, otherwise they can
+    // accumulate. Bash does this, but we run `/bin/sh` in the container, which may or may not be
+    // Bash. So `--init` runs Tini (https://github.com/krallin/tini) as PID 1, which properly reaps
+    // orphaned zombies. (2) PID 1 also does not exhibit the default behavior (crashing) for signals
+    // like SIGINT and SIGTERM. However, PID 1 can still handle these signals by explicitly trapping
+    // them. Tini traps these signals and forwards them to the child process. Then the default
+    // signal handling behavior of the child process (in our case, `/bin/sh`) works normally.
+    let mut args = vec!["--init".to_owned()];
+
+    // Run as the `root` user. We always run `/bin/su` in the container, which switches to the user
+    // specified in the toastfile. We want to run `/bin/su` as root so it can switch users without
+    // requiring a password. Most Docker images already use `root` as the default user, but not
+    // all.
+    args.extend(vec!["--user".to_owned(), "root".to_owned()]);
+
+    // Environment
+    args.extend(
+        environment.iter().flat_map(|(variable, value)| {
+            vec!["--env".to_owned(), format!("{}={}", variable, value)]
+        }),
+    );
+
+    // Location
+    args.extend(vec![
+        "--workdir".to_owned(),
+        location.to_string_lossy().into_owned(),
+    ]);
+
+    // A read-only root filesystem still needs somewhere to write. The location is backed by an
+    // anonymous volume, which `docker container cp` can copy the input files into even though the
+    // rest of the filesystem is read-only. The volume is removed along with the container.
+    if read_only_rootfs {
+        args.extend(vec![
+            "--read-only".to_owned(),
+            "--volume".to_owned(),
+            location.to_string_lossy().into_owned(),
+        ]);
+    }
+
+    // For bind mounts, Docker requires the host path to be absolute. We can't
+    // use `std::fs::canonicalize` here, since on Windows that generates an
+    // extended-length path (e.g., `\\?\C:\Users\...`) which Docker doesn't
+    // understand.
+    let absolute_source_dir = current_dir()
+        .map_err(failure::user(
+            "Unable to determine the current working directory.",
+        ))?
+        .join(source_dir);
+
+    // Mount paths
+    args.extend(mount_paths.iter().flat_map(|mount_path| {
+        // [ref:mount_paths_no_commas]
+        vec![
+            "--mount".to_owned(),
+            if mount_path.is_readonly(mount_readonly) {
+                format!(
+                    "type=bind,source={},target={},readonly",
+                    absolute_source_dir
+                        .join(&mount_path.host_path)
+                        .to_string_lossy(),
+                    mount_target(location, mount_path).to_string_lossy(), // [ref:mount_target]
+                )
+            } else {
+                format!(
+                    "type=bind,source={},target={}",
+                    absolute_source_dir
+                        .join(&mount_path.host_path)
+                        .to_string_lossy(),
+                    mount_target(location, mount_path).to_string_lossy(), // [ref:mount_target]
+                )
+            },
+        ]
+    }));
+
+    // Volumes. Docker creates a volume the first time it's used, and the label lets us find it
+    // later [ref:volume_label]. Relative container paths are relative to the location, as with
+    // mount paths [ref:mount_target].
+    args.extend(volumes.iter().flat_map(|volume| {
+        // [ref:volumes_valid]
+        vec![
+            "--mount".to_owned(),
+            format!(
+                "type=volume,source={},target={},volume-label={VOLUME_LABEL}={}",
+                volume.name,
+                location.join(&volume.container_path).to_string_lossy(),
+                docker_repo,
+            ),
+        ]
+    }));
+
+    // Ports
+    args.extend(
+        ports
+            .iter()
+            .flat_map(|port| vec!["--publish".to_owned(), port.to_string()])
+            .collect::<Vec<_>>(),
+    );
+
+    // GPUs
+    if let Some(gpus) = gpus {
+        args.extend(vec!["--gpus".to_owned(), gpus.to_owned()]);
+    }
+
+    // User-provided arguments
+    args.extend_from_slice(extra_args);
+
+    Ok(args)
+}
+
+// Run a command and return its standard output.
+fn run_quiet(
+    docker_cli: &(impl DockerCli + ?Sized),
+    spinner_message: &str,
+    error: &str,
+    args: &[String],
+    user_command: bool,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<String, Failure> {
+    // Run the child process.
+    let output = run_quiet_output(docker_cli, spinner_message, error, args, interrupted)?;
+
+    // Handle the result.
+    if output.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else if user_command {
+        Err(Failure::User(
+            format!("{}\n{}", error, String::from_utf8_lossy(&output.stderr)),
+            None,
+        ))
+    } else {
+        Err(Failure::System(
+            format!("{}\n{}", error, String::from_utf8_lossy(&output.stderr)),
+            None,
+        ))
+    }
+}
+
+// Run a command and return its output, even if the command failed. An error is only returned if
+// the command couldn't be run or was interrupted.
+fn run_quiet_output(
+    docker_cli: &(impl DockerCli + ?Sized),
+    spinner_message: &str,
+    error: &str,
+    args: &[String],
+    interrupted: &Arc<AtomicBool>,
+) -> Result<Outcome, Failure> {
+    // Render a spinner animation and clear it when we're done.
+    let _guard = spin(spinner_message);
+
+    // This is used to determine whether the user interrupted the program during the execution of
+    // the child process.
+    let was_interrupted = interrupted.load(Ordering::SeqCst);
+
+    // Run the child process.
+    let output = docker_cli.run_quiet(args).map_err(failure::system(format!(
+        "{error} Perhaps you don't have Docker installed.",
+    )))?;
+
+    // Check if the child process was interrupted.
+    if !output.success()
+        && (output.code.is_none() || (!was_interrupted && interrupted.load(Ordering::SeqCst)))
+    {
+        interrupted.store(true, Ordering::SeqCst);
+        return Err(Failure::Interrupted);
+    }
+
+    Ok(output)
+}
+
+// Run a command with the contents of `stdin` on its standard input and return its standard output.
+fn run_quiet_stdin(
+    docker_cli: &(impl DockerCli + ?Sized),
+    spinner_message: &str,
+    error: &str,
+    args: &[String],
+    user_command: bool,
+    stdin: &mut dyn Read,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<String, Failure> {
+    // Render a spinner animation and clear it when we're done.
+    let _guard = spin(spinner_message);
+
+    // This is used to determine whether the user interrupted the program during the execution of
+    // the child process.
+    let was_interrupted = interrupted.load(Ordering::SeqCst);
+
+    // Run the child process.
+    let output = docker_cli
+        .run_quiet_stdin(args, stdin)
+        .map_err(failure::system(format!(
+            "{error} Perhaps you don't have Docker installed.",
+        )))?;
+
+    // Handle the result.
+    if output.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(
+            if output.code.is_none() || (!was_interrupted && interrupted.load(Ordering::SeqCst)) {
+                interrupted.store(true, Ordering::SeqCst);
+                Failure::Interrupted
+            } else if user_command {
+                Failure::User(
+                    format!("{}\n{}", error, String::from_utf8_lossy(&output.stderr)),
+                    None,
+                )
+            } else {
+                Failure::System(
+                    format!("{}\n{}", error, String::from_utf8_lossy(&output.stderr)),
+                    None,
+                )
+            },
+        )
+    }
+}
+
+// Run a command and inherit standard output and error streams.
+fn run_loud(
+    docker_cli: &(impl DockerCli + ?Sized),
+    error: &str,
+    args: &[String],
+    user_command: bool,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    // This is used to determine whether the user interrupted the program during the execution of
+    // the child process.
+    let was_interrupted = interrupted.load(Ordering::SeqCst);
+
+    // Run the child process.
+    let outcome = docker_cli.run_loud(args).map_err(failure::system(format!(
+        "{error} Perhaps you don't have Docker installed.",
+    )))?;
+
+    // Handle the result.
+    check_outcome(&outcome, error, user_command, was_interrupted, interrupted)
+}
+
+// Run a command and write each line of its standard output and error streams to standard output as
+// it arrives, after the given `prefix`.
+fn run_prefixed(
+    docker_cli: &(impl DockerCli + ?Sized),
+    error: &str,
+    args: &[String],
+    prefix: &str,
+    user_command: bool,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    // This is used to determine whether the user interrupted the program during the execution of
+    // the child process.
+    let was_interrupted = interrupted.load(Ordering::SeqCst);
+
+    // Run the child process, printing each line of its output.
+    let prefix = prefix.to_owned();
+    let outcome = docker_cli
+        .run_lines(
+            args,
+            Arc::new(move |line| drop(writeln!(io::stdout().lock(), "{prefix}{line}"))),
+            &mut || {},
+        )
+        .map_err(failure::system(format!(
+            "{error} Perhaps you don't have Docker installed.",
+        )))?;
+
+    // Handle the result.
+    check_outcome(&outcome, error, user_command, was_interrupted, interrupted)
+}
+
+// Run a command, retaining only the last `max_lines` lines of its standard output and error streams.
+// If the command fails, the retained lines are printed after the given `prefix` when it finishes. A
+// notice is logged periodically while lines are being suppressed.
+fn run_tail(
+    docker_cli: &(impl DockerCli + ?Sized),
+    error: &str,
+    args: &[String],
+    max_lines: usize,
+    prefix: &str,
+    user_command: bool,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    // This is used to determine whether the user interrupted the program during the execution of
+    // the child process.
+    let was_interrupted = interrupted.load(Ordering::SeqCst);
+
+    // Run the child process, reading both output streams into a shared tail and reporting
+    // suppressed output every so often.
+    let tail = Arc::new(Mutex::new(Tail::new(max_lines)));
+    let line_tail = tail.clone();
+    let mut reported = 0;
+    let mut last_report = Instant::now();
+    let outcome = docker_cli
+        .run_lines(
+            args,
+            // The `unwrap` will only fail if a panic already occurred.
+            Arc::new(move |line| line_tail.lock().unwrap().push(line)),
+            &mut || {
+                if last_report.elapsed() >= SUPPRESSED_NOTICE_INTERVAL {
+                    // The `unwrap` will only fail if a panic already occurred.
+                    let suppressed = tail.lock().unwrap().suppressed();
+                    if suppressed > reported {
+                        info!(
+                            "\u{2026}suppressed {}\u{2026}",
+                            format::number(suppressed, "line")
+                        );
+                        reported = suppressed;
+                    }
+                    last_report = Instant::now();
+                }
+            },
+        )
+        .map_err(failure::system(format!(
+            "{error} Perhaps you don't have Docker installed.",
+        )))?;
+
+    // Handle the result, printing the retained output if the command failed. The `unwrap` will only
+    // fail if a panic already occurred.
+    let result = check_outcome(&outcome, error, user_command, was_interrupted, interrupted);
+    write_tail(
+        &tail.lock().unwrap(),
+        result.is_err(),
+        prefix,
+        &mut io::stdout().lock(),
+    );
+    result
+}
+
+// Report the output retained by `run_tail`. The lines are only written if the command failed;
+// otherwise, all that's logged is how many lines were suppressed.
+fn write_tail(tail: &Tail, failed: bool, prefix: &str, output: &mut impl Write) {
+    if !failed {
+        let suppressed = tail.suppressed() + tail.lines().count();
+        if suppressed > 0 {
+            info!("\u{2026}suppressed {}.", format::number(suppressed, "line"));
+        }
+        return;
+    }
+
+    if tail.suppressed() > 0 {
+        info!(
+            "\u{2026}suppressed {}. Showing the last {}:",
+            format::number(tail.suppressed(), "line"),
+            format::number(tail.lines().count(), "line"),
+        );
+    }
+    for line in tail.lines() {
+        drop(writeln!(output, "{prefix}{line}"));
+    }
+}
+
+// Run a command and inherit standard input, output, and error streams.
+fn run_attach(
+    docker_cli: &(impl DockerCli + ?Sized),
+    error: &str,
+    args: &[String],
+    user_command: bool,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    // This is used to determine whether the user interrupted the program during the execution of
+    // the child process.
+    let was_interrupted = interrupted.load(Ordering::SeqCst);
+
+    // Run the child process.
+    let outcome = docker_cli
+        .run_attach(args)
+        .map_err(failure::system(format!(
+            "{error} Perhaps you don't have Docker installed.",
+        )))?;
+
+    // Handle the result.
+    check_outcome(&outcome, error, user_command, was_interrupted, interrupted)
+}
+
+// Convert the outcome of a command whose output wasn't captured into a result. A command which was
+// killed by a signal, or which failed after the user interrupted the program, counts as an
+// interruption.
+fn check_outcome(
+    outcome: &Outcome,
+    error: &str,
+    user_command: bool,
+    was_interrupted: bool,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    if outcome.success() {
+        return Ok(());
+    }
+
+    match outcome.code {
+        Some(code) if was_interrupted || !interrupted.load(Ordering::SeqCst) => {
+            Err(if user_command {
+                // Remember the exit code so Toast can exit with it [ref:exit_codes].
+                Failure::User(error.to_owned(), Some(Box::new(CommandExit(code))))
+            } else {
+                Failure::System(error.to_owned(), None)
+            })
+        }
+        _ => {
+            interrupted.store(true, Ordering::SeqCst);
+            Err(Failure::Interrupted)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::docker::{
+            cli::{FakeCli, Outcome, Process},
+            commit_container, container_diff, container_fingerprint, container_stats,
+            copy_from_container, copy_from_container_until, copy_into_container, create_container,
+            delete_container, delete_image, describe_diff, ensure_image, exec_args, image_args,
+            image_created, image_digest, image_exists, image_size, list_volumes,
+            output_path_mapping, parse_diff_output, parse_stats_output, parse_stats_size,
+            parse_timestamp, poll_daemon, push_image, remote_image_exists, server_architecture,
+            spawn_shell, start_container, start_container_with_exec, stop_container,
+            symlink_target_acr, symlink_target_extracted, wait_for_daemon, wait_until, write_tail,
+            Availability, Change, ImagePurpose, PullPolicy, Watchdog,
+        },
+        crate::{failure::Failure, stats::Sample, tail::Tail},
+        std::{
+            cell::{Cell, RefCell},
+            collections::HashMap,
+            io,
+            path::Path,
+            sync::{
+                atomic::{AtomicBool, Ordering},
+                Arc,
+            },
+            time::{Duration, Instant, UNIX_EPOCH},
+        },
+        typed_path::{UnixPath, UnixPathBuf},
+    };
+
+    // Convert a list of string slices into arguments.
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| (*arg).to_owned()).collect()
+    }
+
+    // A command which succeeded with the given output
+    fn succeeded(stdout: &str) -> Outcome {
+        Outcome {
+            code: Some(0),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: vec![],
+        }
+    }
+
+    // A command which failed with the given error output
+    fn failed(stderr: &str) -> Outcome {
+        Outcome {
+            code: Some(1),
+            stdout: vec![],
+            stderr: stderr.as_bytes().to_vec(),
+        }
+    }
+
+    // A command which was killed by a signal
+    fn killed() -> Outcome {
+        Outcome {
+            code: None,
+            stdout: vec![],
+            stderr: vec![],
+        }
+    }
+
+    #[test]
+    fn image_exists_present() {
+        let cli = FakeCli::new(vec![succeeded("[]")]);
+
+        assert!(image_exists(&cli, "encom:os-12", &Arc::new(AtomicBool::new(false))).unwrap());
+        assert_eq!(
+            cli.calls(),
+            vec![args(&["image", "inspect", "encom:os-12"])]
+        );
+    }
+
+    #[test]
+    fn image_exists_absent() {
+        let cli = FakeCli::new(vec![failed("Error: No such image: encom:os-12")]);
+
+        assert!(!image_exists(&cli, "encom:os-12", &Arc::new(AtomicBool::new(false))).unwrap());
+    }
+
+    #[test]
+    fn image_exists_daemon_unreachable() {
+        let cli = FakeCli::new(vec![failed(
+            "Cannot connect to the Docker daemon at unix:///var/run/docker.sock.",
+        )]);
+
+        let result = image_exists(&cli, "encom:os-12", &Arc::new(AtomicBool::new(false)));
+        assert!(matches!(result, Err(Failure::System(_, _))));
+    }
+
+    #[test]
+    fn image_exists_killed() {
+        let cli = FakeCli::new(vec![killed()]);
+        let interrupted = Arc::new(AtomicBool::new(false));
+
+        let result = image_exists(&cli, "encom:os-12", &interrupted);
+        assert!(matches!(result, Err(Failure::Interrupted)));
+        assert!(interrupted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn remote_image_exists_present() {
+        let cli = FakeCli::new(vec![succeeded("{}")]);
+
+        assert!(remote_image_exists(
+            &cli,
+            "registry.example.com/toast:toast-3d5f",
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap());
+        assert_eq!(
+            cli.calls(),
+            vec![args(&[
+                "manifest",
+                "inspect",
+                "registry.example.com/toast:toast-3d5f",
+            ])],
+        );
+    }
+
+    #[test]
+    fn remote_image_exists_absent() {
+        let cli = FakeCli::new(vec![failed(
+            "no such manifest: registry.example.com/toast:toast-3d5f",
+        )]);
+
+        assert!(!remote_image_exists(
+            &cli,
+            "registry.example.com/toast:toast-3d5f",
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn remote_image_exists_unauthorized() {
+        let cli = FakeCli::new(vec![failed("unauthorized: authentication required")]);
+
+        let result = remote_image_exists(
+            &cli,
+            "registry.example.com/toast:toast-3d5f",
+            &Arc::new(AtomicBool::new(false)),
+        );
+        assert!(matches!(result, Err(Failure::System(_, _))));
+    }
+
+    // Build an archive like the one `docker container export` produces.
+    fn export(files: &[(&str, &[u8])], symlinks: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *contents).unwrap();
+        }
+        for (path, target) in symlinks {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_size(0);
+            header.set_mode(0o777);
+            builder.append_link(&mut header, path, target).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn parse_diff_output_kinds() {
+        assert_eq!(
+            parse_diff_output("C /scratch\nA /scratch/foo.txt\nD /tmp/bar.txt\n").unwrap(),
+            vec![
+                (Change::Changed, "/scratch".to_owned()),
+                (Change::Added, "/scratch/foo.txt".to_owned()),
+                (Change::Deleted, "/tmp/bar.txt".to_owned()),
+            ],
+        );
+    }
+
+    #[test]
+    fn parse_diff_output_empty() {
+        assert_eq!(parse_diff_output("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn parse_diff_output_invalid() {
+        assert!(matches!(
+            parse_diff_output("X /scratch\n"),
+            Err(Failure::System(_, _)),
+        ));
+        assert!(matches!(
+            parse_diff_output("garbage\n"),
+            Err(Failure::System(_, _)),
+        ));
+    }
+
+    #[test]
+    fn container_diff_parses_output() {
+        let cli = FakeCli::new(vec![succeeded("C /scratch\nA /scratch/out.txt\n")]);
+
+        assert_eq!(
+            container_diff(&cli, "toast-container", &Arc::new(AtomicBool::new(false))).unwrap(),
+            vec![
+                (Change::Changed, "/scratch".to_owned()),
+                (Change::Added, "/scratch/out.txt".to_owned()),
+            ],
+        );
+        assert_eq!(
+            cli.calls(),
+            vec![args(&["container", "diff", "toast-container"])],
+        );
+    }
+
+    #[test]
+    fn container_diff_failed() {
+        let cli = FakeCli::new(vec![failed("No such container: toast-container")]);
+
+        let result = container_diff(&cli, "toast-container", &Arc::new(AtomicBool::new(false)));
+        assert!(matches!(result, Err(Failure::System(_, _))));
+    }
+
+    #[test]
+    fn describe_diff_groups_changes() {
+        let changes =
+            parse_diff_output("C /scratch\nA /scratch/out.txt\nD /tmp/lock\nA /scratch/log.txt\n")
+                .unwrap();
+
+        assert_eq!(
+            describe_diff(&changes, 200),
+            "\n  Added:\n    /scratch/out.txt\n    /scratch/log.txt\
+             \n  Changed:\n    /scratch\
+             \n  Deleted:\n    /tmp/lock",
+        );
+    }
+
+    #[test]
+    fn describe_diff_truncates() {
+        let changes =
+            parse_diff_output("C /scratch\nA /scratch/out.txt\nD /tmp/lock\nA /scratch/log.txt\n")
+                .unwrap();
+
+        assert_eq!(
+            describe_diff(&changes, 2),
+            "\n  Added:\n    /scratch/out.txt\
+             \n  Changed:\n    /scratch\
+             \n  \u{2026}and 2 more.",
+        );
+    }
+
+    #[test]
+    fn describe_diff_empty() {
+        assert_eq!(describe_diff(&[], 200), "");
+    }
+
+    #[test]
+    fn container_fingerprint_hashes_changed_paths() {
+        let archive = export(
+            &[
+                ("scratch/foo.txt", b"foo"),
+                ("scratch/unchanged.txt", b"unchanged"),
+            ],
+            &[("scratch/link", "foo.txt")],
+        );
+        let cli = FakeCli::new(vec![
+            succeeded("A /scratch/foo.txt\nA /scratch/link\nD /tmp/bar.txt\n"),
+            Outcome {
+                code: Some(0_i32),
+                stdout: archive,
+                stderr: vec![],
+            },
+        ]);
+
+        let fingerprint =
+            container_fingerprint(&cli, "toast-container", &Arc::new(AtomicBool::new(false)))
+                .unwrap();
+
+        assert_eq!(
+            fingerprint.keys().collect::<Vec<_>>(),
+            vec!["/scratch/foo.txt", "/scratch/link", "/tmp/bar.txt"],
+        );
+        assert_eq!(fingerprint["/tmp/bar.txt"], "deleted");
+        assert_ne!(
+            fingerprint["/scratch/foo.txt"],
+            fingerprint["/scratch/link"]
+        );
+        assert_eq!(
+            cli.calls(),
+            vec![
+                args(&["container", "diff", "toast-container"]),
+                args(&["container", "export", "toast-container"]),
+            ],
+        );
+    }
+
+    #[test]
+    fn container_fingerprint_depends_on_contents() {
+        let fingerprint = |contents: &[u8]| {
+            let cli = FakeCli::new(vec![
+                succeeded("A /scratch/foo.txt\n"),
+                Outcome {
+                    code: Some(0_i32),
+                    stdout: export(&[("scratch/foo.txt", contents)], &[]),
+                    stderr: vec![],
+                },
+            ]);
+            container_fingerprint(&cli, "toast-container", &Arc::new(AtomicBool::new(false)))
+                .unwrap()
+        };
+
+        assert_eq!(fingerprint(b"foo"), fingerprint(b"foo"));
+        assert_ne!(fingerprint(b"foo"), fingerprint(b"bar"));
+    }
+
+    #[test]
+    fn container_fingerprint_export_failed() {
+        let cli = FakeCli::new(vec![
+            succeeded("A /scratch/foo.txt\n"),
+            failed("No such container: toast-container"),
+        ]);
+
+        let result =
+            container_fingerprint(&cli, "toast-container", &Arc::new(AtomicBool::new(false)));
+        assert!(matches!(result, Err(Failure::System(_, _))));
+    }
+
+    #[test]
+    fn parse_timestamp_utc() {
+        assert_eq!(
+            parse_timestamp("2024-05-01T12:34:56.123456789Z"),
+            Some(UNIX_EPOCH + Duration::from_secs(1_714_566_896)),
+        );
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(UNIX_EPOCH));
+        assert_eq!(
+            parse_timestamp("2000-02-29T23:59:59Z"),
+            Some(UNIX_EPOCH + Duration::from_secs(951_868_799)),
+        );
+    }
+
+    #[test]
+    fn parse_timestamp_offset() {
+        assert_eq!(
+            parse_timestamp("2024-05-01T14:34:56+02:00"),
+            parse_timestamp("2024-05-01T12:34:56Z"),
+        );
+        assert_eq!(
+            parse_timestamp("2024-05-01T07:04:56.5-05:30"),
+            parse_timestamp("2024-05-01T12:34:56Z"),
+        );
+    }
+
+    #[test]
+    fn parse_timestamp_invalid() {
+        for timestamp in [
+            "",
+            "2024-05-01",
+            "2024-05-01T12:34:56",
+            "2024-13-01T12:34:56Z",
+            "2024-05-01T24:00:00Z",
+            "2024-05-01T12:34Z",
+            "1969-12-31T23:59:59Z",
+            "yesterday",
+        ] {
+            assert_eq!(parse_timestamp(timestamp), None, "{timestamp}");
+        }
+    }
+
+    #[test]
+    fn image_created_parses_output() {
+        let cli = FakeCli::new(vec![succeeded("2024-05-01T12:34:56.123456789Z\n")]);
+
+        assert_eq!(
+            image_created(&cli, "toast:abc", &Arc::new(AtomicBool::new(false))).unwrap(),
+            UNIX_EPOCH + Duration::from_secs(1_714_566_896),
+        );
+        assert_eq!(
+            cli.calls(),
+            vec![args(&[
+                "image",
+                "inspect",
+                "--format",
+                "{{.Created}}",
+                "toast:abc",
+            ])],
+        );
+    }
+
+    #[test]
+    fn image_created_garbage() {
+        let cli = FakeCli::new(vec![succeeded("<no value>\n")]);
+
+        let result = image_created(&cli, "toast:abc", &Arc::new(AtomicBool::new(false)));
+        assert!(matches!(result, Err(Failure::System(_, _))));
+    }
+
+    #[test]
+    fn image_size_parses_output() {
+        let cli = FakeCli::new(vec![succeeded("123456789\n")]);
+
+        assert_eq!(
+            image_size(&cli, "toast:abc", &Arc::new(AtomicBool::new(false))).unwrap(),
+            123_456_789,
+        );
+        assert_eq!(
+            cli.calls(),
+            vec![args(&[
+                "image",
+                "inspect",
+                "--format",
+                "{{.Size}}",
+                "toast:abc",
+            ])],
+        );
+    }
+
+    #[test]
+    fn image_size_garbage() {
+        let cli = FakeCli::new(vec![succeeded("<no value>\n")]);
+
+        let result = image_size(&cli, "toast:abc", &Arc::new(AtomicBool::new(false)));
+        assert!(matches!(result, Err(Failure::System(_, _))));
+    }
+
+    #[test]
+    fn image_digest_single() {
+        let cli = FakeCli::new(vec![succeeded("node@sha256:abc\n")]);
+
+        assert_eq!(
+            image_digest(&cli, "node:20", &Arc::new(AtomicBool::new(false))).unwrap(),
+            Some("node@sha256:abc".to_owned()),
+        );
+        assert_eq!(
+            cli.calls(),
+            vec![args(&[
+                "image",
+                "inspect",
+                "--format",
+                "{{range .RepoDigests}}{{println .}}{{end}}",
+                "node:20",
+            ])],
+        );
+    }
+
+    #[test]
+    fn image_digest_own_repository() {
+        let cli = FakeCli::new(vec![succeeded(
+            "mirror.example.com/node@sha256:abc\nlocalhost:5000/node@sha256:abc\n",
+        )]);
+
+        assert_eq!(
+            image_digest(
+                &cli,
+                "localhost:5000/node:20",
+                &Arc::new(AtomicBool::new(false)),
+            )
+            .unwrap(),
+            Some("localhost:5000/node@sha256:abc".to_owned()),
+        );
+    }
+
+    #[test]
+    fn image_digest_none() {
+        let cli = FakeCli::new(vec![succeeded("\n")]);
+
+        assert_eq!(
+            image_digest(&cli, "my-local-image", &Arc::new(AtomicBool::new(false))).unwrap(),
+            None,
+        );
+    }
+
+    #[test]
+    fn list_volumes_labeled() {
+        let cli = FakeCli::new(vec![succeeded("cargo-registry\nnpm\n")]);
+
+        assert_eq!(
+            list_volumes(&cli, "toast", &Arc::new(AtomicBool::new(false))).unwrap(),
+            vec!["cargo-registry".to_owned(), "npm".to_owned()],
+        );
+        assert_eq!(
+            cli.calls(),
+            vec![args(&[
+                "volume",
+                "ls",
+                "--filter",
+                "label=toast.repo=toast",
+                "--format",
+                "{{.Name}}",
+            ])],
+        );
+    }
+
+    #[test]
+    fn server_architecture_arm64() {
+        let cli = FakeCli::new(vec![succeeded("arm64\n")]);
+
+        assert_eq!(
+            server_architecture(&cli, &Arc::new(AtomicBool::new(false))).unwrap(),
+            "arm64",
+        );
+        assert_eq!(
+            cli.calls(),
+            vec![args(&["version", "--format", "{{.Server.Arch}}"])],
+        );
+    }
+
+    #[test]
+    fn server_architecture_empty() {
+        let cli = FakeCli::new(vec![succeeded("\n")]);
+
+        let result = server_architecture(&cli, &Arc::new(AtomicBool::new(false)));
+        assert!(matches!(result, Err(Failure::System(_, _))));
+    }
+
+    #[test]
+    fn server_architecture_failed() {
+        let cli = FakeCli::new(vec![failed(
+            "Cannot connect to the Docker daemon at unix:///var/run/docker.sock.",
+        )]);
+
+        let result = server_architecture(&cli, &Arc::new(AtomicBool::new(false)));
+        assert!(matches!(result, Err(Failure::System(_, _))));
+    }
+
+    #[test]
+    fn watchdog_finished_in_time() {
+        let watchdog = Watchdog::start("true", "flynn", Duration::from_secs(30));
+
+        assert!(!watchdog.finish());
+    }
+
+    #[test]
+    fn watchdog_expired() {
+        let watchdog = Watchdog::start("true", "flynn", Duration::from_millis(10));
+        std::thread::sleep(Duration::from_millis(100));
+
+        assert!(watchdog.finish());
+    }
+
+    #[test]
+    fn wait_for_daemon_ready() {
+        let cli = FakeCli::new(vec![succeeded("24.0.7\n")]);
+
+        wait_for_daemon(
+            &cli,
+            Duration::from_secs(30),
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+        assert_eq!(
+            cli.calls(),
+            vec![args(&["version", "--format", "{{.Server.Version}}"])],
+        );
+    }
+
+    #[test]
+    fn wait_for_daemon_other_error() {
+        let cli = FakeCli::new(vec![failed("unknown flag: --format")]);
+
+        let result = wait_for_daemon(
+            &cli,
+            Duration::from_secs(30),
+            &Arc::new(AtomicBool::new(false)),
+        );
+        assert!(matches!(result, Err(Failure::System(_, _))));
+        assert_eq!(cli.calls().len(), 1);
+    }
+
+    // Poll a fake daemon which responds with the given results in order, using a fake clock which
+    // only advances when the loop sleeps. Returns the result and the durations slept.
+    fn poll_fake_daemon(
+        timeout: Duration,
+        interrupted: &Arc<AtomicBool>,
+        results: Vec<Result<Option<String>, Failure>>,
+    ) -> (Result<(), Failure>, Vec<Duration>) {
+        let start = Instant::now();
+        let elapsed = Cell::new(Duration::ZERO);
+        let sleeps = RefCell::new(vec![]);
+        let mut results = results.into_iter();
+
+        let result = poll_daemon(
+            timeout,
+            interrupted,
+            || start + elapsed.get(),
+            |duration| {
+                elapsed.set(elapsed.get() + duration);
+                sleeps.borrow_mut().push(duration);
+            },
+            || results.next().unwrap(),
+        );
+
+        (result, sleeps.into_inner())
+    }
+
+    #[test]
+    fn poll_daemon_ready_immediately() {
+        let (result, sleeps) = poll_fake_daemon(
+            Duration::from_secs(10),
+            &Arc::new(AtomicBool::new(false)),
+            vec![Ok(None)],
+        );
+
+        assert!(result.is_ok());
+        assert!(sleeps.is_empty());
+    }
+
+    #[test]
+    fn poll_daemon_backoff() {
+        let unreachable = || Ok(Some("Cannot connect to the Docker daemon.".to_owned()));
+
+        let (result, sleeps) = poll_fake_daemon(
+            Duration::from_secs(30),
+            &Arc::new(AtomicBool::new(false)),
+            vec![
+                unreachable(),
+                unreachable(),
+                unreachable(),
+                unreachable(),
+                unreachable(),
+                unreachable(),
+                Ok(None),
+            ],
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(
+            sleeps,
+            vec![
+                Duration::from_millis(100),
+                Duration::from_millis(200),
+                Duration::from_millis(400),
+                Duration::from_millis(800),
+                Duration::from_secs(1),
+                Duration::from_secs(1),
+            ],
+        );
+    }
+
+    #[test]
+    fn poll_daemon_timeout() {
+        let (result, sleeps) = poll_fake_daemon(
+            Duration::from_secs(2),
+            &Arc::new(AtomicBool::new(false)),
+            (0_u32..100)
+                .map(|_| Ok(Some("Cannot connect to the Docker daemon.".to_owned())))
+                .collect(),
+        );
+
+        match result {
+            Err(Failure::System(message, None)) => {
+                assert!(message.contains("Timed out after 2s"));
+                assert!(message.contains("Cannot connect to the Docker daemon."));
+            }
+            _ => panic!("The poll should have timed out."),
+        }
+
+        // The last sleep is cut short so the loop gives up right at the deadline.
+        assert_eq!(sleeps.iter().sum::<Duration>(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn poll_daemon_permanent_failure() {
+        let (result, sleeps) = poll_fake_daemon(
+            Duration::from_secs(10),
+            &Arc::new(AtomicBool::new(false)),
+            vec![
+                Ok(Some("Cannot connect to the Docker daemon.".to_owned())),
+                Err(Failure::System("Permission denied.".to_owned(), None)),
+            ],
+        );
+
+        assert!(
+            matches!(result, Err(Failure::System(message, None)) if message == "Permission denied.")
+        );
+        assert_eq!(sleeps.len(), 1);
+    }
+
+    #[test]
+    fn poll_daemon_interrupted() {
+        let (result, sleeps) = poll_fake_daemon(
+            Duration::from_secs(10),
+            &Arc::new(AtomicBool::new(true)),
+            vec![],
+        );
+
+        assert!(matches!(result, Err(Failure::Interrupted)));
+        assert!(sleeps.is_empty());
+    }
+
+    #[test]
+    fn ensure_image_present() {
+        let cli = FakeCli::new(vec![succeeded("[]")]);
+
+        assert_eq!(
+            ensure_image(
+                &cli,
+                "encom:os-12",
+                ImagePurpose::BaseImage,
+                PullPolicy::IfMissing,
+                &[],
+                &Arc::new(AtomicBool::new(false)),
+            )
+            .unwrap(),
+            Availability::Local,
+        );
+        assert_eq!(
+            cli.calls(),
+            vec![args(&["image", "inspect", "encom:os-12"])]
+        );
+    }
+
+    #[test]
+    fn ensure_image_pulled() {
+        let cli = FakeCli::new(vec![failed("Error: No such image: encom:os-12")]);
+
+        assert_eq!(
+            ensure_image(
+                &cli,
+                "encom:os-12",
+                ImagePurpose::BaseImage,
+                PullPolicy::IfMissing,
+                &args(&["--platform", "linux/amd64"]),
+                &Arc::new(AtomicBool::new(false)),
+            )
+            .unwrap(),
+            Availability::Pulled,
+        );
+        assert_eq!(
+            cli.calls(),
+            vec![
+                args(&["image", "inspect", "encom:os-12"]),
+                args(&["image", "pull", "--platform", "linux/amd64", "encom:os-12",]),
+            ],
+        );
+    }
+
+    #[test]
+    fn ensure_image_never_pulled() {
+        let cli = FakeCli::new(vec![failed("Error: No such image: encom:os-12")]);
+
+        assert_eq!(
+            ensure_image(
+                &cli,
+                "encom:os-12",
+                ImagePurpose::CacheEntry,
+                PullPolicy::Never,
+                &[],
+                &Arc::new(AtomicBool::new(false)),
+            )
+            .unwrap(),
+            Availability::Missing,
+        );
+        assert_eq!(
+            cli.calls(),
+            vec![args(&["image", "inspect", "encom:os-12"])]
+        );
+    }
+
+    #[test]
+    fn ensure_image_always_pulled() {
+        let cli = FakeCli::new(vec![]);
+
+        assert_eq!(
+            ensure_image(
+                &cli,
+                "encom:os-12",
+                ImagePurpose::BaseImage,
+                PullPolicy::Always,
+                &[],
+                &Arc::new(AtomicBool::new(false)),
+            )
+            .unwrap(),
+            Availability::Pulled,
+        );
+        assert_eq!(cli.calls(), vec![args(&["image", "pull", "encom:os-12"])]);
+    }
+
+    #[test]
+    fn ensure_image_cache_miss() {
+        let cli = FakeCli::new(vec![
+            failed("Error: No such image: encom:os-12"),
+            failed("manifest unknown"),
+        ]);
+
+        assert_eq!(
+            ensure_image(
+                &cli,
+                "encom:os-12",
+                ImagePurpose::CacheEntry,
+                PullPolicy::IfMissing,
+                &[],
+                &Arc::new(AtomicBool::new(false)),
+            )
+            .unwrap(),
+            Availability::Missing,
+        );
+    }
+
+    #[test]
+    fn ensure_image_required_pull_failure() {
+        for purpose in [ImagePurpose::BaseImage, ImagePurpose::Context] {
+            let cli = FakeCli::new(vec![failed("manifest unknown")]);
+
+            let result = ensure_image(
+                &cli,
+                "encom:os-12",
+                purpose,
+                PullPolicy::Always,
+                &[],
+                &Arc::new(AtomicBool::new(false)),
+            );
+            let Err(Failure::System(message, _)) = result else {
+                panic!("Expected a system failure.");
+            };
+            assert!(message.contains("manifest unknown"));
+        }
+    }
+
+    #[test]
+    fn ensure_image_cache_pull_interrupted() {
+        let cli = FakeCli::new(vec![killed()]);
+        let interrupted = Arc::new(AtomicBool::new(false));
+
+        let result = ensure_image(
+            &cli,
+            "encom:os-12",
+            ImagePurpose::CacheEntry,
+            PullPolicy::Always,
+            &[],
+            &interrupted,
+        );
+
+        assert!(matches!(result, Err(Failure::Interrupted)));
+        assert!(interrupted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn ensure_image_daemon_unreachable() {
+        let cli = FakeCli::new(vec![failed(
+            "Cannot connect to the Docker daemon at unix:///var/run/docker.sock.",
+        )]);
+
+        let result = ensure_image(
+            &cli,
+            "encom:os-12",
+            ImagePurpose::CacheEntry,
+            PullPolicy::IfMissing,
+            &[],
+            &Arc::new(AtomicBool::new(false)),
+        );
+
+        assert!(matches!(result, Err(Failure::System(_, _))));
+        assert_eq!(cli.calls().len(), 1);
+    }
+
+    #[test]
+    fn push_image_arguments() {
+        let cli = FakeCli::new(vec![]);
+
+        push_image(&cli, "encom:os-12", &[], &Arc::new(AtomicBool::new(false))).unwrap();
+
+        assert_eq!(cli.calls(), vec![args(&["image", "push", "encom:os-12"])]);
+    }
+
+    #[test]
+    fn delete_image_arguments() {
+        let cli = FakeCli::new(vec![]);
+
+        delete_image(&cli, "encom:os-12", &Arc::new(AtomicBool::new(false))).unwrap();
+
+        assert_eq!(
+            cli.calls(),
+            vec![args(&["image", "rm", "--force", "encom:os-12"])],
+        );
+    }
+
+    #[test]
+    fn create_container_arguments() {
+        let cli = FakeCli::new(vec![succeeded("f3a1\n")]);
+        let mut environment = HashMap::new();
+        environment.insert("GRID".to_owned(), "on".to_owned());
+
+        let container = create_container(
+            &cli,
+            "encom:os-12",
+            Path::new("."),
+            &environment,
+            &[],
+            false,
+            &[],
+            "toast",
+            false,
+            &["3000:80".parse().unwrap()],
+            Some("\"device=0,1\""),
+            UnixPath::new("/scratch"),
+            "flynn",
+            "echo hello",
+            &args(&["--cpus", "2"]),
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(container, "f3a1");
+        assert_eq!(
+            cli.calls(),
+            vec![args(&[
+                "container",
+                "create",
+                "--init",
+                "--user",
+                "root",
+                "--env",
+                "GRID=on",
+                "--workdir",
+                "/scratch",
+                "--publish",
+                "3000:80",
+                "--gpus",
+                "\"device=0,1\"",
+                "--cpus",
+                "2",
+                "encom:os-12",
+                "/bin/su",
+                "-c",
+                "echo hello",
+                "flynn",
+            ])],
+        );
+    }
+
+    #[test]
+    fn spawn_shell_without_prefix() {
+        let cli = FakeCli::new(vec![]);
+
+        spawn_shell(
+            &cli,
+            "encom:os-12",
+            Path::new("."),
+            &HashMap::new(),
+            UnixPath::new("/scratch"),
+            &[],
+            false,
+            &[],
+            "toast",
+            &[],
+            None,
+            "flynn",
+            "",
+            &[],
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            cli.calls(),
+            vec![args(&[
+                "container",
+                "run",
+                "--rm",
+                "--interactive",
+                "--tty",
+                "--init",
+                "--user",
+                "root",
+                "--workdir",
+                "/scratch",
+                "encom:os-12",
+                "/bin/su",
+                "flynn",
+            ])],
+        );
+    }
+
+    #[test]
+    fn spawn_shell_with_prefix() {
+        let cli = FakeCli::new(vec![]);
+
+        spawn_shell(
+            &cli,
+            "encom:os-12",
+            Path::new("."),
+            &HashMap::new(),
+            UnixPath::new("/scratch"),
+            &[],
+            false,
+            &[],
+            "toast",
+            &[],
+            None,
+            "flynn",
+            "source '/opt/tool chain/env.sh' # Set up the toolchain.",
+            &[],
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            cli.calls(),
+            vec![args(&[
+                "container",
+                "run",
+                "--rm",
+                "--interactive",
+                "--tty",
+                "--init",
+                "--user",
+                "root",
+                "--workdir",
+                "/scratch",
+                "encom:os-12",
+                "/bin/su",
+                "-c",
+                "source '/opt/tool chain/env.sh' # Set up the toolchain.\n\
+                 exec \"${SHELL:-/bin/sh}\" -i",
+                "flynn",
+            ])],
+        );
+    }
+
+    #[test]
+    fn create_container_without_gpus() {
+        let cli = FakeCli::new(vec![succeeded("f3a1\n")]);
+
+        create_container(
+            &cli,
+            "encom:os-12",
+            Path::new("."),
+            &HashMap::new(),
+            &[],
+            false,
+            &[],
+            "toast",
+            false,
+            &[],
+            None,
+            UnixPath::new("/scratch"),
+            "flynn",
+            "echo hello",
+            &[],
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert!(!cli.calls()[0].contains(&"--gpus".to_owned()));
+    }
+
+    #[test]
+    fn create_container_read_only_rootfs() {
+        let cli = FakeCli::new(vec![succeeded("f3a1\n")]);
+
+        create_container(
+            &cli,
+            "encom:os-12",
+            Path::new("."),
+            &HashMap::new(),
+            &[],
+            false,
+            &[],
+            "toast",
+            true,
+            &[],
+            None,
+            UnixPath::new("/scratch"),
+            "flynn",
+            "echo hello",
+            &[],
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            cli.calls(),
+            vec![args(&[
+                "container",
+                "create",
+                "--init",
+                "--user",
+                "root",
+                "--workdir",
+                "/scratch",
+                "--read-only",
+                "--volume",
+                "/scratch",
+                "encom:os-12",
+                "/bin/su",
+                "-c",
+                "echo hello",
+                "flynn",
+            ])],
+        );
+    }
+
+    #[test]
+    fn create_container_mount_readonly_per_path() {
+        let cli = FakeCli::new(vec![succeeded("f3a1\n")]);
+
+        create_container(
+            &cli,
+            "encom:os-12",
+            Path::new("/project"),
+            &HashMap::new(),
+            &["src:/code:ro".parse().unwrap(), "cache".parse().unwrap()],
+            false,
+            &[],
+            "toast",
+            false,
+            &[],
+            None,
+            UnixPath::new("/scratch"),
+            "flynn",
+            "echo hello",
+            &[],
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        let calls = cli.calls();
+        assert!(calls[0].windows(2).any(|pair| pair
+            == [
+                "--mount",
+                "type=bind,source=/project/src,target=/code,readonly"
+            ]));
+        assert!(calls[0].windows(2).any(|pair| pair
+            == [
+                "--mount",
+                "type=bind,source=/project/cache,target=/scratch/cache"
+            ]));
+    }
+
+    #[test]
+    fn create_container_volumes() {
+        let cli = FakeCli::new(vec![succeeded("f3a1\n")]);
+
+        create_container(
+            &cli,
+            "encom:os-12",
+            Path::new("."),
+            &HashMap::new(),
+            &[],
+            false,
+            &[
+                "cargo-registry:/usr/local/cargo/registry".parse().unwrap(),
+                "npm:node_modules".parse().unwrap(),
+            ],
+            "registry.encom.com/grid",
+            false,
+            &[],
+            None,
+            UnixPath::new("/scratch"),
+            "flynn",
+            "echo hello",
+            &[],
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        let calls = cli.calls();
+        assert!(calls[0].windows(2).any(|pair| pair
+            == [
+                "--mount",
+                "type=volume,source=cargo-registry,target=/usr/local/cargo/registry,\
+                 volume-label=toast.repo=registry.encom.com/grid",
+            ]));
+        assert!(calls[0].windows(2).any(|pair| pair
+            == [
+                "--mount",
+                "type=volume,source=npm,target=/scratch/node_modules,\
+                 volume-label=toast.repo=registry.encom.com/grid",
+            ]));
+    }
+
+    #[test]
+    fn create_container_gpu_runtime_missing() {
+        let cli = FakeCli::new(vec![failed(
+            "Error response from daemon: could not select device driver \"\" with capabilities: \
+             [[gpu]].",
+        )]);
+
+        let result = create_container(
+            &cli,
+            "encom:os-12",
+            Path::new("."),
+            &HashMap::new(),
+            &[],
+            false,
+            &[],
+            "toast",
+            false,
+            &[],
+            Some("all"),
+            UnixPath::new("/scratch"),
+            "flynn",
+            "echo hello",
+            &[],
+            &Arc::new(AtomicBool::new(false)),
+        );
+
+        let Err(Failure::System(message, _)) = result else {
+            panic!("Expected a system failure.");
+        };
+        assert!(message.contains("could not select device driver"));
+        assert!(message.contains("NVIDIA Container Toolkit"));
+    }
+
+    #[test]
+    fn create_container_failure_without_gpus() {
+        let cli = FakeCli::new(vec![failed("Error: invalid reference format.")]);
+
+        let result = create_container(
+            &cli,
+            "encom:os-12",
+            Path::new("."),
+            &HashMap::new(),
+            &[],
+            false,
+            &[],
+            "toast",
+            false,
+            &[],
+            None,
+            UnixPath::new("/scratch"),
+            "flynn",
+            "echo hello",
+            &[],
+            &Arc::new(AtomicBool::new(false)),
+        );
+
+        let Err(Failure::System(message, _)) = result else {
+            panic!("Expected a system failure.");
+        };
+        assert!(message.contains("invalid reference format"));
+        assert!(!message.contains("NVIDIA"));
+    }
+
+    #[test]
+    fn copy_into_container_stdin() {
+        let cli = FakeCli::new(vec![]);
+
+        copy_into_container(
+            &cli,
+            "f3a1",
+            UnixPath::new("/"),
+            &b"archive"[..],
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(cli.calls(), vec![args(&["container", "cp", "-", "f3a1:/"])]);
+        assert_eq!(cli.stdin(), b"archive");
+    }
+
+    #[test]
+    fn copy_into_container_location() {
+        let cli = FakeCli::new(vec![]);
+
+        copy_into_container(
+            &cli,
+            "f3a1",
+            UnixPath::new("/scratch"),
+            &b"archive"[..],
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            cli.calls(),
+            vec![args(&["container", "cp", "-", "f3a1:/scratch"])],
+        );
+    }
+
+    #[test]
+    fn copy_from_container_failure() {
+        let cli = FakeCli::new(vec![failed("No such container:path")]);
+
+        let result = copy_from_container(
+            &cli,
+            "f3a1",
+            &[UnixPathBuf::from("bin")],
+            UnixPath::new("/scratch"),
+            Path::new("out"),
+            false,
+            &Arc::new(AtomicBool::new(false)),
+        );
+
+        assert!(matches!(result, Err(Failure::User(_, _))));
+        let calls = cli.calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(
+            calls[0][..3],
+            args(&["container", "cp", "f3a1:/scratch/bin"])
+        );
+    }
+
+    #[test]
+    fn copy_from_container_until_failure() {
+        let cli = FakeCli::new(vec![failed("No such container:path")]);
+
+        let result = copy_from_container_until(
+            &cli,
+            "f3a1",
+            &[UnixPathBuf::from("logs")],
+            UnixPath::new("/scratch"),
+            Path::new("out"),
+            false,
+            Instant::now() + Duration::from_secs(10),
+        );
+
+        let Err(Failure::User(message, _)) = result else {
+            panic!("Expected a user failure.");
+        };
+        assert!(message.contains("No such container:path"));
+        let calls = cli.calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(
+            calls[0][..3],
+            args(&["container", "cp", "f3a1:/scratch/logs"])
+        );
+    }
+
+    // Copy files out of a fake container, in which each path is a directory with one file except
+    // `missing`, which fails to copy.
+    fn copy_paths_with_failure(paths: &[&str], destination_dir: &Path) -> Result<(), Failure> {
+        use {crate::docker::copy_paths_from_container, std::fs};
+
+        copy_paths_from_container(
+            "f3a1",
+            &paths.iter().map(UnixPathBuf::from).collect::<Vec<_>>(),
+            UnixPath::new("/scratch"),
+            destination_dir,
+            false,
+            |error, args| {
+                if args[2].ends_with("/missing") {
+                    return Err(Failure::User(error.to_owned(), None));
+                }
+
+                let intermediate = Path::new(args.last().unwrap());
+                fs::create_dir(intermediate).unwrap();
+                fs::write(intermediate.join("file.txt"), "new").unwrap();
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn copy_paths_from_container_failure_removes_created() {
+        use tempfile::tempdir;
+
+        let dir = tempdir().unwrap();
+        let destination_dir = dir.path().join("out").join("nested");
+
+        let result = copy_paths_with_failure(&["dist", "docs", "missing"], &destination_dir);
+
+        let Err(Failure::User(message, None)) = result else {
+            panic!("Expected the original failure.");
+        };
+        assert_eq!(message, "Unable to copy files from the container.");
+        assert!(!dir.path().join("out").exists());
+    }
+
+    #[test]
+    fn copy_paths_from_container_failure_reports_overwritten() {
+        use {
+            std::fs::{create_dir, read_to_string, write},
+            tempfile::tempdir,
+        };
+
+        let dir = tempdir().unwrap();
+        create_dir(dir.path().join("dist")).unwrap();
+        write(dir.path().join("dist").join("file.txt"), "old").unwrap();
+        write(dir.path().join("dist").join("extra.txt"), "old").unwrap();
+
+        let result = copy_paths_with_failure(&["dist", "docs", "missing"], dir.path());
+
+        let Err(Failure::User(message, Some(source))) = result else {
+            panic!("Expected a failure which mentions the overwritten files.");
+        };
+        assert!(message.contains("mix of old and new files"));
+        assert!(message.contains(&dir.path().join("dist").join("file.txt").to_string_lossy()[..]));
+        assert!(!message.contains("extra.txt"));
+        assert_eq!(
+            source.to_string(),
+            "Unable to copy files from the container.",
+        );
+
+        // The existing files stay, but the new ones are removed.
+        assert_eq!(
+            read_to_string(dir.path().join("dist").join("file.txt")).unwrap(),
+            "new",
+        );
+        assert!(dir.path().join("dist").join("extra.txt").exists());
+        assert!(!dir.path().join("docs").exists());
+    }
+
+    // A process which finishes with the given outcome, or never finishes if there is none
+    struct TestProcess {
+        outcome: Option<Outcome>,
+        killed: bool,
+    }
+
+    impl Process for TestProcess {
+        fn try_wait(&mut self) -> io::Result<Option<Outcome>> {
+            Ok(self.outcome.clone())
+        }
+
+        fn wait(&mut self) -> io::Result<Outcome> {
+            Ok(self.outcome.clone().unwrap_or_default())
+        }
+
+        fn kill(&mut self) {
+            self.killed = true;
+        }
+    }
+
+    #[test]
+    fn wait_until_finished() {
+        let mut process = TestProcess {
+            outcome: Some(succeeded("")),
+            killed: false,
+        };
+
+        let outcome = wait_until(&mut process, Instant::now() + Duration::from_secs(10)).unwrap();
+
+        assert_eq!(outcome, Some(succeeded("")));
+        assert!(!process.killed);
+    }
+
+    #[test]
+    fn wait_until_deadline() {
+        let mut process = TestProcess {
+            outcome: None,
+            killed: false,
+        };
+        let start = Instant::now();
+
+        let outcome = wait_until(&mut process, start + Duration::from_millis(100)).unwrap();
+
+        assert_eq!(outcome, None);
+        assert!(process.killed);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn start_container_arguments() {
+        let cli = FakeCli::new(vec![]);
+
+        start_container(&cli, "f3a1", None, None, &Arc::new(AtomicBool::new(false))).unwrap();
+
+        assert_eq!(
+            cli.calls(),
+            vec![args(&["container", "start", "--attach", "f3a1"])],
+        );
+    }
+
+    #[test]
+    fn start_container_failure() {
+        let cli = FakeCli::new(vec![failed("")]);
+        let interrupted = Arc::new(AtomicBool::new(false));
+
+        let result = start_container(&cli, "f3a1", None, None, &interrupted);
+
+        assert!(matches!(result, Err(Failure::User(_, _))));
+        assert!(!interrupted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn start_container_exit_code() {
+        let cli = FakeCli::new(vec![Outcome {
+            code: Some(101_i32),
+            ..Outcome::default()
+        }]);
+
+        let result = start_container(&cli, "f3a1", None, None, &Arc::new(AtomicBool::new(false)));
+
+        assert_eq!(result.unwrap_err().exit_code(), 101_i32);
+    }
+
+    #[test]
+    fn start_container_killed() {
+        let cli = FakeCli::new(vec![killed()]);
+        let interrupted = Arc::new(AtomicBool::new(false));
+
+        let result = start_container(&cli, "f3a1", Some(10), None, &interrupted);
+
+        assert!(matches!(result, Err(Failure::Interrupted)));
+        assert!(interrupted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn start_container_tail() {
+        let cli = FakeCli::new(vec![succeeded("one\ntwo\nthree\n")]);
+
+        start_container(
+            &cli,
+            "f3a1",
+            Some(2),
+            None,
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            cli.calls(),
+            vec![args(&["container", "start", "--attach", "f3a1"])],
+        );
+    }
+
+    #[test]
+    fn start_container_tail_failure() {
+        let cli = FakeCli::new(vec![failed("one\ntwo\nthree\n")]);
+        let interrupted = Arc::new(AtomicBool::new(false));
+
+        let result = start_container(&cli, "f3a1", Some(2), None, &interrupted);
+
+        assert!(matches!(result, Err(Failure::User(_, _))));
+        assert!(!interrupted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn write_tail_succeeded() {
+        let mut tail = Tail::new(2);
+        for line in ["one", "two", "three"] {
+            tail.push(line.to_owned());
+        }
+        let mut output = vec![];
+
+        write_tail(&tail, false, "[build] ", &mut output);
+
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn write_tail_failed() {
+        let mut tail = Tail::new(2);
+        for line in ["one", "two", "three"] {
+            tail.push(line.to_owned());
+        }
+        let mut output = vec![];
+
+        write_tail(&tail, true, "[build] ", &mut output);
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[build] two\n[build] three\n",
+        );
+    }
+
+    #[test]
+    fn start_container_prefixed() {
+        let cli = FakeCli::new(vec![succeeded("one\ntwo\n")]);
+
+        start_container(
+            &cli,
+            "f3a1",
+            None,
+            Some("[build] "),
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            cli.calls(),
+            vec![args(&["container", "start", "--attach", "f3a1"])],
+        );
+    }
+
+    #[test]
+    fn start_container_prefixed_failure() {
+        let cli = FakeCli::new(vec![failed("")]);
+        let interrupted = Arc::new(AtomicBool::new(false));
+
+        let result = start_container(&cli, "f3a1", None, Some("[build] "), &interrupted);
+
+        assert!(matches!(result, Err(Failure::User(_, _))));
+        assert!(!interrupted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn start_container_prefixed_killed() {
+        let cli = FakeCli::new(vec![killed()]);
+        let interrupted = Arc::new(AtomicBool::new(false));
+
+        let result = start_container(&cli, "f3a1", None, Some("[build] "), &interrupted);
+
+        assert!(matches!(result, Err(Failure::Interrupted)));
+        assert!(interrupted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn start_container_with_exec_arguments() {
+        let cli = FakeCli::new(vec![
+            succeeded("f3a1\n"),
+            succeeded(""),
+            succeeded(""),
+            succeeded("0\n"),
+        ]);
+
+        start_container_with_exec(
+            &cli,
+            "f3a1",
+            &["touch ready".to_owned()],
+            &HashMap::new(),
+            UnixPath::new("/scratch"),
+            "flynn",
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            cli.calls(),
+            vec![
+                args(&["container", "start", "f3a1"]),
+                args(&["container", "logs", "--follow", "f3a1"]),
+                args(&[
+                    "container",
+                    "exec",
+                    "--user",
+                    "root",
+                    "--workdir",
+                    "/scratch",
+                    "f3a1",
+                    "/bin/su",
+                    "-c",
+                    "touch ready",
+                    "flynn",
+                ]),
+                args(&["container", "wait", "f3a1"]),
+            ],
+        );
+    }
+
+    #[test]
+    fn start_container_with_exec_command_failure() {
+        let cli = FakeCli::new(vec![succeeded("f3a1\n"), succeeded(""), failed("")]);
+
+        let result = start_container_with_exec(
+            &cli,
+            "f3a1",
+            &["false".to_owned()],
+            &HashMap::new(),
+            UnixPath::new("/scratch"),
+            "flynn",
+            &Arc::new(AtomicBool::new(false)),
+        );
+
+        assert!(matches!(result, Err(Failure::User(_, _))));
+        assert_eq!(
+            cli.calls().last().unwrap(),
+            &args(&["container", "stop", "f3a1"]),
+        );
+    }
+
+    #[test]
+    fn start_container_with_exec_nonzero_exit() {
+        let cli = FakeCli::new(vec![succeeded("f3a1\n"), succeeded(""), succeeded("2\n")]);
+
+        let result = start_container_with_exec(
+            &cli,
+            "f3a1",
+            &[],
+            &HashMap::new(),
+            UnixPath::new("/scratch"),
+            "flynn",
+            &Arc::new(AtomicBool::new(false)),
+        );
+
+        assert!(matches!(result, Err(Failure::User(_, _))));
+    }
+
+    #[test]
+    fn stop_container_arguments() {
+        let cli = FakeCli::new(vec![]);
+
+        stop_container(&cli, "f3a1", &Arc::new(AtomicBool::new(false))).unwrap();
+
+        assert_eq!(cli.calls(), vec![args(&["container", "stop", "f3a1"])]);
+    }
+
+    #[test]
+    fn commit_container_arguments() {
+        let cli = FakeCli::new(vec![]);
+
+        commit_container(
+            &cli,
+            "f3a1",
+            "encom:os-13",
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            cli.calls(),
+            vec![args(&["container", "commit", "f3a1", "encom:os-13"])],
+        );
+    }
+
+    #[test]
+    fn delete_container_arguments() {
+        let cli = FakeCli::new(vec![]);
+
+        delete_container(&cli, "f3a1", &Arc::new(AtomicBool::new(false))).unwrap();
+
+        assert_eq!(
+            cli.calls(),
+            vec![args(&["container", "rm", "--force", "--volumes", "f3a1"])],
+        );
+    }
+
+    #[test]
+    fn container_stats_sample() {
+        let cli = FakeCli::new(vec![succeeded(
+            r#"{"CPUPerc":"50.00%","MemUsage":"1KiB / 2GiB"}"#,
+        )]);
+
+        assert_eq!(
+            container_stats(&cli, "f3a1"),
+            Some(Sample {
+                memory_bytes: 1024,
+                cpu_percent: 50.0,
+            }),
+        );
+        assert_eq!(
+            cli.calls(),
+            vec![args(&[
+                "container",
+                "stats",
+                "--no-stream",
+                "--format",
+                "{{json .}}",
+                "f3a1",
+            ])],
+        );
+    }
+
+    #[test]
+    fn container_stats_not_running() {
+        let cli = FakeCli::new(vec![failed("Error: No such container: f3a1")]);
+
+        assert_eq!(container_stats(&cli, "f3a1"), None);
+    }
+
+    #[test]
+    fn symlink_target_acr_relative() {
+        assert_eq!(
+            symlink_target_acr(
+                UnixPath::new("/scratch/dist/current"),
+                UnixPath::new("./build-1234"),
+            ),
+            UnixPath::new("/scratch/dist/build-1234"),
+        );
+    }
+
+    #[test]
+    fn symlink_target_acr_parent() {
+        assert_eq!(
+            symlink_target_acr(
+                UnixPath::new("/scratch/dist/current"),
+                UnixPath::new("../build/output"),
+            ),
+            UnixPath::new("/scratch/build/output"),
+        );
+    }
+
+    #[test]
+    fn symlink_target_acr_absolute() {
+        assert_eq!(
+            symlink_target_acr(
+                UnixPath::new("/scratch/python"),
+                UnixPath::new("/usr/bin/../bin/python3"),
+            ),
+            UnixPath::new("/usr/bin/python3"),
+        );
+    }
+
+    #[test]
+    fn symlink_target_extracted_sibling_in_directory() {
+        // `dist/` was extracted, and `dist/current -> ./build-1234` points into it.
+        assert!(symlink_target_extracted(
+            UnixPath::new("/scratch/dist/current"),
+            UnixPath::new("./build-1234"),
+            &[UnixPath::new("/scratch/dist").to_owned()],
+        ));
+    }
+
+    #[test]
+    fn symlink_target_extracted_sibling_not_extracted() {
+        // Only `current` was extracted, so `current -> ./build-1234` dangles.
+        assert!(!symlink_target_extracted(
+            UnixPath::new("/scratch/current"),
+            UnixPath::new("./build-1234"),
+            &[UnixPath::new("/scratch/current").to_owned()],
+        ));
+    }
+
+    #[test]
+    fn symlink_target_extracted_sibling_also_extracted() {
+        assert!(symlink_target_extracted(
+            UnixPath::new("/scratch/current"),
+            UnixPath::new("./build-1234"),
+            &[
+                UnixPath::new("/scratch/current").to_owned(),
+                UnixPath::new("/scratch/./build-1234").to_owned(),
+            ],
+        ));
+    }
+
+    #[test]
+    fn symlink_target_extracted_escapes_directory() {
+        assert!(!symlink_target_extracted(
+            UnixPath::new("/scratch/dist/current"),
+            UnixPath::new("../build-1234"),
+            &[UnixPath::new("/scratch/dist").to_owned()],
+        ));
+    }
+
+    #[test]
+    fn symlink_target_extracted_absolute() {
+        assert!(!symlink_target_extracted(
+            UnixPath::new("/scratch/dist/python"),
+            UnixPath::new("/usr/bin/python3"),
+            &[UnixPath::new("/scratch/dist").to_owned()],
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn move_into_place_directory_permissions() {
+        use {
+            crate::docker::{move_into_place, WrittenPaths},
+            std::{
+                fs::{create_dir, metadata, set_permissions, write, Permissions},
+                os::unix::fs::PermissionsExt,
+            },
+            tempfile::tempdir,
+        };
+
+        let mode = |path: &Path| metadata(path).unwrap().permissions().mode() & 0o7777;
+
+        let source_dir = tempdir().unwrap();
+        let intermediate = source_dir.path().join("data");
+        create_dir(&intermediate).unwrap();
+        write(intermediate.join("program"), "#!/bin/sh\n").unwrap();
+        set_permissions(intermediate.join("program"), Permissions::from_mode(0o755)).unwrap();
+        write(intermediate.join("secret"), "hunter2").unwrap();
+        set_permissions(intermediate.join("secret"), Permissions::from_mode(0o600)).unwrap();
+        create_dir(intermediate.join("private")).unwrap();
+        write(intermediate.join("private").join("notes"), "").unwrap();
+        set_permissions(intermediate.join("private"), Permissions::from_mode(0o750)).unwrap();
+        set_permissions(&intermediate, Permissions::from_mode(0o700)).unwrap();
+
+        let destination_dir = tempdir().unwrap();
+        let destination = destination_dir.path().join("dist");
+        move_into_place(
+            &intermediate,
+            &destination,
+            UnixPath::new("/scratch/dist"),
+            &mut vec![],
+            &mut WrittenPaths::default(),
+        )
+        .unwrap();
+
+        assert_eq!(mode(&destination), 0o700);
+        assert_eq!(mode(&destination.join("program")), 0o755);
+        assert_eq!(mode(&destination.join("secret")), 0o600);
+        assert_eq!(mode(&destination.join("private")), 0o750);
+        assert!(destination.join("private").join("notes").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn move_into_place_read_only_directory() {
+        use {
+            crate::docker::{move_into_place, WrittenPaths},
+            std::{
+                fs::{create_dir, metadata, set_permissions, write, Permissions},
+                os::unix::fs::PermissionsExt,
+            },
+            tempfile::tempdir,
+        };
+
+        let source_dir = tempdir().unwrap();
+        let intermediate = source_dir.path().join("data");
+        create_dir(&intermediate).unwrap();
+        create_dir(intermediate.join("frozen")).unwrap();
+        write(intermediate.join("frozen").join("data.bin"), "").unwrap();
+        set_permissions(intermediate.join("frozen"), Permissions::from_mode(0o555)).unwrap();
+
+        let destination_dir = tempdir().unwrap();
+        let destination = destination_dir.path().join("dist");
+        move_into_place(
+            &intermediate,
+            &destination,
+            UnixPath::new("/scratch/dist"),
+            &mut vec![],
+            &mut WrittenPaths::default(),
+        )
+        .unwrap();
+
+        let frozen = destination.join("frozen");
+        assert_eq!(
+            metadata(&frozen).unwrap().permissions().mode() & 0o7777,
+            0o555
+        );
+        assert!(frozen.join("data.bin").exists());
+
+        // Let the temporary directories be cleaned up.
+        set_permissions(&frozen, Permissions::from_mode(0o755)).unwrap();
+        set_permissions(intermediate.join("frozen"), Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn move_into_place_file_permissions() {
+        use {
+            crate::docker::{move_into_place, WrittenPaths},
+            std::{
+                fs::{metadata, set_permissions, write, Permissions},
+                os::unix::fs::PermissionsExt,
+            },
+            tempfile::tempdir,
+        };
+
+        let source_dir = tempdir().unwrap();
+        let intermediate = source_dir.path().join("data");
+        write(&intermediate, "#!/bin/sh\n").unwrap();
+        set_permissions(&intermediate, Permissions::from_mode(0o750)).unwrap();
+
+        let destination_dir = tempdir().unwrap();
+        let destination = destination_dir.path().join("bin").join("program");
+        move_into_place(
+            &intermediate,
+            &destination,
+            UnixPath::new("/scratch/bin/program"),
+            &mut vec![],
+            &mut WrittenPaths::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            metadata(&destination).unwrap().permissions().mode() & 0o7777,
+            0o750,
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn move_into_place_hard_links() {
+        use {
+            crate::docker::{move_into_place, WrittenPaths},
+            std::{
+                fs::{create_dir, hard_link, metadata, read_to_string, write},
+                os::unix::fs::MetadataExt,
+            },
+            tempfile::tempdir,
+        };
+
+        let source_dir = tempdir().unwrap();
+        let intermediate = source_dir.path().join("data");
+        create_dir(&intermediate).unwrap();
+        create_dir(intermediate.join("nested")).unwrap();
+        write(intermediate.join("model.bin"), "weights").unwrap();
+        hard_link(
+            intermediate.join("model.bin"),
+            intermediate.join("nested").join("model.bin"),
+        )
+        .unwrap();
+        write(intermediate.join("other.bin"), "weights").unwrap();
+
+        let destination_dir = tempdir().unwrap();
+        let destination = destination_dir.path().join("dist");
+        move_into_place(
+            &intermediate,
+            &destination,
+            UnixPath::new("/scratch/dist"),
+            &mut vec![],
+            &mut WrittenPaths::default(),
+        )
+        .unwrap();
+
+        let first = metadata(destination.join("model.bin")).unwrap();
+        let second = metadata(destination.join("nested").join("model.bin")).unwrap();
+        let other = metadata(destination.join("other.bin")).unwrap();
+        assert_eq!((first.dev(), first.ino()), (second.dev(), second.ino()));
+        assert_ne!(first.ino(), other.ino());
+        assert_eq!(
+            read_to_string(destination.join("nested").join("model.bin")).unwrap(),
+            "weights",
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn place_file_or_symlink_links_to_copy() {
+        use {
+            crate::docker::place_file_or_symlink,
+            std::{
+                collections::HashMap,
+                fs::{copy, hard_link, metadata, read_to_string, symlink_metadata, write},
+                os::unix::fs::MetadataExt,
+            },
+            tempfile::tempdir,
+        };
+
+        // Simulate the first link having been copied (rather than moved) to the destination, as
+        // happens across filesystems.
+        let source_dir = tempdir().unwrap();
+        let first_source = source_dir.path().join("first");
+        let second_source = source_dir.path().join("second");
+        write(&first_source, "weights").unwrap();
+        hard_link(&first_source, &second_source).unwrap();
+
+        let destination_dir = tempdir().unwrap();
+        let first_destination = destination_dir.path().join("first");
+        let second_destination = destination_dir.path().join("second");
+        copy(&first_source, &first_destination).unwrap();
+
+        let source_metadata = symlink_metadata(&second_source).unwrap();
+        let mut hard_links = HashMap::new();
+        hard_links.insert(
+            (source_metadata.dev(), source_metadata.ino()),
+            first_destination.clone(),
+        );
+
+        place_file_or_symlink(
+            &second_source,
+            &second_destination,
+            &source_metadata,
+            &mut hard_links,
+        )
+        .unwrap();
+
+        // The second link should point to the copy rather than the original file.
+        let first = metadata(&first_destination).unwrap();
+        let second = metadata(&second_destination).unwrap();
+        assert_eq!(first.ino(), second.ino());
+        assert_ne!(source_metadata.ino(), second.ino());
+        assert_eq!(read_to_string(&second_destination).unwrap(), "weights");
+        assert!(second_source.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn place_file_or_symlink_records_hard_links() {
+        use {
+            crate::docker::place_file_or_symlink,
+            std::{
+                collections::HashMap,
+                fs::{hard_link, symlink_metadata, write},
+                os::unix::fs::MetadataExt,
+            },
+            tempfile::tempdir,
+        };
+
+        let source_dir = tempdir().unwrap();
+        let linked = source_dir.path().join("linked");
+        let single = source_dir.path().join("single");
+        write(&linked, "").unwrap();
+        hard_link(&linked, source_dir.path().join("alias")).unwrap();
+        write(&single, "").unwrap();
+
+        let destination_dir = tempdir().unwrap();
+        let linked_metadata = symlink_metadata(&linked).unwrap();
+        let single_metadata = symlink_metadata(&single).unwrap();
+        let mut hard_links = HashMap::new();
+        place_file_or_symlink(
+            &linked,
+            &destination_dir.path().join("linked"),
+            &linked_metadata,
+            &mut hard_links,
+        )
+        .unwrap();
+        place_file_or_symlink(
+            &single,
+            &destination_dir.path().join("single"),
+            &single_metadata,
+            &mut hard_links,
+        )
+        .unwrap();
+
+        // Only files with other links are recorded.
+        assert_eq!(hard_links.len(), 1);
+        assert_eq!(
+            hard_links[&(linked_metadata.dev(), linked_metadata.ino())],
+            destination_dir.path().join("linked"),
+        );
+    }
+
+    #[test]
+    fn parse_stats_size_units() {
+        assert_eq!(parse_stats_size("512B"), Some(512));
+        assert_eq!(parse_stats_size("12kB"), Some(12_000));
+        assert_eq!(parse_stats_size("3.5MiB"), Some(3_670_016));
+        assert_eq!(parse_stats_size(" 2GiB "), Some(2_147_483_648));
+    }
+
+    #[test]
+    fn parse_stats_size_invalid() {
+        assert_eq!(parse_stats_size("lots"), None);
+        assert_eq!(parse_stats_size("12 parsecs"), None);
+    }
+
+    #[test]
+    fn parse_stats_output_valid() {
+        let output = r#"{"BlockIO":"0B / 0B","CPUPerc":"12.50%","Container":"f3a1","ID":"f3a1","MemPerc":"0.05%","MemUsage":"3.5MiB / 7.6GiB","Name":"toast","NetIO":"1kB / 0B","PIDs":"2"}"#;
+
+        assert_eq!(
+            parse_stats_output(output),
+            Some(Sample {
+                memory_bytes: 3_670_016,
+                cpu_percent: 12.5,
+            }),
+        );
+    }
+
+    #[test]
+    fn parse_stats_output_not_running() {
+        let output = r#"{"BlockIO":"--","CPUPerc":"--","MemUsage":"-- / --","Name":"toast"}"#;
+
+        assert_eq!(parse_stats_output(output), None);
+    }
+
+    #[test]
+    fn parse_stats_output_malformed() {
+        assert_eq!(parse_stats_output("Error: No such container"), None);
+    }
+
+    #[test]
+    fn image_args_no_extra_args() {
+        assert_eq!(
+            image_args("pull", "encom:os-12", &[]),
+            vec!["image", "pull", "encom:os-12"],
+        );
+    }
+
+    #[test]
+    fn image_args_extra_args() {
+        assert_eq!(
+            image_args(
+                "push",
+                "encom:os-12",
+                &["--disable-content-trust=false".to_owned()],
+            ),
+            vec![
+                "image",
+                "push",
+                "--disable-content-trust=false",
+                "encom:os-12"
+            ],
+        );
+    }
+
+    #[test]
+    fn exec_args_minimal() {
+        assert_eq!(
+            exec_args(
+                "container",
+                &HashMap::new(),
+                UnixPath::new("/scratch"),
+                "root",
+                "./migrate.sh",
+            ),
+            vec![
+                "container",
+                "exec",
+                "--user",
+                "root",
+                "--workdir",
+                "/scratch",
+                "container",
+                "/bin/su",
+                "-c",
+                "./migrate.sh",
+                "root",
+            ],
+        );
+    }
+
+    #[test]
+    fn exec_args_environment() {
+        let mut environment = HashMap::new();
+        environment.insert("FOO".to_owned(), "foo".to_owned());
+        environment.insert("BAR".to_owned(), "bar baz".to_owned());
+
+        assert_eq!(
+            exec_args(
+                "container",
+                &environment,
+                UnixPath::new("/code"),
+                "user",
+                "echo hello",
+            ),
+            vec![
+                "container",
+                "exec",
+                "--user",
+                "root",
+                "--workdir",
+                "/code",
+                "--env",
+                "BAR=bar baz",
+                "--env",
+                "FOO=foo",
+                "container",
+                "/bin/su",
+                "-c",
+                "echo hello",
+                "user",
+            ],
+        );
+    }
+
+    #[test]
+    fn output_path_mapping_toastfile_dir() {
+        let (source, destination) = output_path_mapping(
+            UnixPath::new("foo/bar.txt"),
+            UnixPath::new("/scratch"),
+            Path::new("project"),
+        )
+        .unwrap();
+
+        assert_eq!(source, UnixPath::new("/scratch/foo/bar.txt"));
+        assert_eq!(
+            destination,
+            Path::new("project").join("foo").join("bar.txt")
+        );
+    }
+
+    #[test]
+    fn output_path_mapping_empty_toastfile_dir() {
+        let (source, destination) = output_path_mapping(
+            UnixPath::new("foo"),
+            UnixPath::new("/scratch"),
+            Path::new(""),
+        )
+        .unwrap();
+
+        assert_eq!(source, UnixPath::new("/scratch/foo"));
+        assert_eq!(destination, Path::new("foo"));
+    }
+
+    #[test]
+    fn output_path_mapping_output_dir() {
+        let (source, destination) = output_path_mapping(
+            UnixPath::new("foo"),
+            UnixPath::new("/code"),
+            Path::new("/tmp/out"),
+        )
+        .unwrap();
+
+        assert_eq!(source, UnixPath::new("/code/foo"));
+        assert_eq!(destination, Path::new("/tmp/out").join("foo"));
+    }
+
+    #[test]
+    fn output_path_mapping_absolute() {
+        let (source, destination) = output_path_mapping(
+            UnixPath::new("/var/log/report.xml"),
+            UnixPath::new("/scratch"),
+            Path::new("project"),
+        )
+        .unwrap();
+
+        assert_eq!(source, UnixPath::new("/var/log/report.xml"));
+        assert_eq!(destination, Path::new("project").join("report.xml"));
     }
-    command
 }
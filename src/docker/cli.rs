@@ -0,0 +1,605 @@
+// This module is the only place where Toast actually runs the Docker CLI. Everything else in the
+// `docker` module goes through the `DockerCli` trait, so it can be tested without a Docker daemon
+// by substituting a fake.
+
+use std::{
+    io,
+    io::{BufRead, BufReader, Read},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    thread::{sleep, JoinHandle},
+    time::Duration,
+};
+
+// How often `run_lines` calls its `tick` callback while the command is running
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+// Extra environment variables for every Docker CLI process (e.g., `DOCKER_CONFIG`). These are
+// decided once at startup based on the configuration.
+static ENVIRONMENT: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+// The IDs of the Docker CLI processes which are running. CTRL+Z at the terminal stops them along
+// with Toast, but the `SIGCONT` which resumes Toast only reaches Toast itself, so these are resumed
+// explicitly [tag:resume_children].
+static CHILDREN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+// Set the extra environment variables for the rest of the program.
+pub fn set_environment(environment: Vec<(String, String)>) {
+    // The `unwrap` will only fail if a panic already occurred.
+    *ENVIRONMENT.lock().unwrap() = environment;
+}
+
+// Resume any Docker CLI processes which were stopped [ref:resume_children].
+#[cfg(unix)]
+pub fn resume_children() {
+    // Hold the lock while sending the signals, so no process can be reaped in the meantime. Once a
+    // process is reaped, its ID could belong to some unrelated process. The `unwrap` will only fail
+    // if a panic already occurred.
+    let children = CHILDREN.lock().unwrap();
+    for id in children.iter() {
+        // The `unwrap` will only fail if the process ID doesn't fit in a `pid_t`, which can't
+        // happen since it came from the operating system.
+        let pid = libc::pid_t::try_from(*id).unwrap();
+
+        // SAFETY: `kill` has no memory safety requirements.
+        if unsafe { libc::kill(pid, libc::SIGCONT) } != 0_i32 {
+            warn!(
+                "Unable to resume Docker CLI process {}. Details: {}",
+                id,
+                io::Error::last_os_error(),
+            );
+        }
+    }
+}
+
+// This keeps track of a running Docker CLI process until it's waited for with `wait` or `try_wait`,
+// which stop tracking the process before reaping it [ref:resume_children]. If the process is never
+// waited for, it's tracked until this is dropped.
+struct Tracked(u32);
+
+impl Tracked {
+    fn new(child: &Child) -> Self {
+        // The `unwrap` will only fail if a panic already occurred.
+        CHILDREN.lock().unwrap().push(child.id());
+        Self(child.id())
+    }
+
+    // Stop tracking the process.
+    fn untrack(&self) {
+        // The `unwrap` will only fail if a panic already occurred.
+        CHILDREN.lock().unwrap().retain(|id| *id != self.0);
+    }
+
+    // Wait for the process to exit, then stop tracking it and reap it.
+    fn wait(&self, child: &mut Child) -> io::Result<ExitStatus> {
+        // Wait for the process to exit without reaping it, so its ID can't be reused until it's no
+        // longer tracked.
+        #[cfg(unix)]
+        loop {
+            // SAFETY: A zeroed `siginfo_t` is a valid value.
+            let mut info = unsafe { std::mem::zeroed::<libc::siginfo_t>() };
+
+            // SAFETY: `info` is valid for writes for the duration of the call.
+            if unsafe {
+                libc::waitid(
+                    libc::P_PID,
+                    self.0,
+                    &raw mut info,
+                    libc::WEXITED | libc::WNOWAIT,
+                )
+            } == 0_i32
+            {
+                break;
+            }
+
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+
+        self.untrack();
+        child.wait()
+    }
+
+    // Check whether the process has exited without blocking. If it has, it's no longer tracked.
+    fn try_wait(&self, child: &mut Child) -> io::Result<Option<ExitStatus>> {
+        // Hold the lock so `resume_children` can't signal the process between reaping it and
+        // untracking it. The `unwrap` will only fail if a panic already occurred.
+        let mut children = CHILDREN.lock().unwrap();
+        let status = child.try_wait()?;
+        if status.is_some() {
+            children.retain(|id| *id != self.0);
+        }
+
+        Ok(status)
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.untrack();
+    }
+}
+
+// What a command did: its exit code (`None` if it was killed by a signal) and whatever it wrote to
+// its standard output and error streams, if they were captured
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Outcome {
+    pub code: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl Outcome {
+    // Whether the command exited successfully
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+
+    // Describe a command that exited with the given status, without any captured output.
+    fn from_status(status: ExitStatus) -> Self {
+        Self {
+            code: status.code(),
+            ..Self::default()
+        }
+    }
+}
+
+// A command running in the background
+pub trait Process: Send {
+    // Check whether the command has finished without blocking.
+    fn try_wait(&mut self) -> io::Result<Option<Outcome>>;
+
+    // Wait for the command to finish.
+    fn wait(&mut self) -> io::Result<Outcome>;
+
+    // Kill the command and wait for it to exit.
+    fn kill(&mut self);
+}
+
+// A way to run Docker commands. Each method takes the arguments to the CLI (e.g., `["image",
+// "inspect", "foo"]`) and differs only in what happens to the standard streams.
+pub trait DockerCli {
+    // Run a command and capture its output.
+    fn run_quiet(&self, args: &[String]) -> io::Result<Outcome>;
+
+    // Run a command with the contents of `stdin` on its standard input, and capture its output.
+    fn run_quiet_stdin(&self, args: &[String], stdin: &mut dyn Read) -> io::Result<Outcome>;
+
+    // Run a command with its output going to the terminal and its standard input closed.
+    fn run_loud(&self, args: &[String]) -> io::Result<Outcome>;
+
+    // Run a command attached to the terminal, including its standard input.
+    fn run_attach(&self, args: &[String]) -> io::Result<Outcome>;
+
+    // Run a command and pass each line it writes to either output stream to `line`. While the
+    // command is running, `tick` is called every so often.
+    fn run_lines(
+        &self,
+        args: &[String],
+        line: Arc<dyn Fn(String) + Send + Sync>,
+        tick: &mut dyn FnMut(),
+    ) -> io::Result<Outcome>;
+
+    // Start a command in the background with its standard input closed. If `show_output` is
+    // `true`, its output goes to the terminal. Otherwise, its standard output is discarded and its
+    // standard error stream is captured.
+    fn spawn(&self, args: &[String], show_output: bool) -> io::Result<Box<dyn Process>>;
+}
+
+// The real Docker CLI is identified by the name of (or path to) its binary.
+impl DockerCli for str {
+    fn run_quiet(&self, args: &[String]) -> io::Result<Outcome> {
+        let mut child = command(self, args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let tracked = Tracked::new(&child);
+        wait_with_output(&mut child, &tracked)
+    }
+
+    fn run_quiet_stdin(&self, args: &[String], stdin: &mut dyn Read) -> io::Result<Outcome> {
+        let mut child = command(self, args)
+            .stdin(Stdio::piped()) // [tag:run_quiet_stdin_piped]
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let tracked = Tracked::new(&child);
+
+        // Pipe data to the child's standard input stream, then close it so the child knows there's
+        // nothing more to read.
+        io::copy(stdin, child.stdin.as_mut().unwrap())?; // [ref:run_quiet_stdin_piped]
+        drop(child.stdin.take());
+
+        wait_with_output(&mut child, &tracked)
+    }
+
+    fn run_loud(&self, args: &[String]) -> io::Result<Outcome> {
+        let mut child = command(self, args).stdin(Stdio::null()).spawn()?;
+        let tracked = Tracked::new(&child);
+        tracked.wait(&mut child).map(Outcome::from_status)
+    }
+
+    fn run_attach(&self, args: &[String]) -> io::Result<Outcome> {
+        let mut child = command(self, args).spawn()?;
+        let tracked = Tracked::new(&child);
+        tracked.wait(&mut child).map(Outcome::from_status)
+    }
+
+    fn run_lines(
+        &self,
+        args: &[String],
+        line: Arc<dyn Fn(String) + Send + Sync>,
+        tick: &mut dyn FnMut(),
+    ) -> io::Result<Outcome> {
+        let mut child = command(self, args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped()) // [tag:run_lines_stdout_piped]
+            .stderr(Stdio::piped()) // [tag:run_lines_stderr_piped]
+            .spawn()?;
+        let tracked = Tracked::new(&child);
+
+        // Read both output streams on separate threads.
+        let readers = vec![
+            read_lines(child.stdout.take().unwrap(), line.clone()), // [ref:run_lines_stdout_piped]
+            read_lines(child.stderr.take().unwrap(), line),         // [ref:run_lines_stderr_piped]
+        ];
+
+        // Wait for the child to terminate.
+        let status = loop {
+            if let Some(status) = tracked.try_wait(&mut child)? {
+                break status;
+            }
+
+            tick();
+            sleep(TICK_INTERVAL);
+        };
+
+        // Wait for the readers to drain the streams. The `unwrap` will only fail if a panic already
+        // occurred.
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        Ok(Outcome::from_status(status))
+    }
+
+    fn spawn(&self, args: &[String], show_output: bool) -> io::Result<Box<dyn Process>> {
+        let mut command = command(self, args);
+        command.stdin(Stdio::null());
+        if !show_output {
+            command.stdout(Stdio::null()).stderr(Stdio::piped());
+        }
+        let mut child = command.spawn()?;
+        let tracked = Tracked::new(&child);
+
+        // Read the standard error stream concurrently so the child can't block on a full pipe.
+        let stderr = child.stderr.take().map(read_all);
+
+        Ok(Box::new(ChildProcess {
+            child,
+            stderr,
+            tracked,
+        }))
+    }
+}
+
+// This allows a `&String` (e.g., from the settings) to be used wherever a CLI is expected.
+impl DockerCli for String {
+    fn run_quiet(&self, args: &[String]) -> io::Result<Outcome> {
+        self.as_str().run_quiet(args)
+    }
+
+    fn run_quiet_stdin(&self, args: &[String], stdin: &mut dyn Read) -> io::Result<Outcome> {
+        self.as_str().run_quiet_stdin(args, stdin)
+    }
+
+    fn run_loud(&self, args: &[String]) -> io::Result<Outcome> {
+        self.as_str().run_loud(args)
+    }
+
+    fn run_attach(&self, args: &[String]) -> io::Result<Outcome> {
+        self.as_str().run_attach(args)
+    }
+
+    fn run_lines(
+        &self,
+        args: &[String],
+        line: Arc<dyn Fn(String) + Send + Sync>,
+        tick: &mut dyn FnMut(),
+    ) -> io::Result<Outcome> {
+        self.as_str().run_lines(args, line, tick)
+    }
+
+    fn spawn(&self, args: &[String], show_output: bool) -> io::Result<Box<dyn Process>> {
+        self.as_str().spawn(args, show_output)
+    }
+}
+
+// A child process of the real Docker CLI running in the background
+struct ChildProcess {
+    child: Child,
+    stderr: Option<JoinHandle<Vec<u8>>>,
+    tracked: Tracked,
+}
+
+impl ChildProcess {
+    // Describe the finished child, including what it wrote to its standard error stream.
+    fn outcome(&mut self, status: ExitStatus) -> Outcome {
+        Outcome {
+            // The `unwrap` will only fail if the reader thread panicked.
+            stderr: self
+                .stderr
+                .take()
+                .map(|reader| reader.join().unwrap())
+                .unwrap_or_default(),
+            ..Outcome::from_status(status)
+        }
+    }
+}
+
+impl Process for ChildProcess {
+    fn try_wait(&mut self) -> io::Result<Option<Outcome>> {
+        Ok(self
+            .tracked
+            .try_wait(&mut self.child)?
+            .map(|status| self.outcome(status)))
+    }
+
+    fn wait(&mut self) -> io::Result<Outcome> {
+        let status = self.tracked.wait(&mut self.child)?;
+        Ok(self.outcome(status))
+    }
+
+    fn kill(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.tracked.wait(&mut self.child);
+    }
+}
+
+// Spawn a thread which passes each line of a stream to a callback until the stream is closed.
+fn read_lines<R: Read + Send + 'static>(
+    stream: R,
+    line: Arc<dyn Fn(String) + Send + Sync>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stream);
+        let mut buffer = vec![];
+        loop {
+            buffer.clear();
+            match reader.read_until(b'\n', &mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => line(
+                    String::from_utf8_lossy(&buffer)
+                        .trim_end_matches(['\r', '\n'])
+                        .to_owned(),
+                ),
+            }
+        }
+    })
+}
+
+// Spawn a thread which reads an output stream of a child process to the end.
+fn read_all<R: Read + Send + 'static>(mut stream: R) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut contents = vec![];
+        let _ = stream.read_to_end(&mut contents);
+        contents
+    })
+}
+
+// Wait for a child process, capturing whatever it writes to its output streams. The streams are
+// read concurrently so the child can't block on a full pipe.
+fn wait_with_output(child: &mut Child, tracked: &Tracked) -> io::Result<Outcome> {
+    let stdout = child.stdout.take().map(read_all);
+    let stderr = child.stderr.take().map(read_all);
+    let status = tracked.wait(child)?;
+
+    // The `unwrap`s will only fail if a reader thread panicked.
+    Ok(Outcome {
+        stdout: stdout
+            .map(|reader| reader.join().unwrap())
+            .unwrap_or_default(),
+        stderr: stderr
+            .map(|reader| reader.join().unwrap())
+            .unwrap_or_default(),
+        ..Outcome::from_status(status)
+    })
+}
+
+// Construct a Docker `Command` from an array of arguments. The command inherits the extra
+// environment variables given to `set_environment`.
+pub fn command(docker_cli: &str, args: &[String]) -> Command {
+    let mut command = Command::new(docker_cli);
+    for arg in args {
+        command.arg(arg);
+    }
+
+    // The `unwrap` will only fail if a panic already occurred.
+    for (variable, value) in ENVIRONMENT.lock().unwrap().iter() {
+        command.env(variable, value);
+    }
+
+    command
+}
+
+// A scriptable stand-in for the Docker CLI. It records the arguments of each command and responds
+// with the scripted outcomes in order. Once the script runs out, commands succeed with no output.
+#[cfg(test)]
+pub struct FakeCli {
+    calls: std::sync::Mutex<Vec<Vec<String>>>,
+    stdin: std::sync::Mutex<Vec<u8>>,
+    outcomes: std::sync::Mutex<std::collections::VecDeque<Outcome>>,
+}
+
+#[cfg(test)]
+impl FakeCli {
+    pub fn new(outcomes: Vec<Outcome>) -> Self {
+        Self {
+            calls: std::sync::Mutex::new(vec![]),
+            stdin: std::sync::Mutex::new(vec![]),
+            outcomes: std::sync::Mutex::new(outcomes.into()),
+        }
+    }
+
+    // The arguments of each command that was run, in order
+    pub fn calls(&self) -> Vec<Vec<String>> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    // Everything that was written to the standard input of the commands
+    pub fn stdin(&self) -> Vec<u8> {
+        self.stdin.lock().unwrap().clone()
+    }
+
+    // Record a command and return its scripted outcome.
+    fn next(&self, args: &[String]) -> Outcome {
+        self.calls.lock().unwrap().push(args.to_vec());
+        self.outcomes
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or_else(|| Outcome {
+                code: Some(0_i32),
+                ..Outcome::default()
+            })
+    }
+}
+
+// A command of the fake CLI, which finishes as soon as it starts
+#[cfg(test)]
+struct FakeProcess(Outcome);
+
+#[cfg(test)]
+impl Process for FakeProcess {
+    fn try_wait(&mut self) -> io::Result<Option<Outcome>> {
+        Ok(Some(self.0.clone()))
+    }
+
+    fn wait(&mut self) -> io::Result<Outcome> {
+        Ok(self.0.clone())
+    }
+
+    fn kill(&mut self) {}
+}
+
+#[cfg(test)]
+impl DockerCli for FakeCli {
+    fn run_quiet(&self, args: &[String]) -> io::Result<Outcome> {
+        Ok(self.next(args))
+    }
+
+    fn run_quiet_stdin(&self, args: &[String], stdin: &mut dyn Read) -> io::Result<Outcome> {
+        stdin.read_to_end(&mut self.stdin.lock().unwrap())?;
+        Ok(self.next(args))
+    }
+
+    fn run_loud(&self, args: &[String]) -> io::Result<Outcome> {
+        Ok(self.next(args))
+    }
+
+    fn run_attach(&self, args: &[String]) -> io::Result<Outcome> {
+        Ok(self.next(args))
+    }
+
+    fn run_lines(
+        &self,
+        args: &[String],
+        line: Arc<dyn Fn(String) + Send + Sync>,
+        tick: &mut dyn FnMut(),
+    ) -> io::Result<Outcome> {
+        let outcome = self.next(args);
+        tick();
+        for output_line in String::from_utf8_lossy(&outcome.stdout).lines() {
+            line(output_line.to_owned());
+        }
+        Ok(outcome)
+    }
+
+    fn spawn(&self, args: &[String], _show_output: bool) -> io::Result<Box<dyn Process>> {
+        Ok(Box::new(FakeProcess(self.next(args))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::docker::cli::{command, set_environment};
+
+    #[test]
+    fn command_environment() {
+        set_environment(vec![
+            ("DOCKER_CONFIG".to_owned(), "/etc/toast/docker".to_owned()),
+            ("TOAST_TEST_VAR".to_owned(), "foo bar".to_owned()),
+        ]);
+
+        // Check the environment of the command itself.
+        let command = command("docker", &["version".to_owned()]);
+        let environment = command
+            .get_envs()
+            .map(|(variable, value)| (variable.to_owned(), value.map(ToOwned::to_owned)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            environment,
+            vec![
+                ("DOCKER_CONFIG".into(), Some("/etc/toast/docker".into())),
+                ("TOAST_TEST_VAR".into(), Some("foo bar".into())),
+            ],
+        );
+
+        // Check that a child process actually sees the variables.
+        #[cfg(unix)]
+        {
+            use crate::docker::cli::DockerCli;
+
+            let outcome = "sh"
+                .run_quiet(&[
+                    "-c".to_owned(),
+                    "printf '%s:%s' \"$DOCKER_CONFIG\" \"$TOAST_TEST_VAR\"".to_owned(),
+                ])
+                .unwrap();
+            assert!(outcome.success());
+            assert_eq!(outcome.stdout, b"/etc/toast/docker:foo bar");
+        }
+
+        set_environment(vec![]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resume_stopped_child() {
+        use {
+            crate::docker::cli::{resume_children, DockerCli},
+            std::{
+                sync::{
+                    atomic::{AtomicBool, Ordering},
+                    Arc,
+                },
+                thread,
+                time::Duration,
+            },
+        };
+
+        // Keep resuming the Docker CLI processes until the child finishes, as `SIGCONT` would.
+        let finished = Arc::new(AtomicBool::new(false));
+        let resumer_finished = finished.clone();
+        let resumer = thread::spawn(move || {
+            while !resumer_finished.load(Ordering::SeqCst) {
+                resume_children();
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+
+        // The child stops itself, as it would if the user pressed CTRL+Z.
+        let outcome = "sh"
+            .run_quiet(&["-c".to_owned(), "kill -STOP $$; printf resumed".to_owned()])
+            .unwrap();
+        finished.store(true, Ordering::SeqCst);
+        resumer.join().unwrap();
+
+        assert!(outcome.success());
+        assert_eq!(outcome.stdout, b"resumed");
+    }
+}
@@ -0,0 +1,141 @@
+// This module centralizes the interpretation of error messages printed by the Docker CLI (or a
+// drop-in replacement such as Podman). Exit codes alone are not enough to distinguish between
+// different kinds of failures, so we have to match on the standard error stream.
+
+// Messages which indicate that an image doesn't exist
+const IMAGE_NOT_FOUND_PATTERNS: &[&str] = &[
+    "no such image",   // Docker
+    "no such object",  // Older versions of Docker
+    "image not known", // Podman
+];
+
+// Messages which indicate that an image doesn't exist in a registry
+const MANIFEST_NOT_FOUND_PATTERNS: &[&str] = &[
+    "no such manifest", // Docker
+    "manifest unknown", // The registry's own error, as relayed by some versions of Docker
+];
+
+// Messages which indicate that the Docker daemon can't be reached
+const DAEMON_UNREACHABLE_PATTERNS: &[&str] = &[
+    "cannot connect to the docker daemon",
+    "error during connect",
+    "connection refused",
+    "is the docker daemon running",
+];
+
+// Messages which indicate that the Docker daemon has no runtime for GPUs
+const GPU_RUNTIME_MISSING_PATTERNS: &[&str] = &[
+    "could not select device driver",
+    "unknown or invalid runtime name: nvidia",
+    "nvidia-container-cli",
+];
+
+// Check whether some text contains any of the given patterns, ignoring case.
+fn contains_any(text: &str, patterns: &[&str]) -> bool {
+    let text = text.to_lowercase();
+    patterns.iter().any(|pattern| text.contains(pattern))
+}
+
+// Determine whether the standard error output of a command indicates that an image doesn't exist.
+pub fn is_image_not_found(stderr: &str) -> bool {
+    !is_daemon_unreachable(stderr) && contains_any(stderr, IMAGE_NOT_FOUND_PATTERNS)
+}
+
+// Determine whether the standard error output of `docker manifest inspect` indicates that an image
+// doesn't exist in the registry.
+pub fn is_manifest_not_found(stderr: &str) -> bool {
+    !is_daemon_unreachable(stderr) && contains_any(stderr, MANIFEST_NOT_FOUND_PATTERNS)
+}
+
+// Determine whether the standard error output of a command indicates that the Docker daemon can't
+// be reached.
+pub fn is_daemon_unreachable(stderr: &str) -> bool {
+    contains_any(stderr, DAEMON_UNREACHABLE_PATTERNS)
+}
+
+// Determine whether the standard error output of a command indicates that the Docker daemon can't
+// provide GPUs to containers.
+pub fn is_gpu_runtime_missing(stderr: &str) -> bool {
+    contains_any(stderr, GPU_RUNTIME_MISSING_PATTERNS)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::docker::errors::{
+        is_daemon_unreachable, is_gpu_runtime_missing, is_image_not_found, is_manifest_not_found,
+    };
+
+    const DOCKER_NOT_FOUND: &str = "Error response from daemon: No such image: foo:bar\n";
+
+    const DOCKER_OLD_NOT_FOUND: &str = "Error: No such object: foo:bar\n";
+
+    const PODMAN_NOT_FOUND: &str = "Error: inspecting object: foo:bar: image not known\n";
+
+    const MANIFEST_NOT_FOUND: &str = "no such manifest: registry.example.com/toast:toast-3d5f\n";
+
+    const MANIFEST_UNKNOWN: &str = "manifest unknown: manifest unknown\n";
+
+    const CONNECTION_REFUSED: &str = "Cannot connect to the Docker daemon at \
+        unix:///var/run/docker.sock. Is the docker daemon running?\n";
+
+    const ERROR_DURING_CONNECT: &str = "error during connect: Get \
+        \"http://%2F%2F.%2Fpipe%2Fdocker_engine/v1.24/images/foo:bar/json\": open \
+        //./pipe/docker_engine: The system cannot find the file specified.\n";
+
+    const GPU_RUNTIME_MISSING: &str =
+        "docker: Error response from daemon: could not select device \
+        driver \"\" with capabilities: [[gpu]].\n";
+
+    #[test]
+    fn image_not_found_docker() {
+        assert!(is_image_not_found(DOCKER_NOT_FOUND));
+        assert!(!is_daemon_unreachable(DOCKER_NOT_FOUND));
+    }
+
+    #[test]
+    fn image_not_found_docker_old() {
+        assert!(is_image_not_found(DOCKER_OLD_NOT_FOUND));
+        assert!(!is_daemon_unreachable(DOCKER_OLD_NOT_FOUND));
+    }
+
+    #[test]
+    fn image_not_found_podman() {
+        assert!(is_image_not_found(PODMAN_NOT_FOUND));
+        assert!(!is_daemon_unreachable(PODMAN_NOT_FOUND));
+    }
+
+    #[test]
+    fn manifest_not_found() {
+        assert!(is_manifest_not_found(MANIFEST_NOT_FOUND));
+        assert!(is_manifest_not_found(MANIFEST_UNKNOWN));
+        assert!(!is_image_not_found(MANIFEST_NOT_FOUND));
+        assert!(!is_manifest_not_found(DOCKER_NOT_FOUND));
+    }
+
+    #[test]
+    fn connection_refused() {
+        assert!(!is_image_not_found(CONNECTION_REFUSED));
+        assert!(!is_manifest_not_found(CONNECTION_REFUSED));
+        assert!(is_daemon_unreachable(CONNECTION_REFUSED));
+    }
+
+    #[test]
+    fn error_during_connect() {
+        assert!(!is_image_not_found(ERROR_DURING_CONNECT));
+        assert!(is_daemon_unreachable(ERROR_DURING_CONNECT));
+    }
+
+    #[test]
+    fn gpu_runtime_missing() {
+        assert!(is_gpu_runtime_missing(GPU_RUNTIME_MISSING));
+        assert!(!is_image_not_found(GPU_RUNTIME_MISSING));
+        assert!(!is_daemon_unreachable(GPU_RUNTIME_MISSING));
+    }
+
+    #[test]
+    fn unrelated_error() {
+        assert!(!is_image_not_found("Error: permission denied\n"));
+        assert!(!is_daemon_unreachable("Error: permission denied\n"));
+        assert!(!is_gpu_runtime_missing("Error: permission denied\n"));
+    }
+}
@@ -0,0 +1,248 @@
+use crate::format::CodeStr;
+
+// Parse the contents of a dotenv file into a list of variables and their values, in the order they
+// appear. Each line has the form `NAME=value`, optionally preceded by `export`. Blank lines and
+// lines starting with `#` are ignored. Values can be wrapped in single quotes (taken literally) or
+// double quotes (which understand `\n`, `\t`, `\"`, and `\\`). An unquoted value ends at a `#`
+// which follows whitespace, and surrounding whitespace is removed.
+pub fn parse(contents: &str) -> Result<Vec<(String, String)>, String> {
+    let mut variables = vec![];
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line
+            .strip_prefix("export")
+            .filter(|rest| rest.starts_with(char::is_whitespace))
+            .map_or(line, str::trim_start);
+
+        let Some((name, value)) = line.split_once('=') else {
+            return Err(format!(
+                "Line {} should have the form {}.",
+                index + 1,
+                "NAME=value".code_str(),
+            ));
+        };
+
+        let name = name.trim_end();
+        if !valid_name(name) {
+            return Err(format!(
+                "Line {} has an invalid variable name: {}.",
+                index + 1,
+                name.code_str(),
+            ));
+        }
+
+        let value = parse_value(value.trim_start())
+            .map_err(|reason| format!("Line {} has an invalid value. {}", index + 1, reason))?;
+
+        variables.push((name.to_owned(), value));
+    }
+
+    Ok(variables)
+}
+
+// Variable names consist of letters, digits, and underscores, and don't start with a digit.
+fn valid_name(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && name
+            .chars()
+            .all(|character| character.is_ascii_alphanumeric() || character == '_')
+}
+
+// Parse the part of a line after the `=`, with leading whitespace already removed.
+fn parse_value(value: &str) -> Result<String, String> {
+    if let Some(rest) = value.strip_prefix('\'') {
+        let Some(end) = rest.find('\'') else {
+            return Err("The single quote isn't closed.".to_owned());
+        };
+        check_trailing(&rest[end + 1..])?;
+
+        Ok(rest[..end].to_owned())
+    } else if let Some(rest) = value.strip_prefix('"') {
+        let mut result = String::new();
+        let mut characters = rest.char_indices();
+        while let Some((position, character)) = characters.next() {
+            match character {
+                '"' => {
+                    check_trailing(&rest[position + 1..])?;
+                    return Ok(result);
+                }
+                '\\' => match characters.next() {
+                    Some((_, 'n')) => result.push('\n'),
+                    Some((_, 't')) => result.push('\t'),
+                    Some((_, other @ ('"' | '\\'))) => result.push(other),
+                    Some((_, other)) => {
+                        result.push('\\');
+                        result.push(other);
+                    }
+                    None => result.push('\\'),
+                },
+                _ => result.push(character),
+            }
+        }
+
+        Err("The double quote isn't closed.".to_owned())
+    } else {
+        // A comment has to be preceded by whitespace, so `#` can appear in the middle of a value.
+        let end = value
+            .char_indices()
+            .find(|&(position, character)| {
+                character == '#' && value[..position].ends_with(char::is_whitespace)
+            })
+            .map_or(value.len(), |(position, _)| position);
+
+        Ok(value[..end].trim_end().to_owned())
+    }
+}
+
+// Only a comment can follow a quoted value.
+fn check_trailing(trailing: &str) -> Result<(), String> {
+    let trailing = trailing.trim_start();
+    if trailing.is_empty() || trailing.starts_with('#') {
+        Ok(())
+    } else {
+        Err("Only a comment can follow a quoted value.".to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dotenv::parse;
+
+    fn variables(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| ((*name).to_owned(), (*value).to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_empty() {
+        assert_eq!(parse(""), Ok(vec![]));
+    }
+
+    #[test]
+    fn parse_blank_lines_and_comments() {
+        let contents = "
+# Credentials for the staging account
+
+AWS_REGION=us-west-2
+  # Indented comments are fine too.
+AWS_PROFILE=staging
+
+";
+
+        assert_eq!(
+            parse(contents),
+            Ok(variables(&[
+                ("AWS_REGION", "us-west-2"),
+                ("AWS_PROFILE", "staging"),
+            ])),
+        );
+    }
+
+    #[test]
+    fn parse_export() {
+        assert_eq!(
+            parse("export FOO=bar\nexport\tBAZ=qux\nexported=yes\n"),
+            Ok(variables(&[
+                ("FOO", "bar"),
+                ("BAZ", "qux"),
+                ("exported", "yes")
+            ])),
+        );
+    }
+
+    #[test]
+    fn parse_whitespace() {
+        assert_eq!(
+            parse("  FOO = bar baz  \r\nEMPTY=\n"),
+            Ok(variables(&[("FOO", "bar baz"), ("EMPTY", "")])),
+        );
+    }
+
+    #[test]
+    fn parse_unquoted_comment() {
+        assert_eq!(
+            parse("FOO=bar # the bar\nCOLOR=#ff0000\nURL=http://host/#anchor\n"),
+            Ok(variables(&[
+                ("FOO", "bar"),
+                ("COLOR", "#ff0000"),
+                ("URL", "http://host/#anchor"),
+            ])),
+        );
+    }
+
+    #[test]
+    fn parse_single_quotes() {
+        assert_eq!(
+            parse("FOO=' bar # \\n \"baz\" $HOME' # comment\n"),
+            Ok(variables(&[("FOO", " bar # \\n \"baz\" $HOME")])),
+        );
+    }
+
+    #[test]
+    fn parse_double_quotes() {
+        assert_eq!(
+            parse("FOO=\"a\\nb\\tc \\\"d\\\" \\\\ \\x 'e' # f\"\n"),
+            Ok(variables(&[("FOO", "a\nb\tc \"d\" \\ \\x 'e' # f")])),
+        );
+    }
+
+    #[test]
+    fn parse_equals_in_value() {
+        assert_eq!(parse("TOKEN=abc==\n"), Ok(variables(&[("TOKEN", "abc==")])),);
+    }
+
+    #[test]
+    fn parse_duplicate() {
+        assert_eq!(
+            parse("FOO=bar\nFOO=baz\n"),
+            Ok(variables(&[("FOO", "bar"), ("FOO", "baz")])),
+        );
+    }
+
+    #[test]
+    fn parse_missing_equals() {
+        let message = parse("FOO=bar\nBAZ\n").unwrap_err();
+
+        assert!(message.contains("Line 2"));
+        assert!(message.contains("NAME=value"));
+    }
+
+    #[test]
+    fn parse_invalid_name() {
+        for contents in ["=bar", "1FOO=bar", "FOO-BAR=baz", "export =bar"] {
+            let message = parse(contents).unwrap_err();
+
+            assert!(message.contains("Line 1"), "{contents}");
+            assert!(message.contains("invalid variable name"), "{contents}");
+        }
+    }
+
+    #[test]
+    fn parse_unclosed_quotes() {
+        assert!(parse("FOO='bar\n")
+            .unwrap_err()
+            .contains("single quote isn't closed"));
+        assert!(parse("FOO=\"bar\n")
+            .unwrap_err()
+            .contains("double quote isn't closed"));
+    }
+
+    #[test]
+    fn parse_trailing_after_quotes() {
+        assert!(parse("FOO='bar' baz\n")
+            .unwrap_err()
+            .contains("Only a comment"));
+        assert!(parse("FOO=\"bar\" baz\n")
+            .unwrap_err()
+            .contains("Only a comment"));
+    }
+}
@@ -11,6 +11,34 @@ pub enum Failure {
     User(String, Option<Box<dyn error::Error>>),
 }
 
+// The codes that Toast exits with, other than the codes of failed commands [tag:exit_codes]
+const USER_EXIT_CODE: i32 = 1;
+const SYSTEM_EXIT_CODE: i32 = 70;
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+impl Failure {
+    // Determine the code that Toast should exit with because of this failure. If the user's command
+    // failed, that's the code it exited with.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Interrupted => INTERRUPTED_EXIT_CODE,
+            Self::System(_, _) => SYSTEM_EXIT_CODE,
+            Self::User(_, source) => {
+                let mut source = source.as_deref();
+                while let Some(error) = source {
+                    if let Some(CommandExit(code)) = error.downcast_ref::<CommandExit>() {
+                        if *code != 0 {
+                            return *code;
+                        }
+                    }
+                    source = error.source();
+                }
+                USER_EXIT_CODE
+            }
+        }
+    }
+}
+
 impl fmt::Display for Failure {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -33,6 +61,19 @@ impl error::Error for Failure {
     }
 }
 
+// This records the code that a user's command exited with. It's attached to the corresponding user
+// failure as its source, so Toast can exit with the same code.
+#[derive(Debug)]
+pub struct CommandExit(pub i32);
+
+impl fmt::Display for CommandExit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The command exited with code {}.", self.0)
+    }
+}
+
+impl error::Error for CommandExit {}
+
 // This is a helper function to convert a `std::error::Error` into a system failure. It's written in
 // a curried style so it can be used in a higher-order fashion, e.g.,
 // `foo.map_err(failure::system("Error doing foo."))`.
@@ -48,3 +89,52 @@ pub fn user<S: Into<String>, E: error::Error + 'static>(message: S) -> impl FnOn
     let message = message.into();
     move |error: E| Failure::User(message, Some(Box::new(error)))
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::failure::{CommandExit, Failure};
+
+    #[test]
+    fn exit_code_interrupted() {
+        assert_eq!(Failure::Interrupted.exit_code(), 130_i32);
+    }
+
+    #[test]
+    fn exit_code_system() {
+        assert_eq!(
+            Failure::System("Docker isn't running.".to_owned(), None).exit_code(),
+            70_i32,
+        );
+    }
+
+    #[test]
+    fn exit_code_user() {
+        assert_eq!(
+            Failure::User("Invalid toastfile.".to_owned(), None).exit_code(),
+            1_i32,
+        );
+    }
+
+    #[test]
+    fn exit_code_command() {
+        let failure = Failure::User(
+            "The command failed.".to_owned(),
+            Some(Box::new(CommandExit(101_i32))),
+        );
+
+        assert_eq!(failure.exit_code(), 101_i32);
+    }
+
+    #[test]
+    fn exit_code_command_nested() {
+        let failure = Failure::User(
+            "Task `test` failed during command execution.".to_owned(),
+            Some(Box::new(Failure::User(
+                "The command failed.".to_owned(),
+                Some(Box::new(CommandExit(2_i32))),
+            ))),
+        );
+
+        assert_eq!(failure.exit_code(), 2_i32);
+    }
+}
@@ -1,4 +1,11 @@
-use colored::{control::SHOULD_COLORIZE, ColoredString, Colorize};
+use {
+    crate::units,
+    colored::{control::SHOULD_COLORIZE, ColoredString, Colorize},
+    std::time::Duration,
+};
+
+// How many characters of the toastfile's content hash the run summary shows
+const SUMMARY_HASH_LENGTH: usize = 12;
 
 // This trait has a function for formatting "code-like" text, such as a file path. The reason it's
 // implemented as a trait and not just a function is so we can use it with method syntax, as in
@@ -19,6 +26,12 @@ impl CodeStr for str {
     }
 }
 
+// This function returns the prefix for a line of output from a task, e.g., "[build] ". The task name
+// is colored if colored output is enabled.
+pub fn output_prefix(task_name: &str) -> String {
+    format!("{} ", format!("[{task_name}]").cyan())
+}
+
 // This function takes a number and a noun and returns a string representing the noun with the
 // given multiplicity (pluralizing if necessary). For example, (3, "cow") becomes "3 cows".
 pub fn number(n: usize, noun: &str) -> String {
@@ -44,9 +57,56 @@ pub fn series(items: &[String]) -> String {
     }
 }
 
+// This function describes the outcome of a run in a single line, e.g.,
+// "toast: 7 tasks, 5 cached, 2 ran, 0 failed, 1m 42s, toastfile 3f2a9c1b4e5d". It's meant to be
+// read by machines as well as humans, so it doesn't use colors or code formatting. Each of the
+// `failures` describes a task which failed, e.g., "build" or "build during command execution". The
+// end of the line identifies the version of the toastfile the run used, by a prefix of its content
+// hash.
+pub fn summary(
+    tasks: usize,
+    cached: usize,
+    ran: usize,
+    failures: &[String],
+    elapsed: Duration,
+    toastfile_hash: &str,
+) -> String {
+    format!(
+        "toast: {}, {} cached, {} ran, {} failed{}, {}, toastfile {}",
+        number(tasks, "task"),
+        cached,
+        ran,
+        failures.len(),
+        if failures.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", series(failures))
+        },
+        units::format_duration(elapsed),
+        &toastfile_hash[..toastfile_hash.len().min(SUMMARY_HASH_LENGTH)],
+    )
+}
+
+// Describe a run which failed before any task started (e.g., because an input file is missing) in
+// the same form as `summary`, e.g., "toast: 7 tasks, failed before any task started, 0s, toastfile
+// 3f2a9c1b4e5d".
+pub fn early_failure_summary(tasks: usize, elapsed: Duration, toastfile_hash: &str) -> String {
+    format!(
+        "toast: {}, failed before any task started, {}, toastfile {}",
+        number(tasks, "task"),
+        units::format_duration(elapsed),
+        &toastfile_hash[..toastfile_hash.len().min(SUMMARY_HASH_LENGTH)],
+    )
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::format::{number, series, CodeStr};
+    use {
+        crate::format::{early_failure_summary, number, output_prefix, series, summary, CodeStr},
+        std::time::Duration,
+    };
+
+    const HASH: &str = "3f2a9c1b4e5d6f708192a3b4c5d6e7f8";
 
     #[test]
     fn code_str_display() {
@@ -54,6 +114,12 @@ mod tests {
         assert_eq!(format!("{}", "foo".code_str()), "`foo`");
     }
 
+    #[test]
+    fn output_prefix_colorless() {
+        // This test depends on colors being disabled [ref:colorless_tests].
+        assert_eq!(output_prefix("build"), "[build] ");
+    }
+
     #[test]
     fn number_zero() {
         assert_eq!(number(0, "cow"), "0 cows");
@@ -91,4 +157,52 @@ mod tests {
             "foo, bar, and baz",
         );
     }
+
+    #[test]
+    fn summary_success() {
+        assert_eq!(
+            summary(7, 5, 2, &[], Duration::from_secs(102), HASH),
+            "toast: 7 tasks, 5 cached, 2 ran, 0 failed, 1m 42s, toastfile 3f2a9c1b4e5d",
+        );
+    }
+
+    #[test]
+    fn summary_failure() {
+        assert_eq!(
+            summary(7, 5, 0, &["build".to_owned()], Duration::from_secs(3), HASH),
+            "toast: 7 tasks, 5 cached, 0 ran, 1 failed (build), 3s, toastfile 3f2a9c1b4e5d",
+        );
+    }
+
+    #[test]
+    fn summary_failure_phase() {
+        assert_eq!(
+            summary(
+                7,
+                5,
+                1,
+                &["test during output extraction".to_owned()],
+                Duration::from_secs(3),
+                HASH,
+            ),
+            "toast: 7 tasks, 5 cached, 1 ran, 1 failed (test during output extraction), 3s, \
+             toastfile 3f2a9c1b4e5d",
+        );
+    }
+
+    #[test]
+    fn summary_one_task() {
+        assert_eq!(
+            summary(1, 1, 0, &[], Duration::ZERO, "abc"),
+            "toast: 1 task, 1 cached, 0 ran, 0 failed, 0s, toastfile abc",
+        );
+    }
+
+    #[test]
+    fn early_failure_summary_tasks() {
+        assert_eq!(
+            early_failure_summary(7, Duration::from_secs(2), HASH),
+            "toast: 7 tasks, failed before any task started, 2s, toastfile 3f2a9c1b4e5d",
+        );
+    }
 }
@@ -0,0 +1,117 @@
+use {
+    crate::{schedule::Plan, toastfile::Toastfile},
+    std::fmt::Write as _,
+};
+
+// Render the dependency graph of the tasks in a plan as a Graphviz DOT document, for `--graph`.
+// The default task, if it's included, is drawn in bold.
+pub fn render(toastfile: &Toastfile, plan: &Plan) -> String {
+    // Sort the steps by name so the output is deterministic.
+    let mut steps = plan.steps.iter().collect::<Vec<_>>();
+    steps.sort_unstable_by_key(|step| step.task);
+
+    let mut output = String::from("digraph toast {\n");
+
+    // Declare the nodes.
+    for step in &steps {
+        if toastfile.default.as_deref() == Some(step.task) {
+            let _ = writeln!(output, "  {} [style=bold];", quote(step.task));
+        } else {
+            let _ = writeln!(output, "  {};", quote(step.task));
+        }
+    }
+
+    // Declare the edges, pointing from each task to its dependencies.
+    for step in &steps {
+        for dependency in &step.dependencies {
+            let _ = writeln!(output, "  {} -> {};", quote(step.task), quote(dependency));
+        }
+    }
+
+    output.push_str("}\n");
+    output
+}
+
+// Quote a task name as a DOT identifier.
+fn quote(name: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in name.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{graph::render, schedule::plan, toastfile::parse};
+
+    const TOASTFILE: &str = r#"
+image: encom:os-12
+default: test
+tasks:
+  install_deps: {}
+  build:
+    dependencies:
+      - install_deps
+  test:
+    dependencies:
+      - build
+      - install_deps
+  'say "hi"':
+    dependencies:
+      - build
+"#;
+
+    #[test]
+    fn render_all() {
+        let toastfile = parse(TOASTFILE).unwrap();
+        let tasks = toastfile
+            .tasks
+            .keys()
+            .map(AsRef::as_ref)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            render(&toastfile, &plan(&toastfile, &tasks)),
+            r#"digraph toast {
+  "build";
+  "install_deps";
+  "say \"hi\"";
+  "test" [style=bold];
+  "build" -> "install_deps";
+  "say \"hi\"" -> "build";
+  "test" -> "build";
+  "test" -> "install_deps";
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn render_closure() {
+        let toastfile = parse(TOASTFILE).unwrap();
+        assert_eq!(
+            render(&toastfile, &plan(&toastfile, &["build"])),
+            r#"digraph toast {
+  "build";
+  "install_deps";
+  "build" -> "install_deps";
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn render_empty() {
+        let toastfile = parse(TOASTFILE).unwrap();
+
+        assert_eq!(
+            render(&toastfile, &plan(&toastfile, &[])),
+            "digraph toast {\n}\n",
+        );
+    }
+}
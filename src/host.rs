@@ -0,0 +1,128 @@
+use std::{fs, num::NonZeroUsize, thread::available_parallelism};
+
+// The cgroup v2 file which contains the CPU quota and period
+const CGROUP_V2_CPU_MAX_PATH: &str = "/sys/fs/cgroup/cpu.max";
+
+// The cgroup v1 files which contain the CPU quota and period
+const CGROUP_V1_CPU_QUOTA_PATH: &str = "/sys/fs/cgroup/cpu/cpu.cfs_quota_us";
+const CGROUP_V1_CPU_PERIOD_PATH: &str = "/sys/fs/cgroup/cpu/cpu.cfs_period_us";
+
+// Convert a CPU quota and period (both in microseconds) into a number of CPUs, rounding up.
+fn cpus_from_quota(quota: i64, period: i64) -> Option<usize> {
+    if quota <= 0 || period <= 0 {
+        return None;
+    }
+
+    usize::try_from((quota + period - 1) / period).ok()
+}
+
+// Parse the contents of a cgroup v2 `cpu.max` file, which has the form `$MAX $PERIOD`. `$MAX` may
+// be `max`, which indicates there is no limit.
+fn parse_cgroup_v2_cpu_max(contents: &str) -> Option<usize> {
+    let mut parts = contents.split_whitespace();
+    let quota = parts.next()?.parse::<i64>().ok()?;
+    let period = parts.next()?.parse::<i64>().ok()?;
+    cpus_from_quota(quota, period)
+}
+
+// Parse the contents of the cgroup v1 `cpu.cfs_quota_us` and `cpu.cfs_period_us` files. A quota of
+// `-1` indicates there is no limit.
+fn parse_cgroup_v1_cpu_quota(quota: &str, period: &str) -> Option<usize> {
+    cpus_from_quota(quota.trim().parse().ok()?, period.trim().parse().ok()?)
+}
+
+// Combine the number of logical CPUs with an optional cgroup CPU limit.
+fn combine_parallelism(logical_cpus: usize, cgroup_cpus: Option<usize>) -> usize {
+    cgroup_cpus
+        .map_or(logical_cpus, |cpus| cpus.min(logical_cpus))
+        .max(1)
+}
+
+// Read the CPU limit imposed by the cgroup this process belongs to, if any.
+fn cgroup_cpus() -> Option<usize> {
+    if let Ok(contents) = fs::read_to_string(CGROUP_V2_CPU_MAX_PATH) {
+        return parse_cgroup_v2_cpu_max(&contents);
+    }
+
+    let quota = fs::read_to_string(CGROUP_V1_CPU_QUOTA_PATH).ok()?;
+    let period = fs::read_to_string(CGROUP_V1_CPU_PERIOD_PATH).ok()?;
+    parse_cgroup_v1_cpu_quota(&quota, &period)
+}
+
+// Determine how many tasks or build jobs the host can effectively run in parallel. This takes
+// cgroup CPU limits into account, since the number of logical CPUs overstates the available
+// parallelism inside a CPU-limited container (e.g., in CI).
+pub fn effective_parallelism() -> usize {
+    let logical_cpus = available_parallelism().map_or(1, NonZeroUsize::get);
+    let cgroup_cpus = cgroup_cpus();
+    let parallelism = combine_parallelism(logical_cpus, cgroup_cpus);
+
+    debug!(
+        "Detected {} logical CPUs and a cgroup CPU limit of {}. Using a parallelism of {}.",
+        logical_cpus,
+        cgroup_cpus.map_or_else(|| "none".to_owned(), |cpus| cpus.to_string()),
+        parallelism,
+    );
+
+    parallelism
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::host::{combine_parallelism, parse_cgroup_v1_cpu_quota, parse_cgroup_v2_cpu_max};
+
+    #[test]
+    fn cgroup_v2_unlimited() {
+        assert_eq!(parse_cgroup_v2_cpu_max("max 100000\n"), None);
+    }
+
+    #[test]
+    fn cgroup_v2_whole() {
+        assert_eq!(parse_cgroup_v2_cpu_max("200000 100000\n"), Some(2));
+    }
+
+    #[test]
+    fn cgroup_v2_fractional() {
+        assert_eq!(parse_cgroup_v2_cpu_max("150000 100000\n"), Some(2));
+    }
+
+    #[test]
+    fn cgroup_v2_less_than_one() {
+        assert_eq!(parse_cgroup_v2_cpu_max("50000 100000\n"), Some(1));
+    }
+
+    #[test]
+    fn cgroup_v2_malformed() {
+        assert_eq!(parse_cgroup_v2_cpu_max("garbage"), None);
+    }
+
+    #[test]
+    fn cgroup_v1_unlimited() {
+        assert_eq!(parse_cgroup_v1_cpu_quota("-1\n", "100000\n"), None);
+    }
+
+    #[test]
+    fn cgroup_v1_limited() {
+        assert_eq!(parse_cgroup_v1_cpu_quota("400000\n", "100000\n"), Some(4));
+    }
+
+    #[test]
+    fn cgroup_v1_zero_period() {
+        assert_eq!(parse_cgroup_v1_cpu_quota("400000\n", "0\n"), None);
+    }
+
+    #[test]
+    fn combine_no_limit() {
+        assert_eq!(combine_parallelism(8, None), 8);
+    }
+
+    #[test]
+    fn combine_limit_below_cpus() {
+        assert_eq!(combine_parallelism(8, Some(2)), 2);
+    }
+
+    #[test]
+    fn combine_limit_above_cpus() {
+        assert_eq!(combine_parallelism(8, Some(16)), 8);
+    }
+}
@@ -0,0 +1,282 @@
+use {
+    crate::{failure::Failure, format::CodeStr, secrets, toastfile::Toastfile},
+    glob::Pattern,
+    std::{collections::HashSet, fmt::Write as _},
+};
+
+// Describe the tasks which have a description, for `--list`. If `filter` is given, only the tasks
+// whose names match that glob pattern are included. If `tree` is `true`, the dependencies of each
+// task are rendered as a tree rather than only the direct ones.
+pub fn render(toastfile: &Toastfile, filter: Option<&str>, tree: bool) -> Result<String, Failure> {
+    let pattern = filter
+        .map(|filter| {
+            Pattern::new(filter).map_err(|e| {
+                Failure::User(
+                    format!(
+                        "{} is not a valid pattern. The problem is that {}.",
+                        filter.code_str(),
+                        e.msg,
+                    ),
+                    None,
+                )
+            })
+        })
+        .transpose()?;
+
+    // Select the names of the tasks that have a description [tag:tasks_have_descriptions].
+    let mut task_names = toastfile
+        .tasks
+        .iter()
+        .filter(|(name, task)| {
+            task.description.is_some()
+                && pattern.as_ref().is_none_or(|pattern| pattern.matches(name))
+        })
+        .map(|(name, _)| name)
+        .collect::<Vec<_>>();
+
+    // Sort the names to avoid relying on the unpredictable order of the tasks in the map.
+    task_names.sort();
+
+    // Describe each task.
+    let mut output = String::new();
+    for task_name in task_names {
+        // Fetch the task data.
+        let task = &toastfile.tasks[task_name];
+
+        // Describe the task. The `unwrap` is safe due to [ref:tasks_have_descriptions].
+        let _ = writeln!(
+            output,
+            "* {} \u{2014} {}",
+            task_name.code_str(),
+            task.description.as_ref().unwrap(),
+        );
+
+        // Describe the dependencies of the task, along with why they're needed.
+        if tree {
+            render_tree(toastfile, task_name, "  ", &mut HashSet::new(), &mut output);
+        } else {
+            for dependency in &task.dependencies {
+                if let Some(reason) = &dependency.reason {
+                    let _ = writeln!(
+                        output,
+                        "  Depends on {}: {}",
+                        dependency.task.code_str(),
+                        reason,
+                    );
+                } else {
+                    let _ = writeln!(output, "  Depends on {}", dependency.task.code_str());
+                }
+            }
+        }
+
+        // Describe the environment variables that can be passed to the task, sorted by name. The
+        // defaults of secret variables are masked [ref:secrets_masked].
+        let mut variables = task.environment.iter().collect::<Vec<_>>();
+        variables.sort_unstable();
+        for (variable, optional_default) in variables {
+            if optional_default.is_some() && task.secret_variables.contains(variable) {
+                let _ = writeln!(output, "  {}: {}", variable.code_str(), secrets::MASK);
+            } else if let Some(default) = optional_default {
+                let _ = writeln!(output, "  {}: {}", variable.code_str(), default.code_str());
+            } else {
+                let _ = writeln!(output, "  {}: (no default provided)", variable.code_str());
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+// Render the dependencies of a task as a tree, one line per dependency. A dependency which was
+// already rendered (e.g., because two tasks share it) isn't expanded again. The recursion
+// terminates because the dependencies form a DAG [ref:tasks_dag].
+fn render_tree<'a>(
+    toastfile: &'a Toastfile,
+    task_name: &str,
+    prefix: &str,
+    shown: &mut HashSet<&'a str>,
+    output: &mut String,
+) {
+    let dependencies = &toastfile.tasks[task_name].dependencies; // [ref:tasks_valid]
+    for (i, dependency) in dependencies.iter().enumerate() {
+        let (branch, indent) = if i + 1 == dependencies.len() {
+            ("\u{2514}\u{2500} ", "   ")
+        } else {
+            ("\u{251c}\u{2500} ", "\u{2502}  ")
+        };
+
+        let expand = shown.insert(&dependency.task);
+        let _ = write!(output, "{prefix}{branch}{}", dependency.task.code_str());
+        if !expand {
+            output.push_str(" (shown above)");
+        }
+        if let Some(reason) = &dependency.reason {
+            let _ = write!(output, ": {reason}");
+        }
+        output.push('\n');
+
+        if expand {
+            render_tree(
+                toastfile,
+                &dependency.task,
+                &format!("{prefix}{indent}"),
+                shown,
+                output,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{list::render, toastfile::parse};
+
+    const TOASTFILE: &str = r"
+image: encom:os-12
+tasks:
+  install_deps:
+    description: Install the dependencies.
+  build:
+    description: Build the program.
+    dependencies:
+      - install_deps
+  test:
+    description: Run the tests.
+    dependencies:
+      - build
+      - task: install_deps
+        reason: The tests need the fixtures.
+  deploy_staging:
+    description: Deploy to staging.
+    dependencies:
+      - test
+      - build
+    environment:
+      REGION: us-east-1
+      TOKEN: null
+  deploy_production:
+    description: Deploy to production.
+    dependencies:
+      - deploy_staging
+  cleanup:
+    dependencies:
+      - build
+";
+
+    #[test]
+    fn render_flat() {
+        let toastfile = parse(TOASTFILE).unwrap();
+
+        assert_eq!(
+            render(&toastfile, None, false).unwrap(),
+            "\
+* `build` \u{2014} Build the program.
+  Depends on `install_deps`
+* `deploy_production` \u{2014} Deploy to production.
+  Depends on `deploy_staging`
+* `deploy_staging` \u{2014} Deploy to staging.
+  Depends on `test`
+  Depends on `build`
+  `REGION`: `us-east-1`
+  `TOKEN`: (no default provided)
+* `install_deps` \u{2014} Install the dependencies.
+* `test` \u{2014} Run the tests.
+  Depends on `build`
+  Depends on `install_deps`: The tests need the fixtures.
+",
+        );
+    }
+
+    #[test]
+    fn render_filtered() {
+        let toastfile = parse(TOASTFILE).unwrap();
+
+        assert_eq!(
+            render(&toastfile, Some("deploy*"), false).unwrap(),
+            "\
+* `deploy_production` \u{2014} Deploy to production.
+  Depends on `deploy_staging`
+* `deploy_staging` \u{2014} Deploy to staging.
+  Depends on `test`
+  Depends on `build`
+  `REGION`: `us-east-1`
+  `TOKEN`: (no default provided)
+",
+        );
+    }
+
+    #[test]
+    fn render_secret() {
+        let input = r"
+image: encom:os-12
+tasks:
+  deploy:
+    description: Deploy the program.
+    environment:
+      API_KEY: abc123
+      REGION: us-east-1
+      TOKEN: null
+    secret_variables:
+      - API_KEY
+      - TOKEN
+";
+        let toastfile = parse(input).unwrap();
+
+        assert_eq!(
+            render(&toastfile, None, false).unwrap(),
+            "\
+* `deploy` \u{2014} Deploy the program.
+  `API_KEY`: ********
+  `REGION`: `us-east-1`
+  `TOKEN`: (no default provided)
+",
+        );
+    }
+
+    #[test]
+    fn render_filtered_no_match() {
+        let toastfile = parse(TOASTFILE).unwrap();
+
+        assert_eq!(render(&toastfile, Some("lint*"), false).unwrap(), "");
+    }
+
+    #[test]
+    fn render_invalid_filter() {
+        let toastfile = parse(TOASTFILE).unwrap();
+
+        assert!(render(&toastfile, Some("deploy["), false).is_err());
+    }
+
+    #[test]
+    fn render_tree_shared_dependencies() {
+        let toastfile = parse(TOASTFILE).unwrap();
+
+        assert_eq!(
+            render(&toastfile, Some("deploy_production"), true).unwrap(),
+            "\
+* `deploy_production` \u{2014} Deploy to production.
+  \u{2514}\u{2500} `deploy_staging`
+     \u{251c}\u{2500} `test`
+     \u{2502}  \u{251c}\u{2500} `build`
+     \u{2502}  \u{2502}  \u{2514}\u{2500} `install_deps`
+     \u{2502}  \u{2514}\u{2500} `install_deps` (shown above): The tests need the fixtures.
+     \u{2514}\u{2500} `build` (shown above)
+",
+        );
+    }
+
+    #[test]
+    fn render_tree_reasons() {
+        let toastfile = parse(TOASTFILE).unwrap();
+
+        assert_eq!(
+            render(&toastfile, Some("test"), true).unwrap(),
+            "\
+* `test` \u{2014} Run the tests.
+  \u{251c}\u{2500} `build`
+  \u{2502}  \u{2514}\u{2500} `install_deps`
+  \u{2514}\u{2500} `install_deps` (shown above): The tests need the fixtures.
+",
+        );
+    }
+}
@@ -1,13 +1,37 @@
 mod cache;
+mod clean;
+mod cleanup;
 mod config;
+mod containerfile;
+mod determinism;
 mod docker;
+mod dotenv;
 mod failure;
 mod format;
+mod graph;
+mod host;
+mod list;
+mod metrics;
+mod outputs;
+mod paths;
+mod pause;
+mod progress;
+mod prune;
+mod registry;
 mod runner;
 mod schedule;
+mod secrets;
 mod spinner;
+mod ssh_cache;
+mod stats;
+mod tail;
 mod tar;
+mod time_limit;
 mod toastfile;
+mod trace;
+mod units;
+mod update;
+mod validation;
 
 use {
     crate::{failure::Failure, format::CodeStr},
@@ -16,13 +40,13 @@ use {
     env_logger::{fmt::Color, Builder},
     log::{Level, LevelFilter},
     std::{
-        collections::{HashMap, HashSet},
+        collections::{BTreeMap, HashMap, HashSet},
         convert::AsRef,
         default::Default,
         env,
         env::current_dir,
         fs,
-        io::{stdout, Write},
+        io::{self, stdout, Write},
         mem::drop,
         path::Path,
         path::PathBuf,
@@ -32,9 +56,10 @@ use {
             atomic::{AtomicBool, Ordering},
             Arc, Mutex,
         },
+        time::{Duration, Instant, SystemTime},
     },
     toastfile::{default_task_mount_readonly, location, user, DEFAULT_USER},
-    typed_path::UnixPath,
+    typed_path::{TryAsRef, UnixPath, UnixPathBuf},
 };
 
 #[macro_use]
@@ -53,22 +78,97 @@ const VERSION: &str = env!("CARGO_PKG_VERSION");
 const TOASTFILE_DEFAULT_NAME: &str = "toast.yml";
 const CONFIG_FILE_XDG_PATH: &str = "toast/toast.yml";
 const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Info;
+const JOBS_AUTO: &str = "auto";
+
+// The environment variable which tells tasks how many jobs they can run in parallel
+pub const JOBS_ENV_VAR: &str = "TOAST_JOBS";
 
 // Command-line argument and option names
 const TOASTFILE_OPTION: &str = "file";
+const SEARCH_ROOT_OPTION: &str = "search-root";
 const CONFIG_FILE_OPTION: &str = "config-file";
 const READ_LOCAL_CACHE_OPTION: &str = "read-local-cache";
 const WRITE_LOCAL_CACHE_OPTION: &str = "write-local-cache";
 const READ_REMOTE_CACHE_OPTION: &str = "read-remote-cache";
 const WRITE_REMOTE_CACHE_OPTION: &str = "write-remote-cache";
+const WRITE_SSH_CACHE_OPTION: &str = "write-ssh-cache";
 const DOCKER_CLI_OPTION: &str = "docker-cli";
 const DOCKER_REPO_OPTION: &str = "docker-repo";
+const WAIT_FOR_DOCKER_OPTION: &str = "wait-for-docker";
 const LIST_OPTION: &str = "list";
+const LIST_TASK_OPTION: &str = "task";
+const LIST_TREE_OPTION: &str = "tree";
+const GRAPH_OPTION: &str = "graph";
+const CONTAINERFILE_OPTION: &str = "containerfile";
+const CONTAINERFILE_OUT_OPTION: &str = "out";
 const SHELL_OPTION: &str = "shell";
+const SHELL_IMAGE_OPTION: &str = "shell-image";
 const TASKS_OPTION: &str = "tasks";
 const FORCE_OPTION: &str = "force";
 const FORCE_ALL_OPTION: &str = "force-all";
+const NO_DEPS_OPTION: &str = "no-deps";
+const TIME_LIMIT_OPTION: &str = "time-limit";
+const ENV_OPTION: &str = "env";
 const OUTPUT_DIR_OPTION: &str = "output-dir";
+const MAX_LOG_LINES_OPTION: &str = "max-log-lines";
+const PREFIX_OUTPUT_OPTION: &str = "prefix-output";
+const STATS_OPTION: &str = "stats";
+const STATS_INTERVAL_OPTION: &str = "stats-interval";
+const DIFF_ON_FAILURE_OPTION: &str = "diff-on-failure";
+const DIFF_LIMIT_OPTION: &str = "diff-limit";
+const JOBS_OPTION: &str = "jobs";
+const NO_SPINNER_OPTION: &str = "no-spinner";
+const LIST_OUTPUTS_OPTION: &str = "list-outputs";
+const HASH_TASK_OPTION: &str = "hash-task";
+const DRY_RUN_OPTION: &str = "dry-run";
+const VERSION_CHECK_OPTION: &str = "version-check";
+const ASSERT_CACHED_OPTION: &str = "assert-cached";
+const ONLY_CACHED_OPTION: &str = "only-cached";
+const EXTRACT_OPTION: &str = "extract";
+const CHECK_OUTPUTS_OPTION: &str = "check-outputs";
+const INTERLEAVE_PULL_OPTION: &str = "interleave-pull";
+const NO_CLEANUP_OPTION: &str = "no-cleanup";
+const TRACE_FILE_OPTION: &str = "trace-file";
+const PROGRESS_FD_OPTION: &str = "progress-fd";
+const PROGRESS_FILE_OPTION: &str = "progress-file";
+const STRICT_INPUT_SIZE_OPTION: &str = "strict-input-size";
+const REQUIRE_PINNED_IMAGE_OPTION: &str = "require-pinned-image";
+const STRICT_MOUNT_PATHS_OPTION: &str = "strict-mount-paths";
+const ALLOW_MISSING_INPUTS_OPTION: &str = "allow-missing-inputs";
+const VERIFY_DETERMINISM_OPTION: &str = "verify-determinism";
+const INPUT_OPTION: &str = "input";
+const MOUNT_OPTION: &str = "mount";
+const COMMAND_OPTION: &str = "command";
+const RUNS_OPTION: &str = "runs";
+const PRUNE_OLDER_THAN_OPTION: &str = "prune-older-than";
+const PRUNE_KEEP_CURRENT_OPTION: &str = "prune-keep-current";
+const PRUNE_VOLUMES_OPTION: &str = "prune-volumes";
+const ALL_OPTION: &str = "all";
+
+// The tasks which request the cache report instead, as in `toast cache report`
+const CACHE_REPORT_COMMAND: [&str; 2] = ["cache", "report"];
+
+// The task which requests removing the images in the cache instead, as in `toast prune`
+const PRUNE_COMMAND: [&str; 1] = ["prune"];
+
+// The tasks which request removing the data Toast saved between runs instead, as in
+// `toast state clear`
+const STATE_CLEAR_COMMAND: [&str; 2] = ["state", "clear"];
+
+// The environment variable which can be set to disable spinners, like `--no-spinner`
+const NO_SPINNER_ENV_VAR: &str = "TOAST_NO_SPINNER";
+
+// CI systems set this environment variable, and the update check is skipped when it's set.
+const CI_ENV_VAR: &str = "CI";
+
+// The default interval between resource usage samples, in seconds
+const DEFAULT_STATS_INTERVAL: &str = "2";
+
+// The default number of changed paths to show for `--diff-on-failure`
+const DEFAULT_DIFF_LIMIT: &str = "200";
+
+// The default number of runs to include in the cache report
+const DEFAULT_CACHE_REPORT_RUNS: &str = "20";
 
 // Set up the logger.
 fn set_up_logging() {
@@ -98,11 +198,12 @@ fn set_up_logging() {
                 }
             }
 
+            // The values of secret variables are masked [ref:secrets_masked].
             writeln!(
                 buf,
                 "{} {}",
                 style.value(format!("[{}]", record.level())),
-                record.args(),
+                secrets::mask(&record.args().to_string()),
             )
         })
         .init();
@@ -110,31 +211,54 @@ fn set_up_logging() {
 
 // Set up the signal handlers.
 fn set_up_signal_handlers(
-    docker_cli: String,
+    cleanup: Arc<cleanup::Cleanup>,
     interrupted: Arc<AtomicBool>,
-    active_containers: Arc<Mutex<HashSet<String>>>,
+    active_containers: Arc<Mutex<HashMap<String, String>>>,
 ) -> Result<(), Failure> {
     // If Toast is in the foreground process group for some TTY, the process will receive a SIGINT
     // when the user types CTRL+C at the terminal. The default behavior is to crash when this signal
     // is received. However, we would rather clean up resources before terminating, so we trap the
     // signal here. This code also traps SIGHUP and SIGTERM, since we compile the `ctrlc` crate with
     // the `termination` feature [ref:ctrlc_term].
+    let pause_interrupted = interrupted.clone();
+    let pause_active_containers = active_containers.clone();
     ctrlc::set_handler(move || {
         // Let the rest of the program know the user wants to quit.
-        if interrupted.swap(true, Ordering::SeqCst) {
-            // Stop any active containers. The `unwrap` will only fail if a panic already occurred.
-            for container in &*active_containers.lock().unwrap() {
-                if let Err(e) = docker::stop_container(&docker_cli, container, &interrupted) {
-                    error!("{}", e);
-                }
-            }
+        let already_interrupted = interrupted.swap(true, Ordering::SeqCst);
+
+        // If the schedule is paused, unpause any active containers so they can be cleaned up.
+        pause::handle(pause::Request::Resume, &active_containers, &interrupted);
+
+        if already_interrupted {
+            // Stop any active containers.
+            stop_active_containers(&cleanup, &active_containers, &interrupted);
 
             // We may have been in the middle of printing a line of output. Here we print a newline
             // to prepare for further printing.
             drop(stdout().write(b"\n"));
         }
     })
-    .map_err(failure::system("Error installing signal handler."))
+    .map_err(failure::system("Error installing signal handler."))?;
+
+    // Trap the signals for pausing and resuming the schedule.
+    pause::set_up_signal_handler(pause_interrupted, pause_active_containers)
+}
+
+// Stop the active containers, so the tasks running in them end now.
+fn stop_active_containers(
+    cleanup: &Arc<cleanup::Cleanup>,
+    active_containers: &Arc<Mutex<HashMap<String, String>>>,
+    interrupted: &Arc<AtomicBool>,
+) {
+    // The `unwrap` will only fail if a panic already occurred.
+    for (container, docker_cli) in &*active_containers.lock().unwrap() {
+        if let Err(e) = cleanup
+            .for_docker_cli(docker_cli)
+            .stop_container(container, interrupted)
+        {
+            error!("{}", e);
+        }
+    }
 }
 
 // Convert a string (from a command-line argument) into a Boolean.
@@ -156,16 +280,66 @@ pub struct Settings {
     toastfile_path: PathBuf,
     docker_cli: String,
     docker_repo: String,
+    wait_for_docker: Option<Duration>,
+    time_limit: Option<time_limit::TimeLimit>,
     read_local_cache: bool,
     write_local_cache: bool,
     read_remote_cache: bool,
     write_remote_cache: bool,
+    confirm_public_push: bool,
+    ssh_cache: Option<ssh_cache::SshCache>,
+    read_ssh_cache: bool,
+    write_ssh_cache: bool,
     list: bool,
+    list_filter: Option<String>,
+    list_tree: bool,
+    graph: bool,
+    containerfile: bool,
+    containerfile_out: Option<PathBuf>,
+    list_outputs: Vec<String>,
+    hash_task: Option<String>,
+    dry_run: bool,
+    assert_cached: bool,
+    only_cached: bool,
+    extract: bool,
+    verify_determinism: Option<String>,
+    cache_report: Option<usize>,
+    prune: bool,
+    prune_older_than: Option<Duration>,
+    prune_keep_current: bool,
+    prune_volumes: bool,
+    state_clear: Option<bool>,
     spawn_shell: bool,
+    shell_image: Option<String>,
     tasks: Option<Vec<String>>,
+    ad_hoc_task: Option<toastfile::Task>,
     forced_tasks: Vec<String>,
     force_all: bool,
+    caching_disabled: bool, // [ref:force_all_caching]
+    no_deps: bool,
+    environment_overrides: HashMap<String, String>,
     output_dir: PathBuf,
+    extra_pull_arguments: Vec<String>,
+    extra_push_arguments: Vec<String>,
+    max_log_lines: Option<usize>,
+    prefix_output: bool,
+    version_check: bool,
+    jobs: usize,
+    stats_interval: Option<Duration>,
+    diff_limit: Option<usize>,
+    check_outputs: bool,
+    interleave_pull: bool,
+    cleanup: Arc<cleanup::Cleanup>,
+    trace_file: Option<PathBuf>,
+    trace: trace::Trace,
+    architectures: Mutex<HashMap<String, String>>,
+    input_size_limits: tar::SizeLimits,
+    strict_mount_paths: bool,
+    allow_missing_inputs: bool,
+    require_pinned_image: bool,
+    resolve_image_digest: bool,
+    summary: bool,
+    registry_login: Option<registry::Login>,
 }
 
 // Parse the command-line arguments.
@@ -186,6 +360,13 @@ fn settings() -> Result<Settings, Failure> {
                 .long(TOASTFILE_OPTION)
                 .help("Sets the path to the toastfile"),
         )
+        .arg(
+            Arg::with_name(SEARCH_ROOT_OPTION)
+                .value_name("PATH")
+                .long(SEARCH_ROOT_OPTION)
+                .conflicts_with(TOASTFILE_OPTION)
+                .help("Sets the highest directory to search for the toastfile"),
+        )
         .arg(
             Arg::with_name(CONFIG_FILE_OPTION)
                 .value_name("PATH")
@@ -224,6 +405,12 @@ fn settings() -> Result<Settings, Failure> {
                 .long(WRITE_REMOTE_CACHE_OPTION)
                 .help("Sets whether remote cache writing is enabled"),
         )
+        .arg(
+            Arg::with_name(WRITE_SSH_CACHE_OPTION)
+                .value_name("BOOL")
+                .long(WRITE_SSH_CACHE_OPTION)
+                .help("Sets whether writing to the SSH cache host is enabled"),
+        )
         .arg(
             Arg::with_name(DOCKER_REPO_OPTION)
                 .value_name("REPO")
@@ -237,18 +424,187 @@ fn settings() -> Result<Settings, Failure> {
                 .long(DOCKER_CLI_OPTION)
                 .help("Sets the Docker CLI binary"),
         )
+        .arg(
+            Arg::with_name(WAIT_FOR_DOCKER_OPTION)
+                .value_name("SECONDS")
+                .long(WAIT_FOR_DOCKER_OPTION)
+                .help("Waits up to this long for the Docker daemon to start responding"),
+        )
+        .arg(
+            Arg::with_name(TIME_LIMIT_OPTION)
+                .value_name("DURATION")
+                .long(TIME_LIMIT_OPTION)
+                .help("Stops the run cleanly once it has taken this long (e.g., `55m`)"),
+        )
         .arg(
             Arg::with_name(LIST_OPTION)
                 .short("l")
                 .long(LIST_OPTION)
                 .help("Lists the tasks that have a description"),
         )
+        .arg(
+            Arg::with_name(LIST_TASK_OPTION)
+                .value_name("GLOB")
+                .long(LIST_TASK_OPTION)
+                .requires(LIST_OPTION)
+                .help("Only lists the tasks whose names match a glob pattern (e.g., `deploy*`)"),
+        )
+        .arg(
+            Arg::with_name(LIST_TREE_OPTION)
+                .long(LIST_TREE_OPTION)
+                .requires(LIST_OPTION)
+                .help("Lists the transitive dependencies of each task as a tree"),
+        )
+        .arg(
+            Arg::with_name(GRAPH_OPTION)
+                .long(GRAPH_OPTION)
+                .conflicts_with_all(&[LIST_OPTION, SHELL_OPTION])
+                .help(
+                    "Prints the dependency graph of the tasks in the DOT format, restricted to \
+                     the given tasks and their dependencies if any are provided",
+                ),
+        )
+        .arg(
+            Arg::with_name(CONTAINERFILE_OPTION)
+                .long(CONTAINERFILE_OPTION)
+                .conflicts_with_all(&[LIST_OPTION, SHELL_OPTION, GRAPH_OPTION])
+                .help("Prints a best-effort Dockerfile equivalent to running the tasks"),
+        )
+        .arg(
+            Arg::with_name(CONTAINERFILE_OUT_OPTION)
+                .value_name("PATH")
+                .long(CONTAINERFILE_OUT_OPTION)
+                .requires(CONTAINERFILE_OPTION)
+                .help("Writes the Dockerfile for `--containerfile` to a file instead"),
+        )
+        .arg(
+            Arg::with_name(HASH_TASK_OPTION)
+                .value_name("TASK")
+                .long(HASH_TASK_OPTION)
+                .help("Prints a hash of the definition of a task, for detecting changes to it"),
+        )
+        .arg(
+            Arg::with_name(DRY_RUN_OPTION)
+                .long(DRY_RUN_OPTION)
+                .help("Prints which tasks would run and which are cached, without running anything")
+                .conflicts_with(SHELL_OPTION),
+        )
+        .arg(
+            Arg::with_name(ASSERT_CACHED_OPTION)
+                .long(ASSERT_CACHED_OPTION)
+                .help("Fails unless every task is cached, without running anything")
+                .conflicts_with_all(&[SHELL_OPTION, DRY_RUN_OPTION]),
+        )
+        .arg(
+            Arg::with_name(ONLY_CACHED_OPTION)
+                .long(ONLY_CACHED_OPTION)
+                .help("Fails unless the final task is cached, without running anything")
+                .conflicts_with_all(&[SHELL_OPTION, DRY_RUN_OPTION, ASSERT_CACHED_OPTION]),
+        )
+        .arg(
+            Arg::with_name(EXTRACT_OPTION)
+                .long(EXTRACT_OPTION)
+                .help("Makes `--only-cached` copy the output files out of the cached images")
+                .requires(ONLY_CACHED_OPTION),
+        )
+        .arg(
+            Arg::with_name(LIST_OUTPUTS_OPTION)
+                .value_name("TASK")
+                .long(LIST_OUTPUTS_OPTION)
+                .help("Lists where the output files of a task will be written")
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name(CHECK_OUTPUTS_OPTION)
+                .long(CHECK_OUTPUTS_OPTION)
+                .help(
+                    "Verifies the contents of previously extracted output files before deciding \
+                     everything is up to date",
+                ),
+        )
+        .arg(
+            Arg::with_name(INTERLEAVE_PULL_OPTION)
+                .long(INTERLEAVE_PULL_OPTION)
+                .help("Pulls the base image in the background while reading input files"),
+        )
+        .arg(
+            Arg::with_name(NO_CLEANUP_OPTION)
+                .long(NO_CLEANUP_OPTION)
+                .help("Leaves all containers and images in place for debugging"),
+        )
+        .arg(
+            Arg::with_name(TRACE_FILE_OPTION)
+                .value_name("PATH")
+                .long(TRACE_FILE_OPTION)
+                .help("Writes a timeline of the run in the Chrome Trace Event Format"),
+        )
+        .arg(
+            Arg::with_name(PROGRESS_FD_OPTION)
+                .value_name("FD")
+                .long(PROGRESS_FD_OPTION)
+                .help("Writes progress events as JSON lines to an open file descriptor"),
+        )
+        .arg(
+            Arg::with_name(PROGRESS_FILE_OPTION)
+                .value_name("PATH")
+                .long(PROGRESS_FILE_OPTION)
+                .conflicts_with(PROGRESS_FD_OPTION)
+                .help("Writes progress events as JSON lines to a file"),
+        )
+        .arg(
+            Arg::with_name(STRICT_INPUT_SIZE_OPTION)
+                .long(STRICT_INPUT_SIZE_OPTION)
+                .help("Fails instead of warning when the input files of a task are suspiciously large"),
+        )
+        .arg(
+            Arg::with_name(STRICT_MOUNT_PATHS_OPTION)
+                .long(STRICT_MOUNT_PATHS_OPTION)
+                .help(
+                    "Fails instead of warning when mount paths hide files copied or created in \
+                     the container",
+                ),
+        )
+        .arg(
+            Arg::with_name(ALLOW_MISSING_INPUTS_OPTION)
+                .long(ALLOW_MISSING_INPUTS_OPTION)
+                .help(
+                    "Skips checking that the input paths of the tasks exist before running \
+                     anything",
+                ),
+        )
+        .arg(
+            Arg::with_name(REQUIRE_PINNED_IMAGE_OPTION)
+                .long(REQUIRE_PINNED_IMAGE_OPTION)
+                .help("Fails unless the images in the toastfile are pinned to a digest"),
+        )
+        .arg(
+            Arg::with_name(VERIFY_DETERMINISM_OPTION)
+                .value_name("TASK")
+                .long(VERIFY_DETERMINISM_OPTION)
+                .conflicts_with(TASKS_OPTION)
+                .help(
+                    "Runs a task twice without the cache and checks that both runs make the same \
+                     changes to the filesystem",
+                ),
+        )
+        .arg(
+            Arg::with_name(NO_SPINNER_OPTION)
+                .long(NO_SPINNER_OPTION)
+                .help("Logs progress messages instead of rendering spinners"),
+        )
         .arg(
             Arg::with_name(SHELL_OPTION)
                 .short("s")
                 .long(SHELL_OPTION)
                 .help("Drops you into a containerized shell after the tasks are finished"),
         )
+        .arg(
+            Arg::with_name(SHELL_IMAGE_OPTION)
+                .value_name("IMAGE")
+                .long(SHELL_IMAGE_OPTION)
+                .help("Uses this image for the shell instead of the one from the last task")
+                .requires(SHELL_OPTION),
+        )
         .arg(
             Arg::with_name(FORCE_OPTION)
                 .value_name("TASK")
@@ -256,41 +612,258 @@ fn settings() -> Result<Settings, Failure> {
                 .help("Runs a task unconditionally, even if it\u{2019}s cached")
                 .multiple(true),
         )
+        .arg(
+            Arg::with_name(ENV_OPTION)
+                .value_name("KEY=VALUE")
+                .long(ENV_OPTION)
+                .help("Sets an environment variable for the tasks, overriding its usual value")
+                .multiple(true)
+                .number_of_values(1),
+        )
         .arg(
             Arg::with_name(FORCE_ALL_OPTION)
                 .long(FORCE_ALL_OPTION)
                 .help("Pulls the base image and runs all tasks unconditionally"),
         )
+        .arg(
+            Arg::with_name(NO_DEPS_OPTION)
+                .long(NO_DEPS_OPTION)
+                .help("Skips the dependencies of the tasks, starting from their cached images")
+                .conflicts_with_all(&[
+                    FORCE_ALL_OPTION,
+                    DRY_RUN_OPTION,
+                    ASSERT_CACHED_OPTION,
+                    ONLY_CACHED_OPTION,
+                ]),
+        )
+        .arg(
+            Arg::with_name(MAX_LOG_LINES_OPTION)
+                .value_name("LINES")
+                .long(MAX_LOG_LINES_OPTION)
+                .help("Only shows the last few lines of output from each task, if it fails"),
+        )
+        .arg(
+            Arg::with_name(PREFIX_OUTPUT_OPTION)
+                .long(PREFIX_OUTPUT_OPTION)
+                .help("Prefixes each line of output from a task with the name of the task"),
+        )
+        .arg(
+            Arg::with_name(VERSION_CHECK_OPTION)
+                .long(VERSION_CHECK_OPTION)
+                .help("Checks (at most once a day) whether a newer version of Toast is available"),
+        )
+        .arg(
+            Arg::with_name(STATS_OPTION)
+                .long(STATS_OPTION)
+                .help("Reports the peak memory and CPU time used by each task"),
+        )
+        .arg(
+            Arg::with_name(STATS_INTERVAL_OPTION)
+                .value_name("SECONDS")
+                .long(STATS_INTERVAL_OPTION)
+                .requires(STATS_OPTION)
+                .help("Sets how often resource usage is sampled for `--stats` (default: 2)"),
+        )
+        .arg(
+            Arg::with_name(DIFF_ON_FAILURE_OPTION)
+                .long(DIFF_ON_FAILURE_OPTION)
+                .help("Shows the paths a task changed in the container if it fails"),
+        )
+        .arg(
+            Arg::with_name(DIFF_LIMIT_OPTION)
+                .value_name("PATHS")
+                .long(DIFF_LIMIT_OPTION)
+                .requires(DIFF_ON_FAILURE_OPTION)
+                .help(
+                    "Sets how many changed paths are shown for `--diff-on-failure` (default: 200)",
+                ),
+        )
+        .arg(
+            Arg::with_name(RUNS_OPTION)
+                .value_name("RUNS")
+                .long(RUNS_OPTION)
+                .help("Sets how many recent runs `toast cache report` covers (default: 20)"),
+        )
+        .arg(
+            Arg::with_name(PRUNE_OLDER_THAN_OPTION)
+                .value_name("DURATION")
+                .long(PRUNE_OLDER_THAN_OPTION)
+                .help("Makes `toast prune` only remove images older than this (e.g., `7d`)"),
+        )
+        .arg(
+            Arg::with_name(PRUNE_KEEP_CURRENT_OPTION)
+                .long(PRUNE_KEEP_CURRENT_OPTION)
+                .help("Makes `toast prune` keep the images the default tasks would use"),
+        )
+        .arg(
+            Arg::with_name(PRUNE_VOLUMES_OPTION)
+                .long(PRUNE_VOLUMES_OPTION)
+                .help("Makes `toast prune` also remove the volumes created for tasks"),
+        )
+        .arg(
+            Arg::with_name(ALL_OPTION)
+                .long(ALL_OPTION)
+                .help("Makes `toast state clear` remove the saved data for every toastfile"),
+        )
+        .arg(
+            Arg::with_name(JOBS_OPTION)
+                .value_name("JOBS")
+                .short("j")
+                .long(JOBS_OPTION)
+                .help(
+                    "Sets the parallelism exposed to tasks via `TOAST_JOBS` (a number or `auto`)",
+                ),
+        )
+        .arg(
+            Arg::with_name(INPUT_OPTION)
+                .value_name("PATH")
+                .long(INPUT_OPTION)
+                .help("Copies a path into the container for `toast run -- COMMAND`")
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name(MOUNT_OPTION)
+                .value_name("PATH")
+                .long(MOUNT_OPTION)
+                .help("Mounts a path into the container for `toast run -- COMMAND`")
+                .multiple(true)
+                .number_of_values(1),
+        )
         .arg(
             Arg::with_name(TASKS_OPTION)
                 .value_name("TASKS")
                 .help("Sets the tasks to run")
                 .multiple(true),
         )
+        .arg(
+            Arg::with_name(COMMAND_OPTION)
+                .value_name("COMMAND")
+                .help("Sets a one-off command to run, as in `toast run -- COMMAND`")
+                .multiple(true)
+                .last(true),
+        )
         .get_matches();
 
-    // Find the toastfile.
+    // Read how many runs to report on, if the user wants the cache report. It's requested as
+    // `toast cache report`, so `cache` and `report` show up as the tasks.
+    let cache_report = if matches
+        .values_of(TASKS_OPTION)
+        .is_some_and(|tasks| tasks.eq(CACHE_REPORT_COMMAND))
+    {
+        let runs = matches
+            .value_of(RUNS_OPTION)
+            .unwrap_or(DEFAULT_CACHE_REPORT_RUNS);
+        Some(runs.parse::<usize>().map_err(failure::user(format!(
+            "{} is not a valid number of runs.",
+            runs.code_str(),
+        )))?)
+    } else if matches.is_present(RUNS_OPTION) {
+        return Err(Failure::User(
+            format!(
+                "{} can only be used with {}.",
+                format!("--{RUNS_OPTION}").code_str(),
+                "toast cache report".code_str(),
+            ),
+            None,
+        ));
+    } else {
+        None
+    };
+
+    // Read the settings for removing the images in the cache, if the user asked for that with
+    // `toast prune`.
+    let prune = matches
+        .values_of(TASKS_OPTION)
+        .is_some_and(|tasks| tasks.eq(PRUNE_COMMAND));
+    let prune_older_than = if prune {
+        matches
+            .value_of(PRUNE_OLDER_THAN_OPTION)
+            .map(|value| {
+                units::parse_duration(
+                    value,
+                    &format!(
+                        "The value of {}",
+                        format!("--{PRUNE_OLDER_THAN_OPTION}").code_str()
+                    ),
+                )
+            })
+            .transpose()?
+    } else {
+        for option in [
+            PRUNE_OLDER_THAN_OPTION,
+            PRUNE_KEEP_CURRENT_OPTION,
+            PRUNE_VOLUMES_OPTION,
+        ] {
+            if matches.is_present(option) {
+                return Err(Failure::User(
+                    format!(
+                        "{} can only be used with {}.",
+                        format!("--{option}").code_str(),
+                        "toast prune".code_str(),
+                    ),
+                    None,
+                ));
+            }
+        }
+        None
+    };
+    let prune_keep_current = matches.is_present(PRUNE_KEEP_CURRENT_OPTION);
+    let prune_volumes = matches.is_present(PRUNE_VOLUMES_OPTION);
+
+    // Read whether to remove the saved data for every toastfile, if the user asked to remove saved
+    // data with `toast state clear`. The value is whether `--all` was given.
+    let state_clear = if matches
+        .values_of(TASKS_OPTION)
+        .is_some_and(|tasks| tasks.eq(STATE_CLEAR_COMMAND))
+    {
+        Some(matches.is_present(ALL_OPTION))
+    } else if matches.is_present(ALL_OPTION) {
+        return Err(Failure::User(
+            format!(
+                "{} can only be used with {}.",
+                format!("--{ALL_OPTION}").code_str(),
+                "toast state clear".code_str(),
+            ),
+            None,
+        ));
+    } else {
+        None
+    };
+
+    // Find the toastfile. If it isn't in the working directory, tell the user where it was found so
+    // they aren't surprised by which tasks run. The cache report and `toast state clear --all`
+    // don't need a toastfile.
     let toastfile_path = matches.value_of(TOASTFILE_OPTION).map_or_else(
         || {
-            let mut candidate_dir =
+            if cache_report.is_some() || state_clear == Some(true) {
+                return Ok(PathBuf::from(TOASTFILE_DEFAULT_NAME));
+            }
+
+            let working_dir =
                 current_dir().map_err(failure::system("Unable to determine working directory."))?;
-            loop {
-                let candidate_path = candidate_dir.join(TOASTFILE_DEFAULT_NAME);
-                if let Ok(metadata) = fs::metadata(&candidate_path) {
-                    if metadata.file_type().is_file() {
-                        return Ok(candidate_path);
-                    }
-                }
-                if !candidate_dir.pop() {
-                    return Err(Failure::User(
-                        format!(
-                            "Unable to locate file {}.",
-                            TOASTFILE_DEFAULT_NAME.code_str(),
-                        ),
-                        None,
-                    ));
-                }
+            let search_root = matches
+                .value_of(SEARCH_ROOT_OPTION)
+                .map(|path| {
+                    fs::canonicalize(path).map_err(failure::user(format!(
+                        "Unable to find the search root {}.",
+                        path.code_str(),
+                    )))
+                })
+                .transpose()?;
+            let toastfile_path =
+                toastfile::find(&working_dir, search_root.as_deref(), TOASTFILE_DEFAULT_NAME)?;
+            if toastfile_path.parent() != Some(working_dir.as_path()) {
+                info!(
+                    "Using the toastfile in {}.",
+                    toastfile_path
+                        .parent()
+                        .unwrap_or(&working_dir)
+                        .to_string_lossy()
+                        .code_str(),
+                );
             }
+            Ok(toastfile_path)
         },
         |x| Ok(Path::new(x).to_owned()),
     )?;
@@ -341,21 +914,45 @@ fn settings() -> Result<Settings, Failure> {
             .code_str(),
     )))?;
 
+    // Read the force all switch. [tag:force_all_caching] It means nothing is read from the caches.
+    // Nothing is written to them either, unless that's explicitly enabled on the command line
+    // (e.g., to refresh the caches with a fresh build). If nothing is written, caching is disabled
+    // altogether, just as if every task had been given by `--force`.
+    let force_all = matches.is_present(FORCE_ALL_OPTION);
+
     // Read the local caching switches.
     let read_local_cache = matches
         .value_of(READ_LOCAL_CACHE_OPTION)
-        .map_or(Ok(config.read_local_cache), parse_bool)?;
+        .map_or(Ok(config.read_local_cache), parse_bool)?
+        && !force_all;
     let write_local_cache = matches
         .value_of(WRITE_LOCAL_CACHE_OPTION)
-        .map_or(Ok(config.write_local_cache), parse_bool)?;
+        .map_or(Ok(config.write_local_cache && !force_all), parse_bool)?;
 
     // Read the remote caching switches.
     let read_remote_cache = matches
         .value_of(READ_REMOTE_CACHE_OPTION)
-        .map_or(Ok(config.read_remote_cache), parse_bool)?;
+        .map_or(Ok(config.read_remote_cache), parse_bool)?
+        && !force_all;
     let write_remote_cache = matches
         .value_of(WRITE_REMOTE_CACHE_OPTION)
-        .map_or(Ok(config.write_remote_cache), parse_bool)?;
+        .map_or(Ok(config.write_remote_cache && !force_all), parse_bool)?;
+
+    // Read the SSH cache settings.
+    let read_ssh_cache = !force_all;
+    let write_ssh_cache = matches
+        .value_of(WRITE_SSH_CACHE_OPTION)
+        .map_or(Ok(config.write_ssh_cache && !force_all), parse_bool)?;
+    let ssh_cache = config
+        .ssh_cache_host
+        .clone()
+        .map(|host| ssh_cache::SshCache {
+            ssh_cli: config.ssh_cli.clone(),
+            host,
+            timeout: Duration::from_secs(config.ssh_cache_timeout),
+        });
+    let caching_disabled = force_all
+        && !(write_local_cache || write_remote_cache || (write_ssh_cache && ssh_cache.is_some()));
 
     // Read the Docker repo.
     let docker_repo = matches
@@ -369,77 +966,590 @@ fn settings() -> Result<Settings, Failure> {
         .unwrap_or(&config.docker_cli)
         .to_owned();
 
+    // Read how long to wait for the Docker daemon, if at all.
+    let wait_for_docker =
+        matches
+            .value_of(WAIT_FOR_DOCKER_OPTION)
+            .map_or(Ok(config.wait_for_docker), |seconds| {
+                seconds.parse::<u64>().map_err(failure::user(format!(
+                    "{} is not a valid number of seconds.",
+                    seconds.code_str(),
+                )))
+            })?;
+    let wait_for_docker = (wait_for_docker > 0).then(|| Duration::from_secs(wait_for_docker));
+
+    // Read the time limit. The clock starts now, since that's close to when the process started.
+    let time_limit = matches
+        .value_of(TIME_LIMIT_OPTION)
+        .map(|value| {
+            units::parse_duration(
+                value,
+                &format!(
+                    "The value of {}",
+                    format!("--{TIME_LIMIT_OPTION}").code_str()
+                ),
+            )
+        })
+        .transpose()?
+        .map(|limit| time_limit::TimeLimit::new(limit, Instant::now()));
+
+    // Set the environment for the Docker CLI processes.
+    docker::set_environment(config::docker_environment(&config));
+
+    // Read the cleanup switch.
+    let cleanup = Arc::new(cleanup::Cleanup::new(
+        &docker_cli,
+        !matches.is_present(NO_CLEANUP_OPTION),
+    ));
+
+    // Read the trace file path.
+    let trace_file = matches.value_of(TRACE_FILE_OPTION).map(PathBuf::from);
+
+    // Determine where to report progress events, if anywhere.
+    let progress_destination = if let Some(descriptor) = matches.value_of(PROGRESS_FD_OPTION) {
+        if cfg!(not(unix)) {
+            return Err(Failure::User(
+                format!(
+                    "{} is only supported on Unix-like platforms. Use {} instead.",
+                    format!("--{PROGRESS_FD_OPTION}").code_str(),
+                    format!("--{PROGRESS_FILE_OPTION}").code_str(),
+                ),
+                None,
+            ));
+        }
+
+        Some(progress::Destination::Descriptor(
+            descriptor.parse::<u32>().map_err(failure::user(format!(
+                "{} is not a valid file descriptor.",
+                descriptor.code_str(),
+            )))?,
+        ))
+    } else {
+        matches
+            .value_of(PROGRESS_FILE_OPTION)
+            .map(|path| progress::Destination::File(PathBuf::from(path)))
+    };
+
+    // The run ends with a summary on standard output, unless the progress events go there. Then the
+    // `run_finished` event takes its place, so the output is still all JSON.
+    let summary = !matches!(
+        progress_destination,
+        Some(progress::Destination::Descriptor(1)),
+    );
+
+    // Read the thresholds for suspiciously large input files.
+    let input_size_limits = tar::SizeLimits::new(
+        config.input_size_warning_bytes,
+        config.input_size_warning_files,
+        matches.is_present(STRICT_INPUT_SIZE_OPTION),
+    );
+
+    // Read the switch for treating hidden input files as an error.
+    let strict_mount_paths = matches.is_present(STRICT_MOUNT_PATHS_OPTION);
+
+    // Read the switch for allowing input paths which don't exist yet.
+    let allow_missing_inputs = matches.is_present(ALLOW_MISSING_INPUTS_OPTION);
+
+    // Read the switch for requiring the images to be pinned to a digest.
+    let require_pinned_image =
+        matches.is_present(REQUIRE_PINNED_IMAGE_OPTION) || config.require_pinned_image;
+
+    // Read how to log in to the registry, if at all. An empty command means there's no login
+    // [tag:registry_login_command_nonempty].
+    let registry_login = (!config.registry_login_command.is_empty()).then(|| registry::Login {
+        command: config.registry_login_command.clone(),
+        mode: config.registry_login_mode,
+        user: config.registry_user.clone(),
+    });
+
     // Read the list switch.
     let list = matches.is_present(LIST_OPTION);
+    let list_filter = matches.value_of(LIST_TASK_OPTION).map(ToOwned::to_owned);
+    let list_tree = matches.is_present(LIST_TREE_OPTION);
+
+    // Read the graph switch.
+    let graph = matches.is_present(GRAPH_OPTION);
+
+    // Read the Dockerfile export switch and where to write the Dockerfile.
+    let containerfile = matches.is_present(CONTAINERFILE_OPTION);
+    let containerfile_out = matches
+        .value_of(CONTAINERFILE_OUT_OPTION)
+        .map(PathBuf::from);
+
+    // Read the task for which to print a hash of the definition.
+    let hash_task = matches.value_of(HASH_TASK_OPTION).map(ToOwned::to_owned);
+
+    // Read the dry run switch.
+    let dry_run = matches.is_present(DRY_RUN_OPTION);
+
+    // Read the cache assertion switch.
+    let assert_cached = matches.is_present(ASSERT_CACHED_OPTION);
+
+    // Read the switches for only using the cache.
+    let only_cached = matches.is_present(ONLY_CACHED_OPTION);
+    let extract = matches.is_present(EXTRACT_OPTION);
+
+    // Read the list of tasks for which to list the outputs.
+    let list_outputs = matches
+        .values_of(LIST_OUTPUTS_OPTION)
+        .map_or_else(Vec::new, |tasks| {
+            tasks
+                .map(std::borrow::ToOwned::to_owned)
+                .collect::<Vec<_>>()
+        });
 
     // Read the shell switch.
     let spawn_shell = matches.is_present(SHELL_OPTION);
 
-    // Read the list of tasks.
-    let tasks = matches.values_of(TASKS_OPTION).map(|tasks| {
-        tasks
-            .map(std::borrow::ToOwned::to_owned)
-            .collect::<Vec<_>>()
-    });
+    // Read the image for the shell, if any.
+    let shell_image = matches
+        .value_of(SHELL_IMAGE_OPTION)
+        .map(std::borrow::ToOwned::to_owned);
+    if let Some(image) = &shell_image {
+        toastfile::check_image(image)?;
+    }
 
-    // Read the list of forced tasks.
-    let forced_tasks = matches
+    // Read the output verification switch.
+    let check_outputs = matches.is_present(CHECK_OUTPUTS_OPTION);
+
+    // Read the background pull switch.
+    let interleave_pull = matches.is_present(INTERLEAVE_PULL_OPTION);
+
+    // Decide once whether to render spinners. Spinners are disabled by the `--no-spinner` flag or
+    // by setting `TOAST_NO_SPINNER` to a nonempty value.
+    spinner::set_enabled(
+        !matches.is_present(NO_SPINNER_OPTION)
+            && env::var_os(NO_SPINNER_ENV_VAR).is_none_or(|value| value.is_empty()),
+    );
+
+    // Read the list of tasks. For `toast prune`, there aren't any.
+    let tasks = matches
+        .values_of(TASKS_OPTION)
+        .filter(|_| !prune)
+        .map(|tasks| {
+            tasks
+                .map(std::borrow::ToOwned::to_owned)
+                .collect::<Vec<_>>()
+        });
+
+    // Read the one-off command, if any. It's given as `toast run -- COMMAND`, so `run` shows up as
+    // the only task. The command becomes a task which replaces the ones in the toastfile.
+    let ad_hoc_task = if let Some(command) = matches.values_of(COMMAND_OPTION) {
+        if tasks.as_deref() != Some(&[toastfile::AD_HOC_TASK.to_owned()]) {
+            return Err(Failure::User(
+                format!(
+                    "To run a one-off command, use {}.",
+                    "toast run -- COMMAND".code_str(),
+                ),
+                None,
+            ));
+        }
+
+        let input_paths = matches
+            .values_of(INPUT_OPTION)
+            .into_iter()
+            .flatten()
+            .map(|path| {
+                UnixPathBuf::try_from(PathBuf::from(path))
+                    .map_err(|_| Failure::User(format!("Invalid path {}.", path.code_str()), None))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mount_paths = matches
+            .values_of(MOUNT_OPTION)
+            .into_iter()
+            .flatten()
+            .map(|path| {
+                path.parse().map_err(|reason| {
+                    Failure::User(
+                        format!("Invalid mount path {}. {}", path.code_str(), reason),
+                        None,
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Some(toastfile::ad_hoc_task(
+            &command.collect::<Vec<_>>().join(" "),
+            input_paths,
+            mount_paths,
+        )?)
+    } else {
+        if matches.is_present(INPUT_OPTION) || matches.is_present(MOUNT_OPTION) {
+            return Err(Failure::User(
+                format!(
+                    "{} and {} are only supported with {}.",
+                    "--input".code_str(),
+                    "--mount".code_str(),
+                    "toast run -- COMMAND".code_str(),
+                ),
+                None,
+            ));
+        }
+        None
+    };
+
+    // Read the task to check for determinism, if any.
+    let verify_determinism = matches
+        .value_of(VERIFY_DETERMINISM_OPTION)
+        .map(ToOwned::to_owned);
+
+    // Read the list of forced tasks. A task being checked for determinism is forced, since both of
+    // its runs need to actually happen.
+    let mut forced_tasks = matches
         .values_of(FORCE_OPTION)
         .map_or_else(Vec::new, |tasks| {
             tasks
                 .map(std::borrow::ToOwned::to_owned)
                 .collect::<Vec<_>>()
         });
+    forced_tasks.extend(verify_determinism.iter().cloned());
+
+    // Read the no dependencies switch.
+    let no_deps = matches.is_present(NO_DEPS_OPTION);
+
+    // Read the environment variables given on the command line. They take precedence over the
+    // environment and the default values in the toastfile [ref:environment_overrides].
+    let environment_overrides = matches
+        .values_of(ENV_OPTION)
+        .into_iter()
+        .flatten()
+        .map(|assignment| match assignment.split_once('=') {
+            Some((variable, value)) if !variable.is_empty() => {
+                Ok((variable.to_owned(), value.to_owned()))
+            }
+            _ => Err(Failure::User(
+                format!(
+                    "Invalid environment variable {}. It should have the form {}.",
+                    assignment.code_str(),
+                    "KEY=VALUE".code_str(),
+                ),
+                None,
+            )),
+        })
+        .collect::<Result<HashMap<_, _>, _>>()?;
+
+    // Read the output line limit.
+    let max_log_lines = matches
+        .value_of(MAX_LOG_LINES_OPTION)
+        .map(|lines| {
+            lines.parse::<usize>().map_err(failure::user(format!(
+                "{} is not a valid number of lines.",
+                lines.code_str(),
+            )))
+        })
+        .transpose()?;
+
+    // Read the switch for prefixing the output of each task with its name.
+    let prefix_output = matches.is_present(PREFIX_OUTPUT_OPTION) || config.prefix_output;
+
+    // Determine whether to check for a newer release. This is never done in CI.
+    let version_check = (matches.is_present(VERSION_CHECK_OPTION) || config.check_for_updates)
+        && env::var_os(CI_ENV_VAR).is_none_or(|value| value.is_empty());
+
+    // Read the resource usage sampling interval, if resource usage reporting is enabled.
+    let stats_interval = if matches.is_present(STATS_OPTION) {
+        let interval = matches
+            .value_of(STATS_INTERVAL_OPTION)
+            .unwrap_or(DEFAULT_STATS_INTERVAL);
+        match interval.parse::<f64>() {
+            Ok(seconds) if seconds > 0.0_f64 && seconds.is_finite() => {
+                Some(Duration::from_secs_f64(seconds))
+            }
+            _ => {
+                return Err(Failure::User(
+                    format!(
+                        "{} is not a positive number of seconds.",
+                        interval.code_str()
+                    ),
+                    None,
+                ));
+            }
+        }
+    } else {
+        None
+    };
 
-    // Read the force all switch.
-    let force_all = matches.is_present(FORCE_ALL_OPTION);
+    // Read how many changed paths to show when a task fails, if requested.
+    let diff_limit = if matches.is_present(DIFF_ON_FAILURE_OPTION) {
+        let limit = matches
+            .value_of(DIFF_LIMIT_OPTION)
+            .unwrap_or(DEFAULT_DIFF_LIMIT);
+        Some(limit.parse::<usize>().map_err(failure::user(format!(
+            "{} is not a valid number of paths.",
+            limit.code_str(),
+        )))?)
+    } else {
+        None
+    };
+
+    // Read the parallelism. By default, we use the parallelism of the host, taking cgroup CPU
+    // limits into account.
+    let jobs = match matches.value_of(JOBS_OPTION) {
+        None | Some(JOBS_AUTO) => host::effective_parallelism(),
+        Some(jobs) => match jobs.parse::<usize>() {
+            Ok(jobs) if jobs > 0 => jobs,
+            _ => {
+                return Err(Failure::User(
+                    format!(
+                        "{} is not a positive number or {}.",
+                        jobs.code_str(),
+                        JOBS_AUTO.code_str(),
+                    ),
+                    None,
+                ));
+            }
+        },
+    };
 
     Ok(Settings {
         toastfile_path,
         docker_cli,
         docker_repo,
+        wait_for_docker,
+        time_limit,
         read_local_cache,
         write_local_cache,
         read_remote_cache,
         write_remote_cache,
+        confirm_public_push: config.confirm_public_push,
+        ssh_cache,
+        read_ssh_cache,
+        write_ssh_cache,
         list,
+        list_filter,
+        list_tree,
+        graph,
+        containerfile,
+        containerfile_out,
+        list_outputs,
+        hash_task,
+        dry_run,
+        assert_cached,
+        only_cached,
+        extract,
+        verify_determinism,
+        cache_report,
+        prune,
+        prune_older_than,
+        prune_keep_current,
+        prune_volumes,
+        state_clear,
         spawn_shell,
+        shell_image,
         tasks,
+        ad_hoc_task,
         forced_tasks,
         force_all,
+        caching_disabled,
+        no_deps,
+        environment_overrides,
         output_dir,
+        extra_pull_arguments: config.extra_pull_arguments,
+        extra_push_arguments: config.extra_push_arguments,
+        max_log_lines,
+        prefix_output,
+        version_check,
+        jobs,
+        stats_interval,
+        diff_limit,
+        check_outputs,
+        interleave_pull,
+        cleanup,
+        trace: trace::Trace::new(
+            trace_file.is_some(),
+            progress::Progress::new(progress_destination),
+        ),
+        trace_file,
+        architectures: Mutex::new(HashMap::new()),
+        input_size_limits,
+        strict_mount_paths,
+        allow_missing_inputs,
+        require_pinned_image,
+        resolve_image_digest: config.resolve_image_digest,
+        summary,
+        registry_login,
     })
 }
 
-// Parse a toastfile.
-fn parse_toastfile(toastfile_path: &Path) -> Result<toastfile::Toastfile, Failure> {
-    // Read the file from disk.
+// Read a toastfile from disk and compute a hash of its contents.
+fn read_toastfile(toastfile_path: &Path) -> Result<(String, String), Failure> {
     let toastfile_data = fs::read_to_string(toastfile_path).map_err(failure::user(format!(
         "Unable to read file {}.",
         toastfile_path.to_string_lossy().code_str(),
     )))?;
+    let toastfile_hash = toastfile::content_hash(&toastfile_data);
 
-    // Parse it.
-    toastfile::parse(&toastfile_data).map_err(failure::user(format!(
-        "Unable to parse file {}.",
-        toastfile_path.to_string_lossy().code_str(),
-    )))
+    Ok((toastfile_data, toastfile_hash))
 }
 
-// Determine which tasks the user wants to run.
-fn get_roots<'a>(
-    settings: &'a Settings,
-    toastfile: &'a toastfile::Toastfile,
-) -> Result<Vec<&'a str>, Failure> {
-    // Start with the tasks provided via positional arguments.
-    let mut roots: Vec<&'a str> = settings
-        .tasks
-        .as_ref()
-        .map_or_else(Vec::new, |tasks| tasks.iter().map(AsRef::as_ref).collect());
+// Make sure a command like `toast prune` isn't mistaken for a request to run tasks with the same
+// names, or vice versa. If the toastfile has tasks with all the words of the command as their
+// names, it's ambiguous which the user meant, so this fails. The tasks can still be run by listing
+// them differently, as in `alternative`. The toastfile is only read if it exists, and it isn't
+// validated, since the commands don't otherwise need it.
+fn check_command_tasks(
+    toastfile_path: &Path,
+    toastfile: Option<&toastfile::Toastfile>,
+    command: &[&str],
+    alternative: &str,
+) -> Result<(), Failure> {
+    let parsed;
+    let toastfile = if let Some(toastfile) = toastfile {
+        toastfile
+    } else {
+        let Some(toastfile) = read_toastfile(toastfile_path)
+            .ok()
+            .and_then(|(toastfile_data, _)| toastfile::parse_validated(&toastfile_data).ok())
+        else {
+            return Ok(());
+        };
+        parsed = toastfile;
+        &parsed
+    };
 
-    // Add the tasks that were provided via the `--force` flag.
-    roots.extend(
-        settings
+    if command
+        .iter()
+        .all(|word| toastfile.tasks.contains_key(*word))
+    {
+        let (tasks, them) = if command.len() == 1 {
+            ("a task", "it")
+        } else {
+            ("tasks", "them")
+        };
+        return Err(Failure::User(
+            format!(
+                "{} is ambiguous, since {} has {} named {}. To run {}, use {}. To run the command \
+                 instead, rename {}.",
+                format!("toast {}", command.join(" ")).code_str(),
+                toastfile_path.to_string_lossy().code_str(),
+                tasks,
+                format::series(
+                    &command
+                        .iter()
+                        .map(|word| word.code_str().to_string())
+                        .collect::<Vec<_>>(),
+                ),
+                them,
+                alternative.code_str(),
+                them,
+            ),
+            None,
+        ));
+    }
+
+    Ok(())
+}
+
+// Parse a toastfile and return it along with a hash of its contents.
+fn parse_toastfile(
+    toastfile_path: &Path,
+    strict_mount_paths: bool,
+    require_pinned_image: bool,
+) -> Result<(toastfile::Toastfile, String), Failure> {
+    // Read the file from disk.
+    let (toastfile_data, toastfile_hash) = read_toastfile(toastfile_path)?;
+    debug!(
+        "The hash of {} is {}.",
+        toastfile_path.to_string_lossy().code_str(),
+        toastfile_hash.code_str(),
+    );
+
+    // Parse it. If the same toastfile passed validation before, most of the checks are skipped.
+    let validated = validation::validated(toastfile_path, &toastfile_hash);
+    let mut toastfile = if validated {
+        debug!("The toastfile was already validated.");
+        toastfile::parse_validated(&toastfile_data)
+    } else {
+        toastfile::parse(&toastfile_data)
+    }
+    .map_err(failure::user(format!(
+        "Unable to parse file {}.",
+        toastfile_path.to_string_lossy().code_str(),
+    )))?;
+
+    // Remember that the toastfile is valid for next time. That's only an optimization, so it's fine
+    // if it fails.
+    if !validated {
+        if let Err(e) = validation::record(toastfile_path, &toastfile_hash) {
+            debug!("{}", e);
+        }
+    }
+
+    // Read the commands and environment variables which are stored in separate files. Their paths
+    // are relative to the toastfile.
+    let toastfile_dir = toastfile_path.parent().unwrap_or_else(|| Path::new(""));
+    toastfile::load_command_files(&mut toastfile, toastfile_dir)
+        .and_then(|()| toastfile::load_env_files(&mut toastfile, toastfile_dir))
+        .map_err(failure::user(format!(
+            "Unable to parse file {}.",
+            toastfile_path.to_string_lossy().code_str(),
+        )))?;
+
+    // Make sure the images are pinned to a digest, if the user asked for that.
+    if require_pinned_image {
+        toastfile::check_pinned_images(&toastfile).map_err(|error| {
+            Failure::User(
+                format!(
+                    "Unable to use file {}. {}",
+                    toastfile_path.to_string_lossy().code_str(),
+                    error,
+                ),
+                None,
+            )
+        })?;
+    }
+
+    // Report anything that's legal but probably a mistake. Hidden input files are an error if the
+    // user asked for that.
+    let warnings = toastfile::warnings(&toastfile);
+    if strict_mount_paths && !warnings.is_empty() {
+        return Err(Failure::User(
+            format!(
+                "Unable to use file {}. {}",
+                toastfile_path.to_string_lossy().code_str(),
+                warnings.join(" "),
+            ),
+            None,
+        ));
+    }
+    for warning in warnings {
+        warn!("{}", warning);
+    }
+
+    // Report dependencies which are listed more than once.
+    for warning in toastfile::dependency_warnings(&toastfile) {
+        warn!("{}", warning);
+    }
+
+    // Warn about tasks which could clobber each other's mounted files. Relative host paths in mounts
+    // are relative to the toastfile. The directory is made absolute so they can be compared with
+    // absolute host paths.
+    let toastfile_dir = toastfile_path.parent().unwrap_or_else(|| Path::new(""));
+    let toastfile_dir = current_dir().map_or_else(
+        |_| toastfile_dir.to_owned(),
+        |working_dir| working_dir.join(toastfile_dir),
+    );
+    for warning in toastfile::shared_mount_warnings(&toastfile, &toastfile_dir) {
+        warn!("{}", warning);
+    }
+
+    Ok((toastfile, toastfile_hash))
+}
+
+// Determine which tasks the user wants to run.
+fn get_roots<'a>(
+    settings: &'a Settings,
+    toastfile: &'a toastfile::Toastfile,
+) -> Result<Vec<&'a str>, Failure> {
+    // Start with the tasks provided via positional arguments.
+    let mut roots: Vec<&'a str> = settings
+        .tasks
+        .as_ref()
+        .map_or_else(Vec::new, |tasks| tasks.iter().map(AsRef::as_ref).collect());
+
+    // Add the tasks that were provided via the `--force` flag.
+    roots.extend(
+        settings
             .forced_tasks
             .iter()
             .map(AsRef::as_ref)
@@ -476,141 +1586,1590 @@ fn get_roots<'a>(
             }
         }
 
-        // Run the tasks that the user provided.
-        Ok(roots)
+        // Run the tasks that the user provided.
+        Ok(roots)
+    }
+}
+
+// Print where the output files of the tasks given by `--list-outputs` will be written.
+fn list_outputs(settings: &Settings, toastfile: &toastfile::Toastfile) -> Result<(), Failure> {
+    for task_name in &settings.list_outputs {
+        // Fetch the task data.
+        let task_data = toastfile.tasks.get(task_name).ok_or_else(|| {
+            Failure::User(
+                format!(
+                    "No task named {} in {}.",
+                    task_name.code_str(),
+                    settings.toastfile_path.to_string_lossy().code_str(),
+                ),
+                None,
+            )
+        })?;
+
+        // Print the task name.
+        println!("* {}", task_name.code_str());
+
+        // Print each output path and where it will be written.
+        let location = location(toastfile, task_data);
+        for (paths, condition) in [
+            (&task_data.output_paths, "on success"),
+            (&task_data.output_paths_on_failure, "on failure"),
+            (&task_data.output_paths_always, "always"),
+        ] {
+            for path in paths {
+                let (source, destination) =
+                    docker::output_path_mapping(path, &location, &settings.output_dir)?;
+                println!(
+                    "  {} \u{2192} {} ({}{})",
+                    source.to_string_lossy().code_str(),
+                    destination.to_string_lossy().code_str(),
+                    condition,
+                    if fs::symlink_metadata(&destination).is_ok() {
+                        ", will overwrite existing files"
+                    } else {
+                        ""
+                    },
+                );
+            }
+        }
+
+        // Let the user know if the task has no output files.
+        if task_data.output_paths.is_empty()
+            && task_data.output_paths_on_failure.is_empty()
+            && task_data.output_paths_always.is_empty()
+        {
+            println!("  (no output paths)");
+        }
+    }
+
+    Ok(())
+}
+
+// Resolve the base image to a reference to its digest (e.g., `node@sha256:…`), so the cache keys of
+// the tasks which build on it change when the tag is moved to a different image. The image is
+// pulled first if it isn't available locally (or if every task is forced), unless `pull` is false.
+// Returns `None` if the base image should be used as is.
+fn resolve_image_digest(
+    schedule: &[&str],
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+    pull: bool,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<Option<String>, Failure> {
+    // An image which is already pinned to a digest doesn't need to be resolved.
+    if toastfile.image.contains('@') {
+        return Ok(None);
+    }
+
+    // Use the same container engine as the schedule [ref:tasks_valid].
+    let Some(first_task) = schedule.first().map(|task| &toastfile.tasks[*task]) else {
+        return Ok(None);
+    };
+    let docker_cli = toastfile::docker_cli(first_task, &settings.docker_cli);
+
+    let policy = if !pull {
+        docker::PullPolicy::Never
+    } else if settings.force_all {
+        docker::PullPolicy::Always
+    } else {
+        docker::PullPolicy::IfMissing
+    };
+    if !docker::ensure_image(
+        docker_cli,
+        &toastfile.image,
+        docker::ImagePurpose::BaseImage,
+        policy,
+        &settings.extra_pull_arguments,
+        interrupted,
+    )?
+    .available()
+    {
+        warn!(
+            "The base image {} isn't available locally, so the cache keys are based on its tag \
+             rather than its digest.",
+            toastfile.image.code_str(),
+        );
+        return Ok(None);
+    }
+
+    let digest = docker::image_digest(docker_cli, &toastfile.image, interrupted)?;
+    if let Some(digest) = &digest {
+        info!(
+            "Resolved the base image {} to {}.",
+            toastfile.image.code_str(),
+            digest.code_str(),
+        );
+    } else {
+        warn!(
+            "The base image {} doesn't have a digest (e.g., because it was built locally), so the \
+             cache keys are based on its tag.",
+            toastfile.image.code_str(),
+        );
+    }
+
+    Ok(digest)
+}
+
+// Remove the data Toast saved between runs for the toastfile, or for every toastfile if `all` is
+// set, as in `toast state clear`.
+fn clear_state(settings: &Settings, all: bool) -> Result<(), Failure> {
+    let dirs = if all {
+        vec![paths::state_dir(), paths::cache_dir()]
+    } else {
+        vec![paths::project_state_dir(&settings.toastfile_path)]
+    };
+
+    let removed = paths::clear(&dirs.into_iter().flatten().collect::<Vec<_>>())?;
+
+    if removed.files == 0 {
+        info!("There is no saved state to remove.");
+    } else {
+        info!(
+            "Removed {}, totaling {}.",
+            format::number(removed.files, "file"),
+            units::format_bytes(removed.bytes).code_str(),
+        );
+    }
+
+    Ok(())
+}
+
+// Determine whether the extracted outputs of a schedule can be recorded, so a later run can tell
+// they're up to date. That's only the case if the whole schedule would be read from the cache. A
+// run with `--no-deps` doesn't copy the output files of the dependencies, so it can't rely on or
+// update the records either.
+fn outputs_recordable(
+    schedule: &[&str],
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+) -> bool {
+    !settings.spawn_shell
+        && !settings.no_deps
+        && (settings.read_local_cache
+            || settings.read_remote_cache
+            || (settings.read_ssh_cache && settings.ssh_cache.is_some()))
+        && schedule::caching(
+            toastfile,
+            schedule,
+            &settings.forced_tasks,
+            settings.caching_disabled,
+        )
+        .into_iter()
+        .all(|enabled| enabled)
+}
+
+// Compute the key under which the extracted outputs of a schedule are recorded. This is based on
+// the cache key of the final task (or the base image, if there are no tasks) and the output
+// directory.
+fn outputs_key(image: &str, settings: &Settings) -> String {
+    cache::combine(image, &*settings.output_dir.to_string_lossy())
+}
+
+// Check whether the extracted outputs of a schedule were recorded before and are still on the host,
+// without running anything. This only reads the input files if there are records to compare
+// against, and the cache keys use the architectures stored with the records rather than asking the
+// Docker daemons.
+fn outputs_up_to_date(
+    schedule: &[&str],
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+    environment: &HashMap<String, String>,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<bool, Failure> {
+    for architectures in outputs::recorded_architectures(&settings.toastfile_path) {
+        // A record which doesn't know the architecture of a daemon the tasks use can't match.
+        if schedule.iter().any(|task_name| {
+            let task_data = &toastfile.tasks[*task_name]; // [ref:tasks_valid]
+            !task_data.arch_independent_cache
+                && !architectures
+                    .contains_key(toastfile::docker_cli(task_data, &settings.docker_cli))
+        }) {
+            continue;
+        }
+
+        let images = cache_keys_with(
+            schedule,
+            settings,
+            toastfile,
+            environment,
+            &|task_data| {
+                Ok(if task_data.arch_independent_cache {
+                    String::new()
+                } else {
+                    architectures[toastfile::docker_cli(task_data, &settings.docker_cli)].clone()
+                })
+            },
+            interrupted,
+        )?;
+        let key = outputs_key(images.last().unwrap_or(&toastfile.image), settings);
+        if outputs::up_to_date(&settings.toastfile_path, &key, settings.check_outputs) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+// Compute the cache key of each task in a schedule in turn, just as the runner does, but without
+// running anything. This reads the input files of every task.
+fn cache_keys(
+    schedule: &[&str],
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+    environment: &HashMap<String, String>,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<Vec<String>, Failure> {
+    cache_keys_with(
+        schedule,
+        settings,
+        toastfile,
+        environment,
+        &|task_data| cache_key_architecture(task_data, settings, interrupted),
+        interrupted,
+    )
+}
+
+// Compute the cache keys like `cache_keys`, but with the given way of determining the architecture
+// of the Docker daemon each task uses.
+fn cache_keys_with(
+    schedule: &[&str],
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+    environment: &HashMap<String, String>,
+    architecture: &dyn Fn(&toastfile::Task) -> Result<String, Failure>,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<Vec<String>, Failure> {
+    // All relative paths are relative to where the toastfile lives.
+    let mut toastfile_dir = settings.toastfile_path.clone();
+    toastfile_dir.pop();
+
+    let mut images = vec![];
+    let mut image = toastfile.image.clone();
+    for task_name in schedule {
+        let task_data = &toastfile.tasks[*task_name]; // [ref:tasks_valid]
+
+        // A task with its own image doesn't build on the previous task
+        // [ref:task_image_fresh_context].
+        if let Some(task_image) = &task_data.image {
+            image.clone_from(task_image);
+        }
+
+        let (_, input_files_hash) = tar::create(
+            "Reading files\u{2026}",
+            io::sink(),
+            &task_data.input_paths,
+            &task_data.excluded_input_paths,
+            task_data.allow_empty_globs,
+            task_data.respect_gitignore,
+            &toastfile_dir,
+            &location(toastfile, task_data),
+            toastfile::input_modes(task_data),
+            true,
+            &settings.input_size_limits,
+            interrupted,
+        )?;
+        image = cache::image_name(
+            &image,
+            toastfile::docker_repo(task_data, &settings.docker_repo),
+            toastfile,
+            task_data,
+            &input_files_hash,
+            environment,
+            &architecture(task_data)?,
+        );
+        images.push(image.clone());
+    }
+
+    Ok(images)
+}
+
+// The tasks which `--no-deps` skips, along with what the tasks which are run start from instead
+#[derive(Default)]
+struct SkippedTasks {
+    // The image each skipped task would have produced, if it's in the local cache
+    images: HashMap<String, String>,
+
+    // The image each task which is run starts from, if a skipped task would have produced it
+    starting_images: HashMap<String, String>,
+}
+
+// For `--no-deps`, remove all but the root tasks from a schedule. Each remaining task starts from
+// the image the task before it in the full schedule would have produced, just as it would in a
+// normal run, so its cache key is the same. The tasks before it aren't run, so that image must
+// already be in the local cache.
+fn skip_dependencies<'a>(
+    schedule: &[&'a str],
+    root_tasks: &[&str],
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+    environment: &HashMap<String, String>,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(Vec<&'a str>, SkippedTasks), Failure> {
+    let mut remaining = vec![];
+    let mut skipped = SkippedTasks::default();
+    if schedule
+        .iter()
+        .all(|task_name| root_tasks.contains(task_name))
+    {
+        return Ok((schedule.to_vec(), skipped));
+    }
+
+    // Compute the cache keys of all the tasks, including the ones which won't run.
+    let caching = schedule::caching(
+        toastfile,
+        schedule,
+        &settings.forced_tasks,
+        settings.caching_disabled,
+    );
+    let images = cache_keys(schedule, settings, toastfile, environment, interrupted)?;
+
+    for (i, task_name) in schedule.iter().enumerate() {
+        let task_data = &toastfile.tasks[*task_name]; // [ref:tasks_valid]
+        let docker_cli = toastfile::docker_cli(task_data, &settings.docker_cli);
+        if !root_tasks.contains(task_name) {
+            if caching[i] && docker::image_exists(docker_cli, &images[i], interrupted)? {
+                skipped
+                    .images
+                    .insert((*task_name).to_owned(), images[i].clone());
+            }
+            continue;
+        }
+
+        remaining.push(*task_name);
+
+        // A task with its own image doesn't build on the previous task
+        // [ref:task_image_fresh_context], and a task after a root task builds on the image which
+        // that task produces in this run.
+        if i == 0 || task_data.image.is_some() || root_tasks.contains(&schedule[i - 1]) {
+            continue;
+        }
+
+        let predecessor = schedule[i - 1];
+        if !caching[i - 1] {
+            return Err(Failure::User(
+                format!(
+                    "Unable to run task {} without its dependencies, since task {} doesn\u{2019}t \
+                     use the cache.",
+                    task_name.code_str(),
+                    predecessor.code_str(),
+                ),
+                None,
+            ));
+        }
+
+        if !skipped.images.contains_key(predecessor) {
+            return Err(Failure::User(
+                format!(
+                    "Unable to run task {} without its dependencies, since the image task {} \
+                     would produce ({}) isn\u{2019}t in the local cache. Run {} first, or leave \
+                     out {}.",
+                    task_name.code_str(),
+                    predecessor.code_str(),
+                    images[i - 1].code_str(),
+                    format!("toast {predecessor}").code_str(),
+                    "--no-deps".code_str(),
+                ),
+                None,
+            ));
+        }
+
+        skipped
+            .starting_images
+            .insert((*task_name).to_owned(), images[i - 1].clone());
+    }
+
+    Ok((remaining, skipped))
+}
+
+// Determine the architecture of the Docker daemon a task uses, for its cache key, or return an
+// empty string if the task doesn't need it. Each daemon is only asked once per run.
+fn cache_key_architecture(
+    task: &toastfile::Task,
+    settings: &Settings,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<String, Failure> {
+    if task.arch_independent_cache {
+        return Ok(String::new());
+    }
+
+    let docker_cli = toastfile::docker_cli(task, &settings.docker_cli);
+    // The `unwrap` will only fail if a panic already occurred.
+    let mut architectures = settings.architectures.lock().unwrap();
+    if let Some(architecture) = architectures.get(docker_cli) {
+        return Ok(architecture.clone());
+    }
+
+    let architecture = docker::server_architecture(docker_cli, interrupted)?;
+    architectures.insert(docker_cli.to_owned(), architecture.clone());
+    Ok(architecture)
+}
+
+// Remove the images in the cache, for `toast prune`. Images are found in the configured repository
+// and in any repositories the tasks use instead. With `--prune-keep-current`, the images which the
+// default tasks (i.e., the ones `toast` runs without arguments) would use are kept. With
+// `--prune-volumes`, the volumes created for tasks are removed too.
+fn prune_images(
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    let mut repos = vec![settings.docker_repo.as_str()];
+    repos.extend(
+        toastfile
+            .tasks
+            .values()
+            .filter_map(|task| task.docker_repo.as_deref()),
+    );
+    repos.sort_unstable();
+    repos.dedup();
+
+    let keep = if settings.prune_keep_current {
+        let roots = get_roots(settings, toastfile)?;
+        let schedule = schedule::compute(toastfile, &roots);
+        let environment = fetch_environment(
+            &schedule,
+            &roots,
+            &toastfile.tasks,
+            &settings.environment_overrides,
+        )?;
+        cache_keys(&schedule, settings, toastfile, &environment, interrupted)?
+            .into_iter()
+            .collect()
+    } else {
+        HashSet::new()
+    };
+
+    let now = SystemTime::now();
+    let images = prune::select(
+        prune::find(settings.docker_cli.as_str(), &repos, interrupted)?,
+        now,
+        settings.prune_older_than,
+        &keep,
+    );
+    prune::remove(
+        settings.docker_cli.as_str(),
+        &images,
+        now,
+        settings.dry_run,
+        interrupted,
+    )?;
+
+    // The volumes don't have cache keys or ages that matter, so the other options don't apply. They
+    // are found with the Docker CLI of each task that could have created them, by the repository
+    // they're labeled with [ref:volume_label].
+    if settings.prune_volumes {
+        let mut docker_repos = BTreeMap::<&str, Vec<&str>>::new();
+        docker_repos
+            .entry(settings.docker_cli.as_str())
+            .or_default()
+            .push(settings.docker_repo.as_str());
+        for task in toastfile.tasks.values() {
+            docker_repos
+                .entry(toastfile::docker_cli(task, &settings.docker_cli))
+                .or_default()
+                .push(toastfile::docker_repo(task, &settings.docker_repo));
+        }
+
+        let mut volumes = vec![];
+        for (docker_cli, mut docker_repos) in docker_repos {
+            docker_repos.sort_unstable();
+            docker_repos.dedup();
+            volumes.push((
+                docker_cli,
+                prune::find_volumes(docker_cli, &docker_repos, interrupted)?,
+            ));
+        }
+
+        if volumes.iter().all(|(_, volumes)| volumes.is_empty()) {
+            info!("There are no volumes to remove.");
+        }
+        for (docker_cli, volumes) in volumes {
+            if !volumes.is_empty() {
+                prune::remove_volumes(docker_cli, &volumes, settings.dry_run, interrupted)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Check that every task in a schedule would be read from the cache, for `--assert-cached`. This
+// consults the same caches as the runner, including the remote cache (with `docker manifest
+// inspect`, so nothing is pulled). Nothing is run, and no output files are copied.
+fn assert_cached(
+    schedule: &[&str],
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+    environment: &HashMap<String, String>,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    // Determine which tasks can use the cache, just as the runner does.
+    let caching = schedule::caching(
+        toastfile,
+        schedule,
+        &settings.forced_tasks,
+        settings.caching_disabled,
+    );
+
+    // Compute the cache keys and find the tasks which aren't cached.
+    let images = cache_keys(schedule, settings, toastfile, environment, interrupted)?;
+    let mut uncached = vec![];
+    for ((task_name, image), caching_enabled) in schedule.iter().zip(&images).zip(caching) {
+        if !caching_enabled {
+            uncached.push(format!("{} (caching is disabled)", task_name.code_str()));
+        } else if !image_cached(settings, toastfile, task_name, image, interrupted)? {
+            uncached.push(format!("{} ({})", task_name.code_str(), image.code_str()));
+        }
+    }
+
+    if uncached.is_empty() {
+        info!(
+            "{} cached.",
+            if schedule.len() == 1 {
+                "The task is".to_owned()
+            } else {
+                format!("All {} are", format::number(schedule.len(), "task"))
+            },
+        );
+        Ok(())
+    } else {
+        Err(Failure::User(
+            format!(
+                "{} would run:\n{}",
+                format::number(uncached.len(), "task"),
+                uncached
+                    .iter()
+                    .map(|task| format!("- {task}"))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+            None,
+        ))
+    }
+}
+
+// Report whether each task in a schedule would be read from the cache or run, without creating
+// containers, pulling images, or otherwise changing anything. The remote cache can't be checked
+// without pulling from it, so it isn't consulted.
+fn dry_run(
+    schedule: &[&str],
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+    environment: &HashMap<String, String>,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    // Determine which tasks can use the cache, just as the runner does.
+    let caching = schedule::caching(
+        toastfile,
+        schedule,
+        &settings.forced_tasks,
+        settings.caching_disabled,
+    );
+
+    // Compute the cache keys and check where each image can be found.
+    let images = cache_keys(schedule, settings, toastfile, environment, interrupted)?;
+    for ((task_name, image), caching_enabled) in schedule.iter().zip(&images).zip(caching) {
+        let task_data = &toastfile.tasks[*task_name]; // [ref:tasks_valid]
+        let status = if !caching_enabled {
+            "will run (caching is disabled)"
+        } else if settings.read_local_cache
+            && docker::image_exists(
+                toastfile::docker_cli(task_data, &settings.docker_cli),
+                image,
+                interrupted,
+            )?
+        {
+            "cached (local)"
+        } else if ssh_image_exists(settings, image, interrupted)? {
+            "cached (SSH)"
+        } else if settings.read_remote_cache {
+            "will run (unless it's in the remote cache)"
+        } else {
+            "will run"
+        };
+
+        println!("{}: {}", task_name.code_str(), status);
+    }
+
+    Ok(())
+}
+
+// Check that the final task in a schedule would be read from the cache, for `--only-cached`. This
+// consults the same caches as `--assert-cached` without pulling anything. With `--extract`, every
+// task has to be cached, and the run continues so the output files are copied out of the cached
+// images. Otherwise, nothing is run and no output files are copied. Returns whether to continue.
+fn only_cached(
+    schedule: &[&str],
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+    environment: &HashMap<String, String>,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<bool, Failure> {
+    // Determine which tasks can use the cache, just as the runner does.
+    let caching = schedule::caching(
+        toastfile,
+        schedule,
+        &settings.forced_tasks,
+        settings.caching_disabled,
+    );
+
+    // Compute the cache keys and check which images are in the cache, starting with the final one.
+    // Without `--extract`, only the final image matters, so the rest are only checked if it's
+    // missing.
+    let images = cache_keys(schedule, settings, toastfile, environment, interrupted)?;
+    let mut found = vec![false; schedule.len()];
+    for (i, task_name) in schedule.iter().enumerate().rev() {
+        found[i] =
+            caching[i] && image_cached(settings, toastfile, task_name, &images[i], interrupted)?;
+        if found[i] && i + 1 == schedule.len() && !settings.extract {
+            break;
+        }
+    }
+
+    let misses = schedule::cache_misses(schedule, &caching, &found, settings.extract);
+    if misses.is_empty() {
+        if let Some(task_name) = schedule.last() {
+            info!("The final task {} is cached.", task_name.code_str());
+        }
+        return Ok(settings.extract);
+    }
+
+    let images = schedule.iter().zip(&images).collect::<HashMap<_, _>>();
+    Err(Failure::User(
+        format!(
+            "{} would run:\n{}",
+            format::number(misses.len(), "task"),
+            misses
+                .iter()
+                .map(|(task_name, miss)| {
+                    format!(
+                        "- {} ({})",
+                        task_name.code_str(),
+                        match miss {
+                            schedule::Miss::CachingDisabled => "caching is disabled".to_owned(),
+                            schedule::Miss::FirstDivergence => format!(
+                                "the cache keys diverge here: {} isn't cached",
+                                images[task_name].code_str(),
+                            ),
+                            schedule::Miss::AfterDivergence => {
+                                format!("{} isn't cached", images[task_name].code_str())
+                            }
+                        },
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+        None,
+    ))
+}
+
+// Check whether the image for a task is in any of the caches the runner reads from. Nothing is
+// pulled.
+fn image_cached(
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+    task_name: &str,
+    image: &str,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<bool, Failure> {
+    // [ref:tasks_valid]
+    let docker_cli = toastfile::docker_cli(&toastfile.tasks[task_name], &settings.docker_cli);
+    Ok(
+        (settings.read_local_cache && docker::image_exists(docker_cli, image, interrupted)?)
+            || ssh_image_exists(settings, image, interrupted)?
+            || (settings.read_remote_cache
+                && docker::remote_image_exists(docker_cli, image, interrupted)?),
+    )
+}
+
+// Check whether the SSH cache, if there is one, has an image. If the host can't be reached, we
+// treat it as a cache miss, just as the runner does.
+fn ssh_image_exists(
+    settings: &Settings,
+    image: &str,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<bool, Failure> {
+    let (true, Some(ssh_cache)) = (settings.read_ssh_cache, &settings.ssh_cache) else {
+        return Ok(false);
+    };
+
+    match ssh_cache.image_exists(image, interrupted) {
+        Ok(exists) => Ok(exists),
+        Err(Failure::Interrupted) => Err(Failure::Interrupted),
+        Err(e) => {
+            warn!("{}", e);
+            Ok(false)
+        }
+    }
+}
+
+// Determine where the output files of the tasks in a schedule are written on the host.
+fn output_destinations(
+    schedule: &[&str],
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+) -> Result<Vec<PathBuf>, Failure> {
+    let mut destinations = vec![];
+
+    for task_name in schedule {
+        let task_data = &toastfile.tasks[*task_name]; // [ref:tasks_valid]
+        let location = location(toastfile, task_data);
+        for path in task_data
+            .output_paths
+            .iter()
+            .chain(&task_data.output_paths_always)
+        {
+            let (_, destination) =
+                docker::output_path_mapping(path, &location, &settings.output_dir)?;
+            destinations.push(destination);
+        }
+    }
+
+    Ok(destinations)
+}
+
+// Check that the input paths of the tasks in the schedule exist, except for the ones which their
+// dependencies may produce.
+fn check_input_paths(
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+    schedule: &[&str],
+) -> Result<(), Failure> {
+    let toastfile_dir = settings
+        .toastfile_path
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+
+    // Find the output directory relative to the toastfile directory. Either of them could be
+    // relative to the working directory, so they're compared as absolute paths.
+    let working_dir =
+        current_dir().map_err(failure::system("Unable to determine working directory."))?;
+    let output_dir_rsd = working_dir
+        .join(&settings.output_dir)
+        .strip_prefix(working_dir.join(toastfile_dir))
+        .ok()
+        .and_then(|path| UnixPathBuf::try_from(path.to_owned()).ok());
+
+    let missing = toastfile::missing_input_paths(
+        toastfile,
+        schedule,
+        output_dir_rsd.as_deref(),
+        |path_rsd| {
+            path_rsd
+                .try_as_ref()
+                .is_some_and(|path: &Path| fs::symlink_metadata(toastfile_dir.join(path)).is_ok())
+        },
+    );
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let count = missing.iter().map(|(_, paths)| paths.len()).sum::<usize>();
+    Err(Failure::User(
+        format!(
+            "{}:\n{}\nIf {} created some other way, use {}.",
+            if count == 1 {
+                "An input path doesn't exist"
+            } else {
+                "Some input paths don't exist"
+            },
+            missing
+                .iter()
+                .map(|(task, paths)| {
+                    format!(
+                        "- Task {}: {}",
+                        task.code_str(),
+                        format::series(
+                            &paths
+                                .iter()
+                                .map(|path| path.to_string_lossy().code_str().to_string())
+                                .collect::<Vec<_>>(),
+                        ),
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            if count == 1 { "it's" } else { "they're" },
+            format!("--{ALLOW_MISSING_INPUTS_OPTION}").code_str(),
+        ),
+        None,
+    ))
+}
+
+// Fetch all the environment variables used by the tasks in the schedule.
+fn fetch_environment(
+    schedule: &[&str],
+    roots: &[&str],
+    tasks: &BTreeMap<String, toastfile::Task>,
+    overrides: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, Failure> {
+    let mut env = HashMap::new();
+    let mut violations = HashMap::new();
+
+    for task in schedule {
+        match toastfile::environment(&tasks[*task], overrides) {
+            // [ref:tasks_valid]
+            Ok(env_for_task) => {
+                // Make sure the values of the secret variables never show up in the logs
+                // [ref:secrets_masked].
+                for variable in &tasks[*task].secret_variables {
+                    if let Some(value) = env_for_task.get(variable) {
+                        secrets::register(value);
+                    }
+                }
+
+                env.extend(env_for_task);
+            }
+            Err(toastfile::EnvironmentError::Missing(vars)) => {
+                violations.insert((*task).to_owned(), vars);
+            }
+            Err(toastfile::EnvironmentError::Interpolation(reason)) => {
+                return Err(Failure::User(
+                    format!(
+                        "Unable to determine the environment for task {}. {}",
+                        task.code_str(),
+                        reason,
+                    ),
+                    None,
+                ));
+            }
+        }
+    }
+
+    // A variable given on the command line which no task uses is probably a typo.
+    let mut unused = overrides
+        .keys()
+        .filter(|variable| {
+            !schedule
+                .iter()
+                .any(|task| tasks[*task].environment.contains_key(*variable)) // [ref:tasks_valid]
+        })
+        .collect::<Vec<_>>();
+    unused.sort_unstable();
+    for variable in unused {
+        warn!(
+            "None of the tasks use the environment variable {} from {}.",
+            variable.code_str(),
+            format!("--{ENV_OPTION}").code_str(),
+        );
+    }
+
+    if !violations.is_empty() {
+        // [tag:environment_valid]
+        return Err(Failure::User(
+            toastfile::missing_variables_message(tasks, &violations, schedule, roots, |variable| {
+                env::var_os(variable)
+            }),
+            None,
+        ));
+    }
+
+    Ok(env)
+}
+
+// Run some tasks and return the final context and the last attempted task. The returned context
+// should not be `None` if `need_context` is `true`.
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::too_many_lines)]
+fn run_tasks(
+    schedule: &[&str],
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+    environment: &HashMap<String, String>,
+    skipped: &SkippedTasks,
+    need_context: bool,
+    interrupted: &Arc<AtomicBool>,
+    active_containers: &Arc<Mutex<HashMap<String, String>>>,
+    background_pull: &mut Option<docker::BackgroundPull>,
+    reports: &mut Vec<(String, runner::Report)>,
+) -> (Result<(), Failure>, Option<runner::Context>, Option<String>) {
+    // Determine which tasks can use the cache. Once a task can't (e.g., because it has
+    // `cache: false` or was given by `--force`), none of the tasks after it can either.
+    let caching = schedule::caching(
+        toastfile,
+        schedule,
+        &settings.forced_tasks,
+        settings.caching_disabled,
+    );
+
+    // We start with the base image.
+    let mut context = Some(runner::Context {
+        image: toastfile.image.clone(),
+        persist: true,
+        interrupted: interrupted.clone(),
+        cleanup: settings.cleanup.clone(),
+    });
+
+    // Run each task in the schedule.
+    for (i, task_name) in schedule.iter().enumerate() {
+        // Fetch the data for the current task.
+        let task_data = &toastfile.tasks[*task_name]; // [ref:tasks_valid]
+
+        // If the user paused the schedule, wait until they resume it.
+        pause::wait_while_paused(interrupted);
+
+        // If the user wants to stop the schedule, quit now.
+        if interrupted.load(Ordering::SeqCst) {
+            return (
+                Err(Failure::Interrupted),
+                context,
+                Some((*task_name).to_owned()),
+            );
+        }
+
+        // Determine the architecture of the Docker daemon for the cache key, if the task needs it.
+        let architecture = match cache_key_architecture(task_data, settings, interrupted) {
+            Ok(architecture) => architecture,
+            Err(e) => return (Err(e), context, Some((*task_name).to_owned())),
+        };
+
+        // A task with its own image starts from a fresh container rather than the result of the
+        // previous task, so its cache key is based on its own image [tag:task_image_fresh_context].
+        // The schedule still runs it after its dependencies. With `--no-deps`, a task can also
+        // start from the cached image of a task which was skipped.
+        if let Some(image) = task_data
+            .image
+            .as_ref()
+            .or_else(|| skipped.starting_images.get(*task_name))
+        {
+            context = Some(runner::Context {
+                image: image.clone(),
+                persist: true,
+                interrupted: interrupted.clone(),
+                cleanup: settings.cleanup.clone(),
+            });
+        }
+
+        // Tell the task about the images produced by its dependencies, including the skipped ones.
+        let mut images = skipped.images.clone();
+        images.extend(reports.iter().filter_map(|(name, report)| {
+            report
+                .image
+                .as_ref()
+                .map(|image| (name.clone(), image.clone()))
+        }));
+        let image_environment = runner::image_environment(toastfile, task_name, &images);
+
+        // If the user wants to check whether this task is deterministic, run it an extra time first.
+        // The extra run gets a copy of the context which doesn't own the image, so the image is
+        // still there for the real run.
+        let verify_determinism = settings.verify_determinism.as_deref() == Some(*task_name);
+        let mut first_report = runner::Report::default();
+        if verify_determinism {
+            info!(
+                "Running task {} for the first of two times\u{2026}",
+                task_name.code_str(),
+            );
+            let task_span = settings.trace.span(task_name, trace::TASK);
+            let (result, _) = runner::run(
+                settings,
+                environment,
+                interrupted,
+                active_containers,
+                toastfile,
+                task_name,
+                task_data,
+                false,
+                settings.force_all && (i == 0 || task_data.image.is_some()),
+                &architecture,
+                runner::Context {
+                    image: context.as_ref().unwrap().image.clone(), // [ref:context_needed_if_not_final_task]
+                    persist: true,
+                    interrupted: interrupted.clone(),
+                    cleanup: settings.cleanup.clone(),
+                },
+                false,
+                &image_environment,
+                background_pull,
+                true,
+                &mut first_report,
+            );
+            task_span.finish(result.is_ok());
+            if let Err(e) = result {
+                return (
+                    Err(runner::describe_failure(
+                        task_name,
+                        first_report.failed_phase,
+                        e,
+                    )),
+                    context,
+                    Some((*task_name).to_owned()),
+                );
+            }
+        }
+
+        // Let the user know how much of the time limit is left, if there is one.
+        if let Some(time_limit) = settings.time_limit {
+            info!(
+                "{} left before the time limit.",
+                units::format_duration(time_limit.remaining(Instant::now())),
+            );
+        }
+
+        // Run the task.
+        info!("Running task {}\u{2026}", task_name.code_str());
+        let task_span = settings.trace.span(task_name, trace::TASK);
+        let mut report = runner::Report::default();
+        let (result, new_context) = runner::run(
+            settings,
+            environment,
+            interrupted,
+            active_containers,
+            toastfile,
+            task_name,
+            task_data,
+            caching[i],
+            settings.force_all && (i == 0 || task_data.image.is_some()),
+            &architecture,
+            context.unwrap(), // Safe due to [ref:context_needed_if_not_final_task].
+            need_context || i != schedule.len() - 1, // [tag:context_needed_if_not_final_task]
+            &image_environment,
+            background_pull,
+            verify_determinism,
+            &mut report,
+        );
+        task_span.finish(result.is_ok());
+        let result =
+            result.map_err(|e| runner::describe_failure(task_name, report.failed_phase, e));
+
+        // Show what the task changed in the container before it failed, if requested. The full list
+        // is logged for debugging.
+        if let (Some(limit), Some(diff)) = (settings.diff_limit, &report.diff) {
+            info!(
+                "Task {} changed {} before it failed:{}",
+                task_name.code_str(),
+                format::number(diff.len(), "path"),
+                docker::describe_diff(diff, limit),
+            );
+            debug!(
+                "All the paths task {} changed:{}",
+                task_name.code_str(),
+                docker::describe_diff(diff, usize::MAX),
+            );
+        }
+
+        // Report the resources used by the task, if they were measured.
+        if let Some(usage) = &report.usage {
+            info!(
+                "Task {} used {}.",
+                task_name.code_str(),
+                stats::format_usage(usage),
+            );
+        }
+        reports.push(((*task_name).to_owned(), report));
+
+        // Compare the two runs of the task, if it was checked for determinism.
+        let result = result.and_then(|()| {
+            if verify_determinism {
+                let paths = determinism::compare(
+                    first_report
+                        .fingerprint
+                        .as_ref()
+                        .unwrap_or(&BTreeMap::new()),
+                    reports
+                        .last()
+                        .and_then(|(_, report)| report.fingerprint.as_ref())
+                        .unwrap_or(&BTreeMap::new()),
+                );
+                if !paths.is_empty() {
+                    return Err(Failure::User(determinism::report(task_name, &paths), None));
+                }
+                info!(
+                    "Task {} is deterministic. Both runs made the same changes to the filesystem.",
+                    task_name.code_str(),
+                );
+            }
+            Ok(())
+        });
+
+        // Remember the context for the next task, if there is one.
+        context = new_context;
+
+        // Return an error if the task failed.
+        if let Err(e) = result {
+            return (Err(e), context, Some((*task_name).to_owned()));
+        }
+    }
+
+    // Everything succeeded.
+    (
+        Ok(()),
+        context,
+        schedule.last().map(|task_name| (*task_name).to_owned()),
+    )
+}
+
+// What happened during a run, for the summary at the end and the shell afterward
+#[derive(Default)]
+struct RunRecord {
+    // The number of tasks in the schedule, once it's known
+    tasks: usize,
+
+    // The report of each task which was run or found in the cache, in order
+    reports: Vec<(String, runner::Report)>,
+
+    // Whether the outputs were already up to date, so no task needed to be checked
+    up_to_date: bool,
+
+    // The last task which was run or found in the cache, or the one which failed
+    last_task: Option<String>,
+
+    // The context after the last task, if the tasks ran
+    context: Option<runner::Context>,
+
+    // The environment variables used by the tasks
+    environment: HashMap<String, String>,
+}
+
+// Summarize a run in one line, whether or not it succeeded.
+fn summary(
+    record: &RunRecord,
+    result: &Result<(), Failure>,
+    elapsed: Duration,
+    toastfile_hash: &str,
+) -> String {
+    if record.up_to_date {
+        return format::summary(record.tasks, record.tasks, 0, &[], elapsed, toastfile_hash);
+    }
+
+    // If no task was reached, the failure was in something else (e.g., a missing input file).
+    if result.is_err() && record.last_task.is_none() {
+        return format::early_failure_summary(record.tasks, elapsed, toastfile_hash);
+    }
+
+    let failed_tasks = match (result, &record.last_task) {
+        (Err(_), Some(last_task)) => vec![last_task.clone()],
+        _ => vec![],
+    };
+
+    // Say which phase each failed task was in, if it's known.
+    let failures = failed_tasks
+        .iter()
+        .map(|task_name| {
+            match record
+                .reports
+                .iter()
+                .rev()
+                .find(|(name, _)| name == task_name)
+                .and_then(|(_, report)| report.failed_phase)
+            {
+                Some(phase) => format!("{task_name} during {phase}"),
+                None => task_name.clone(),
+            }
+        })
+        .collect::<Vec<_>>();
+    let count = |cached: bool| {
+        record
+            .reports
+            .iter()
+            .filter(|(task_name, report)| {
+                report.cached == cached && !failed_tasks.contains(task_name)
+            })
+            .count()
+    };
+
+    format::summary(
+        record.tasks,
+        count(true),
+        count(false),
+        &failures,
+        elapsed,
+        toastfile_hash,
+    )
+}
+
+// Run the tasks the user asked for, recording what happened for the summary. This covers everything
+// from computing the schedule to recording the outputs afterward, except dropping into a shell.
+#[allow(clippy::too_many_lines)]
+fn run_schedule(
+    settings: &Settings,
+    toastfile: &toastfile::Toastfile,
+    start: Instant,
+    interrupted: &Arc<AtomicBool>,
+    active_containers: &Arc<Mutex<HashMap<String, String>>>,
+    record: &mut RunRecord,
+) -> Result<(), Failure> {
+    // Determine which tasks the user wants to run.
+    let root_tasks = get_roots(settings, toastfile)?;
+
+    // Compute a schedule of tasks to run.
+    let plan = schedule::plan(toastfile, &root_tasks);
+    let schedule = plan.order();
+    record.tasks = schedule.len();
+    for step in &plan.steps {
+        debug!(
+            "Task {} is at depth {} and is needed for {}.",
+            step.task.code_str(),
+            step.depth,
+            format::series(
+                &step
+                    .roots
+                    .iter()
+                    .map(|root| root.code_str().to_string())
+                    .collect::<Vec<_>>(),
+            ),
+        );
+    }
+
+    // Report mounts which hide files a dependency put in the container. This is an error if the
+    // user asked for that, as with the mounts which hide a task's own input files.
+    let shadowing_warnings = toastfile::dependency_shadowing_warnings(toastfile, &schedule);
+    if settings.strict_mount_paths && !shadowing_warnings.is_empty() {
+        return Err(Failure::User(
+            format!(
+                "Unable to use file {}. {}",
+                settings.toastfile_path.to_string_lossy().code_str(),
+                shadowing_warnings.join(" "),
+            ),
+            None,
+        ));
+    }
+    for warning in shadowing_warnings {
+        warn!("{}", warning);
+    }
+
+    if !schedule.is_empty() {
+        info!(
+            "Ready to run {}: {}.",
+            format::number(schedule.len(), "task"),
+            format::series(
+                schedule
+                    .iter()
+                    .map(|task| task.code_str().to_string())
+                    .collect::<Vec<_>>()
+                    .as_ref(),
+            ),
+        );
+    }
+
+    // Make it clear that nothing will come from the caches [ref:force_all_caching].
+    if settings.force_all && !schedule.is_empty() {
+        if settings.caching_disabled {
+            info!(
+                "Caching is disabled for this run because of {}.",
+                "--force-all".code_str(),
+            );
+        } else {
+            info!(
+                "Reading from the cache is disabled for this run because of {}, but the images \
+                 will still be written to it.",
+                "--force-all".code_str(),
+            );
+        }
+    }
+
+    // Make sure no task builds on an image from a different container engine.
+    schedule::check_docker_clis(toastfile, &schedule, &settings.docker_cli)?;
+
+    // Give the Docker daemon a chance to start before anything talks to it, if requested.
+    if let Some(timeout) = settings.wait_for_docker {
+        docker::wait_for_daemon(&settings.docker_cli, timeout, interrupted)?;
     }
-}
 
-// Fetch all the environment variables used by the tasks in the schedule.
-fn fetch_environment(
-    schedule: &[&str],
-    tasks: &HashMap<String, toastfile::Task>,
-) -> Result<HashMap<String, String>, Failure> {
-    let mut env = HashMap::new();
-    let mut violations = HashMap::new();
+    // Start pulling the base image of the first task now, if requested, so the pull overlaps with
+    // checking the input files and computing the cache keys. This is skipped if nothing will run or
+    // if the image digest will be resolved, which pulls the image anyway. If the pull turns out not
+    // to be needed (e.g., because the tasks are cached), it's cancelled when it's dropped.
+    let mut background_pull = (settings.interleave_pull
+        && !schedule.is_empty()
+        && !(settings.dry_run || settings.assert_cached || settings.only_cached)
+        && !(settings.resolve_image_digest || toastfile.resolve_image_digest))
+        .then(|| {
+            let first_task = &toastfile.tasks[schedule[0]]; // [ref:tasks_valid]
+            docker::BackgroundPull::start(
+                toastfile::docker_cli(first_task, &settings.docker_cli),
+                &toastfile::base_image(toastfile, first_task),
+                &settings.extra_pull_arguments,
+                settings.force_all,
+                interrupted,
+            )
+        });
 
-    for task in schedule {
-        match toastfile::environment(&tasks[*task]) {
-            // [ref:tasks_valid]
-            Ok(env_for_task) => {
-                env.extend(env_for_task);
-            }
-            Err(vars) => {
-                violations.insert((*task).to_owned(), vars);
+    // Make sure the input files exist, so the user finds out about all the missing ones before
+    // anything runs. Some may be created some other way, so the user can skip this.
+    if !settings.allow_missing_inputs {
+        check_input_paths(settings, toastfile, &schedule)?;
+    }
+
+    // Fetch all the environment variables used by the tasks in the schedule.
+    let environment = fetch_environment(
+        &schedule,
+        &root_tasks,
+        &toastfile.tasks,
+        &settings.environment_overrides,
+    )?;
+
+    // Tie the cache keys to the contents of the base image rather than its tag, if requested. This
+    // doesn't pull anything if the user just wants to know what would happen. The schedule borrows
+    // the task names from the toastfile, so the resolved image goes into a copy of it.
+    let resolved_toastfile;
+    let toastfile = if settings.resolve_image_digest || toastfile.resolve_image_digest {
+        match resolve_image_digest(
+            &schedule,
+            settings,
+            toastfile,
+            !(settings.dry_run || settings.assert_cached || settings.only_cached),
+            interrupted,
+        )? {
+            Some(image) => {
+                resolved_toastfile = toastfile::Toastfile {
+                    image,
+                    ..toastfile.clone()
+                };
+                &resolved_toastfile
             }
+            None => toastfile,
         }
+    } else {
+        toastfile
+    };
+
+    // If the user just wants to know what would happen, report that and quit.
+    if settings.dry_run {
+        return dry_run(&schedule, settings, toastfile, &environment, interrupted);
     }
 
-    if !violations.is_empty() {
-        // [tag:environment_valid]
-        return Err(Failure::User(
-            format!(
-                "The following tasks use variables which are missing from the environment: {}.",
-                format::series(
-                    violations
-                        .iter()
-                        .map(|(task_name, vars)| {
-                            format!(
-                                "{} ({})",
-                                task_name.code_str(),
-                                format::series(
-                                    vars.iter()
-                                        .map(|var| format!("{}", var.code_str()))
-                                        .collect::<Vec<_>>()
-                                        .as_ref(),
-                                ),
-                            )
-                        })
-                        .collect::<Vec<_>>()
-                        .as_ref(),
-                ),
-            ),
-            None,
-        ));
+    // If the user just wants to make sure everything is cached, check that and quit.
+    if settings.assert_cached {
+        return assert_cached(&schedule, settings, toastfile, &environment, interrupted);
     }
 
-    Ok(env)
-}
+    // If the user only wants to use the cache, make sure that's possible. Unless the output files
+    // should be extracted, that's all there is to do.
+    if settings.only_cached
+        && !only_cached(&schedule, settings, toastfile, &environment, interrupted)?
+    {
+        return Ok(());
+    }
 
-// Run some tasks and return the final context and the last attempted task. The returned context
-// should not be `None` if `need_context` is `true`.
-#[allow(clippy::too_many_arguments)]
-fn run_tasks(
-    schedule: &[&str],
-    settings: &Settings,
-    toastfile: &toastfile::Toastfile,
-    environment: &HashMap<String, String>,
-    need_context: bool,
-    interrupted: &Arc<AtomicBool>,
-    active_containers: &Arc<Mutex<HashSet<String>>>,
-) -> (Result<(), Failure>, Option<runner::Context>, Option<String>) {
-    // This variable will be `true` as long as we're executing tasks that have `cache: true`. As
-    // soon as we encounter a task with `cache: false`, this variable will be permanently set to
-    // `false`. If the user provided the `--force-image-pull` flag, this variable will always be
-    // `false`.
-    let mut caching_enabled = !settings.force_all;
+    // If the schedule is fully cached and its outputs are already on the host, there's nothing to
+    // do. Otherwise, the outputs will be recorded after a successful run.
+    let outputs_recordable = outputs_recordable(&schedule, settings, toastfile);
+    if outputs_recordable
+        && outputs_up_to_date(&schedule, settings, toastfile, &environment, interrupted)?
+    {
+        info!("Everything up to date.");
+        record.up_to_date = true;
+        settings
+            .trace
+            .progress()
+            .run_finished(start.elapsed(), true);
+        return Ok(());
+    }
 
-    // We start with the base image.
-    let mut context = Some(runner::Context {
-        image: toastfile.image.clone(),
-        persist: true,
-        interrupted: interrupted.clone(),
-        docker_cli: settings.docker_cli.clone(),
-    });
+    // Warn if the remote cache would be written to Docker Hub without the user saying so.
+    if settings.write_remote_cache && !settings.confirm_public_push {
+        let docker_repos = schedule
+            .iter()
+            .zip(schedule::caching(
+                toastfile,
+                &schedule,
+                &settings.forced_tasks,
+                settings.caching_disabled,
+            ))
+            .filter(|(_, enabled)| *enabled)
+            .map(|(task_name, _)| {
+                toastfile::docker_repo(
+                    &toastfile.tasks[*task_name], // [ref:tasks_valid]
+                    &settings.docker_repo,
+                )
+            })
+            .collect::<Vec<_>>();
 
-    // Run each task in the schedule.
-    for (i, task_name) in schedule.iter().enumerate() {
-        // Fetch the data for the current task.
-        let task_data = &toastfile.tasks[*task_name]; // [ref:tasks_valid]
+        if let Some(warning) = registry::public_push_warning(&docker_repos) {
+            warn!("{}", warning);
+        }
+    }
 
-        // If the current task is not cacheable, don't read or write to any form of cache from now
-        // on.
-        caching_enabled = caching_enabled
-            && task_data.cache
-            && !settings
-                .forced_tasks
-                .iter()
-                .any(|forced_task| task_name == forced_task);
+    // Log in to the registries of the repositories the tasks use for the remote cache, if any, with
+    // the Docker CLI of each task.
+    if let Some(login) = &settings.registry_login {
+        if settings.read_remote_cache || settings.write_remote_cache {
+            let mut docker_repos = HashMap::<&str, Vec<&str>>::new();
+            for (task_name, enabled) in schedule.iter().zip(schedule::caching(
+                toastfile,
+                &schedule,
+                &settings.forced_tasks,
+                settings.caching_disabled,
+            )) {
+                if enabled {
+                    let task_data = &toastfile.tasks[*task_name]; // [ref:tasks_valid]
+                    docker_repos
+                        .entry(toastfile::docker_cli(task_data, &settings.docker_cli))
+                        .or_default()
+                        .push(toastfile::docker_repo(task_data, &settings.docker_repo));
+                }
+            }
 
-        // If the user wants to stop the schedule, quit now.
-        if interrupted.load(Ordering::SeqCst) {
-            return (
-                Err(Failure::Interrupted),
-                context,
-                Some((*task_name).to_owned()),
-            );
+            let mut docker_clis = docker_repos.into_iter().collect::<Vec<_>>();
+            docker_clis.sort_unstable();
+            for (docker_cli, docker_repos) in docker_clis {
+                registry::login(docker_cli, login, &docker_repos, interrupted)?;
+            }
         }
+    }
 
-        // Run the task.
-        info!("Running task {}\u{2026}", task_name.code_str());
-        let (result, new_context) = runner::run(
+    // Leave out the dependencies of the root tasks, if requested. From now on, the schedule only
+    // has the tasks which will run.
+    let (schedule, skipped) = if settings.no_deps {
+        skip_dependencies(
+            &schedule,
+            &root_tasks,
             settings,
-            environment,
-            interrupted,
-            active_containers,
             toastfile,
-            task_data,
-            caching_enabled,
-            settings.force_all && i == 0,
-            context.unwrap(), // Safe due to [ref:context_needed_if_not_final_task].
-            need_context || i != schedule.len() - 1, // [tag:context_needed_if_not_final_task]
+            &environment,
+            interrupted,
+        )?
+    } else {
+        (schedule, SkippedTasks::default())
+    };
+    record.tasks = schedule.len();
+    let skipped_tasks = plan
+        .order()
+        .into_iter()
+        .filter(|task_name| !schedule.contains(task_name))
+        .map(|task_name| task_name.code_str().to_string())
+        .collect::<Vec<_>>();
+    if !skipped_tasks.is_empty() {
+        info!(
+            "Skipping {} because of {}: {}.",
+            format::number(skipped_tasks.len(), "task"),
+            "--no-deps".code_str(),
+            format::series(&skipped_tasks),
         );
+    }
 
-        // Remember the context for the next task, if there is one.
-        context = new_context;
+    // With `--no-deps`, the first task which runs might not start from the image being pulled
+    // (e.g., if it starts from the image of a skipped dependency), so the pull isn't needed.
+    let first_image = schedule
+        .first()
+        .filter(|task_name| !skipped.starting_images.contains_key(**task_name))
+        .map(|task_name| {
+            toastfile::base_image(toastfile, &toastfile.tasks[*task_name]) // [ref:tasks_valid]
+        });
+    if let Some(pull) = background_pull.take() {
+        if first_image.as_deref() == Some(pull.image()) {
+            background_pull = Some(pull);
+        } else {
+            pull.cancel();
+        }
+    }
 
-        // Return an error if the task failed.
-        if let Err(e) = result {
-            return (Err(e), context, Some((*task_name).to_owned()));
+    // Execute the schedule.
+    let (result, context, last_task) = run_tasks(
+        &schedule,
+        settings,
+        toastfile,
+        &environment,
+        &skipped,
+        settings.spawn_shell, // [tag:spawn_shell_requires_context]
+        interrupted,
+        active_containers,
+        &mut background_pull,
+        &mut record.reports,
+    );
+    record.context = context;
+    record.last_task = last_task;
+
+    // If the base image turned out not to be needed (e.g., because the tasks were cached), stop
+    // pulling it.
+    if let Some(background_pull) = background_pull {
+        background_pull.cancel();
+    }
+
+    // Report the end of the run to whatever is reading the progress events, if anything.
+    settings
+        .trace
+        .progress()
+        .run_finished(start.elapsed(), result.is_ok());
+
+    // Write the timeline of the run, if requested.
+    if let Some(trace_file) = &settings.trace_file {
+        if let Err(e) = settings.trace.write(trace_file) {
+            warn!("{}", e);
         }
     }
 
-    // Everything succeeded.
-    (
-        Ok(()),
-        context,
-        schedule.last().map(|task_name| (*task_name).to_owned()),
-    )
+    // Summarize the resources used by each task, if requested.
+    if record
+        .reports
+        .iter()
+        .any(|(_, report)| report.usage.is_some())
+    {
+        info!("Resource usage by task:");
+        for (task_name, report) in &record.reports {
+            if let Some(usage) = &report.usage {
+                info!("  {}: {}", task_name.code_str(), stats::format_usage(usage));
+            }
+        }
+    }
+
+    // Show which image each task produced.
+    if record
+        .reports
+        .iter()
+        .any(|(_, report)| report.image.is_some())
+    {
+        info!("Images by task:");
+        for (task_name, report) in &record.reports {
+            if let Some(image) = &report.image {
+                info!(
+                    "  {} ({}): {}",
+                    task_name.code_str(),
+                    runner::image_env_var(task_name).code_str(),
+                    image.code_str(),
+                );
+            }
+        }
+    }
+
+    // Remember the extracted outputs, so a future run can tell they're already up to date. The key
+    // is based on the cache keys the runner computed, along with the architectures it used.
+    if result.is_ok() && outputs_recordable {
+        let key = outputs_key(
+            record
+                .reports
+                .last()
+                .and_then(|(_, report)| report.cache_key.as_deref())
+                .unwrap_or(&toastfile.image),
+            settings,
+        );
+        let architectures = {
+            // The `unwrap` will only fail if a panic already occurred.
+            let architectures = settings.architectures.lock().unwrap();
+            schedule
+                .iter()
+                .map(|task_name| &toastfile.tasks[*task_name]) // [ref:tasks_valid]
+                .filter(|task_data| !task_data.arch_independent_cache)
+                .filter_map(|task_data| {
+                    let docker_cli = toastfile::docker_cli(task_data, &settings.docker_cli);
+                    architectures
+                        .get(docker_cli)
+                        .map(|architecture| (docker_cli.to_owned(), architecture.clone()))
+                })
+                .collect()
+        };
+        if let Err(e) =
+            output_destinations(&schedule, settings, toastfile).and_then(|destinations| {
+                outputs::record(&settings.toastfile_path, &key, architectures, &destinations)
+            })
+        {
+            warn!("{}", e);
+        }
+    }
+
+    // Remember how much the cache helped, for `toast cache report`.
+    if !record.reports.is_empty() {
+        if let Err(e) = metrics::record(&record.reports) {
+            warn!("{}", e);
+        }
+    }
+
+    // The shell needs the environment variables too.
+    record.environment = environment;
+
+    result
 }
 
 // Program entrypoint
@@ -624,95 +3183,213 @@ fn entry() -> Result<(), Failure> {
 
     // Set up global mutable state (yum!).
     let interrupted = Arc::new(AtomicBool::new(false));
-    let active_containers = Arc::new(Mutex::new(HashSet::<String>::new()));
+    let active_containers = Arc::new(Mutex::new(HashMap::<String, String>::new()));
 
     // Parse the command-line arguments;
     let settings = settings()?;
 
+    // Let the user know if they're missing out on a newer release, if they asked to be told.
+    if settings.version_check {
+        update::check(VERSION);
+    }
+
+    // If the user just wants the cache report, print it and quit.
+    if let Some(runs) = settings.cache_report {
+        check_command_tasks(
+            &settings.toastfile_path,
+            None,
+            &CACHE_REPORT_COMMAND,
+            "toast report cache",
+        )?;
+        println!("{}", metrics::report(&metrics::recent_runs(runs)));
+        return Ok(());
+    }
+
+    // If the user just wants to remove the saved data, do that and quit.
+    if let Some(all) = settings.state_clear {
+        check_command_tasks(
+            &settings.toastfile_path,
+            None,
+            &STATE_CLEAR_COMMAND,
+            "toast clear state",
+        )?;
+        return clear_state(&settings, all);
+    }
+
+    // Keep track of how long the run takes, for the summary.
+    let start = Instant::now();
+
+    // Make sure the user doesn't forget they disabled cleanup. When we're done (successfully or
+    // not), tell them what was left behind. This is declared before any contexts so that it runs
+    // after they're dropped.
+    if !settings.cleanup.enabled() {
+        warn!(
+            "Cleanup is disabled by {}. Containers and images will be left behind.",
+            format!("--{NO_CLEANUP_OPTION}").code_str(),
+        );
+    }
+    let cleanup = settings.cleanup.clone();
+    defer! {{
+        cleanup.report();
+    }}
+
     // Set up the signal handlers.
     set_up_signal_handlers(
-        settings.docker_cli.clone(),
+        settings.cleanup.clone(),
         interrupted.clone(),
         active_containers.clone(),
     )?;
 
-    // Parse the toastfile.
-    let toastfile = parse_toastfile(&settings.toastfile_path)?;
-
-    // If the user just wants to list all the tasks, do that and quit.
-    if settings.list {
-        info!("Here are the tasks that have a description:");
-
-        // Select the names of the tasks that have a description [tag:tasks_have_descriptions].
-        let mut task_names = toastfile
-            .tasks
-            .iter()
-            .filter(|(_, t)| t.description.is_some())
-            .map(|(k, _)| k)
-            .collect::<Vec<_>>();
+    // Interrupt the run if it takes longer than the time limit, just as if the user had pressed
+    // CTRL+C. The tasks running at the time are stopped, since they wouldn't get the signal.
+    if let Some(time_limit) = settings.time_limit {
+        let cleanup = settings.cleanup.clone();
+        let active_containers = active_containers.clone();
+        let watchdog_interrupted = interrupted.clone();
+        time_limit.watch(interrupted.clone(), move || {
+            pause::handle(
+                pause::Request::Resume,
+                &active_containers,
+                &watchdog_interrupted,
+            );
+            stop_active_containers(&cleanup, &active_containers, &watchdog_interrupted);
+        });
+    }
 
-        // Sort the names to avoid relying on the unpredictable order of the tasks in the map.
-        task_names.sort();
+    // Parse the toastfile.
+    let (mut toastfile, toastfile_hash) = parse_toastfile(
+        &settings.toastfile_path,
+        settings.strict_mount_paths,
+        settings.require_pinned_image,
+    )?;
 
-        // Print a summary of each task.
-        for task_name in task_names {
-            // Fetch the task data.
-            let task_data = &toastfile.tasks[task_name];
+    // If the user wants to remove the images in the cache, do that and quit.
+    if settings.prune {
+        check_command_tasks(
+            &settings.toastfile_path,
+            Some(&toastfile),
+            &PRUNE_COMMAND,
+            "toast prune prune",
+        )?;
+        return prune_images(&settings, &toastfile, &interrupted);
+    }
 
-            // Print the task name and the description. The `unwrap` is safe due to
-            // [ref:tasks_have_descriptions].
-            println!(
-                "* {} \u{2014} {}",
-                task_name.code_str(),
-                task_data.description.as_ref().unwrap(),
-            );
+    // If the user wants to run a one-off command, it replaces the tasks in the toastfile. That way,
+    // it can't affect them (e.g., their cache keys), but it still uses the top-level settings.
+    if let Some(task) = &settings.ad_hoc_task {
+        let mut task = task.clone();
+        toastfile::apply_shared_environment(
+            &toastfile.environment,
+            &toastfile.secret_variables,
+            &mut task,
+        );
+        toastfile.default = None;
+        toastfile.tasks = BTreeMap::from([(toastfile::AD_HOC_TASK.to_owned(), task)]);
+
+        // The task also gets the values from the top-level `env_file`.
+        toastfile::load_env_files(
+            &mut toastfile,
+            settings
+                .toastfile_path
+                .parent()
+                .unwrap_or_else(|| Path::new("")),
+        )?;
+    }
 
-            // Print the environment variables that can be passed to the task.
-            for (variable, optional_default) in &task_data.environment {
-                if let Some(default) = optional_default {
-                    println!("  {}: {}", variable.code_str(), default.code_str());
-                } else {
-                    println!("  {}: (no default provided)", variable.code_str());
-                }
+    // If the user just wants to list all the tasks, do that and quit.
+    if settings.list {
+        let listing = list::render(
+            &toastfile,
+            settings.list_filter.as_deref(),
+            settings.list_tree,
+        )?;
+        if listing.is_empty() {
+            if let Some(filter) = &settings.list_filter {
+                info!(
+                    "There are no tasks that have a description and match {}.",
+                    filter.code_str(),
+                );
+            } else {
+                info!("There are no tasks that have a description.");
             }
+        } else {
+            info!("Here are the tasks that have a description:");
+            print!("{listing}");
         }
 
         // The user just wanted to list the tasks. We're done.
         return Ok(());
     }
 
-    // Determine which tasks the user wants to run.
-    let root_tasks = get_roots(&settings, &toastfile)?;
-
-    // Compute a schedule of tasks to run.
-    let schedule = schedule::compute(&toastfile, &root_tasks);
-    if !schedule.is_empty() {
-        info!(
-            "Ready to run {}: {}.",
-            format::number(schedule.len(), "task"),
-            format::series(
-                schedule
-                    .iter()
-                    .map(|task| task.code_str().to_string())
-                    .collect::<Vec<_>>()
-                    .as_ref(),
-            ),
+    // If the user just wants the dependency graph, print it and quit. If no tasks were given, the
+    // graph includes all of them rather than just the default.
+    if settings.graph {
+        let roots = if settings.tasks.is_some() {
+            get_roots(&settings, &toastfile)?
+        } else {
+            toastfile.tasks.keys().map(AsRef::as_ref).collect()
+        };
+        print!(
+            "{}",
+            graph::render(&toastfile, &schedule::plan(&toastfile, &roots)),
         );
+        return Ok(());
     }
 
-    // Fetch all the environment variables used by the tasks in the schedule.
-    let environment = fetch_environment(&schedule, &toastfile.tasks)?;
+    // If the user just wants an equivalent Dockerfile, print (or write) it and quit.
+    if settings.containerfile {
+        let roots = get_roots(&settings, &toastfile)?;
+        let containerfile =
+            containerfile::render(&toastfile, &schedule::compute(&toastfile, &roots));
+        if let Some(path) = &settings.containerfile_out {
+            fs::write(path, containerfile).map_err(failure::user(format!(
+                "Unable to write the Dockerfile to {}.",
+                path.to_string_lossy().code_str(),
+            )))?;
+        } else {
+            print!("{containerfile}");
+        }
+        return Ok(());
+    }
 
-    // Execute the schedule.
-    let (result, context, last_task) = run_tasks(
-        &schedule,
+    // If the user just wants the hash of a task definition, print it and quit.
+    if let Some(task_name) = &settings.hash_task {
+        let task_data = toastfile.tasks.get(task_name).ok_or_else(|| {
+            Failure::User(
+                format!(
+                    "No task named {} in {}.",
+                    task_name.code_str(),
+                    settings.toastfile_path.to_string_lossy().code_str(),
+                ),
+                None,
+            )
+        })?;
+        println!("{}", cache::task_hash(&toastfile, task_data));
+        return Ok(());
+    }
+
+    // If the user just wants to list where the output files of some tasks will go, do that and quit.
+    if !settings.list_outputs.is_empty() {
+        return list_outputs(&settings, &toastfile);
+    }
+
+    // Run the tasks. However that goes, end with a summary of the run (if it's wanted). Nothing
+    // runs with `--dry-run` or `--assert-cached`, so there's nothing to summarize.
+    let mut record = RunRecord::default();
+    let result = run_schedule(
         &settings,
         &toastfile,
-        &environment,
-        settings.spawn_shell, // [tag:spawn_shell_requires_context]
+        start,
         &interrupted,
         &active_containers,
+        &mut record,
     );
+    if settings.summary && !settings.dry_run && !settings.assert_cached {
+        println!(
+            "{}",
+            summary(&record, &result, start.elapsed(), &toastfile_hash),
+        );
+    }
 
     // Return early if needed.
     match result {
@@ -723,70 +3400,143 @@ fn entry() -> Result<(), Failure> {
             // There was an error not caused by a regular task failure. Quit now.
             return result;
         }
-    };
+    }
 
-    // Drop the user into a shell if requested.
+    // Drop the user into a shell if requested. If the run stopped before the tasks started (e.g.,
+    // because an input file is missing), there's nothing to drop into.
     if settings.spawn_shell {
+        let Some(context) = record.context else {
+            return result;
+        };
+
         // If one of the tasks failed, tell the user now before we drop into a shell.
         if let Err(e) = &result {
             error!("{}", e);
         }
 
+        // The shell uses the task settings from when the toastfile was parsed. If the toastfile has
+        // been edited since then, let the user know.
+        if let Err(e) = read_toastfile(&settings.toastfile_path)
+            .and_then(|(_, hash)| toastfile::check_content_hash(&toastfile_hash, &hash))
+        {
+            warn!(
+                "{} The shell will use the settings from when the run started.",
+                e
+            );
+        }
+
         // Inform the user of what's about to happen.
         info!("Preparing a shell\u{2026}");
 
-        // Determine the environment, location, mount settings, ports, and user for the shell.
-        let (task_environment, location, mount_paths, mount_readonly, ports, user, extra_args) =
-            if let Some(last_task) = last_task {
-                // Get the data for the last task.
-                let last_task = &toastfile.tasks[&last_task]; // [ref:tasks_valid]
-
-                // Prepare the environment.
-                let mut task_environment = HashMap::<String, String>::new();
-                for variable in last_task.environment.keys() {
-                    // [ref:environment_valid]
-                    task_environment.insert(variable.clone(), environment[variable].clone());
-                }
+        // Determine the environment, location, mount settings, volumes (along with the repository
+        // they're labeled with), ports, user, command prefix, and Docker CLI for the shell.
+        let (
+            mut task_environment,
+            location,
+            mount_paths,
+            mount_readonly,
+            volumes,
+            docker_repo,
+            ports,
+            gpus,
+            user,
+            command_prefix,
+            extra_args,
+            docker_cli,
+        ) = if let Some(last_task) = record.last_task {
+            // Get the data for the last task.
+            let last_task = &toastfile.tasks[&last_task]; // [ref:tasks_valid]
+
+            // Prepare the environment.
+            let mut task_environment = HashMap::<String, String>::new();
+            for variable in last_task.environment.keys() {
+                // [ref:environment_valid]
+                task_environment.insert(variable.clone(), record.environment[variable].clone());
+            }
 
-                // Use the settings from the last task.
-                (
-                    task_environment,
-                    location(&toastfile, last_task),
-                    last_task.mount_paths.clone(),
-                    last_task.mount_readonly,
-                    last_task.ports.clone(),
-                    user(&toastfile, last_task),
-                    last_task.extra_docker_arguments.clone(),
-                )
-            } else {
-                // There is no last task, so the context will be the base image. Use default
-                // settings.
-                (
-                    HashMap::default(),            // [ref:default_environment]
-                    UnixPath::new("/").to_owned(), // `toastfile::DEFAULT_LOCATION` might not exist.
-                    Vec::default(),                // [ref:default_mount_paths]
-                    default_task_mount_readonly(),
-                    Vec::default(), // [ref:default_ports]
-                    DEFAULT_USER.to_owned(),
-                    Vec::default(),
-                )
-            };
+            // Use the settings from the last task.
+            (
+                task_environment,
+                location(&toastfile, last_task),
+                last_task.mount_paths.clone(),
+                last_task.mount_readonly,
+                last_task.volumes.clone(),
+                toastfile::docker_repo(last_task, &settings.docker_repo).to_owned(),
+                last_task.ports.clone(),
+                toastfile::gpus(last_task),
+                user(&toastfile, last_task),
+                toastfile::command_prefix(&toastfile, last_task).to_owned(),
+                last_task.extra_docker_arguments.clone(),
+                toastfile::docker_cli(last_task, &settings.docker_cli).to_owned(),
+            )
+        } else {
+            // There is no last task, so the context will be the base image. Use default
+            // settings.
+            (
+                HashMap::default(),            // [ref:default_environment]
+                UnixPath::new("/").to_owned(), // `toastfile::DEFAULT_LOCATION` might not exist.
+                Vec::default(),                // [ref:default_mount_paths]
+                default_task_mount_readonly(),
+                Vec::default(), // [ref:default_volumes]
+                settings.docker_repo.clone(),
+                Vec::default(), // [ref:default_ports]
+                None,
+                DEFAULT_USER.to_owned(),
+                String::new(),
+                Vec::default(),
+                settings.docker_cli.clone(),
+            )
+        };
+
+        // Tell the shell how much parallelism is available, just like for tasks.
+        task_environment.insert(JOBS_ENV_VAR.to_owned(), settings.jobs.to_string());
 
         // All relative paths are relative to where the toastfile lives.
         let mut toastfile_dir = PathBuf::from(&settings.toastfile_path);
         toastfile_dir.pop();
 
+        // The shell normally runs in the image from the last task. With `--shell-image`, it runs in
+        // another image (pulled if necessary) with the same settings, but then only the mounts are
+        // there, not what the tasks wrote to the filesystem.
+        let context_image = &context.image; // [ref:spawn_shell_requires_context]
+        let image = if let Some(shell_image) = &settings.shell_image {
+            warn!(
+                "The shell runs in {} rather than the image from the last task, so it only has the \
+                 mounts and not the files the tasks wrote.",
+                shell_image.code_str(),
+            );
+            docker::ensure_image(
+                &*docker_cli,
+                shell_image,
+                docker::ImagePurpose::Shell,
+                docker::PullPolicy::IfMissing,
+                &settings.extra_pull_arguments,
+                &interrupted,
+            )?;
+            shell_image
+        } else {
+            context_image
+        };
+
         // Spawn the shell.
         docker::spawn_shell(
-            &settings.docker_cli,
-            &context.unwrap().image, // Safe due to [ref:spawn_shell_requires_context].
+            &*docker_cli,
+            image,
             &toastfile_dir,
             &task_environment,
             &location,
             &mount_paths,
             mount_readonly,
+            &volumes,
+            &docker_repo,
             &ports,
+            gpus.as_deref(),
             &user,
+            if toastfile.shell_applies_prefix {
+                &command_prefix
+            } else {
+                ""
+            },
             &extra_args,
             &interrupted,
         )?;
@@ -799,8 +3549,8 @@ fn entry() -> Result<(), Failure> {
 // Let the fun begin!
 fn main() {
     // Jump to the entrypoint and handle any resulting errors.
-    if let Err(e) = entry() {
+    if let Err(e) = entry().map_err(time_limit::explain) {
         error!("{}", e);
-        exit(1);
+        exit(e.exit_code());
     }
 }
@@ -0,0 +1,440 @@
+use {
+    crate::{failure, failure::Failure, format, format::CodeStr, paths, runner::Report, units},
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::BTreeMap,
+        fmt::Write as _,
+        fs::{create_dir_all, read_to_string, write},
+        path::PathBuf,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+};
+
+// Where the metrics are stored, relative to the state directory
+const STATE_FILE: &str = "metrics.yml";
+
+// How many runs to remember
+const MAX_RUNS: usize = 1000;
+
+// How many task durations to remember. When there are more, the least recently used are forgotten.
+const MAX_DURATIONS: usize = 10_000;
+
+// The cache metrics of a single run
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Run {
+    // When the run finished, in seconds since the Unix epoch
+    pub timestamp: u64,
+
+    // The number of tasks which actually ran
+    pub executed: usize,
+
+    // The number of tasks which were found in a cache
+    pub cached: usize,
+
+    // The total duration of the last real run of each cached task, in seconds
+    pub time_saved_secs: f64,
+
+    // The number of cached tasks which have never been seen to run, so the time they saved is unknown
+    pub unknown_time_saved: usize,
+
+    // The sizes of the images pulled from and pushed to the remote cache
+    pub bytes_pulled: u64,
+    pub bytes_pushed: u64,
+}
+
+// How long a task with a particular cache key took the last time it actually ran
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+struct Timing {
+    duration_secs: f64,
+    last_used: u64,
+}
+
+// Everything we remember across runs
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+struct State {
+    #[serde(default)]
+    runs: Vec<Run>,
+
+    #[serde(default)]
+    durations: BTreeMap<String, Timing>,
+}
+
+// Determine where the metrics are stored.
+fn state_path() -> Option<PathBuf> {
+    paths::state_dir().map(|dir| dir.join(STATE_FILE))
+}
+
+// Read the metrics from previous runs. Missing or unreadable metrics are treated as empty, since
+// they're only informational.
+fn load() -> State {
+    state_path()
+        .and_then(|state_path| read_to_string(state_path).ok())
+        .and_then(|contents| serde_yaml::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+// Incorporate the reports of a run into the state. `now` is in seconds since the Unix epoch.
+fn update(state: &mut State, reports: &[(String, Report)], now: u64) {
+    let mut run = Run {
+        timestamp: now,
+        ..Run::default()
+    };
+
+    for (_, report) in reports {
+        run.bytes_pulled += report.bytes_pulled;
+        run.bytes_pushed += report.bytes_pushed;
+
+        if report.cached {
+            run.cached += 1;
+
+            // A cache hit saved as much time as the task took the last time it actually ran.
+            match report
+                .cache_key
+                .as_ref()
+                .and_then(|key| state.durations.get_mut(key))
+            {
+                Some(timing) => {
+                    run.time_saved_secs += timing.duration_secs;
+                    timing.last_used = now;
+                }
+                None => run.unknown_time_saved += 1,
+            }
+        } else if let Some(duration) = report.duration {
+            run.executed += 1;
+
+            // Remember how long the task took, in case it's found in the cache later.
+            if let Some(key) = &report.cache_key {
+                state.durations.insert(
+                    key.clone(),
+                    Timing {
+                        duration_secs: duration.as_secs_f64(),
+                        last_used: now,
+                    },
+                );
+            }
+        } else {
+            // The task ran but didn't succeed.
+            run.executed += 1;
+        }
+    }
+
+    // Forget the oldest runs and the least recently used durations.
+    state.runs.push(run);
+    if state.runs.len() > MAX_RUNS {
+        state.runs.drain(..state.runs.len() - MAX_RUNS);
+    }
+    if state.durations.len() > MAX_DURATIONS {
+        let mut keys = state
+            .durations
+            .iter()
+            .map(|(key, timing)| (timing.last_used, key.clone()))
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        for (_, key) in keys.into_iter().take(state.durations.len() - MAX_DURATIONS) {
+            state.durations.remove(&key);
+        }
+    }
+}
+
+// Record the cache metrics of a run.
+pub fn record(reports: &[(String, Report)]) -> Result<(), Failure> {
+    // If there's no state directory on this platform, there's nowhere to record anything.
+    let Some(state_path) = state_path() else {
+        return Ok(());
+    };
+
+    let mut state = load();
+    update(
+        &mut state,
+        reports,
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    );
+
+    // The `unwrap` is safe because the state path was constructed inside a directory.
+    let state_dir = state_path.parent().unwrap();
+    create_dir_all(state_dir).map_err(failure::system(format!(
+        "Unable to create directory {}.",
+        state_dir.to_string_lossy().code_str(),
+    )))?;
+
+    write(
+        &state_path,
+        serde_yaml::to_string(&state)
+            .map_err(failure::system("Unable to serialize the cache metrics."))?,
+    )
+    .map_err(failure::system(format!(
+        "Unable to write file {}.",
+        state_path.to_string_lossy().code_str(),
+    )))
+}
+
+// Fetch the metrics of the last `count` runs, oldest first.
+pub fn recent_runs(count: usize) -> Vec<Run> {
+    let mut runs = load().runs;
+    runs.drain(..runs.len().saturating_sub(count));
+    runs
+}
+
+// Summarize the metrics of some runs.
+#[allow(clippy::cast_precision_loss)]
+pub fn report(runs: &[Run]) -> String {
+    if runs.is_empty() {
+        return "No runs have been recorded yet.".to_owned();
+    }
+
+    let executed = runs.iter().map(|run| run.executed).sum::<usize>();
+    let cached = runs.iter().map(|run| run.cached).sum::<usize>();
+    let tasks = executed + cached;
+    let time_saved = Duration::from_secs_f64(runs.iter().map(|run| run.time_saved_secs).sum());
+    let unknown_time_saved = runs.iter().map(|run| run.unknown_time_saved).sum::<usize>();
+    let bytes_pulled = runs.iter().map(|run| run.bytes_pulled).sum::<u64>();
+    let bytes_pushed = runs.iter().map(|run| run.bytes_pushed).sum::<u64>();
+
+    let mut report = format!(
+        "Cache metrics for the last {}:",
+        format::number(runs.len(), "run")
+    );
+    let _ = write!(
+        report,
+        "\n  Tasks: {} ({} cached, {} ran), a hit rate of {:.0}%",
+        tasks,
+        cached,
+        executed,
+        if tasks == 0 {
+            0.0_f64
+        } else {
+            cached as f64 / tasks as f64 * 100.0_f64
+        },
+    );
+    let _ = write!(
+        report,
+        "\n  Time saved: {} in total, {} per run on average",
+        units::format_duration(time_saved),
+        units::format_duration(time_saved / u32::try_from(runs.len()).unwrap_or(u32::MAX)),
+    );
+    if unknown_time_saved > 0 {
+        let _ = write!(
+            report,
+            " (not counting {} whose tasks were never seen to run)",
+            format::number(unknown_time_saved, "cache hit"),
+        );
+    }
+    let _ = write!(
+        report,
+        "\n  Remote cache: {} pulled, {} pushed",
+        units::format_bytes(bytes_pulled),
+        units::format_bytes(bytes_pushed),
+    );
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{
+            metrics::{report, update, Run, State, Timing, MAX_DURATIONS, MAX_RUNS},
+            runner::Report,
+        },
+        std::time::Duration,
+    };
+
+    fn ran(cache_key: Option<&str>, seconds: u64) -> (String, Report) {
+        (
+            "foo".to_owned(),
+            Report {
+                cache_key: cache_key.map(ToOwned::to_owned),
+                duration: Some(Duration::from_secs(seconds)),
+                bytes_pushed: 100,
+                ..Report::default()
+            },
+        )
+    }
+
+    fn hit(cache_key: &str) -> (String, Report) {
+        (
+            "foo".to_owned(),
+            Report {
+                cache_key: Some(cache_key.to_owned()),
+                cached: true,
+                bytes_pulled: 1000,
+                ..Report::default()
+            },
+        )
+    }
+
+    #[test]
+    fn update_records_durations() {
+        let mut state = State::default();
+
+        update(&mut state, &[ran(Some("toast:abc"), 60), ran(None, 5)], 42);
+
+        assert_eq!(
+            state.runs,
+            vec![Run {
+                timestamp: 42,
+                executed: 2,
+                cached: 0,
+                time_saved_secs: 0.0_f64,
+                unknown_time_saved: 0,
+                bytes_pulled: 0,
+                bytes_pushed: 200,
+            }],
+        );
+        assert_eq!(state.durations.len(), 1);
+        assert_eq!(
+            state.durations["toast:abc"],
+            Timing {
+                duration_secs: 60.0_f64,
+                last_used: 42,
+            },
+        );
+    }
+
+    #[test]
+    fn update_estimates_time_saved() {
+        let mut state = State::default();
+
+        update(&mut state, &[ran(Some("toast:abc"), 60)], 1);
+        update(&mut state, &[hit("toast:abc"), hit("toast:def")], 2);
+
+        assert_eq!(
+            state.runs[1],
+            Run {
+                timestamp: 2,
+                executed: 0,
+                cached: 2,
+                time_saved_secs: 60.0_f64,
+                unknown_time_saved: 1,
+                bytes_pulled: 2000,
+                bytes_pushed: 0,
+            },
+        );
+        assert_eq!(state.durations["toast:abc"].last_used, 2);
+    }
+
+    #[test]
+    fn update_counts_failures() {
+        let mut state = State::default();
+
+        let (name, mut failed) = ran(Some("toast:abc"), 60);
+        failed.duration = None;
+        update(&mut state, &[(name, failed)], 1);
+
+        assert_eq!(state.runs[0].executed, 1);
+        assert!(state.durations.is_empty());
+    }
+
+    #[test]
+    fn update_forgets_old_runs() {
+        let mut state = State::default();
+
+        for i in 0..=MAX_RUNS as u64 {
+            update(&mut state, &[], i);
+        }
+
+        assert_eq!(state.runs.len(), MAX_RUNS);
+        assert_eq!(state.runs[0].timestamp, 1);
+    }
+
+    #[test]
+    fn update_forgets_least_recently_used_durations() {
+        let mut state = State::default();
+
+        for i in 0..MAX_DURATIONS as u64 {
+            state.durations.insert(
+                format!("toast:{i}"),
+                Timing {
+                    duration_secs: 1.0_f64,
+                    last_used: i + 10,
+                },
+            );
+        }
+        update(
+            &mut state,
+            &[hit("toast:0"), ran(Some("toast:new"), 1)],
+            1_000_000,
+        );
+
+        assert_eq!(state.durations.len(), MAX_DURATIONS);
+        assert!(state.durations.contains_key("toast:0"));
+        assert!(state.durations.contains_key("toast:new"));
+        assert!(!state.durations.contains_key("toast:1"));
+        assert!(state.durations.contains_key("toast:2"));
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let mut state = State::default();
+        update(&mut state, &[ran(Some("toast:abc"), 60)], 1);
+        update(&mut state, &[hit("toast:abc")], 2);
+
+        let serialized = serde_yaml::to_string(&state).unwrap();
+        assert_eq!(serde_yaml::from_str::<State>(&serialized).unwrap(), state);
+    }
+
+    #[test]
+    fn state_missing_fields() {
+        assert_eq!(
+            serde_yaml::from_str::<State>("{}").unwrap(),
+            State::default()
+        );
+    }
+
+    #[test]
+    fn report_empty() {
+        assert_eq!(report(&[]), "No runs have been recorded yet.");
+    }
+
+    #[test]
+    fn report_aggregates_runs() {
+        let runs = vec![
+            Run {
+                timestamp: 1,
+                executed: 3,
+                cached: 1,
+                time_saved_secs: 30.0_f64,
+                unknown_time_saved: 0,
+                bytes_pulled: 1024,
+                bytes_pushed: 3 * 1024 * 1024,
+            },
+            Run {
+                timestamp: 2,
+                executed: 1,
+                cached: 3,
+                time_saved_secs: 150.0_f64,
+                unknown_time_saved: 2,
+                bytes_pulled: 0,
+                bytes_pushed: 0,
+            },
+        ];
+
+        assert_eq!(
+            report(&runs),
+            "Cache metrics for the last 2 runs:\n  \
+             Tasks: 8 (4 cached, 4 ran), a hit rate of 50%\n  \
+             Time saved: 3m 0s in total, 1m 30s per run on average (not counting 2 cache hits \
+             whose tasks were never seen to run)\n  \
+             Remote cache: 1.0 KiB pulled, 3.0 MiB pushed",
+        );
+    }
+
+    #[test]
+    fn report_no_tasks() {
+        let runs = vec![Run {
+            timestamp: 1,
+            ..Run::default()
+        }];
+
+        assert_eq!(
+            report(&runs),
+            "Cache metrics for the last 1 run:\n  \
+             Tasks: 0 (0 cached, 0 ran), a hit rate of 0%\n  \
+             Time saved: 0s in total, 0s per run on average\n  \
+             Remote cache: 0 B pulled, 0 B pushed",
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use {
+    crate::{cache, cache::CryptoHash, failure, failure::Failure, format::CodeStr, paths},
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::BTreeMap,
+        fs::{
+            create_dir_all, read_dir, read_link, read_to_string, symlink_metadata, write, File,
+            Metadata,
+        },
+        path::{Path, PathBuf},
+        time::{Duration, UNIX_EPOCH},
+    },
+    walkdir::WalkDir,
+};
+
+// Where the records of extracted outputs are stored, relative to the state directory for the
+// toastfile
+const STATE_DIR: &str = "outputs";
+
+// What we remember about a file, symlink, or directory that was extracted from a container
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct Artifact {
+    path: PathBuf,
+    size: u64,
+    modified_secs: u64,
+    modified_nanos: u32,
+    hash: String,
+}
+
+// The artifacts produced by a run with a particular final cache key
+#[derive(Deserialize, Serialize)]
+struct State {
+    // The architecture of the Docker daemon behind each Docker CLI the run used for cache keys, so
+    // a later run can compute the key without asking the daemons
+    #[serde(default)]
+    architectures: BTreeMap<String, String>,
+
+    artifacts: Vec<Artifact>,
+}
+
+// Determine where the state for a given key is stored.
+fn state_path(toastfile_path: &Path, key: &str) -> Option<PathBuf> {
+    paths::project_state_dir(toastfile_path).map(|dir| {
+        dir.join(STATE_DIR)
+            .join(format!("{}.yml", key.crypto_hash()))
+    })
+}
+
+// Compute a hash of the contents of a file, the target of a symlink, or the fact that a path is a
+// directory.
+fn hash_artifact(path: &Path, metadata: &Metadata) -> Result<String, Failure> {
+    if metadata.file_type().is_symlink() {
+        let target = read_link(path).map_err(failure::system(format!(
+            "Unable to read target of symbolic link {}.",
+            path.to_string_lossy().code_str(),
+        )))?;
+        Ok(cache::combine("symlink", &*target.to_string_lossy()))
+    } else if metadata.is_dir() {
+        Ok("directory".crypto_hash())
+    } else {
+        let mut file = File::open(path).map_err(failure::system(format!(
+            "Unable to open file {}.",
+            path.to_string_lossy().code_str(),
+        )))?;
+        cache::hash_read(&mut file)
+    }
+}
+
+// Fetch the modification time of a path relative to the Unix epoch, or zero if it's unavailable.
+fn modified_time(metadata: &Metadata) -> Duration {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default()
+}
+
+// Describe a path on the host, including a hash of its contents.
+fn describe(path: &Path, metadata: &Metadata) -> Result<Artifact, Failure> {
+    let modified = modified_time(metadata);
+
+    Ok(Artifact {
+        path: path.to_owned(),
+        size: metadata.len(),
+        modified_secs: modified.as_secs(),
+        modified_nanos: modified.subsec_nanos(),
+        hash: hash_artifact(path, metadata)?,
+    })
+}
+
+// Describe everything at and below the given paths. Paths which don't exist are skipped.
+fn describe_all(paths: &[PathBuf]) -> Result<Vec<Artifact>, Failure> {
+    let mut artifacts = vec![];
+
+    for path in paths {
+        if symlink_metadata(path).is_err() {
+            continue;
+        }
+
+        for entry in WalkDir::new(path) {
+            let entry = entry.map_err(failure::system(format!(
+                "Unable to traverse directory {}.",
+                path.to_string_lossy().code_str(),
+            )))?;
+            let metadata = entry.metadata().map_err(failure::system(format!(
+                "Unable to fetch filesystem metadata for {}.",
+                entry.path().to_string_lossy().code_str(),
+            )))?;
+            artifacts.push(describe(entry.path(), &metadata)?);
+        }
+    }
+
+    Ok(artifacts)
+}
+
+// Check whether an artifact is still on the host as it was recorded. Unless `verify_contents` is
+// `true`, an artifact whose size and modification time are unchanged is assumed to be intact.
+fn artifact_intact(artifact: &Artifact, verify_contents: bool) -> bool {
+    symlink_metadata(&artifact.path).is_ok_and(|metadata| {
+        let modified = modified_time(&metadata);
+
+        metadata.len() == artifact.size
+            && modified.as_secs() == artifact.modified_secs
+            && modified.subsec_nanos() == artifact.modified_nanos
+            && (!verify_contents
+                || hash_artifact(&artifact.path, &metadata).is_ok_and(|hash| hash == artifact.hash))
+    })
+}
+
+// Record the outputs produced by a run whose final cache key is `key`, along with the architectures
+// of the Docker daemons which went into it.
+pub fn record(
+    toastfile_path: &Path,
+    key: &str,
+    architectures: BTreeMap<String, String>,
+    paths: &[PathBuf],
+) -> Result<(), Failure> {
+    // If there's no state directory on this platform, there's nowhere to record anything.
+    let Some(state_path) = state_path(toastfile_path, key) else {
+        return Ok(());
+    };
+
+    let state = State {
+        architectures,
+        artifacts: describe_all(paths)?,
+    };
+
+    // The `unwrap` is safe because the state path was constructed inside a directory.
+    let state_dir = state_path.parent().unwrap();
+    create_dir_all(state_dir).map_err(failure::system(format!(
+        "Unable to create directory {}.",
+        state_dir.to_string_lossy().code_str(),
+    )))?;
+
+    write(
+        &state_path,
+        serde_yaml::to_string(&state)
+            .map_err(failure::system("Unable to serialize the output records."))?,
+    )
+    .map_err(failure::system(format!(
+        "Unable to write file {}.",
+        state_path.to_string_lossy().code_str(),
+    )))
+}
+
+// Fetch the architectures stored with each record of the outputs for a toastfile, without
+// duplicates. If nothing was recorded, this is empty.
+pub fn recorded_architectures(toastfile_path: &Path) -> Vec<BTreeMap<String, String>> {
+    let mut architectures = paths::project_state_dir(toastfile_path)
+        .and_then(|dir| read_dir(dir.join(STATE_DIR)).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| read_to_string(entry.ok()?.path()).ok())
+        .filter_map(|contents| serde_yaml::from_str::<State>(&contents).ok())
+        .map(|state| state.architectures)
+        .collect::<Vec<_>>();
+    architectures.sort();
+    architectures.dedup();
+    architectures
+}
+
+// Determine whether the outputs recorded for `key` are still on the host, unmodified.
+pub fn up_to_date(toastfile_path: &Path, key: &str, verify_contents: bool) -> bool {
+    state_path(toastfile_path, key)
+        .and_then(|state_path| read_to_string(state_path).ok())
+        .and_then(|contents| serde_yaml::from_str::<State>(&contents).ok())
+        .is_some_and(|state| {
+            state
+                .artifacts
+                .iter()
+                .all(|artifact| artifact_intact(artifact, verify_contents))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::outputs::{artifact_intact, describe_all},
+        std::fs::{create_dir, remove_file, write},
+        tempfile::tempdir,
+    };
+
+    #[test]
+    fn artifacts_matched() {
+        let dir = tempdir().unwrap();
+        create_dir(dir.path().join("dist")).unwrap();
+        write(dir.path().join("dist").join("app"), "binary").unwrap();
+
+        let artifacts = describe_all(&[dir.path().join("dist")]).unwrap();
+
+        assert_eq!(artifacts.len(), 2);
+        assert!(artifacts
+            .iter()
+            .all(|artifact| artifact_intact(artifact, false)));
+        assert!(artifacts
+            .iter()
+            .all(|artifact| artifact_intact(artifact, true)));
+    }
+
+    #[test]
+    fn artifacts_modified() {
+        let dir = tempdir().unwrap();
+        write(dir.path().join("app"), "binary").unwrap();
+
+        let artifacts = describe_all(&[dir.path().join("app")]).unwrap();
+        write(dir.path().join("app"), "tampered").unwrap();
+
+        assert!(!artifact_intact(&artifacts[0], true));
+    }
+
+    #[test]
+    fn artifacts_modified_same_metadata() {
+        let dir = tempdir().unwrap();
+        write(dir.path().join("app"), "binary").unwrap();
+
+        let mut artifacts = describe_all(&[dir.path().join("app")]).unwrap();
+        artifacts[0].hash = "stale".to_owned();
+
+        // Without verifying the contents, only the size and modification time are compared.
+        assert!(artifact_intact(&artifacts[0], false));
+        assert!(!artifact_intact(&artifacts[0], true));
+    }
+
+    #[test]
+    fn artifacts_deleted() {
+        let dir = tempdir().unwrap();
+        write(dir.path().join("app"), "binary").unwrap();
+
+        let artifacts = describe_all(&[dir.path().join("app")]).unwrap();
+        remove_file(dir.path().join("app")).unwrap();
+
+        assert!(!artifact_intact(&artifacts[0], false));
+        assert!(!artifact_intact(&artifacts[0], true));
+    }
+
+    #[test]
+    fn artifacts_missing_paths_skipped() {
+        let dir = tempdir().unwrap();
+
+        assert!(describe_all(&[dir.path().join("nothing")])
+            .unwrap()
+            .is_empty());
+    }
+}
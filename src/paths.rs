@@ -0,0 +1,208 @@
+use {
+    crate::{cache::CryptoHash, failure, failure::Failure, format::CodeStr},
+    std::{
+        env,
+        ffi::OsString,
+        fs,
+        path::{Path, PathBuf},
+    },
+    walkdir::WalkDir,
+};
+
+// The directory Toast uses within each of the user's base directories
+const APP_DIR: &str = "toast";
+
+// The directory within Toast's state directory for the state which belongs to particular toastfiles
+const PROJECTS_DIR: &str = "projects";
+
+// The environment variable which overrides the user's state directory on Linux and other Unix-like
+// platforms
+#[cfg(all(unix, not(target_os = "macos")))]
+const XDG_STATE_HOME_ENV_VAR: &str = "XDG_STATE_HOME";
+
+// How much `clear` removed
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Removed {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+// Determine where Toast keeps data which should persist between runs but which isn't worth backing
+// up, such as metrics and records of extracted output files. On Linux and other Unix-like
+// platforms, this follows `XDG_STATE_HOME`. macOS and Windows have no such convention, so the local
+// data directory (e.g., `~/Library/Application Support` on macOS) is used there instead.
+pub fn state_dir() -> Option<PathBuf> {
+    #[cfg(all(unix, not(target_os = "macos")))]
+    let base_dir = xdg_state_dir(env::var_os(XDG_STATE_HOME_ENV_VAR), dirs::home_dir());
+
+    #[cfg(not(all(unix, not(target_os = "macos"))))]
+    let base_dir = dirs::data_local_dir();
+
+    base_dir.map(|dir| dir.join(APP_DIR))
+}
+
+// Determine where Toast keeps data which only saves time, such as when it last checked for a new
+// release. Deleting it is always safe.
+pub fn cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join(APP_DIR))
+}
+
+// Determine where Toast keeps the state which belongs to a particular toastfile. The directory is
+// named after a hash of the absolute path to the toastfile, so each project gets its own, and a
+// project which is moved starts afresh.
+pub fn project_state_dir(toastfile_path: &Path) -> Option<PathBuf> {
+    state_dir().map(|dir| project_dir(&dir, toastfile_path))
+}
+
+// Compute the user's state directory according to the XDG Base Directory Specification. As with the
+// other XDG variables, a relative path in `XDG_STATE_HOME` is ignored.
+#[cfg_attr(not(all(unix, not(target_os = "macos"))), allow(dead_code))]
+fn xdg_state_dir(xdg_state_home: Option<OsString>, home_dir: Option<PathBuf>) -> Option<PathBuf> {
+    xdg_state_home
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| home_dir.map(|dir| dir.join(".local").join("state")))
+}
+
+// Compute the directory for the state which belongs to a toastfile, given Toast's state directory.
+fn project_dir(state_dir: &Path, toastfile_path: &Path) -> PathBuf {
+    // Resolve symbolic links and `..` components if possible, so the same toastfile always gets the
+    // same directory.
+    let absolute_path = fs::canonicalize(toastfile_path).unwrap_or_else(|_| {
+        env::current_dir().map_or_else(
+            |_| toastfile_path.to_owned(),
+            |dir| dir.join(toastfile_path),
+        )
+    });
+
+    state_dir
+        .join(PROJECTS_DIR)
+        .join(absolute_path.crypto_hash())
+}
+
+// Remove the given directories and everything in them, for `toast state clear`. Directories which
+// don't exist are skipped.
+pub fn clear(dirs: &[PathBuf]) -> Result<Removed, Failure> {
+    let mut removed = Removed::default();
+
+    for dir in dirs {
+        if !dir.exists() {
+            continue;
+        }
+
+        // Add up what's there before removing it, for the summary.
+        for entry in WalkDir::new(dir) {
+            let entry = entry.map_err(failure::system(format!(
+                "Unable to traverse directory {}.",
+                dir.to_string_lossy().code_str(),
+            )))?;
+            if entry.file_type().is_file() {
+                removed.files += 1;
+                removed.bytes += entry.metadata().map_or(0, |metadata| metadata.len());
+            }
+        }
+
+        fs::remove_dir_all(dir).map_err(failure::system(format!(
+            "Unable to remove directory {}.",
+            dir.to_string_lossy().code_str(),
+        )))?;
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::paths::{clear, project_dir, xdg_state_dir, Removed},
+        std::{ffi::OsString, fs, path::Path},
+        tempfile::tempdir,
+    };
+
+    #[test]
+    fn xdg_state_dir_override() {
+        assert_eq!(
+            xdg_state_dir(
+                Some(OsString::from("/var/lib/flynn/state")),
+                Some(Path::new("/home/flynn").to_owned()),
+            ),
+            Some(Path::new("/var/lib/flynn/state").to_owned()),
+        );
+    }
+
+    #[test]
+    fn xdg_state_dir_default() {
+        assert_eq!(
+            xdg_state_dir(None, Some(Path::new("/home/flynn").to_owned())),
+            Some(Path::new("/home/flynn/.local/state").to_owned()),
+        );
+    }
+
+    #[test]
+    fn xdg_state_dir_relative_override() {
+        assert_eq!(
+            xdg_state_dir(
+                Some(OsString::from("state")),
+                Some(Path::new("/home/flynn").to_owned()),
+            ),
+            Some(Path::new("/home/flynn/.local/state").to_owned()),
+        );
+    }
+
+    #[test]
+    fn xdg_state_dir_no_home() {
+        assert_eq!(xdg_state_dir(None, None), None);
+    }
+
+    #[test]
+    fn project_dir_per_toastfile() {
+        let projects = tempdir().unwrap();
+        fs::create_dir_all(projects.path().join("foo/sub")).unwrap();
+        fs::create_dir_all(projects.path().join("bar")).unwrap();
+        fs::write(projects.path().join("foo/toast.yml"), "").unwrap();
+        fs::write(projects.path().join("bar/toast.yml"), "").unwrap();
+        let state_dir = Path::new("/state");
+
+        let foo = project_dir(state_dir, &projects.path().join("foo/toast.yml"));
+        let bar = project_dir(state_dir, &projects.path().join("bar/toast.yml"));
+
+        assert!(foo.starts_with("/state/projects"));
+        assert_ne!(foo, bar);
+        assert_eq!(
+            project_dir(state_dir, &projects.path().join("foo/sub/../toast.yml")),
+            foo,
+        );
+    }
+
+    #[test]
+    fn clear_scoped() {
+        let state_dir = tempdir().unwrap();
+        let foo = state_dir.path().join("projects/foo");
+        let bar = state_dir.path().join("projects/bar");
+        fs::create_dir_all(foo.join("outputs")).unwrap();
+        fs::create_dir_all(&bar).unwrap();
+        fs::write(foo.join("outputs/a.yml"), "12345").unwrap();
+        fs::write(foo.join("outputs/b.yml"), "123").unwrap();
+        fs::write(bar.join("c.yml"), "1").unwrap();
+        fs::write(state_dir.path().join("metrics.yml"), "1").unwrap();
+
+        assert_eq!(
+            clear(&[foo.clone(), state_dir.path().join("missing")]).unwrap(),
+            Removed { files: 2, bytes: 8 },
+        );
+
+        assert!(!foo.exists());
+        assert!(bar.join("c.yml").exists());
+        assert!(state_dir.path().join("metrics.yml").exists());
+    }
+
+    #[test]
+    fn clear_nothing() {
+        let state_dir = tempdir().unwrap();
+
+        assert_eq!(
+            clear(&[state_dir.path().join("missing")]).unwrap(),
+            Removed::default(),
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use {
+    crate::{docker, failure::Failure, format::CodeStr},
+    std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        thread::sleep,
+        time::Duration,
+    },
+};
+
+// How often to check whether the schedule has been resumed
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Whether the user has asked Toast to pause
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+// A request from the user to pause or resume
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Request {
+    Pause,
+    Resume,
+}
+
+// What needs to happen to the active containers in response to a request
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Action {
+    PauseContainers,
+    UnpauseContainers,
+    Nothing,
+}
+
+// Compute the next paused state and what to do with the active containers. Requests which don't
+// change the state (e.g., pausing twice) do nothing.
+fn transition(paused: bool, request: Request) -> (bool, Action) {
+    match (paused, request) {
+        (false, Request::Pause) => (true, Action::PauseContainers),
+        (true, Request::Resume) => (false, Action::UnpauseContainers),
+        (true, Request::Pause) => (true, Action::Nothing),
+        (false, Request::Resume) => (false, Action::Nothing),
+    }
+}
+
+// Pause or resume the schedule. Pausing also freezes any active containers with `docker pause`.
+// The active containers are mapped to the Docker CLI which manages them.
+pub fn handle(
+    request: Request,
+    active_containers: &Arc<Mutex<HashMap<String, String>>>,
+    interrupted: &Arc<AtomicBool>,
+) {
+    // The `unwrap` will only fail if a panic already occurred.
+    let containers = active_containers.lock().unwrap().clone();
+
+    // The `unwrap` is safe because the closure always returns `Some`.
+    let paused = PAUSED
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |paused| {
+            Some(transition(paused, request).0)
+        })
+        .unwrap();
+
+    match transition(paused, request).1 {
+        Action::PauseContainers => {
+            for (container, docker_cli) in &containers {
+                if let Err(e) = docker::pause_container(&**docker_cli, container, interrupted) {
+                    error!("{}", e);
+                }
+            }
+
+            warn!(
+                "Paused \u{2014} send {} to resume (e.g., {}).",
+                "SIGCONT".code_str(),
+                format!("kill -CONT {}", std::process::id()).code_str(),
+            );
+        }
+        Action::UnpauseContainers => {
+            // The Docker CLI processes were stopped along with Toast, so they need to be resumed
+            // too.
+            #[cfg(unix)]
+            docker::resume_children();
+
+            for (container, docker_cli) in &containers {
+                if let Err(e) = docker::unpause_container(&**docker_cli, container, interrupted) {
+                    error!("{}", e);
+                }
+            }
+
+            info!("Resumed.");
+        }
+        Action::Nothing => {}
+    }
+}
+
+// Block while the schedule is paused. Returns early if the user interrupts the program.
+pub fn wait_while_paused(interrupted: &Arc<AtomicBool>) {
+    while PAUSED.load(Ordering::SeqCst) && !interrupted.load(Ordering::SeqCst) {
+        sleep(POLL_INTERVAL);
+    }
+}
+
+// Trap SIGTSTP (e.g., CTRL+Z at the terminal) to pause and SIGCONT to resume. Rather than stopping
+// the whole process, this lets the current container be frozen and the schedule wait between tasks.
+#[cfg(unix)]
+pub fn set_up_signal_handler(
+    interrupted: Arc<AtomicBool>,
+    active_containers: Arc<Mutex<HashMap<String, String>>>,
+) -> Result<(), Failure> {
+    use {
+        crate::failure,
+        signal_hook::{
+            consts::{SIGCONT, SIGTSTP},
+            iterator::Signals,
+        },
+        std::thread,
+    };
+
+    let mut signals = Signals::new([SIGTSTP, SIGCONT])
+        .map_err(failure::system("Error installing signal handler."))?;
+
+    thread::spawn(move || {
+        for signal in &mut signals {
+            let request = if signal == SIGTSTP {
+                Request::Pause
+            } else {
+                Request::Resume
+            };
+
+            handle(request, &active_containers, &interrupted);
+        }
+    });
+
+    Ok(())
+}
+
+// Pausing via signals is only supported on Unix-like platforms.
+#[cfg(not(unix))]
+#[allow(clippy::needless_pass_by_value, clippy::unnecessary_wraps)]
+pub fn set_up_signal_handler(
+    _interrupted: Arc<AtomicBool>,
+    _active_containers: Arc<Mutex<HashMap<String, String>>>,
+) -> Result<(), Failure> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pause::{transition, Action, Request};
+
+    #[test]
+    fn transition_pause() {
+        assert_eq!(
+            transition(false, Request::Pause),
+            (true, Action::PauseContainers),
+        );
+    }
+
+    #[test]
+    fn transition_resume() {
+        assert_eq!(
+            transition(true, Request::Resume),
+            (false, Action::UnpauseContainers),
+        );
+    }
+
+    #[test]
+    fn transition_pause_twice() {
+        assert_eq!(transition(true, Request::Pause), (true, Action::Nothing));
+    }
+
+    #[test]
+    fn transition_resume_when_running() {
+        assert_eq!(transition(false, Request::Resume), (false, Action::Nothing));
+    }
+
+    #[test]
+    fn transition_round_trip() {
+        let (paused, _) = transition(false, Request::Pause);
+        let (paused, _) = transition(paused, Request::Resume);
+
+        assert!(!paused);
+    }
+}
@@ -0,0 +1,402 @@
+use {
+    crate::{format, format::CodeStr, trace::json_string},
+    std::{
+        fmt::Write as _,
+        fs::{File, OpenOptions},
+        io::{self, Write},
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+            Mutex,
+        },
+        thread,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+};
+
+// How many events can be waiting to be written before new ones are dropped. The reader only falls
+// this far behind if it stops reading, since there are only a handful of events per task.
+const QUEUE_CAPACITY: usize = 1024;
+
+// How long to wait at the end of the run for the remaining events to be written
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Where to write the progress events
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Destination {
+    Descriptor(u32),
+    File(PathBuf),
+}
+
+impl Destination {
+    fn open(&self) -> io::Result<File> {
+        match self {
+            // The descriptor is opened again via `/dev/fd`, so Toast doesn't take ownership of it.
+            // It's opened for writing only, so a regular file isn't truncated.
+            Self::Descriptor(descriptor) => OpenOptions::new()
+                .write(true)
+                .open(format!("/dev/fd/{descriptor}")),
+            Self::File(path) => File::create(path),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::Descriptor(descriptor) => format!("file descriptor {descriptor}"),
+            Self::File(path) => path.to_string_lossy().code_str().to_string(),
+        }
+    }
+}
+
+// Something which happened during the run. The JSON rendering of these is a stable interface for
+// programs which wrap Toast, so fields may be added but not removed or renamed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    TaskStarted {
+        task: String,
+    },
+    TaskCached {
+        task: String,
+    },
+    PhaseStarted {
+        task: String,
+        phase: String,
+    },
+    PhaseFinished {
+        task: String,
+        phase: String,
+        duration: Duration,
+    },
+    TaskFinished {
+        task: String,
+        duration: Duration,
+        success: bool,
+    },
+    RunFinished {
+        duration: Duration,
+        success: bool,
+    },
+}
+
+impl Event {
+    // Render the event as a line of JSON. The time is relative to the Unix epoch, and times and
+    // durations are in milliseconds.
+    pub fn to_json(&self, time: Duration) -> String {
+        let (name, task, phase, duration, success) = match self {
+            Self::TaskStarted { task } => ("task_started", Some(task), None, None, None),
+            Self::TaskCached { task } => ("task_cached", Some(task), None, None, None),
+            Self::PhaseStarted { task, phase } => {
+                ("phase_started", Some(task), Some(phase), None, None)
+            }
+            Self::PhaseFinished {
+                task,
+                phase,
+                duration,
+            } => (
+                "phase_finished",
+                Some(task),
+                Some(phase),
+                Some(duration),
+                None,
+            ),
+            Self::TaskFinished {
+                task,
+                duration,
+                success,
+            } => (
+                "task_finished",
+                Some(task),
+                None,
+                Some(duration),
+                Some(success),
+            ),
+            Self::RunFinished { duration, success } => {
+                ("run_finished", None, None, Some(duration), Some(success))
+            }
+        };
+
+        let mut json = format!(
+            "{{\"event\":{},\"time\":{}",
+            json_string(name),
+            time.as_millis(),
+        );
+        if let Some(task) = task {
+            let _ = write!(json, ",\"task\":{}", json_string(task));
+        }
+        if let Some(phase) = phase {
+            let _ = write!(json, ",\"phase\":{}", json_string(phase));
+        }
+        if let Some(duration) = duration {
+            let _ = write!(json, ",\"duration_ms\":{}", duration.as_millis());
+        }
+        if let Some(success) = success {
+            let _ = write!(json, ",\"success\":{success}");
+        }
+        json.push_str("}\n");
+
+        json
+    }
+}
+
+// This struct writes progress events as newline-delimited JSON from a background thread. Sending
+// an event never blocks: if the reader doesn't keep up, events are dropped. If it's disabled, no
+// thread is started and events are discarded.
+pub struct Progress {
+    sender: Mutex<Option<SyncSender<String>>>,
+    finished: Mutex<Option<Receiver<()>>>,
+    dropped: AtomicUsize,
+
+    // The task which is currently running, for the phase events. Tasks run one at a time.
+    task: Mutex<String>,
+}
+
+impl Progress {
+    pub fn new(destination: Option<Destination>) -> Self {
+        let (sender, finished) = destination.map_or((None, None), |destination| {
+            let (sender, receiver) = sync_channel::<String>(QUEUE_CAPACITY);
+            let (finished_sender, finished) = sync_channel(1);
+
+            thread::spawn(move || {
+                // The destination is opened here rather than up front, since opening a named pipe
+                // waits for a reader.
+                match destination.open() {
+                    Ok(mut file) => {
+                        // Stop when the run is over or the reader goes away.
+                        for line in receiver {
+                            if file.write_all(line.as_bytes()).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Err(error) => warn!(
+                        "Unable to open {} for progress events. Reason: {}",
+                        destination.describe(),
+                        error,
+                    ),
+                }
+
+                let _ = finished_sender.send(());
+            });
+
+            (Some(sender), Some(finished))
+        });
+
+        Self {
+            sender: Mutex::new(sender),
+            finished: Mutex::new(finished),
+            dropped: AtomicUsize::new(0),
+            task: Mutex::new(String::new()),
+        }
+    }
+
+    // Queue an event to be written, unless the queue is full.
+    fn send(&self, event: &Event) {
+        // The `unwrap` will only fail if a panic already occurred.
+        if let Some(sender) = &*self.sender.lock().unwrap() {
+            let time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+
+            if let Err(TrySendError::Full(_)) = sender.try_send(event.to_json(time)) {
+                self.dropped.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    pub fn task_started(&self, task: &str) {
+        // The `unwrap` will only fail if a panic already occurred.
+        task.clone_into(&mut self.task.lock().unwrap());
+
+        self.send(&Event::TaskStarted {
+            task: task.to_owned(),
+        });
+    }
+
+    pub fn task_cached(&self, task: &str) {
+        self.send(&Event::TaskCached {
+            task: task.to_owned(),
+        });
+    }
+
+    pub fn phase_started(&self, phase: &str) {
+        self.send(&Event::PhaseStarted {
+            // The `unwrap` will only fail if a panic already occurred.
+            task: self.task.lock().unwrap().clone(),
+            phase: phase.to_owned(),
+        });
+    }
+
+    pub fn phase_finished(&self, phase: &str, duration: Duration) {
+        self.send(&Event::PhaseFinished {
+            // The `unwrap` will only fail if a panic already occurred.
+            task: self.task.lock().unwrap().clone(),
+            phase: phase.to_owned(),
+            duration,
+        });
+    }
+
+    pub fn task_finished(&self, task: &str, duration: Duration, success: bool) {
+        self.send(&Event::TaskFinished {
+            task: task.to_owned(),
+            duration,
+            success,
+        });
+    }
+
+    // Report the end of the run, and wait (briefly) for the events to be written. Events sent after
+    // this are discarded.
+    pub fn run_finished(&self, duration: Duration, success: bool) {
+        self.send(&Event::RunFinished { duration, success });
+
+        // Dropping the sender tells the thread to stop once it has written everything. The
+        // `unwrap`s will only fail if a panic already occurred.
+        self.sender.lock().unwrap().take();
+        if let Some(finished) = self.finished.lock().unwrap().take() {
+            let _ = finished.recv_timeout(FLUSH_TIMEOUT);
+        }
+
+        let dropped = self.dropped.load(Ordering::SeqCst);
+        if dropped > 0 {
+            debug!(
+                "Dropped {} because they weren't read quickly enough.",
+                format::number(dropped, "progress event"),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::progress::{Destination, Event, Progress},
+        std::{fs, time::Duration},
+        tempfile::tempdir,
+    };
+
+    const TIME: Duration = Duration::from_millis(1_700_000_000_123);
+
+    #[test]
+    fn event_task_started() {
+        assert_eq!(
+            Event::TaskStarted {
+                task: "build".to_owned(),
+            }
+            .to_json(TIME),
+            "{\"event\":\"task_started\",\"time\":1700000000123,\"task\":\"build\"}\n",
+        );
+    }
+
+    #[test]
+    fn event_task_cached() {
+        assert_eq!(
+            Event::TaskCached {
+                task: "build".to_owned(),
+            }
+            .to_json(TIME),
+            "{\"event\":\"task_cached\",\"time\":1700000000123,\"task\":\"build\"}\n",
+        );
+    }
+
+    #[test]
+    fn event_phase_started() {
+        assert_eq!(
+            Event::PhaseStarted {
+                task: "build".to_owned(),
+                phase: "run".to_owned(),
+            }
+            .to_json(TIME),
+            "{\"event\":\"phase_started\",\"time\":1700000000123,\"task\":\"build\",\
+             \"phase\":\"run\"}\n",
+        );
+    }
+
+    #[test]
+    fn event_phase_finished() {
+        assert_eq!(
+            Event::PhaseFinished {
+                task: "build".to_owned(),
+                phase: "run".to_owned(),
+                duration: Duration::from_micros(1_500_900),
+            }
+            .to_json(TIME),
+            "{\"event\":\"phase_finished\",\"time\":1700000000123,\"task\":\"build\",\
+             \"phase\":\"run\",\"duration_ms\":1500}\n",
+        );
+    }
+
+    #[test]
+    fn event_task_finished() {
+        assert_eq!(
+            Event::TaskFinished {
+                task: "say \"hi\"".to_owned(),
+                duration: Duration::from_secs(2),
+                success: false,
+            }
+            .to_json(TIME),
+            "{\"event\":\"task_finished\",\"time\":1700000000123,\"task\":\"say \\\"hi\\\"\",\
+             \"duration_ms\":2000,\"success\":false}\n",
+        );
+    }
+
+    #[test]
+    fn event_run_finished() {
+        assert_eq!(
+            Event::RunFinished {
+                duration: Duration::from_secs(3),
+                success: true,
+            }
+            .to_json(TIME),
+            "{\"event\":\"run_finished\",\"time\":1700000000123,\"duration_ms\":3000,\
+             \"success\":true}\n",
+        );
+    }
+
+    #[test]
+    fn progress_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("progress.jsonl");
+        let progress = Progress::new(Some(Destination::File(path.clone())));
+
+        progress.task_started("build");
+        progress.phase_started("run");
+        progress.phase_finished("run", Duration::from_millis(5));
+        progress.task_finished("build", Duration::from_millis(7), true);
+        progress.run_finished(Duration::from_millis(9), true);
+        progress.task_started("ignored");
+
+        let events = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                // Leave out the time, since it varies.
+                let start = line.find(",\"time\":").unwrap();
+                let end = start + 1 + line[start + 1..].find(',').unwrap();
+                format!("{}{}", &line[..start], &line[end..])
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            vec![
+                "{\"event\":\"task_started\",\"task\":\"build\"}",
+                "{\"event\":\"phase_started\",\"task\":\"build\",\"phase\":\"run\"}",
+                "{\"event\":\"phase_finished\",\"task\":\"build\",\"phase\":\"run\",\
+                 \"duration_ms\":5}",
+                "{\"event\":\"task_finished\",\"task\":\"build\",\"duration_ms\":7,\
+                 \"success\":true}",
+                "{\"event\":\"run_finished\",\"duration_ms\":9,\"success\":true}",
+            ],
+        );
+    }
+
+    #[test]
+    fn progress_disabled() {
+        let progress = Progress::new(None);
+
+        progress.task_started("build");
+        progress.run_finished(Duration::from_millis(9), true);
+
+        assert!(progress.sender.lock().unwrap().is_none());
+        assert!(progress.finished.lock().unwrap().is_none());
+    }
+}
@@ -0,0 +1,416 @@
+use {
+    crate::{
+        cache::TAG_PREFIX, docker, docker::DockerCli, failure::Failure, format, format::CodeStr,
+        units,
+    },
+    std::{
+        collections::HashSet,
+        sync::{atomic::AtomicBool, Arc},
+        time::{Duration, SystemTime},
+    },
+};
+
+// An image which Toast created for the cache
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CachedImage {
+    pub name: String,
+    pub created: SystemTime,
+    pub size: u64,
+}
+
+// Find the images in the cache in the given repositories. Other images in those repositories (e.g.,
+// ones the user tagged themselves) are left out [ref:cache_tag_prefix].
+pub fn find(
+    docker_cli: &(impl DockerCli + ?Sized),
+    repos: &[&str],
+    interrupted: &Arc<AtomicBool>,
+) -> Result<Vec<CachedImage>, Failure> {
+    let mut images = vec![];
+
+    for repo in repos {
+        for name in docker::list_images(docker_cli, repo, interrupted)? {
+            if is_cached_image(&name) {
+                let created = docker::image_created(docker_cli, &name, interrupted)?;
+                let size = docker::image_size(docker_cli, &name, interrupted)?;
+                images.push(CachedImage {
+                    name,
+                    created,
+                    size,
+                });
+            }
+        }
+    }
+
+    images.sort_unstable_by(|x, y| x.name.cmp(&y.name));
+    images.dedup_by(|x, y| x.name == y.name);
+
+    Ok(images)
+}
+
+// Determine whether an image, given as `repository:tag`, is in the cache.
+fn is_cached_image(name: &str) -> bool {
+    name.rsplit_once(':')
+        .is_some_and(|(_, tag)| tag.starts_with(TAG_PREFIX))
+}
+
+// Decide which images to remove. Only images created at least `older_than` before `now` are
+// removed, if it's given. The images in `keep` are never removed.
+pub fn select(
+    images: Vec<CachedImage>,
+    now: SystemTime,
+    older_than: Option<Duration>,
+    keep: &HashSet<String>,
+) -> Vec<CachedImage> {
+    images
+        .into_iter()
+        .filter(|image| !keep.contains(&image.name))
+        .filter(|image| {
+            older_than.is_none_or(|older_than| {
+                // An image from the future (e.g., due to clock skew) counts as brand new.
+                now.duration_since(image.created)
+                    .is_ok_and(|age| age >= older_than)
+            })
+        })
+        .collect()
+}
+
+// Describe how long ago an image was created, in days if it's at least a day old.
+fn age(image: &CachedImage, now: SystemTime) -> String {
+    let age = now.duration_since(image.created).unwrap_or(Duration::ZERO);
+    let days = age.as_secs() / (24 * 60 * 60);
+    if days > 0 {
+        format!("{days}d")
+    } else {
+        units::format_duration(age)
+    }
+}
+
+// Remove the given images, or just list them if `dry_run` is set. Layers shared with other images
+// aren't freed, so the total size may overstate how much disk space is reclaimed.
+pub fn remove(
+    docker_cli: &(impl DockerCli + ?Sized),
+    images: &[CachedImage],
+    now: SystemTime,
+    dry_run: bool,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    let total = units::format_bytes(images.iter().map(|image| image.size).sum());
+
+    if images.is_empty() {
+        info!("There are no cached images to remove.");
+        return Ok(());
+    }
+
+    for image in images {
+        println!(
+            "{} ({}, created {} ago)",
+            image.name,
+            units::format_bytes(image.size),
+            age(image, now),
+        );
+    }
+
+    if dry_run {
+        info!(
+            "{} would be removed, totaling {}.",
+            format::number(images.len(), "image"),
+            total.code_str(),
+        );
+        return Ok(());
+    }
+
+    for image in images {
+        docker::delete_image(docker_cli, &image.name, interrupted)?;
+    }
+
+    info!(
+        "Removed {}, totaling {}.",
+        format::number(images.len(), "image"),
+        total.code_str(),
+    );
+
+    Ok(())
+}
+
+// Find the volumes Docker created for the `volumes` of tasks which use any of the given
+// repositories.
+pub fn find_volumes(
+    docker_cli: &(impl DockerCli + ?Sized),
+    repos: &[&str],
+    interrupted: &Arc<AtomicBool>,
+) -> Result<Vec<String>, Failure> {
+    let mut volumes = vec![];
+    for repo in repos {
+        volumes.extend(docker::list_volumes(docker_cli, repo, interrupted)?);
+    }
+    volumes.sort_unstable();
+    volumes.dedup();
+
+    Ok(volumes)
+}
+
+// Remove the volumes Docker created for the `volumes` of tasks, or just list them if `dry_run` is
+// set. A volume which a container is still using can't be removed, so that fails the prune.
+pub fn remove_volumes(
+    docker_cli: &(impl DockerCli + ?Sized),
+    volumes: &[String],
+    dry_run: bool,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    if volumes.is_empty() {
+        info!("There are no volumes to remove.");
+        return Ok(());
+    }
+
+    for volume in volumes {
+        println!("{volume} (volume)");
+    }
+
+    if dry_run {
+        info!(
+            "{} would be removed.",
+            format::number(volumes.len(), "volume"),
+        );
+        return Ok(());
+    }
+
+    for volume in volumes {
+        docker::delete_volume(docker_cli, volume, interrupted)?;
+    }
+
+    info!("Removed {}.", format::number(volumes.len(), "volume"));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{
+            docker::{FakeCli, Outcome},
+            prune::{
+                find, find_volumes, is_cached_image, remove, remove_volumes, select, CachedImage,
+            },
+        },
+        std::{
+            collections::HashSet,
+            sync::{atomic::AtomicBool, Arc},
+            time::{Duration, SystemTime, UNIX_EPOCH},
+        },
+    };
+
+    const DAY: Duration = Duration::from_hours(24);
+
+    fn succeeded(stdout: &str) -> Outcome {
+        Outcome {
+            code: Some(0),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: vec![],
+        }
+    }
+
+    fn image(name: &str, created: SystemTime) -> CachedImage {
+        CachedImage {
+            name: name.to_owned(),
+            created,
+            size: 1_000,
+        }
+    }
+
+    #[test]
+    fn is_cached_image_toast() {
+        assert!(is_cached_image("toast:toast-3d5f"));
+        assert!(is_cached_image("localhost:5000/toast:toast-3d5f"));
+    }
+
+    #[test]
+    fn is_cached_image_other() {
+        assert!(!is_cached_image("toast:latest"));
+        assert!(!is_cached_image("localhost:5000/toast-3d5f"));
+        assert!(!is_cached_image("toast"));
+    }
+
+    #[test]
+    fn find_cached_images() {
+        let cli = FakeCli::new(vec![
+            succeeded("toast:toast-b2\ntoast:latest\ntoast:toast-a1\n"),
+            succeeded("2024-05-01T12:00:00Z\n"),
+            succeeded("100\n"),
+            succeeded("2024-05-02T12:00:00Z\n"),
+            succeeded("200\n"),
+        ]);
+
+        let images = find(&cli, &["toast"], &Arc::new(AtomicBool::new(false))).unwrap();
+
+        assert_eq!(
+            images
+                .iter()
+                .map(|image| (image.name.as_str(), image.size))
+                .collect::<Vec<_>>(),
+            vec![("toast:toast-a1", 200), ("toast:toast-b2", 100)],
+        );
+        let calls = cli.calls();
+        assert_eq!(
+            calls[0],
+            vec![
+                "image",
+                "ls",
+                "--format",
+                "{{.Repository}}:{{.Tag}}",
+                "toast",
+            ],
+        );
+        assert_eq!(calls.len(), 5);
+    }
+
+    #[test]
+    fn select_all() {
+        let now = UNIX_EPOCH + 100 * DAY;
+        let images = vec![image("toast:toast-a1", now), image("toast:toast-b2", now)];
+
+        assert_eq!(select(images.clone(), now, None, &HashSet::new()), images);
+    }
+
+    #[test]
+    fn select_older_than() {
+        let now = UNIX_EPOCH + 100 * DAY;
+        let images = vec![
+            image("toast:toast-a1", now - 8 * DAY),
+            image("toast:toast-b2", now - 7 * DAY),
+            image("toast:toast-c3", now - DAY),
+            image("toast:toast-d4", now + DAY),
+        ];
+
+        assert_eq!(
+            select(images.clone(), now, Some(7 * DAY), &HashSet::new()),
+            images[..2].to_vec(),
+        );
+    }
+
+    #[test]
+    fn select_keep() {
+        let now = UNIX_EPOCH + 100 * DAY;
+        let images = vec![
+            image("toast:toast-a1", now - 8 * DAY),
+            image("toast:toast-b2", now - 8 * DAY),
+        ];
+        let keep = HashSet::from(["toast:toast-a1".to_owned()]);
+
+        assert_eq!(
+            select(images.clone(), now, Some(7 * DAY), &keep),
+            images[1..].to_vec(),
+        );
+    }
+
+    #[test]
+    fn remove_images() {
+        let now = UNIX_EPOCH + 100 * DAY;
+        let cli = FakeCli::new(vec![succeeded(""), succeeded("")]);
+
+        remove(
+            &cli,
+            &[image("toast:toast-a1", now), image("toast:toast-b2", now)],
+            now,
+            false,
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            cli.calls(),
+            vec![
+                vec!["image", "rm", "--force", "toast:toast-a1"],
+                vec!["image", "rm", "--force", "toast:toast-b2"],
+            ],
+        );
+    }
+
+    #[test]
+    fn remove_dry_run() {
+        let now = UNIX_EPOCH + 100 * DAY;
+        let cli = FakeCli::new(vec![]);
+
+        remove(
+            &cli,
+            &[image("toast:toast-a1", now)],
+            now,
+            true,
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert!(cli.calls().is_empty());
+    }
+
+    #[test]
+    fn find_volumes_by_repo() {
+        let cli = FakeCli::new(vec![succeeded("npm\n"), succeeded("cargo-registry\n")]);
+
+        assert_eq!(
+            find_volumes(
+                &cli,
+                &["toast", "registry.encom.com/grid"],
+                &Arc::new(AtomicBool::new(false)),
+            )
+            .unwrap(),
+            vec!["cargo-registry".to_owned(), "npm".to_owned()],
+        );
+        assert_eq!(
+            cli.calls(),
+            vec![
+                vec![
+                    "volume",
+                    "ls",
+                    "--filter",
+                    "label=toast.repo=toast",
+                    "--format",
+                    "{{.Name}}",
+                ],
+                vec![
+                    "volume",
+                    "ls",
+                    "--filter",
+                    "label=toast.repo=registry.encom.com/grid",
+                    "--format",
+                    "{{.Name}}",
+                ],
+            ],
+        );
+    }
+
+    #[test]
+    fn remove_volumes_all() {
+        let cli = FakeCli::new(vec![succeeded("cargo-registry\n"), succeeded("npm\n")]);
+
+        remove_volumes(
+            &cli,
+            &["cargo-registry".to_owned(), "npm".to_owned()],
+            false,
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            cli.calls(),
+            vec![
+                vec!["volume", "rm", "cargo-registry"],
+                vec!["volume", "rm", "npm"],
+            ],
+        );
+    }
+
+    #[test]
+    fn remove_volumes_dry_run() {
+        let cli = FakeCli::new(vec![]);
+
+        remove_volumes(
+            &cli,
+            &["cargo-registry".to_owned()],
+            true,
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert!(cli.calls().is_empty());
+    }
+}
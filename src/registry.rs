@@ -0,0 +1,438 @@
+use {
+    crate::{docker, docker::DockerCli, failure, failure::Failure, format::CodeStr},
+    serde::Deserialize,
+    std::{
+        process::{Command, Stdio},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    },
+};
+
+// How the command given by `registry_login_command` logs in to the registry
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LoginMode {
+    // The command runs `docker login` itself.
+    #[default]
+    Command,
+
+    // The command prints a token, which Toast passes to `docker login`.
+    Token,
+}
+
+// A way to log in to the registry used for remote caching before the run
+pub struct Login {
+    // Must be nonempty [ref:registry_login_command_nonempty]
+    pub command: Vec<String>,
+
+    pub mode: LoginMode,
+
+    // Must be present if `mode` is `LoginMode::Token` [ref:registry_user_for_token]
+    pub user: Option<String>,
+}
+
+// Determine the registry of a Docker repository, or `None` if it's on Docker Hub. Like Docker, we
+// consider the first component of the repository to be a registry if it looks like a host name,
+// i.e., if it contains a dot or a port, is `localhost`, or has uppercase letters (which repository
+// names can't have).
+pub fn registry_host(docker_repo: &str) -> Option<&str> {
+    docker_repo
+        .split_once('/')
+        .map(|(first, _)| first)
+        .filter(|first| {
+            first.contains(['.', ':'])
+                || *first == "localhost"
+                || first.chars().any(|c| c.is_ascii_uppercase())
+        })
+}
+
+// Warn about repositories which would be pushed to Docker Hub, since they don't name a registry.
+// It's easy to do this by accident and publish images containing private code.
+pub fn public_push_warning(docker_repos: &[&str]) -> Option<String> {
+    let mut public_repos = docker_repos
+        .iter()
+        .copied()
+        .filter(|docker_repo| registry_host(docker_repo).is_none())
+        .collect::<Vec<_>>();
+    public_repos.sort_unstable();
+    public_repos.dedup();
+
+    if public_repos.is_empty() {
+        return None;
+    }
+
+    Some(format!(
+        "Remote cache writes are enabled, and the following {} a registry, so images \
+         will be pushed to Docker Hub, where they may be public: {}. To use a private registry, \
+         include its host in the repository (e.g., {}). If pushing to Docker Hub is intended, set \
+         {} in the configuration file.",
+        if public_repos.len() == 1 {
+            "repository doesn't name"
+        } else {
+            "repositories don't name"
+        },
+        public_repos
+            .iter()
+            .map(|docker_repo| docker_repo.code_str().to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+        format!("registry.example.com/{}", public_repos[0]).code_str(),
+        "confirm_public_push: true".code_str(),
+    ))
+}
+
+// Log in to the registries for some Docker repositories. The command is only run once, and in token
+// mode the token is used for each distinct registry. The output of the command may contain
+// credentials, so it's never logged.
+pub fn login(
+    docker_cli: &(impl DockerCli + ?Sized),
+    login: &Login,
+    docker_repos: &[&str],
+    interrupted: &Arc<AtomicBool>,
+) -> Result<(), Failure> {
+    info!(
+        "Logging in to the registry with {}\u{2026}",
+        login.command.join(" ").code_str(),
+    );
+
+    // Run the command. Its standard error stream goes to the terminal so the user can see what went
+    // wrong, but in token mode its standard output is the token.
+    let output = Command::new(&login.command[0]) // [ref:registry_login_command_nonempty]
+        .args(&login.command[1..])
+        .stdin(Stdio::inherit())
+        .stdout(if login.mode == LoginMode::Token {
+            Stdio::piped()
+        } else {
+            Stdio::inherit()
+        })
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(failure::user(format!(
+            "Unable to run {}.",
+            login.command.join(" ").code_str(),
+        )))?;
+
+    // Check if the command was interrupted or failed.
+    if output.status.code().is_none() {
+        interrupted.store(true, Ordering::SeqCst);
+        return Err(Failure::Interrupted);
+    }
+    if !output.status.success() {
+        return Err(Failure::User(
+            format!(
+                "Unable to log in to the registry. {} failed.",
+                login.command.join(" ").code_str(),
+            ),
+            None,
+        ));
+    }
+
+    // In token mode, pass the token to `docker login`.
+    if login.mode == LoginMode::Token {
+        let token = String::from_utf8_lossy(&output.stdout);
+        let token = token.trim();
+        if token.is_empty() {
+            return Err(Failure::User(
+                format!(
+                    "Unable to log in to the registry. {} didn't print a token.",
+                    login.command.join(" ").code_str(),
+                ),
+                None,
+            ));
+        }
+
+        let mut registries = docker_repos
+            .iter()
+            .map(|docker_repo| registry_host(docker_repo))
+            .collect::<Vec<_>>();
+        registries.sort_unstable();
+        registries.dedup();
+
+        for registry in registries {
+            docker::login(
+                docker_cli,
+                registry,
+                login.user.as_deref().unwrap_or_default(), // [ref:registry_user_for_token]
+                token,
+                interrupted,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{
+            docker::{FakeCli, Outcome},
+            failure::Failure,
+            registry::{login, public_push_warning, registry_host, Login, LoginMode},
+        },
+        std::sync::{atomic::AtomicBool, Arc},
+    };
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&arg| arg.to_owned()).collect()
+    }
+
+    #[test]
+    fn registry_host_docker_hub() {
+        assert_eq!(registry_host("toast"), None);
+        assert_eq!(registry_host("stephanmisc/toast"), None);
+    }
+
+    #[test]
+    fn registry_host_domain() {
+        assert_eq!(
+            registry_host("registry.example.com/team/toast"),
+            Some("registry.example.com"),
+        );
+    }
+
+    #[test]
+    fn registry_host_port() {
+        assert_eq!(registry_host("cache:5000/toast"), Some("cache:5000"));
+    }
+
+    #[test]
+    fn registry_host_localhost() {
+        assert_eq!(registry_host("localhost/toast"), Some("localhost"));
+    }
+
+    #[test]
+    fn registry_host_uppercase() {
+        assert_eq!(registry_host("Cache/toast"), Some("Cache"));
+    }
+
+    #[test]
+    fn registry_host_not_first_component() {
+        assert_eq!(registry_host("team/cache.example.com"), None);
+        assert_eq!(registry_host("localhost"), None);
+        assert_eq!(registry_host("toast:latest"), None);
+    }
+
+    #[test]
+    fn public_push_warning_docker_hub() {
+        let warning = public_push_warning(&["mycompany/toast-cache", "toast"]).unwrap();
+
+        assert!(warning.contains("following repositories don't name a registry"));
+        assert!(warning.contains("mycompany/toast-cache"));
+        assert!(warning.contains("registry.example.com/mycompany/toast-cache"));
+    }
+
+    #[test]
+    fn public_push_warning_deduplicated() {
+        let warning =
+            public_push_warning(&["toast", "registry.example.com/toast", "toast"]).unwrap();
+
+        assert!(warning.contains("following repository doesn't name a registry"));
+        // Once in the list of repositories and once in the example
+        assert_eq!(warning.matches("toast").count(), 2);
+    }
+
+    #[test]
+    fn public_push_warning_registries() {
+        assert_eq!(
+            public_push_warning(&[
+                "registry.example.com/team/toast",
+                "cache:5000/toast",
+                "localhost/toast",
+                "docker.io/mycompany/toast",
+            ]),
+            None,
+        );
+        assert_eq!(public_push_warning(&[]), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn login_command_mode() {
+        let cli = FakeCli::new(vec![]);
+        let login_settings = Login {
+            command: args(&["true"]),
+            mode: LoginMode::Command,
+            user: None,
+        };
+
+        login(
+            &cli,
+            &login_settings,
+            &["registry.example.com/toast"],
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert!(cli.calls().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn login_command_mode_failure() {
+        let cli = FakeCli::new(vec![]);
+        let login_settings = Login {
+            command: args(&["false"]),
+            mode: LoginMode::Command,
+            user: None,
+        };
+
+        let result = login(
+            &cli,
+            &login_settings,
+            &["registry.example.com/toast"],
+            &Arc::new(AtomicBool::new(false)),
+        );
+
+        assert!(matches!(result, Err(Failure::User(_, _))));
+        assert!(result.unwrap_err().to_string().contains("false"));
+        assert!(cli.calls().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn login_token_mode() {
+        let cli = FakeCli::new(vec![]);
+        let login_settings = Login {
+            command: args(&["sh", "-c", "printf 'hunter2\\n'"]),
+            mode: LoginMode::Token,
+            user: Some("flynn".to_owned()),
+        };
+
+        login(
+            &cli,
+            &login_settings,
+            &["registry.example.com/toast"],
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            cli.calls(),
+            vec![args(&[
+                "login",
+                "--username",
+                "flynn",
+                "--password-stdin",
+                "registry.example.com",
+            ])],
+        );
+        assert_eq!(cli.stdin(), b"hunter2");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn login_token_mode_multiple_registries() {
+        let cli = FakeCli::new(vec![]);
+        let login_settings = Login {
+            command: args(&["sh", "-c", "printf hunter2"]),
+            mode: LoginMode::Token,
+            user: Some("flynn".to_owned()),
+        };
+
+        login(
+            &cli,
+            &login_settings,
+            &[
+                "registry.example.com/toast",
+                "cache:5000/toast",
+                "registry.example.com/team/toast",
+            ],
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            cli.calls(),
+            vec![
+                args(&[
+                    "login",
+                    "--username",
+                    "flynn",
+                    "--password-stdin",
+                    "cache:5000",
+                ]),
+                args(&[
+                    "login",
+                    "--username",
+                    "flynn",
+                    "--password-stdin",
+                    "registry.example.com",
+                ]),
+            ],
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn login_token_mode_docker_hub() {
+        let cli = FakeCli::new(vec![]);
+        let login_settings = Login {
+            command: args(&["sh", "-c", "printf hunter2"]),
+            mode: LoginMode::Token,
+            user: Some("flynn".to_owned()),
+        };
+
+        login(
+            &cli,
+            &login_settings,
+            &["toast"],
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            cli.calls(),
+            vec![args(&["login", "--username", "flynn", "--password-stdin"])],
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn login_token_mode_empty_token() {
+        let cli = FakeCli::new(vec![]);
+        let login_settings = Login {
+            command: args(&["true"]),
+            mode: LoginMode::Token,
+            user: Some("flynn".to_owned()),
+        };
+
+        let result = login(
+            &cli,
+            &login_settings,
+            &["toast"],
+            &Arc::new(AtomicBool::new(false)),
+        );
+
+        assert!(matches!(result, Err(Failure::User(_, _))));
+        assert!(cli.calls().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn login_token_mode_rejected() {
+        let cli = FakeCli::new(vec![Outcome {
+            code: Some(1_i32),
+            stdout: vec![],
+            stderr: b"Error response from daemon: unauthorized".to_vec(),
+        }]);
+        let login_settings = Login {
+            command: args(&["sh", "-c", "printf hunter2"]),
+            mode: LoginMode::Token,
+            user: Some("flynn".to_owned()),
+        };
+
+        let result = login(
+            &cli,
+            &login_settings,
+            &["toast"],
+            &Arc::new(AtomicBool::new(false)),
+        );
+
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("unauthorized"));
+        assert!(!message.contains("hunter2"));
+    }
+}
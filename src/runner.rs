@@ -1,26 +1,49 @@
 use {
     crate::{
-        cache, docker, failure,
+        cache, clean,
+        cleanup::Cleanup,
+        docker,
+        docker::DockerCli,
+        failure,
         failure::Failure,
-        tar,
-        toastfile::{command, location, user, Task, Toastfile},
+        format,
+        format::CodeStr,
+        schedule, secrets, stats, tar, toastfile,
+        toastfile::{
+            base_image, cache_ttl, command, gpus, input_modes, location, timeout, user,
+            MappingPath, Secret, Task, Toastfile,
+        },
+        trace, units, JOBS_ENV_VAR,
     },
+    scopeguard::guard,
     std::{
-        collections::{HashMap, HashSet},
-        io::{Seek, SeekFrom},
-        path::PathBuf,
-        sync::{
-            atomic::{AtomicBool, Ordering},
-            Arc, Mutex,
-        },
+        collections::{BTreeMap, HashMap},
+        env, fmt,
+        fmt::Write as _,
+        fs,
+        io::{Read, Seek, SeekFrom},
+        path::{Path, PathBuf},
+        sync::{atomic::AtomicBool, Arc, Mutex},
+        time::{Duration, Instant},
     },
-    tempfile::tempfile,
+    tempfile::{tempdir, tempfile, TempDir},
+    typed_path::UnixPath,
 };
 
+// Where the secrets of a task are mounted in the container [ref:secrets_mounted]
+const SECRETS_DIR: &str = "/run/secrets";
+
+// How many entries of the archive to show when it can't be copied into the container
+const ARCHIVE_ENTRIES_SHOWN: usize = 5;
+
+// How long to spend copying `output_paths_on_failure` out of the container after the user
+// interrupts a task
+const INTERRUPT_EXTRACTION_TIMEOUT: Duration = Duration::from_secs(10);
+
 // A context is an image that may need to be cleaned up.
 #[derive(Clone)]
 pub struct Context {
-    pub docker_cli: String,
+    pub cleanup: Arc<Cleanup>,
     pub image: String,
     pub persist: bool,
     pub interrupted: Arc<AtomicBool>,
@@ -30,30 +53,360 @@ impl Drop for Context {
     fn drop(&mut self) {
         // Delete the image if needed.
         if !self.persist {
-            if let Err(e) = docker::delete_image(&self.docker_cli, &self.image, &self.interrupted) {
+            if let Err(e) = self.cleanup.delete_image(&self.image, &self.interrupted) {
                 error!("{}", e);
             }
         }
     }
 }
 
+// Information about a task which is reported back to the caller of `run`
+#[derive(Default)]
+pub struct Report {
+    // The resources used by the container, if they were measured
+    pub usage: Option<stats::Usage>,
+
+    // The image produced by the task, if it's kept after the run
+    pub image: Option<String>,
+
+    // Whether the task was skipped because its image was found in a cache
+    pub cached: bool,
+
+    // A fingerprint of the changes the task made to the filesystem, if requested
+    pub fingerprint: Option<BTreeMap<String, String>>,
+
+    // The paths the task changed in the container, if it failed and they were requested
+    pub diff: Option<Vec<(docker::Change, String)>>,
+
+    // The cache key of the task (i.e., the name of its image), if caching was enabled for it
+    pub cache_key: Option<String>,
+
+    // How long the task took, if it actually ran (i.e., it wasn't cached) and succeeded
+    pub duration: Option<Duration>,
+
+    // The sizes of the images pulled from and pushed to the remote cache
+    pub bytes_pulled: u64,
+    pub bytes_pushed: u64,
+
+    // The phase of the task which failed, if it failed
+    pub failed_phase: Option<Phase>,
+}
+
+impl Report {
+    // Remember which phase of the task failed, and pass the failure along.
+    fn failed_during(&mut self, phase: Phase, failure: Failure) -> Failure {
+        self.failed_phase = Some(phase);
+        failure
+    }
+}
+
+// The phases of running a task, for explaining where it failed
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Phase {
+    Hashing,
+    CacheLookup,
+    Pull,
+    ContainerCreation,
+    CommandExecution,
+    OutputExtraction,
+    Fingerprinting,
+    Commit,
+    Push,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Hashing => "input hashing",
+                Self::CacheLookup => "cache lookup",
+                Self::Pull => "image pull",
+                Self::ContainerCreation => "container creation",
+                Self::CommandExecution => "command execution",
+                Self::OutputExtraction => "output extraction",
+                Self::Fingerprinting => "fingerprinting",
+                Self::Commit => "commit",
+                Self::Push => "cache upload",
+            },
+        )
+    }
+}
+
+// Explain which task failed and in which phase, if the phase is known. Interruptions are passed
+// along unchanged, since they aren't the fault of the task.
+pub fn describe_failure(task_name: &str, phase: Option<Phase>, failure: Failure) -> Failure {
+    match (phase, failure) {
+        (Some(phase), Failure::User(message, source)) => Failure::User(
+            format!(
+                "Task {} failed during {}: {}",
+                task_name.code_str(),
+                phase,
+                message,
+            ),
+            source,
+        ),
+        (Some(phase), Failure::System(message, source)) => Failure::System(
+            format!(
+                "Task {} failed during {}: {}",
+                task_name.code_str(),
+                phase,
+                message,
+            ),
+            source,
+        ),
+        (_, failure) => failure,
+    }
+}
+
+// The prefix of the environment variables which tell a task about the images of its dependencies
+const IMAGE_ENV_VAR_PREFIX: &str = "TOAST_IMAGE_";
+
+// Compute the name of the environment variable which holds the image of a task. Characters which
+// aren't allowed in environment variable names are replaced with underscores.
+pub fn image_env_var(task_name: &str) -> String {
+    format!(
+        "{}{}",
+        IMAGE_ENV_VAR_PREFIX,
+        task_name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            })
+            .collect::<String>(),
+    )
+}
+
+// Compute the environment variables which tell a task about the images produced by its (transitive)
+// dependencies. `images` maps each completed task to its image.
+pub fn image_environment(
+    toastfile: &Toastfile,
+    task_name: &str,
+    images: &HashMap<String, String>,
+) -> HashMap<String, String> {
+    schedule::compute(toastfile, &[task_name])
+        .into_iter()
+        .filter(|dependency| *dependency != task_name)
+        .filter_map(|dependency| {
+            images
+                .get(dependency)
+                .map(|image| (image_env_var(dependency), image.clone()))
+        })
+        .collect()
+}
+
+// Explain that the command of a task failed. If the task's root filesystem is read-only, that might
+// be why, and the command's own error (e.g., `Read-only file system`) may not make that clear.
+fn command_failed_message(task: &Task) -> String {
+    if task.read_only_rootfs {
+        format!(
+            "The command failed. Note that the task has {}, so the command can only write to its \
+             location and its mount paths.",
+            "read_only_rootfs: true".code_str(),
+        )
+    } else {
+        "The command failed.".to_owned()
+    }
+}
+
+// Add the first few entries of the archive to a failure to copy it into the container. Docker's
+// errors (e.g., `not a directory`) don't say which entry caused the problem, which is usually one
+// of the directories leading to the location (e.g., because it's a symbolic link in the image).
+fn explain_copy_failure<R: Read + Seek>(
+    failure: Failure,
+    tar_file: &mut R,
+    archive_root_acr: &UnixPath,
+    task: &Task,
+    location: &UnixPath,
+) -> Failure {
+    let entries = if tar_file.seek(SeekFrom::Start(0)).is_ok() {
+        tar::describe_entries(tar_file, ARCHIVE_ENTRIES_SHOWN)
+    } else {
+        vec![]
+    };
+
+    let mut explanation = format!(
+        "The archive was extracted at {} for the location {}.",
+        archive_root_acr.to_string_lossy().code_str(),
+        location.to_string_lossy().code_str(),
+    );
+    if !entries.is_empty() {
+        explanation.push_str(" Its first entries were:");
+        for entry in entries {
+            let _ = write!(explanation, "\n- {entry}");
+        }
+    }
+    if !task.copy_via_exec {
+        let _ = write!(
+            explanation,
+            "\nIf the image doesn't handle these paths the way {} expects, try setting {} for \
+             the task.",
+            "docker container cp".code_str(),
+            "copy_via_exec: true".code_str(),
+        );
+    }
+
+    match failure {
+        Failure::Interrupted => Failure::Interrupted,
+        Failure::System(message, source) => {
+            Failure::System(format!("{}\n{explanation}", message.trim_end()), source)
+        }
+        Failure::User(message, source) => {
+            Failure::User(format!("{}\n{explanation}", message.trim_end()), source)
+        }
+    }
+}
+
+// Determine where the `output_paths` of a task go on the host, relative to the output directory.
+fn output_host_paths(task: &Task) -> Vec<&UnixPath> {
+    task.output_paths
+        .iter()
+        .map(|path| toastfile::output_host_path(path))
+        .collect()
+}
+
+// Copy the `output_paths_on_failure` of a task which the user interrupted, if the task asks for
+// that. The user wants to quit, so this is only a best-effort attempt: it gives up at the
+// `deadline`, and problems are logged rather than returned. Returns whether the copy was attempted.
+fn extract_failure_outputs_on_interrupt(
+    docker_cli: &(impl DockerCli + ?Sized),
+    container: &str,
+    task: &Task,
+    location: &UnixPath,
+    output_dir: &Path,
+    deadline: Instant,
+) -> bool {
+    if !task.extract_failure_outputs_on_interrupt || task.output_paths_on_failure.is_empty() {
+        return false;
+    }
+
+    warn!(
+        "The task was interrupted. Attempting to copy its {} out of the container before \
+            cleaning up. This is best-effort and may be incomplete.",
+        "output_paths_on_failure".code_str(),
+    );
+
+    if let Err(e) = docker::copy_from_container_until(
+        docker_cli,
+        container,
+        &task.output_paths_on_failure,
+        location,
+        output_dir,
+        task.resolve_output_symlinks,
+        deadline,
+    ) {
+        warn!("{}", e);
+    }
+
+    true
+}
+
+// [tag:secrets_mounted] Write the secrets of a task to files in a temporary directory, and return
+// the directory along with the paths to mount: the task's own `mount_paths`, plus a read-only mount
+// of each file at `/run/secrets/<name>`. The files are mounted one by one, since the directory
+// itself is only accessible to the current user. The values are masked in the logs
+// [ref:secrets_masked].
+fn prepare_secrets(
+    toastfile_dir: &Path,
+    task: &Task,
+) -> Result<(Option<TempDir>, Vec<MappingPath>), Failure> {
+    let mut mount_paths = task.mount_paths.clone();
+    if task.secrets.is_empty() {
+        return Ok((None, mount_paths));
+    }
+
+    let secrets_dir =
+        tempdir().map_err(failure::system("Unable to create temporary directory."))?;
+
+    let mut names = task.secrets.keys().collect::<Vec<_>>();
+    names.sort();
+    for name in names {
+        let contents = match &task.secrets[name] {
+            Secret::File(path) => {
+                fs::read(toastfile_dir.join(path)).map_err(failure::user(format!(
+                    "Unable to read secret {} from file {}.",
+                    name.code_str(),
+                    path.to_string_lossy().code_str(),
+                )))?
+            }
+            Secret::Env(variable) => env::var(variable)
+                .map_err(failure::user(format!(
+                    "Unable to read secret {} from environment variable {}.",
+                    name.code_str(),
+                    variable.code_str(),
+                )))?
+                .into_bytes(),
+        };
+
+        // A file often ends with a newline which isn't part of the secret. Binary secrets can't
+        // show up in the logs as is, so they aren't masked.
+        if let Ok(text) = std::str::from_utf8(&contents) {
+            secrets::register(text.trim_end());
+        }
+
+        let host_path = secrets_dir.path().join(name);
+        fs::write(&host_path, contents).map_err(failure::system(format!(
+            "Unable to write file {}.",
+            host_path.to_string_lossy().code_str(),
+        )))?;
+
+        mount_paths.push(MappingPath {
+            host_path,
+            container_path: UnixPath::new(SECRETS_DIR).join(name),
+            readonly: Some(true),
+        });
+    }
+
+    Ok((Some(secrets_dir), mount_paths))
+}
+
+// Measure an image for the cache metrics. The metrics are only informational, so failures (other
+// than interruptions) are reported as warnings.
+fn image_bytes(
+    docker_cli: &str,
+    image: &str,
+    interrupted: &Arc<AtomicBool>,
+) -> Result<u64, Failure> {
+    match docker::image_size(docker_cli, image, interrupted) {
+        Ok(size) => Ok(size),
+        Err(Failure::Interrupted) => Err(Failure::Interrupted),
+        Err(e) => {
+            warn!("{}", e);
+            Ok(0)
+        }
+    }
+}
+
 // Run a task in a given context and return a new context. The returned context should not be `None`
 // if `need_context` is `true` and `Err(Failure::Interrupted | Failure::System(_, _))` was not
 // returned.
+#[allow(clippy::fn_params_excessive_bools)]
 #[allow(clippy::too_many_arguments)]
 #[allow(clippy::too_many_lines)]
 pub fn run(
     settings: &super::Settings,
     environment: &HashMap<String, String>,
     interrupted: &Arc<AtomicBool>,
-    active_containers: &Arc<Mutex<HashSet<String>>>,
+    active_containers: &Arc<Mutex<HashMap<String, String>>>,
     toastfile: &Toastfile,
+    task_name: &str,
     task: &Task,
     caching_enabled: bool,
     force_pull: bool,
+    architecture: &str,
     context: Context,
     need_context: bool,
+    image_environment: &HashMap<String, String>,
+    background_pull: &mut Option<docker::BackgroundPull>,
+    fingerprint: bool,
+    report: &mut Report,
 ) -> (Result<(), Failure>, Option<Context>) {
+    // Keep track of how long the task takes, for the cache metrics.
+    let start = Instant::now();
+
     // All relative paths are relative to where the toastfile lives.
     let mut toastfile_dir = PathBuf::from(&settings.toastfile_path);
     toastfile_dir.pop();
@@ -64,48 +417,76 @@ pub fn run(
     let location = location(toastfile, task);
     let user = user(toastfile, task);
     let command = command(toastfile, task);
+    let docker_cli = toastfile::docker_cli(task, &settings.docker_cli);
+    let docker_repo = toastfile::docker_repo(task, &settings.docker_repo);
+    let cleanup = settings.cleanup.for_docker_cli(docker_cli);
 
     // Create a temporary archive for the input file contents.
     let tar_file = match tempfile() {
         Ok(tar_file) => tar_file,
         Err(e) => {
             return (
-                Err(failure::system("Unable to create temporary file.")(e)),
+                Err(report.failed_during(
+                    Phase::Hashing,
+                    failure::system("Unable to create temporary file.")(e),
+                )),
                 Some(context),
             )
         }
     };
 
-    // Write to the archive.
+    // Determine where the archive is extracted. Usually that's the root of the filesystem, so the
+    // archive contains the location itself. But if the root filesystem is read-only, only the volume
+    // at the location is writable, so the archive is extracted there instead.
+    let (archive_root_acr, archive_location_acr) = if task.read_only_rootfs {
+        (location.as_ref(), UnixPath::new("/"))
+    } else {
+        (UnixPath::new("/"), location.as_ref())
+    };
+
+    // Write to the archive. The hash of the inputs is only needed for the cache key, so we don't
+    // bother computing it if caching is disabled for the task. In that case, the task neither reads
+    // from nor writes to any cache, so the resulting image name is only used for the context.
+    let hash_span = settings.trace.span("hash", trace::PHASE);
     let (mut tar_file, input_files_hash) = match tar::create(
         "Reading files\u{2026}",
         tar_file,
         &task.input_paths,
         &task.excluded_input_paths,
+        task.allow_empty_globs,
+        task.respect_gitignore,
         &toastfile_dir,
-        &location,
+        archive_location_acr,
+        input_modes(task),
+        caching_enabled,
+        &settings.input_size_limits,
         interrupted,
     ) {
         Ok((tar_file, input_files_hash)) => (tar_file, input_files_hash),
-        Err(e) => return (Err(e), Some(context)),
+        Err(e) => return (Err(report.failed_during(Phase::Hashing, e)), Some(context)),
     };
+    drop(hash_span);
 
     // Seek back to the beginning of the archive to prepare for copying it into the container.
     if let Err(e) = tar_file.seek(SeekFrom::Start(0)) {
         return (
-            Err(failure::system("Unable to seek temporary file.")(e)),
+            Err(report.failed_during(
+                Phase::ContainerCreation,
+                failure::system("Unable to seek temporary file.")(e),
+            )),
             Some(context),
         );
-    };
+    }
 
     // Compute the name of the image that this task produces.
     let image = cache::image_name(
         &context.image,
-        &settings.docker_repo,
+        docker_repo,
         toastfile,
         task,
         &input_files_hash,
         environment,
+        architecture,
     );
 
     // If we need to return the same image as the one that was passed in, we should be careful to
@@ -113,6 +494,7 @@ pub fn run(
     // be dropped at the end of this function, which may delete the image that we need to return.
     // This variable will be used to determine whether that situation applies.
     let context_unchanged = image == context.image;
+    report.cache_key = caching_enabled.then(|| image.clone());
 
     // Construct the environment.
     let mut task_environment = HashMap::<String, String>::new();
@@ -121,43 +503,148 @@ pub fn run(
         task_environment.insert(variable.clone(), environment[variable].clone());
     }
 
+    // Tell the task how much parallelism is available. This is deliberately not part of the cache
+    // key, since it depends on the host rather than the task.
+    task_environment.insert(JOBS_ENV_VAR.to_owned(), settings.jobs.to_string());
+
+    // Tell the task which images its dependencies produced. These are derived from the cache keys of
+    // the dependencies, so they aren't part of the cache key either.
+    task_environment.extend(
+        image_environment
+            .iter()
+            .map(|(variable, image)| (variable.clone(), image.clone())),
+    );
+
     // Check the cache, if applicable.
     let mut cached = false;
     if caching_enabled {
-        // Check the local cache.
-        cached = settings.read_local_cache
-            && match docker::image_exists(&settings.docker_cli, &image, interrupted) {
-                Ok(exists) => exists,
-                Err(e) => return (Err(e), Some(context)),
+        // Check the local cache and then the remote cache, depending on which are enabled.
+        let policy = match (settings.read_local_cache, settings.read_remote_cache) {
+            (true, true) => Some(docker::PullPolicy::IfMissing),
+            (true, false) => Some(docker::PullPolicy::Never),
+            (false, true) => Some(docker::PullPolicy::Always),
+            (false, false) => None,
+        };
+        if let Some(policy) = policy {
+            let _span = settings.trace.span("cache", trace::PHASE);
+            cached = match docker::ensure_image(
+                docker_cli,
+                &image,
+                docker::ImagePurpose::CacheEntry,
+                policy,
+                &settings.extra_pull_arguments,
+                interrupted,
+            ) {
+                Ok(docker::Availability::Pulled) => {
+                    match image_bytes(docker_cli, &image, interrupted) {
+                        Ok(bytes) => report.bytes_pulled += bytes,
+                        Err(e) => {
+                            return (
+                                Err(report.failed_during(Phase::CacheLookup, e)),
+                                Some(context),
+                            )
+                        }
+                    }
+                    true
+                }
+                Ok(availability) => availability.available(),
+                Err(e) => {
+                    return (
+                        Err(report.failed_during(Phase::CacheLookup, e)),
+                        Some(context),
+                    )
+                }
             };
+        }
 
-        // Check the remote cache.
-        if !cached && settings.read_remote_cache {
-            if let Err(e) = docker::pull_image(&settings.docker_cli, &image, interrupted) {
-                // If the pull failed, it could be because the user killed the child process (e.g.,
-                // by hitting CTRL+C).
-                if interrupted.load(Ordering::SeqCst) {
-                    return (Err(e), Some(context));
+        // Check the SSH cache. If the host can't be reached, we treat it as a cache miss.
+        if let (false, true, Some(ssh_cache)) =
+            (cached, settings.read_ssh_cache, &settings.ssh_cache)
+        {
+            let _span = settings.trace.span("cache", trace::PHASE);
+            match ssh_cache
+                .image_exists(&image, interrupted)
+                .and_then(|exists| {
+                    if exists {
+                        ssh_cache
+                            .fetch_image(docker_cli, &image, interrupted)
+                            .map(|()| true)
+                    } else {
+                        Ok(false)
+                    }
+                }) {
+                Ok(exists) => cached = exists,
+                Err(Failure::Interrupted) => return (Err(Failure::Interrupted), Some(context)),
+                Err(e) => warn!("{}", e),
+            }
+        }
+
+        // Treat the cached image as a miss if it's older than the task allows. Stale images are
+        // deleted, since running the task will replace them anyway.
+        if let (true, false, Some(ttl)) = (cached, context_unchanged, cache_ttl(task)) {
+            let _span = settings.trace.span("cache", trace::PHASE);
+            match docker::image_created(docker_cli, &image, interrupted) {
+                Ok(created) => {
+                    if created.elapsed().is_ok_and(|age| age > ttl) {
+                        info!(
+                            "The cached image is older than the {} of the task. Ignoring it.",
+                            "cache_ttl".code_str(),
+                        );
+                        cached = false;
+                        if let Err(e) = cleanup.delete_image(&image, interrupted) {
+                            warn!("{}", e);
+                        }
+                    }
+                }
+                Err(Failure::Interrupted) => return (Err(Failure::Interrupted), Some(context)),
+                Err(e) => {
+                    warn!("{}", e);
+                    cached = false;
                 }
-            } else {
-                cached = true;
             }
         }
     }
 
+    // With `--only-cached`, the cache was checked before anything ran, but the image could have
+    // disappeared since then (e.g., because it was older than the `cache_ttl` of the task).
+    if settings.only_cached && !cached {
+        return (
+            Err(report.failed_during(
+                Phase::CacheLookup,
+                Failure::User(
+                    format!(
+                        "The task isn't cached, and {} prevents it from running.",
+                        "--only-cached".code_str(),
+                    ),
+                    None,
+                ),
+            )),
+            Some(context),
+        );
+    }
+
     // If the task is cached, extract the output files if applicable.
+    report.cached = cached;
     if cached {
+        settings.trace.progress().task_cached(task_name);
+
         // The task is cached. Check if there are any output files.
-        if !task.output_paths.is_empty() {
+        if !task.output_paths.is_empty() || !task.output_paths_always.is_empty() {
+            let _span = settings.trace.span("copy-out", trace::PHASE);
+
             // We need to create a container from which we can extract the output files.
             let container = match docker::create_container(
-                &settings.docker_cli,
+                docker_cli,
                 &image,
                 &toastfile_dir,
                 &task_environment,
                 &task.mount_paths,
                 task.mount_readonly,
+                &task.volumes,
+                docker_repo,
+                task.read_only_rootfs,
                 &task.ports,
+                gpus(task).as_deref(),
                 &location,
                 &user,
                 &command,
@@ -165,34 +652,56 @@ pub fn run(
                 interrupted,
             ) {
                 Ok(container) => container,
-                Err(e) => return (Err(e), Some(context)),
+                Err(e) => {
+                    return (
+                        Err(report.failed_during(Phase::OutputExtraction, e)),
+                        Some(context),
+                    )
+                }
             };
 
             // Delete the container when we're done.
             defer! {{
-              if let Err(e) = docker::delete_container(
-                  &settings.docker_cli,
-                  &container,
-                  interrupted
-              ) {
+              if let Err(e) = cleanup.delete_container(&container, interrupted) {
                 error!("{}", e);
               }
             }}
 
+            // Remove stale output files from previous runs, if requested.
+            if task.clean_output_paths {
+                if let Err(e) = clean::clean_output_paths(
+                    output_dir,
+                    &output_host_paths(task),
+                    task.backup_output_paths,
+                ) {
+                    return (
+                        Err(report.failed_during(Phase::OutputExtraction, e)),
+                        Some(context),
+                    );
+                }
+            }
+
             // Extract the output files from the container.
-            if let Err(e) = docker::copy_from_container(
-                &settings.docker_cli,
-                &container,
-                &task.output_paths,
-                &location,
-                output_dir,
-                interrupted,
-            ) {
-                return (Err(e), Some(context));
+            for paths in [&task.output_paths, &task.output_paths_always] {
+                if let Err(e) = docker::copy_from_container(
+                    docker_cli,
+                    &container,
+                    paths,
+                    &location,
+                    output_dir,
+                    task.resolve_output_symlinks,
+                    interrupted,
+                ) {
+                    return (
+                        Err(report.failed_during(Phase::OutputExtraction, e)),
+                        Some(context),
+                    );
+                }
             }
         }
 
         // The cached image becomes the new context.
+        report.image = Some(image.clone());
         (
             Ok(()),
             Some(if context_unchanged {
@@ -202,124 +711,426 @@ pub fn run(
                     image,
                     persist: true,
                     interrupted: interrupted.clone(),
-                    docker_cli: settings.docker_cli.clone(),
+                    cleanup: cleanup.clone(),
                 }
             }),
         )
     } else {
-        // Pull the image if necessary. Force reading from the remote if configured.
-        if force_pull
-            || !match docker::image_exists(&settings.docker_cli, &context.image, interrupted) {
-                Ok(exists) => exists,
-                Err(e) => return (Err(e), Some(context)),
+        // Write the secrets to a temporary directory, from which they're mounted into the
+        // container. The directory is deleted when this function returns.
+        let (_secrets_dir, mount_paths) = match prepare_secrets(&toastfile_dir, task) {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                return (
+                    Err(report.failed_during(Phase::ContainerCreation, e)),
+                    Some(context),
+                )
             }
+        };
+
+        // If the image is being pulled in the background, wait for that to finish. Its errors are
+        // only reported now that we know the image is needed.
+        let pull_span = settings.trace.span("pull", trace::PHASE);
+        let prepulled = if background_pull
+            .as_ref()
+            .is_some_and(|pull| pull.image() == context.image)
         {
-            if let Err(e) = docker::pull_image(&settings.docker_cli, &context.image, interrupted) {
-                return (Err(e), Some(context));
+            // The `unwrap` is safe due to the check above.
+            if let Err(e) = background_pull.take().unwrap().join() {
+                return (Err(report.failed_during(Phase::Pull, e)), Some(context));
             }
-        }
 
-        // Create a container from the image.
-        let container = match docker::create_container(
-            &settings.docker_cli,
-            &context.image,
-            &toastfile_dir,
-            &task_environment,
-            &task.mount_paths,
-            task.mount_readonly,
-            &task.ports,
-            &location,
-            &user,
-            &command,
-            &task.extra_docker_arguments,
-            interrupted,
-        ) {
-            Ok(container) => container,
-            Err(e) => return (Err(e), Some(context)),
+            true
+        } else {
+            false
         };
 
-        // If the user interrupts the program, kill the container. The `unwrap` will only fail if a
-        // panic already occurred.
-        {
-            active_containers.lock().unwrap().insert(container.clone());
+        // Pull the image if necessary. Force reading from the remote if configured.
+        if !prepulled {
+            if let Err(e) = docker::ensure_image(
+                docker_cli,
+                &context.image,
+                if context.image == base_image(toastfile, task) {
+                    docker::ImagePurpose::BaseImage
+                } else {
+                    docker::ImagePurpose::Context
+                },
+                if force_pull {
+                    docker::PullPolicy::Always
+                } else {
+                    docker::PullPolicy::IfMissing
+                },
+                &settings.extra_pull_arguments,
+                interrupted,
+            ) {
+                return (Err(report.failed_during(Phase::Pull, e)), Some(context));
+            }
         }
+        drop(pull_span);
 
-        // Delete the container when we're done.
-        defer! {{
-          // If the user interrupts the program, don't bother killing the container. We're about to
-          // kill it here. The `unwrap` will only fail if a panic already occurred.
-          {
-            active_containers.lock().unwrap().remove(&container);
-          }
-
-          // Delete the container.
-          if let Err(e) = docker::delete_container(&settings.docker_cli, &container, interrupted) {
-            error!("{}", e);
-          }
-        }}
-
-        // Copy files into the container. If `task.input_paths` is empty, then this will just create
-        // a directory for `location`.
-        if let Err(e) = docker::copy_into_container(
-            &settings.docker_cli,
-            &container,
-            &mut tar_file,
-            interrupted,
-        ) {
-            return (Err(e), Some(context));
-        }
+        // Run the task in a fresh container, retrying user failures (but not interruptions or
+        // system failures) up to the number of times the task allows. Only the final attempt
+        // determines the outcome of the task.
+        let attempts = task.retries.saturating_add(1);
+        let mut attempt = 1;
+        let (container, result, monitor) = loop {
+            // Start each attempt with the whole archive.
+            if attempt > 1 {
+                if let Err(e) = tar_file.seek(SeekFrom::Start(0)) {
+                    return (
+                        Err(report.failed_during(
+                            Phase::ContainerCreation,
+                            failure::system("Unable to seek temporary file.")(e),
+                        )),
+                        Some(context),
+                    );
+                }
+            }
+
+            // Create a container from the image. With `copy_via_exec`, the container just stays up
+            // until it's stopped, and the command runs via `docker exec` once the input files are
+            // in place.
+            let create_span = settings.trace.span("create", trace::PHASE);
+            let (container_user, container_command) = if task.copy_via_exec {
+                ("root", docker::KEEP_ALIVE_COMMAND)
+            } else {
+                (user.as_str(), command.as_str())
+            };
+            let container = match docker::create_container(
+                docker_cli,
+                &context.image,
+                &toastfile_dir,
+                &task_environment,
+                &mount_paths,
+                task.mount_readonly,
+                &task.volumes,
+                docker_repo,
+                task.read_only_rootfs,
+                &task.ports,
+                gpus(task).as_deref(),
+                &location,
+                container_user,
+                container_command,
+                &task.extra_docker_arguments,
+                interrupted,
+            ) {
+                Ok(container) => container,
+                Err(e) => {
+                    return (
+                        Err(report.failed_during(Phase::ContainerCreation, e)),
+                        Some(context),
+                    )
+                }
+            };
+
+            // If the user interrupts the program, kill the container. The `unwrap` will only fail
+            // if a panic already occurred.
+            {
+                active_containers
+                    .lock()
+                    .unwrap()
+                    .insert(container.clone(), docker_cli.to_owned());
+            }
 
-        // Start the container to run the command.
-        let result = docker::start_container(&settings.docker_cli, &container, interrupted)
+            // Delete the container when we're done with it, which is at the end of this attempt if
+            // the task is retried.
+            let container = guard(container, |container| {
+                // If the user interrupts the program, don't bother killing the container. We're
+                // about to kill it here. The `unwrap` will only fail if a panic already occurred.
+                {
+                    active_containers.lock().unwrap().remove(&container);
+                }
+
+                // Delete the container.
+                if let Err(e) = cleanup.delete_container(&container, interrupted) {
+                    error!("{}", e);
+                }
+            });
+
+            // Copy files into the container. If `task.input_paths` is empty, then this will just
+            // create a directory for `location`. With `copy_via_exec`, the container has to be
+            // running first.
+            let copy_result = if task.copy_via_exec {
+                docker::start_container_in_background(docker_cli, &container, interrupted).and_then(
+                    |()| {
+                        docker::copy_into_container_via_exec(
+                            docker_cli,
+                            &container,
+                            archive_root_acr,
+                            &mut tar_file,
+                            interrupted,
+                        )
+                    },
+                )
+            } else {
+                docker::copy_into_container(
+                    docker_cli,
+                    &container,
+                    archive_root_acr,
+                    &mut tar_file,
+                    interrupted,
+                )
+            };
+            if let Err(e) = copy_result {
+                return (
+                    Err(report.failed_during(
+                        Phase::ContainerCreation,
+                        explain_copy_failure(e, &mut tar_file, archive_root_acr, task, &location),
+                    )),
+                    Some(context),
+                );
+            }
+            drop(create_span);
+
+            // If requested, measure the resources used by the container while it runs.
+            let monitor = settings
+                .stats_interval
+                .map(|interval| stats::Monitor::start(docker_cli, &container, interval));
+
+            // Stop the container if it runs for too long, if the task has a timeout.
+            let task_timeout = timeout(toastfile, task);
+            let watchdog = task_timeout
+                .map(|duration| docker::Watchdog::start(docker_cli, &container, duration));
+
+            // Start the container to run the command. If there is nothing to run, the task only
+            // stages files into the image, so we can skip starting the container and just commit
+            // it.
+            let run_span = settings.trace.span("run", trace::PHASE);
+            let result = if command.is_empty() && task.exec_after_start.is_empty() {
+                debug!("There is no command to run. Skipping starting the container.");
+                Ok(())
+            } else if task.copy_via_exec {
+                // The container is already running [ref:copy_via_exec_nand_exec_after_start].
+                docker::exec_in_container(
+                    docker_cli,
+                    &container,
+                    &task_environment,
+                    &location,
+                    &user,
+                    &command,
+                    task.max_log_lines.or(settings.max_log_lines),
+                    settings
+                        .prefix_output
+                        .then(|| format::output_prefix(task_name))
+                        .as_deref(),
+                    interrupted,
+                )
+            } else if task.exec_after_start.is_empty() {
+                docker::start_container(
+                    docker_cli,
+                    &container,
+                    task.max_log_lines.or(settings.max_log_lines),
+                    settings
+                        .prefix_output
+                        .then(|| format::output_prefix(task_name))
+                        .as_deref(),
+                    interrupted,
+                )
+            } else {
+                docker::start_container_with_exec(
+                    docker_cli,
+                    &container,
+                    &task.exec_after_start,
+                    &task_environment,
+                    &location,
+                    &user,
+                    interrupted,
+                )
+            }
             .map_err(|e| match e {
                 Failure::Interrupted => e,
-                Failure::System(_, _) | Failure::User(_, _) => {
-                    Failure::User("Task failed.".to_owned(), None)
-                }
+                Failure::System(_, _) => Failure::User(command_failed_message(task), None),
+                // Keep the exit code of the command [ref:exit_codes].
+                Failure::User(_, source) => Failure::User(command_failed_message(task), source),
             });
 
+            // With `copy_via_exec`, the container is still running, so stop it before anything is
+            // copied out of it or committed. If the command already failed, that's what's reported.
+            let result = if task.copy_via_exec {
+                match (
+                    result,
+                    docker::stop_container(docker_cli, &container, interrupted),
+                ) {
+                    (Ok(()), stopped) => stopped,
+                    (Err(e), Ok(())) => Err(e),
+                    (Err(e), Err(stop_error)) => {
+                        error!("{}", stop_error);
+                        Err(e)
+                    }
+                }
+            } else {
+                result
+            };
+
+            // If the container was stopped because it ran for too long, say so. The output files
+            // for failure are still extracted below.
+            let timed_out = watchdog.is_some_and(docker::Watchdog::finish);
+            let result = match (result, task_timeout) {
+                (Err(Failure::User(_, _)), Some(duration)) if timed_out => Err(Failure::User(
+                    format!(
+                        "The command timed out after {}.",
+                        units::format_duration(duration),
+                    ),
+                    None,
+                )),
+                (result, _) => result,
+            };
+            drop(run_span);
+
+            if matches!(result, Err(Failure::User(_, _))) && attempt < attempts {
+                attempt += 1;
+                drop(monitor); // Only the final attempt's resource usage is reported.
+                warn!(
+                    "Retrying task {} (attempt {} of {})\u{2026}",
+                    task_name.code_str(),
+                    attempt,
+                    attempts,
+                );
+                continue;
+            }
+
+            break (container, result, monitor);
+        };
+
+        // Stop measuring resource usage now that the container has exited.
+        report.usage = monitor.map(stats::Monitor::finish);
+
+        // If the command failed, that's the failure reported for the task unless a later phase
+        // fails too.
+        if result.is_err() {
+            report.failed_phase = Some(Phase::CommandExecution);
+        }
+
         // Copy files from the container, if applicable.
+        let copy_out_span = settings.trace.span("copy-out", trace::PHASE);
         match result {
             Ok(()) if !task.output_paths.is_empty() => {
+                // Remove stale output files from previous runs, if requested.
+                if task.clean_output_paths {
+                    if let Err(e) = clean::clean_output_paths(
+                        output_dir,
+                        &output_host_paths(task),
+                        task.backup_output_paths,
+                    ) {
+                        return (
+                            Err(report.failed_during(Phase::OutputExtraction, e)),
+                            Some(context),
+                        );
+                    }
+                }
+
                 if let Err(e) = docker::copy_from_container(
-                    &settings.docker_cli,
+                    docker_cli,
                     &container,
                     &task.output_paths,
                     &location,
                     output_dir,
+                    task.resolve_output_symlinks,
                     interrupted,
                 ) {
-                    return (Err(e), Some(context));
+                    return (
+                        Err(report.failed_during(Phase::OutputExtraction, e)),
+                        Some(context),
+                    );
                 }
             }
+            Err(Failure::Interrupted) => {
+                extract_failure_outputs_on_interrupt(
+                    docker_cli,
+                    &container,
+                    task,
+                    &location,
+                    output_dir,
+                    Instant::now() + INTERRUPT_EXTRACTION_TIMEOUT,
+                );
+            }
             Err(_) if !task.output_paths_on_failure.is_empty() => {
                 if let Err(e) = docker::copy_from_container(
-                    &settings.docker_cli,
+                    docker_cli,
                     &container,
                     &task.output_paths_on_failure,
                     &location,
                     output_dir,
+                    task.resolve_output_symlinks,
                     interrupted,
                 ) {
-                    return (Err(e), Some(context));
+                    return (
+                        Err(report.failed_during(Phase::OutputExtraction, e)),
+                        Some(context),
+                    );
                 }
             }
             _ => {}
         }
 
+        // Copy the files which are extracted regardless of whether the task succeeded.
+        if !task.output_paths_always.is_empty() {
+            if let Err(e) = docker::copy_from_container(
+                docker_cli,
+                &container,
+                &task.output_paths_always,
+                &location,
+                output_dir,
+                task.resolve_output_symlinks,
+                interrupted,
+            ) {
+                return (
+                    Err(report.failed_during(Phase::OutputExtraction, e)),
+                    Some(context),
+                );
+            }
+        }
+        drop(copy_out_span);
+
+        // If the task failed, record what it changed in the container, if requested. This has to
+        // happen before the container is deleted.
+        if settings.diff_limit.is_some() && matches!(result, Err(Failure::User(_, _))) {
+            match docker::container_diff(docker_cli, &container, interrupted) {
+                Ok(diff) => report.diff = Some(diff),
+                Err(Failure::Interrupted) => return (Err(Failure::Interrupted), Some(context)),
+                Err(e) => warn!("{}", e),
+            }
+        }
+
+        // Fingerprint the changes the task made to the filesystem, if requested. This has to happen
+        // before the container is deleted.
+        if fingerprint && result.is_ok() {
+            let _span = settings.trace.span("fingerprint", trace::PHASE);
+            match docker::container_fingerprint(docker_cli, &container, interrupted) {
+                Ok(fingerprint) => report.fingerprint = Some(fingerprint),
+                Err(e) => {
+                    return (
+                        Err(report.failed_during(Phase::Fingerprinting, e)),
+                        Some(context),
+                    )
+                }
+            }
+        }
+
+        // Remember how long the task took, so a future cache hit can estimate the time it saved.
+        if result.is_ok() {
+            report.duration = Some(start.elapsed());
+        }
+
         // Decide whether to the image needs to be persisted.
         let cacheable = result.is_ok() && caching_enabled;
         let persist_locally = cacheable && settings.write_local_cache;
         let persist_remotely = cacheable && settings.write_remote_cache;
+        let persist_ssh = cacheable && settings.write_ssh_cache && settings.ssh_cache.is_some();
         let failed_fatally = matches!(result, Err(Failure::Interrupted | Failure::System(_, _)));
 
         // Only commit the container if we actually need to return a context.
-        if (need_context || persist_locally || persist_remotely) && !failed_fatally {
+        if (need_context || persist_locally || persist_remotely || persist_ssh) && !failed_fatally {
             // Commit the container.
-            if let Err(e) =
-                docker::commit_container(&settings.docker_cli, &container, &image, interrupted)
-            {
-                return (Err(e), Some(context));
+            let commit_span = settings.trace.span("commit", trace::PHASE);
+            if let Err(e) = docker::commit_container(docker_cli, &container, &image, interrupted) {
+                return (Err(report.failed_during(Phase::Commit, e)), Some(context));
+            }
+            drop(commit_span);
+
+            // Let the caller know about the image, unless it's going to be deleted.
+            if persist_locally {
+                report.image = Some(image.clone());
             }
 
             // Construct a new context, if needed.
@@ -330,16 +1141,39 @@ pub fn run(
                     image,
                     persist: persist_locally,
                     interrupted: interrupted.clone(),
-                    docker_cli: settings.docker_cli.clone(),
+                    cleanup: cleanup.clone(),
                 }
             };
 
             // Write to remote cache, if applicable.
             if persist_remotely {
-                if let Err(e) =
-                    docker::push_image(&settings.docker_cli, &new_context.image, interrupted)
-                {
-                    return (Err(e), Some(new_context));
+                let _span = settings.trace.span("push", trace::PHASE);
+                if let Err(e) = docker::push_image(
+                    docker_cli,
+                    &new_context.image,
+                    &settings.extra_push_arguments,
+                    interrupted,
+                ) {
+                    return (Err(report.failed_during(Phase::Push, e)), Some(new_context));
+                }
+                match image_bytes(docker_cli, &new_context.image, interrupted) {
+                    Ok(bytes) => report.bytes_pushed += bytes,
+                    Err(e) => {
+                        return (Err(report.failed_during(Phase::Push, e)), Some(new_context))
+                    }
+                }
+            }
+
+            // Write to the SSH cache, if applicable. The `unwrap` is safe due to how `persist_ssh`
+            // is computed.
+            if persist_ssh {
+                let _span = settings.trace.span("push", trace::PHASE);
+                if let Err(e) = settings.ssh_cache.as_ref().unwrap().send_image(
+                    docker_cli,
+                    &new_context.image,
+                    interrupted,
+                ) {
+                    return (Err(report.failed_during(Phase::Push, e)), Some(new_context));
                 }
             }
 
@@ -351,3 +1185,439 @@ pub fn run(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{
+            cleanup::Cleanup,
+            docker,
+            docker::{FakeCli, Outcome},
+            failure::Failure,
+            runner::{
+                command_failed_message, describe_failure, extract_failure_outputs_on_interrupt,
+                image_env_var, image_environment, prepare_secrets, Context, Phase, Report,
+            },
+            toastfile::{
+                Dependency, MappingPath, Secret, Task, Toastfile, DEFAULT_LOCATION, DEFAULT_USER,
+            },
+        },
+        std::{
+            collections::{BTreeMap, HashMap},
+            env, fs,
+            path::{Path, PathBuf},
+            sync::{atomic::AtomicBool, Arc, Mutex},
+            time::{Duration, Instant},
+        },
+        typed_path::{UnixPath, UnixPathBuf},
+    };
+
+    // A task with the given `output_paths_on_failure` and `extract_failure_outputs_on_interrupt`
+    fn task_with_failure_outputs(
+        output_paths_on_failure: Vec<UnixPathBuf>,
+        extract_failure_outputs_on_interrupt: bool,
+    ) -> Task {
+        Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure,
+            extract_failure_outputs_on_interrupt,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        }
+    }
+
+    // Try to extract the failure outputs of the task, and return whether the copy was attempted
+    // along with the commands that were run.
+    fn extract_on_interrupt(task: &Task, outcomes: Vec<Outcome>) -> (bool, Vec<Vec<String>>) {
+        let cli = FakeCli::new(outcomes);
+
+        let attempted = extract_failure_outputs_on_interrupt(
+            &cli,
+            "f3a1",
+            task,
+            UnixPath::new("/scratch"),
+            Path::new("out"),
+            Instant::now() + Duration::from_secs(10),
+        );
+
+        (attempted, cli.calls())
+    }
+
+    #[test]
+    fn extract_failure_outputs_on_interrupt_disabled() {
+        let task = task_with_failure_outputs(vec![UnixPathBuf::from("logs")], false);
+
+        assert_eq!(extract_on_interrupt(&task, vec![]), (false, vec![]));
+    }
+
+    #[test]
+    fn extract_failure_outputs_on_interrupt_no_paths() {
+        let task = task_with_failure_outputs(vec![], true);
+
+        assert_eq!(extract_on_interrupt(&task, vec![]), (false, vec![]));
+    }
+
+    #[test]
+    fn extract_failure_outputs_on_interrupt_enabled() {
+        let task = task_with_failure_outputs(vec![UnixPathBuf::from("logs")], true);
+
+        // The copy fails, but that's only logged, since it's best-effort.
+        let (attempted, calls) = extract_on_interrupt(
+            &task,
+            vec![Outcome {
+                code: Some(1_i32),
+                stdout: vec![],
+                stderr: b"No such container:path".to_vec(),
+            }],
+        );
+
+        assert!(attempted);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(
+            calls[0][..3],
+            ["container", "cp", "f3a1:/scratch/logs"].map(ToOwned::to_owned),
+        );
+    }
+
+    #[test]
+    fn context_drop_deletes_image() {
+        let deleted = Arc::new(Mutex::new(0_usize));
+        let recorder = deleted.clone();
+        let cleanup = Arc::new(Cleanup::with_performer(
+            "docker",
+            true,
+            Box::new(move |_, _| {
+                *recorder.lock().unwrap() += 1;
+                Ok(())
+            }),
+        ));
+
+        drop(Context {
+            cleanup,
+            image: "toast:foo".to_owned(),
+            persist: false,
+            interrupted: Arc::new(AtomicBool::new(false)),
+        });
+
+        assert_eq!(*deleted.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn context_drop_without_cleanup() {
+        let deleted = Arc::new(Mutex::new(0_usize));
+        let recorder = deleted.clone();
+        let cleanup = Arc::new(Cleanup::with_performer(
+            "docker",
+            false,
+            Box::new(move |_, _| {
+                *recorder.lock().unwrap() += 1;
+                Ok(())
+            }),
+        ));
+
+        drop(Context {
+            cleanup,
+            image: "toast:foo".to_owned(),
+            persist: false,
+            interrupted: Arc::new(AtomicBool::new(false)),
+        });
+
+        assert_eq!(*deleted.lock().unwrap(), 0);
+    }
+
+    // A command which failed with the given error
+    fn failed(stderr: &str) -> Outcome {
+        Outcome {
+            code: Some(1_i32),
+            stdout: vec![],
+            stderr: stderr.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn failed_during_output_extraction() {
+        let cli = FakeCli::new(vec![failed("No such container:path")]);
+        let mut report = Report::default();
+
+        let failure = docker::copy_from_container(
+            &cli,
+            "f3a1",
+            &[UnixPathBuf::from("dist")],
+            UnixPath::new("/scratch"),
+            Path::new("out"),
+            false,
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .map_err(|e| report.failed_during(Phase::OutputExtraction, e))
+        .unwrap_err();
+
+        assert_eq!(report.failed_phase, Some(Phase::OutputExtraction));
+        let Failure::User(message, _) = describe_failure("build", report.failed_phase, failure)
+        else {
+            panic!("Expected a user failure.");
+        };
+        assert!(message.starts_with("Task `build` failed during output extraction: "));
+        assert!(message.contains("No such container:path"));
+    }
+
+    #[test]
+    fn failed_during_commit() {
+        let cli = FakeCli::new(vec![failed("Error response from daemon: no space left")]);
+        let mut report = Report::default();
+
+        let failure =
+            docker::commit_container(&cli, "f3a1", "toast:foo", &Arc::new(AtomicBool::new(false)))
+                .map_err(|e| report.failed_during(Phase::Commit, e))
+                .unwrap_err();
+
+        assert_eq!(report.failed_phase, Some(Phase::Commit));
+        assert!(describe_failure("build", report.failed_phase, failure)
+            .to_string()
+            .starts_with("Task `build` failed during commit: "));
+    }
+
+    #[test]
+    fn describe_failure_system() {
+        let failure = describe_failure(
+            "build",
+            Some(Phase::Hashing),
+            Failure::System("Unable to create temporary file.".to_owned(), None),
+        );
+
+        let Failure::System(message, _) = failure else {
+            panic!("Expected a system failure.");
+        };
+        assert_eq!(
+            message,
+            "Task `build` failed during input hashing: Unable to create temporary file.",
+        );
+    }
+
+    #[test]
+    fn command_failed_message_default() {
+        let task = task_with_failure_outputs(vec![], false);
+
+        assert_eq!(command_failed_message(&task), "The command failed.");
+    }
+
+    #[test]
+    fn command_failed_message_read_only_rootfs() {
+        let mut task = task_with_failure_outputs(vec![], false);
+        task.read_only_rootfs = true;
+
+        assert!(command_failed_message(&task).contains("`read_only_rootfs: true`"));
+    }
+
+    #[test]
+    fn describe_failure_unknown_phase() {
+        let failure = describe_failure(
+            "build",
+            None,
+            Failure::User("The command failed.".to_owned(), None),
+        );
+
+        assert_eq!(failure.to_string(), "The command failed.");
+    }
+
+    #[test]
+    fn describe_failure_interrupted() {
+        assert!(matches!(
+            describe_failure("build", Some(Phase::CommandExecution), Failure::Interrupted),
+            Failure::Interrupted,
+        ));
+    }
+
+    #[test]
+    fn prepare_secrets_none() {
+        let mut task = task_with_failure_outputs(vec![], false);
+        task.mount_paths = vec![MappingPath {
+            host_path: PathBuf::from("cache"),
+            container_path: UnixPathBuf::from("/cache"),
+            readonly: None,
+        }];
+
+        let (secrets_dir, mount_paths) = prepare_secrets(Path::new(""), &task).unwrap();
+
+        assert!(secrets_dir.is_none());
+        assert_eq!(mount_paths, task.mount_paths);
+    }
+
+    #[test]
+    fn prepare_secrets_file_and_env() {
+        let toastfile_dir = tempfile::tempdir().unwrap();
+        fs::write(toastfile_dir.path().join("token.txt"), "abc123\n").unwrap();
+        env::set_var("TOAST_TEST_PREPARE_SECRETS", "xyz789");
+
+        let mut task = task_with_failure_outputs(vec![], false);
+        task.secrets = HashMap::from([
+            (
+                "npm_token".to_owned(),
+                Secret::File(PathBuf::from("token.txt")),
+            ),
+            (
+                "api_key".to_owned(),
+                Secret::Env("TOAST_TEST_PREPARE_SECRETS".to_owned()),
+            ),
+        ]);
+
+        let (secrets_dir, mount_paths) = prepare_secrets(toastfile_dir.path(), &task).unwrap();
+        let secrets_dir = secrets_dir.unwrap();
+
+        assert_eq!(
+            mount_paths,
+            vec![
+                MappingPath {
+                    host_path: secrets_dir.path().join("api_key"),
+                    container_path: UnixPathBuf::from("/run/secrets/api_key"),
+                    readonly: Some(true),
+                },
+                MappingPath {
+                    host_path: secrets_dir.path().join("npm_token"),
+                    container_path: UnixPathBuf::from("/run/secrets/npm_token"),
+                    readonly: Some(true),
+                },
+            ],
+        );
+        assert_eq!(
+            fs::read_to_string(secrets_dir.path().join("api_key")).unwrap(),
+            "xyz789",
+        );
+        assert_eq!(
+            fs::read_to_string(secrets_dir.path().join("npm_token")).unwrap(),
+            "abc123\n",
+        );
+
+        // The files are deleted along with the directory.
+        let path = secrets_dir.path().to_owned();
+        drop(secrets_dir);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn prepare_secrets_missing() {
+        let mut task = task_with_failure_outputs(vec![], false);
+        task.secrets = HashMap::from([(
+            "api_key".to_owned(),
+            Secret::Env("TOAST_TEST_PREPARE_SECRETS_MISSING".to_owned()),
+        )]);
+        let Err(Failure::User(message, _)) = prepare_secrets(Path::new(""), &task) else {
+            panic!("Expected a user failure.");
+        };
+        assert_eq!(
+            message,
+            "Unable to read secret `api_key` from environment variable \
+             `TOAST_TEST_PREPARE_SECRETS_MISSING`.",
+        );
+
+        task.secrets = HashMap::from([(
+            "npm_token".to_owned(),
+            Secret::File(PathBuf::from("missing.txt")),
+        )]);
+        let Err(Failure::User(message, _)) = prepare_secrets(Path::new(""), &task) else {
+            panic!("Expected a user failure.");
+        };
+        assert_eq!(
+            message,
+            "Unable to read secret `npm_token` from file `missing.txt`.",
+        );
+    }
+
+    #[test]
+    fn image_env_var_simple() {
+        assert_eq!(image_env_var("build"), "TOAST_IMAGE_BUILD");
+    }
+
+    #[test]
+    fn image_env_var_sanitized() {
+        assert_eq!(
+            image_env_var("build-release.v2"),
+            "TOAST_IMAGE_BUILD_RELEASE_V2"
+        );
+    }
+
+    #[test]
+    fn image_environment_dependencies() {
+        let task = |dependencies: &[&str]| Task {
+            dependencies: dependencies
+                .iter()
+                .map(|dependency| Dependency {
+                    task: (*dependency).to_owned(),
+                    reason: None,
+                })
+                .collect(),
+            ..task_with_failure_outputs(vec![], false)
+        };
+        let tasks = BTreeMap::from([
+            ("foo".to_owned(), task(&[])),
+            ("bar".to_owned(), task(&["foo"])),
+            ("baz".to_owned(), task(&["bar"])),
+            ("qux".to_owned(), task(&[])),
+        ]);
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            default: None,
+            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
+            tasks,
+        };
+
+        let mut images = HashMap::new();
+        images.insert("foo".to_owned(), "toast:foo-image".to_owned());
+        images.insert("bar".to_owned(), "toast:bar-image".to_owned());
+        images.insert("qux".to_owned(), "toast:qux-image".to_owned());
+
+        let mut expected = HashMap::new();
+        expected.insert("TOAST_IMAGE_FOO".to_owned(), "toast:foo-image".to_owned());
+        expected.insert("TOAST_IMAGE_BAR".to_owned(), "toast:bar-image".to_owned());
+
+        assert_eq!(image_environment(&toastfile, "baz", &images), expected);
+        assert!(image_environment(&toastfile, "foo", &images).is_empty());
+    }
+}
@@ -1,15 +1,64 @@
 use {
-    crate::toastfile::Toastfile,
-    std::{collections::HashSet, convert::AsRef},
+    crate::{
+        failure::Failure,
+        format::CodeStr,
+        toastfile::{docker_cli, Toastfile},
+    },
+    std::{
+        collections::{HashMap, HashSet},
+        convert::AsRef,
+    },
 };
 
+// A plan for running the transitive reflexive closure of a set of tasks
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Plan<'a> {
+    // The tasks in the order they should run, as computed by `compute`
+    pub steps: Vec<Step<'a>>,
+}
+
+// A task in a plan
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Step<'a> {
+    pub task: &'a str,
+
+    // The direct dependencies of the task, in lexicographical order and without duplicates. They all
+    // come before the task in the plan.
+    pub dependencies: Vec<&'a str>,
+
+    // The length of the longest chain of dependencies below the task. Tasks without dependencies
+    // have depth 0, and tasks with the same depth don't depend on each other.
+    pub depth: usize,
+
+    // The requested tasks which pulled this task into the plan (including the task itself, if it was
+    // requested), in lexicographical order and without duplicates
+    pub roots: Vec<&'a str>,
+}
+
+impl<'a> Plan<'a> {
+    // The names of the tasks in the order they should run
+    pub fn order(&self) -> Vec<&'a str> {
+        self.steps.iter().map(|step| step.task).collect()
+    }
+}
+
 // Compute a topological sort of the transitive reflexive closure of a set of tasks. The resulting
 // schedule does not depend on the order of the inputs or dependencies. We assume the tasks form a
 // DAG [ref:tasks_dag].
 pub fn compute<'a>(toastfile: &'a Toastfile, tasks: &[&'a str]) -> Vec<&'a str> {
+    plan(toastfile, tasks).order()
+}
+
+// Compute a plan for running the transitive reflexive closure of a set of tasks. The order of the
+// steps is the one described by `compute`: the requested tasks are visited in lexicographical
+// order, and each one is preceded by its dependencies (which are themselves visited in
+// lexicographical order) unless they were already scheduled. So the plan doesn't depend on the
+// order of the inputs or dependencies. We assume the tasks form a DAG [ref:tasks_dag].
+pub fn plan<'a>(toastfile: &'a Toastfile, tasks: &[&'a str]) -> Plan<'a> {
     // Sort the input tasks to ensure the given order doesn't matter.
     let mut roots: Vec<&'a str> = tasks.to_vec();
     roots.sort_unstable();
+    roots.dedup();
 
     // We will use this set to keep track of what tasks have already been seen.
     let mut visited: HashSet<&'a str> = HashSet::new();
@@ -19,7 +68,7 @@ pub fn compute<'a>(toastfile: &'a Toastfile, tasks: &[&'a str]) -> Vec<&'a str>
 
     // For each root, compute its transitive reflexive closure, topsort it, and add it to the
     // schedule.
-    for root in roots {
+    for &root in &roots {
         // We'll this frontier as a stack for a depth-first traversal.
         let mut frontier: Vec<(&'a str, bool)> = vec![(root, true)];
 
@@ -53,7 +102,7 @@ pub fn compute<'a>(toastfile: &'a Toastfile, tasks: &[&'a str]) -> Vec<&'a str>
                 let mut dependencies: Vec<&'a str> = toastfile.tasks[task]
                     .dependencies
                     .iter()
-                    .map(AsRef::as_ref)
+                    .map(|dependency| dependency.task.as_ref())
                     .collect();
                 dependencies.sort_unstable();
                 dependencies.reverse();
@@ -72,39 +121,227 @@ pub fn compute<'a>(toastfile: &'a Toastfile, tasks: &[&'a str]) -> Vec<&'a str>
         schedule.extend(topological_sort);
     }
 
-    // Return the final schedule.
+    // Describe each task in the schedule. The indexing is safe due to [ref:tasks_valid].
+    let mut steps: Vec<Step<'a>> = vec![];
+    let mut indices: HashMap<&'a str, usize> = HashMap::new();
+    for task in schedule {
+        let mut dependencies: Vec<&'a str> = toastfile.tasks[task]
+            .dependencies
+            .iter()
+            .map(|dependency| dependency.task.as_ref())
+            .collect();
+        dependencies.sort_unstable();
+        dependencies.dedup();
+
+        // The dependencies were scheduled before this task, so their depths are already known.
+        let depth = dependencies
+            .iter()
+            .map(|dependency| steps[indices[dependency]].depth + 1)
+            .max()
+            .unwrap_or(0);
+
+        indices.insert(task, steps.len());
+        steps.push(Step {
+            task,
+            dependencies,
+            depth,
+            roots: vec![],
+        });
+    }
+
+    // Record which roots pulled in each task. The roots are visited in lexicographical order, so
+    // each list of roots ends up sorted.
+    for &root in &roots {
+        let mut visited: HashSet<&'a str> = HashSet::new();
+        let mut frontier = vec![root];
+        while let Some(task) = frontier.pop() {
+            if visited.insert(task) {
+                let step = &mut steps[indices[task]];
+                step.roots.push(root);
+                frontier.extend(step.dependencies.iter().copied());
+            }
+        }
+    }
+
+    Plan { steps }
+}
+
+// Determine which tasks in a schedule may read from and write to the cache. Each task builds on the
+// image produced by the task before it, so once a task can't use the cache, none of the tasks after
+// it can either. This happens for a task with `cache: false` and for a task given by `--force`, even
+// if forcing it produces the same cache key as the existing entry. A forced task doesn't affect the
+// tasks scheduled before it.
+pub fn caching(
+    toastfile: &Toastfile,
+    schedule: &[&str],
+    forced_tasks: &[String],
+    force_all: bool,
+) -> Vec<bool> {
+    let mut caching_enabled = !force_all;
+
+    schedule
+        .iter()
+        .map(|task_name| {
+            caching_enabled = caching_enabled
+                && toastfile.tasks[*task_name].cache // [ref:tasks_valid]
+                && !forced_tasks
+                    .iter()
+                    .any(|forced_task| task_name == forced_task);
+
+            caching_enabled
+        })
+        .collect()
+}
+
+// Why a task would have to run, for `--only-cached`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Miss {
+    // The task can't use the cache (e.g., because of `cache: false` or `--force`).
+    CachingDisabled,
+
+    // The task is the first one whose cache key isn't in the cache, which is where the keys of the
+    // schedule diverge from what was built before.
+    FirstDivergence,
+
+    // The task comes after the divergence and isn't in the cache either.
+    AfterDivergence,
+}
+
+// Decide which tasks in a schedule would have to run, for `--only-cached`. `caching` says which
+// tasks may use the cache (see `caching`), and `found` says which of their images were found in it.
+// If the image of the final task is in the cache, the schedule counts as built, even if the images
+// of earlier tasks were evicted. But copying out output files needs the image of every task, so
+// `extract` requires all of them. An empty result means nothing would run.
+pub fn cache_misses<'a>(
+    schedule: &[&'a str],
+    caching: &[bool],
+    found: &[bool],
+    extract: bool,
+) -> Vec<(&'a str, Miss)> {
+    let available = |i: usize| caching[i] && found[i];
+
+    if !extract && (schedule.is_empty() || available(schedule.len() - 1)) {
+        return vec![];
+    }
+
+    let mut diverged = false;
     schedule
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !available(*i))
+        .map(|(i, task)| {
+            let miss = if !caching[i] {
+                Miss::CachingDisabled
+            } else if diverged {
+                Miss::AfterDivergence
+            } else {
+                Miss::FirstDivergence
+            };
+            diverged = true;
+            (*task, miss)
+        })
+        .collect()
+}
+
+// Check that each task in a schedule which builds on the image produced by the task before it uses
+// the same Docker CLI as that task [tag:docker_clis_consistent]. Images committed by one engine
+// (e.g., Docker) aren't visible to another (e.g., Podman). A task with its own `image` starts fresh,
+// so it can switch to any CLI.
+pub fn check_docker_clis(
+    toastfile: &Toastfile,
+    schedule: &[&str],
+    default_docker_cli: &str,
+) -> Result<(), Failure> {
+    for pair in schedule.windows(2) {
+        let (previous, current) = (pair[0], pair[1]);
+        let previous_task = &toastfile.tasks[previous]; // [ref:tasks_valid]
+        let current_task = &toastfile.tasks[current]; // [ref:tasks_valid]
+        let previous_cli = docker_cli(previous_task, default_docker_cli);
+        let current_cli = docker_cli(current_task, default_docker_cli);
+
+        if current_task.image.is_none() && current_cli != previous_cli {
+            return Err(Failure::User(
+                format!(
+                    "Task {} uses {}, but it builds on the result of task {}, which uses {}. \
+                        Images created with one aren't visible to the other. Give {} its own {} \
+                        so it starts fresh, or use the same {} for both tasks.",
+                    current.code_str(),
+                    current_cli.code_str(),
+                    previous.code_str(),
+                    previous_cli.code_str(),
+                    current.code_str(),
+                    "image".code_str(),
+                    "docker_cli".code_str(),
+                ),
+                None,
+            ));
+        }
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use {
         crate::{
-            schedule::compute,
-            toastfile::{Task, Toastfile, DEFAULT_LOCATION, DEFAULT_USER},
+            failure::Failure,
+            schedule::{cache_misses, caching, check_docker_clis, compute, plan, Miss, Step},
+            toastfile::{Dependency, Task, Toastfile, DEFAULT_LOCATION, DEFAULT_USER},
         },
-        std::collections::HashMap,
+        std::collections::{BTreeMap, HashMap},
         typed_path::UnixPath,
     };
 
     fn task_with_dependencies(dependencies: Vec<String>) -> Task {
         Task {
             description: None,
-            dependencies,
+            dependencies: dependencies
+                .into_iter()
+                .map(|task| Dependency { task, reason: None })
+                .collect(),
             cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
             environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
             input_paths: vec![],
             excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
             output_paths: vec![],
             output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
             mount_paths: vec![],
             mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
             ports: vec![],
+            gpus: None,
+            image: None,
             location: None,
             user: None,
             command: String::new(),
+            command_file: None,
             command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
             extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         }
     }
 
@@ -120,7 +357,13 @@ mod tests {
             location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
             command_prefix: String::new(),
-            tasks: HashMap::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
+            tasks: BTreeMap::new(),
         };
 
         let actual: Vec<&str> = compute(&toastfile, &[]);
@@ -131,7 +374,7 @@ mod tests {
 
     #[test]
     fn schedule_single() {
-        let mut tasks = HashMap::new();
+        let mut tasks = BTreeMap::new();
         tasks.insert("foo".to_owned(), empty_task());
 
         let toastfile = Toastfile {
@@ -140,6 +383,12 @@ mod tests {
             location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
             command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
             tasks,
         };
 
@@ -151,7 +400,7 @@ mod tests {
 
     #[test]
     fn schedule_linear() {
-        let mut tasks = HashMap::new();
+        let mut tasks = BTreeMap::new();
         tasks.insert("foo".to_owned(), empty_task());
         tasks.insert(
             "bar".to_owned(),
@@ -168,6 +417,12 @@ mod tests {
             location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
             command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
             tasks,
         };
 
@@ -179,7 +434,7 @@ mod tests {
 
     #[test]
     fn schedule_diamond() {
-        let mut tasks = HashMap::new();
+        let mut tasks = BTreeMap::new();
         tasks.insert("foo".to_owned(), empty_task());
         tasks.insert(
             "bar".to_owned(),
@@ -200,6 +455,12 @@ mod tests {
             location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
             command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
             tasks,
         };
 
@@ -211,7 +472,7 @@ mod tests {
 
     #[test]
     fn schedule_lexicographical_tie_breaking() {
-        let mut tasks = HashMap::new();
+        let mut tasks = BTreeMap::new();
         tasks.insert("foo".to_owned(), empty_task());
         tasks.insert("bar".to_owned(), empty_task());
         tasks.insert("baz".to_owned(), empty_task());
@@ -222,6 +483,12 @@ mod tests {
             location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
             command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
             tasks,
         };
 
@@ -233,7 +500,7 @@ mod tests {
 
     #[test]
     fn schedule_dependency_duplicates() {
-        let mut tasks1 = HashMap::new();
+        let mut tasks1 = BTreeMap::new();
         tasks1.insert("foo".to_owned(), empty_task());
         tasks1.insert("bar".to_owned(), empty_task());
         tasks1.insert(
@@ -241,7 +508,7 @@ mod tests {
             task_with_dependencies(vec!["foo".to_owned(), "bar".to_owned(), "foo".to_owned()]),
         );
 
-        let mut tasks2 = HashMap::new();
+        let mut tasks2 = BTreeMap::new();
         tasks2.insert("foo".to_owned(), empty_task());
         tasks2.insert("bar".to_owned(), empty_task());
         tasks2.insert(
@@ -255,6 +522,12 @@ mod tests {
             location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
             command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
             tasks: tasks1,
         };
 
@@ -264,6 +537,12 @@ mod tests {
             location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
             command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
             tasks: tasks2,
         };
 
@@ -275,7 +554,7 @@ mod tests {
 
     #[test]
     fn schedule_input_duplicates() {
-        let mut tasks = HashMap::new();
+        let mut tasks = BTreeMap::new();
         tasks.insert("foo".to_owned(), empty_task());
         tasks.insert("bar".to_owned(), empty_task());
         tasks.insert("baz".to_owned(), empty_task());
@@ -286,6 +565,12 @@ mod tests {
             location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
             command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
             tasks,
         };
 
@@ -297,7 +582,7 @@ mod tests {
 
     #[test]
     fn schedule_dependency_order() {
-        let mut tasks1 = HashMap::new();
+        let mut tasks1 = BTreeMap::new();
         tasks1.insert("foo".to_owned(), empty_task());
         tasks1.insert("bar".to_owned(), empty_task());
         tasks1.insert("baz".to_owned(), empty_task());
@@ -306,7 +591,7 @@ mod tests {
             task_with_dependencies(vec!["foo".to_owned(), "bar".to_owned(), "baz".to_owned()]),
         );
 
-        let mut tasks2 = HashMap::new();
+        let mut tasks2 = BTreeMap::new();
         tasks2.insert("foo".to_owned(), empty_task());
         tasks2.insert("bar".to_owned(), empty_task());
         tasks2.insert("baz".to_owned(), empty_task());
@@ -321,6 +606,12 @@ mod tests {
             location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
             command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
             tasks: tasks1,
         };
 
@@ -330,6 +621,12 @@ mod tests {
             location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
             command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
             tasks: tasks2,
         };
 
@@ -341,7 +638,7 @@ mod tests {
 
     #[test]
     fn schedule_input_order() {
-        let mut tasks = HashMap::new();
+        let mut tasks = BTreeMap::new();
         tasks.insert("foo".to_owned(), empty_task());
         tasks.insert("bar".to_owned(), empty_task());
         tasks.insert("baz".to_owned(), empty_task());
@@ -352,6 +649,12 @@ mod tests {
             location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
             command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
             tasks,
         };
 
@@ -360,4 +663,428 @@ mod tests {
 
         assert_eq!(first, second);
     }
+
+    // Construct a toastfile with the given tasks and their dependencies.
+    fn plan_toastfile(tasks: &[(&str, &[&str])]) -> Toastfile {
+        Toastfile {
+            image: "encom:os-12".to_owned(),
+            default: None,
+            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
+            tasks: tasks
+                .iter()
+                .map(|(name, dependencies)| {
+                    (
+                        (*name).to_owned(),
+                        task_with_dependencies(
+                            dependencies.iter().map(|&task| task.to_owned()).collect(),
+                        ),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn step<'a>(
+        task: &'a str,
+        dependencies: &[&'a str],
+        depth: usize,
+        roots: &[&'a str],
+    ) -> Step<'a> {
+        Step {
+            task,
+            dependencies: dependencies.to_vec(),
+            depth,
+            roots: roots.to_vec(),
+        }
+    }
+
+    #[test]
+    fn plan_empty() {
+        let toastfile = plan_toastfile(&[("foo", &[])]);
+
+        assert!(plan(&toastfile, &[]).steps.is_empty());
+    }
+
+    #[test]
+    fn plan_single() {
+        let toastfile = plan_toastfile(&[("foo", &[])]);
+
+        assert_eq!(
+            plan(&toastfile, &["foo"]).steps,
+            vec![step("foo", &[], 0, &["foo"])],
+        );
+    }
+
+    #[test]
+    fn plan_diamond() {
+        let toastfile = plan_toastfile(&[
+            ("foo", &[]),
+            ("bar", &["foo"]),
+            ("baz", &["foo"]),
+            ("qux", &["baz", "bar"]),
+        ]);
+
+        assert_eq!(
+            plan(&toastfile, &["qux"]).steps,
+            vec![
+                step("foo", &[], 0, &["qux"]),
+                step("bar", &["foo"], 1, &["qux"]),
+                step("baz", &["foo"], 1, &["qux"]),
+                step("qux", &["bar", "baz"], 2, &["qux"]),
+            ],
+        );
+    }
+
+    #[test]
+    fn plan_depth_is_longest_chain() {
+        // `qux` depends on `foo` directly and through `bar` and `baz`, so its depth is determined
+        // by the longer path.
+        let toastfile = plan_toastfile(&[
+            ("foo", &[]),
+            ("bar", &["foo"]),
+            ("baz", &["bar"]),
+            ("qux", &["foo", "baz"]),
+        ]);
+
+        assert_eq!(
+            plan(&toastfile, &["qux"]).steps,
+            vec![
+                step("foo", &[], 0, &["qux"]),
+                step("bar", &["foo"], 1, &["qux"]),
+                step("baz", &["bar"], 2, &["qux"]),
+                step("qux", &["baz", "foo"], 3, &["qux"]),
+            ],
+        );
+    }
+
+    #[test]
+    fn plan_multiple_roots() {
+        let toastfile = plan_toastfile(&[
+            ("foo", &[]),
+            ("bar", &["foo"]),
+            ("baz", &[]),
+            ("qux", &["baz"]),
+        ]);
+
+        assert_eq!(
+            plan(&toastfile, &["qux", "bar"]).steps,
+            vec![
+                step("foo", &[], 0, &["bar"]),
+                step("bar", &["foo"], 1, &["bar"]),
+                step("baz", &[], 0, &["qux"]),
+                step("qux", &["baz"], 1, &["qux"]),
+            ],
+        );
+    }
+
+    #[test]
+    fn plan_overlapping_closures() {
+        // The roots `bar` and `baz` share the dependency `foo`, and `qux` depends on `bar` while
+        // also being a root itself.
+        let toastfile = plan_toastfile(&[
+            ("foo", &[]),
+            ("bar", &["foo"]),
+            ("baz", &["foo"]),
+            ("qux", &["bar"]),
+        ]);
+
+        assert_eq!(
+            plan(&toastfile, &["qux", "baz", "bar"]).steps,
+            vec![
+                step("foo", &[], 0, &["bar", "baz", "qux"]),
+                step("bar", &["foo"], 1, &["bar", "qux"]),
+                step("baz", &["foo"], 1, &["baz"]),
+                step("qux", &["bar"], 2, &["qux"]),
+            ],
+        );
+    }
+
+    #[test]
+    fn plan_duplicates() {
+        let toastfile = plan_toastfile(&[("foo", &[]), ("bar", &["foo", "foo"])]);
+
+        assert_eq!(
+            plan(&toastfile, &["bar", "bar"]).steps,
+            vec![
+                step("foo", &[], 0, &["bar"]),
+                step("bar", &["foo"], 1, &["bar"]),
+            ],
+        );
+    }
+
+    #[test]
+    fn plan_order_matches_compute() {
+        let toastfile = plan_toastfile(&[
+            ("foo", &[]),
+            ("bar", &["foo"]),
+            ("baz", &["foo"]),
+            ("qux", &["baz", "bar"]),
+            ("corge", &[]),
+        ]);
+
+        for roots in [
+            vec!["qux"],
+            vec!["corge", "bar"],
+            vec!["baz", "corge", "qux"],
+        ] {
+            assert_eq!(
+                plan(&toastfile, &roots).order(),
+                compute(&toastfile, &roots),
+            );
+        }
+    }
+
+    #[test]
+    fn plan_input_order() {
+        let toastfile = plan_toastfile(&[
+            ("foo", &[]),
+            ("bar", &["foo"]),
+            ("baz", &["foo"]),
+            ("qux", &["baz", "bar"]),
+        ]);
+
+        assert_eq!(
+            plan(&toastfile, &["qux", "baz", "bar"]),
+            plan(&toastfile, &["bar", "qux", "baz"]),
+        );
+    }
+
+    // Construct a toastfile with a chain of tasks `foo`, `bar`, and `baz` and an unrelated task
+    // `qux`, which is scheduled after them.
+    fn caching_toastfile() -> Toastfile {
+        let mut tasks = BTreeMap::new();
+        tasks.insert("foo".to_owned(), empty_task());
+        tasks.insert(
+            "bar".to_owned(),
+            task_with_dependencies(vec!["foo".to_owned()]),
+        );
+        tasks.insert(
+            "baz".to_owned(),
+            task_with_dependencies(vec!["bar".to_owned()]),
+        );
+        tasks.insert("qux".to_owned(), empty_task());
+
+        Toastfile {
+            image: "encom:os-12".to_owned(),
+            default: None,
+            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
+            tasks,
+        }
+    }
+
+    #[test]
+    fn caching_nothing_forced() {
+        let toastfile = caching_toastfile();
+        let schedule = compute(&toastfile, &["baz", "qux"]);
+
+        assert_eq!(schedule, vec!["foo", "bar", "baz", "qux"]);
+        assert_eq!(
+            caching(&toastfile, &schedule, &[], false),
+            vec![true, true, true, true],
+        );
+    }
+
+    #[test]
+    fn caching_force_all() {
+        let toastfile = caching_toastfile();
+        let schedule = compute(&toastfile, &["baz", "qux"]);
+
+        assert_eq!(
+            caching(&toastfile, &schedule, &[], true),
+            vec![false, false, false, false],
+        );
+    }
+
+    #[test]
+    fn caching_forced_first() {
+        let toastfile = caching_toastfile();
+        let schedule = compute(&toastfile, &["baz", "qux"]);
+
+        assert_eq!(
+            caching(&toastfile, &schedule, &["foo".to_owned()], false),
+            vec![false, false, false, false],
+        );
+    }
+
+    #[test]
+    fn caching_forced_middle() {
+        let toastfile = caching_toastfile();
+        let schedule = compute(&toastfile, &["baz", "qux"]);
+
+        // The tasks downstream of `bar` must not be read from the cache, even if they have entries
+        // from before.
+        assert_eq!(
+            caching(&toastfile, &schedule, &["bar".to_owned()], false),
+            vec![true, false, false, false],
+        );
+    }
+
+    #[test]
+    fn caching_forced_last() {
+        let toastfile = caching_toastfile();
+        let schedule = compute(&toastfile, &["baz", "qux"]);
+
+        // Forcing a task doesn't retroactively disable caching for the tasks before it.
+        assert_eq!(
+            caching(&toastfile, &schedule, &["qux".to_owned()], false),
+            vec![true, true, true, false],
+        );
+    }
+
+    #[test]
+    fn caching_forced_unscheduled() {
+        let toastfile = caching_toastfile();
+        let schedule = compute(&toastfile, &["bar"]);
+
+        assert_eq!(
+            caching(&toastfile, &schedule, &["qux".to_owned()], false),
+            vec![true, true],
+        );
+    }
+
+    #[test]
+    fn caching_uncacheable_then_forced() {
+        let mut toastfile = caching_toastfile();
+        toastfile.tasks.get_mut("foo").unwrap().cache = false;
+        let schedule = compute(&toastfile, &["baz", "qux"]);
+
+        assert_eq!(
+            caching(&toastfile, &schedule, &["baz".to_owned()], false),
+            vec![false, false, false, false],
+        );
+    }
+
+    #[test]
+    fn check_docker_clis_default() {
+        let toastfile = caching_toastfile();
+        let schedule = compute(&toastfile, &["baz", "qux"]);
+
+        assert!(check_docker_clis(&toastfile, &schedule, "docker").is_ok());
+    }
+
+    #[test]
+    fn check_docker_clis_same_override() {
+        let mut toastfile = caching_toastfile();
+        for task in toastfile.tasks.values_mut() {
+            task.docker_cli = Some("podman".to_owned());
+        }
+        let schedule = compute(&toastfile, &["baz", "qux"]);
+
+        assert!(check_docker_clis(&toastfile, &schedule, "docker").is_ok());
+    }
+
+    #[test]
+    fn check_docker_clis_crossing() {
+        let mut toastfile = caching_toastfile();
+        toastfile.tasks.get_mut("bar").unwrap().docker_cli = Some("podman".to_owned());
+        let schedule = compute(&toastfile, &["bar"]);
+
+        match check_docker_clis(&toastfile, &schedule, "docker") {
+            Err(Failure::User(message, None)) => {
+                assert!(message.contains("Task `bar` uses `podman`"));
+                assert!(message.contains("task `foo`, which uses `docker`"));
+            }
+            _ => panic!("The tasks should have been rejected."),
+        }
+    }
+
+    #[test]
+    fn check_docker_clis_fresh_image() {
+        let mut toastfile = caching_toastfile();
+        let bar = toastfile.tasks.get_mut("bar").unwrap();
+        bar.docker_cli = Some("podman".to_owned());
+        bar.image = Some("encom:os-13".to_owned());
+        let schedule = compute(&toastfile, &["bar"]);
+
+        assert!(check_docker_clis(&toastfile, &schedule, "docker").is_ok());
+    }
+
+    #[test]
+    fn cache_misses_all_cached() {
+        assert!(cache_misses(
+            &["foo", "bar", "baz"],
+            &[true, true, true],
+            &[true, true, true],
+            true,
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn cache_misses_final_cached() {
+        assert!(cache_misses(
+            &["foo", "bar", "baz"],
+            &[true, true, true],
+            &[false, false, true],
+            false,
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn cache_misses_final_cached_extract() {
+        assert_eq!(
+            cache_misses(
+                &["foo", "bar", "baz"],
+                &[true, true, true],
+                &[false, true, true],
+                true,
+            ),
+            vec![("foo", Miss::FirstDivergence)],
+        );
+    }
+
+    #[test]
+    fn cache_misses_divergence() {
+        assert_eq!(
+            cache_misses(
+                &["foo", "bar", "baz", "qux"],
+                &[true, true, true, true],
+                &[true, false, true, false],
+                false,
+            ),
+            vec![
+                ("bar", Miss::FirstDivergence),
+                ("qux", Miss::AfterDivergence)
+            ],
+        );
+    }
+
+    #[test]
+    fn cache_misses_caching_disabled() {
+        assert_eq!(
+            cache_misses(
+                &["foo", "bar", "baz"],
+                &[true, false, false],
+                &[true, true, true],
+                false,
+            ),
+            vec![
+                ("bar", Miss::CachingDisabled),
+                ("baz", Miss::CachingDisabled),
+            ],
+        );
+    }
+
+    #[test]
+    fn cache_misses_empty_schedule() {
+        assert!(cache_misses(&[], &[], &[], false).is_empty());
+        assert!(cache_misses(&[], &[], &[], true).is_empty());
+    }
 }
@@ -0,0 +1,127 @@
+use std::{
+    borrow::Cow,
+    cmp::Reverse,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+// What the value of a secret is replaced with
+pub const MASK: &str = "********";
+
+// Secrets with fewer characters than this aren't masked, since a value like `1` or `yes` would be
+// found all over the logs and make them unreadable.
+pub const MIN_LENGTH: usize = 4;
+
+// [tag:secrets_masked] The values of the secret environment variables and the `secrets` of the
+// tasks being run. Everything Toast logs (including errors, which may contain output from the
+// Docker CLI) has these replaced with `MASK`. The values still reach the container.
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+// Whether the user has been warned about a secret which is too short to be masked
+static SHORT_SECRET_WARNED: AtomicBool = AtomicBool::new(false);
+
+// Remember the value of a secret, so it's masked from now on. A value shorter than `MIN_LENGTH`
+// isn't masked, and the first one causes a warning. An empty value is skipped without a warning,
+// since there's nothing to leak.
+pub fn register(value: &str) {
+    if value.is_empty() {
+        return;
+    }
+
+    if value.chars().count() < MIN_LENGTH {
+        // The logger masks secrets too, so this must not hold the lock.
+        if !SHORT_SECRET_WARNED.swap(true, Ordering::SeqCst) {
+            warn!(
+                "A secret is shorter than {MIN_LENGTH} characters, so it won\u{2019}t be masked in \
+                 the logs. The same goes for any other secrets that short.",
+            );
+        }
+
+        return;
+    }
+
+    // The `unwrap` will only fail if a panic already occurred.
+    let mut secrets = SECRETS.lock().unwrap();
+    if !secrets.iter().any(|secret| secret == value) {
+        secrets.push(value.to_owned());
+
+        // Longer secrets are masked first, so one which contains another is masked entirely.
+        secrets.sort_by_key(|secret| Reverse(secret.len()));
+    }
+}
+
+// Replace the values of the secrets in some text.
+pub fn mask(text: &str) -> Cow<'_, str> {
+    // The `unwrap` will only fail if a panic already occurred.
+    mask_with(&SECRETS.lock().unwrap(), text)
+}
+
+fn mask_with<'a>(secrets: &[String], text: &'a str) -> Cow<'a, str> {
+    let mut result = Cow::Borrowed(text);
+
+    for secret in secrets {
+        if result.contains(secret.as_str()) {
+            result = Cow::Owned(result.replace(secret.as_str(), MASK));
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::secrets::{mask, mask_with, register, SHORT_SECRET_WARNED},
+        std::sync::atomic::Ordering,
+    };
+
+    #[test]
+    fn register_and_mask() {
+        // The secrets are shared by all the tests, so this one uses values no other test does.
+        register("");
+        register("hunter2-for-register-test");
+        register("hunter2-for-register-test");
+
+        assert_eq!(
+            mask("password: hunter2-for-register-test"),
+            "password: ********",
+        );
+        assert_eq!(mask("nothing to hide"), "nothing to hide");
+    }
+
+    #[test]
+    fn register_short() {
+        register("z9!");
+        register("z9?");
+
+        assert!(SHORT_SECRET_WARNED.load(Ordering::SeqCst));
+        assert_eq!(mask("codes z9! and z9?"), "codes z9! and z9?");
+    }
+
+    #[test]
+    fn mask_none() {
+        assert_eq!(mask_with(&[], "token abc123"), "token abc123");
+    }
+
+    #[test]
+    fn mask_all_occurrences() {
+        assert_eq!(
+            mask_with(
+                &["abc123".to_owned()],
+                "--env TOKEN=abc123\nInvalid token abc123.",
+            ),
+            "--env TOKEN=********\nInvalid token ********.",
+        );
+    }
+
+    #[test]
+    fn mask_overlapping() {
+        // The longer secret comes first, as `register` arranges.
+        assert_eq!(
+            mask_with(&["abc123xyz".to_owned(), "abc".to_owned()], "abc123xyz abc",),
+            "******** ********",
+        );
+    }
+}
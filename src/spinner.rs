@@ -1,7 +1,8 @@
 use {
     atty::Stream,
-    crossbeam::channel::{bounded, Sender},
+    crossbeam::channel::{bounded, Receiver, Sender},
     indicatif::{ProgressBar, ProgressStyle},
+    log::Level,
     scopeguard::guard,
     std::{
         sync::{
@@ -14,8 +15,40 @@ use {
     },
 };
 
-// Render a spinner in the terminal. When the returned value is dropped, the spinner is stopped.
+// Whether spinners should be rendered. This is decided once at startup (e.g., based on the
+// `--no-spinner` flag) and consulted by `spin`.
+static SPINNER_ENABLED: AtomicBool = AtomicBool::new(true);
+
+// Enable or disable spinners for the rest of the program.
+pub fn set_enabled(enabled: bool) {
+    SPINNER_ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+// Stop a spinner, if one was started. This is the destructor of the guard returned by `spin`.
+fn stop(state: Option<(Arc<AtomicBool>, Receiver<()>)>) {
+    if let Some((spinning, response_receiver)) = state {
+        // Tell the spinner service to stop the spinner.
+        spinning.store(false, Ordering::SeqCst);
+
+        // Wait for the spinner to stop. The `unwrap` is safe since we never hang up the channel.
+        response_receiver.recv().unwrap();
+    }
+}
+
+// Render a spinner in the terminal. When the returned value is dropped, the spinner is stopped. If
+// spinners are disabled, the message is logged instead, unless debug logging is enabled. Callers
+// log a more detailed version of the message at that level, so it would only be repeated.
+#[allow(clippy::non_std_lazy_statics)]
 pub fn spin(message: &str) -> impl Drop {
+    // If spinners are disabled, just log the message and return a guard that does nothing.
+    if !SPINNER_ENABLED.load(Ordering::SeqCst) {
+        if !log_enabled!(Level::Debug) {
+            info!("{}", message);
+        }
+
+        return guard(None, stop);
+    }
+
     // Start a thread for our spinner-as-a-service. This thread will only be created once and will
     // live for the duration of the whole program.
     lazy_static! {
@@ -82,11 +115,21 @@ pub fn spin(message: &str) -> impl Drop {
         .unwrap();
 
     // Return a guard that stops the spinner via its destructor.
-    guard((), move |()| {
-        // Tell the spinner service to stop the spinner.
-        spinning.store(false, Ordering::SeqCst);
+    guard(Some((spinning, response_receiver)), stop)
+}
 
-        // Wait for the spinner to stop. The `unwrap` is safe since we never hang up the channel.
-        response_receiver.recv().unwrap();
-    })
+#[cfg(test)]
+mod tests {
+    use {
+        crate::spinner::{set_enabled, spin},
+        std::mem::drop,
+    };
+
+    #[test]
+    fn spin_disabled() {
+        // If the guard tried to stop a spinner that was never started, this would block forever.
+        set_enabled(false);
+        let guard = spin("Reticulating splines\u{2026}");
+        drop(guard);
+    }
 }
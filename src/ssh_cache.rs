@@ -0,0 +1,477 @@
+use {
+    crate::{docker, failure, failure::Failure, format::CodeStr, spinner::spin},
+    std::{
+        io::Read,
+        process::{Child, ChildStderr, Command, ExitStatus, Stdio},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::sleep,
+        time::{Duration, Instant},
+    },
+};
+
+// How often to check whether a child process has finished
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// The exit status `ssh` uses for its own errors (e.g., failed authentication), as opposed to those
+// of the remote command
+const SSH_ERROR_EXIT_CODE: i32 = 255;
+
+// A cache of images on another machine which is reachable via SSH but doesn't share a registry.
+// Images are transferred with `docker image save` and `docker image load`.
+pub struct SshCache {
+    pub ssh_cli: String,
+    pub host: String,
+    pub timeout: Duration,
+}
+
+impl SshCache {
+    // Construct a command which runs `docker` on the remote host. `BatchMode` makes `ssh` fail
+    // rather than prompt for a password.
+    fn remote_docker(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(&self.ssh_cli);
+        command
+            .arg("-o")
+            .arg("BatchMode=yes")
+            .arg("-o")
+            .arg(format!("ConnectTimeout={}", self.timeout.as_secs().max(1)))
+            .arg(&self.host)
+            .arg("docker")
+            .args(args);
+        command
+    }
+
+    // Explain a failure of `ssh` itself.
+    fn connection_failure(&self, stderr: &str) -> Failure {
+        Failure::User(
+            format!(
+                "Unable to reach the SSH cache host {}. Make sure {} works without prompting for a \
+                 password. {}",
+                self.host.code_str(),
+                format!("ssh -o BatchMode=yes {}", self.host).code_str(),
+                stderr.trim(),
+            ),
+            None,
+        )
+    }
+
+    // Interpret the exit status of an `ssh` command which failed.
+    fn remote_failure(&self, status: ExitStatus, stderr: &str, error: &str) -> Failure {
+        if status.code() == Some(SSH_ERROR_EXIT_CODE) {
+            self.connection_failure(stderr)
+        } else {
+            Failure::User(format!("{} {}", error, stderr.trim()), None)
+        }
+    }
+
+    // Check whether the remote host has an image. This is subject to the timeout.
+    pub fn image_exists(
+        &self,
+        image: &str,
+        interrupted: &Arc<AtomicBool>,
+    ) -> Result<bool, Failure> {
+        debug!(
+            "Checking whether image {} exists on SSH cache host {}\u{2026}",
+            image.code_str(),
+            self.host.code_str(),
+        );
+
+        // Render a spinner animation and clear it when we're done.
+        let _guard = spin("Checking the SSH cache\u{2026}");
+
+        // Ask the remote Docker daemon about the image.
+        let mut child = self
+            .remote_docker(&["image", "inspect", image])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(failure::system(format!(
+                "Unable to run {}.",
+                self.ssh_cli.code_str(),
+            )))?;
+        let status = wait_with_deadline(
+            &mut child,
+            Instant::now() + self.timeout,
+            interrupted,
+            &format!(
+                "Timed out checking the SSH cache host {}.",
+                self.host.code_str(),
+            ),
+        )?;
+
+        // Any failure other than one from `ssh` itself means the image doesn't exist.
+        match status.code() {
+            Some(0) => Ok(true),
+            Some(SSH_ERROR_EXIT_CODE) => {
+                Err(self.connection_failure(&read_stderr(child.stderr.take())))
+            }
+            Some(_) => Ok(false),
+            None => {
+                interrupted.store(true, Ordering::SeqCst);
+                Err(Failure::Interrupted)
+            }
+        }
+    }
+
+    // Copy an image from the remote host into the local Docker daemon.
+    pub fn fetch_image(
+        &self,
+        docker_cli: &str,
+        image: &str,
+        interrupted: &Arc<AtomicBool>,
+    ) -> Result<(), Failure> {
+        debug!(
+            "Fetching image {} from SSH cache host {}\u{2026}",
+            image.code_str(),
+            self.host.code_str(),
+        );
+
+        let _guard = spin("Fetching image from the SSH cache\u{2026}");
+        let mut destination = docker::command(docker_cli, &["image".to_owned(), "load".to_owned()]);
+        self.pipe(
+            &mut self.remote_docker(&["image", "save", image]),
+            &mut destination,
+            true,
+            &format!(
+                "Unable to fetch image {} from the SSH cache host {}.",
+                image.code_str(),
+                self.host.code_str(),
+            ),
+            interrupted,
+        )
+    }
+
+    // Copy an image from the local Docker daemon to the remote host.
+    pub fn send_image(
+        &self,
+        docker_cli: &str,
+        image: &str,
+        interrupted: &Arc<AtomicBool>,
+    ) -> Result<(), Failure> {
+        debug!(
+            "Sending image {} to SSH cache host {}\u{2026}",
+            image.code_str(),
+            self.host.code_str(),
+        );
+
+        let _guard = spin("Sending image to the SSH cache\u{2026}");
+        let mut source = docker::command(
+            docker_cli,
+            &["image".to_owned(), "save".to_owned(), image.to_owned()],
+        );
+        self.pipe(
+            &mut source,
+            &mut self.remote_docker(&["image", "load"]),
+            false,
+            &format!(
+                "Unable to send image {} to the SSH cache host {}.",
+                image.code_str(),
+                self.host.code_str(),
+            ),
+            interrupted,
+        )
+    }
+
+    // Pipe the output of `source` into `destination`. One of them runs `ssh`, as indicated by
+    // `ssh_is_source`.
+    fn pipe(
+        &self,
+        source: &mut Command,
+        destination: &mut Command,
+        ssh_is_source: bool,
+        error: &str,
+        interrupted: &Arc<AtomicBool>,
+    ) -> Result<(), Failure> {
+        // This is used to determine whether the user interrupted the program during the transfer.
+        let was_interrupted = interrupted.load(Ordering::SeqCst);
+
+        // Start both ends of the pipeline.
+        let mut source_child = source
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(failure::system(error.to_owned()))?;
+
+        // The `unwrap` is safe because we asked for the standard output stream to be piped.
+        let mut destination_child = destination
+            .stdin(Stdio::from(source_child.stdout.take().unwrap()))
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(failure::system(error.to_owned()))?;
+
+        // Wait for both ends to finish.
+        let destination_status = destination_child
+            .wait()
+            .map_err(failure::system(error.to_owned()))?;
+        let source_status = source_child
+            .wait()
+            .map_err(failure::system(error.to_owned()))?;
+
+        // Check if the transfer was interrupted.
+        if !was_interrupted
+            && interrupted.load(Ordering::SeqCst)
+            && !(source_status.success() && destination_status.success())
+        {
+            return Err(Failure::Interrupted);
+        }
+
+        // Report a failure of the `ssh` end first, since it may have caused the other end to fail
+        // (e.g., due to a broken pipe).
+        let (ssh_status, ssh_stderr, local_status, local_stderr) = if ssh_is_source {
+            (
+                source_status,
+                source_child.stderr.take(),
+                destination_status,
+                destination_child.stderr.take(),
+            )
+        } else {
+            (
+                destination_status,
+                destination_child.stderr.take(),
+                source_status,
+                source_child.stderr.take(),
+            )
+        };
+
+        if !ssh_status.success() {
+            return Err(self.remote_failure(ssh_status, &read_stderr(ssh_stderr), error));
+        }
+
+        if !local_status.success() {
+            return Err(Failure::System(
+                format!("{} {}", error, read_stderr(local_stderr).trim()),
+                None,
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+// Wait for a child process to finish, killing it if the deadline passes or the user interrupts the
+// program.
+fn wait_with_deadline(
+    child: &mut Child,
+    deadline: Instant,
+    interrupted: &Arc<AtomicBool>,
+    timeout_error: &str,
+) -> Result<ExitStatus, Failure> {
+    loop {
+        if let Some(status) = child
+            .try_wait()
+            .map_err(failure::system("Unable to wait for child process."))?
+        {
+            return Ok(status);
+        }
+
+        if interrupted.load(Ordering::SeqCst) {
+            drop(child.kill());
+            drop(child.wait());
+            return Err(Failure::Interrupted);
+        }
+
+        if Instant::now() >= deadline {
+            drop(child.kill());
+            drop(child.wait());
+            return Err(Failure::User(timeout_error.to_owned(), None));
+        }
+
+        sleep(POLL_INTERVAL);
+    }
+}
+
+// Read whatever a child process wrote to its standard error stream.
+fn read_stderr(stderr: Option<ChildStderr>) -> String {
+    let mut contents = String::new();
+    if let Some(mut stderr) = stderr {
+        drop(stderr.read_to_string(&mut contents));
+    }
+    contents
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use {
+        crate::ssh_cache::SshCache,
+        std::{
+            fs::{read_to_string, set_permissions, write, Permissions},
+            os::unix::fs::PermissionsExt,
+            path::Path,
+            sync::{atomic::AtomicBool, Arc, Mutex},
+            time::Duration,
+        },
+        tempfile::{tempdir, TempDir},
+    };
+
+    // Executing a script while another thread has it open for writing fails with `ETXTBSY`, so the
+    // tests which write scripts run one at a time.
+    static SCRIPTS: Mutex<()> = Mutex::new(());
+
+    // Write an executable shell script.
+    fn script(dir: &Path, name: &str, body: &str) -> String {
+        let path = dir.join(name);
+        write(&path, format!("#!/bin/sh\n{body}")).unwrap();
+        set_permissions(&path, Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    // Construct an SSH cache which uses a fake `ssh` that emulates a remote Docker daemon with a
+    // single image called `toast:present`. Images loaded on the remote are written to `loaded`.
+    fn fake_cache(dir: &TempDir, timeout: Duration) -> SshCache {
+        let loaded = dir.path().join("loaded");
+        let ssh_cli = script(
+            dir.path(),
+            "ssh",
+            &format!(
+                r#"
+while [ "$1" = "-o" ]; do shift 2; done
+shift
+shift
+case "$1 $2" in
+  "image inspect")
+    [ "$3" = "toast:present" ] || {{ echo "Error: No such image: $3" >&2; exit 1; }} ;;
+  "image save")
+    printf 'remote-image' ;;
+  "image load")
+    cat > '{}' ;;
+esac
+"#,
+                loaded.to_string_lossy(),
+            ),
+        );
+
+        SshCache {
+            ssh_cli,
+            host: "cache.example.com".to_owned(),
+            timeout,
+        }
+    }
+
+    #[test]
+    fn image_exists_present() {
+        let _lock = SCRIPTS.lock().unwrap();
+        let dir = tempdir().unwrap();
+        let cache = fake_cache(&dir, Duration::from_secs(10));
+
+        assert!(cache
+            .image_exists("toast:present", &Arc::new(AtomicBool::new(false)))
+            .unwrap());
+    }
+
+    #[test]
+    fn image_exists_absent() {
+        let _lock = SCRIPTS.lock().unwrap();
+        let dir = tempdir().unwrap();
+        let cache = fake_cache(&dir, Duration::from_secs(10));
+
+        assert!(!cache
+            .image_exists("toast:absent", &Arc::new(AtomicBool::new(false)))
+            .unwrap());
+    }
+
+    #[test]
+    fn image_exists_auth_failure() {
+        let _lock = SCRIPTS.lock().unwrap();
+        let dir = tempdir().unwrap();
+        let cache = SshCache {
+            ssh_cli: script(
+                dir.path(),
+                "ssh",
+                "echo 'Permission denied (publickey).' >&2\nexit 255\n",
+            ),
+            host: "cache.example.com".to_owned(),
+            timeout: Duration::from_secs(10),
+        };
+
+        let error = cache
+            .image_exists("toast:present", &Arc::new(AtomicBool::new(false)))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("BatchMode"));
+        assert!(error.contains("Permission denied"));
+    }
+
+    #[test]
+    fn image_exists_timeout() {
+        let _lock = SCRIPTS.lock().unwrap();
+        let dir = tempdir().unwrap();
+        let cache = SshCache {
+            ssh_cli: script(dir.path(), "ssh", "exec sleep 10\n"),
+            host: "cache.example.com".to_owned(),
+            timeout: Duration::from_millis(200),
+        };
+
+        let error = cache
+            .image_exists("toast:present", &Arc::new(AtomicBool::new(false)))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Timed out"));
+    }
+
+    #[test]
+    fn fetch_image() {
+        let _lock = SCRIPTS.lock().unwrap();
+        let dir = tempdir().unwrap();
+        let cache = fake_cache(&dir, Duration::from_secs(10));
+        let docker_loaded = dir.path().join("docker-loaded");
+        let docker_cli = script(
+            dir.path(),
+            "docker",
+            &format!("cat > '{}'\n", docker_loaded.to_string_lossy()),
+        );
+
+        cache
+            .fetch_image(
+                &docker_cli,
+                "toast:present",
+                &Arc::new(AtomicBool::new(false)),
+            )
+            .unwrap();
+
+        assert_eq!(read_to_string(docker_loaded).unwrap(), "remote-image");
+    }
+
+    #[test]
+    fn send_image() {
+        let _lock = SCRIPTS.lock().unwrap();
+        let dir = tempdir().unwrap();
+        let cache = fake_cache(&dir, Duration::from_secs(10));
+        let docker_cli = script(dir.path(), "docker", "printf 'local-image'\n");
+
+        cache
+            .send_image(&docker_cli, "toast:new", &Arc::new(AtomicBool::new(false)))
+            .unwrap();
+
+        assert_eq!(
+            read_to_string(dir.path().join("loaded")).unwrap(),
+            "local-image",
+        );
+    }
+
+    #[test]
+    fn send_image_auth_failure() {
+        let _lock = SCRIPTS.lock().unwrap();
+        let dir = tempdir().unwrap();
+        let cache = SshCache {
+            ssh_cli: script(
+                dir.path(),
+                "ssh",
+                "cat > /dev/null\necho 'Permission denied (publickey).' >&2\nexit 255\n",
+            ),
+            host: "cache.example.com".to_owned(),
+            timeout: Duration::from_secs(10),
+        };
+        let docker_cli = script(dir.path(), "docker", "printf 'local-image'\n");
+
+        let error = cache
+            .send_image(&docker_cli, "toast:new", &Arc::new(AtomicBool::new(false)))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Permission denied"));
+    }
+}
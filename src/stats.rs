@@ -0,0 +1,171 @@
+use {
+    crate::{docker, units},
+    std::{
+        sync::{
+            mpsc::{channel, RecvTimeoutError, Sender},
+            Arc, Mutex,
+        },
+        thread,
+        time::{Duration, Instant},
+    },
+};
+
+// A single reading of the resources used by a container
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    pub memory_bytes: u64,
+    pub cpu_percent: f64,
+}
+
+// The resources used by a container over the course of a task
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Usage {
+    pub peak_memory_bytes: u64,
+    pub cpu_seconds: f64,
+    pub samples: usize,
+}
+
+impl Usage {
+    // Incorporate a sample which was taken `elapsed` after the previous one. The CPU percentage is
+    // assumed to hold for that whole period.
+    pub fn record(&mut self, sample: Sample, elapsed: Duration) {
+        self.peak_memory_bytes = self.peak_memory_bytes.max(sample.memory_bytes);
+        self.cpu_seconds += sample.cpu_percent / 100.0_f64 * elapsed.as_secs_f64();
+        self.samples += 1;
+    }
+}
+
+// This struct polls the resource usage of a container from a background thread.
+pub struct Monitor {
+    stop: Sender<()>,
+    usage: Arc<Mutex<Usage>>,
+}
+
+impl Monitor {
+    // Start polling a container every `interval`.
+    pub fn start(docker_cli: &str, container: &str, interval: Duration) -> Self {
+        let (stop, stopped) = channel();
+        let usage = Arc::new(Mutex::new(Usage::default()));
+
+        let docker_cli = docker_cli.to_owned();
+        let container = container.to_owned();
+        let thread_usage = usage.clone();
+        thread::spawn(move || {
+            let mut last_sample = Instant::now();
+
+            loop {
+                // If the container isn't running (yet or anymore), there's nothing to record.
+                if let Some(sample) = docker::container_stats(&docker_cli, &container) {
+                    let now = Instant::now();
+
+                    // The `unwrap` will only fail if a panic already occurred.
+                    thread_usage
+                        .lock()
+                        .unwrap()
+                        .record(sample, now - last_sample);
+
+                    last_sample = now;
+                }
+
+                // Wait for the next poll, unless we've been told to stop.
+                match stopped.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        });
+
+        Self { stop, usage }
+    }
+
+    // Stop polling and return what was recorded. This doesn't wait for a poll in progress to
+    // finish, so the task isn't held up by a slow daemon.
+    pub fn finish(self) -> Usage {
+        let _ = self.stop.send(());
+
+        // The `unwrap` will only fail if a panic already occurred.
+        *self.usage.lock().unwrap()
+    }
+}
+
+// Render a summary of the resources used by a task.
+pub fn format_usage(usage: &Usage) -> String {
+    if usage.samples == 0 {
+        "no samples".to_owned()
+    } else {
+        format!(
+            "peak memory {}, CPU time {:.1}s",
+            units::format_bytes(usage.peak_memory_bytes),
+            usage.cpu_seconds,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::stats::{format_usage, Sample, Usage},
+        std::time::Duration,
+    };
+
+    #[test]
+    fn usage_empty() {
+        let usage = Usage::default();
+
+        assert_eq!(usage.peak_memory_bytes, 0);
+        assert_eq!(usage.samples, 0);
+        assert_eq!(format_usage(&usage), "no samples");
+    }
+
+    #[test]
+    fn usage_peak_memory() {
+        let mut usage = Usage::default();
+        for memory_bytes in [100, 300, 200] {
+            usage.record(
+                Sample {
+                    memory_bytes,
+                    cpu_percent: 0.0,
+                },
+                Duration::from_secs(1),
+            );
+        }
+
+        assert_eq!(usage.peak_memory_bytes, 300);
+        assert_eq!(usage.samples, 3);
+    }
+
+    #[test]
+    fn usage_cumulative_cpu() {
+        let mut usage = Usage::default();
+        usage.record(
+            Sample {
+                memory_bytes: 0,
+                cpu_percent: 50.0,
+            },
+            Duration::from_secs(2),
+        );
+        usage.record(
+            Sample {
+                memory_bytes: 0,
+                cpu_percent: 200.0,
+            },
+            Duration::from_secs(3),
+        );
+
+        assert!((usage.cpu_seconds - 7.0_f64).abs() < 1e-9_f64);
+    }
+
+    #[test]
+    fn format_usage_samples() {
+        let mut usage = Usage::default();
+        usage.record(
+            Sample {
+                memory_bytes: 1024,
+                cpu_percent: 100.0,
+            },
+            Duration::from_millis(1500),
+        );
+
+        assert_eq!(format_usage(&usage), "peak memory 1.0 KiB, CPU time 1.5s");
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::VecDeque;
+
+// This struct retains the last few lines of a stream of output and counts how many earlier lines
+// were discarded to stay within the limit.
+pub struct Tail {
+    max_lines: usize,
+    lines: VecDeque<String>,
+    suppressed: usize,
+}
+
+impl Tail {
+    // Construct an empty tail which retains at most `max_lines` lines.
+    pub fn new(max_lines: usize) -> Self {
+        Self {
+            max_lines,
+            lines: VecDeque::new(),
+            suppressed: 0,
+        }
+    }
+
+    // Add a line, evicting the oldest retained line if the limit has been reached.
+    pub fn push(&mut self, line: String) {
+        if self.max_lines == 0 {
+            self.suppressed += 1;
+            return;
+        }
+
+        if self.lines.len() == self.max_lines {
+            self.lines.pop_front();
+            self.suppressed += 1;
+        }
+
+        self.lines.push_back(line);
+    }
+
+    // Return the number of lines which have been discarded so far.
+    pub fn suppressed(&self) -> usize {
+        self.suppressed
+    }
+
+    // Return the retained lines, oldest first.
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(AsRef::as_ref)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tail::Tail;
+
+    #[test]
+    fn tail_empty() {
+        let tail = Tail::new(3);
+
+        assert_eq!(tail.lines().count(), 0);
+        assert_eq!(tail.suppressed(), 0);
+    }
+
+    #[test]
+    fn tail_under_limit() {
+        let mut tail = Tail::new(3);
+        tail.push("foo".to_owned());
+        tail.push("bar".to_owned());
+
+        assert_eq!(tail.lines().collect::<Vec<_>>(), vec!["foo", "bar"]);
+        assert_eq!(tail.suppressed(), 0);
+    }
+
+    #[test]
+    fn tail_at_limit() {
+        let mut tail = Tail::new(3);
+        tail.push("foo".to_owned());
+        tail.push("bar".to_owned());
+        tail.push("baz".to_owned());
+
+        assert_eq!(tail.lines().collect::<Vec<_>>(), vec!["foo", "bar", "baz"]);
+        assert_eq!(tail.suppressed(), 0);
+    }
+
+    #[test]
+    fn tail_over_limit() {
+        let mut tail = Tail::new(3);
+        for line in ["foo", "bar", "baz", "qux", "quux"] {
+            tail.push(line.to_owned());
+        }
+
+        assert_eq!(tail.lines().collect::<Vec<_>>(), vec!["baz", "qux", "quux"]);
+        assert_eq!(tail.suppressed(), 2);
+    }
+
+    #[test]
+    fn tail_zero_limit() {
+        let mut tail = Tail::new(0);
+        tail.push("foo".to_owned());
+        tail.push("bar".to_owned());
+
+        assert_eq!(tail.lines().count(), 0);
+        assert_eq!(tail.suppressed(), 2);
+    }
+}
@@ -1,17 +1,22 @@
 use {
-    crate::{cache, cache::CryptoHash, failure, failure::Failure, format::CodeStr, spinner::spin},
+    crate::{
+        cache, cache::CryptoHash, failure, failure::Failure, format, format::CodeStr,
+        spinner::spin, units::format_bytes,
+    },
+    glob::{glob_with, MatchOptions, Pattern},
+    ignore::WalkBuilder,
     std::{
-        collections::HashSet,
-        fs::{read_link, symlink_metadata, File, Metadata},
+        collections::{HashMap, HashSet},
+        fs::{canonicalize, read_link, symlink_metadata, File, Metadata},
         io::{empty, Read, Seek, SeekFrom, Write},
-        path::Path,
+        path::{Component, Path, PathBuf},
         sync::{
             atomic::{AtomicBool, Ordering},
-            Arc,
+            Arc, Mutex,
         },
     },
-    tar::{Builder, EntryType, Header},
-    typed_path::{TryAsRef, UnixPath, UnixPathBuf},
+    tar::{Archive, Builder, EntryType, Header},
+    typed_path::{unix::UnixComponent, TryAsRef, UnixPath, UnixPathBuf},
     walkdir::WalkDir,
 };
 
@@ -27,6 +32,34 @@ use {
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 
+// The permissions given to directories and files in the archive. Executable files additionally get
+// the execute bit wherever `file` has the read bit.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Modes {
+    pub directory: u32,
+    pub file: u32,
+}
+
+impl Default for Modes {
+    fn default() -> Self {
+        Self {
+            directory: 0o777,
+            file: 0o666,
+        }
+    }
+}
+
+impl Modes {
+    // Compute the mode for a file.
+    fn file_mode(self, executable: bool) -> u32 {
+        if executable {
+            self.file | ((self.file & 0o444) >> 2)
+        } else {
+            self.file
+        }
+    }
+}
+
 #[cfg(unix)]
 fn is_file_executable(metadata: &Metadata) -> bool {
     // Determine if the file has the executable bit set.
@@ -40,11 +73,208 @@ fn is_file_executable(_metadata: &Metadata) -> bool {
     true
 }
 
+// The number of subdirectories to mention when the input files are suspiciously large
+const LARGEST_SUBDIRECTORIES: usize = 3;
+
+// Thresholds above which the input files of a task are suspiciously large, e.g., because an input
+// path accidentally includes a big data directory. Exceeding them produces a warning, or an error if
+// `strict` is `true`.
+pub struct SizeLimits {
+    pub bytes: u64,
+    pub files: u64,
+    pub strict: bool,
+
+    // The input paths which have already been warned about, so each warning is only shown once even
+    // if the files are read more than once
+    warned: Mutex<HashSet<String>>,
+}
+
+impl SizeLimits {
+    pub fn new(bytes: u64, files: u64, strict: bool) -> Self {
+        Self {
+            bytes,
+            files,
+            strict,
+            warned: Mutex::new(HashSet::new()),
+        }
+    }
+}
+
+// This struct keeps track of how much data is being added to an archive.
+#[derive(Default)]
+struct SizeTracker {
+    bytes: u64,
+    files: u64,
+
+    // The number of bytes in each subdirectory (relative to the source directory) of the input
+    // paths
+    subdirectories: HashMap<PathBuf, u64>,
+
+    exceeded: bool,
+}
+
+impl SizeTracker {
+    // Account for a file, which may be in a subdirectory of an input path. Returns whether this
+    // caused the limits to be exceeded for the first time.
+    fn add_file(&mut self, limits: &SizeLimits, subdirectory: Option<PathBuf>, size: u64) -> bool {
+        self.bytes += size;
+        self.files += 1;
+        if let Some(subdirectory) = subdirectory {
+            *self.subdirectories.entry(subdirectory).or_default() += size;
+        }
+
+        if !self.exceeded && (self.bytes > limits.bytes || self.files > limits.files) {
+            self.exceeded = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Find the largest subdirectories seen so far, in descending order of size.
+    fn largest_subdirectories(&self, count: usize) -> Vec<(&Path, u64)> {
+        let mut subdirectories = self
+            .subdirectories
+            .iter()
+            .map(|(path, bytes)| (path.as_path(), *bytes))
+            .collect::<Vec<_>>();
+        subdirectories.sort_by(|(x_path, x_bytes), (y_path, y_bytes)| {
+            y_bytes.cmp(x_bytes).then(x_path.cmp(y_path))
+        });
+        subdirectories.truncate(count);
+        subdirectories
+    }
+}
+
+// Warn the user about input files which exceeded the size limits while reading the given input
+// path, or fail if the limits are strict.
+fn report_size(
+    limits: &SizeLimits,
+    tracker: &SizeTracker,
+    input_path_rsd: &UnixPath,
+) -> Result<(), Failure> {
+    let largest = tracker
+        .largest_subdirectories(LARGEST_SUBDIRECTORIES)
+        .into_iter()
+        .map(|(path, bytes)| {
+            format!(
+                "{} ({})",
+                path.to_string_lossy().code_str(),
+                format_bytes(bytes),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let message =
+        format!(
+        "The input files are suspiciously large ({} in {} so far) while reading {}.{} If some of \
+         them aren't needed, consider listing them in {}.",
+        format_bytes(tracker.bytes),
+        format::number(usize::try_from(tracker.files).unwrap_or(usize::MAX), "file"),
+        input_path_rsd.to_string_lossy().code_str(),
+        if largest.is_empty() {
+            String::new()
+        } else {
+            format!(" The largest subdirectories are {}.", format::series(&largest))
+        },
+        "excluded_input_paths".code_str(),
+    );
+
+    if limits.strict {
+        return Err(Failure::User(message, None));
+    }
+
+    // The `unwrap` will only fail if a panic already occurred.
+    if limits
+        .warned
+        .lock()
+        .unwrap()
+        .insert(input_path_rsd.to_string_lossy().into_owned())
+    {
+        warn!("{}", message);
+    }
+
+    Ok(())
+}
+
 // Tar archives must contain only relative paths. For our purposes, the paths will be relative to
-// the filesystem root, so we need to strip the leading `/` before adding paths to the archive.
-fn strip_root_rcr(path_acr: &UnixPath) -> &UnixPath {
-    // The `unwrap` is safe since `absolute_path` is absolute and Toast only supports Unix.
-    path_acr.strip_prefix("/").unwrap()
+// the filesystem root, so we need to strip the leading `/` before adding paths to the archive. Any
+// `.` components are dropped too, so a file always ends up with the same path in the archive.
+fn strip_root_rcr(path_acr: &UnixPath) -> Result<UnixPathBuf, Failure> {
+    let mut components = path_acr.components();
+    if components.next() != Some(UnixComponent::RootDir) {
+        return Err(Failure::System(
+            format!(
+                "Unable to add {} to the archive, since it isn't absolute.",
+                path_acr.to_string_lossy().code_str(),
+            ),
+            None,
+        ));
+    }
+
+    let mut path_rcr = UnixPathBuf::new();
+    for component in components {
+        if component != UnixComponent::CurDir {
+            path_rcr.push(component);
+        }
+    }
+
+    Ok(path_rcr)
+}
+
+// Lexically remove the `.` components of a path in the host filesystem. Only a leading one can
+// remain after parsing, but it's enough to make `./src/foo.txt` fail to be under `src`.
+fn normalize_cd(path_cd: &Path) -> PathBuf {
+    path_cd
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+// Resolve the symbolic links in a path, except for the last component, which may itself be a
+// symbolic link that the archive should contain rather than follow.
+fn canonicalize_parent_cd(path_cd: &Path) -> Option<PathBuf> {
+    match (path_cd.parent(), path_cd.file_name()) {
+        (Some(parent_cd), Some(name)) => canonicalize(if parent_cd.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent_cd
+        })
+        .ok()
+        .map(|parent| parent.join(name)),
+        _ => canonicalize(path_cd).ok(),
+    }
+}
+
+// Compute the path of a host file relative to the source directory. The paths are compared
+// lexically first. If that fails, which can happen when they don't take the same route through a
+// symbolic link (e.g., `/tmp` is a link to `/private/tmp` on macOS), they are compared again with
+// the symbolic links resolved.
+fn relativize_rsd(path_cd: &Path, source_dir_cd: &Path) -> Result<PathBuf, Failure> {
+    let normalized_path_cd = normalize_cd(path_cd);
+    let normalized_source_dir_cd = normalize_cd(source_dir_cd);
+    if let Ok(path_rsd) = normalized_path_cd.strip_prefix(&normalized_source_dir_cd) {
+        return Ok(path_rsd.to_owned());
+    }
+
+    canonicalize_parent_cd(path_cd)
+        .zip(canonicalize(source_dir_cd).ok())
+        .and_then(|(canonical_path_cd, canonical_source_dir_cd)| {
+            canonical_path_cd
+                .strip_prefix(canonical_source_dir_cd)
+                .ok()
+                .map(Path::to_owned)
+        })
+        .ok_or_else(|| {
+            Failure::System(
+                format!(
+                    "Unable to relativize path {} with respect to {}.",
+                    path_cd.to_string_lossy().code_str(),
+                    source_dir_cd.to_string_lossy().code_str(),
+                ),
+                None,
+            )
+        })
 }
 
 // Check if a file is denied by `excluded_input_paths`.
@@ -59,6 +289,115 @@ fn path_excluded(excluded_input_paths_rcr: &[UnixPathBuf], path_rcr: &UnixPath)
     false
 }
 
+// Compute where a path in the host filesystem goes in the container, relative to the root.
+fn host_path_rcr(
+    path_cd: &Path,
+    source_dir_cd: &Path,
+    destination_dir_acr: &UnixPath,
+) -> Result<UnixPathBuf, Failure> {
+    let path_rsd = relativize_rsd(path_cd, source_dir_cd)?;
+    let path_acr = destination_dir_acr.join(path_rsd.try_as_ref().ok_or_else(|| {
+        Failure::User(
+            format!("Invalid path {}", path_rsd.to_string_lossy().code_str()),
+            None,
+        )
+    })?);
+
+    strip_root_rcr(&path_acr)
+}
+
+// An entry found while traversing a directory of input files
+struct WalkEntry {
+    path_cd: PathBuf,
+    metadata: Metadata,
+
+    // How many levels below the traversed directory the entry is, where the directory itself is at
+    // depth zero
+    depth: usize,
+}
+
+// Traverse a directory of input files without following symbolic links. Directories which are denied
+// by `excluded_input_paths` aren't descended into. This is merely an optimization, since `add_path`
+// would otherwise skip their contents anyway. If `respect_gitignore` is `true`, the paths which are
+// ignored by `.gitignore` or `.ignore` files are skipped too, as is the `.git` directory. Global and
+// repository-local exclusions (e.g., `.git/info/exclude`) aren't used, since they can differ between
+// machines and would make the cache keys unpredictable.
+fn walk_directory(
+    directory_cd: &Path,
+    source_dir_cd: &Path,
+    destination_dir_acr: &UnixPath,
+    excluded_input_paths_rcr: &[UnixPathBuf],
+    respect_gitignore: bool,
+) -> Box<dyn Iterator<Item = Result<WalkEntry, Failure>>> {
+    let source_dir_cd = source_dir_cd.to_owned();
+    let destination_dir_acr = destination_dir_acr.to_owned();
+    let excluded_input_paths_rcr = excluded_input_paths_rcr.to_vec();
+    let included = move |path_cd: &Path, is_dir: bool| {
+        !is_dir
+            || host_path_rcr(path_cd, &source_dir_cd, &destination_dir_acr)
+                .map_or(true, |path_rcr| {
+                    !path_excluded(&excluded_input_paths_rcr, &path_rcr)
+                })
+    };
+    let traversal_error = {
+        let directory_cd = directory_cd.to_owned();
+        move || {
+            format!(
+                "Unable to traverse directory {}.",
+                directory_cd.to_string_lossy().code_str(),
+            )
+        }
+    };
+
+    if respect_gitignore {
+        Box::new(
+            WalkBuilder::new(directory_cd)
+                .standard_filters(false)
+                .git_ignore(true)
+                .ignore(true)
+                .parents(true)
+                .require_git(false)
+                .filter_entry(move |entry| {
+                    let is_dir = entry
+                        .file_type()
+                        .is_some_and(|file_type| file_type.is_dir());
+                    !(is_dir && entry.file_name() == ".git") && included(entry.path(), is_dir)
+                })
+                .build()
+                .map(move |entry| {
+                    let entry = entry.map_err(failure::user(traversal_error()))?;
+                    let metadata = entry.metadata().map_err(failure::system(format!(
+                        "Unable to fetch filesystem metadata for {}.",
+                        entry.path().to_string_lossy().code_str(),
+                    )))?;
+                    Ok(WalkEntry {
+                        depth: entry.depth(),
+                        path_cd: entry.into_path(),
+                        metadata,
+                    })
+                }),
+        )
+    } else {
+        Box::new(
+            WalkDir::new(directory_cd)
+                .into_iter()
+                .filter_entry(move |entry| included(entry.path(), entry.file_type().is_dir()))
+                .map(move |entry| {
+                    let entry = entry.map_err(failure::user(traversal_error()))?;
+                    let metadata = entry.metadata().map_err(failure::system(format!(
+                        "Unable to fetch filesystem metadata for {}.",
+                        entry.path().to_string_lossy().code_str(),
+                    )))?;
+                    Ok(WalkEntry {
+                        depth: entry.depth(),
+                        path_cd: entry.into_path(),
+                        metadata,
+                    })
+                }),
+        )
+    }
+}
+
 // Check if a path can be added to the archive. This function also adds the path to `visited_paths`.
 fn can_add_path(
     visited_paths_rcr: &mut HashSet<UnixPathBuf>,
@@ -85,11 +424,12 @@ fn add_file<R: Read, W: Write>(
     data: R,
     size: u64,
     executable: bool,
+    modes: Modes,
 ) -> Result<(), Failure> {
     // Construct a tar header for this entry.
     let mut header = Header::new_gnu();
     header.set_entry_type(EntryType::Regular);
-    header.set_mode(if executable { 0o777 } else { 0o666 });
+    header.set_mode(modes.file_mode(executable));
     header.set_size(size);
 
     // Add the entry to the archive.
@@ -151,7 +491,11 @@ fn add_symlink<W: Write>(
 }
 
 // Add a directory to a tar archive.
-fn add_directory<W: Write>(builder: &mut Builder<W>, path_rcr: &UnixPath) -> Result<(), Failure> {
+fn add_directory<W: Write>(
+    builder: &mut Builder<W>,
+    path_rcr: &UnixPath,
+    modes: Modes,
+) -> Result<(), Failure> {
     // If the path has no components, there's nothing to do. The root directory will already exist.
     // Without this check, we could encounter the following error: `paths in archives must have at
     // least one component when setting path for`.
@@ -162,7 +506,7 @@ fn add_directory<W: Write>(builder: &mut Builder<W>, path_rcr: &UnixPath) -> Res
     // Construct a tar header for this entry.
     let mut header = Header::new_gnu();
     header.set_entry_type(EntryType::Directory);
-    header.set_mode(0o777);
+    header.set_mode(modes.directory);
     header.set_size(0);
 
     // Add the entry to the archive.
@@ -184,14 +528,16 @@ fn add_directory<W: Write>(builder: &mut Builder<W>, path_rcr: &UnixPath) -> Res
 }
 
 // Add a file, symlink, or directory to a tar archive.
+#[allow(clippy::too_many_arguments)]
 fn add_path<W: Write>(
     builder: &mut Builder<W>,
-    content_hashes: &mut Vec<String>,
+    content_hashes: Option<&mut Vec<String>>,
     visited_paths_rcr: &mut HashSet<UnixPathBuf>,
     excluded_input_paths_rcr: &[UnixPathBuf],
     path_cd: &Path,
     path_rcr: &UnixPath,
     metadata: &Metadata,
+    modes: Modes,
 ) -> Result<(), Failure> {
     // Check if this path should be added.
     if !can_add_path(visited_paths_rcr, excluded_input_paths_rcr, path_rcr) {
@@ -203,7 +549,7 @@ fn add_path<W: Write>(
     if let Some(parent_rcr) = path_rcr.parent() {
         for ancestor_rcr in parent_rcr.ancestors() {
             if can_add_path(visited_paths_rcr, excluded_input_paths_rcr, ancestor_rcr) {
-                add_directory(builder, ancestor_rcr)?;
+                add_directory(builder, ancestor_rcr, modes)?;
             }
         }
     }
@@ -219,21 +565,23 @@ fn add_path<W: Write>(
             path_cd.to_string_lossy().code_str(),
         )))?;
 
-        // Compute the hash of the file contents and metadata.
-        content_hashes.push(cache::combine(
-            &cache::combine(&path_rcr.crypto_hash(), &cache::hash_read(&mut file)?),
-            if executable { "+x" } else { "-x" },
-        ));
+        // Compute the hash of the file contents and metadata, if needed.
+        if let Some(content_hashes) = content_hashes {
+            content_hashes.push(cache::combine(
+                &cache::combine(&path_rcr.crypto_hash(), &cache::hash_read(&mut file)?),
+                if executable { "+x" } else { "-x" },
+            ));
 
-        // Jump back to the beginning of the file so the tar builder can read it.
-        file.seek(SeekFrom::Start(0))
-            .map_err(failure::system(format!(
-                "Unable to seek file {}.",
-                path_cd.to_string_lossy().code_str(),
-            )))?;
+            // Jump back to the beginning of the file so the tar builder can read it.
+            file.seek(SeekFrom::Start(0))
+                .map_err(failure::system(format!(
+                    "Unable to seek file {}.",
+                    path_cd.to_string_lossy().code_str(),
+                )))?;
+        }
 
         // Add the file to the archive and return.
-        add_file(builder, path_rcr, file, metadata.len(), executable)
+        add_file(builder, path_rcr, file, metadata.len(), executable, modes)
     } else if metadata.file_type().is_symlink() {
         // It's a symlink. Read the target path.
         let target_path_std = read_link(path_cd).map_err(failure::system(format!(
@@ -247,17 +595,21 @@ fn add_path<W: Write>(
             )
         })?;
 
-        // Compute the hash of the symlink path and the target path.
-        content_hashes.push(cache::combine(path_rcr, target_path));
+        // Compute the hash of the symlink path and the target path, if needed.
+        if let Some(content_hashes) = content_hashes {
+            content_hashes.push(cache::combine(path_rcr, target_path));
+        }
 
         // Add the symlink to the archive.
         add_symlink(builder, path_rcr, target_path)
     } else if metadata.file_type().is_dir() {
         // It's a directory. Only its name is relevant for the cache key.
-        content_hashes.push(path_rcr.crypto_hash());
+        if let Some(content_hashes) = content_hashes {
+            content_hashes.push(path_rcr.crypto_hash());
+        }
 
         // Add the directory to the archive.
-        add_directory(builder, path_rcr)
+        add_directory(builder, path_rcr, modes)
     } else {
         Err(Failure::User(
             format!(
@@ -269,24 +621,130 @@ fn add_path<W: Write>(
     }
 }
 
+// Glob patterns in input paths follow the usual shell conventions: `*` doesn't match `/`, but `**`
+// matches any number of directories.
+const GLOB_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+// Check whether an input path is a glob pattern rather than a literal path.
+pub fn is_glob(path: &UnixPath) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+// Check whether a glob pattern is valid, returning an explanation if not.
+pub fn check_glob(path: &UnixPath) -> Result<(), String> {
+    Pattern::new(&path.to_string_lossy())
+        .map(|_| ())
+        .map_err(|error| error.msg.to_owned())
+}
+
+// Replace any glob patterns among the given paths with the paths they match, in sorted order.
+// Literal paths are kept as they are. Unless `allow_empty` is set, it's an error for a pattern to
+// match nothing, since that's probably a typo.
+fn expand_globs(
+    paths_rsd: &[UnixPathBuf],
+    source_dir_cd: &Path,
+    allow_empty: bool,
+) -> Result<Vec<UnixPathBuf>, Failure> {
+    let mut expanded_paths_rsd = vec![];
+
+    for path_rsd in paths_rsd {
+        if !is_glob(path_rsd) {
+            expanded_paths_rsd.push(path_rsd.clone());
+            continue;
+        }
+
+        // The pattern is relative to `source_dir_cd`, which could itself contain special characters.
+        let pattern_cd = Path::new(&Pattern::escape(&source_dir_cd.to_string_lossy()))
+            .join(&*path_rsd.to_string_lossy());
+        let matches = glob_with(&pattern_cd.to_string_lossy(), GLOB_OPTIONS).map_err(
+            failure::user(format!(
+                "Invalid glob pattern {}.",
+                path_rsd.to_string_lossy().code_str(),
+            )),
+        )?;
+
+        let mut found = false;
+        for match_cd in matches {
+            let match_cd = match_cd.map_err(failure::user(format!(
+                "Unable to expand glob pattern {}.",
+                path_rsd.to_string_lossy().code_str(),
+            )))?;
+            let match_rsd = relativize_rsd(&match_cd, source_dir_cd)?;
+            let match_rsd: &UnixPath = match_rsd.try_as_ref().ok_or_else(|| {
+                Failure::User(
+                    format!("Invalid path {}", match_rsd.to_string_lossy().code_str()),
+                    None,
+                )
+            })?;
+            expanded_paths_rsd.push(match_rsd.to_owned());
+            found = true;
+        }
+
+        if !found && !allow_empty {
+            return Err(Failure::User(
+                format!(
+                    "The glob pattern {} didn't match any paths. To allow this, set {}.",
+                    path_rsd.to_string_lossy().code_str(),
+                    "allow_empty_globs: true".code_str(),
+                ),
+                None,
+            ));
+        }
+    }
+
+    Ok(expanded_paths_rsd)
+}
+
+// The "hash" of the inputs when they aren't hashed. Real hashes are either empty or hexadecimal, so
+// this can't be confused with one.
+pub const UNHASHED: &str = "unhashed";
+
 // Construct a tar archive and return a hash of its contents. This function does not follow symbolic
-// links.
-#[allow(clippy::similar_names, clippy::too_many_lines)]
+// links. Glob patterns in the input paths are expanded first, so the hash covers whatever files
+// they currently match. Non-default `modes` are included in the hash, so the default modes don't
+// change existing cache keys. If `hash_contents` is `false`, the files are archived without being hashed, and
+// `UNHASHED` is returned instead of the hash. This saves reading each file twice when the hash
+// won't be used. If `respect_gitignore` is `true`, the files which are ignored by `.gitignore` or
+// `.ignore` files in the input directories are left out, along with their hashes.
+#[allow(
+    clippy::similar_names,
+    clippy::too_many_arguments,
+    clippy::too_many_lines
+)]
 pub fn create<W: Write>(
     spinner_message: &str,
     writer: W,
     input_paths_rsd: &[UnixPathBuf],
     excluded_input_paths_rsd: &[UnixPathBuf],
+    allow_empty_globs: bool,
+    respect_gitignore: bool,
     source_dir_cd: &Path,
     destination_dir_acr: &UnixPath,
+    modes: Modes,
+    hash_contents: bool,
+    size_limits: &SizeLimits,
     interrupted: &Arc<AtomicBool>,
 ) -> Result<(W, String), Failure> {
+    debug!(
+        "Reading files from {}\u{2026}",
+        source_dir_cd.to_string_lossy().code_str(),
+    );
+
     // Render a spinner animation in the terminal.
     let _guard = spin(spinner_message);
 
+    // Expand any glob patterns. An excluded pattern which matches nothing is harmless, so only the
+    // input paths are subject to `allow_empty_globs`.
+    let input_paths_rsd = expand_globs(input_paths_rsd, source_dir_cd, allow_empty_globs)?;
+    let excluded_input_paths_rsd = expand_globs(excluded_input_paths_rsd, source_dir_cd, true)?;
+
     // This vector will store all the hashes of the contents and metadata of all the files in the
     // archive. In the end, we will sort this vector and then take the hash of the whole thing.
-    let mut content_hashes = vec![];
+    let mut content_hashes = hash_contents.then(Vec::new);
 
     // This set is used to avoid adding the same path to the archive multiple times, which could
     // otherwise easily happen since we explicitly add all ancestor directories for every entry
@@ -296,20 +754,21 @@ pub fn create<W: Write>(
     // This builder will be responsible for writing to the tar file.
     let mut builder = Builder::new(writer);
 
+    // Keep track of how much data is being added, in case it's more than the user intended.
+    let mut size_tracker = SizeTracker::default();
+
     // Add `destination_dir_acr` to the archive.
-    add_directory(&mut builder, strip_root_rcr(destination_dir_acr))?;
+    add_directory(&mut builder, &strip_root_rcr(destination_dir_acr)?, modes)?;
     visited_paths_rcr.insert(UnixPathBuf::new());
 
     // Convert the `excluded_input_paths` to be relative to the container filesystem root.
     let excluded_input_paths_rcr = excluded_input_paths_rsd
         .iter()
-        .map(|excluded_input_path| {
-            strip_root_rcr(&destination_dir_acr.join(excluded_input_path)).to_owned()
-        })
-        .collect::<Vec<_>>();
+        .map(|excluded_input_path| strip_root_rcr(&destination_dir_acr.join(excluded_input_path)))
+        .collect::<Result<Vec<_>, _>>()?;
 
     // Add each path to the archive.
-    for input_path_rsd in input_paths_rsd {
+    for input_path_rsd in &input_paths_rsd {
         // The original `input_path` is relative to `source_dir_cd`. Here we make it relative to the
         // current working directory instead.
         let input_path_cd = source_dir_cd.join(input_path_rsd.try_as_ref().ok_or_else(|| {
@@ -332,101 +791,821 @@ pub fn create<W: Write>(
         // Check what type of filesystem object the path corresponds to.
         if input_path_metadata.is_dir() {
             // It's a directory. Traverse it.
-            let mut iterator = WalkDir::new(&input_path_cd).into_iter();
-            loop {
+            for entry in walk_directory(
+                &input_path_cd,
+                source_dir_cd,
+                destination_dir_acr,
+                &excluded_input_paths_rcr,
+                respect_gitignore,
+            ) {
                 // If the user wants to stop the operation, quit now.
                 if interrupted.load(Ordering::SeqCst) {
                     return Err(Failure::Interrupted);
                 }
 
-                // Unwrap the entry.
-                let entry = if let Some(entry) = iterator.next() {
-                    entry
-                } else {
-                    break;
-                }
-                .map_err(failure::user(format!(
-                    "Unable to traverse directory {}.",
-                    input_path_cd.to_string_lossy().code_str(),
-                )))?;
-
                 // Compute the path relative to the container filesystem root.
-                let entry_path_rsd =
-                    entry
-                        .path()
-                        .strip_prefix(source_dir_cd)
-                        .map_err(failure::system(format!(
-                            "Unable to relativize path {} with respect to {}.",
-                            entry.path().to_string_lossy().code_str(),
-                            source_dir_cd.to_string_lossy().code_str(),
-                        )))?;
-                let entry_path_acr =
-                    destination_dir_acr.join(entry_path_rsd.try_as_ref().ok_or_else(|| {
-                        Failure::User(
-                            format!(
-                                "Invalid path {}",
-                                entry_path_rsd.to_string_lossy().code_str(),
-                            ),
-                            None,
-                        )
-                    })?);
-                let entry_path_rcr = strip_root_rcr(&entry_path_acr);
-
-                // Fetch the metadata for this entry.
-                let entry_metadata = entry.metadata().map_err(failure::system(format!(
-                    "Unable to fetch filesystem metadata for {}.",
-                    entry.path().to_string_lossy().code_str(),
-                )))?;
+                let entry = entry?;
+                let entry_path_rcr =
+                    host_path_rcr(&entry.path_cd, source_dir_cd, destination_dir_acr)?;
 
-                // Skip descending into directories which are denied by `excluded_input_paths`.
-                // This is merely an optimization, since `add_path` would otherwise skip the
-                // contents of the directory anyway.
-                if entry_metadata.is_dir()
-                    && path_excluded(&excluded_input_paths_rcr, entry_path_rcr)
+                // Account for the size of the file. Files in subdirectories of the input path are
+                // attributed to those subdirectories, so the largest ones can be pointed out.
+                if entry.metadata.is_file()
+                    && !path_excluded(&excluded_input_paths_rcr, &entry_path_rcr)
                 {
-                    iterator.skip_current_dir();
-                    continue;
+                    let subdirectory = if entry.depth > 1 {
+                        entry
+                            .path_cd
+                            .strip_prefix(&input_path_cd)
+                            .ok()
+                            .and_then(|path| path.components().next())
+                            .and_then(|component| {
+                                relativize_rsd(&input_path_cd.join(component), source_dir_cd).ok()
+                            })
+                    } else {
+                        None
+                    };
+                    if size_tracker.add_file(size_limits, subdirectory, entry.metadata.len()) {
+                        report_size(size_limits, &size_tracker, input_path_rsd)?;
+                    }
                 }
 
                 // Add the path to the archive.
                 add_path(
                     &mut builder,
-                    &mut content_hashes,
+                    content_hashes.as_mut(),
                     &mut visited_paths_rcr,
                     &excluded_input_paths_rcr,
-                    entry.path(),
-                    entry_path_rcr,
-                    &entry_metadata,
+                    &entry.path_cd,
+                    &entry_path_rcr,
+                    &entry.metadata,
+                    modes,
                 )?;
             }
         } else {
             // Compute the path relative to the container filesystem root.
             let input_path_acr = destination_dir_acr.join(input_path_rsd);
-            let input_path_rcr = strip_root_rcr(&input_path_acr);
+            let input_path_rcr = strip_root_rcr(&input_path_acr)?;
+
+            // Account for the size of the file.
+            if input_path_metadata.is_file()
+                && size_tracker.add_file(size_limits, None, input_path_metadata.len())
+            {
+                report_size(size_limits, &size_tracker, input_path_rsd)?;
+            }
 
             // It's not a directory, so hopefully it's a file or symlink. Add it to the archive.
             add_path(
                 &mut builder,
-                &mut content_hashes,
+                content_hashes.as_mut(),
                 &mut visited_paths_rcr,
                 &excluded_input_paths_rcr,
                 &input_path_cd,
-                input_path_rcr,
+                &input_path_rcr,
                 &input_path_metadata,
+                modes,
             )?;
         }
     }
 
-    // Sort the file hashes to ensure the directory traversal order doesn't matter.
-    content_hashes.sort();
+    // Compute the hash of the contents, if needed.
+    let hash = content_hashes.map_or_else(
+        || UNHASHED.to_owned(),
+        |mut content_hashes| {
+            // Sort the file hashes to ensure the directory traversal order doesn't matter.
+            content_hashes.sort();
+
+            // Incorporate the modes if they differ from the defaults.
+            if modes != Modes::default() {
+                content_hashes.push(format!("modes:{:o}:{:o}", modes.directory, modes.file));
+            }
+
+            content_hashes
+                .iter()
+                .fold(String::new(), |acc, x| cache::combine(&acc, x))
+        },
+    );
 
     // Return the tar file and the hash of its contents.
     Ok((
         builder
             .into_inner()
             .map_err(failure::system("Error writing tar archive."))?,
-        content_hashes
-            .iter()
-            .fold(String::new(), |acc, x| cache::combine(&acc, x)),
+        hash,
     ))
 }
+
+// Describe the first `limit` entries of an archive created by `create`, for explaining why Docker
+// couldn't extract it. Each description has the path of the entry and its type. Directories can
+// appear more than once in an archive, but each path is only described once. An archive which can't
+// be read just has fewer entries.
+pub fn describe_entries<R: Read>(archive: R, limit: usize) -> Vec<String> {
+    let mut archive = Archive::new(archive);
+    let Ok(entries) = archive.entries() else {
+        return vec![];
+    };
+
+    let mut described_paths = HashSet::new();
+    entries
+        .map_while(Result::ok)
+        .filter_map(|entry| {
+            let path = entry.path().map_or_else(
+                |_| "?".to_owned(),
+                |path| path.to_string_lossy().into_owned(),
+            );
+            described_paths
+                .insert(path.clone())
+                .then(|| (path, entry.header().entry_type()))
+        })
+        .take(limit)
+        .map(|(path, entry_type)| {
+            format!(
+                "{} ({})",
+                path.code_str(),
+                if entry_type.is_dir() {
+                    "directory"
+                } else if entry_type.is_symlink() {
+                    "symbolic link"
+                } else if entry_type.is_file() {
+                    "file"
+                } else {
+                    "other"
+                },
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{
+            failure::Failure,
+            tar::{
+                create, describe_entries, relativize_rsd, strip_root_rcr, Modes, SizeLimits,
+                SizeTracker, UNHASHED,
+            },
+        },
+        std::{
+            collections::HashMap,
+            fs::{create_dir, create_dir_all, write},
+            io::Cursor,
+            path::{Path, PathBuf},
+            sync::{atomic::AtomicBool, Arc},
+        },
+        tar::Archive,
+        tempfile::tempdir,
+        typed_path::{UnixPath, UnixPathBuf},
+    };
+
+    #[cfg(unix)]
+    use std::os::unix::fs::symlink;
+
+    // Build an archive from a directory containing `foo/bar.txt` and return the mode of each entry
+    // along with the hash of the contents.
+    fn archive_modes(modes: Modes) -> (HashMap<String, u32>, String) {
+        archive(&[UnixPathBuf::from("foo")], modes, true)
+    }
+
+    // Build an archive of the given paths from a directory containing `foo/bar.txt` and return the
+    // mode of each entry along with the hash of the contents.
+    fn archive(
+        input_paths: &[UnixPathBuf],
+        modes: Modes,
+        hash_contents: bool,
+    ) -> (HashMap<String, u32>, String) {
+        let source_dir = tempdir().unwrap();
+        create_dir(source_dir.path().join("foo")).unwrap();
+        write(source_dir.path().join("foo").join("bar.txt"), "bar").unwrap();
+
+        let (archive, hash) = create(
+            "Reading files\u{2026}",
+            Cursor::new(vec![]),
+            input_paths,
+            &[],
+            false,
+            false,
+            source_dir.path(),
+            UnixPath::new("/scratch"),
+            modes,
+            hash_contents,
+            &SizeLimits::new(u64::MAX, u64::MAX, false),
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        let mut archive = Archive::new(Cursor::new(archive.into_inner()));
+        let entries = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (
+                    entry.path().unwrap().to_string_lossy().into_owned(),
+                    entry.header().mode().unwrap(),
+                )
+            })
+            .collect();
+
+        (entries, hash)
+    }
+
+    // Build an archive of `data` from a directory containing files of various sizes in several
+    // subdirectories of `data`.
+    fn archive_data(limits: &SizeLimits) -> Result<(), Failure> {
+        let source_dir = tempdir().unwrap();
+        let data_dir = source_dir.path().join("data");
+        create_dir(&data_dir).unwrap();
+        for (subdirectory, size) in [("small", 1), ("large", 300), ("medium", 200), ("tiny", 50)] {
+            create_dir(data_dir.join(subdirectory)).unwrap();
+            write(data_dir.join(subdirectory).join("file"), vec![0_u8; size]).unwrap();
+        }
+        write(data_dir.join("top.txt"), vec![0_u8; 1000]).unwrap();
+
+        create(
+            "Reading files\u{2026}",
+            Cursor::new(vec![]),
+            &[UnixPathBuf::from("data")],
+            &[],
+            false,
+            false,
+            source_dir.path(),
+            UnixPath::new("/scratch"),
+            Modes::default(),
+            true,
+            limits,
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .map(|_| ())
+    }
+
+    #[test]
+    fn modes_file_mode() {
+        let modes = Modes {
+            directory: 0o755,
+            file: 0o644,
+        };
+
+        assert_eq!(modes.file_mode(false), 0o644);
+        assert_eq!(modes.file_mode(true), 0o755);
+        assert_eq!(Modes::default().file_mode(true), 0o777);
+    }
+
+    #[test]
+    fn create_default_modes() {
+        let (entries, _) = archive_modes(Modes::default());
+
+        assert_eq!(entries["scratch"], 0o777);
+        assert_eq!(entries["scratch/foo"], 0o777);
+        assert_eq!(entries["scratch/foo/bar.txt"], 0o666);
+    }
+
+    #[test]
+    fn create_custom_modes() {
+        let (entries, _) = archive_modes(Modes {
+            directory: 0o755,
+            file: 0o644,
+        });
+
+        assert_eq!(entries["scratch"], 0o755);
+        assert_eq!(entries["scratch/foo"], 0o755);
+        assert_eq!(entries["scratch/foo/bar.txt"], 0o644);
+    }
+
+    #[test]
+    fn create_custom_modes_change_hash() {
+        let (_, default_hash) = archive_modes(Modes::default());
+        let (_, custom_hash) = archive_modes(Modes {
+            directory: 0o755,
+            file: 0o666,
+        });
+
+        assert_ne!(default_hash, custom_hash);
+    }
+
+    #[test]
+    fn create_without_hashing() {
+        let (hashed_entries, hash) = archive(&[UnixPathBuf::from("foo")], Modes::default(), true);
+        let (unhashed_entries, unhashed) =
+            archive(&[UnixPathBuf::from("foo")], Modes::default(), false);
+
+        assert_eq!(hashed_entries, unhashed_entries);
+        assert_eq!(unhashed, UNHASHED);
+        assert_ne!(hash, UNHASHED);
+    }
+
+    #[test]
+    fn unhashed_differs_from_empty_hash() {
+        let (_, hash) = archive(&[], Modes::default(), true);
+
+        assert_ne!(hash, UNHASHED);
+    }
+
+    #[test]
+    fn size_tracker_largest_subdirectories() {
+        let limits = SizeLimits::new(u64::MAX, u64::MAX, false);
+        let mut tracker = SizeTracker::default();
+        tracker.add_file(&limits, Some(PathBuf::from("b")), 10);
+        tracker.add_file(&limits, Some(PathBuf::from("a")), 10);
+        tracker.add_file(&limits, Some(PathBuf::from("c")), 5);
+        tracker.add_file(&limits, Some(PathBuf::from("c")), 15);
+        tracker.add_file(&limits, None, 100);
+
+        assert_eq!(tracker.bytes, 140);
+        assert_eq!(tracker.files, 5);
+        assert_eq!(
+            tracker.largest_subdirectories(2),
+            vec![(Path::new("c"), 20), (Path::new("a"), 10)],
+        );
+    }
+
+    #[test]
+    fn size_tracker_exceeded_once() {
+        let limits = SizeLimits::new(100, 3, false);
+        let mut tracker = SizeTracker::default();
+
+        assert!(!tracker.add_file(&limits, None, 50));
+        assert!(!tracker.add_file(&limits, None, 50));
+        assert!(tracker.add_file(&limits, None, 50));
+        assert!(!tracker.add_file(&limits, None, 50));
+    }
+
+    #[test]
+    fn size_tracker_too_many_files() {
+        let limits = SizeLimits::new(u64::MAX, 2, false);
+        let mut tracker = SizeTracker::default();
+
+        assert!(!tracker.add_file(&limits, None, 0));
+        assert!(!tracker.add_file(&limits, None, 0));
+        assert!(tracker.add_file(&limits, None, 0));
+    }
+
+    #[test]
+    fn create_within_size_limits() {
+        let limits = SizeLimits::new(u64::MAX, u64::MAX, true);
+
+        assert!(archive_data(&limits).is_ok());
+        assert!(limits.warned.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn create_exceeds_size_limits_warning() {
+        let limits = SizeLimits::new(100, u64::MAX, false);
+
+        assert!(archive_data(&limits).is_ok());
+        assert!(archive_data(&limits).is_ok());
+        assert_eq!(
+            limits.warned.lock().unwrap().iter().collect::<Vec<_>>(),
+            vec!["data"],
+        );
+    }
+
+    #[test]
+    fn create_exceeds_size_limits_strict() {
+        let limits = SizeLimits::new(1000, u64::MAX, true);
+
+        let error = archive_data(&limits).unwrap_err().to_string();
+        assert!(error.contains("suspiciously large"));
+        assert!(error.contains("excluded_input_paths"));
+    }
+
+    #[test]
+    fn create_exceeds_size_limits_largest_subdirectories() {
+        // Exceed the limit on the last file so every subdirectory has been counted.
+        let limits = SizeLimits::new(u64::MAX, 4, true);
+
+        let error = archive_data(&limits).unwrap_err().to_string();
+        let large = error.find("data/large").unwrap();
+        let medium = error.find("data/medium").unwrap();
+        let tiny = error.find("data/tiny").unwrap();
+        assert!(large < medium && medium < tiny);
+        assert!(!error.contains("data/small"));
+    }
+
+    // Build an archive from a directory containing the given files and return the paths of the files
+    // in the archive along with the hash of the contents.
+    fn archive_files(
+        files: &[&str],
+        input_paths: &[&str],
+        excluded_input_paths: &[&str],
+        allow_empty_globs: bool,
+        respect_gitignore: bool,
+    ) -> Result<(Vec<String>, String), Failure> {
+        let source_dir = tempdir().unwrap();
+        for file in files {
+            let path = source_dir.path().join(file);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            write(path, file).unwrap();
+        }
+
+        let paths = |paths: &[&str]| {
+            paths
+                .iter()
+                .map(|path| UnixPathBuf::from(*path))
+                .collect::<Vec<_>>()
+        };
+
+        let (archive, hash) = create(
+            "Reading files\u{2026}",
+            Cursor::new(vec![]),
+            &paths(input_paths),
+            &paths(excluded_input_paths),
+            allow_empty_globs,
+            respect_gitignore,
+            source_dir.path(),
+            UnixPath::new("/scratch"),
+            Modes::default(),
+            true,
+            &SizeLimits::new(u64::MAX, u64::MAX, false),
+            &Arc::new(AtomicBool::new(false)),
+        )?;
+
+        let mut archive = Archive::new(Cursor::new(archive.into_inner()));
+        let mut entries = archive
+            .entries()
+            .unwrap()
+            .map(Result::unwrap)
+            .filter(|entry| entry.header().entry_type().is_file())
+            .map(|entry| entry.path().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        entries.sort();
+
+        Ok((entries, hash))
+    }
+
+    #[test]
+    fn create_glob_input_paths() {
+        let (entries, _) = archive_files(
+            &[
+                "packages/foo/package.json",
+                "packages/bar/package.json",
+                "packages/bar/nested/package.json",
+                "package.json",
+            ],
+            &["packages/*/package.json"],
+            &[],
+            false,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                "scratch/packages/bar/package.json",
+                "scratch/packages/foo/package.json",
+            ],
+        );
+    }
+
+    #[test]
+    fn create_glob_recursive() {
+        let (entries, _) = archive_files(
+            &[
+                "foo.proto",
+                "api/bar.proto",
+                "api/v1/baz.proto",
+                "api/v1/baz.json",
+            ],
+            &["**/*.proto"],
+            &[],
+            false,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                "scratch/api/bar.proto",
+                "scratch/api/v1/baz.proto",
+                "scratch/foo.proto",
+            ],
+        );
+    }
+
+    #[test]
+    fn create_glob_excluded_input_paths() {
+        let (entries, _) = archive_files(
+            &["api/bar.proto", "api/v1/baz.proto", "test/qux.proto"],
+            &["."],
+            &["**/v1", "*/qux.proto", "nothing/*"],
+            false,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(entries, vec!["scratch/api/bar.proto"]);
+    }
+
+    #[test]
+    fn create_glob_no_matches() {
+        let Err(Failure::User(message, _)) =
+            archive_files(&["foo.json"], &["*.proto"], &[], false, false)
+        else {
+            panic!("Expected a user error.");
+        };
+        assert!(message.contains("*.proto"));
+        assert!(message.contains("allow_empty_globs"));
+    }
+
+    #[test]
+    fn create_glob_no_matches_allowed() {
+        let (entries, _) = archive_files(&["foo.json"], &["*.proto"], &[], true, false).unwrap();
+
+        assert!(entries.is_empty());
+    }
+
+    #[test]
+    fn create_glob_new_match_changes_hash() {
+        let (_, hash) = archive_files(&["foo.proto"], &["*.proto"], &[], false, false).unwrap();
+        let (_, other_hash) =
+            archive_files(&["foo.proto", "bar.proto"], &["*.proto"], &[], false, false).unwrap();
+
+        assert_ne!(hash, other_hash);
+    }
+
+    // Build an archive of `project` from a directory containing the given files (with the given
+    // contents) and return the paths of the files in the archive along with the hash of the contents.
+    fn archive_project(
+        files: &[(&str, &str)],
+        excluded_input_paths: &[&str],
+        respect_gitignore: bool,
+    ) -> (Vec<String>, String) {
+        let source_dir = tempdir().unwrap();
+        for (file, contents) in files {
+            let path = source_dir.path().join("project").join(file);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            write(path, contents).unwrap();
+        }
+
+        let (archive, hash) = create(
+            "Reading files\u{2026}",
+            Cursor::new(vec![]),
+            &[UnixPathBuf::from("project")],
+            &excluded_input_paths
+                .iter()
+                .map(|path| UnixPathBuf::from(*path))
+                .collect::<Vec<_>>(),
+            false,
+            respect_gitignore,
+            source_dir.path(),
+            UnixPath::new("/scratch"),
+            Modes::default(),
+            true,
+            &SizeLimits::new(u64::MAX, u64::MAX, false),
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        let mut archive = Archive::new(Cursor::new(archive.into_inner()));
+        let mut entries = archive
+            .entries()
+            .unwrap()
+            .map(Result::unwrap)
+            .filter(|entry| entry.header().entry_type().is_file())
+            .map(|entry| entry.path().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        entries.sort();
+
+        (entries, hash)
+    }
+
+    // A project with ignore files at several levels
+    const PROJECT: &[(&str, &str)] = &[
+        (".gitignore", "target/\n*.log\n"),
+        (".git/HEAD", "ref: refs/heads/main\n"),
+        ("src/main.rs", "fn main() {}\n"),
+        ("src/debug.log", "oops\n"),
+        ("target/debug/app", "binary\n"),
+        ("web/.ignore", "node_modules\n"),
+        ("web/index.js", "main();\n"),
+        ("web/node_modules/left-pad/index.js", "pad();\n"),
+        ("docs/.gitignore", "*.html\n!index.html\n"),
+        ("docs/index.html", "<p>Hi</p>\n"),
+        ("docs/guide.html", "<p>Guide</p>\n"),
+    ];
+
+    #[test]
+    fn create_respect_gitignore() {
+        let (entries, _) = archive_project(PROJECT, &[], true);
+
+        assert_eq!(
+            entries,
+            vec![
+                "scratch/project/.gitignore",
+                "scratch/project/docs/.gitignore",
+                "scratch/project/docs/index.html",
+                "scratch/project/src/main.rs",
+                "scratch/project/web/.ignore",
+                "scratch/project/web/index.js",
+            ],
+        );
+    }
+
+    #[test]
+    fn create_respect_gitignore_disabled() {
+        let (entries, _) = archive_project(PROJECT, &[], false);
+
+        assert_eq!(entries.len(), PROJECT.len());
+    }
+
+    #[test]
+    fn create_respect_gitignore_with_excluded_input_paths() {
+        let (entries, _) = archive_project(PROJECT, &["project/docs"], true);
+
+        assert_eq!(
+            entries,
+            vec![
+                "scratch/project/.gitignore",
+                "scratch/project/src/main.rs",
+                "scratch/project/web/.ignore",
+                "scratch/project/web/index.js",
+            ],
+        );
+    }
+
+    #[test]
+    fn create_respect_gitignore_ignored_files_dont_affect_hash() {
+        let (_, hash) = archive_project(PROJECT, &[], true);
+        let mut project = PROJECT.to_vec();
+        project.push(("target/release/app", "another binary\n"));
+        project.push(("web/node_modules/react/index.js", "render();\n"));
+        let (_, other_hash) = archive_project(&project, &[], true);
+
+        assert_eq!(hash, other_hash);
+    }
+
+    #[test]
+    fn strip_root_rcr_dot_components() {
+        assert_eq!(
+            strip_root_rcr(UnixPath::new("/scratch/./foo/./bar.txt")).unwrap(),
+            UnixPathBuf::from("scratch/foo/bar.txt"),
+        );
+        assert_eq!(
+            strip_root_rcr(UnixPath::new("/")).unwrap(),
+            UnixPathBuf::new(),
+        );
+    }
+
+    #[test]
+    fn strip_root_rcr_relative() {
+        let Err(Failure::System(message, _)) = strip_root_rcr(UnixPath::new("scratch/foo")) else {
+            panic!("A relative path was accepted.");
+        };
+
+        assert!(message.contains("scratch/foo"));
+    }
+
+    #[test]
+    fn relativize_rsd_dot_components() {
+        assert_eq!(
+            relativize_rsd(Path::new("./src/foo.txt"), Path::new("src")).unwrap(),
+            PathBuf::from("foo.txt"),
+        );
+        assert_eq!(
+            relativize_rsd(Path::new("src/./foo.txt"), Path::new("./src/.")).unwrap(),
+            PathBuf::from("foo.txt"),
+        );
+        assert_eq!(
+            relativize_rsd(Path::new("./foo.txt"), Path::new(".")).unwrap(),
+            PathBuf::from("foo.txt"),
+        );
+    }
+
+    #[test]
+    fn relativize_rsd_outside() {
+        let source_dir = tempdir().unwrap();
+        let other_dir = tempdir().unwrap();
+        let path = other_dir.path().join("foo.txt");
+        write(&path, "foo").unwrap();
+
+        let Err(Failure::System(message, _)) = relativize_rsd(&path, source_dir.path()) else {
+            panic!("A path outside the source directory was relativized.");
+        };
+
+        assert!(message.contains(&*path.to_string_lossy()));
+        assert!(message.contains(&*source_dir.path().to_string_lossy()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn relativize_rsd_symlinked_source_dir() {
+        let dir = tempdir().unwrap();
+        let real_dir = dir.path().join("real");
+        let link_dir = dir.path().join("link");
+        create_dir_all(real_dir.join("foo")).unwrap();
+        write(real_dir.join("foo").join("bar.txt"), "bar").unwrap();
+        symlink(&real_dir, &link_dir).unwrap();
+
+        // The entry doesn't go through the symbolic link, but the source directory does.
+        assert_eq!(
+            relativize_rsd(&real_dir.join("foo").join("bar.txt"), &link_dir).unwrap(),
+            PathBuf::from("foo/bar.txt"),
+        );
+
+        // The other way around works too.
+        assert_eq!(
+            relativize_rsd(&link_dir.join("foo").join("bar.txt"), &real_dir).unwrap(),
+            PathBuf::from("foo/bar.txt"),
+        );
+
+        // A symbolic link in the source directory isn't followed, even if it points elsewhere.
+        symlink(dir.path(), real_dir.join("foo").join("escape")).unwrap();
+        assert_eq!(
+            relativize_rsd(&real_dir.join("foo").join("escape"), &link_dir).unwrap(),
+            PathBuf::from("foo/escape"),
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn create_symlinked_source_dir() {
+        let dir = tempdir().unwrap();
+        let real_dir = dir.path().join("real");
+        let link_dir = dir.path().join("link");
+        create_dir_all(real_dir.join("foo")).unwrap();
+        write(real_dir.join("foo").join("bar.txt"), "bar").unwrap();
+        symlink(&real_dir, &link_dir).unwrap();
+
+        let archive_from = |source_dir: &Path| {
+            create(
+                "Reading files\u{2026}",
+                Cursor::new(vec![]),
+                &[UnixPathBuf::from("./foo"), UnixPathBuf::from("*/bar.txt")],
+                &[],
+                false,
+                false,
+                source_dir,
+                UnixPath::new("/scratch"),
+                Modes::default(),
+                true,
+                &SizeLimits::new(u64::MAX, u64::MAX, false),
+                &Arc::new(AtomicBool::new(false)),
+            )
+            .unwrap()
+            .1
+        };
+
+        assert_eq!(archive_from(&link_dir), archive_from(&real_dir));
+    }
+
+    #[test]
+    fn create_dot_components() {
+        let files = &["foo/bar.txt", "foo/baz/qux.txt"];
+        let (entries, hash) = archive_files(
+            files,
+            &["./foo", "foo/./bar.txt"],
+            &["./foo/baz"],
+            false,
+            false,
+        )
+        .unwrap();
+        let (other_entries, other_hash) =
+            archive_files(files, &["foo"], &["foo/baz"], false, false).unwrap();
+
+        assert_eq!(entries, vec!["scratch/foo/bar.txt"]);
+        assert_eq!(entries, other_entries);
+        assert_eq!(hash, other_hash);
+    }
+
+    #[test]
+    fn describe_entries_limit() {
+        let source_dir = tempdir().unwrap();
+        create_dir(source_dir.path().join("foo")).unwrap();
+        write(source_dir.path().join("foo").join("bar.txt"), "bar").unwrap();
+        write(source_dir.path().join("baz.txt"), "baz").unwrap();
+
+        let (archive, _) = create(
+            "Reading files\u{2026}",
+            Cursor::new(vec![]),
+            &[UnixPathBuf::from("foo")],
+            &[],
+            false,
+            false,
+            source_dir.path(),
+            UnixPath::new("/scratch"),
+            Modes::default(),
+            false,
+            &SizeLimits::new(u64::MAX, u64::MAX, false),
+            &Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+        let archive = archive.into_inner();
+
+        assert_eq!(
+            describe_entries(Cursor::new(&archive), 10),
+            vec![
+                "`scratch` (directory)",
+                "`scratch/foo` (directory)",
+                "`scratch/foo/bar.txt` (file)",
+            ],
+        );
+        assert_eq!(describe_entries(Cursor::new(&archive), 1).len(), 1);
+    }
+
+    #[test]
+    fn describe_entries_invalid() {
+        assert!(describe_entries(Cursor::new(b"not an archive".to_vec()), 10).is_empty());
+    }
+}
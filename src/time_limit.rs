@@ -0,0 +1,219 @@
+use {
+    crate::{failure::Failure, format::CodeStr, units},
+    std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+        time::{Duration, Instant},
+    },
+};
+
+// The time limit, once it has run out. The failure which the interruption causes is then reported
+// as such, rather than as if the user had pressed CTRL+C.
+static EXPIRED: Mutex<Option<Duration>> = Mutex::new(None);
+
+// A budget for the whole run, given by `--time-limit`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TimeLimit {
+    limit: Duration,
+    deadline: Instant,
+}
+
+impl TimeLimit {
+    pub fn new(limit: Duration, start: Instant) -> Self {
+        Self {
+            limit,
+            deadline: start + limit,
+        }
+    }
+
+    // Determine how much of the budget is left.
+    pub fn remaining(&self, now: Instant) -> Duration {
+        self.deadline.saturating_duration_since(now)
+    }
+
+    // Start a thread which interrupts the run when the budget runs out, just as CTRL+C would. Then
+    // `stop` is called to stop the active containers, since the Docker CLI doesn't get a signal as
+    // it does for CTRL+C. If the run was already interrupted, nothing happens.
+    pub fn watch(self, interrupted: Arc<AtomicBool>, stop: impl FnOnce() + Send + 'static) {
+        thread::spawn(move || {
+            if wait(self.deadline, &interrupted, Instant::now, thread::sleep) {
+                // The `unwrap` will only fail if a panic already occurred.
+                *EXPIRED.lock().unwrap() = Some(self.limit);
+                interrupted.store(true, Ordering::SeqCst);
+
+                warn!(
+                    "The time limit of {} ran out. Stopping\u{2026}",
+                    units::format_duration(self.limit),
+                );
+                stop();
+            }
+        });
+    }
+}
+
+// Wait until the deadline, and return whether the run should be interrupted then (i.e., it wasn't
+// already). The clock and `sleep` are parameters so this can be tested without waiting.
+fn wait(
+    deadline: Instant,
+    interrupted: &Arc<AtomicBool>,
+    mut now: impl FnMut() -> Instant,
+    mut sleep: impl FnMut(Duration),
+) -> bool {
+    loop {
+        let current = now();
+        if current >= deadline {
+            return !interrupted.load(Ordering::SeqCst);
+        }
+
+        sleep(deadline - current);
+    }
+}
+
+// If the run was interrupted because the time limit ran out, say so.
+pub fn explain(failure: Failure) -> Failure {
+    // The `unwrap` will only fail if a panic already occurred.
+    explain_with(*EXPIRED.lock().unwrap(), failure)
+}
+
+fn explain_with(expired: Option<Duration>, failure: Failure) -> Failure {
+    match (failure, expired) {
+        (Failure::Interrupted, Some(limit)) => Failure::User(
+            format!(
+                "The time limit of {} given by {} was exceeded.",
+                units::format_duration(limit),
+                "--time-limit".code_str(),
+            ),
+            None,
+        ),
+        (failure, _) => failure,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{
+            failure::Failure,
+            time_limit::{explain_with, wait, TimeLimit},
+        },
+        std::{
+            cell::{Cell, RefCell},
+            sync::{
+                atomic::{AtomicBool, Ordering},
+                Arc,
+            },
+            time::{Duration, Instant},
+        },
+    };
+
+    #[test]
+    fn remaining() {
+        let start = Instant::now();
+        let time_limit = TimeLimit::new(Duration::from_secs(100), start);
+
+        assert_eq!(time_limit.remaining(start), Duration::from_secs(100));
+        assert_eq!(
+            time_limit.remaining(start + Duration::from_secs(45)),
+            Duration::from_secs(55),
+        );
+        assert_eq!(
+            time_limit.remaining(start + Duration::from_secs(150)),
+            Duration::ZERO,
+        );
+    }
+
+    #[test]
+    fn wait_until_deadline() {
+        let start = Instant::now();
+        let clock = Cell::new(start);
+        let sleeps = RefCell::new(vec![]);
+        let interrupted = Arc::new(AtomicBool::new(false));
+
+        // The first sleep ends early, as it's allowed to.
+        let expired = wait(
+            start + Duration::from_secs(100),
+            &interrupted,
+            || clock.get(),
+            |duration| {
+                sleeps.borrow_mut().push(duration);
+                clock.set(clock.get() + duration.min(Duration::from_secs(70)));
+            },
+        );
+
+        assert!(expired);
+        assert_eq!(
+            sleeps.into_inner(),
+            vec![Duration::from_secs(100), Duration::from_secs(30)],
+        );
+        assert_eq!(clock.get(), start + Duration::from_secs(100));
+    }
+
+    #[test]
+    fn wait_past_deadline() {
+        let start = Instant::now();
+        let interrupted = Arc::new(AtomicBool::new(false));
+
+        assert!(wait(
+            start,
+            &interrupted,
+            || start + Duration::from_secs(1),
+            |_| panic!("The watchdog slept after the deadline."),
+        ));
+    }
+
+    #[test]
+    fn wait_interrupted() {
+        let start = Instant::now();
+        let clock = Cell::new(start);
+        let interrupted = Arc::new(AtomicBool::new(false));
+
+        // The user presses CTRL+C before the time runs out.
+        let expired = wait(
+            start + Duration::from_secs(100),
+            &interrupted,
+            || clock.get(),
+            |duration| {
+                interrupted.store(true, Ordering::SeqCst);
+                clock.set(clock.get() + duration);
+            },
+        );
+
+        assert!(!expired);
+    }
+
+    #[test]
+    fn explain_expired() {
+        let Failure::User(message, None) =
+            explain_with(Some(Duration::from_secs(3305)), Failure::Interrupted)
+        else {
+            panic!("The interruption wasn't attributed to the time limit.");
+        };
+
+        assert_eq!(
+            message,
+            "The time limit of 55m 5s given by `--time-limit` was exceeded.",
+        );
+    }
+
+    #[test]
+    fn explain_not_expired() {
+        assert!(matches!(
+            explain_with(None, Failure::Interrupted),
+            Failure::Interrupted,
+        ));
+    }
+
+    #[test]
+    fn explain_other_failure() {
+        assert!(matches!(
+            explain_with(
+                Some(Duration::from_secs(100)),
+                Failure::System("Docker isn't running.".to_owned(), None),
+            ),
+            Failure::System(_, None),
+        ));
+    }
+}
@@ -1,13 +1,18 @@
 use {
-    crate::{failure::Failure, format, format::CodeStr},
+    crate::{cache::CryptoHash, dotenv, failure::Failure, format, format::CodeStr, tar, units},
     serde::{de::Error, Deserialize, Deserializer},
+    serde_yaml::{Mapping, Value},
     std::{
-        collections::{HashMap, HashSet},
+        collections::{BTreeMap, HashMap, HashSet},
         env,
-        fmt::{self, Display, Formatter},
-        path::PathBuf,
+        fmt::{self, Display, Formatter, Write as _},
+        fs,
+        net::IpAddr,
+        path::{Component, Path, PathBuf},
+        str::FromStr,
+        time::Duration,
     },
-    typed_path::{UnixPath, UnixPathBuf},
+    typed_path::{unix::UnixComponent, TryAsRef, UnixPath, UnixPathBuf},
 };
 
 // The default location for commands and files copied into the container
@@ -16,6 +21,9 @@ pub const DEFAULT_LOCATION: &str = "/scratch";
 // The default user for commands and files copied into the container
 pub const DEFAULT_USER: &str = "root";
 
+// The name of the task for a one-off command given on the command line
+pub const AD_HOC_TASK: &str = "run";
+
 // Deserializer for `UnixPathBuf`
 fn deserialize_unix_path_buf<'de, D>(deserializer: D) -> Result<UnixPathBuf, D::Error>
 where
@@ -64,15 +72,30 @@ where
 pub struct MappingPath {
     pub host_path: PathBuf,
     pub container_path: UnixPathBuf,
+
+    // Given by a `:ro` or `:rw` suffix. If `None`, the task's `mount_readonly` applies.
+    pub readonly: Option<bool>,
+}
+
+impl MappingPath {
+    // Determine whether the path is mounted read-only, given the task's `mount_readonly`.
+    pub fn is_readonly(&self, mount_readonly: bool) -> bool {
+        self.readonly.unwrap_or(mount_readonly)
+    }
 }
 
 impl Display for MappingPath {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(
             f,
-            "{}:{}",
+            "{}:{}{}",
             self.host_path.to_string_lossy(),
             self.container_path.to_string_lossy(),
+            match self.readonly {
+                Some(true) => ":ro",
+                Some(false) => ":rw",
+                None => "",
+            },
         )
     }
 }
@@ -90,19 +113,36 @@ impl serde::de::Visitor<'_> for MappingPathVisitor {
     where
         E: serde::de::Error,
     {
-        if let Some((host_path, container_path)) = v.split_once(':') {
+        v.parse().map_err(E::custom)
+    }
+}
+
+impl FromStr for MappingPath {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // The path may end with `:ro` or `:rw`, as with `docker run --volume`.
+        let (s, readonly) = if let Some(s) = s.strip_suffix(":ro") {
+            (s, Some(true))
+        } else if let Some(s) = s.strip_suffix(":rw") {
+            (s, Some(false))
+        } else {
+            (s, None)
+        };
+
+        if let Some((host_path, container_path)) = s.split_once(':') {
             Ok(MappingPath {
-                host_path: host_path
-                    .parse()
-                    .map_err(|_| E::custom("Illegal host path."))?,
+                host_path: host_path.parse().map_err(|_| "Illegal host path.")?,
                 container_path: container_path
                     .parse()
-                    .map_err(|_| E::custom("Illegal container path."))?,
+                    .map_err(|_| "Illegal container path.")?,
+                readonly,
             })
         } else {
             Ok(MappingPath {
-                host_path: v.parse().map_err(|_| E::custom("Illegal path."))?,
-                container_path: v.parse().map_err(|_| E::custom("Illegal path."))?,
+                host_path: s.parse().map_err(|_| "Illegal path.")?,
+                container_path: s.parse().map_err(|_| "Illegal path.")?,
+                readonly,
             })
         }
     }
@@ -117,37 +157,396 @@ impl<'de> Deserialize<'de> for MappingPath {
     }
 }
 
+// This struct represents a named Docker volume and where it's mounted in the container, written as
+// `name:container_path`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Volume {
+    // Must be a valid volume name [ref:volumes_valid]
+    pub name: String,
+
+    // Must not contain `,` [ref:volumes_valid]
+    pub container_path: UnixPathBuf,
+}
+
+impl Display for Volume {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.name, self.container_path.to_string_lossy())
+    }
+}
+
+impl FromStr for Volume {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, container_path) = s.split_once(':').ok_or_else(|| {
+            format!(
+                "{} should be of the form {}.",
+                s.code_str(),
+                "name:container_path".code_str(),
+            )
+        })?;
+
+        Ok(Self {
+            name: name.to_owned(),
+            container_path: container_path
+                .parse()
+                .map_err(|_| "Illegal container path.".to_owned())?,
+        })
+    }
+}
+
+struct VolumeVisitor;
+
+impl serde::de::Visitor<'_> for VolumeVisitor {
+    type Value = Volume;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str("a volume")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        v.parse().map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Volume {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(VolumeVisitor)
+    }
+}
+
+// The transport protocol of a published port
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+    Sctp,
+}
+
+impl Display for Protocol {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Tcp => write!(f, "tcp"),
+            Self::Udp => write!(f, "udp"),
+            Self::Sctp => write!(f, "sctp"),
+        }
+    }
+}
+
+// A single port or an inclusive range of ports, written as `start-end`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl PortRange {
+    // The number of ports in the range
+    pub fn len(self) -> u32 {
+        u32::from(self.end) - u32::from(self.start) + 1
+    }
+}
+
+impl Display for PortRange {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let port = |port: &str| match port.parse::<u16>() {
+            Ok(port) if port != 0 => Ok(port),
+            _ => Err(format!("{} is not a valid port.", port.code_str())),
+        };
+
+        let (start, end) = if let Some((start, end)) = s.split_once('-') {
+            (port(start)?, port(end)?)
+        } else {
+            (port(s)?, port(s)?)
+        };
+
+        if start > end {
+            return Err(format!("{} is not a valid port range.", s.code_str()));
+        }
+
+        Ok(Self { start, end })
+    }
+}
+
+// This struct represents a port published by a task, using the syntax of
+// `docker container create --publish`: `[[host_ip:][host_port]:]container_port[/protocol]`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Port {
+    pub host_ip: Option<IpAddr>,
+    pub host_ports: Option<PortRange>,
+    pub container_ports: PortRange,
+
+    // If `None`, Docker uses TCP.
+    pub protocol: Option<Protocol>,
+
+    // The port as the user wrote it. This goes into the cache key rather than the rendered port,
+    // which may differ (e.g., `08080:80` is rendered as `8080:80`), so existing cache keys don't
+    // change.
+    pub spec: String,
+}
+
+impl Display for Port {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.host_ip {
+            Some(IpAddr::V4(ip)) => write!(f, "{ip}:")?,
+            Some(IpAddr::V6(ip)) => write!(f, "[{ip}]:")?,
+            None => {}
+        }
+        if let Some(host_ports) = self.host_ports {
+            write!(f, "{host_ports}:")?;
+        } else if self.host_ip.is_some() {
+            write!(f, ":")?;
+        }
+        write!(f, "{}", self.container_ports)?;
+        if let Some(protocol) = self.protocol {
+            write!(f, "/{protocol}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Port {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("{} is not a valid port.", s.code_str());
+
+        // Split off the protocol.
+        let (address, protocol) = match s.rsplit_once('/') {
+            Some((address, "tcp")) => (address, Some(Protocol::Tcp)),
+            Some((address, "udp")) => (address, Some(Protocol::Udp)),
+            Some((address, "sctp")) => (address, Some(Protocol::Sctp)),
+            Some((_, protocol)) => {
+                return Err(format!(
+                    "{} has an unknown protocol {}.",
+                    s.code_str(),
+                    protocol.code_str(),
+                ))
+            }
+            None => (s, None),
+        };
+
+        // Split off the host IP, if there is one. IPv6 addresses are written in brackets.
+        let (host_ip, ports) = if let Some(rest) = address.strip_prefix('[') {
+            let (ip, ports) = rest.split_once("]:").ok_or_else(invalid)?;
+            (Some(ip), ports)
+        } else {
+            match address.matches(':').count() {
+                0 | 1 => (None, address),
+                2 => {
+                    let (ip, ports) = address.split_once(':').ok_or_else(invalid)?;
+                    (Some(ip), ports)
+                }
+                _ => return Err(invalid()),
+            }
+        };
+        let host_ip = host_ip
+            .map(|ip| ip.parse::<IpAddr>().map_err(|_| invalid()))
+            .transpose()?;
+
+        // Parse the host and container ports. The host ports can be omitted when there's a host IP.
+        let (host_ports, container_ports): (Option<PortRange>, PortRange) = match ports
+            .split_once(':')
+        {
+            Some(("", container_ports)) if host_ip.is_some() => (None, container_ports.parse()?),
+            Some((host_ports, container_ports)) => {
+                (Some(host_ports.parse()?), container_ports.parse()?)
+            }
+            None if host_ip.is_none() => (None, ports.parse()?),
+            None => return Err(invalid()),
+        };
+
+        // A range of container ports must be published to a range of host ports of the same size.
+        if let Some(host_ports) = host_ports {
+            if container_ports.len() > 1 && host_ports.len() != container_ports.len() {
+                return Err(format!(
+                    "{} publishes a range of container ports to a range of host ports of a \
+                     different size.",
+                    s.code_str(),
+                ));
+            }
+        }
+
+        Ok(Self {
+            host_ip,
+            host_ports,
+            container_ports,
+            protocol,
+            spec: s.to_owned(),
+        })
+    }
+}
+
+struct PortVisitor;
+
+impl serde::de::Visitor<'_> for PortVisitor {
+    type Value = Port;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str("a port")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        v.parse().map_err(E::custom)
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.visit_str(&v.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Port {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(PortVisitor)
+    }
+}
+
+// Where the contents of a secret come from. In the toastfile, it's a map with either a `file` (a
+// path on the host, relative to the toastfile unless it's absolute) or an `env` (the name of an
+// environment variable on the host).
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Secret {
+    File(PathBuf),
+    Env(String),
+}
+
+// This struct represents an edge in the dependency graph, optionally with an explanation of why the
+// dependency is needed. In the toastfile, it's either just the name of the task or a map with a
+// `task` and an optional `reason`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(from = "DependencyForm")]
+pub struct Dependency {
+    pub task: String,
+    pub reason: Option<String>,
+}
+
+// The forms a dependency can take in the toastfile
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DependencyForm {
+    Name(String),
+    Detailed(DetailedDependency),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DetailedDependency {
+    task: String,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+impl From<DependencyForm> for Dependency {
+    fn from(form: DependencyForm) -> Self {
+        match form {
+            DependencyForm::Name(task) => Self { task, reason: None },
+            DependencyForm::Detailed(DetailedDependency { task, reason }) => Self { task, reason },
+        }
+    }
+}
+
 // This struct represents a task.
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
 #[serde(deny_unknown_fields)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Task {
     pub description: Option<String>,
 
     // Must point to valid task names [ref:dependencies_exist] and the dependency DAG must not form
     // cycles [ref:tasks_dag]
     #[serde(default)]
-    pub dependencies: Vec<String>,
+    pub dependencies: Vec<Dependency>,
 
     // Must be disabled if any of the following conditions hold:
     // - `mount_paths` is nonempty [ref:mount_paths_nand_cache]
+    // - `volumes` is nonempty [ref:volumes_nand_cache]
     // - `ports` is nonempty [ref:ports_nand_cache]
     // - `extra_docker_arguments` is nonempty [ref:extra_docker_arguments_nand_cache]
+    // - `read_only_rootfs` is enabled [ref:read_only_rootfs_nand_cache]
     #[serde(default = "default_task_cache")]
     pub cache: bool,
 
+    // If enabled, the architecture of the Docker daemon isn't part of the cache key.
+    #[serde(default)]
+    pub arch_independent_cache: bool,
+
+    // Cached images older than this are ignored. Must be a duration such as `12h` or `7d`
+    // [ref:cache_ttl_valid].
+    #[serde(default)]
+    pub cache_ttl: Option<String>,
+
     // Keys must not contain `=` [ref:env_var_equals]
     #[serde(default)] // [tag:default_environment]
     pub environment: HashMap<String, Option<String>>,
 
-    // Must be relative [ref:input_paths_relative]
+    // A dotenv file with values for the environment variables, which take precedence over the one
+    // from the toastfile. Must be relative [ref:task_env_file_relative].
+    #[serde(default, deserialize_with = "deserialize_option_unix_path_buf")]
+    pub env_file: Option<UnixPathBuf>,
+
+    // Not part of the toastfile. When the toastfile is loaded, this is filled in with the values
+    // from the `env_file`s [ref:env_files_loaded].
+    #[serde(skip)]
+    pub env_file_values: HashMap<String, String>,
+
+    // Variables in the `environment` whose values are masked wherever Toast logs them
+    // [ref:secrets_masked]. They must be declared [ref:secret_variables_declared].
+    #[serde(default)]
+    pub secret_variables: Vec<String>,
+
+    // Must be relative, and glob patterns must be valid [ref:input_paths_relative]
     #[serde(default, deserialize_with = "deserialize_vec_unix_path_buf")]
     pub input_paths: Vec<UnixPathBuf>,
 
-    // Must be relative [ref:excluded_input_paths_relative]
+    // Must be relative, and glob patterns must be valid [ref:excluded_input_paths_relative]
     #[serde(default, deserialize_with = "deserialize_vec_unix_path_buf")]
     pub excluded_input_paths: Vec<UnixPathBuf>,
 
-    // Must be relative [ref:output_paths_relative]
+    // Whether glob patterns in `input_paths` may match nothing
+    #[serde(default)]
+    pub allow_empty_globs: bool,
+
+    // Whether to skip the input files which are ignored by `.gitignore` or `.ignore` files
+    #[serde(default)]
+    pub respect_gitignore: bool,
+
+    // Must be an octal string [ref:input_modes_valid]
+    #[serde(default)]
+    pub input_dir_mode: Option<String>,
+
+    // Must be an octal string [ref:input_modes_valid]
+    #[serde(default)]
+    pub input_file_mode: Option<String>,
+
+    // Must be absolute or relative without escaping the location [ref:output_paths_valid]
     #[serde(default, deserialize_with = "deserialize_vec_unix_path_buf")]
     pub output_paths: Vec<UnixPathBuf>,
 
@@ -155,6 +554,28 @@ pub struct Task {
     #[serde(default, deserialize_with = "deserialize_vec_unix_path_buf")]
     pub output_paths_on_failure: Vec<UnixPathBuf>,
 
+    // If enabled, `output_paths_on_failure` are also extracted (on a best-effort basis) when the
+    // user interrupts the task.
+    #[serde(default)]
+    pub extract_failure_outputs_on_interrupt: bool,
+
+    // Must be relative [ref:output_paths_always_relative]
+    #[serde(default, deserialize_with = "deserialize_vec_unix_path_buf")]
+    pub output_paths_always: Vec<UnixPathBuf>,
+
+    // If enabled, the host destinations of `output_paths` are removed before extraction.
+    #[serde(default)]
+    pub clean_output_paths: bool,
+
+    // Must only be enabled if `clean_output_paths` is enabled [ref:backup_output_paths_needs_clean]
+    #[serde(default)]
+    pub backup_output_paths: bool,
+
+    // If enabled, symbolic links in the output which point outside the extracted paths are replaced
+    // with a copy of their targets. Otherwise, a warning is logged for each such link.
+    #[serde(default)]
+    pub resolve_output_symlinks: bool,
+
     // Can be relative or absolute (absolute paths are allowed in order to support mounting the
     //   Docker socket, which is usually located at `/var/run/docker.sock`)
     // Must not contain `,` [ref:mount_paths_no_commas]
@@ -167,9 +588,42 @@ pub struct Task {
     #[serde(default = "default_task_mount_readonly")]
     pub mount_readonly: bool,
 
+    // Named Docker volumes, which persist between runs. Relative container paths are relative to
+    // the location, as with `mount_paths` [ref:mount_target]. The names must be valid
+    // [ref:volumes_valid], and this must be empty if `cache` is enabled [ref:volumes_nand_cache].
+    #[serde(default)] // [tag:default_volumes]
+    pub volumes: Vec<Volume>,
+
+    // If enabled, no warning is reported when another task mounts the same host path writably.
+    #[serde(default)]
+    pub allow_shared_mounts: bool,
+
+    // Files mounted read-only at `/run/secrets/<name>` when the task runs [ref:secrets_mounted].
+    // Unlike `mount_paths`, these are allowed when `cache` is enabled [ref:secrets_nand_commit].
+    // The names must be valid [ref:secret_names_valid].
+    #[serde(default)]
+    pub secrets: HashMap<String, Secret>,
+
+    // If enabled, the container's root filesystem is read-only, except for `location` (which is a
+    // fresh volume) and `mount_paths`. Must be disabled if `cache` is enabled
+    // [ref:read_only_rootfs_nand_cache].
+    #[serde(default)]
+    pub read_only_rootfs: bool,
+
     // Must be empty if `cache` is enabled [ref:ports_nand_cache]
     #[serde(default)] // [tag:default_ports]
-    pub ports: Vec<String>,
+    pub ports: Vec<Port>,
+
+    // Must use the syntax of `docker container create --gpus` [ref:gpus_valid]
+    #[serde(default)]
+    pub gpus: Option<String>,
+
+    // If `None`, the corresponding top-level value in the toastfile should be used. There is a
+    // helper function [ref:image_helper] which implements that logic. A task with its own image
+    // doesn't build on the previous task [ref:task_image_fresh_context]. The image must be valid
+    // [ref:task_image_valid].
+    #[serde(default)]
+    pub image: Option<String>,
 
     // If `None`, the corresponding top-level value in the toastfile should be used. There is a
     // helper function [ref:location_helper] which implements that logic. This path must be absolute
@@ -186,14 +640,58 @@ pub struct Task {
     #[serde(default)]
     pub command: String,
 
+    // Must be relative, and `command` must be empty if this is present [ref:command_file_valid].
+    // When the toastfile is loaded, the contents of this file become the `command`
+    // [ref:command_files_loaded].
+    #[serde(default, deserialize_with = "deserialize_option_unix_path_buf")]
+    pub command_file: Option<UnixPathBuf>,
+
     // If `None`, the corresponding top-level value in the toastfile should be used. There is a
     // helper function [ref:command_helper] which implements that logic.
     #[serde(default)]
     pub command_prefix: Option<String>,
 
+    // If `None`, the `docker_cli` from the configuration should be used. There is a helper function
+    // [ref:docker_cli_helper] which implements that logic. Must not be empty
+    // [ref:task_docker_cli_nonempty]. A task which doesn't have its own `image` must use the same CLI
+    // as the task before it [ref:docker_clis_consistent].
+    #[serde(default)]
+    pub docker_cli: Option<String>,
+
+    // If `None`, the `docker_repo` from the configuration should be used. There is a helper function
+    // [ref:docker_repo_helper] which implements that logic. Must not be empty
+    // [ref:task_docker_repo_nonempty].
+    #[serde(default)]
+    pub docker_repo: Option<String>,
+
+    // Commands to run via `docker exec` once the container has started, e.g., to set up a service
+    // which runs in the foreground as the main command
+    #[serde(default)]
+    pub exec_after_start: Vec<String>,
+
+    // Whether to copy the input files into the container by piping them to `tar` in the running
+    // container rather than with `docker container cp`. Must be disabled if `exec_after_start` is
+    // nonempty [ref:copy_via_exec_nand_exec_after_start].
+    #[serde(default)]
+    pub copy_via_exec: bool,
+
     // Must be empty if `cache` is enabled [ref:extra_docker_arguments_nand_cache]
     #[serde(default)]
     pub extra_docker_arguments: Vec<String>,
+
+    // If `None`, the `--max-log-lines` command-line option should be used.
+    #[serde(default)]
+    pub max_log_lines: Option<usize>,
+
+    // In seconds. If `None`, the corresponding top-level value in the toastfile should be used. There
+    // is a helper function [ref:timeout_helper] which implements that logic. Must be positive
+    // [ref:task_timeout_positive].
+    #[serde(default)]
+    pub timeout: Option<u64>,
+
+    // How many more times to run the task if it fails
+    #[serde(default)]
+    pub retries: u32,
 }
 
 fn default_task_cache() -> bool {
@@ -224,8 +722,34 @@ pub struct Toastfile {
     #[serde(default)]
     pub command_prefix: String,
 
+    #[serde(default = "default_shell_applies_prefix")]
+    pub shell_applies_prefix: bool,
+
+    #[serde(default)]
+    pub resolve_image_digest: bool,
+
+    // In seconds. Must be positive [ref:toastfile_timeout_positive].
+    #[serde(default)]
+    pub timeout: Option<u64>,
+
+    // Shared by every task, which can override any of them [ref:shared_environment]. Keys must not
+    // contain `=` [ref:toastfile_env_var_equals].
+    #[serde(default)]
+    pub environment: HashMap<String, Option<String>>,
+
+    // A dotenv file with values for the environment variables of every task. Must be relative
+    // [ref:toastfile_env_file_relative]. When the toastfile is loaded, the values are given to each
+    // task [ref:env_files_loaded].
+    #[serde(default, deserialize_with = "deserialize_option_unix_path_buf")]
+    pub env_file: Option<UnixPathBuf>,
+
+    // Variables which are secret in every task which declares them [ref:shared_environment]. Each
+    // must be declared by the toastfile or a task [ref:secret_variables_declared].
+    #[serde(default)]
+    pub secret_variables: Vec<String>,
+
     #[serde(default)]
-    pub tasks: HashMap<String, Task>,
+    pub tasks: BTreeMap<String, Task>,
 }
 
 fn default_location() -> UnixPathBuf {
@@ -236,492 +760,3576 @@ fn default_user() -> String {
     DEFAULT_USER.to_owned()
 }
 
-// Parse config data.
-pub fn parse(toastfile_data: &str) -> Result<Toastfile, Failure> {
-    // Deserialize the data.
-    let toastfile: Toastfile =
-        serde_yaml::from_str(toastfile_data).map_err(|e| Failure::User(format!("{e}"), None))?;
-
-    // Make sure the dependencies are valid.
-    check_dependencies(&toastfile)?;
+fn default_shell_applies_prefix() -> bool {
+    true
+}
 
-    // Check that `location` is absolute [tag:toastfile_location_absolute].
-    if !toastfile.location.is_absolute() {
-        return Err(Failure::User(
-            format!(
-                "Toastfile has a relative {}: {}.",
-                "location".code_str(),
-                toastfile.location.to_string_lossy().code_str(),
-            ),
-            None,
-        ));
-    }
+// The directories which mark the root of a version-controlled project
+const VCS_DIRS: &[&str] = &[".git", ".hg", ".svn"];
 
-    // Make sure each task is valid.
-    for (name, task) in &toastfile.tasks {
-        check_task(name, task)?;
-    }
+// Determine which filesystem a directory is on, if possible.
+#[cfg(unix)]
+fn device(dir: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
 
-    // Return the toastfile.
-    Ok(toastfile)
+    fs::metadata(dir).ok().map(|metadata| metadata.dev())
 }
 
-// Fetch the variables for a task from the environment.
-pub fn environment(task: &Task) -> Result<HashMap<String, String>, Vec<&str>> {
-    // The result will be a map from variable name to value.
-    let mut result = HashMap::new();
-
-    // We accumulate a list of errors to be shown to the user when there is a problem.
-    let mut violations = vec![];
-
-    // Fetch each environment variable.
-    for (arg, default) in &task.environment {
-        // Read the variable from the environment.
-        let maybe_var = env::var(arg);
+#[cfg(not(unix))]
+fn device(_dir: &Path) -> Option<u64> {
+    None
+}
 
-        // If a default value was provided, use that if the variable is missing from the
-        // environment. If there was no default, the variable must be in the environment or else
-        // we'll report a violation.
-        if let Some(default) = default {
-            result.insert(arg.clone(), maybe_var.unwrap_or_else(|_| default.clone()));
-        } else if let Ok(var) = maybe_var {
-            result.insert(arg.clone(), var);
-        } else {
-            violations.push(arg.as_ref());
+// Find the toastfile by searching `start_dir` and its ancestors. The search stops after the root of
+// the repository containing `start_dir` (if any), after `search_root` (if given), and before
+// crossing into a different filesystem. `start_dir` and `search_root` must be absolute.
+pub fn find(
+    start_dir: &Path,
+    search_root: Option<&Path>,
+    file_name: &str,
+) -> Result<PathBuf, Failure> {
+    if let Some(search_root) = search_root {
+        if !start_dir.starts_with(search_root) {
+            return Err(Failure::User(
+                format!(
+                    "The search root {} does not contain the working directory {}.",
+                    search_root.to_string_lossy().code_str(),
+                    start_dir.to_string_lossy().code_str(),
+                ),
+                None,
+            ));
         }
     }
 
-    // If there were no violations, return the map. Otherwise, report the violations.
-    if violations.is_empty() {
-        Ok(result)
-    } else {
-        Err(violations)
-    }
-}
+    let start_device = device(start_dir);
+    let mut candidate_dir = start_dir.to_owned();
+    loop {
+        let candidate_path = candidate_dir.join(file_name);
+        if let Ok(metadata) = fs::metadata(&candidate_path) {
+            if metadata.file_type().is_file() {
+                return Ok(candidate_path);
+            }
+        }
 
-// [tag:location_helper] Fetch the location for a task, defaulting to the top-level location if
-// needed.
-pub fn location(toastfile: &Toastfile, task: &Task) -> UnixPathBuf {
-    task.location
-        .clone()
-        .unwrap_or_else(|| toastfile.location.clone())
+        // Decide whether to keep searching.
+        let boundary = if search_root == Some(candidate_dir.as_path()) {
+            Some("the search root")
+        } else if VCS_DIRS
+            .iter()
+            .any(|vcs_dir| candidate_dir.join(vcs_dir).is_dir())
+        {
+            Some("the root of the repository")
+        } else {
+            match candidate_dir.parent() {
+                None => Some("the root of the filesystem"),
+                Some(parent) if device(parent) != start_device => Some("a mount point"),
+                Some(_) => None,
+            }
+        };
+
+        if let Some(boundary) = boundary {
+            return Err(Failure::User(
+                format!(
+                    "Unable to locate file {}. The search stopped at {}, which is {}.",
+                    file_name.code_str(),
+                    candidate_dir.to_string_lossy().code_str(),
+                    boundary,
+                ),
+                None,
+            ));
+        }
+
+        candidate_dir.pop();
+    }
 }
 
-// [tag:user_helper] Fetch the user for a task, defaulting to the top-level location if needed.
-pub fn user(toastfile: &Toastfile, task: &Task) -> String {
-    task.user.clone().unwrap_or_else(|| toastfile.user.clone())
+// The key which merges the entries of other mappings into a mapping (e.g., `<<: *defaults`)
+const MERGE_KEY: &str = "<<";
+
+// Determine whether a YAML value has any merge keys.
+fn has_merge_keys(value: &Value) -> bool {
+    match value {
+        Value::Mapping(mapping) => mapping
+            .iter()
+            .any(|(key, value)| key.as_str() == Some(MERGE_KEY) || has_merge_keys(value)),
+        Value::Sequence(sequence) => sequence.iter().any(has_merge_keys),
+        _ => false,
+    }
 }
 
-// [tag:command_helper] Fetch the command for a task, including the prefix, using the top-level
-// prefix if needed.
-pub fn command(toastfile: &Toastfile, task: &Task) -> String {
-    let mut command = String::new();
+// Describe where a value is in a YAML document, for error messages.
+fn yaml_path(parent: &str, key: &Value) -> String {
+    let key = match key {
+        Value::String(key) => key.clone(),
+        key => serde_yaml::to_string(key).map_or_else(
+            |_| "?".to_owned(),
+            |key| key.trim_start_matches("---").trim().to_owned(),
+        ),
+    };
 
-    if let Some(command_prefix) = &task.command_prefix {
-        command.push_str(command_prefix);
+    if parent.is_empty() {
+        key
     } else {
-        command.push_str(&toastfile.command_prefix);
+        format!("{parent}.{key}")
     }
+}
 
-    if !command.is_empty() && !task.command.is_empty() {
-        command.push('\n');
+// Describe the kind of a YAML value, for error messages.
+fn yaml_kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Sequence(_) => "a list",
+        Value::Mapping(_) => "a mapping",
     }
+}
 
-    command.push_str(&task.command);
+// Resolve the merge keys in a YAML value according to the YAML 1.1 merge key specification. The
+// entries which are written out in a mapping take precedence over the merged ones, and when a list of
+// mappings is merged, the earlier mappings take precedence over the later ones. Merged mappings can
+// have merge keys of their own. The `path` is where the value is in the document.
+fn resolve_merge_keys(value: Value, path: &str) -> Result<Value, String> {
+    match value {
+        Value::Mapping(mapping) => {
+            let mut merged = Mapping::new();
+            let mut explicit = Mapping::new();
+            for (key, value) in mapping {
+                let key_path = yaml_path(path, &key);
+                let value = resolve_merge_keys(value, &key_path)?;
+                if key.as_str() != Some(MERGE_KEY) {
+                    explicit.insert(key, value);
+                    continue;
+                }
 
-    command
+                let sources = match value {
+                    Value::Mapping(source) => vec![source],
+                    Value::Sequence(sources) => sources
+                        .into_iter()
+                        .map(|source| match source {
+                            Value::Mapping(source) => Ok(source),
+                            source => Err(format!(
+                                "The merge key at {} refers to a list containing {}, but it can \
+                                 only contain mappings (e.g., anchored with {}).",
+                                key_path.code_str(),
+                                yaml_kind(&source),
+                                "&defaults".code_str(),
+                            )),
+                        })
+                        .collect::<Result<Vec<_>, _>>()?,
+                    value => {
+                        return Err(format!(
+                            "The merge key at {} refers to {}, but it must refer to a mapping or a \
+                             list of mappings (e.g., {}).",
+                            key_path.code_str(),
+                            yaml_kind(&value),
+                            "<<: *defaults".code_str(),
+                        ));
+                    }
+                };
+
+                for source in sources {
+                    for (key, value) in source {
+                        if !merged.contains_key(&key) {
+                            merged.insert(key, value);
+                        }
+                    }
+                }
+            }
+
+            for (key, value) in explicit {
+                merged.insert(key, value);
+            }
+
+            Ok(Value::Mapping(merged))
+        }
+        Value::Sequence(sequence) => sequence
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| resolve_merge_keys(value, &format!("{path}[{i}]")))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Sequence),
+        value => Ok(value),
+    }
 }
 
-// Check that all dependencies exist and form a DAG (no cycles).
-#[allow(clippy::too_many_lines)]
-fn check_dependencies<'a>(toastfile: &'a Toastfile) -> Result<(), Failure> {
-    // Check the default task [tag:valid_default].
-    let valid_default = toastfile
-        .default
-        .as_ref()
-        .map_or(true, |default| toastfile.tasks.contains_key(default));
+// Parse config data.
+pub fn parse(toastfile_data: &str) -> Result<Toastfile, Failure> {
+    parse_and_check(toastfile_data, true)
+}
 
-    // Map from task to vector of invalid dependencies.
-    let mut violations: HashMap<String, Vec<String>> = HashMap::new();
+// Parse config data which is known to be valid, e.g., because identical data passed validation
+// before. Checking the dependencies and tasks is the slow part of parsing a large toastfile, so
+// that's skipped.
+pub fn parse_validated(toastfile_data: &str) -> Result<Toastfile, Failure> {
+    parse_and_check(toastfile_data, false)
+}
 
-    // Scan for invalid dependencies [tag:task_valid].
-    for task in toastfile.tasks.keys() {
-        // [ref:task_valid]
-        for dependency in &toastfile.tasks[task].dependencies {
-            if !toastfile.tasks.contains_key(dependency) {
-                // [tag:dependencies_exist]
-                violations
-                    .entry(task.clone())
-                    .or_default()
-                    .push(dependency.clone());
-            }
+#[allow(clippy::too_many_lines)]
+fn parse_and_check(toastfile_data: &str, check_tasks: bool) -> Result<Toastfile, Failure> {
+    // Deserialize the data. If there are merge keys, they're resolved first. Otherwise, the value
+    // is deserialized without parsing the data again (which is slow for a large toastfile), unless
+    // that fails. Then the data is deserialized directly, so that errors refer to the line and
+    // column where they occur.
+    let value: Value =
+        serde_yaml::from_str(toastfile_data).map_err(|e| Failure::User(format!("{e}"), None))?;
+    let mut toastfile: Toastfile = if has_merge_keys(&value) {
+        serde_yaml::from_value(
+            resolve_merge_keys(value, "").map_err(|message| Failure::User(message, None))?,
+        )
+    } else {
+        serde_yaml::from_value(value).or_else(|_| serde_yaml::from_str(toastfile_data))
+    }
+    .map_err(|e| Failure::User(format!("{e}"), None))?;
+
+    // Surrounding whitespace in the image is easy to add by accident in YAML, so we remove it (from
+    // the images of the tasks too). Then make sure what's left is something Docker will accept
+    // [tag:image_valid].
+    toastfile.image = String::from(toastfile.image.trim());
+    for task in toastfile.tasks.values_mut() {
+        if let Some(image) = &mut task.image {
+            *image = String::from(image.trim());
         }
     }
 
-    // If there were any invalid dependencies, report them.
-    if !violations.is_empty() {
-        let violations_series = format::series(
-            violations
-                .iter()
-                .map(|(task, dependencies)| {
-                    format!(
-                        "{} ({})",
-                        task.code_str(),
-                        format::series(
-                            dependencies
-                                .iter()
-                                .map(|task| format!("{}", task.code_str()))
-                                .collect::<Vec<_>>()
-                                .as_ref(),
-                        ),
-                    )
-                })
-                .collect::<Vec<_>>()
-                .as_ref(),
-        );
+    // We accumulate all the problems with the toastfile, so the user can fix them at once.
+    let mut problems = vec![];
+    if let Err(e) = check_image(&toastfile.image) {
+        problems.push(e.to_string());
+    }
 
-        if valid_default {
-            return Err(Failure::User(
-                format!("The following tasks have invalid dependencies: {violations_series}."),
-                None,
-            ));
+    // Make sure the dependencies are valid.
+    if check_tasks {
+        if let Err(e) = check_dependencies(&toastfile) {
+            problems.push(e.to_string());
         }
+    }
 
-        return Err(Failure::User(
-            format!(
-                "The default task {} does not exist, and the following tasks have invalid \
-                 dependencies: {}.",
-                toastfile.default.as_ref().unwrap().code_str(), // [ref:valid_default]
-                violations_series,
-            ),
-            None,
-        ));
-    } else if !valid_default {
-        return Err(Failure::User(
-            format!(
-                "The default task {} does not exist.",
-                toastfile.default.as_ref().unwrap().code_str(), // [ref:valid_default]
-            ),
-            None,
+    // Check that `location` is absolute [tag:toastfile_location_absolute].
+    if !toastfile.location.is_absolute() {
+        problems.push(format!(
+            "Toastfile has a relative {}: {}.",
+            "location".code_str(),
+            toastfile.location.to_string_lossy().code_str(),
         ));
     }
 
-    // Check that the dependencies aren't cyclic [tag:tasks_dag].
-    let mut visited: HashSet<&'a str> = HashSet::new();
-    for task in toastfile.tasks.keys() {
-        let mut frontier: Vec<(&'a str, usize)> = vec![(task, 0)];
-        let mut ancestors_set: HashSet<&'a str> = HashSet::new();
-        let mut ancestors_stack: Vec<&'a str> = vec![];
+    // Check that `env_file` is relative [tag:toastfile_env_file_relative].
+    if let Some(path) = &toastfile.env_file {
+        if !path.is_relative() {
+            problems.push(format!(
+                "Toastfile has an absolute {}: {}.",
+                "env_file".code_str(),
+                path.to_string_lossy().code_str(),
+            ));
+        }
+    }
 
-        // Keep going as long as there are more nodes to process [tag:toastfile_frontier_nonempty].
-        while let Some((task, task_depth)) = frontier.pop() {
-            // Take the top task from the frontier. This is safe due to
-            // [ref:toastfile_frontier_nonempty].
+    // Check that the default timeout is positive [tag:toastfile_timeout_positive].
+    if toastfile.timeout == Some(0) {
+        problems.push(format!(
+            "Toastfile has a {} of zero. Use a positive number of seconds, or remove it to let \
+                tasks run as long as they need.",
+            "timeout".code_str(),
+        ));
+    }
 
-            // Update the ancestors set and stack.
-            for _ in 0..ancestors_stack.len() - task_depth {
-                // The `unwrap` is safe because `ancestors_stack.len()` is positive in every
-                // iteration of this loop.
-                let task_to_remove = ancestors_stack.pop().unwrap();
-                ancestors_set.remove(task_to_remove);
-            }
+    // Check that the names of the shared environment variables don't have `=` in them
+    // [tag:toastfile_env_var_equals].
+    let mut variables = toastfile.environment.keys().collect::<Vec<_>>();
+    variables.sort();
+    for variable in variables {
+        if variable.contains('=') {
+            problems.push(format!(
+                "Environment variable {} of the toastfile contains {}.",
+                variable.code_str(),
+                "=".code_str(),
+            ));
+        }
+    }
 
-            // If this task is an ancestor of itself, we have a cycle. Return an error.
-            if ancestors_set.contains(task) {
-                let mut cycle_iter = ancestors_stack.iter();
-                cycle_iter.find(|&&x| x == task);
-                let mut cycle = cycle_iter.collect::<Vec<_>>();
-                cycle.push(&task); // [tag:cycle_nonempty]
-                let error_message = if cycle.len() == 1 {
-                    format!("{} depends on itself.", cycle[0].code_str())
-                } else if cycle.len() == 2 {
-                    format!(
-                        "{} and {} depend on each other.",
-                        cycle[0].code_str(),
-                        cycle[1].code_str(),
-                    )
-                } else {
-                    let mut cycle_dependencies = cycle[1..].to_owned();
-                    cycle_dependencies.push(cycle[0]); // [ref:cycle_nonempty]
-                    format!(
-                        "{}.",
-                        format::series(
-                            cycle
-                                .iter()
-                                .zip(cycle_dependencies)
-                                .map(|(x, y)| {
-                                    format!("{} depends on {}", x.code_str(), y.code_str())
-                                })
-                                .collect::<Vec<_>>()
-                                .as_ref(),
-                        ),
-                    )
-                };
-                return Err(Failure::User(
-                    format!("The dependencies are cyclic. {error_message}"),
-                    None,
-                ));
+    // Make sure each task is valid. The tasks are sorted so their problems are grouped together in
+    // a predictable order.
+    let mut invalid_tasks = vec![];
+    if check_tasks {
+        let mut task_names = toastfile.tasks.keys().collect::<Vec<_>>();
+        task_names.sort();
+        for name in task_names {
+            let task_problems = check_task(name, &toastfile.tasks[name]);
+            if !task_problems.is_empty() {
+                invalid_tasks.push(format!("{}", name.code_str()));
+                problems.extend(task_problems);
             }
+        }
+    }
 
-            // If we've never seen this task before, add its dependencies to the frontier.
-            if !visited.contains(task) {
-                visited.insert(task);
+    // Give every task the shared environment variables. This happens after the tasks are checked,
+    // so a problem with a shared variable is only reported once.
+    let environment = toastfile.environment.clone();
+    let secret_variables = toastfile.secret_variables.clone();
+    for task in toastfile.tasks.values_mut() {
+        apply_shared_environment(&environment, &secret_variables, task);
+    }
 
-                ancestors_set.insert(task);
-                ancestors_stack.push(task);
+    // Check that the secret variables are declared. This happens after the shared variables are
+    // added to the tasks, since a task can mark one of them as secret.
+    if check_tasks {
+        problems.extend(check_secret_variables(&toastfile, &mut invalid_tasks));
+    }
 
-                for dependency in &toastfile.tasks[task].dependencies {
-                    frontier.push((dependency, task_depth + 1));
-                }
-            }
-        }
+    // Report the problems, if there are any.
+    match problems.len() {
+        0 => Ok(toastfile),
+        1 => Err(Failure::User(problems.remove(0), None)),
+        _ => Err(Failure::User(
+            format!(
+                "The toastfile has {}{}:\n{}",
+                format::number(problems.len(), "problem"),
+                match invalid_tasks.len() {
+                    0 => String::new(),
+                    1 => format!(", including some in task {}", invalid_tasks[0]),
+                    _ => format!(
+                        ", including some in tasks {}",
+                        format::series(&invalid_tasks),
+                    ),
+                },
+                problems
+                    .iter()
+                    .map(|problem| format!("- {problem}"))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+            None,
+        )),
     }
+}
 
-    // No violations
-    Ok(())
+// Compute a hash of the contents of a toastfile. This can be used to detect whether the toastfile
+// has changed since it was parsed.
+pub fn content_hash(toastfile_data: &str) -> String {
+    toastfile_data.crypto_hash()
 }
 
-// Check that a task is valid.
-#[allow(clippy::too_many_lines)]
-fn check_task(name: &str, task: &Task) -> Result<(), Failure> {
-    // Check that environment variable names don't have `=` in them [tag:env_var_equals].
-    for variable in task.environment.keys() {
-        if variable.contains('=') {
-            return Err(Failure::User(
-                format!(
-                    "Environment variable {} of task {} contains {}.",
-                    variable.code_str(),
-                    name.code_str(),
-                    "=".code_str(),
-                ),
-                None,
-            ));
-        }
+// Verify that the hash of a toastfile matches the hash from when it was originally parsed.
+pub fn check_content_hash(expected_hash: &str, actual_hash: &str) -> Result<(), Failure> {
+    if expected_hash == actual_hash {
+        Ok(())
+    } else {
+        Err(Failure::User(
+            format!(
+                "The toastfile changed since the referenced run (expected hash {}, found {}).",
+                expected_hash.code_str(),
+                actual_hash.code_str(),
+            ),
+            None,
+        ))
     }
+}
 
-    // Check that `input_paths` are relative [tag:input_paths_relative].
-    for path in &task.input_paths {
-        if !path.is_relative() {
-            return Err(Failure::User(
-                format!(
-                    "Task {} has an absolute {}: {}.",
-                    name.code_str(),
-                    "input_path".code_str(),
-                    path.to_string_lossy().code_str(),
-                ),
-                None,
+// A problem with the environment variables of a task
+#[derive(Debug, Eq, PartialEq)]
+pub enum EnvironmentError<'a> {
+    // Variables without defaults which are missing from the environment
+    Missing(Vec<&'a str>),
+
+    // A default value which couldn't be interpolated, with an explanation
+    Interpolation(String),
+}
+
+// [tag:secret_variables_declared] Check that each secret variable is declared in an `environment`,
+// and return a description of each problem. The names of the tasks with problems are added to
+// `invalid_tasks`.
+fn check_secret_variables(toastfile: &Toastfile, invalid_tasks: &mut Vec<String>) -> Vec<String> {
+    let mut problems = vec![];
+
+    for variable in &toastfile.secret_variables {
+        if !toastfile.environment.contains_key(variable)
+            && !toastfile
+                .tasks
+                .values()
+                .any(|task| task.environment.contains_key(variable))
+        {
+            problems.push(format!(
+                "The toastfile has a secret variable {} which no {} declares.",
+                variable.code_str(),
+                "environment".code_str(),
             ));
         }
     }
 
-    // Check that `excluded_input_paths` are relative [tag:excluded_input_paths_relative].
-    for path in &task.excluded_input_paths {
-        if !path.is_relative() {
-            return Err(Failure::User(
-                format!(
-                    "Task {} has an absolute {}: {}.",
+    let mut task_names = toastfile.tasks.keys().collect::<Vec<_>>();
+    task_names.sort();
+    for name in task_names {
+        let task = &toastfile.tasks[name];
+        for variable in &task.secret_variables {
+            if !task.environment.contains_key(variable) {
+                problems.push(format!(
+                    "Task {} has a secret variable {} which isn't in its {}.",
                     name.code_str(),
-                    "excluded_input_path".code_str(),
-                    path.to_string_lossy().code_str(),
-                ),
-                None,
-            ));
+                    variable.code_str(),
+                    "environment".code_str(),
+                ));
+                let name = format!("{}", name.code_str());
+                if !invalid_tasks.contains(&name) {
+                    invalid_tasks.push(name);
+                }
+            }
         }
     }
 
-    // Check that `output_paths` are relative [tag:output_paths_relative].
-    for path in &task.output_paths {
-        if !path.is_relative() {
-            return Err(Failure::User(
-                format!(
-                    "Task {} has an absolute path in {}: {}.",
-                    name.code_str(),
-                    "output_paths".code_str(),
-                    path.to_string_lossy().code_str(),
-                ),
-                None,
-            ));
-        }
+    problems
+}
+
+// [tag:shared_environment] Add the environment variables shared by every task in a toastfile to a
+// task. The task's own variables (and their defaults) take precedence. Afterward, the task is just
+// as if it had declared the shared variables itself, so they affect its cache key the same way. A
+// shared secret variable is secret in the task if the task has it, even if the task declares it
+// itself.
+pub fn apply_shared_environment(
+    environment: &HashMap<String, Option<String>>,
+    secret_variables: &[String],
+    task: &mut Task,
+) {
+    for (variable, default) in environment {
+        task.environment
+            .entry(variable.clone())
+            .or_insert_with(|| default.clone());
     }
 
-    // Check that `output_paths_on_failure` are relative [tag:output_paths_on_failure_relative].
-    for path in &task.output_paths_on_failure {
-        if !path.is_relative() {
-            return Err(Failure::User(
-                format!(
-                    "Task {} has an absolute path in {}: {}.",
-                    name.code_str(),
-                    "output_paths_on_failure".code_str(),
-                    path.to_string_lossy().code_str(),
-                ),
-                None,
-            ));
+    for variable in secret_variables {
+        if task.environment.contains_key(variable) && !task.secret_variables.contains(variable) {
+            task.secret_variables.push(variable.clone());
         }
     }
+}
 
-    // Check `mount_paths`.
-    for path in &task.mount_paths {
-        // Check that the path doesn't contain any commas [tag:mount_paths_no_commas].
-        if path.container_path.to_string_lossy().contains(',')
-            || path.host_path.to_string_lossy().contains(',')
-        {
-            return Err(Failure::User(
-                format!(
-                    "Mount path {} of task {} has a {}.",
-                    format!("{path}").code_str(),
-                    name.code_str(),
-                    ",".code_str(),
-                ),
-                None,
-            ));
+// Fetch the variables for a task from the environment. Default values may refer to other variables
+// with `${NAME}`, which is resolved against the task's other variables and then the environment.
+// [tag:environment_overrides] The `overrides` (from `--env`) take precedence over the environment,
+// and the values from the task's `env_file`s come after the environment but before the defaults.
+// They're all resolved the same way, so they affect cache keys just like the environment does.
+pub fn environment<'a>(
+    task: &'a Task,
+    overrides: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, EnvironmentError<'a>> {
+    // The result will be a map from variable name to value. Default values are interpolated after
+    // everything else has been resolved.
+    let mut result = HashMap::new();
+    let mut defaults = HashMap::new();
+
+    // We accumulate a list of errors to be shown to the user when there is a problem.
+    let mut violations = vec![];
+
+    // Fetch each environment variable.
+    for (arg, default) in &task.environment {
+        // Read the variable from the overrides, the environment, or the `env_file`s.
+        let maybe_var = overrides
+            .get(arg)
+            .cloned()
+            .or_else(|| env::var(arg).ok())
+            .or_else(|| task.env_file_values.get(arg).cloned());
+
+        // If a default value was provided, use that if the variable is missing from the
+        // environment. If there was no default, the variable must be in the environment or else
+        // we'll report a violation.
+        if let Some(var) = maybe_var {
+            result.insert(arg.clone(), var);
+        } else if let Some(default) = default {
+            defaults.insert(arg.as_str(), default.as_str());
+        } else {
+            violations.push(arg.as_ref());
         }
     }
 
-    // Check that `location` is absolute [tag:task_location_absolute].
-    if let Some(location) = &task.location {
-        if !location.is_absolute() {
-            return Err(Failure::User(
-                format!(
-                    "Task {} has a relative {}: {}.",
-                    name.code_str(),
-                    "location".code_str(),
-                    location.to_string_lossy().code_str(),
-                ),
-                None,
-            ));
+    // If there were any violations, report them in a deterministic order.
+    if !violations.is_empty() {
+        violations.sort_unstable();
+        return Err(EnvironmentError::Missing(violations));
+    }
+
+    // Interpolate the default values. Sort the variables so that errors are deterministic.
+    let mut variables = defaults.keys().copied().collect::<Vec<_>>();
+    variables.sort_unstable();
+    for variable in variables {
+        resolve_default(
+            variable,
+            &defaults,
+            overrides,
+            &task.env_file_values,
+            &mut result,
+            &mut vec![],
+        )
+        .map_err(EnvironmentError::Interpolation)?;
+    }
+
+    Ok(result)
+}
+
+// Explain which variables are missing from the environment and how to provide them. `missing` maps
+// each task to the variables it needs but doesn't have, `schedule` is the list of tasks being run,
+// and `roots` are the tasks the user asked for (for the example invocation). `lookup` reads a
+// variable from the environment, so a variable which is set but isn't valid Unicode can be
+// distinguished from one which isn't set at all. An empty value counts as set.
+pub fn missing_variables_message(
+    tasks: &BTreeMap<String, Task>,
+    missing: &HashMap<String, Vec<&str>>,
+    schedule: &[&str],
+    roots: &[&str],
+    lookup: impl Fn(&str) -> Option<std::ffi::OsString>,
+) -> String {
+    // Find which tasks need each variable.
+    let mut variables = HashMap::<&str, Vec<&str>>::new();
+    for (task_name, task_variables) in missing {
+        for variable in task_variables {
+            variables
+                .entry(variable)
+                .or_default()
+                .push(task_name.as_str());
         }
     }
+    let mut variables = variables.into_iter().collect::<Vec<_>>();
+    variables.sort_unstable();
 
-    // If a task has any mount paths, then caching should be disabled [tag:mount_paths_nand_cache].
-    if !task.mount_paths.is_empty() && task.cache {
-        return Err(Failure::User(
-            format!(
-                "Task {} has {} but does not disable caching. \
-                 To fix this, set {} for this task.",
-                name.code_str(),
-                "mount_paths".code_str(),
-                "cache: false".code_str(),
+    let mut message = format!(
+        "{} missing from the environment:",
+        if variables.len() == 1 {
+            "A variable is"
+        } else {
+            "Some variables are"
+        },
+    );
+    for (variable, mut needed_by) in variables.iter().cloned() {
+        needed_by.sort_unstable();
+        let _ = write!(
+            message,
+            "\n- {} is needed by {} {}.",
+            variable.code_str(),
+            if needed_by.len() == 1 {
+                "task"
+            } else {
+                "tasks"
+            },
+            format::series(
+                &needed_by
+                    .iter()
+                    .map(|task_name| task_name.code_str().to_string())
+                    .collect::<Vec<_>>(),
             ),
-            None,
+        );
+
+        // A variable which is set to something other than Unicode is treated as missing, which is
+        // surprising.
+        if lookup(variable).is_some() {
+            message.push_str(" It's set, but its value isn't valid Unicode.");
+        }
+
+        // Defaults are per task, so a default in another task doesn't help. This is a common source
+        // of confusion.
+        let mut providers = schedule
+            .iter()
+            .filter(|task_name| {
+                tasks
+                    .get(**task_name)
+                    .and_then(|task| task.environment.get(variable))
+                    .is_some_and(Option::is_some)
+            })
+            .map(|task_name| task_name.code_str().to_string())
+            .collect::<Vec<_>>();
+        providers.sort_unstable();
+        if !providers.is_empty() {
+            let _ = write!(
+                message,
+                " {}, but defaults only apply to the task which declares them.",
+                if providers.len() == 1 {
+                    format!("Task {} has a default for it", providers[0])
+                } else {
+                    format!("Tasks {} have defaults for it", format::series(&providers))
+                },
+            );
+        }
+    }
+
+    // Show the quickest way to provide the variables.
+    let _ = write!(
+        message,
+        "\nTo provide {}, run: {}",
+        if variables.len() == 1 { "it" } else { "them" },
+        variables
+            .iter()
+            .map(|(variable, _)| format!("{variable}=\u{2026}"))
+            .chain(std::iter::once("toast".to_owned()))
+            .chain(roots.iter().map(|root| (*root).to_owned()))
+            .collect::<Vec<_>>()
+            .join(" ")
+            .code_str(),
+    );
+
+    message
+}
+
+// Interpolate the default value of a variable and add it to `resolved`, along with any defaults it
+// refers to. The `chain` is the sequence of variables whose defaults led to this one, for detecting
+// cycles.
+fn resolve_default<'a>(
+    variable: &'a str,
+    defaults: &HashMap<&'a str, &'a str>,
+    overrides: &HashMap<String, String>,
+    env_file_values: &HashMap<String, String>,
+    resolved: &mut HashMap<String, String>,
+    chain: &mut Vec<&'a str>,
+) -> Result<String, String> {
+    if let Some(value) = resolved.get(variable) {
+        return Ok(value.clone());
+    }
+
+    if chain.contains(&variable) {
+        chain.push(variable);
+        return Err(format!(
+            "The default values of these variables refer to each other: {}.",
+            format_chain(chain),
         ));
     }
 
-    // If a task exposes ports, then caching should be disabled [tag:ports_nand_cache].
-    if !&task.ports.is_empty() && task.cache {
-        return Err(Failure::User(
-            format!(
-                "Task {} exposes ports but does not disable caching. \
-                 To fix this, set {} for this task.",
-                name.code_str(),
-                "cache: false".code_str(),
-            ),
-            None,
+    chain.push(variable);
+    let value = interpolate(defaults[variable], &mut |reference| {
+        if let Some((&reference, _)) = defaults.get_key_value(reference) {
+            resolve_default(
+                reference,
+                defaults,
+                overrides,
+                env_file_values,
+                resolved,
+                chain,
+            )
+        } else if let Some(value) = resolved.get(reference).or_else(|| overrides.get(reference)) {
+            Ok(value.clone())
+        } else if let Ok(value) = env::var(reference) {
+            Ok(value)
+        } else if let Some(value) = env_file_values.get(reference) {
+            Ok(value.clone())
+        } else {
+            Err(format!(
+                "The default value of {} refers to {}, which is not defined{}.",
+                chain.last().unwrap().code_str(), // Safe since `chain` is nonempty.
+                reference.code_str(),
+                if chain.len() > 1 {
+                    format!(" (via {})", format_chain(chain))
+                } else {
+                    String::new()
+                },
+            ))
+        }
+    })?;
+    chain.pop();
+
+    resolved.insert(variable.to_owned(), value.clone());
+    Ok(value)
+}
+
+// Render a sequence of variables whose default values refer to one another.
+fn format_chain(chain: &[&str]) -> String {
+    chain
+        .iter()
+        .map(|variable| variable.code_str().to_string())
+        .collect::<Vec<_>>()
+        .join(" \u{2192} ")
+}
+
+// Expand each `${NAME}` in a string using `lookup`. A `$$` produces a literal `$`, and a `$` which
+// isn't followed by `$` or `{` is left alone.
+fn interpolate(
+    value: &str,
+    lookup: &mut dyn FnMut(&str) -> Result<String, String>,
+) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = value;
+
+    while let Some(index) = rest.find('$') {
+        result.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            result.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('{') {
+            let Some(end) = after.find('}') else {
+                return Err(format!(
+                    "The default value {} has a {} without a matching {}.",
+                    value.code_str(),
+                    "${".code_str(),
+                    "}".code_str(),
+                ));
+            };
+            result.push_str(&lookup(&after[..end])?);
+            rest = &after[end + 1..];
+        } else {
+            result.push('$');
+        }
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+// [tag:image_helper] Fetch the base image for a task, defaulting to the top-level image if needed.
+pub fn base_image(toastfile: &Toastfile, task: &Task) -> String {
+    task.image
+        .clone()
+        .unwrap_or_else(|| toastfile.image.clone())
+}
+
+// [tag:location_helper] Fetch the location for a task, defaulting to the top-level location if
+// needed.
+pub fn location(toastfile: &Toastfile, task: &Task) -> UnixPathBuf {
+    task.location
+        .clone()
+        .unwrap_or_else(|| toastfile.location.clone())
+}
+
+// [tag:timeout_helper] Fetch the timeout for a task, defaulting to the top-level timeout if needed.
+pub fn timeout(toastfile: &Toastfile, task: &Task) -> Option<Duration> {
+    task.timeout.or(toastfile.timeout).map(Duration::from_secs)
+}
+
+// [tag:docker_cli_helper] Fetch the Docker CLI for a task, defaulting to the configured one if
+// needed.
+pub fn docker_cli<'a>(task: &'a Task, default: &'a str) -> &'a str {
+    task.docker_cli.as_deref().unwrap_or(default)
+}
+
+// [tag:docker_repo_helper] Fetch the Docker repository for a task, defaulting to the configured one
+// if needed.
+pub fn docker_repo<'a>(task: &'a Task, default: &'a str) -> &'a str {
+    task.docker_repo.as_deref().unwrap_or(default)
+}
+
+// [tag:user_helper] Fetch the user for a task, defaulting to the top-level location if needed.
+pub fn user(toastfile: &Toastfile, task: &Task) -> String {
+    task.user.clone().unwrap_or_else(|| toastfile.user.clone())
+}
+
+// Parse a permission mode given as an octal string (e.g., `0755`).
+pub fn parse_mode(mode: &str) -> Option<u32> {
+    u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+}
+
+// Parse a GPU request in the syntax accepted by `docker container create --gpus`, e.g., `all`, `2`,
+// or `device=0,1,capabilities=compute`. Returns the value to pass to Docker, with any list values
+// quoted so Docker doesn't split them, or a description of the problem.
+pub fn parse_gpus(value: &str) -> Result<String, String> {
+    let value = value.trim();
+
+    // The simple forms request all GPUs or a number of them.
+    if value == "all" || is_gpu_count(value) {
+        return Ok(value.to_owned());
+    }
+
+    // Otherwise, the value is a comma-separated list of `key=value` fields. Docker parses it as a
+    // CSV record, so a field may be quoted to include commas. For convenience, we also allow a list
+    // value to continue after a comma without quotes (e.g., `device=0,1`).
+    let mut fields: Vec<(String, Vec<String>)> = vec![];
+    for token in split_gpu_fields(value)? {
+        if let Some((key, field_value)) = token.split_once('=') {
+            if !["count", "device", "driver", "capabilities", "options"].contains(&key) {
+                return Err(format!("{} is not a known field", key.code_str()));
+            }
+            if fields.iter().any(|(other_key, _)| other_key == key) {
+                return Err(format!("{} is given more than once", key.code_str()));
+            }
+            fields.push((
+                key.to_owned(),
+                field_value.split(',').map(ToOwned::to_owned).collect(),
+            ));
+        } else if let Some((_, values)) = fields
+            .last_mut()
+            .filter(|(key, _)| key == "device" || key == "capabilities")
+        {
+            values.push(token);
+        } else {
+            return Err(format!(
+                "{} is not a {} field",
+                token.code_str(),
+                "key=value".code_str()
+            ));
+        }
+    }
+
+    // Check the values of each field.
+    for (key, values) in &fields {
+        for field_value in values {
+            let valid = match key.as_str() {
+                "count" => values.len() == 1 && (field_value == "all" || is_gpu_count(field_value)),
+                "device" => {
+                    !field_value.is_empty()
+                        && field_value
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == ':')
+                }
+                "capabilities" | "driver" => {
+                    !field_value.is_empty()
+                        && field_value
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                }
+                _ => !field_value.is_empty(),
+            };
+            if !valid {
+                return Err(format!(
+                    "{} is not a valid value for {}",
+                    field_value.code_str(),
+                    key.code_str(),
+                ));
+            }
+        }
+    }
+
+    // Docker doesn't allow requesting both a number of GPUs and specific devices.
+    if fields.iter().any(|(key, _)| key == "count") && fields.iter().any(|(key, _)| key == "device")
+    {
+        return Err(format!(
+            "{} and {} can't both be given",
+            "count".code_str(),
+            "device".code_str(),
         ));
     }
 
-    // If a task has any extra Docker arguments, then caching should be disabled.
-    // [tag:extra_docker_arguments_nand_cache]
-    if !&task.extra_docker_arguments.is_empty() && task.cache {
-        return Err(Failure::User(
-            format!(
-                "Task {} has extra Docker arguments but does not disable caching. \
-                 To fix this, set {} for this task.",
+    // Render the fields for Docker, quoting the ones which contain commas.
+    Ok(fields
+        .iter()
+        .map(|(key, values)| {
+            let field = format!("{}={}", key, values.join(","));
+            if values.len() > 1 {
+                format!("\"{field}\"")
+            } else {
+                field
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(","))
+}
+
+// Determine whether a string is a positive number of GPUs.
+fn is_gpu_count(value: &str) -> bool {
+    value.chars().all(|c| c.is_ascii_digit()) && value.parse::<u32>().is_ok_and(|count| count > 0)
+}
+
+// Split a GPU request into fields at the commas which aren't inside double quotes, removing the
+// quotes.
+fn split_gpu_fields(value: &str) -> Result<Vec<String>, String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+
+    for c in value.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err("a quote is never closed".to_owned());
+    }
+    fields.push(field);
+
+    if fields.iter().any(String::is_empty) {
+        return Err("it has an empty field".to_owned());
+    }
+
+    Ok(fields)
+}
+
+// Fetch the value of `--gpus` for a task, if it requests any GPUs. The value was validated when the
+// toastfile was parsed [ref:gpus_valid].
+pub fn gpus(task: &Task) -> Option<String> {
+    task.gpus
+        .as_deref()
+        .and_then(|value| parse_gpus(value).ok())
+}
+
+// Fetch the maximum age of the cached images for a task, if it has one. The duration was validated
+// when the toastfile was parsed [ref:cache_ttl_valid].
+pub fn cache_ttl(task: &Task) -> Option<Duration> {
+    task.cache_ttl
+        .as_deref()
+        .and_then(|ttl| units::parse_duration(ttl, "").ok())
+}
+
+// Fetch the modes for the input files of a task, using the defaults if needed. The modes were
+// validated when the toastfile was parsed [ref:input_modes_valid].
+pub fn input_modes(task: &Task) -> tar::Modes {
+    let defaults = tar::Modes::default();
+
+    tar::Modes {
+        directory: task
+            .input_dir_mode
+            .as_deref()
+            .and_then(parse_mode)
+            .unwrap_or(defaults.directory),
+        file: task
+            .input_file_mode
+            .as_deref()
+            .and_then(parse_mode)
+            .unwrap_or(defaults.file),
+    }
+}
+
+// Read the `command_file` of each task into its `command` [tag:command_files_loaded]. The paths are
+// relative to `toastfile_dir`. Since the contents become the command, they're part of the cache key
+// (but the path isn't), and the file doesn't need to be listed in `input_paths`.
+pub fn load_command_files(toastfile: &mut Toastfile, toastfile_dir: &Path) -> Result<(), Failure> {
+    // We accumulate all the problems, so the user can fix them at once.
+    let mut problems = vec![];
+
+    for (name, task) in &mut toastfile.tasks {
+        let Some(command_file) = &task.command_file else {
+            continue;
+        };
+
+        let contents = command_file
+            .try_as_ref()
+            .map(|path: &Path| toastfile_dir.join(path))
+            .and_then(|path| fs::read_to_string(path).ok());
+        match contents {
+            // The contents replace the command, which is empty [ref:command_file_valid].
+            Some(contents) => task.command = contents,
+            None => problems.push(format!(
+                "Unable to read the {} of task {}: {}.",
+                "command_file".code_str(),
                 name.code_str(),
-                "cache: false".code_str(),
-            ),
-            None,
-        ));
+                command_file.to_string_lossy().code_str(),
+            )),
+        }
     }
 
-    // If we made it this far, the task is valid.
-    Ok(())
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Failure::User(problems.join(" "), None))
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use {
-        crate::toastfile::{
-            check_dependencies, check_task, command, environment, location, parse, user,
-            MappingPath, Task, Toastfile, DEFAULT_LOCATION, DEFAULT_USER,
-        },
-        std::{collections::HashMap, env, path::Path},
-        typed_path::UnixPath,
+// [tag:env_files_loaded] Read the `env_file` of the toastfile and of each task into the task's
+// `env_file_values`. The paths are relative to the toastfile. A task's own file takes precedence
+// over the top-level one. The values are only used for the variables the task declares
+// [ref:environment_overrides].
+pub fn load_env_files(toastfile: &mut Toastfile, toastfile_dir: &Path) -> Result<(), Failure> {
+    // We accumulate all the problems, so the user can fix them at once.
+    let mut problems = vec![];
+
+    let mut read = |env_file: &UnixPathBuf, owner: &dyn Fn() -> String| {
+        let contents = env_file
+            .try_as_ref()
+            .map(|path: &Path| toastfile_dir.join(path))
+            .and_then(|path| fs::read_to_string(path).ok());
+        match contents.map(|contents| dotenv::parse(&contents)) {
+            Some(Ok(variables)) => variables,
+            Some(Err(reason)) => {
+                problems.push(format!(
+                    "Unable to parse the {} of {}: {}. {}",
+                    "env_file".code_str(),
+                    owner(),
+                    env_file.to_string_lossy().code_str(),
+                    reason,
+                ));
+                vec![]
+            }
+            None => {
+                problems.push(format!(
+                    "Unable to read the {} of {}: {}.",
+                    "env_file".code_str(),
+                    owner(),
+                    env_file.to_string_lossy().code_str(),
+                ));
+                vec![]
+            }
+        }
     };
 
-    #[test]
-    fn parse_empty() {
-        let input = r"
-image: encom:os-12
-    "
-        .trim();
+    let shared = toastfile
+        .env_file
+        .as_ref()
+        .map_or_else(Vec::new, |env_file| {
+            read(env_file, &|| "the toastfile".to_owned())
+        });
 
-        let toastfile = Toastfile {
-            image: "encom:os-12".to_owned(),
-            default: None,
-            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
-            user: DEFAULT_USER.to_owned(),
-            command_prefix: String::new(),
-            tasks: HashMap::new(),
-        };
+    for (name, task) in &mut toastfile.tasks {
+        let own = task.env_file.as_ref().map_or_else(Vec::new, |env_file| {
+            read(env_file, &|| format!("task {}", name.code_str()))
+        });
 
-        assert_eq!(parse(input).unwrap(), toastfile);
+        task.env_file_values = shared.iter().cloned().chain(own).collect();
     }
 
-    #[test]
-    fn parse_minimal_task() {
-        let input = r"
-image: encom:os-12
-tasks:
-  foo: {}
-    "
-        .trim();
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Failure::User(problems.join(" "), None))
+    }
+}
 
-        let mut tasks = HashMap::new();
-        tasks.insert(
-            "foo".to_owned(),
-            Task {
-                description: None,
-                dependencies: vec![],
-                cache: true,
-                environment: HashMap::new(),
-                input_paths: vec![],
-                excluded_input_paths: vec![],
-                output_paths: vec![],
-                output_paths_on_failure: vec![],
-                mount_paths: vec![],
-                mount_readonly: false,
-                ports: vec![],
-                location: None,
-                user: None,
-                command: String::new(),
-                command_prefix: None,
-                extra_docker_arguments: vec![],
-            },
-        );
+// Fetch the command prefix for a task, using the top-level prefix if needed.
+pub fn command_prefix<'a>(toastfile: &'a Toastfile, task: &'a Task) -> &'a str {
+    task.command_prefix
+        .as_deref()
+        .unwrap_or(&toastfile.command_prefix)
+}
 
-        let toastfile = Toastfile {
-            image: "encom:os-12".to_owned(),
-            default: None,
-            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
-            user: DEFAULT_USER.to_owned(),
-            command_prefix: String::new(),
-            tasks,
-        };
+// [tag:command_helper] Fetch the command for a task, including the prefix, using the top-level
+// prefix if needed.
+pub fn command(toastfile: &Toastfile, task: &Task) -> String {
+    let mut command = command_prefix(toastfile, task).to_owned();
 
-        assert_eq!(parse(input).unwrap(), toastfile);
+    // The prefix and the command are separated by a newline [tag:command_prefix_newline].
+    if !command.is_empty() && !task.command.is_empty() {
+        command.push('\n');
     }
 
-    #[test]
-    #[allow(clippy::too_many_lines)]
-    fn parse_comprehensive_task() {
-        let input = r"
-image: encom:os-12
-default: bar
-location: /default_location
-user: default_user
-command_prefix: prefix
-tasks:
-  foo: {}
+    command.push_str(&task.command);
+
+    command
+}
+
+// Check that an image reference is valid. Docker would reject an invalid one anyway, but only after
+// the input files have been read, and with a less helpful message.
+pub fn check_image(image: &str) -> Result<(), Failure> {
+    if let Some(problem) = image_problem(image) {
+        Err(Failure::User(
+            format!("Invalid image {}: {}.", image.code_str(), problem),
+            None,
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+// Check that the base image of the toastfile and those of the tasks are pinned to a digest, for
+// `--require-pinned-image`. The tasks are checked in order of their names.
+pub fn check_pinned_images(toastfile: &Toastfile) -> Result<(), Failure> {
+    let mut images = vec![(
+        format!("The base image {}", toastfile.image.code_str()),
+        &toastfile.image,
+    )];
+    let mut task_names = toastfile.tasks.keys().collect::<Vec<_>>();
+    task_names.sort();
+    for name in task_names {
+        if let Some(image) = &toastfile.tasks[name].image {
+            images.push((
+                format!("The image {} of task {}", image.code_str(), name.code_str()),
+                image,
+            ));
+        }
+    }
+
+    for (description, image) in images {
+        if !image.contains("@sha256:") {
+            return Err(Failure::User(
+                format!(
+                    "{} isn't pinned to a digest. To pin it, append the digest of the image \
+                     (e.g., {}). You can find the digest with {}.",
+                    description,
+                    format!("{image}@sha256:\u{2026}").code_str(),
+                    format!("docker image inspect --format '{{{{.RepoDigests}}}}' {image}")
+                        .code_str(),
+                ),
+                None,
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// Describe what's wrong with an image reference of the form `[host[:port]/]path[:tag][@digest]`, if
+// anything. This follows the grammar that Docker uses.
+fn image_problem(image: &str) -> Option<String> {
+    if image.is_empty() {
+        return Some("image is empty".to_owned());
+    }
+
+    if image.chars().any(char::is_whitespace) {
+        return Some("image contains whitespace".to_owned());
+    }
+
+    // Split off the digest, if there is one.
+    let (name_and_tag, digest) = match image.split_once('@') {
+        Some((name_and_tag, digest)) => (name_and_tag, Some(digest)),
+        None => (image, None),
+    };
+
+    // Split off the tag, if there is one. A colon before the last slash belongs to a port number.
+    let last_slash = name_and_tag.rfind('/').map_or(0, |index| index + 1);
+    let (name, tag) = match name_and_tag[last_slash..].find(':') {
+        Some(index) => (
+            &name_and_tag[..last_slash + index],
+            Some(&name_and_tag[last_slash + index + 1..]),
+        ),
+        None => (name_and_tag, None),
+    };
+
+    // The first component of the name is a registry host if it looks like one.
+    let mut components = name.split('/').collect::<Vec<_>>();
+    if components.len() > 1
+        && (components[0].contains(['.', ':'])
+            || components[0] == "localhost"
+            || components[0].chars().any(|c| c.is_ascii_uppercase()))
+    {
+        if let Some(problem) = host_problem(components[0]) {
+            return Some(problem);
+        }
+        components.remove(0);
+    }
+
+    for component in components {
+        if let Some(problem) = path_component_problem(component) {
+            return Some(problem);
+        }
+    }
+
+    if let Some(problem) = tag.and_then(tag_problem) {
+        return Some(problem);
+    }
+
+    digest.and_then(digest_problem)
+}
+
+// Describe what's wrong with a registry host and optional port, if anything.
+fn host_problem(host: &str) -> Option<String> {
+    let (domain, port) = match host.split_once(':') {
+        Some((domain, port)) => (domain, Some(port)),
+        None => (host, None),
+    };
+
+    if domain.split('.').any(|component| {
+        component.is_empty()
+            || !component
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+            || component.starts_with('-')
+            || component.ends_with('-')
+    }) {
+        return Some(format!("registry host {} is invalid", domain.code_str()));
+    }
+
+    if port.is_some_and(|port| port.is_empty() || !port.chars().all(|c| c.is_ascii_digit())) {
+        return Some(format!("registry port in {} is invalid", host.code_str()));
+    }
+
+    None
+}
+
+// Describe what's wrong with a component of an image path (e.g., `library` or `node`), if anything.
+// Components consist of lowercase alphanumeric runs joined by `.`, `_`, `__`, or any number of `-`.
+fn path_component_problem(component: &str) -> Option<String> {
+    if component.is_empty() {
+        return Some("image name has an empty component".to_owned());
+    }
+
+    if component.chars().any(|c| c.is_ascii_uppercase()) {
+        return Some(format!(
+            "image name contains uppercase letters in {}",
+            component.code_str(),
+        ));
+    }
+
+    if let Some(c) = component
+        .chars()
+        .find(|c| !(c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '.' | '_' | '-')))
+    {
+        return Some(format!(
+            "image name contains {} in {}",
+            c.to_string().code_str(),
+            component.code_str(),
+        ));
+    }
+
+    let is_separator = |c: char| matches!(c, '.' | '_' | '-');
+    if component.starts_with(is_separator)
+        || component.ends_with(is_separator)
+        || component
+            .split(|c: char| !is_separator(c))
+            .any(|separator| {
+                !(matches!(separator, "" | "." | "_" | "__") || separator.chars().all(|c| c == '-'))
+            })
+    {
+        return Some(format!(
+            "image name has an invalid separator in {}",
+            component.code_str(),
+        ));
+    }
+
+    None
+}
+
+// Describe what's wrong with a tag, if anything. Tags are up to 128 word characters, periods, and
+// dashes, and they can't start with a period or dash.
+fn tag_problem(tag: &str) -> Option<String> {
+    if tag.is_empty() {
+        return Some("tag is empty".to_owned());
+    }
+
+    if tag.len() > 128 {
+        return Some("tag is longer than 128 characters".to_owned());
+    }
+
+    if tag.starts_with(['.', '-']) {
+        return Some(format!(
+            "tag {} starts with {}",
+            tag.code_str(),
+            tag[..1].code_str(),
+        ));
+    }
+
+    tag.chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-')))
+        .map(|c| {
+            format!(
+                "tag {} contains {}",
+                tag.code_str(),
+                c.to_string().code_str(),
+            )
+        })
+}
+
+// Describe what's wrong with a digest (e.g., `sha256:` followed by hexadecimal digits), if anything.
+fn digest_problem(digest: &str) -> Option<String> {
+    let Some((algorithm, encoded)) = digest.split_once(':') else {
+        return Some(format!(
+            "digest {} is missing an algorithm",
+            digest.code_str(),
+        ));
+    };
+
+    if algorithm.is_empty()
+        || !algorithm.starts_with(|c: char| c.is_ascii_alphabetic())
+        || !algorithm
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '.' | '_' | '-'))
+    {
+        return Some(format!(
+            "digest algorithm {} is invalid",
+            algorithm.code_str(),
+        ));
+    }
+
+    if encoded.len() < 32 || !encoded.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Some(format!(
+            "digest {} must have at least 32 hexadecimal digits",
+            digest.code_str(),
+        ));
+    }
+
+    None
+}
+
+// Check that all dependencies exist and form a DAG (no cycles).
+#[allow(clippy::too_many_lines)]
+fn check_dependencies<'a>(toastfile: &'a Toastfile) -> Result<(), Failure> {
+    // Check the default task [tag:valid_default].
+    let valid_default = toastfile
+        .default
+        .as_ref()
+        .is_none_or(|default| toastfile.tasks.contains_key(default));
+
+    // Map from task to vector of invalid dependencies. This is ordered so the error is deterministic.
+    let mut violations: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    // Scan for invalid dependencies [tag:task_valid].
+    for task in toastfile.tasks.keys() {
+        // [ref:task_valid]
+        for dependency in &toastfile.tasks[task].dependencies {
+            if !toastfile.tasks.contains_key(&dependency.task) {
+                // [tag:dependencies_exist]
+                violations
+                    .entry(task.clone())
+                    .or_default()
+                    .push(dependency.task.clone());
+            }
+        }
+    }
+
+    // If there were any invalid dependencies, report them.
+    if !violations.is_empty() {
+        let violations_series = format::series(
+            violations
+                .iter()
+                .map(|(task, dependencies)| {
+                    format!(
+                        "{} ({})",
+                        task.code_str(),
+                        format::series(
+                            dependencies
+                                .iter()
+                                .map(|task| format!("{}", task.code_str()))
+                                .collect::<Vec<_>>()
+                                .as_ref(),
+                        ),
+                    )
+                })
+                .collect::<Vec<_>>()
+                .as_ref(),
+        );
+
+        if valid_default {
+            return Err(Failure::User(
+                format!("The following tasks have invalid dependencies: {violations_series}."),
+                None,
+            ));
+        }
+
+        return Err(Failure::User(
+            format!(
+                "The default task {} does not exist, and the following tasks have invalid \
+                 dependencies: {}.",
+                toastfile.default.as_ref().unwrap().code_str(), // [ref:valid_default]
+                violations_series,
+            ),
+            None,
+        ));
+    } else if !valid_default {
+        return Err(Failure::User(
+            format!(
+                "The default task {} does not exist.",
+                toastfile.default.as_ref().unwrap().code_str(), // [ref:valid_default]
+            ),
+            None,
+        ));
+    }
+
+    // Check that the dependencies aren't cyclic [tag:tasks_dag].
+    let mut visited: HashSet<&'a str> = HashSet::new();
+    for task in toastfile.tasks.keys() {
+        let mut frontier: Vec<(&'a str, usize)> = vec![(task, 0)];
+        let mut ancestors_set: HashSet<&'a str> = HashSet::new();
+        let mut ancestors_stack: Vec<&'a str> = vec![];
+
+        // Keep going as long as there are more nodes to process [tag:toastfile_frontier_nonempty].
+        while let Some((task, task_depth)) = frontier.pop() {
+            // Take the top task from the frontier. This is safe due to
+            // [ref:toastfile_frontier_nonempty].
+
+            // Update the ancestors set and stack.
+            for _ in 0..ancestors_stack.len() - task_depth {
+                // The `unwrap` is safe because `ancestors_stack.len()` is positive in every
+                // iteration of this loop.
+                let task_to_remove = ancestors_stack.pop().unwrap();
+                ancestors_set.remove(task_to_remove);
+            }
+
+            // If this task is an ancestor of itself, we have a cycle. Return an error.
+            if ancestors_set.contains(task) {
+                let mut cycle_iter = ancestors_stack.iter();
+                cycle_iter.find(|&&x| x == task);
+                let mut cycle = cycle_iter.collect::<Vec<_>>();
+                cycle.push(&task); // [tag:cycle_nonempty]
+
+                // Self-dependencies are skipped below, so the cycle has at least two tasks.
+                let error_message = if cycle.len() == 2 {
+                    format!(
+                        "{} and {} depend on each other.",
+                        cycle[0].code_str(),
+                        cycle[1].code_str(),
+                    )
+                } else {
+                    let mut cycle_dependencies = cycle[1..].to_owned();
+                    cycle_dependencies.push(cycle[0]); // [ref:cycle_nonempty]
+                    format!(
+                        "{}.",
+                        format::series(
+                            cycle
+                                .iter()
+                                .zip(cycle_dependencies)
+                                .map(|(x, y)| {
+                                    format!("{} depends on {}", x.code_str(), y.code_str())
+                                })
+                                .collect::<Vec<_>>()
+                                .as_ref(),
+                        ),
+                    )
+                };
+                return Err(Failure::User(
+                    format!("The dependencies are cyclic. {error_message}"),
+                    None,
+                ));
+            }
+
+            // If we've never seen this task before, add its dependencies to the frontier.
+            if !visited.contains(task) {
+                visited.insert(task);
+
+                ancestors_set.insert(task);
+                ancestors_stack.push(task);
+
+                // A task which depends on itself is reported by `check_task` with a more specific
+                // message [ref:no_self_dependency], so that edge is skipped here.
+                for dependency in &toastfile.tasks[task].dependencies {
+                    if dependency.task != task {
+                        frontier.push((&dependency.task, task_depth + 1));
+                    }
+                }
+            }
+        }
+    }
+
+    // No violations
+    Ok(())
+}
+
+// Lexically normalize a relative path into its components.
+fn normalized_components(path: &UnixPath) -> Vec<&[u8]> {
+    let mut components = vec![];
+
+    for component in path.components() {
+        match component {
+            UnixComponent::Normal(name) => components.push(name),
+            UnixComponent::ParentDir => {
+                if components.pop().is_none() {
+                    components.push(b"..");
+                }
+            }
+            UnixComponent::CurDir | UnixComponent::RootDir => {}
+        }
+    }
+
+    components
+}
+
+// Determine whether two relative paths are the same or one contains the other.
+fn paths_overlap(x: &UnixPath, y: &UnixPath) -> bool {
+    let x = normalized_components(x);
+    let y = normalized_components(y);
+    x.starts_with(&y) || y.starts_with(&x)
+}
+
+// Determine where an output path is copied to on the host, relative to the output directory. An
+// absolute path in the container is copied directly into the output directory under its file name
+// [ref:output_paths_valid] [ref:output_host_paths_distinct].
+pub fn output_host_path(path: &UnixPath) -> &UnixPath {
+    if path.is_absolute() {
+        path.file_name().map_or(path, UnixPath::new)
+    } else {
+        path
+    }
+}
+
+// [tag:mount_target] Compute where a mount appears in the container. Relative container paths are
+// relative to the location of the task.
+pub fn mount_target(location: &UnixPath, mount_path: &MappingPath) -> UnixPathBuf {
+    location.join(&mount_path.container_path)
+}
+
+// Find the mounts which hide files Toast copies into the container for a task, and return a
+// description of each. A mount hides the input paths at or below its target [ref:mount_target].
+// If it hides the whole location, anything previous tasks wrote there is hidden as well. This is
+// legal, so it's only a warning, but it's rarely what the user wants.
+fn check_mount_shadowing(toastfile: &Toastfile, name: &str, task: &Task) -> Vec<String> {
+    let mut warnings = vec![];
+
+    // If there are no input paths, there's nothing to hide.
+    if task.input_paths.is_empty() {
+        return warnings;
+    }
+
+    let location = location(toastfile, task);
+    let location_components = normalized_components(&location);
+    for mount_path in &task.mount_paths {
+        let target = mount_target(&location, mount_path);
+        let target_components = normalized_components(&target);
+
+        // Check whether the mount hides the whole location.
+        if location_components.starts_with(&target_components) {
+            warnings.push(format!(
+                "The mount {} of task {} hides its {} {}, including the {} copied there.",
+                mount_path.to_string().code_str(),
+                name.code_str(),
+                "location".code_str(),
+                location.to_string_lossy().code_str(),
+                "input_paths".code_str(),
+            ));
+            continue;
+        }
+
+        // Check whether the mount hides some of the input paths.
+        for input_path in &task.input_paths {
+            let input_path_acr = location.join(input_path);
+            if normalized_components(&input_path_acr).starts_with(&target_components) {
+                warnings.push(format!(
+                    "The mount {} of task {} hides its {} {} at {}.",
+                    mount_path.to_string().code_str(),
+                    name.code_str(),
+                    "input_path".code_str(),
+                    input_path.to_string_lossy().code_str(),
+                    input_path_acr.to_string_lossy().code_str(),
+                ));
+            }
+        }
+    }
+
+    warnings
+}
+
+// Resolve a host path against the directory containing the toastfile and normalize away `.` and
+// `..` components, without consulting the filesystem.
+fn normalized_host_path(toastfile_dir: &Path, path: &Path) -> Vec<String> {
+    let mut components = vec![];
+    for component in toastfile_dir.join(path).components() {
+        match component {
+            Component::Prefix(prefix) => {
+                components.push(prefix.as_os_str().to_string_lossy().into_owned());
+            }
+            Component::RootDir => components.push("/".to_owned()),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if components.last().is_some_and(|last| last != "/") {
+                    components.pop();
+                }
+            }
+            Component::Normal(name) => components.push(name.to_string_lossy().into_owned()),
+        }
+    }
+    components
+}
+
+// Determine whether `task` depends on `other`, directly or transitively. The dependency graph was
+// checked for cycles when the toastfile was parsed [ref:tasks_dag].
+fn depends_on(toastfile: &Toastfile, task: &str, other: &str) -> bool {
+    let mut visited = HashSet::new();
+    let mut frontier = vec![task];
+    while let Some(current) = frontier.pop() {
+        if let Some(current_task) = toastfile.tasks.get(current) {
+            for dependency in &current_task.dependencies {
+                if dependency.task == other {
+                    return true;
+                }
+                if visited.insert(dependency.task.as_str()) {
+                    frontier.push(&dependency.task);
+                }
+            }
+        }
+    }
+    false
+}
+
+// Find the paths which a task mounts writably.
+fn writable_mount_paths(task: &Task) -> impl Iterator<Item = &MappingPath> {
+    task.mount_paths
+        .iter()
+        .filter(|mount_path| !mount_path.is_readonly(task.mount_readonly))
+}
+
+// Find pairs of tasks which mount overlapping host paths writably even though neither depends on
+// the other, and return a description of each. Such tasks could run at the same time (e.g., from two
+// terminals) and corrupt each other's files. Relative host paths are resolved against the directory
+// containing the toastfile.
+pub fn shared_mount_warnings(toastfile: &Toastfile, toastfile_dir: &Path) -> Vec<String> {
+    // Checking whether two tasks depend on each other is slow for a large toastfile, so the tasks
+    // without writable mounts are left out up front.
+    let mut task_names = toastfile
+        .tasks
+        .iter()
+        .filter(|(_, task)| {
+            !task.allow_shared_mounts && writable_mount_paths(task).next().is_some()
+        })
+        .map(|(name, _)| name)
+        .collect::<Vec<_>>();
+    task_names.sort();
+
+    let mut warnings = vec![];
+    for (i, name) in task_names.iter().enumerate() {
+        for other_name in &task_names[i + 1..] {
+            if depends_on(toastfile, name, other_name) || depends_on(toastfile, other_name, name) {
+                continue;
+            }
+
+            for mount_path in writable_mount_paths(&toastfile.tasks[*name]) {
+                let components = normalized_host_path(toastfile_dir, &mount_path.host_path);
+                for other_mount_path in writable_mount_paths(&toastfile.tasks[*other_name]) {
+                    let other_components =
+                        normalized_host_path(toastfile_dir, &other_mount_path.host_path);
+                    if components.starts_with(&other_components)
+                        || other_components.starts_with(&components)
+                    {
+                        warnings.push(format!(
+                            "Tasks {} and {} both mount {} writably (as {} and {}), but neither \
+                             depends on the other, so running them at the same time could corrupt \
+                             it. If this is intended, set {} on one of them.",
+                            name.code_str(),
+                            other_name.code_str(),
+                            if components.len() < other_components.len() {
+                                &mount_path.host_path
+                            } else {
+                                &other_mount_path.host_path
+                            }
+                            .to_string_lossy()
+                            .code_str(),
+                            mount_path.to_string().code_str(),
+                            other_mount_path.to_string().code_str(),
+                            "allow_shared_mounts: true".code_str(),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    warnings
+}
+
+// Find the mounts which hide files a dependency put in the container, and return a description of
+// each. Dependencies run first, so their `location`, `input_paths`, and `output_paths` are in the
+// image a task starts from, and a mount whose target contains one of them hides it
+// [ref:mount_target]. For example, a task which mounts the project onto `/scratch` can't see the
+// `node_modules` a dependency installed there. This needs the schedule, since only dependencies
+// which actually run are relevant.
+pub fn dependency_shadowing_warnings(toastfile: &Toastfile, schedule: &[&str]) -> Vec<String> {
+    let mut warnings = vec![];
+
+    for (i, name) in schedule.iter().enumerate() {
+        let task = &toastfile.tasks[*name];
+        let task_location = location(toastfile, task);
+
+        for mount_path in &task.mount_paths {
+            let target = mount_target(&task_location, mount_path);
+            let target_components = normalized_components(&target);
+
+            // Every dependency is scheduled before the task [ref:tasks_dag].
+            for dependency_name in &schedule[..i] {
+                if !depends_on(toastfile, name, dependency_name) {
+                    continue;
+                }
+
+                let dependency = &toastfile.tasks[*dependency_name];
+                let dependency_location = location(toastfile, dependency);
+
+                // If the mount hides the whole location of the dependency, there's no need to
+                // mention the paths within it.
+                if normalized_components(&dependency_location).starts_with(&target_components) {
+                    warnings.push(format!(
+                        "The mount {} of task {} hides the {} {} of its dependency {}, so \
+                         anything {} created there isn't visible.",
+                        mount_path.to_string().code_str(),
+                        name.code_str(),
+                        "location".code_str(),
+                        dependency_location.to_string_lossy().code_str(),
+                        dependency_name.code_str(),
+                        dependency_name.code_str(),
+                    ));
+                    continue;
+                }
+
+                for (field, paths) in [
+                    ("input_paths", &dependency.input_paths),
+                    ("output_paths", &dependency.output_paths),
+                ] {
+                    for path in paths {
+                        let path_acr = dependency_location.join(path);
+                        if normalized_components(&path_acr).starts_with(&target_components) {
+                            warnings.push(format!(
+                                "The mount {} of task {} hides the {} entry {} of its dependency \
+                                 {} at {}.",
+                                mount_path.to_string().code_str(),
+                                name.code_str(),
+                                field.code_str(),
+                                path.to_string_lossy().code_str(),
+                                dependency_name.code_str(),
+                                path_acr.to_string_lossy().code_str(),
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    warnings
+}
+
+// Find the `input_paths` and `excluded_input_paths` of the tasks in a schedule which don't exist,
+// so they can all be reported before anything runs. The result lists the tasks with missing paths
+// in the order of the schedule, and each task's input paths come before its excluded input paths.
+// `exists` checks a path relative to the toastfile directory, and `output_dir_rsd` is the output
+// directory relative to the toastfile directory (or `None` if it's somewhere else). Glob patterns
+// are skipped, since whether they may match nothing is up to `allow_empty_globs`. A path which a
+// dependency in the schedule may produce is skipped too, i.e., one which is the same as, contains,
+// or is contained in one of its output paths on the host.
+pub fn missing_input_paths<'a>(
+    toastfile: &'a Toastfile,
+    schedule: &[&'a str],
+    output_dir_rsd: Option<&UnixPath>,
+    exists: impl Fn(&UnixPath) -> bool,
+) -> Vec<(&'a str, Vec<&'a UnixPath>)> {
+    let mut missing = vec![];
+
+    for (i, name) in schedule.iter().enumerate() {
+        let task = &toastfile.tasks[*name];
+
+        // Find where the dependencies write their output files on the host. Every dependency is
+        // scheduled before the task [ref:tasks_dag].
+        let produced_rsd = output_dir_rsd.map_or_else(Vec::new, |output_dir_rsd| {
+            schedule[..i]
+                .iter()
+                .filter(|dependency_name| depends_on(toastfile, name, dependency_name))
+                .flat_map(|dependency_name| {
+                    let dependency = &toastfile.tasks[*dependency_name];
+                    dependency
+                        .output_paths
+                        .iter()
+                        .chain(&dependency.output_paths_on_failure)
+                        .chain(&dependency.output_paths_always)
+                })
+                .map(|path| output_dir_rsd.join(output_host_path(path)))
+                .collect::<Vec<_>>()
+        });
+
+        let task_missing = task
+            .input_paths
+            .iter()
+            .chain(&task.excluded_input_paths)
+            .map(UnixPathBuf::as_path)
+            .filter(|path| {
+                !tar::is_glob(path)
+                    && !produced_rsd
+                        .iter()
+                        .any(|produced| paths_overlap(path, produced))
+                    && !exists(path)
+            })
+            .collect::<Vec<_>>();
+        if !task_missing.is_empty() {
+            missing.push((*name, task_missing));
+        }
+    }
+
+    missing
+}
+
+// Find things in a toastfile which are legal but probably mistakes, and return a description of
+// each. The tasks are sorted so the warnings are reported in a predictable order.
+pub fn warnings(toastfile: &Toastfile) -> Vec<String> {
+    let mut task_names = toastfile.tasks.keys().collect::<Vec<_>>();
+    task_names.sort();
+    task_names
+        .into_iter()
+        .flat_map(|name| check_mount_shadowing(toastfile, name, &toastfile.tasks[name]))
+        .collect()
+}
+
+// Find the dependencies which are listed more than once by the same task, and return a description
+// of each. This is harmless, but it's probably a mistake (e.g., from resolving a merge conflict).
+pub fn dependency_warnings(toastfile: &Toastfile) -> Vec<String> {
+    let mut warnings = vec![];
+
+    for (name, task) in &toastfile.tasks {
+        let mut seen = HashSet::new();
+        let mut duplicates = vec![];
+        for dependency in &task.dependencies {
+            if !seen.insert(&dependency.task) && !duplicates.contains(&&dependency.task) {
+                duplicates.push(&dependency.task);
+            }
+        }
+
+        for duplicate in duplicates {
+            warnings.push(format!(
+                "Task {} lists {} more than once in its {}.",
+                name.code_str(),
+                duplicate.code_str(),
+                "dependencies".code_str(),
+            ));
+        }
+    }
+
+    warnings
+}
+
+// Build the task for a one-off command (`toast run -- COMMAND`). It uses the top-level location,
+// user, and command prefix, and it's never cached, since the command isn't part of the toastfile.
+pub fn ad_hoc_task(
+    command: &str,
+    input_paths: Vec<UnixPathBuf>,
+    mount_paths: Vec<MappingPath>,
+) -> Result<Task, Failure> {
+    let task = Task {
+        description: None,
+        dependencies: vec![],
+        cache: false,
+        arch_independent_cache: false,
+        cache_ttl: None,
+        environment: HashMap::new(), // [ref:default_environment]
+        env_file: None,
+        env_file_values: HashMap::new(),
+        secret_variables: vec![],
+        input_paths,
+        excluded_input_paths: vec![],
+        allow_empty_globs: false,
+        respect_gitignore: false,
+        input_dir_mode: None,
+        input_file_mode: None,
+        output_paths: vec![],
+        output_paths_on_failure: vec![],
+        extract_failure_outputs_on_interrupt: false,
+        output_paths_always: vec![],
+        clean_output_paths: false,
+        backup_output_paths: false,
+        resolve_output_symlinks: false,
+        mount_paths,
+        mount_readonly: default_task_mount_readonly(),
+        volumes: vec![], // [ref:default_volumes]
+        allow_shared_mounts: false,
+        secrets: HashMap::new(),
+        read_only_rootfs: false,
+        ports: vec![], // [ref:default_ports]
+        gpus: None,
+        image: None,
+        location: None,
+        user: None,
+        command: command.to_owned(),
+        command_file: None,
+        command_prefix: None,
+        docker_cli: None,
+        docker_repo: None,
+        exec_after_start: vec![],
+        copy_via_exec: false,
+        extra_docker_arguments: vec![],
+        max_log_lines: None,
+        timeout: None,
+        retries: 0,
+    };
+
+    // The paths come from the command line, so they need the same checks as the ones in the
+    // toastfile.
+    let problems = check_task(AD_HOC_TASK, &task);
+    if problems.is_empty() {
+        Ok(task)
+    } else {
+        Err(Failure::User(problems.join(" "), None))
+    }
+}
+
+// Determine whether Docker accepts a volume name, i.e., whether it matches
+// `[a-zA-Z0-9][a-zA-Z0-9_.-]*`.
+fn volume_name_valid(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
+
+// Check that a task is valid, and return a description of each problem.
+#[allow(clippy::too_many_lines)]
+fn check_task(name: &str, task: &Task) -> Vec<String> {
+    // We accumulate all the problems with the task, so the user can fix them at once.
+    let mut problems = vec![];
+
+    // Check that the task doesn't depend on itself [tag:no_self_dependency].
+    if task
+        .dependencies
+        .iter()
+        .any(|dependency| dependency.task == name)
+    {
+        problems.push(format!(
+            "Task {} lists itself in its {}. Remove {} from the list.",
+            name.code_str(),
+            "dependencies".code_str(),
+            name.code_str(),
+        ));
+    }
+
+    // Check that the image is something Docker will accept [tag:task_image_valid].
+    if let Some(image) = &task.image {
+        if let Some(problem) = image_problem(image) {
+            problems.push(format!(
+                "Task {} has an invalid {} {}: {}.",
+                name.code_str(),
+                "image".code_str(),
+                image.code_str(),
+                problem,
+            ));
+        }
+    }
+
+    // Check that environment variable names don't have `=` in them [tag:env_var_equals]. They're
+    // sorted so the problems are reported in a predictable order.
+    let mut variables = task.environment.keys().collect::<Vec<_>>();
+    variables.sort();
+    for variable in variables {
+        if variable.contains('=') {
+            problems.push(format!(
+                "Environment variable {} of task {} contains {}.",
+                variable.code_str(),
+                name.code_str(),
+                "=".code_str(),
+            ));
+        }
+    }
+
+    // Check that `command_file` is relative and isn't given along with `command`
+    // [tag:command_file_valid].
+    if let Some(path) = &task.command_file {
+        if !path.is_relative() {
+            problems.push(format!(
+                "Task {} has an absolute {}: {}.",
+                name.code_str(),
+                "command_file".code_str(),
+                path.to_string_lossy().code_str(),
+            ));
+        }
+
+        if !task.command.is_empty() {
+            problems.push(format!(
+                "Task {} has both a {} and a {}. Only one of them can be given.",
+                name.code_str(),
+                "command".code_str(),
+                "command_file".code_str(),
+            ));
+        }
+    }
+
+    // Check that `env_file` is relative [tag:task_env_file_relative].
+    if let Some(path) = &task.env_file {
+        if !path.is_relative() {
+            problems.push(format!(
+                "Task {} has an absolute {}: {}.",
+                name.code_str(),
+                "env_file".code_str(),
+                path.to_string_lossy().code_str(),
+            ));
+        }
+    }
+
+    // Check that `input_paths` are relative and that any glob patterns are valid
+    // [tag:input_paths_relative].
+    for path in &task.input_paths {
+        if !path.is_relative() {
+            problems.push(format!(
+                "Task {} has an absolute {}: {}.",
+                name.code_str(),
+                "input_path".code_str(),
+                path.to_string_lossy().code_str(),
+            ));
+        }
+
+        if tar::is_glob(path) {
+            if let Err(reason) = tar::check_glob(path) {
+                problems.push(format!(
+                    "Task {} has an invalid glob pattern in {}: {} ({}).",
+                    name.code_str(),
+                    "input_paths".code_str(),
+                    path.to_string_lossy().code_str(),
+                    reason,
+                ));
+            }
+        }
+    }
+
+    // Check that `excluded_input_paths` are relative and that any glob patterns are valid
+    // [tag:excluded_input_paths_relative].
+    for path in &task.excluded_input_paths {
+        if !path.is_relative() {
+            problems.push(format!(
+                "Task {} has an absolute {}: {}.",
+                name.code_str(),
+                "excluded_input_path".code_str(),
+                path.to_string_lossy().code_str(),
+            ));
+        }
+
+        if tar::is_glob(path) {
+            if let Err(reason) = tar::check_glob(path) {
+                problems.push(format!(
+                    "Task {} has an invalid glob pattern in {}: {} ({}).",
+                    name.code_str(),
+                    "excluded_input_paths".code_str(),
+                    path.to_string_lossy().code_str(),
+                    reason,
+                ));
+            }
+        }
+    }
+
+    // Check that the input modes are valid octal permissions [tag:input_modes_valid].
+    for (field, mode) in [
+        ("input_dir_mode", &task.input_dir_mode),
+        ("input_file_mode", &task.input_file_mode),
+    ] {
+        if let Some(mode) = mode {
+            if parse_mode(mode).is_none() {
+                problems.push(format!(
+                    "Task {} has an invalid {}: {}. It should be an octal string such as {}.",
+                    name.code_str(),
+                    field.code_str(),
+                    mode.code_str(),
+                    "'0755'".code_str(),
+                ));
+            }
+        }
+    }
+
+    // Check that the cache TTL is a valid duration [tag:cache_ttl_valid].
+    if let Some(ttl) = &task.cache_ttl {
+        if let Err(e) = units::parse_duration(
+            ttl,
+            &format!("The {} of task {}", "cache_ttl".code_str(), name.code_str()),
+        ) {
+            problems.push(e.to_string());
+        }
+    }
+
+    // Check that the timeout is positive [tag:task_timeout_positive].
+    if task.timeout == Some(0) {
+        problems.push(format!(
+            "Task {} has a {} of zero. Use a positive number of seconds.",
+            name.code_str(),
+            "timeout".code_str(),
+        ));
+    }
+
+    // Check that the Docker CLI [tag:task_docker_cli_nonempty] and repository
+    // [tag:task_docker_repo_nonempty] aren't empty.
+    for (field, value) in [
+        ("docker_cli", &task.docker_cli),
+        ("docker_repo", &task.docker_repo),
+    ] {
+        if value
+            .as_deref()
+            .is_some_and(|value| value.trim().is_empty())
+        {
+            problems.push(format!(
+                "Task {} has an empty {}. Remove it to use the one from the configuration.",
+                name.code_str(),
+                field.code_str(),
+            ));
+        }
+    }
+
+    // Check that the GPU request is valid [tag:gpus_valid].
+    if let Some(gpus) = &task.gpus {
+        if let Err(problem) = parse_gpus(gpus) {
+            problems.push(format!(
+                "Task {} has an invalid {}: {}. The problem is that {}.",
+                name.code_str(),
+                "gpus".code_str(),
+                gpus.code_str(),
+                problem,
+            ));
+        }
+    }
+
+    // Check that `output_paths` are either absolute (with a file name to use on the host) or
+    // relative without escaping the location [tag:output_paths_valid].
+    for path in &task.output_paths {
+        if path.is_absolute() {
+            if path.file_name().is_none() {
+                problems.push(format!(
+                    "Task {} has an absolute path without a file name in {}: {}.",
+                    name.code_str(),
+                    "output_paths".code_str(),
+                    path.to_string_lossy().code_str(),
+                ));
+            }
+        } else if normalized_components(path).first() == Some(&&b".."[..]) {
+            problems.push(format!(
+                "Task {} has a path in {} which is outside its location: {}.",
+                name.code_str(),
+                "output_paths".code_str(),
+                path.to_string_lossy().code_str(),
+            ));
+        }
+    }
+
+    // Check that no absolute path in `output_paths` is copied to the same place on the host as another
+    // output path, since it's copied under its file name [tag:output_host_paths_distinct].
+    let output_paths = task
+        .output_paths
+        .iter()
+        .chain(&task.output_paths_on_failure)
+        .chain(&task.output_paths_always)
+        .collect::<Vec<_>>();
+    for (i, first_path) in output_paths.iter().enumerate() {
+        for second_path in &output_paths[i + 1..] {
+            let first_host_path = output_host_path(first_path);
+            let second_host_path = output_host_path(second_path);
+            if (first_path.is_absolute() || second_path.is_absolute())
+                && first_host_path.is_relative()
+                && second_host_path.is_relative()
+                && paths_overlap(first_host_path, second_host_path)
+            {
+                problems.push(format!(
+                    "Task {} has output paths {} and {} which would be copied to {} and {} on \
+                     the host. An absolute path is copied into the output directory under its \
+                     file name.",
+                    name.code_str(),
+                    first_path.to_string_lossy().code_str(),
+                    second_path.to_string_lossy().code_str(),
+                    first_host_path.to_string_lossy().code_str(),
+                    second_host_path.to_string_lossy().code_str(),
+                ));
+            }
+        }
+    }
+
+    // Check that `output_paths_on_failure` are relative [tag:output_paths_on_failure_relative].
+    for path in &task.output_paths_on_failure {
+        if !path.is_relative() {
+            problems.push(format!(
+                "Task {} has an absolute path in {}: {}.",
+                name.code_str(),
+                "output_paths_on_failure".code_str(),
+                path.to_string_lossy().code_str(),
+            ));
+        }
+    }
+
+    // Check that `output_paths_always` are relative [tag:output_paths_always_relative].
+    for path in &task.output_paths_always {
+        if !path.is_relative() {
+            problems.push(format!(
+                "Task {} has an absolute path in {}: {}.",
+                name.code_str(),
+                "output_paths_always".code_str(),
+                path.to_string_lossy().code_str(),
+            ));
+        }
+    }
+
+    // Check that the output path lists don't overlap, since `output_paths` and
+    // `output_paths_on_failure` are mutually exclusive and `output_paths_always` covers both cases.
+    for (first_field, first_paths, second_field, second_paths) in [
+        (
+            "output_paths",
+            &task.output_paths,
+            "output_paths_on_failure",
+            &task.output_paths_on_failure,
+        ),
+        (
+            "output_paths",
+            &task.output_paths,
+            "output_paths_always",
+            &task.output_paths_always,
+        ),
+        (
+            "output_paths_on_failure",
+            &task.output_paths_on_failure,
+            "output_paths_always",
+            &task.output_paths_always,
+        ),
+    ] {
+        for first_path in first_paths {
+            for second_path in second_paths {
+                if paths_overlap(first_path, second_path) {
+                    problems.push(format!(
+                        "Task {} lists {} in {} and {} in {}. {} \
+                         To copy a path regardless of whether the task succeeds, list it only \
+                         in {}.",
+                        name.code_str(),
+                        first_path.to_string_lossy().code_str(),
+                        first_field.code_str(),
+                        second_path.to_string_lossy().code_str(),
+                        second_field.code_str(),
+                        if second_field == "output_paths_on_failure" {
+                            "Only one of these lists is copied, depending on whether the task \
+                             succeeds."
+                        } else {
+                            "The paths would be copied twice."
+                        },
+                        "output_paths_always".code_str(),
+                    ));
+                }
+            }
+        }
+    }
+
+    // Check that `backup_output_paths` is only used with `clean_output_paths`
+    // [tag:backup_output_paths_needs_clean].
+    if task.backup_output_paths && !task.clean_output_paths {
+        problems.push(format!(
+            "Task {} enables {} without {}.",
+            name.code_str(),
+            "backup_output_paths".code_str(),
+            "clean_output_paths".code_str(),
+        ));
+    }
+
+    // Check `mount_paths`.
+    for path in &task.mount_paths {
+        // Check that the path doesn't contain any commas [tag:mount_paths_no_commas].
+        if path.container_path.to_string_lossy().contains(',')
+            || path.host_path.to_string_lossy().contains(',')
+        {
+            problems.push(format!(
+                "Mount path {} of task {} has a {}.",
+                format!("{path}").code_str(),
+                name.code_str(),
+                ",".code_str(),
+            ));
+        }
+    }
+
+    // Check that the volume names are ones Docker accepts and that the container paths don't
+    // contain any commas [tag:volumes_valid].
+    for volume in &task.volumes {
+        if !volume_name_valid(&volume.name) {
+            problems.push(format!(
+                "Volume {} of task {} has an invalid name. Names must start with a letter or \
+                 digit and may only contain letters, digits, {}, {}, and {}.",
+                volume.to_string().code_str(),
+                name.code_str(),
+                "_".code_str(),
+                ".".code_str(),
+                "-".code_str(),
+            ));
+        }
+
+        if volume.container_path.to_string_lossy().contains(',') {
+            problems.push(format!(
+                "Volume {} of task {} has a {}.",
+                volume.to_string().code_str(),
+                name.code_str(),
+                ",".code_str(),
+            ));
+        }
+    }
+
+    // Check that `location` is absolute [tag:task_location_absolute].
+    if let Some(location) = &task.location {
+        if !location.is_absolute() {
+            problems.push(format!(
+                "Task {} has a relative {}: {}.",
+                name.code_str(),
+                "location".code_str(),
+                location.to_string_lossy().code_str(),
+            ));
+        }
+    }
+
+    // If a task has any mount paths, then caching should be disabled [tag:mount_paths_nand_cache].
+    if !task.mount_paths.is_empty() && task.cache {
+        problems.push(format!(
+            "Task {} has {} but does not disable caching. \
+             To fix this, set {} for this task.",
+            name.code_str(),
+            "mount_paths".code_str(),
+            "cache: false".code_str(),
+        ));
+    }
+
+    // [tag:secrets_nand_commit] Secrets don't need caching to be disabled, unlike `mount_paths`.
+    // Each one is a read-only bind mount of a file [ref:secrets_mounted], and `docker container
+    // commit` doesn't include the contents of bind mounts, so the secrets never end up in a cached
+    // image (unless the task copies them elsewhere). They aren't part of the cache key either, so
+    // changing a secret doesn't invalidate the cache.
+    //
+    // [tag:secret_names_valid] The names of secrets become file names in the mount target, and the
+    // targets are given to `docker container create --mount`, which doesn't allow commas.
+    let mut secret_names = task.secrets.keys().collect::<Vec<_>>();
+    secret_names.sort();
+    for secret_name in secret_names {
+        if secret_name.is_empty()
+            || secret_name == "."
+            || secret_name == ".."
+            || secret_name.contains(['/', ','])
+        {
+            problems.push(format!(
+                "Task {} has a secret with an invalid name: {}.",
+                name.code_str(),
+                secret_name.code_str(),
+            ));
+        }
+
+        if let Secret::Env(variable) = &task.secrets[secret_name] {
+            if variable.is_empty() || variable.contains('=') {
+                problems.push(format!(
+                    "Secret {} of task {} comes from an invalid environment variable: {}.",
+                    secret_name.code_str(),
+                    name.code_str(),
+                    variable.code_str(),
+                ));
+            }
+        }
+    }
+
+    // If a task has any volumes, then caching should be disabled, since the files it writes to them
+    // wouldn't be cached [tag:volumes_nand_cache].
+    if !task.volumes.is_empty() && task.cache {
+        problems.push(format!(
+            "Task {} has {} but does not disable caching. To fix this, set {} for this task.",
+            name.code_str(),
+            "volumes".code_str(),
+            "cache: false".code_str(),
+        ));
+    }
+
+    // If a task has a read-only root filesystem, then caching should be disabled. Files written to
+    // `location` are in a volume, so they wouldn't be in the committed image
+    // [tag:read_only_rootfs_nand_cache].
+    if task.read_only_rootfs && task.cache {
+        problems.push(format!(
+            "Task {} has {} but does not disable caching, since the files it writes to {} \
+             wouldn't be cached. To fix this, set {} for this task.",
+            name.code_str(),
+            "read_only_rootfs: true".code_str(),
+            "location".code_str(),
+            "cache: false".code_str(),
+        ));
+    }
+
+    // With `copy_via_exec`, the command runs via `docker exec` too, so it can't run alongside the
+    // `exec_after_start` commands [tag:copy_via_exec_nand_exec_after_start].
+    if task.copy_via_exec && !task.exec_after_start.is_empty() {
+        problems.push(format!(
+            "Task {} has both {} and {}, which can't be used together.",
+            name.code_str(),
+            "copy_via_exec: true".code_str(),
+            "exec_after_start".code_str(),
+        ));
+    }
+
+    // If a task exposes ports, then caching should be disabled [tag:ports_nand_cache].
+    if !&task.ports.is_empty() && task.cache {
+        problems.push(format!(
+            "Task {} exposes ports but does not disable caching. \
+             To fix this, set {} for this task.",
+            name.code_str(),
+            "cache: false".code_str(),
+        ));
+    }
+
+    // If a task has any extra Docker arguments, then caching should be disabled.
+    // [tag:extra_docker_arguments_nand_cache]
+    if !&task.extra_docker_arguments.is_empty() && task.cache {
+        problems.push(format!(
+            "Task {} has extra Docker arguments but does not disable caching. \
+             To fix this, set {} for this task.",
+            name.code_str(),
+            "cache: false".code_str(),
+        ));
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::failure::Failure,
+        crate::toastfile::{
+            ad_hoc_task, base_image, cache_ttl, check_content_hash, check_dependencies,
+            check_image, check_pinned_images, check_task, command, command_prefix, content_hash,
+            dependency_shadowing_warnings, dependency_warnings, docker_cli, docker_repo,
+            environment, find, interpolate, load_command_files, load_env_files, location,
+            missing_input_paths, missing_variables_message, mount_target, output_host_path, parse,
+            parse_gpus, parse_validated, shared_mount_warnings, timeout, user, warnings,
+            Dependency, EnvironmentError, MappingPath, Port, PortRange, Protocol, Secret, Task,
+            Toastfile, Volume, DEFAULT_LOCATION, DEFAULT_USER,
+        },
+        std::{
+            collections::{BTreeMap, HashMap},
+            env,
+            ffi::OsString,
+            fs,
+            path::{Path, PathBuf},
+            time::Duration,
+        },
+        tempfile::tempdir,
+        typed_path::UnixPath,
+    };
+
+    // Construct a dependency without a reason.
+    fn dependency(task: &str) -> Dependency {
+        Dependency {
+            task: task.to_owned(),
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn find_in_start_dir() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("toast.yml"), "").unwrap();
+
+        assert_eq!(
+            find(dir.path(), None, "toast.yml").unwrap(),
+            dir.path().join("toast.yml"),
+        );
+    }
+
+    #[test]
+    fn find_in_ancestor() {
+        let dir = tempdir().unwrap();
+        let start_dir = dir.path().join("foo").join("bar");
+        fs::create_dir_all(&start_dir).unwrap();
+        fs::create_dir_all(dir.path().join("foo").join("toast.yml")).unwrap();
+        fs::write(dir.path().join("toast.yml"), "").unwrap();
+
+        assert_eq!(
+            find(&start_dir, None, "toast.yml").unwrap(),
+            dir.path().join("toast.yml"),
+        );
+    }
+
+    #[test]
+    fn find_stops_at_repository_root() {
+        let dir = tempdir().unwrap();
+        let repository = dir.path().join("repo");
+        let start_dir = repository.join("foo");
+        fs::create_dir_all(&start_dir).unwrap();
+        fs::create_dir_all(repository.join(".git")).unwrap();
+        fs::write(dir.path().join("toast.yml"), "").unwrap();
+
+        let message = find(&start_dir, None, "toast.yml").unwrap_err().to_string();
+        assert!(message.contains("root of the repository"));
+
+        fs::write(repository.join("toast.yml"), "").unwrap();
+        assert_eq!(
+            find(&start_dir, None, "toast.yml").unwrap(),
+            repository.join("toast.yml"),
+        );
+    }
+
+    #[test]
+    fn find_stops_at_search_root() {
+        let dir = tempdir().unwrap();
+        let search_root = dir.path().join("foo");
+        let start_dir = search_root.join("bar");
+        fs::create_dir_all(&start_dir).unwrap();
+        fs::write(dir.path().join("toast.yml"), "").unwrap();
+
+        let message = find(&start_dir, Some(&search_root), "toast.yml")
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("search root"));
+    }
+
+    #[test]
+    fn find_search_root_outside() {
+        let dir = tempdir().unwrap();
+        let start_dir = dir.path().join("foo");
+        let search_root = dir.path().join("bar");
+        fs::create_dir_all(&start_dir).unwrap();
+        fs::create_dir_all(&search_root).unwrap();
+
+        let message = find(&start_dir, Some(&search_root), "toast.yml")
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("does not contain"));
+    }
+
+    #[test]
+    fn parse_empty() {
+        let input = r"
+image: encom:os-12
+    "
+        .trim();
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            default: None,
+            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
+            tasks: BTreeMap::new(),
+        };
+
+        assert_eq!(parse(input).unwrap(), toastfile);
+    }
+
+    #[test]
+    fn parse_image_trimmed() {
+        let input = "image: 'encom:os-12 '";
+
+        assert_eq!(parse(input).unwrap().image, "encom:os-12");
+    }
+
+    #[test]
+    fn parse_image_invalid() {
+        let input = "image: 'encom: os-12'";
+
+        let result = parse(input);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("whitespace"));
+    }
+
+    #[test]
+    fn parse_task_image() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo: {}
+  bar:
+    image: ' node:20 '
+";
+
+        let toastfile = parse(input).unwrap();
+        assert_eq!(toastfile.tasks["foo"].image, None);
+        assert_eq!(toastfile.tasks["bar"].image, Some("node:20".to_owned()));
+        assert_eq!(
+            base_image(&toastfile, &toastfile.tasks["foo"]),
+            "encom:os-12",
+        );
+        assert_eq!(base_image(&toastfile, &toastfile.tasks["bar"]), "node:20");
+    }
+
+    #[test]
+    fn parse_task_image_invalid() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    image: Node:20
+";
+
+        let message = parse(input).unwrap_err().to_string();
+        assert!(message.contains("foo"));
+        assert!(message.contains("uppercase"));
+    }
+
+    #[test]
+    fn parse_docker_overrides() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo: {}
+  bar:
+    image: encom:os-13
+    docker_cli: podman
+    docker_repo: registry.example.com/toast
+";
+
+        let toastfile = parse(input).unwrap();
+        let (foo, bar) = (&toastfile.tasks["foo"], &toastfile.tasks["bar"]);
+        assert_eq!(docker_cli(foo, "docker"), "docker");
+        assert_eq!(docker_cli(bar, "docker"), "podman");
+        assert_eq!(docker_repo(foo, "toast"), "toast");
+        assert_eq!(docker_repo(bar, "toast"), "registry.example.com/toast");
+    }
+
+    #[test]
+    fn parse_docker_overrides_empty() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    docker_cli: ''
+    docker_repo: ' '
+";
+
+        let message = parse(input).unwrap_err().to_string();
+        assert!(message.contains("Task `foo` has an empty `docker_cli`."));
+        assert!(message.contains("Task `foo` has an empty `docker_repo`."));
+    }
+
+    #[test]
+    fn parse_retries() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo: {}
+  bar:
+    retries: 2
+";
+
+        let toastfile = parse(input).unwrap();
+        assert_eq!(toastfile.tasks["foo"].retries, 0);
+        assert_eq!(toastfile.tasks["bar"].retries, 2);
+    }
+
+    #[test]
+    fn parse_timeout() {
+        let input = r"
+image: encom:os-12
+timeout: 90
+tasks:
+  foo: {}
+  bar:
+    timeout: 30
+";
+
+        let toastfile = parse(input).unwrap();
+        assert_eq!(
+            timeout(&toastfile, &toastfile.tasks["foo"]),
+            Some(Duration::from_secs(90)),
+        );
+        assert_eq!(
+            timeout(&toastfile, &toastfile.tasks["bar"]),
+            Some(Duration::from_secs(30)),
+        );
+    }
+
+    #[test]
+    fn parse_timeout_none() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo: {}
+";
+
+        let toastfile = parse(input).unwrap();
+        assert_eq!(timeout(&toastfile, &toastfile.tasks["foo"]), None);
+    }
+
+    #[test]
+    fn parse_timeout_zero() {
+        let input = r"
+image: encom:os-12
+timeout: 0
+tasks:
+  foo:
+    timeout: 0
+";
+
+        let message = parse(input).unwrap_err().to_string();
+        assert!(message.contains("Toastfile has a `timeout` of zero."));
+        assert!(message.contains("Task `foo` has a `timeout` of zero."));
+    }
+
+    #[test]
+    fn parse_shared_environment() {
+        let input = r"
+image: encom:os-12
+environment:
+  CI: 'true'
+  CARGO_TERM_COLOR: always
+  RUSTFLAGS: null
+tasks:
+  foo:
+    environment:
+      CARGO_TERM_COLOR: never
+      PROFILE: release
+  bar: {}
+";
+
+        let toastfile = parse(input).unwrap();
+
+        assert_eq!(
+            toastfile.tasks["foo"].environment,
+            HashMap::from([
+                ("CI".to_owned(), Some("true".to_owned())),
+                ("CARGO_TERM_COLOR".to_owned(), Some("never".to_owned())),
+                ("RUSTFLAGS".to_owned(), None),
+                ("PROFILE".to_owned(), Some("release".to_owned())),
+            ]),
+        );
+        assert_eq!(toastfile.tasks["bar"].environment, toastfile.environment);
+    }
+
+    #[test]
+    fn parse_shared_environment_equals() {
+        let input = r"
+image: encom:os-12
+environment:
+  FOO=BAR: null
+tasks:
+  foo: {}
+";
+
+        assert_eq!(
+            parse(input).unwrap_err().to_string(),
+            "Environment variable `FOO=BAR` of the toastfile contains `=`.",
+        );
+    }
+
+    #[test]
+    fn parse_secret_variables() {
+        let input = r"
+image: encom:os-12
+environment:
+  AWS_SECRET_ACCESS_KEY: null
+secret_variables:
+  - AWS_SECRET_ACCESS_KEY
+  - NPM_TOKEN
+tasks:
+  foo:
+    environment:
+      NPM_TOKEN: null
+      DB_PASSWORD: hunter2
+    secret_variables:
+      - DB_PASSWORD
+  bar: {}
+";
+
+        let toastfile = parse(input).unwrap();
+
+        let mut foo_secrets = toastfile.tasks["foo"].secret_variables.clone();
+        foo_secrets.sort();
+        assert_eq!(
+            foo_secrets,
+            vec![
+                "AWS_SECRET_ACCESS_KEY".to_owned(),
+                "DB_PASSWORD".to_owned(),
+                "NPM_TOKEN".to_owned(),
+            ],
+        );
+        assert_eq!(
+            toastfile.tasks["bar"].secret_variables,
+            vec!["AWS_SECRET_ACCESS_KEY".to_owned()],
+        );
+    }
+
+    #[test]
+    fn parse_secret_variables_undeclared() {
+        let input = r"
+image: encom:os-12
+secret_variables:
+  - NPM_TOKEN
+tasks:
+  foo:
+    environment:
+      DB_USER: flynn
+    secret_variables:
+      - DB_PASSWORD
+";
+
+        let message = parse(input).unwrap_err().to_string();
+        assert!(message.contains(
+            "The toastfile has a secret variable `NPM_TOKEN` which no `environment` declares.",
+        ));
+        assert!(message.contains(
+            "Task `foo` has a secret variable `DB_PASSWORD` which isn't in its `environment`.",
+        ));
+    }
+
+    #[test]
+    fn parse_secrets() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    secrets:
+      npm_token:
+        file: .npmrc
+      api_key:
+        env: API_KEY
+";
+
+        let toastfile = parse(input).unwrap();
+
+        // Secrets are allowed even though caching is enabled.
+        assert!(toastfile.tasks["foo"].cache);
+        assert_eq!(
+            toastfile.tasks["foo"].secrets,
+            HashMap::from([
+                (
+                    "npm_token".to_owned(),
+                    Secret::File(PathBuf::from(".npmrc")),
+                ),
+                ("api_key".to_owned(), Secret::Env("API_KEY".to_owned())),
+            ]),
+        );
+    }
+
+    #[test]
+    fn parse_secrets_invalid() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    secrets:
+      ../token:
+        file: token.txt
+      a,b:
+        env: API_KEY
+      api_key:
+        env: API=KEY
+";
+
+        let message = parse(input).unwrap_err().to_string();
+        assert!(message.contains("Task `foo` has a secret with an invalid name: `../token`."));
+        assert!(message.contains("Task `foo` has a secret with an invalid name: `a,b`."));
+        assert!(message.contains(
+            "Secret `api_key` of task `foo` comes from an invalid environment variable: \
+             `API=KEY`.",
+        ));
+    }
+
+    #[test]
+    fn parse_ports() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    cache: false
+    ports:
+      - 3000
+      - 127.0.0.1:8125:8125/udp
+    "
+        .trim();
+
+        assert_eq!(
+            parse(input).unwrap().tasks["foo"].ports,
+            vec![
+                Port {
+                    host_ip: None,
+                    host_ports: None,
+                    container_ports: PortRange {
+                        start: 3000,
+                        end: 3000,
+                    },
+                    protocol: None,
+                    spec: "3000".to_owned(),
+                },
+                Port {
+                    host_ip: Some("127.0.0.1".parse().unwrap()),
+                    host_ports: Some(PortRange {
+                        start: 8125,
+                        end: 8125,
+                    }),
+                    container_ports: PortRange {
+                        start: 8125,
+                        end: 8125,
+                    },
+                    protocol: Some(Protocol::Udp),
+                    spec: "127.0.0.1:8125:8125/udp".to_owned(),
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn parse_ports_invalid() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    cache: false
+    ports:
+      - 8125/quic
+    "
+        .trim();
+
+        assert!(parse(input)
+            .unwrap_err()
+            .to_string()
+            .contains("unknown protocol"));
+    }
+
+    #[test]
+    fn port_round_trip() {
+        for port in [
+            "80",
+            "80/tcp",
+            "3000:80",
+            "3000:80/udp",
+            "8000-8010:9000-9010/sctp",
+            "8000-8010:80",
+            "127.0.0.1:3000:80",
+            "127.0.0.1::80",
+            "[::1]:3000:80/udp",
+            "[::1]::80",
+        ] {
+            assert_eq!(port.parse::<Port>().unwrap().to_string(), port);
+        }
+    }
+
+    #[test]
+    fn port_structure() {
+        assert_eq!(
+            "[::1]::9000-9010/udp".parse::<Port>().unwrap(),
+            Port {
+                host_ip: Some("::1".parse().unwrap()),
+                host_ports: None,
+                container_ports: PortRange {
+                    start: 9000,
+                    end: 9010,
+                },
+                protocol: Some(Protocol::Udp),
+                spec: "[::1]::9000-9010/udp".to_owned(),
+            },
+        );
+    }
+
+    #[test]
+    fn port_spec() {
+        let port = "08080:80".parse::<Port>().unwrap();
+        assert_eq!(port.to_string(), "8080:80");
+        assert_eq!(port.spec, "08080:80");
+    }
+
+    #[test]
+    fn port_invalid() {
+        for port in [
+            "",
+            "0",
+            "65536",
+            "http",
+            "80/",
+            "80/quic",
+            "3000:",
+            ":80",
+            "90-80",
+            "localhost:3000:80",
+            "127.0.0.1:80",
+            "1:2:3:4",
+            "[::1]:80",
+            "8000-8001:9000-9010",
+        ] {
+            assert!(port.parse::<Port>().is_err(), "{port}");
+        }
+    }
+
+    #[test]
+    fn parse_single_problem() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    input_paths:
+      - /bar
+    "
+        .trim();
+
+        let message = parse(input).unwrap_err().to_string();
+        assert!(message.starts_with("Task `foo` has an absolute `input_path`: `/bar`."));
+        assert!(!message.contains("problems"));
+    }
+
+    #[test]
+    fn parse_multiple_problems() {
+        let input = r"
+image: encom:os-12
+location: scratch
+tasks:
+  foo:
+    input_paths:
+      - /bar
+    ports:
+      - 3000:80
+  bar:
+    input_dir_mode: '0789'
+  baz: {}
+    "
+        .trim();
+
+        let message = parse(input).unwrap_err().to_string();
+        let lines = message.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            "The toastfile has 4 problems, including some in tasks `bar` and `foo`:",
+        );
+        assert_eq!(lines.len(), 5);
+        assert!(lines[1].starts_with("- Toastfile has a relative `location`"));
+        assert!(lines[2].starts_with("- Task `bar` has an invalid `input_dir_mode`"));
+        assert!(lines[3].starts_with("- Task `foo` has an absolute `input_path`"));
+        assert!(lines[4].starts_with("- Task `foo` exposes ports"));
+    }
+
+    #[test]
+    fn parse_dependency_forms() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo: {}
+  bar: {}
+  baz:
+    dependencies:
+      - foo
+      - task: bar
+        reason: needs node_modules
+      - task: foo
+    "
+        .trim();
+
+        assert_eq!(
+            parse(input).unwrap().tasks["baz"].dependencies,
+            vec![
+                dependency("foo"),
+                Dependency {
+                    task: "bar".to_owned(),
+                    reason: Some("needs node_modules".to_owned()),
+                },
+                dependency("foo"),
+            ],
+        );
+    }
+
+    #[test]
+    fn parse_dependency_nonexistent() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    dependencies:
+      - task: bar
+        reason: needs node_modules
+    "
+        .trim();
+
+        let result = parse(input);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("bar"));
+    }
+
+    #[test]
+    fn parse_dependency_self() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    dependencies:
+      - bar
+      - foo
+  bar: {}
+    "
+        .trim();
+
+        let message = parse(input).unwrap_err().to_string();
+        assert!(message.contains("`foo` lists itself in its `dependencies`"));
+        assert!(!message.contains("cyclic"));
+    }
+
+    #[test]
+    fn parse_dependency_duplicates() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    dependencies:
+      - bar
+      - baz
+      - bar
+      - task: bar
+        reason: needs node_modules
+  bar: {}
+  baz: {}
+    "
+        .trim();
+
+        let toastfile = parse(input).unwrap();
+        assert_eq!(
+            dependency_warnings(&toastfile),
+            vec!["Task `foo` lists `bar` more than once in its `dependencies`.".to_owned()],
+        );
+    }
+
+    #[test]
+    fn parse_dependency_diamond() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    dependencies: [bar, baz]
+  bar:
+    dependencies: [qux]
+  baz:
+    dependencies: [qux]
+  qux: {}
+    "
+        .trim();
+
+        let toastfile = parse(input).unwrap();
+        assert!(dependency_warnings(&toastfile).is_empty());
+    }
+
+    #[test]
+    fn parse_dependency_nonexistent_deterministic() {
+        let input = r"
+image: encom:os-12
+tasks:
+  zeta:
+    dependencies:
+      - missing_z
+  alpha:
+    dependencies:
+      - missing_a
+  mu:
+    dependencies:
+      - missing_m2
+      - missing_m1
+    "
+        .trim();
+
+        let expected =
+            "The following tasks have invalid dependencies: `alpha` (`missing_a`), `mu` \
+                        (`missing_m2` and `missing_m1`), and `zeta` (`missing_z`).";
+        for _ in 0_i32..10_i32 {
+            assert_eq!(parse(input).unwrap_err().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn parse_dependency_cycles_deterministic() {
+        let input = r"
+image: encom:os-12
+tasks:
+  qux:
+    dependencies:
+      - quux
+  quux:
+    dependencies:
+      - qux
+  foo:
+    dependencies:
+      - bar
+  bar:
+    dependencies:
+      - foo
+    "
+        .trim();
+
+        let expected = "The dependencies are cyclic. `foo` and `bar` depend on each other.";
+        for _ in 0_i32..10_i32 {
+            assert_eq!(parse(input).unwrap_err().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn parse_validated_skips_task_checks() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    dependencies:
+      - bar
+  bar:
+    dependencies:
+      - foo
+    ports:
+      - 3000
+    "
+        .trim();
+
+        assert!(parse(input).is_err());
+        assert!(parse_validated(input).is_ok());
+    }
+
+    #[test]
+    fn parse_validated_same_result() {
+        let input = r"
+image: encom:os-12
+environment:
+  CARGO_TERM_COLOR: always
+tasks:
+  foo:
+    environment:
+      PROFILE: debug
+    input_paths:
+      - src
+    command: make
+  bar:
+    dependencies:
+      - foo
+    command: make test
+    "
+        .trim();
+
+        assert_eq!(parse_validated(input).unwrap(), parse(input).unwrap());
+    }
+
+    #[test]
+    fn parse_validated_still_checks_image() {
+        let input = r"
+image: ''
+tasks: {}
+    "
+        .trim();
+
+        assert!(parse_validated(input).is_err());
+        assert!(parse_validated("tasks: [").is_err());
+    }
+
+    #[test]
+    fn parse_dependency_unknown_field() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo: {}
+  bar:
+    dependencies:
+      - task: foo
+        why: needs node_modules
+    "
+        .trim();
+
+        assert!(parse(input).is_err());
+    }
+
+    #[test]
+    fn check_image_valid() {
+        for image in [
+            "ubuntu",
+            "ubuntu:24.04",
+            "library/ubuntu:latest",
+            "node:20-alpine3.19",
+            "my_org/my__image",
+            "my-org/my---image:V1_RC.2",
+            "ghcr.io/stepchowfun/toast:main",
+            "localhost/encom",
+            "localhost:5000/encom/os:12",
+            "Registry.Example.com:443/encom",
+            "encom@sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+            "encom:os-12@sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+        ] {
+            assert!(check_image(image).is_ok(), "{image}");
+        }
+    }
+
+    #[test]
+    fn check_image_invalid() {
+        for (image, problem) in [
+            ("", "empty"),
+            ("node :20", "whitespace"),
+            ("Ubuntu", "uppercase"),
+            ("encom/OS:12", "uppercase"),
+            ("encom//os", "empty component"),
+            ("encom/", "empty component"),
+            ("-encom", "separator"),
+            ("encom_", "separator"),
+            ("en.-com", "separator"),
+            ("en___com", "separator"),
+            ("en$com", "$"),
+            ("encom:", "tag is empty"),
+            ("encom:.12", "starts with"),
+            ("encom:os+12", "+"),
+            ("localhost:port/encom", "port"),
+            ("-bad.example.com/encom", "registry host"),
+            ("encom@sha256", "algorithm"),
+            ("encom@sha256:abc", "hexadecimal"),
+            ("encom@1sha:0123456789abcdef0123456789abcdef", "algorithm"),
+        ] {
+            let result = check_image(image);
+            assert!(result.is_err(), "{image}");
+            assert!(result.unwrap_err().to_string().contains(problem), "{image}");
+        }
+    }
+
+    #[test]
+    fn check_pinned_images_pinned() {
+        let input = r"
+image: encom:os-12@sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef
+tasks:
+  foo: {}
+  bar:
+    image: encom@sha256:fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543210
+    "
+        .trim();
+
+        assert!(check_pinned_images(&parse(input).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn check_pinned_images_base_image_floating() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo: {}
+    "
+        .trim();
+
+        let result = check_pinned_images(&parse(input).unwrap());
+        let Err(Failure::User(message, _)) = result else {
+            panic!("Expected a user failure.");
+        };
+        assert!(message.contains("The base image `encom:os-12` isn't pinned"));
+        assert!(message.contains("`encom:os-12@sha256:\u{2026}`"));
+    }
+
+    #[test]
+    fn check_pinned_images_task_image_floating() {
+        let input = r"
+image: encom:os-12@sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef
+tasks:
+  foo: {}
+  bar:
+    image: encom:os-13
+    "
+        .trim();
+
+        let result = check_pinned_images(&parse(input).unwrap());
+        let Err(Failure::User(message, _)) = result else {
+            panic!("Expected a user failure.");
+        };
+        assert!(message.contains("The image `encom:os-13` of task `bar` isn't pinned"));
+    }
+
+    // A toastfile which shares task fragments with anchors and merge keys, including merges of mappings
+    // which have merge keys of their own
+    const MERGE_KEYS_TOASTFILE: &str = r"
+image: encom:os-12
+tasks:
+  base: &base
+    description: Set up.
+    environment: &base_environment
+      RUST_LOG: info
+      PROFILE: dev
+    input_paths:
+      - src
+    command: echo base
+  build: &build
+    <<: *base
+    description: Build the program.
+    environment:
+      <<: *base_environment
+      PROFILE: release
+  release:
+    <<: *build
+    dependencies:
+      - build
+    command: echo release
+";
+
+    // The same toastfile as `MERGE_KEYS_TOASTFILE` with the merges expanded by hand
+    const MERGE_KEYS_EXPANDED_TOASTFILE: &str = r"
+image: encom:os-12
+tasks:
+  base:
+    description: Set up.
+    environment:
+      RUST_LOG: info
+      PROFILE: dev
+    input_paths:
+      - src
+    command: echo base
+  build:
+    description: Build the program.
+    environment:
+      RUST_LOG: info
+      PROFILE: release
+    input_paths:
+      - src
+    command: echo base
+  release:
+    description: Build the program.
+    environment:
+      RUST_LOG: info
+      PROFILE: release
+    input_paths:
+      - src
+    dependencies:
+      - build
+    command: echo release
+";
+
+    #[test]
+    fn parse_merge_keys_nested() {
+        assert_eq!(
+            parse(MERGE_KEYS_TOASTFILE).unwrap(),
+            parse(MERGE_KEYS_EXPANDED_TOASTFILE).unwrap(),
+        );
+    }
+
+    #[test]
+    fn parse_merge_keys_list() {
+        // Earlier mappings take precedence over later ones, and explicit entries take precedence
+        // over all of them.
+        let input = r"
+image: encom:os-12
+tasks:
+  foo: &foo
+    user: foo
+    location: /foo
+    command: echo foo
+  bar: &bar
+    user: bar
+    location: /bar
+    cache: false
+    command: echo bar
+  baz:
+    <<: [*foo, *bar]
+    command: echo baz
+    ";
+
+        let expanded = r"
+image: encom:os-12
+tasks:
+  foo:
+    user: foo
+    location: /foo
+    command: echo foo
+  bar:
+    user: bar
+    location: /bar
+    cache: false
+    command: echo bar
+  baz:
+    user: foo
+    location: /foo
+    cache: false
+    command: echo baz
+    ";
+
+        assert_eq!(parse(input).unwrap(), parse(expanded).unwrap());
+    }
+
+    #[test]
+    fn parse_merge_keys_in_list() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo: {}
+  bar: {}
+  baz:
+    dependencies:
+      - &dependency
+        task: foo
+        reason: Generates the sources.
+      - <<: *dependency
+        task: bar
+    ";
+
+        let expanded = r"
+image: encom:os-12
+tasks:
+  foo: {}
+  bar: {}
+  baz:
+    dependencies:
+      - task: foo
+        reason: Generates the sources.
+      - task: bar
+        reason: Generates the sources.
+    ";
+
+        assert_eq!(parse(input).unwrap(), parse(expanded).unwrap());
+    }
+
+    #[test]
+    fn parse_merge_keys_invalid() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    <<: 3
+    command: echo foo
+    ";
+
+        let Err(Failure::User(message, _)) = parse(input) else {
+            panic!("Expected a user failure.");
+        };
+        assert!(message.contains("`tasks.foo.<<`"), "{message}");
+        assert!(message.contains("a number"), "{message}");
+    }
+
+    #[test]
+    fn parse_merge_keys_invalid_list() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo: &foo
+    command: echo foo
+  bar:
+    <<: [*foo, bar]
+    ";
+
+        let Err(Failure::User(message, _)) = parse(input) else {
+            panic!("Expected a user failure.");
+        };
+        assert!(message.contains("`tasks.bar.<<`"), "{message}");
+        assert!(message.contains("a string"), "{message}");
+    }
+
+    #[test]
+    fn parse_merge_keys_unknown_field() {
+        // The merged entries are checked like any others.
+        let input = r"
+image: encom:os-12
+tasks:
+  foo: &foo
+    command: echo foo
+  bar:
+    <<: *foo
+    comand: echo bar
+    ";
+
+        assert!(parse(input).is_err());
+    }
+
+    #[test]
+    fn parse_minimal_task() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo: {}
+    "
+        .trim();
+
+        let mut tasks = BTreeMap::new();
+        tasks.insert(
+            "foo".to_owned(),
+            Task {
+                description: None,
+                dependencies: vec![],
+                cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
+                environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
+                input_paths: vec![],
+                excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
+                output_paths: vec![],
+                output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
+                mount_paths: vec![],
+                mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
+                ports: vec![],
+                gpus: None,
+                image: None,
+                location: None,
+                user: None,
+                command: String::new(),
+                command_file: None,
+                command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
+                extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
+            },
+        );
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            default: None,
+            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
+            tasks,
+        };
+
+        assert_eq!(parse(input).unwrap(), toastfile);
+    }
+
+    #[test]
+    #[allow(clippy::too_many_lines)]
+    fn parse_comprehensive_task() {
+        let input = r"
+image: encom:os-12
+default: bar
+location: /default_location
+user: default_user
+command_prefix: prefix
+shell_applies_prefix: false
+resolve_image_digest: true
+tasks:
+  foo: {}
   bar:
     description: Reticulate splines.
     dependencies:
@@ -740,292 +4348,3880 @@ tasks:
       - ham
       - eggs
     output_paths:
-      - corge
-      - grault
-      - garply
-    output_paths_on_failure:
-      - fnord
-      - smurf
-      - xyzzy
-    mount_paths:
-      - wibble
-      - /wobble
-      - wubble:wabble
-    mount_readonly: true
-    ports:
-      - 3000
-      - 3001
-      - 3002
-    location: /code
-    user: waldo
-    command: flob
-    command_prefix: flob_prefix
-    extra_docker_arguments:
-      - --cpus
-      - '4'
-    "
-        .trim();
+      - corge
+      - grault
+      - garply
+    output_paths_on_failure:
+      - fnord
+      - smurf
+      - xyzzy
+    mount_paths:
+      - wibble
+      - /wobble
+      - wubble:wabble
+    mount_readonly: true
+    ports:
+      - 3000
+      - 3001
+      - 3002
+    location: /code
+    user: waldo
+    command: flob
+    command_prefix: flob_prefix
+    extra_docker_arguments:
+      - --cpus
+      - '4'
+    max_log_lines: 100
+    clean_output_paths: true
+    backup_output_paths: true
+
+    exec_after_start:
+      - ./migrate.sh
+    resolve_output_symlinks: true
+    output_paths_always:
+      - qux.log
+    input_dir_mode: '0755'
+    input_file_mode: '0644'
+    "
+        .trim();
+
+        let mut environment = HashMap::new();
+        environment.insert("SPAM".to_owned(), Some("monty".to_owned()));
+        environment.insert("HAM".to_owned(), None);
+        environment.insert("EGGS".to_owned(), None);
+
+        let mut tasks = BTreeMap::new();
+        tasks.insert(
+            "foo".to_owned(),
+            Task {
+                description: None,
+                dependencies: vec![],
+                cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
+                environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
+                input_paths: vec![],
+                excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
+                output_paths: vec![],
+                output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
+                mount_paths: vec![],
+                mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
+                ports: vec![],
+                gpus: None,
+                image: None,
+                location: None,
+                user: None,
+                command: String::new(),
+                command_file: None,
+                command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
+                extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
+            },
+        );
+        tasks.insert(
+            "bar".to_owned(),
+            Task {
+                description: Some("Reticulate splines.".to_owned()),
+                dependencies: vec![dependency("foo")],
+                cache: false,
+                arch_independent_cache: false,
+                cache_ttl: None,
+                environment,
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
+                input_paths: vec![
+                    UnixPath::new("qux").to_owned(),
+                    UnixPath::new("quux").to_owned(),
+                    UnixPath::new("quuz").to_owned(),
+                ],
+                excluded_input_paths: vec![
+                    UnixPath::new("spam").to_owned(),
+                    UnixPath::new("ham").to_owned(),
+                    UnixPath::new("eggs").to_owned(),
+                ],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: Some("0755".to_owned()),
+                input_file_mode: Some("0644".to_owned()),
+                output_paths: vec![
+                    UnixPath::new("corge").to_owned(),
+                    UnixPath::new("grault").to_owned(),
+                    UnixPath::new("garply").to_owned(),
+                ],
+                output_paths_on_failure: vec![
+                    UnixPath::new("fnord").to_owned(),
+                    UnixPath::new("smurf").to_owned(),
+                    UnixPath::new("xyzzy").to_owned(),
+                ],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec!["qux.log".into()],
+                clean_output_paths: true,
+                backup_output_paths: true,
+                resolve_output_symlinks: true,
+                mount_paths: vec![
+                    MappingPath {
+                        host_path: Path::new("wibble").to_owned(),
+                        container_path: UnixPath::new("wibble").to_owned(),
+                        readonly: None,
+                    },
+                    MappingPath {
+                        host_path: Path::new("/wobble").to_owned(),
+                        container_path: UnixPath::new("/wobble").to_owned(),
+                        readonly: None,
+                    },
+                    MappingPath {
+                        host_path: Path::new("wubble").to_owned(),
+                        container_path: UnixPath::new("wabble").to_owned(),
+                        readonly: None,
+                    },
+                ],
+                mount_readonly: true,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
+                ports: vec![
+                    "3000".parse().unwrap(),
+                    "3001".parse().unwrap(),
+                    "3002".parse().unwrap(),
+                ],
+                gpus: None,
+                image: None,
+                location: Some(UnixPath::new("/code").to_owned()),
+                user: Some("waldo".to_owned()),
+                command: "flob".to_owned(),
+                command_file: None,
+                command_prefix: Some("flob_prefix".to_owned()),
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec!["./migrate.sh".to_owned()],
+                copy_via_exec: false,
+                extra_docker_arguments: vec!["--cpus".to_owned(), "4".to_owned()],
+                max_log_lines: Some(100),
+                timeout: None,
+                retries: 0,
+            },
+        );
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            default: Some("bar".to_owned()),
+            location: UnixPath::new("/default_location").to_owned(),
+            user: "default_user".to_owned(),
+            command_prefix: "prefix".to_owned(),
+            shell_applies_prefix: false,
+            resolve_image_digest: true,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
+            tasks,
+        };
+
+        assert_eq!(parse(input).unwrap(), toastfile);
+    }
+
+    #[test]
+    fn check_dependencies_valid_default() {
+        let mut tasks = BTreeMap::new();
+        tasks.insert(
+            "foo".to_owned(),
+            Task {
+                description: None,
+                dependencies: vec![],
+                cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
+                environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
+                input_paths: vec![],
+                excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
+                output_paths: vec![],
+                output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
+                mount_paths: vec![],
+                mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
+                ports: vec![],
+                gpus: None,
+                image: None,
+                location: None,
+                user: None,
+                command: String::new(),
+                command_file: None,
+                command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
+                extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
+            },
+        );
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            default: Some("foo".to_owned()),
+            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
+            tasks,
+        };
+
+        assert!(check_dependencies(&toastfile).is_ok());
+    }
+
+    #[test]
+    fn check_dependencies_invalid_default() {
+        let mut tasks = BTreeMap::new();
+        tasks.insert(
+            "foo".to_owned(),
+            Task {
+                description: None,
+                dependencies: vec![],
+                cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
+                environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
+                input_paths: vec![],
+                excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
+                output_paths: vec![],
+                output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
+                mount_paths: vec![],
+                mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
+                ports: vec![],
+                gpus: None,
+                image: None,
+                location: None,
+                user: None,
+                command: String::new(),
+                command_file: None,
+                command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
+                extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
+            },
+        );
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            default: Some("bar".to_owned()),
+            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
+            tasks,
+        };
+
+        let result = check_dependencies(&toastfile);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("bar"));
+    }
+
+    #[test]
+    fn check_dependencies_empty() {
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            default: None,
+            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
+            tasks: BTreeMap::new(),
+        };
+
+        assert!(check_dependencies(&toastfile).is_ok());
+    }
+
+    #[test]
+    fn check_dependencies_single() {
+        let mut tasks = BTreeMap::new();
+        tasks.insert(
+            "foo".to_owned(),
+            Task {
+                description: None,
+                dependencies: vec![],
+                cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
+                environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
+                input_paths: vec![],
+                excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
+                output_paths: vec![],
+                output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
+                mount_paths: vec![],
+                mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
+                ports: vec![],
+                gpus: None,
+                image: None,
+                location: None,
+                user: None,
+                command: String::new(),
+                command_file: None,
+                command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
+                extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
+            },
+        );
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            default: None,
+            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
+            tasks,
+        };
+
+        assert!(check_dependencies(&toastfile).is_ok());
+    }
+
+    #[test]
+    #[allow(clippy::too_many_lines)]
+    fn check_task_dependencies_nonempty() {
+        let mut tasks = BTreeMap::new();
+        tasks.insert(
+            "foo".to_owned(),
+            Task {
+                description: None,
+                dependencies: vec![],
+                cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
+                environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
+                input_paths: vec![],
+                excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
+                output_paths: vec![],
+                output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
+                mount_paths: vec![],
+                mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
+                ports: vec![],
+                gpus: None,
+                image: None,
+                location: None,
+                user: None,
+                command: String::new(),
+                command_file: None,
+                command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
+                extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
+            },
+        );
+        tasks.insert(
+            "bar".to_owned(),
+            Task {
+                description: None,
+                dependencies: vec![dependency("foo")],
+                cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
+                environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
+                input_paths: vec![],
+                excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
+                output_paths: vec![],
+                output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
+                mount_paths: vec![],
+                mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
+                ports: vec![],
+                gpus: None,
+                image: None,
+                location: None,
+                user: None,
+                command: String::new(),
+                command_file: None,
+                command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
+                extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
+            },
+        );
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            default: None,
+            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
+            tasks,
+        };
+
+        assert!(check_dependencies(&toastfile).is_ok());
+    }
+
+    #[test]
+    #[allow(clippy::too_many_lines)]
+    fn check_dependencies_nonexistent() {
+        let mut tasks = BTreeMap::new();
+        tasks.insert(
+            "foo".to_owned(),
+            Task {
+                description: None,
+                dependencies: vec![],
+                cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
+                environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
+                input_paths: vec![],
+                excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
+                output_paths: vec![],
+                output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
+                mount_paths: vec![],
+                mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
+                ports: vec![],
+                gpus: None,
+                image: None,
+                location: None,
+                user: None,
+                command: String::new(),
+                command_file: None,
+                command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
+                extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
+            },
+        );
+        tasks.insert(
+            "bar".to_owned(),
+            Task {
+                description: None,
+                dependencies: vec![dependency("foo"), dependency("baz")],
+                cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
+                environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
+                input_paths: vec![],
+                excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
+                output_paths: vec![],
+                output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
+                mount_paths: vec![],
+                mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
+                ports: vec![],
+                gpus: None,
+                image: None,
+                location: None,
+                user: None,
+                command: String::new(),
+                command_file: None,
+                command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
+                extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
+            },
+        );
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            default: None,
+            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
+            tasks,
+        };
+
+        let result = check_dependencies(&toastfile);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("baz"));
+    }
+
+    #[test]
+    fn check_dependencies_self() {
+        let mut tasks = BTreeMap::new();
+        tasks.insert(
+            "foo".to_owned(),
+            Task {
+                description: None,
+                dependencies: vec![dependency("foo")],
+                cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
+                environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
+                input_paths: vec![],
+                excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
+                output_paths: vec![],
+                output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
+                mount_paths: vec![],
+                mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
+                ports: vec![],
+                gpus: None,
+                image: None,
+                location: None,
+                user: None,
+                command: String::new(),
+                command_file: None,
+                command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
+                extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
+            },
+        );
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            default: None,
+            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
+            tasks,
+        };
+
+        // Self-dependencies are reported by `check_task` rather than the cycle detector.
+        assert!(check_dependencies(&toastfile).is_ok());
+        let problems = check_task("foo", &toastfile.tasks["foo"]);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("lists itself"));
+    }
+
+    #[test]
+    #[allow(clippy::too_many_lines)]
+    fn check_dependencies_cycle_2() {
+        let mut tasks = BTreeMap::new();
+        tasks.insert(
+            "foo".to_owned(),
+            Task {
+                description: None,
+                dependencies: vec![dependency("bar")],
+                cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
+                environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
+                input_paths: vec![],
+                excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
+                output_paths: vec![],
+                output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
+                mount_paths: vec![],
+                mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
+                ports: vec![],
+                gpus: None,
+                image: None,
+                location: None,
+                user: None,
+                command: String::new(),
+                command_file: None,
+                command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
+                extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
+            },
+        );
+        tasks.insert(
+            "bar".to_owned(),
+            Task {
+                description: None,
+                dependencies: vec![dependency("foo")],
+                cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
+                environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
+                input_paths: vec![],
+                excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
+                output_paths: vec![],
+                output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
+                mount_paths: vec![],
+                mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
+                ports: vec![],
+                gpus: None,
+                image: None,
+                location: None,
+                user: None,
+                command: String::new(),
+                command_file: None,
+                command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
+                extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
+            },
+        );
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            default: None,
+            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
+            tasks,
+        };
+
+        let result = check_dependencies(&toastfile);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("cyclic"));
+    }
+
+    #[test]
+    #[allow(clippy::too_many_lines)]
+    fn check_dependencies_cycle_3() {
+        let mut tasks = BTreeMap::new();
+        tasks.insert(
+            "foo".to_owned(),
+            Task {
+                description: None,
+                dependencies: vec![dependency("baz")],
+                cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
+                environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
+                input_paths: vec![],
+                excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
+                output_paths: vec![],
+                output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
+                mount_paths: vec![],
+                mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
+                ports: vec![],
+                gpus: None,
+                image: None,
+                location: None,
+                user: None,
+                command: String::new(),
+                command_file: None,
+                command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
+                extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
+            },
+        );
+        tasks.insert(
+            "bar".to_owned(),
+            Task {
+                description: None,
+                dependencies: vec![dependency("foo")],
+                cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
+                environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
+                input_paths: vec![],
+                excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
+                output_paths: vec![],
+                output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
+                mount_paths: vec![],
+                mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
+                ports: vec![],
+                gpus: None,
+                image: None,
+                location: None,
+                user: None,
+                command: String::new(),
+                command_file: None,
+                command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
+                extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
+            },
+        );
+        tasks.insert(
+            "baz".to_owned(),
+            Task {
+                description: None,
+                dependencies: vec![dependency("bar")],
+                cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
+                environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
+                input_paths: vec![],
+                excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
+                output_paths: vec![],
+                output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
+                mount_paths: vec![],
+                mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
+                ports: vec![],
+                gpus: None,
+                image: None,
+                location: None,
+                user: None,
+                command: String::new(),
+                command_file: None,
+                command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
+                extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
+            },
+        );
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            default: None,
+            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
+            tasks,
+        };
+
+        let result = check_dependencies(&toastfile);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("cyclic"));
+    }
+
+    #[test]
+    fn check_task_environment_ok() {
+        let mut environment = HashMap::new();
+        environment.insert("corge".to_owned(), None);
+        environment.insert("grault".to_owned(), None);
+        environment.insert("garply".to_owned(), None);
+
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment,
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        assert!(check_task("foo", &task).is_empty());
+    }
+
+    #[test]
+    fn check_task_environment_equals() {
+        let mut environment = HashMap::new();
+        environment.insert("corge".to_owned(), None);
+        environment.insert("gra=ult".to_owned(), None);
+        environment.insert("garply".to_owned(), None);
+
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment,
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let problems = check_task("foo", &task);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains('='));
+    }
+
+    #[test]
+    fn check_task_paths_ok() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: false,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![UnixPath::new("bar").to_owned()],
+            excluded_input_paths: vec![UnixPath::new("baz").to_owned()],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![UnixPath::new("qux").to_owned()],
+            output_paths_on_failure: vec![UnixPath::new("quux").to_owned()],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![
+                MappingPath {
+                    host_path: Path::new("quuy").to_owned(),
+                    container_path: UnixPath::new("quuz").to_owned(),
+                    readonly: None,
+                },
+                MappingPath {
+                    host_path: Path::new("quuy").to_owned(),
+                    container_path: UnixPath::new("/quuz").to_owned(),
+                    readonly: None,
+                },
+                MappingPath {
+                    host_path: Path::new("quuy").to_owned(),
+                    container_path: UnixPath::new("/quuz").to_owned(),
+                    readonly: None,
+                },
+                MappingPath {
+                    host_path: Path::new("/quuy").to_owned(),
+                    container_path: UnixPath::new("/quuz").to_owned(),
+                    readonly: None,
+                },
+            ],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: Some(UnixPath::new("/corge").to_owned()),
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        assert!(check_task("foo", &task).is_empty());
+    }
+
+    #[test]
+    fn check_task_paths_absolute_input_paths() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![UnixPath::new("/bar").to_owned()],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let problems = check_task("foo", &task);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("/bar"));
+    }
+
+    #[test]
+    fn check_task_paths_valid_globs() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![UnixPath::new("packages/*/package.json").to_owned()],
+            excluded_input_paths: vec![UnixPath::new("**/target").to_owned()],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        assert!(check_task("foo", &task).is_empty());
+    }
+
+    #[test]
+    fn check_task_paths_invalid_globs() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![UnixPath::new("src/[a-").to_owned()],
+            excluded_input_paths: vec![UnixPath::new("***").to_owned()],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let problems = check_task("foo", &task);
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].contains("src/[a-"));
+        assert!(problems[1].contains("***"));
+    }
+
+    #[test]
+    fn check_task_paths_absolute_excluded_input_paths() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![UnixPath::new("/bar").to_owned()],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let problems = check_task("foo", &task);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("/bar"));
+    }
+
+    #[test]
+    fn check_task_paths_absolute_output_paths() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: false,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![UnixPath::new("/var/log/report.xml").to_owned()],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        assert!(check_task("foo", &task).is_empty());
+    }
+
+    #[test]
+    fn check_task_paths_output_paths_root() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    output_paths:
+      - /
+";
+
+        let result = parse(input);
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("without a file name"));
+    }
+
+    #[test]
+    fn check_task_paths_output_paths_escaping() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    output_paths:
+      - bar/../../baz
+";
+
+        let result = parse(input);
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("outside its location"));
+    }
+
+    #[test]
+    fn check_task_paths_output_paths_same_file_name() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    output_paths:
+      - /a/report.xml
+      - /b/report.xml
+";
+
+        let result = parse(input);
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("which would be copied to `report.xml` and `report.xml` on the host"));
+    }
+
+    #[test]
+    fn check_task_paths_output_paths_absolute_and_relative() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    output_paths:
+      - /var/log/foo
+    output_paths_always:
+      - foo/bar
+";
+
+        let result = parse(input);
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("which would be copied to `foo` and `foo/bar` on the host"));
+    }
+
+    #[test]
+    fn check_task_paths_output_paths_distinct_file_names() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    output_paths:
+      - /var/log/foo
+      - /var/log/bar
+      - baz
+";
+
+        assert!(parse(input).is_ok());
+    }
+
+    #[test]
+    fn output_host_path_relative() {
+        assert_eq!(
+            output_host_path(UnixPath::new("out/report.xml")),
+            UnixPath::new("out/report.xml"),
+        );
+    }
+
+    #[test]
+    fn output_host_path_absolute() {
+        assert_eq!(
+            output_host_path(UnixPath::new("/var/log/report.xml")),
+            UnixPath::new("report.xml"),
+        );
+    }
+
+    #[test]
+    fn check_task_paths_absolute_output_paths_on_failure() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: false,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![UnixPath::new("/bar").to_owned()],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let problems = check_task("foo", &task);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("/bar"));
+    }
+
+    #[test]
+    fn check_task_paths_absolute_output_paths_always() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: false,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![UnixPath::new("/bar").to_owned()],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let problems = check_task("foo", &task);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("/bar"));
+    }
+
+    #[test]
+    fn check_task_invalid_input_dir_mode() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: Some("0789".to_owned()),
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let problems = check_task("foo", &task);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("0789"));
+    }
+
+    #[test]
+    fn check_task_invalid_input_file_mode() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: Some("17777".to_owned()),
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let problems = check_task("foo", &task);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("17777"));
+    }
+
+    #[test]
+    fn check_task_valid_input_modes() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: Some("0755".to_owned()),
+            input_file_mode: Some("644".to_owned()),
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        assert!(check_task("foo", &task).is_empty());
+    }
+
+    #[test]
+    fn check_task_valid_gpus() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: Some("device=0,1".to_owned()),
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        assert!(check_task("foo", &task).is_empty());
+    }
+
+    #[test]
+    fn check_task_invalid_gpus() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: Some("device=0,count=2".to_owned()),
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let problems = check_task("foo", &task);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("gpus"));
+    }
+
+    #[test]
+    fn ad_hoc_task_defaults() {
+        let task = ad_hoc_task("make test", vec![], vec![]).unwrap();
+
+        assert_eq!(task.command, "make test");
+        assert!(!task.cache);
+        assert_eq!(task.location, None);
+        assert_eq!(task.user, None);
+        assert_eq!(task.command_prefix, None);
+        assert!(task.input_paths.is_empty());
+        assert!(task.mount_paths.is_empty());
+    }
+
+    #[test]
+    fn ad_hoc_task_inherits_top_level_settings() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+location: /code
+user: flynn
+command_prefix: set -eu
+",
+        )
+        .unwrap();
+        let task = ad_hoc_task("make test", vec![], vec![]).unwrap();
+
+        assert_eq!(location(&toastfile, &task), UnixPath::new("/code"));
+        assert_eq!(user(&toastfile, &task), "flynn");
+        assert_eq!(command(&toastfile, &task), "set -eu\nmake test");
+    }
+
+    #[test]
+    fn ad_hoc_task_paths() {
+        let task = ad_hoc_task(
+            "make test",
+            vec![UnixPath::new(".").to_owned()],
+            vec!["data:/data".parse().unwrap()],
+        )
+        .unwrap();
+
+        assert_eq!(task.input_paths, vec![UnixPath::new(".").to_owned()]);
+        assert_eq!(
+            task.mount_paths,
+            vec![MappingPath {
+                host_path: Path::new("data").to_owned(),
+                container_path: UnixPath::new("/data").to_owned(),
+                readonly: None,
+            }],
+        );
+    }
+
+    #[test]
+    fn ad_hoc_task_absolute_input_path() {
+        let result = ad_hoc_task("make test", vec![UnixPath::new("/etc").to_owned()], vec![]);
+
+        assert!(result.unwrap_err().to_string().contains("absolute"));
+    }
+
+    #[test]
+    fn check_task_valid_cache_ttl() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: Some("1d12h".to_owned()),
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        assert!(check_task("foo", &task).is_empty());
+        assert_eq!(cache_ttl(&task), Some(Duration::from_hours(36)));
+    }
+
+    #[test]
+    fn check_task_invalid_cache_ttl() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: Some("7 days".to_owned()),
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let problems = check_task("foo", &task);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("cache_ttl"));
+    }
+
+    #[test]
+    fn parse_gpus_valid() {
+        for (value, expected) in [
+            ("all", "all"),
+            (" 2 ", "2"),
+            ("count=all", "count=all"),
+            ("device=0", "device=0"),
+            ("device=0,1", "\"device=0,1\""),
+            ("\"device=0,1\"", "\"device=0,1\""),
+            (
+                "device=GPU-3a23c669-1f69-c64e-cf85-44e9b07e7a2a",
+                "device=GPU-3a23c669-1f69-c64e-cf85-44e9b07e7a2a",
+            ),
+            (
+                "device=0,1,capabilities=compute,utility",
+                "\"device=0,1\",\"capabilities=compute,utility\"",
+            ),
+            ("count=2,driver=nvidia", "count=2,driver=nvidia"),
+        ] {
+            assert_eq!(parse_gpus(value).unwrap(), expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn parse_gpus_invalid() {
+        for (value, problem) in [
+            ("", "empty field"),
+            ("0", "key=value"),
+            ("some", "key=value"),
+            ("device=0,,1", "empty field"),
+            ("\"device=0,1", "quote"),
+            ("devices=0", "not a known field"),
+            ("device=0,device=1", "more than once"),
+            ("count=2,1", "key=value"),
+            ("count=0", "not a valid value"),
+            ("count=two", "not a valid value"),
+            ("device=0 1", "not a valid value"),
+            ("driver=", "not a valid value"),
+            ("count=1,device=0", "both"),
+        ] {
+            let result = parse_gpus(value);
+            assert!(result.is_err(), "{value}");
+            assert!(result.unwrap_err().contains(problem), "{value}");
+        }
+    }
+
+    #[test]
+    fn check_task_output_paths_on_failure_identical() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: false,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![UnixPath::new("bar").to_owned()],
+            output_paths_on_failure: vec![UnixPath::new("./bar").to_owned()],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let problems = check_task("foo", &task);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("depending on whether the task succeeds"));
+    }
+
+    #[test]
+    fn check_task_output_paths_on_failure_prefix() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: false,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![UnixPath::new("bar/baz.txt").to_owned()],
+            output_paths_on_failure: vec![UnixPath::new("bar").to_owned()],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let problems = check_task("foo", &task);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("bar/baz.txt"));
+    }
+
+    #[test]
+    fn check_task_output_paths_always_overlap() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: false,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![UnixPath::new("bar").to_owned()],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![UnixPath::new("bar/qux").to_owned()],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let problems = check_task("foo", &task);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("copied twice"));
+    }
+
+    #[test]
+    fn check_task_output_paths_disjoint() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: false,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![UnixPath::new("bar").to_owned()],
+            output_paths_on_failure: vec![UnixPath::new("barbaz").to_owned()],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![UnixPath::new("qux").to_owned()],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        assert!(check_task("foo", &task).is_empty());
+    }
+
+    #[test]
+    fn check_task_paths_mount_paths_comma() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![MappingPath {
+                host_path: Path::new("bar,baz").to_owned(),
+                container_path: UnixPath::new("bar,baz").to_owned(),
+                readonly: None,
+            }],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        // The task also has `mount_paths` without disabling caching, which is reported too.
+        let problems = check_task("foo", &task);
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].contains("bar,baz"));
+        assert!(problems[1].contains("caching"));
+    }
+
+    #[test]
+    fn check_task_paths_relative_location() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: Some(UnixPath::new("code").to_owned()),
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let problems = check_task("foo", &task);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("code"));
+    }
+
+    #[test]
+    fn check_task_copy_via_exec_with_exec_after_start() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec!["./migrate.sh".to_owned()],
+            copy_via_exec: true,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let problems = check_task("foo", &task);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("exec_after_start"));
+    }
+
+    #[test]
+    fn check_task_copy_via_exec_without_exec_after_start() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: true,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        assert!(check_task("foo", &task).is_empty());
+    }
+
+    #[test]
+    fn check_task_backup_output_paths_without_clean() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: true,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let problems = check_task("foo", &task);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("clean_output_paths"));
+    }
+
+    #[test]
+    fn check_task_backup_output_paths_with_clean() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: true,
+            backup_output_paths: true,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        assert!(check_task("foo", &task).is_empty());
+    }
+
+    #[test]
+    fn check_task_caching_enabled_with_mount_paths() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![MappingPath {
+                host_path: Path::new("bar").to_owned(),
+                container_path: UnixPath::new("bar").to_owned(),
+                readonly: None,
+            }],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let problems = check_task("foo", &task);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("mount_paths"));
+    }
+
+    #[test]
+    fn check_task_caching_disabled_with_mount_paths() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: false,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![MappingPath {
+                host_path: Path::new("bar").to_owned(),
+                container_path: UnixPath::new("bar").to_owned(),
+                readonly: None,
+            }],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec!["3000:80".parse().unwrap()],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        assert!(check_task("foo", &task).is_empty());
+    }
+
+    #[test]
+    fn check_task_caching_enabled_with_ports() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec!["3000:80".parse().unwrap()],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let problems = check_task("foo", &task);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("caching"));
+    }
+
+    #[test]
+    fn check_task_caching_disabled_with_ports() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: false,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec!["3000:80".parse().unwrap()],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        assert!(check_task("foo", &task).is_empty());
+    }
+
+    #[test]
+    fn check_task_caching_enabled_with_extra_docker_arguments() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec!["--cpus".to_owned(), "4".to_owned()],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let problems = check_task("foo", &task);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("caching"));
+    }
+
+    #[test]
+    fn check_task_caching_disabled_with_extra_docker_arguments() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: false,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec!["--cpus".to_owned(), "4".to_owned()],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        assert!(check_task("foo", &task).is_empty());
+    }
+
+    #[test]
+    fn parse_read_only_rootfs_with_caching() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    read_only_rootfs: true
+    command: make
+    ";
+
+        let Err(Failure::User(message, _)) = parse(input) else {
+            panic!("Expected a user failure.");
+        };
+        assert!(message.contains("read_only_rootfs: true"), "{message}");
+        assert!(message.contains("cache: false"), "{message}");
+    }
 
-        let mut environment = HashMap::new();
-        environment.insert("SPAM".to_owned(), Some("monty".to_owned()));
-        environment.insert("HAM".to_owned(), None);
-        environment.insert("EGGS".to_owned(), None);
+    #[test]
+    fn parse_read_only_rootfs_without_caching() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    cache: false
+    read_only_rootfs: true
+    output_paths:
+      - dist
+    command: make
+    ";
 
-        let mut tasks = HashMap::new();
-        tasks.insert(
-            "foo".to_owned(),
-            Task {
-                description: None,
-                dependencies: vec![],
-                cache: true,
-                environment: HashMap::new(),
-                input_paths: vec![],
-                excluded_input_paths: vec![],
-                output_paths: vec![],
-                output_paths_on_failure: vec![],
-                mount_paths: vec![],
-                mount_readonly: false,
-                ports: vec![],
-                location: None,
-                user: None,
-                command: String::new(),
-                command_prefix: None,
-                extra_docker_arguments: vec![],
-            },
+        assert!(parse(input).unwrap().tasks["foo"].read_only_rootfs);
+    }
+
+    #[test]
+    fn content_hash_pure() {
+        assert_eq!(content_hash("image: foo"), content_hash("image: foo"));
+    }
+
+    #[test]
+    fn content_hash_not_constant() {
+        assert_ne!(content_hash("image: foo"), content_hash("image: bar"));
+    }
+
+    #[test]
+    fn check_content_hash_unchanged() {
+        let hash = content_hash("image: foo");
+
+        assert!(check_content_hash(&hash, &hash).is_ok());
+    }
+
+    #[test]
+    fn check_content_hash_changed() {
+        let result = check_content_hash(&content_hash("image: foo"), &content_hash("image: bar"));
+
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("changed since the referenced run"));
+    }
+
+    #[test]
+    fn environment_empty() {
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: HashMap::new(),
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        assert_eq!(environment(&task, &HashMap::new()), Ok(HashMap::new()));
+    }
+
+    #[test]
+    fn environment_default_overridden() {
+        // NOTE: We add an index to the test arg ("foo1", "foo2", ...) to avoid having parallel
+        // tests clobbering environment variables used by other threads.
+        let mut env_map = HashMap::new();
+        env_map.insert("foo1".to_owned(), Some("bar".to_owned()));
+
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: env_map,
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let mut expected = HashMap::new();
+        expected.insert("foo1".to_owned(), "baz".to_owned());
+
+        env::set_var("foo1", "baz");
+        assert_eq!(env::var("foo1"), Ok("baz".to_owned()));
+        assert_eq!(environment(&task, &HashMap::new()), Ok(expected));
+    }
+
+    #[test]
+    fn environment_default_not_overridden() {
+        // NOTE: We add an index to the test arg ("foo1", "foo2", ...) to avoid having parallel
+        // tests clobbering environment variables used by other threads.
+        let mut env_map = HashMap::new();
+        env_map.insert("foo2".to_owned(), Some("bar".to_owned()));
+
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: env_map,
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        let mut expected = HashMap::new();
+        expected.insert("foo2".to_owned(), "bar".to_owned());
+
+        env::remove_var("foo2");
+        assert!(env::var("foo2").is_err());
+        assert_eq!(environment(&task, &HashMap::new()), Ok(expected));
+    }
+
+    #[test]
+    fn environment_missing() {
+        // NOTE: We add an index to the test arg ("foo1", "foo2", ...) to avoid having parallel
+        // tests clobbering environment variables used by other threads.
+        let mut env_map = HashMap::new();
+        env_map.insert("foo3".to_owned(), None);
+
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: env_map,
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        env::remove_var("foo3");
+        assert!(env::var("foo3").is_err());
+        let result = environment(&task, &HashMap::new());
+        assert_eq!(result, Err(EnvironmentError::Missing(vec!["foo3"])));
+    }
+
+    #[test]
+    fn environment_missing_sorted() {
+        // NOTE: We add an index to the test args to avoid having parallel tests clobbering
+        // environment variables used by other threads.
+        let mut env_map = HashMap::new();
+        for variable in ["foo23", "foo21", "foo24", "foo22"] {
+            env_map.insert(variable.to_owned(), None);
+            env::remove_var(variable);
+        }
+
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: env_map,
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        assert_eq!(
+            environment(&task, &HashMap::new()),
+            Err(EnvironmentError::Missing(vec![
+                "foo21", "foo22", "foo23", "foo24",
+            ])),
         );
-        tasks.insert(
-            "bar".to_owned(),
-            Task {
-                description: Some("Reticulate splines.".to_owned()),
-                dependencies: vec!["foo".to_owned()],
-                cache: false,
-                environment,
-                input_paths: vec![
-                    UnixPath::new("qux").to_owned(),
-                    UnixPath::new("quux").to_owned(),
-                    UnixPath::new("quuz").to_owned(),
-                ],
-                excluded_input_paths: vec![
-                    UnixPath::new("spam").to_owned(),
-                    UnixPath::new("ham").to_owned(),
-                    UnixPath::new("eggs").to_owned(),
-                ],
-                output_paths: vec![
-                    UnixPath::new("corge").to_owned(),
-                    UnixPath::new("grault").to_owned(),
-                    UnixPath::new("garply").to_owned(),
-                ],
-                output_paths_on_failure: vec![
-                    UnixPath::new("fnord").to_owned(),
-                    UnixPath::new("smurf").to_owned(),
-                    UnixPath::new("xyzzy").to_owned(),
-                ],
-                mount_paths: vec![
-                    MappingPath {
-                        host_path: Path::new("wibble").to_owned(),
-                        container_path: UnixPath::new("wibble").to_owned(),
-                    },
-                    MappingPath {
-                        host_path: Path::new("/wobble").to_owned(),
-                        container_path: UnixPath::new("/wobble").to_owned(),
-                    },
-                    MappingPath {
-                        host_path: Path::new("wubble").to_owned(),
-                        container_path: UnixPath::new("wabble").to_owned(),
-                    },
-                ],
-                mount_readonly: true,
-                ports: vec!["3000".to_owned(), "3001".to_owned(), "3002".to_owned()],
-                location: Some(UnixPath::new("/code").to_owned()),
-                user: Some("waldo".to_owned()),
-                command: "flob".to_owned(),
-                command_prefix: Some("flob_prefix".to_owned()),
-                extra_docker_arguments: vec!["--cpus".to_owned(), "4".to_owned()],
-            },
+    }
+
+    #[test]
+    fn interpolate_escapes() {
+        assert_eq!(
+            interpolate("$${foo} costs $5", &mut |_| panic!("Unexpected lookup.")),
+            Ok("${foo} costs $5".to_owned()),
         );
+    }
 
-        let toastfile = Toastfile {
-            image: "encom:os-12".to_owned(),
-            default: Some("bar".to_owned()),
-            location: UnixPath::new("/default_location").to_owned(),
-            user: "default_user".to_owned(),
-            command_prefix: "prefix".to_owned(),
-            tasks,
+    #[test]
+    fn interpolate_unterminated() {
+        assert!(interpolate("${foo", &mut |_| Ok(String::new())).is_err());
+    }
+
+    #[test]
+    fn environment_interpolation_chain() {
+        // NOTE: We add an index to the test arg ("foo1", "foo2", ...) to avoid having parallel
+        // tests clobbering environment variables used by other threads.
+        let mut env_map = HashMap::new();
+        env_map.insert("foo4".to_owned(), Some("${foo5}/bin".to_owned()));
+        env_map.insert("foo5".to_owned(), Some("${foo6}/.local".to_owned()));
+        env_map.insert("foo6".to_owned(), None);
+
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: env_map,
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         };
 
-        assert_eq!(parse(input).unwrap(), toastfile);
+        let mut expected = HashMap::new();
+        expected.insert("foo4".to_owned(), "/home/flynn/.local/bin".to_owned());
+        expected.insert("foo5".to_owned(), "/home/flynn/.local".to_owned());
+        expected.insert("foo6".to_owned(), "/home/flynn".to_owned());
+
+        env::remove_var("foo4");
+        env::remove_var("foo5");
+        env::set_var("foo6", "/home/flynn");
+        assert_eq!(environment(&task, &HashMap::new()), Ok(expected));
     }
 
     #[test]
-    fn check_dependencies_valid_default() {
-        let mut tasks = HashMap::new();
-        tasks.insert(
-            "foo".to_owned(),
-            Task {
-                description: None,
-                dependencies: vec![],
-                cache: true,
-                environment: HashMap::new(),
-                input_paths: vec![],
-                excluded_input_paths: vec![],
-                output_paths: vec![],
-                output_paths_on_failure: vec![],
-                mount_paths: vec![],
-                mount_readonly: false,
-                ports: vec![],
-                location: None,
-                user: None,
-                command: String::new(),
-                command_prefix: None,
-                extra_docker_arguments: vec![],
-            },
-        );
+    fn environment_overrides() {
+        // NOTE: We add an index to the test arg ("foo1", "foo2", ...) to avoid having parallel
+        // tests clobbering environment variables used by other threads.
+        let mut env_map = HashMap::new();
+        env_map.insert("foo25".to_owned(), Some("debug".to_owned()));
+        env_map.insert("foo26".to_owned(), None);
 
-        let toastfile = Toastfile {
-            image: "encom:os-12".to_owned(),
-            default: Some("foo".to_owned()),
-            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
-            user: DEFAULT_USER.to_owned(),
-            command_prefix: String::new(),
-            tasks,
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: env_map,
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         };
 
-        assert!(check_dependencies(&toastfile).is_ok());
+        let mut overrides = HashMap::new();
+        overrides.insert("foo25".to_owned(), "release".to_owned());
+        overrides.insert("foo26".to_owned(), String::new());
+
+        let mut expected = HashMap::new();
+        expected.insert("foo25".to_owned(), "release".to_owned());
+        expected.insert("foo26".to_owned(), String::new());
+
+        env::set_var("foo25", "profile");
+        env::set_var("foo26", "qux");
+        assert_eq!(environment(&task, &overrides), Ok(expected));
     }
 
     #[test]
-    fn check_dependencies_invalid_default() {
-        let mut tasks = HashMap::new();
-        tasks.insert(
-            "foo".to_owned(),
-            Task {
-                description: None,
-                dependencies: vec![],
-                cache: true,
-                environment: HashMap::new(),
-                input_paths: vec![],
-                excluded_input_paths: vec![],
-                output_paths: vec![],
-                output_paths_on_failure: vec![],
-                mount_paths: vec![],
-                mount_readonly: false,
-                ports: vec![],
-                location: None,
-                user: None,
-                command: String::new(),
-                command_prefix: None,
-                extra_docker_arguments: vec![],
-            },
-        );
+    fn environment_overrides_interpolation() {
+        // NOTE: We add an index to the test arg ("foo1", "foo2", ...) to avoid having parallel
+        // tests clobbering environment variables used by other threads.
+        let mut env_map = HashMap::new();
+        env_map.insert("foo27".to_owned(), Some("target/${foo28}".to_owned()));
 
-        let toastfile = Toastfile {
-            image: "encom:os-12".to_owned(),
-            default: Some("bar".to_owned()),
-            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
-            user: DEFAULT_USER.to_owned(),
-            command_prefix: String::new(),
-            tasks,
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: env_map,
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         };
 
-        let result = check_dependencies(&toastfile);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("bar"));
+        let mut overrides = HashMap::new();
+        overrides.insert("foo28".to_owned(), "release".to_owned());
+
+        let mut expected = HashMap::new();
+        expected.insert("foo27".to_owned(), "target/release".to_owned());
+
+        env::set_var("foo28", "debug");
+        assert_eq!(environment(&task, &overrides), Ok(expected));
     }
 
     #[test]
-    fn check_dependencies_empty() {
-        let toastfile = Toastfile {
-            image: "encom:os-12".to_owned(),
-            default: None,
-            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
-            user: DEFAULT_USER.to_owned(),
-            command_prefix: String::new(),
-            tasks: HashMap::new(),
+    fn environment_interpolation_host_value_literal() {
+        // NOTE: We add an index to the test arg ("foo1", "foo2", ...) to avoid having parallel
+        // tests clobbering environment variables used by other threads.
+        let mut env_map = HashMap::new();
+        env_map.insert("foo7".to_owned(), Some("${foo8}".to_owned()));
+
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: env_map,
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         };
 
-        assert!(check_dependencies(&toastfile).is_ok());
+        let mut expected = HashMap::new();
+        expected.insert("foo7".to_owned(), "${foo8}".to_owned());
+
+        env::set_var("foo7", "${foo8}");
+        env::remove_var("foo8");
+        assert_eq!(environment(&task, &HashMap::new()), Ok(expected));
     }
 
     #[test]
-    fn check_dependencies_single() {
-        let mut tasks = HashMap::new();
-        tasks.insert(
-            "foo".to_owned(),
-            Task {
-                description: None,
-                dependencies: vec![],
-                cache: true,
-                environment: HashMap::new(),
-                input_paths: vec![],
-                excluded_input_paths: vec![],
-                output_paths: vec![],
-                output_paths_on_failure: vec![],
-                mount_paths: vec![],
-                mount_readonly: false,
-                ports: vec![],
-                location: None,
-                user: None,
-                command: String::new(),
-                command_prefix: None,
-                extra_docker_arguments: vec![],
-            },
-        );
+    fn environment_interpolation_cycle() {
+        // NOTE: We add an index to the test arg ("foo1", "foo2", ...) to avoid having parallel
+        // tests clobbering environment variables used by other threads.
+        let mut env_map = HashMap::new();
+        env_map.insert("foo9".to_owned(), Some("${foo10}".to_owned()));
+        env_map.insert("foo10".to_owned(), Some("x${foo9}".to_owned()));
 
-        let toastfile = Toastfile {
-            image: "encom:os-12".to_owned(),
-            default: None,
-            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
-            user: DEFAULT_USER.to_owned(),
-            command_prefix: String::new(),
-            tasks,
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: env_map,
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
         };
 
-        assert!(check_dependencies(&toastfile).is_ok());
+        env::remove_var("foo9");
+        env::remove_var("foo10");
+        let Err(EnvironmentError::Interpolation(reason)) = environment(&task, &HashMap::new())
+        else {
+            panic!("Expected an interpolation error.");
+        };
+        assert!(reason.contains("refer to each other"));
+        assert!(reason.contains("foo10"));
+        assert!(reason.contains("foo9"));
     }
 
     #[test]
-    fn check_task_dependencies_nonempty() {
-        let mut tasks = HashMap::new();
-        tasks.insert(
-            "foo".to_owned(),
-            Task {
-                description: None,
-                dependencies: vec![],
-                cache: true,
-                environment: HashMap::new(),
-                input_paths: vec![],
-                excluded_input_paths: vec![],
-                output_paths: vec![],
-                output_paths_on_failure: vec![],
-                mount_paths: vec![],
-                mount_readonly: false,
-                ports: vec![],
-                location: None,
-                user: None,
-                command: String::new(),
-                command_prefix: None,
-                extra_docker_arguments: vec![],
-            },
-        );
+    fn environment_interpolation_undefined() {
+        // NOTE: We add an index to the test arg ("foo1", "foo2", ...) to avoid having parallel
+        // tests clobbering environment variables used by other threads.
+        let mut env_map = HashMap::new();
+        env_map.insert("foo11".to_owned(), Some("${foo12}/bin".to_owned()));
+
+        let task = Task {
+            description: None,
+            dependencies: vec![],
+            cache: true,
+            arch_independent_cache: false,
+            cache_ttl: None,
+            environment: env_map,
+            env_file: None,
+            env_file_values: HashMap::new(),
+            secret_variables: vec![],
+            input_paths: vec![],
+            excluded_input_paths: vec![],
+            allow_empty_globs: false,
+            respect_gitignore: false,
+            input_dir_mode: None,
+            input_file_mode: None,
+            output_paths: vec![],
+            output_paths_on_failure: vec![],
+            extract_failure_outputs_on_interrupt: false,
+            output_paths_always: vec![],
+            clean_output_paths: false,
+            backup_output_paths: false,
+            resolve_output_symlinks: false,
+            mount_paths: vec![],
+            mount_readonly: false,
+            volumes: vec![],
+            allow_shared_mounts: false,
+            secrets: HashMap::new(),
+            read_only_rootfs: false,
+            ports: vec![],
+            gpus: None,
+            image: None,
+            location: None,
+            user: None,
+            command: String::new(),
+            command_file: None,
+            command_prefix: None,
+            docker_cli: None,
+            docker_repo: None,
+            exec_after_start: vec![],
+            copy_via_exec: false,
+            extra_docker_arguments: vec![],
+            max_log_lines: None,
+            timeout: None,
+            retries: 0,
+        };
+
+        env::remove_var("foo11");
+        env::remove_var("foo12");
+        let Err(EnvironmentError::Interpolation(reason)) = environment(&task, &HashMap::new())
+        else {
+            panic!("Expected an interpolation error.");
+        };
+        assert!(reason.contains("foo11"));
+        assert!(reason.contains("foo12"));
+    }
+
+    #[test]
+    fn location_default() {
+        let mut tasks = BTreeMap::new();
         tasks.insert(
-            "bar".to_owned(),
+            "foo".to_owned(),
             Task {
                 description: None,
-                dependencies: vec!["foo".to_owned()],
+                dependencies: vec![],
                 cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
                 environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
                 input_paths: vec![],
                 excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
                 mount_paths: vec![],
                 mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
                 ports: vec![],
+                gpus: None,
+                image: None,
                 location: None,
                 user: None,
                 command: String::new(),
+                command_file: None,
                 command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
                 extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
             },
         );
 
@@ -1035,55 +8231,145 @@ tasks:
             location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
             command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
             tasks,
         };
 
-        assert!(check_dependencies(&toastfile).is_ok());
+        assert_eq!(
+            location(&toastfile, &toastfile.tasks["foo"]),
+            UnixPath::new(DEFAULT_LOCATION),
+        );
     }
 
     #[test]
-    fn check_dependencies_nonexistent() {
-        let mut tasks = HashMap::new();
+    fn location_override() {
+        let mut tasks = BTreeMap::new();
         tasks.insert(
             "foo".to_owned(),
             Task {
                 description: None,
                 dependencies: vec![],
                 cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
                 environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
                 input_paths: vec![],
                 excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
                 mount_paths: vec![],
                 mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
                 ports: vec![],
-                location: None,
+                gpus: None,
+                image: None,
+                location: Some(UnixPath::new("/bar").to_owned()),
                 user: None,
                 command: String::new(),
+                command_file: None,
                 command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
                 extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
             },
         );
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            default: None,
+            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
+            tasks,
+        };
+
+        assert_eq!(
+            location(&toastfile, &toastfile.tasks["foo"]),
+            UnixPath::new("/bar"),
+        );
+    }
+
+    #[test]
+    fn user_default() {
+        let mut tasks = BTreeMap::new();
         tasks.insert(
-            "bar".to_owned(),
+            "foo".to_owned(),
             Task {
                 description: None,
-                dependencies: vec!["foo".to_owned(), "baz".to_owned()],
+                dependencies: vec![],
                 cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
                 environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
                 input_paths: vec![],
                 excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
                 mount_paths: vec![],
                 mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
                 ports: vec![],
+                gpus: None,
+                image: None,
                 location: None,
                 user: None,
                 command: String::new(),
+                command_file: None,
                 command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
                 extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
             },
         );
 
@@ -1093,36 +8379,71 @@ tasks:
             location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
             command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
             tasks,
         };
 
-        let result = check_dependencies(&toastfile);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("baz"));
+        assert_eq!(
+            user(&toastfile, &toastfile.tasks["foo"]),
+            DEFAULT_USER.to_owned(),
+        );
     }
 
     #[test]
-    fn check_dependencies_cycle_1() {
-        let mut tasks = HashMap::new();
+    fn user_override() {
+        let mut tasks = BTreeMap::new();
         tasks.insert(
             "foo".to_owned(),
             Task {
                 description: None,
-                dependencies: vec!["foo".to_owned()],
+                dependencies: vec![],
                 cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
                 environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
                 input_paths: vec![],
                 excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
                 mount_paths: vec![],
                 mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
                 ports: vec![],
+                gpus: None,
+                image: None,
                 location: None,
-                user: None,
+                user: Some("bar".to_owned()),
                 command: String::new(),
+                command_file: None,
                 command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
                 extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
             },
         );
 
@@ -1132,57 +8453,146 @@ tasks:
             location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
             command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
             tasks,
         };
 
-        let result = check_dependencies(&toastfile);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("cyclic"));
+        assert_eq!(user(&toastfile, &toastfile.tasks["foo"]), "bar");
     }
 
     #[test]
-    fn check_dependencies_cycle_2() {
-        let mut tasks = HashMap::new();
+    fn command_default_prefix_default() {
+        let mut tasks = BTreeMap::new();
         tasks.insert(
             "foo".to_owned(),
             Task {
                 description: None,
-                dependencies: vec!["bar".to_owned()],
+                dependencies: vec![],
                 cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
                 environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
                 input_paths: vec![],
                 excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
                 mount_paths: vec![],
                 mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
                 ports: vec![],
+                gpus: None,
+                image: None,
                 location: None,
                 user: None,
                 command: String::new(),
+                command_file: None,
                 command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
                 extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
             },
         );
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            default: None,
+            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            command_prefix: "set -euxo pipefail".to_owned(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
+            tasks,
+        };
+
+        assert_eq!(
+            command(&toastfile, &toastfile.tasks["foo"]),
+            "set -euxo pipefail".to_owned(),
+        );
+        assert_eq!(
+            command_prefix(&toastfile, &toastfile.tasks["foo"]),
+            "set -euxo pipefail",
+        );
+    }
+
+    #[test]
+    fn command_override_prefix_default() {
+        let mut tasks = BTreeMap::new();
         tasks.insert(
-            "bar".to_owned(),
+            "foo".to_owned(),
             Task {
                 description: None,
-                dependencies: vec!["foo".to_owned()],
+                dependencies: vec![],
                 cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
                 environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
                 input_paths: vec![],
                 excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
                 mount_paths: vec![],
                 mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
                 ports: vec![],
+                gpus: None,
+                image: None,
                 location: None,
                 user: None,
-                command: String::new(),
+                command: "echo hello".to_owned(),
+                command_file: None,
                 command_prefix: None,
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
                 extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
             },
         );
 
@@ -1192,950 +8602,1496 @@ tasks:
             location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
             user: DEFAULT_USER.to_owned(),
             command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
             tasks,
         };
 
-        let result = check_dependencies(&toastfile);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("cyclic"));
+        assert_eq!(
+            command(&toastfile, &toastfile.tasks["foo"]),
+            "echo hello".to_owned(),
+        );
     }
 
     #[test]
-    fn check_dependencies_cycle_3() {
-        let mut tasks = HashMap::new();
+    fn command_default_prefix_override() {
+        let mut tasks = BTreeMap::new();
         tasks.insert(
             "foo".to_owned(),
             Task {
                 description: None,
-                dependencies: vec!["baz".to_owned()],
+                dependencies: vec![],
                 cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
                 environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
                 input_paths: vec![],
                 excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
                 mount_paths: vec![],
                 mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
                 ports: vec![],
+                gpus: None,
+                image: None,
                 location: None,
                 user: None,
                 command: String::new(),
-                command_prefix: None,
+                command_file: None,
+                command_prefix: Some("set -euxo pipefail".to_owned()),
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
                 extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
             },
         );
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            default: None,
+            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
+            tasks,
+        };
+
+        assert_eq!(
+            command(&toastfile, &toastfile.tasks["foo"]),
+            "set -euxo pipefail".to_owned(),
+        );
+    }
+
+    #[test]
+    fn command_override_prefix_override() {
+        let mut tasks = BTreeMap::new();
         tasks.insert(
-            "bar".to_owned(),
+            "foo".to_owned(),
             Task {
                 description: None,
-                dependencies: vec!["foo".to_owned()],
+                dependencies: vec![],
                 cache: true,
+                arch_independent_cache: false,
+                cache_ttl: None,
                 environment: HashMap::new(),
+                env_file: None,
+                env_file_values: HashMap::new(),
+                secret_variables: vec![],
                 input_paths: vec![],
                 excluded_input_paths: vec![],
+                allow_empty_globs: false,
+                respect_gitignore: false,
+                input_dir_mode: None,
+                input_file_mode: None,
                 output_paths: vec![],
                 output_paths_on_failure: vec![],
+                extract_failure_outputs_on_interrupt: false,
+                output_paths_always: vec![],
+                clean_output_paths: false,
+                backup_output_paths: false,
+                resolve_output_symlinks: false,
                 mount_paths: vec![],
                 mount_readonly: false,
+                volumes: vec![],
+                allow_shared_mounts: false,
+                secrets: HashMap::new(),
+                read_only_rootfs: false,
                 ports: vec![],
+                gpus: None,
+                image: None,
                 location: None,
                 user: None,
-                command: String::new(),
-                command_prefix: None,
+                command: "echo hello".to_owned(),
+                command_file: None,
+                command_prefix: Some("set -euxo pipefail".to_owned()),
+                docker_cli: None,
+                docker_repo: None,
+                exec_after_start: vec![],
+                copy_via_exec: false,
                 extra_docker_arguments: vec![],
+                max_log_lines: None,
+                timeout: None,
+                retries: 0,
+            },
+        );
+
+        let toastfile = Toastfile {
+            image: "encom:os-12".to_owned(),
+            default: None,
+            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
+            user: DEFAULT_USER.to_owned(),
+            command_prefix: String::new(),
+            shell_applies_prefix: true,
+            resolve_image_digest: false,
+            timeout: None,
+            environment: HashMap::new(),
+            env_file: None,
+            secret_variables: vec![],
+            tasks,
+        };
+
+        assert_eq!(
+            command(&toastfile, &toastfile.tasks["foo"]),
+            "set -euxo pipefail\necho hello".to_owned(),
+        );
+        assert_eq!(
+            command_prefix(&toastfile, &toastfile.tasks["foo"]),
+            "set -euxo pipefail",
+        );
+    }
+
+    #[test]
+    fn mount_target_relative() {
+        assert_eq!(
+            mount_target(
+                UnixPath::new("/scratch"),
+                &MappingPath {
+                    host_path: Path::new("foo").to_owned(),
+                    container_path: UnixPath::new("bar").to_owned(),
+                    readonly: None,
+                },
+            ),
+            UnixPath::new("/scratch/bar"),
+        );
+    }
+
+    #[test]
+    fn mount_target_absolute() {
+        assert_eq!(
+            mount_target(
+                UnixPath::new("/scratch"),
+                &MappingPath {
+                    host_path: Path::new("/var/run/docker.sock").to_owned(),
+                    container_path: UnixPath::new("/var/run/docker.sock").to_owned(),
+                    readonly: None,
+                },
+            ),
+            UnixPath::new("/var/run/docker.sock"),
+        );
+    }
+
+    #[test]
+    fn missing_variables_message_one_variable() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  build:
+    environment:
+      TOKEN: null
+",
+        )
+        .unwrap();
+        let missing = HashMap::from([("build".to_owned(), vec!["TOKEN"])]);
+
+        assert_eq!(
+            missing_variables_message(&toastfile.tasks, &missing, &["build"], &["build"], |_| None),
+            "A variable is missing from the environment:\n\
+             - `TOKEN` is needed by task `build`.\n\
+             To provide it, run: `TOKEN=\u{2026} toast build`",
+        );
+    }
+
+    #[test]
+    fn missing_variables_message_several_variables() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  build:
+    environment:
+      TOKEN: null
+      REGION: null
+  test:
+    dependencies:
+      - build
+    environment:
+      TOKEN: null
+  deploy:
+    dependencies:
+      - test
+    environment:
+      REGION: us-east-1
+",
+        )
+        .unwrap();
+        let missing = HashMap::from([
+            ("build".to_owned(), vec!["TOKEN", "REGION"]),
+            ("test".to_owned(), vec!["TOKEN"]),
+        ]);
+
+        assert_eq!(
+            missing_variables_message(
+                &toastfile.tasks,
+                &missing,
+                &["build", "test", "deploy"],
+                &[],
+                |variable| (variable == "TOKEN").then(|| OsString::from("\u{fffd}")),
+            ),
+            "Some variables are missing from the environment:\n\
+             - `REGION` is needed by task `build`. Task `deploy` has a default for it, but \
+             defaults only apply to the task which declares them.\n\
+             - `TOKEN` is needed by tasks `build` and `test`. It's set, but its value isn't valid \
+             Unicode.\n\
+             To provide them, run: `REGION=\u{2026} TOKEN=\u{2026} toast`",
+        );
+    }
+
+    #[test]
+    fn shared_mount_warnings_same_path() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    cache: false
+    mount_paths:
+      - data:/data
+  bar:
+    cache: false
+    mount_paths:
+      - ./data/:/var/data
+",
+        )
+        .unwrap();
+
+        let warnings = shared_mount_warnings(&toastfile, Path::new("/project"));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("Tasks `bar` and `foo` both mount"));
+        assert!(warnings[0].contains("data:/data"));
+        assert!(warnings[0].contains("./data/:/var/data"));
+    }
+
+    #[test]
+    fn shared_mount_warnings_nested_paths() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    cache: false
+    mount_paths:
+      - /project/data/cache:/cache
+  bar:
+    cache: false
+    mount_paths:
+      - src/../data
+",
+        )
+        .unwrap();
+
+        let warnings = shared_mount_warnings(&toastfile, Path::new("/project"));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("both mount `src/../data` writably"));
+    }
+
+    #[test]
+    fn shared_mount_warnings_disjoint_paths() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    cache: false
+    mount_paths:
+      - data
+  bar:
+    cache: false
+    mount_paths:
+      - database
+      - /data
+",
+        )
+        .unwrap();
+
+        assert!(shared_mount_warnings(&toastfile, Path::new("/project")).is_empty());
+    }
+
+    #[test]
+    fn shared_mount_warnings_read_only() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    cache: false
+    mount_paths:
+      - data
+    mount_readonly: true
+  bar:
+    cache: false
+    mount_paths:
+      - data
+",
+        )
+        .unwrap();
+
+        assert!(shared_mount_warnings(&toastfile, Path::new("/project")).is_empty());
+    }
+
+    #[test]
+    fn shared_mount_warnings_read_only_mount() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    cache: false
+    mount_paths:
+      - data:/data:ro
+      - build-cache
+  bar:
+    cache: false
+    mount_paths:
+      - data
+",
+        )
+        .unwrap();
+
+        assert!(shared_mount_warnings(&toastfile, Path::new("/project")).is_empty());
+    }
+
+    #[test]
+    fn shared_mount_warnings_read_write_mount() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    cache: false
+    mount_paths:
+      - data:rw
+    mount_readonly: true
+  bar:
+    cache: false
+    mount_paths:
+      - data
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            shared_mount_warnings(&toastfile, Path::new("/project")).len(),
+            1,
+        );
+    }
+
+    #[test]
+    fn mapping_path_suffix() {
+        assert_eq!(
+            "src:/code:ro".parse::<MappingPath>().unwrap(),
+            MappingPath {
+                host_path: Path::new("src").to_owned(),
+                container_path: UnixPath::new("/code").to_owned(),
+                readonly: Some(true),
             },
         );
-        tasks.insert(
-            "baz".to_owned(),
-            Task {
-                description: None,
-                dependencies: vec!["bar".to_owned()],
-                cache: true,
-                environment: HashMap::new(),
-                input_paths: vec![],
-                excluded_input_paths: vec![],
-                output_paths: vec![],
-                output_paths_on_failure: vec![],
-                mount_paths: vec![],
-                mount_readonly: false,
-                ports: vec![],
-                location: None,
-                user: None,
-                command: String::new(),
-                command_prefix: None,
-                extra_docker_arguments: vec![],
+        assert_eq!(
+            "build-cache:rw".parse::<MappingPath>().unwrap(),
+            MappingPath {
+                host_path: Path::new("build-cache").to_owned(),
+                container_path: UnixPath::new("build-cache").to_owned(),
+                readonly: Some(false),
             },
         );
+        assert_eq!("data".parse::<MappingPath>().unwrap().readonly, None);
+    }
 
-        let toastfile = Toastfile {
-            image: "encom:os-12".to_owned(),
-            default: None,
-            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
-            user: DEFAULT_USER.to_owned(),
-            command_prefix: String::new(),
-            tasks,
-        };
+    #[test]
+    fn mapping_path_round_trip() {
+        for path in ["src:/code:ro", "build-cache:/cache:rw", "data:/data"] {
+            assert_eq!(path.parse::<MappingPath>().unwrap().to_string(), path,);
+        }
+    }
 
-        let result = check_dependencies(&toastfile);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("cyclic"));
+    #[test]
+    fn mapping_path_is_readonly() {
+        let read_only = "src:ro".parse::<MappingPath>().unwrap();
+        let read_write = "src:rw".parse::<MappingPath>().unwrap();
+        let default = "src".parse::<MappingPath>().unwrap();
+
+        assert!(read_only.is_readonly(false));
+        assert!(!read_write.is_readonly(true));
+        assert!(default.is_readonly(true));
+        assert!(!default.is_readonly(false));
     }
 
     #[test]
-    fn check_task_environment_ok() {
-        let mut environment = HashMap::new();
-        environment.insert("corge".to_owned(), None);
-        environment.insert("grault".to_owned(), None);
-        environment.insert("garply".to_owned(), None);
+    fn volume_parse() {
+        assert_eq!(
+            "cargo-registry:/usr/local/cargo/registry"
+                .parse::<Volume>()
+                .unwrap(),
+            Volume {
+                name: "cargo-registry".to_owned(),
+                container_path: UnixPath::new("/usr/local/cargo/registry").to_owned(),
+            },
+        );
+        assert!("cargo-registry".parse::<Volume>().is_err());
+    }
 
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: true,
-            environment,
-            input_paths: vec![],
-            excluded_input_paths: vec![],
-            output_paths: vec![],
-            output_paths_on_failure: vec![],
-            mount_paths: vec![],
-            mount_readonly: false,
-            ports: vec![],
-            location: None,
-            user: None,
-            command: String::new(),
-            command_prefix: None,
-            extra_docker_arguments: vec![],
-        };
+    #[test]
+    fn volume_round_trip() {
+        for volume in [
+            "cargo-registry:/usr/local/cargo/registry",
+            "npm:node_modules",
+        ] {
+            assert_eq!(volume.parse::<Volume>().unwrap().to_string(), volume);
+        }
+    }
 
-        assert!(check_task("foo", &task).is_ok());
+    #[test]
+    fn parse_volumes() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  build:
+    cache: false
+    volumes:
+      - cargo-registry:/usr/local/cargo/registry
+      - target.v2:target
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            toastfile.tasks["build"]
+                .volumes
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                "cargo-registry:/usr/local/cargo/registry",
+                "target.v2:target"
+            ],
+        );
     }
 
     #[test]
-    fn check_task_environment_equals() {
-        let mut environment = HashMap::new();
-        environment.insert("corge".to_owned(), None);
-        environment.insert("gra=ult".to_owned(), None);
-        environment.insert("garply".to_owned(), None);
+    fn parse_volumes_invalid() {
+        let result = parse(
+            r"
+image: encom:os-12
+tasks:
+  build:
+    cache: false
+    volumes:
+      - -cache:/cache
+      - my cache:/cache
+      - cache:/a,b
+",
+        );
 
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: true,
-            environment,
-            input_paths: vec![],
-            excluded_input_paths: vec![],
-            output_paths: vec![],
-            output_paths_on_failure: vec![],
-            mount_paths: vec![],
-            mount_readonly: false,
-            ports: vec![],
-            location: None,
-            user: None,
-            command: String::new(),
-            command_prefix: None,
-            extra_docker_arguments: vec![],
-        };
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("-cache:/cache"));
+        assert!(message.contains("my cache:/cache"));
+        assert!(message.contains("cache:/a,b"));
+    }
 
-        let result = check_task("foo", &task);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains('='));
+    #[test]
+    fn parse_volumes_with_cache() {
+        let result = parse(
+            r"
+image: encom:os-12
+tasks:
+  build:
+    volumes:
+      - cargo-registry:/usr/local/cargo/registry
+",
+        );
+
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("does not disable caching"));
     }
 
     #[test]
-    fn check_task_paths_ok() {
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: false,
-            environment: HashMap::new(),
-            input_paths: vec![UnixPath::new("bar").to_owned()],
-            excluded_input_paths: vec![UnixPath::new("baz").to_owned()],
-            output_paths: vec![UnixPath::new("qux").to_owned()],
-            output_paths_on_failure: vec![UnixPath::new("quux").to_owned()],
-            mount_paths: vec![
-                MappingPath {
-                    host_path: Path::new("quuy").to_owned(),
-                    container_path: UnixPath::new("quuz").to_owned(),
-                },
-                MappingPath {
-                    host_path: Path::new("quuy").to_owned(),
-                    container_path: UnixPath::new("/quuz").to_owned(),
-                },
-                MappingPath {
-                    host_path: Path::new("quuy").to_owned(),
-                    container_path: UnixPath::new("/quuz").to_owned(),
-                },
-                MappingPath {
-                    host_path: Path::new("/quuy").to_owned(),
-                    container_path: UnixPath::new("/quuz").to_owned(),
-                },
-            ],
-            mount_readonly: false,
-            ports: vec![],
-            location: Some(UnixPath::new("/corge").to_owned()),
-            user: None,
-            command: String::new(),
-            command_prefix: None,
-            extra_docker_arguments: vec![],
-        };
+    fn shared_mount_warnings_dependency() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    cache: false
+    mount_paths:
+      - data
+  bar:
+    dependencies:
+      - foo
+  baz:
+    cache: false
+    dependencies:
+      - bar
+    mount_paths:
+      - data
+",
+        )
+        .unwrap();
 
-        assert!(check_task("foo", &task).is_ok());
+        assert!(shared_mount_warnings(&toastfile, Path::new("/project")).is_empty());
     }
 
     #[test]
-    fn check_task_paths_absolute_input_paths() {
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: true,
-            environment: HashMap::new(),
-            input_paths: vec![UnixPath::new("/bar").to_owned()],
-            excluded_input_paths: vec![],
-            output_paths: vec![],
-            output_paths_on_failure: vec![],
-            mount_paths: vec![],
-            mount_readonly: false,
-            ports: vec![],
-            location: None,
-            user: None,
-            command: String::new(),
-            command_prefix: None,
-            extra_docker_arguments: vec![],
-        };
+    fn shared_mount_warnings_allowed() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    cache: false
+    mount_paths:
+      - data
+    allow_shared_mounts: true
+  bar:
+    cache: false
+    mount_paths:
+      - data
+",
+        )
+        .unwrap();
 
-        let result = check_task("foo", &task);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("/bar"));
+        assert!(shared_mount_warnings(&toastfile, Path::new("/project")).is_empty());
     }
 
     #[test]
-    fn check_task_paths_absolute_excluded_input_paths() {
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: true,
-            environment: HashMap::new(),
-            input_paths: vec![],
-            excluded_input_paths: vec![UnixPath::new("/bar").to_owned()],
-            output_paths: vec![],
-            output_paths_on_failure: vec![],
-            mount_paths: vec![],
-            mount_readonly: false,
-            ports: vec![],
-            location: None,
-            user: None,
-            command: String::new(),
-            command_prefix: None,
-            extra_docker_arguments: vec![],
-        };
+    fn warnings_none() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    cache: false
+    input_paths:
+      - package.json
+    mount_paths:
+      - node_modules
+      - /var/run/docker.sock
+",
+        )
+        .unwrap();
 
-        let result = check_task("foo", &task);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("/bar"));
+        assert!(warnings(&toastfile).is_empty());
     }
 
     #[test]
-    fn check_task_paths_absolute_output_paths() {
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: false,
-            environment: HashMap::new(),
-            input_paths: vec![],
-            excluded_input_paths: vec![],
-            output_paths: vec![UnixPath::new("/bar").to_owned()],
-            output_paths_on_failure: vec![],
-            mount_paths: vec![],
-            mount_readonly: false,
-            ports: vec![],
-            location: None,
-            user: None,
-            command: String::new(),
-            command_prefix: None,
-            extra_docker_arguments: vec![],
-        };
+    fn warnings_no_input_paths() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    cache: false
+    mount_paths:
+      - .
+",
+        )
+        .unwrap();
+
+        assert!(warnings(&toastfile).is_empty());
+    }
+
+    #[test]
+    fn warnings_mount_hides_location() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    cache: false
+    input_paths:
+      - package.json
+    mount_paths:
+      - ./
+",
+        )
+        .unwrap();
+
+        let warnings = warnings(&toastfile);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("./:./"));
+        assert!(warnings[0].contains("/scratch"));
+    }
+
+    #[test]
+    fn warnings_mount_hides_ancestor_of_location() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+location: /code/app
+tasks:
+  foo:
+    cache: false
+    input_paths:
+      - package.json
+    mount_paths:
+      - src:/code
+",
+        )
+        .unwrap();
 
-        let result = check_task("foo", &task);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("/bar"));
+        let warnings = warnings(&toastfile);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("src:/code"));
+        assert!(warnings[0].contains("/code/app"));
     }
 
     #[test]
-    fn check_task_paths_absolute_output_paths_on_failure() {
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: false,
-            environment: HashMap::new(),
-            input_paths: vec![],
-            excluded_input_paths: vec![],
-            output_paths: vec![],
-            output_paths_on_failure: vec![UnixPath::new("/bar").to_owned()],
-            mount_paths: vec![],
-            mount_readonly: false,
-            ports: vec![],
-            location: None,
-            user: None,
-            command: String::new(),
-            command_prefix: None,
-            extra_docker_arguments: vec![],
-        };
+    fn warnings_mount_hides_input_path() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    cache: false
+    input_paths:
+      - src/main.rs
+      - Cargo.toml
+    mount_paths:
+      - src
+",
+        )
+        .unwrap();
 
-        let result = check_task("foo", &task);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("/bar"));
+        let warnings = warnings(&toastfile);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("src/main.rs"));
+        assert!(warnings[0].contains("/scratch/src/main.rs"));
+        assert!(!warnings[0].contains("Cargo.toml"));
     }
 
     #[test]
-    fn check_task_paths_mount_paths_comma() {
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: true,
-            environment: HashMap::new(),
-            input_paths: vec![],
-            excluded_input_paths: vec![],
-            output_paths: vec![],
-            output_paths_on_failure: vec![],
-            mount_paths: vec![MappingPath {
-                host_path: Path::new("bar,baz").to_owned(),
-                container_path: UnixPath::new("bar,baz").to_owned(),
-            }],
-            mount_readonly: false,
-            ports: vec![],
-            location: None,
-            user: None,
-            command: String::new(),
-            command_prefix: None,
-            extra_docker_arguments: vec![],
-        };
+    fn warnings_mount_equals_input_path() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    cache: false
+    location: /code
+    input_paths:
+      - ./src
+    mount_paths:
+      - lib:src
+",
+        )
+        .unwrap();
 
-        let result = check_task("foo", &task);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("bar,baz"));
+        let warnings = warnings(&toastfile);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("/code/./src"));
     }
 
     #[test]
-    fn check_task_paths_relative_location() {
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: true,
-            environment: HashMap::new(),
-            input_paths: vec![],
-            excluded_input_paths: vec![],
-            output_paths: vec![],
-            output_paths_on_failure: vec![],
-            mount_paths: vec![],
-            mount_readonly: false,
-            ports: vec![],
-            location: Some(UnixPath::new("code").to_owned()),
-            user: None,
-            command: String::new(),
-            command_prefix: None,
-            extra_docker_arguments: vec![],
-        };
+    fn warnings_mount_inside_input_path() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    cache: false
+    input_paths:
+      - src
+    mount_paths:
+      - src/generated
+",
+        )
+        .unwrap();
 
-        let result = check_task("foo", &task);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("code"));
+        assert!(warnings(&toastfile).is_empty());
     }
 
     #[test]
-    fn check_task_caching_enabled_with_mount_paths() {
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: true,
-            environment: HashMap::new(),
-            input_paths: vec![],
-            excluded_input_paths: vec![],
-            output_paths: vec![],
-            output_paths_on_failure: vec![],
-            mount_paths: vec![MappingPath {
-                host_path: Path::new("bar").to_owned(),
-                container_path: UnixPath::new("bar").to_owned(),
-            }],
-            mount_readonly: false,
-            ports: vec![],
-            location: None,
-            user: None,
-            command: String::new(),
-            command_prefix: None,
-            extra_docker_arguments: vec![],
-        };
+    fn warnings_sibling_prefix() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    cache: false
+    input_paths:
+      - src.txt
+    mount_paths:
+      - src
+",
+        )
+        .unwrap();
 
-        let result = check_task("foo", &task);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("mount_paths"));
+        assert!(warnings(&toastfile).is_empty());
     }
 
     #[test]
-    fn check_task_caching_disabled_with_mount_paths() {
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: false,
-            environment: HashMap::new(),
-            input_paths: vec![],
-            excluded_input_paths: vec![],
-            output_paths: vec![],
-            output_paths_on_failure: vec![],
-            mount_paths: vec![MappingPath {
-                host_path: Path::new("bar").to_owned(),
-                container_path: UnixPath::new("bar").to_owned(),
-            }],
-            mount_readonly: false,
-            ports: vec!["3000:80".to_owned()],
-            location: None,
-            user: None,
-            command: String::new(),
-            command_prefix: None,
-            extra_docker_arguments: vec![],
-        };
+    fn dependency_shadowing_warnings_location() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  deps:
+    input_paths:
+      - package.json
+    command: npm install
+  dev:
+    dependencies:
+      - deps
+    cache: false
+    mount_paths:
+      - .
+    command: npm run dev
+",
+        )
+        .unwrap();
 
-        assert!(check_task("foo", &task).is_ok());
+        let warnings = dependency_shadowing_warnings(&toastfile, &["deps", "dev"]);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("`dev`"));
+        assert!(warnings[0].contains("`deps`"));
+        assert!(warnings[0].contains("/scratch"));
     }
 
     #[test]
-    fn check_task_caching_enabled_with_ports() {
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: true,
-            environment: HashMap::new(),
-            input_paths: vec![],
-            excluded_input_paths: vec![],
-            output_paths: vec![],
-            output_paths_on_failure: vec![],
-            mount_paths: vec![],
-            mount_readonly: false,
-            ports: vec!["3000:80".to_owned()],
-            location: None,
-            user: None,
-            command: String::new(),
-            command_prefix: None,
-            extra_docker_arguments: vec![],
-        };
+    fn dependency_shadowing_warnings_paths() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  build:
+    input_paths:
+      - src
+      - Cargo.toml
+    output_paths:
+      - target/release/app
+    command: cargo build --release
+  serve:
+    dependencies:
+      - build
+    cache: false
+    mount_paths:
+      - src
+      - /tmp/target:target
+    command: ./target/release/app
+",
+        )
+        .unwrap();
+
+        let warnings = dependency_shadowing_warnings(&toastfile, &["build", "serve"]);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("`input_paths`"));
+        assert!(warnings[0].contains("/scratch/src"));
+        assert!(warnings[1].contains("`output_paths`"));
+        assert!(warnings[1].contains("/scratch/target/release/app"));
+    }
 
-        let result = check_task("foo", &task);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("caching"));
+    #[test]
+    fn dependency_shadowing_warnings_transitive() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  deps:
+    location: /code
+    command: npm install
+  build:
+    dependencies:
+      - deps
+    command: npm run build
+  dev:
+    dependencies:
+      - build
+    cache: false
+    mount_paths:
+      - .:/code
+    command: npm run dev
+",
+        )
+        .unwrap();
+
+        let warnings = dependency_shadowing_warnings(&toastfile, &["deps", "build", "dev"]);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("`deps`"));
+        assert!(warnings[0].contains("/code"));
     }
 
     #[test]
-    fn check_task_caching_disabled_with_ports() {
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: false,
-            environment: HashMap::new(),
-            input_paths: vec![],
-            excluded_input_paths: vec![],
-            output_paths: vec![],
-            output_paths_on_failure: vec![],
-            mount_paths: vec![],
-            mount_readonly: false,
-            ports: vec!["3000:80".to_owned()],
-            location: None,
-            user: None,
-            command: String::new(),
-            command_prefix: None,
-            extra_docker_arguments: vec![],
-        };
+    fn dependency_shadowing_warnings_unrelated() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  deps:
+    command: npm install
+  dev:
+    cache: false
+    mount_paths:
+      - .
+    command: npm run dev
+  other:
+    cache: false
+    mount_paths:
+      - /tmp/cache:/cache
+    dependencies:
+      - deps
+",
+        )
+        .unwrap();
 
-        assert!(check_task("foo", &task).is_ok());
+        assert!(dependency_shadowing_warnings(&toastfile, &["deps", "dev", "other"]).is_empty());
     }
 
     #[test]
-    fn check_task_caching_enabled_with_extra_docker_arguments() {
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: true,
-            environment: HashMap::new(),
-            input_paths: vec![],
-            excluded_input_paths: vec![],
-            output_paths: vec![],
-            output_paths_on_failure: vec![],
-            mount_paths: vec![],
-            mount_readonly: false,
-            ports: vec![],
-            location: None,
-            user: None,
-            command: String::new(),
-            command_prefix: None,
-            extra_docker_arguments: vec!["--cpus".to_owned(), "4".to_owned()],
-        };
+    fn parse_command_file_with_command() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    command: make
+    command_file: build.sh
+";
+
+        let message = parse(input).unwrap_err().to_string();
+        assert!(message.contains("foo"));
+        assert!(message.contains("Only one of them can be given"));
+    }
 
-        let result = check_task("foo", &task);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("caching"));
+    #[test]
+    fn parse_command_file_absolute() {
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    command_file: /build.sh
+";
+
+        let message = parse(input).unwrap_err().to_string();
+        assert!(message.contains("absolute"));
+        assert!(message.contains("command_file"));
     }
 
     #[test]
-    fn check_task_caching_disabled_with_extra_docker_arguments() {
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: false,
-            environment: HashMap::new(),
-            input_paths: vec![],
-            excluded_input_paths: vec![],
-            output_paths: vec![],
-            output_paths_on_failure: vec![],
-            mount_paths: vec![],
-            mount_readonly: false,
-            ports: vec![],
-            location: None,
-            user: None,
-            command: String::new(),
-            command_prefix: None,
-            extra_docker_arguments: vec!["--cpus".to_owned(), "4".to_owned()],
-        };
+    fn load_command_files_ok() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("scripts")).unwrap();
+        fs::write(dir.path().join("scripts/build.sh"), "make\nmake test\n").unwrap();
 
-        assert!(check_task("foo", &task).is_ok());
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    command_file: scripts/build.sh
+  bar:
+    command: echo bar
+";
+
+        let mut toastfile = parse(input).unwrap();
+        load_command_files(&mut toastfile, dir.path()).unwrap();
+
+        assert_eq!(toastfile.tasks["foo"].command, "make\nmake test\n");
+        assert_eq!(toastfile.tasks["bar"].command, "echo bar");
     }
 
     #[test]
-    fn environment_empty() {
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: true,
-            environment: HashMap::new(),
-            input_paths: vec![],
-            excluded_input_paths: vec![],
-            output_paths: vec![],
-            output_paths_on_failure: vec![],
-            mount_paths: vec![],
-            mount_readonly: false,
-            ports: vec![],
-            location: None,
-            user: None,
-            command: String::new(),
-            command_prefix: None,
-            extra_docker_arguments: vec![],
-        };
+    fn load_command_files_missing() {
+        let dir = tempdir().unwrap();
+
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    command_file: build.sh
+";
+
+        let mut toastfile = parse(input).unwrap();
+        let message = load_command_files(&mut toastfile, dir.path())
+            .unwrap_err()
+            .to_string();
 
-        assert_eq!(environment(&task), Ok(HashMap::new()));
+        assert!(message.contains("foo"));
+        assert!(message.contains("build.sh"));
     }
 
     #[test]
-    fn environment_default_overridden() {
-        // NOTE: We add an index to the test arg ("foo1", "foo2", ...) to avoid having parallel
-        // tests clobbering environment variables used by other threads.
-        let mut env_map = HashMap::new();
-        env_map.insert("foo1".to_owned(), Some("bar".to_owned()));
+    fn parse_env_file_absolute() {
+        let input = r"
+image: encom:os-12
+env_file: /shared.env
+tasks:
+  foo:
+    env_file: /foo.env
+";
+
+        let message = parse(input).unwrap_err().to_string();
+        assert!(message.contains("Toastfile has an absolute"));
+        assert!(message.contains("/shared.env"));
+        assert!(message.contains("Task `foo` has an absolute"));
+        assert!(message.contains("/foo.env"));
+    }
 
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: true,
-            environment: env_map,
-            input_paths: vec![],
-            excluded_input_paths: vec![],
-            output_paths: vec![],
-            output_paths_on_failure: vec![],
-            mount_paths: vec![],
-            mount_readonly: false,
-            ports: vec![],
-            location: None,
-            user: None,
-            command: String::new(),
-            command_prefix: None,
-            extra_docker_arguments: vec![],
-        };
+    #[test]
+    fn load_env_files_ok() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join(".env"),
+            "# Shared\nREGION=us-west-2\nPROFILE=default\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("foo.env"), "export PROFILE='staging'\n").unwrap();
 
-        let mut expected = HashMap::new();
-        expected.insert("foo1".to_owned(), "baz".to_owned());
+        let input = r"
+image: encom:os-12
+env_file: .env
+tasks:
+  foo:
+    env_file: foo.env
+  bar: {}
+";
+
+        let mut toastfile = parse(input).unwrap();
+        load_env_files(&mut toastfile, dir.path()).unwrap();
+
+        let mut foo_values = HashMap::new();
+        foo_values.insert("REGION".to_owned(), "us-west-2".to_owned());
+        foo_values.insert("PROFILE".to_owned(), "staging".to_owned());
+        assert_eq!(toastfile.tasks["foo"].env_file_values, foo_values);
+
+        let mut bar_values = HashMap::new();
+        bar_values.insert("REGION".to_owned(), "us-west-2".to_owned());
+        bar_values.insert("PROFILE".to_owned(), "default".to_owned());
+        assert_eq!(toastfile.tasks["bar"].env_file_values, bar_values);
+    }
 
-        env::set_var("foo1", "baz");
-        assert_eq!(env::var("foo1"), Ok("baz".to_owned()));
-        assert_eq!(environment(&task), Ok(expected));
+    #[test]
+    fn load_env_files_missing() {
+        let dir = tempdir().unwrap();
+
+        let input = r"
+image: encom:os-12
+env_file: .env
+tasks:
+  foo:
+    env_file: foo.env
+";
+
+        let mut toastfile = parse(input).unwrap();
+        let message = load_env_files(&mut toastfile, dir.path())
+            .unwrap_err()
+            .to_string();
+
+        assert!(message.contains("Unable to read the `env_file` of the toastfile: `.env`."));
+        assert!(message.contains("Unable to read the `env_file` of task `foo`: `foo.env`."));
     }
 
     #[test]
-    fn environment_default_not_overridden() {
-        // NOTE: We add an index to the test arg ("foo1", "foo2", ...) to avoid having parallel
-        // tests clobbering environment variables used by other threads.
-        let mut env_map = HashMap::new();
-        env_map.insert("foo2".to_owned(), Some("bar".to_owned()));
+    fn load_env_files_invalid() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("foo.env"), "FOO=bar\nbaz\n").unwrap();
 
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: true,
-            environment: env_map,
-            input_paths: vec![],
-            excluded_input_paths: vec![],
-            output_paths: vec![],
-            output_paths_on_failure: vec![],
-            mount_paths: vec![],
-            mount_readonly: false,
-            ports: vec![],
-            location: None,
-            user: None,
-            command: String::new(),
-            command_prefix: None,
-            extra_docker_arguments: vec![],
-        };
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    env_file: foo.env
+";
 
-        let mut expected = HashMap::new();
-        expected.insert("foo2".to_owned(), "bar".to_owned());
+        let mut toastfile = parse(input).unwrap();
+        let message = load_env_files(&mut toastfile, dir.path())
+            .unwrap_err()
+            .to_string();
 
-        env::remove_var("foo2");
-        assert!(env::var("foo2").is_err());
-        assert_eq!(environment(&task), Ok(expected));
+        assert!(message.contains("Unable to parse the `env_file` of task `foo`: `foo.env`."));
+        assert!(message.contains("Line 2"));
     }
 
     #[test]
-    fn environment_missing() {
+    fn environment_env_file_values() {
         // NOTE: We add an index to the test arg ("foo1", "foo2", ...) to avoid having parallel
         // tests clobbering environment variables used by other threads.
-        let mut env_map = HashMap::new();
-        env_map.insert("foo3".to_owned(), None);
-
-        let task = Task {
-            description: None,
-            dependencies: vec![],
-            cache: true,
-            environment: env_map,
-            input_paths: vec![],
-            excluded_input_paths: vec![],
-            output_paths: vec![],
-            output_paths_on_failure: vec![],
-            mount_paths: vec![],
-            mount_readonly: false,
-            ports: vec![],
-            location: None,
-            user: None,
-            command: String::new(),
-            command_prefix: None,
-            extra_docker_arguments: vec![],
-        };
+        let input = r"
+image: encom:os-12
+tasks:
+  foo:
+    environment:
+      foo29: default
+      foo30: null
+      foo31: default
+      foo32: default
+      foo33: ${foo34}
+";
+
+        let mut toastfile = parse(input).unwrap();
+        let task = toastfile.tasks.get_mut("foo").unwrap();
+        for variable in ["foo29", "foo30", "foo31", "foo32", "foo34"] {
+            task.env_file_values
+                .insert(variable.to_owned(), format!("{variable} from file"));
+        }
+        env::set_var("foo31", "environment");
+        let mut overrides = HashMap::new();
+        overrides.insert("foo32".to_owned(), "override".to_owned());
+
+        let result = environment(&toastfile.tasks["foo"], &overrides).unwrap();
+
+        assert_eq!(result["foo29"], "foo29 from file");
+        assert_eq!(result["foo30"], "foo30 from file");
+        assert_eq!(result["foo31"], "environment");
+        assert_eq!(result["foo32"], "override");
+        assert_eq!(result["foo33"], "foo34 from file");
+        assert!(!result.contains_key("foo34"));
+    }
 
-        env::remove_var("foo3");
-        assert!(env::var("foo3").is_err());
-        let result = environment(&task);
-        assert!(result.is_err());
-        assert_eq!(result.unwrap_err()[0].to_owned(), "foo3");
+    // Find the missing input paths, given the paths which exist, as strings.
+    fn missing_input_path_strings<'a>(
+        toastfile: &'a Toastfile,
+        schedule: &[&'a str],
+        output_dir_rsd: Option<&str>,
+        existing: &[&str],
+    ) -> Vec<(&'a str, Vec<String>)> {
+        missing_input_paths(
+            toastfile,
+            schedule,
+            output_dir_rsd.map(UnixPath::new),
+            |path| existing.contains(&&*path.to_string_lossy()),
+        )
+        .into_iter()
+        .map(|(task, paths)| {
+            (
+                task,
+                paths
+                    .into_iter()
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .collect(),
+            )
+        })
+        .collect()
     }
 
     #[test]
-    fn location_default() {
-        let mut tasks = HashMap::new();
-        tasks.insert(
-            "foo".to_owned(),
-            Task {
-                description: None,
-                dependencies: vec![],
-                cache: true,
-                environment: HashMap::new(),
-                input_paths: vec![],
-                excluded_input_paths: vec![],
-                output_paths: vec![],
-                output_paths_on_failure: vec![],
-                mount_paths: vec![],
-                mount_readonly: false,
-                ports: vec![],
-                location: None,
-                user: None,
-                command: String::new(),
-                command_prefix: None,
-                extra_docker_arguments: vec![],
-            },
+    fn missing_input_paths_none() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    input_paths:
+      - src
+      - Cargo.toml
+",
+        )
+        .unwrap();
+
+        assert!(
+            missing_input_path_strings(&toastfile, &["foo"], Some(""), &["src", "Cargo.toml"])
+                .is_empty(),
         );
+    }
 
-        let toastfile = Toastfile {
-            image: "encom:os-12".to_owned(),
-            default: None,
-            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
-            user: DEFAULT_USER.to_owned(),
-            command_prefix: String::new(),
-            tasks,
-        };
+    #[test]
+    fn missing_input_paths_grouped_by_task() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    input_paths:
+      - a
+      - b
+      - c
+  bar:
+    dependencies:
+      - foo
+    input_paths:
+      - d
+  baz:
+    input_paths:
+      - e
+",
+        )
+        .unwrap();
 
         assert_eq!(
-            location(&toastfile, &toastfile.tasks["foo"]),
-            UnixPath::new(DEFAULT_LOCATION),
+            missing_input_path_strings(&toastfile, &["foo", "bar", "baz"], Some(""), &["b", "e"]),
+            vec![
+                ("foo", vec!["a".to_owned(), "c".to_owned()]),
+                ("bar", vec!["d".to_owned()]),
+            ],
         );
     }
 
     #[test]
-    fn location_override() {
-        let mut tasks = HashMap::new();
-        tasks.insert(
-            "foo".to_owned(),
-            Task {
-                description: None,
-                dependencies: vec![],
-                cache: true,
-                environment: HashMap::new(),
-                input_paths: vec![],
-                excluded_input_paths: vec![],
-                output_paths: vec![],
-                output_paths_on_failure: vec![],
-                mount_paths: vec![],
-                mount_readonly: false,
-                ports: vec![],
-                location: Some(UnixPath::new("/bar").to_owned()),
-                user: None,
-                command: String::new(),
-                command_prefix: None,
-                extra_docker_arguments: vec![],
-            },
-        );
-
-        let toastfile = Toastfile {
-            image: "encom:os-12".to_owned(),
-            default: None,
-            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
-            user: DEFAULT_USER.to_owned(),
-            command_prefix: String::new(),
-            tasks,
-        };
+    fn missing_input_paths_unscheduled_task() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    input_paths:
+      - a
+  bar:
+    input_paths:
+      - b
+",
+        )
+        .unwrap();
 
         assert_eq!(
-            location(&toastfile, &toastfile.tasks["foo"]),
-            UnixPath::new("/bar"),
+            missing_input_path_strings(&toastfile, &["bar"], Some(""), &[]),
+            vec![("bar", vec!["b".to_owned()])],
         );
     }
 
     #[test]
-    fn user_default() {
-        let mut tasks = HashMap::new();
-        tasks.insert(
-            "foo".to_owned(),
-            Task {
-                description: None,
-                dependencies: vec![],
-                cache: true,
-                environment: HashMap::new(),
-                input_paths: vec![],
-                excluded_input_paths: vec![],
-                output_paths: vec![],
-                output_paths_on_failure: vec![],
-                mount_paths: vec![],
-                mount_readonly: false,
-                ports: vec![],
-                location: None,
-                user: None,
-                command: String::new(),
-                command_prefix: None,
-                extra_docker_arguments: vec![],
-            },
+    fn missing_input_paths_skips_globs() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  foo:
+    input_paths:
+      - src/*.rs
+      - data/file?.txt
+      - docs/[ab].md
+    excluded_input_paths:
+      - target/*
+",
+        )
+        .unwrap();
+
+        assert!(missing_input_path_strings(&toastfile, &["foo"], Some(""), &[]).is_empty());
+    }
+
+    #[test]
+    fn missing_input_paths_excluded_paths() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  generate:
+    output_paths:
+      - gen
+  foo:
+    input_paths:
+      - a
+    excluded_input_paths:
+      - target
+      - docs
+  bar:
+    dependencies:
+      - generate
+    input_paths:
+      - src
+    excluded_input_paths:
+      - gen/tmp
+      - src/tmp
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            missing_input_path_strings(
+                &toastfile,
+                &["generate", "foo", "bar"],
+                Some(""),
+                &["docs", "src"],
+            ),
+            vec![
+                ("foo", vec!["a".to_owned(), "target".to_owned()]),
+                ("bar", vec!["src/tmp".to_owned()]),
+            ],
         );
+    }
 
-        let toastfile = Toastfile {
-            image: "encom:os-12".to_owned(),
-            default: None,
-            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
-            user: DEFAULT_USER.to_owned(),
-            command_prefix: String::new(),
-            tasks,
-        };
+    #[test]
+    fn missing_input_paths_produced_by_dependency() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  generate:
+    output_paths:
+      - gen/schema.rs
+  build:
+    dependencies:
+      - generate
+    input_paths:
+      - gen/schema.rs
+      - src
+",
+        )
+        .unwrap();
 
         assert_eq!(
-            user(&toastfile, &toastfile.tasks["foo"]),
-            DEFAULT_USER.to_owned(),
+            missing_input_path_strings(&toastfile, &["generate", "build"], Some(""), &[]),
+            vec![("build", vec!["src".to_owned()])],
         );
     }
 
     #[test]
-    fn user_override() {
-        let mut tasks = HashMap::new();
-        tasks.insert(
-            "foo".to_owned(),
-            Task {
-                description: None,
-                dependencies: vec![],
-                cache: true,
-                environment: HashMap::new(),
-                input_paths: vec![],
-                excluded_input_paths: vec![],
-                output_paths: vec![],
-                output_paths_on_failure: vec![],
-                mount_paths: vec![],
-                mount_readonly: false,
-                ports: vec![],
-                location: None,
-                user: Some("bar".to_owned()),
-                command: String::new(),
-                command_prefix: None,
-                extra_docker_arguments: vec![],
-            },
-        );
+    fn missing_input_paths_produced_by_transitive_dependency() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  generate:
+    output_paths:
+      - gen
+  build:
+    dependencies:
+      - generate
+  test:
+    dependencies:
+      - build
+    input_paths:
+      - gen
+",
+        )
+        .unwrap();
 
-        let toastfile = Toastfile {
-            image: "encom:os-12".to_owned(),
-            default: None,
-            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
-            user: DEFAULT_USER.to_owned(),
-            command_prefix: String::new(),
-            tasks,
-        };
+        assert!(missing_input_path_strings(
+            &toastfile,
+            &["generate", "build", "test"],
+            Some(""),
+            &[],
+        )
+        .is_empty());
+    }
 
-        assert_eq!(user(&toastfile, &toastfile.tasks["foo"]), "bar");
+    #[test]
+    fn missing_input_paths_inside_output_directory() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  generate:
+    output_paths:
+      - gen
+  build:
+    dependencies:
+      - generate
+    input_paths:
+      - gen/schema.rs
+      - ./gen/../gen/types.rs
+",
+        )
+        .unwrap();
+
+        assert!(
+            missing_input_path_strings(&toastfile, &["generate", "build"], Some(""), &[])
+                .is_empty(),
+        );
     }
 
     #[test]
-    fn command_default_prefix_default() {
-        let mut tasks = HashMap::new();
-        tasks.insert(
-            "foo".to_owned(),
-            Task {
-                description: None,
-                dependencies: vec![],
-                cache: true,
-                environment: HashMap::new(),
-                input_paths: vec![],
-                excluded_input_paths: vec![],
-                output_paths: vec![],
-                output_paths_on_failure: vec![],
-                mount_paths: vec![],
-                mount_readonly: false,
-                ports: vec![],
-                location: None,
-                user: None,
-                command: String::new(),
-                command_prefix: None,
-                extra_docker_arguments: vec![],
-            },
+    fn missing_input_paths_contains_output() {
+        // The dependency creates the directory by writing a file into it.
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  generate:
+    output_paths:
+      - gen/schema.rs
+  build:
+    dependencies:
+      - generate
+    input_paths:
+      - gen
+",
+        )
+        .unwrap();
+
+        assert!(
+            missing_input_path_strings(&toastfile, &["generate", "build"], Some(""), &[])
+                .is_empty(),
         );
+    }
 
-        let toastfile = Toastfile {
-            image: "encom:os-12".to_owned(),
-            default: None,
-            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
-            user: DEFAULT_USER.to_owned(),
-            command_prefix: "set -euxo pipefail".to_owned(),
-            tasks,
-        };
+    #[test]
+    fn missing_input_paths_sibling_of_output() {
+        // Sharing a prefix of the name isn't enough.
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  generate:
+    output_paths:
+      - gen
+  build:
+    dependencies:
+      - generate
+    input_paths:
+      - generated
+      - gen-old/schema.rs
+",
+        )
+        .unwrap();
 
         assert_eq!(
-            command(&toastfile, &toastfile.tasks["foo"]),
-            "set -euxo pipefail".to_owned(),
+            missing_input_path_strings(&toastfile, &["generate", "build"], Some(""), &[]),
+            vec![(
+                "build",
+                vec!["generated".to_owned(), "gen-old/schema.rs".to_owned()],
+            )],
         );
     }
 
     #[test]
-    fn command_override_prefix_default() {
-        let mut tasks = HashMap::new();
-        tasks.insert(
-            "foo".to_owned(),
-            Task {
-                description: None,
-                dependencies: vec![],
-                cache: true,
-                environment: HashMap::new(),
-                input_paths: vec![],
-                excluded_input_paths: vec![],
-                output_paths: vec![],
-                output_paths_on_failure: vec![],
-                mount_paths: vec![],
-                mount_readonly: false,
-                ports: vec![],
-                location: None,
-                user: None,
-                command: "echo hello".to_owned(),
-                command_prefix: None,
-                extra_docker_arguments: vec![],
-            },
+    fn missing_input_paths_other_output_fields() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  generate:
+    cache: false
+    output_paths_on_failure:
+      - logs
+    output_paths_always:
+      - report.txt
+  build:
+    dependencies:
+      - generate
+    input_paths:
+      - logs/generate.log
+      - report.txt
+",
+        )
+        .unwrap();
+
+        assert!(
+            missing_input_path_strings(&toastfile, &["generate", "build"], Some(""), &[])
+                .is_empty(),
         );
+    }
 
-        let toastfile = Toastfile {
-            image: "encom:os-12".to_owned(),
-            default: None,
-            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
-            user: DEFAULT_USER.to_owned(),
-            command_prefix: String::new(),
-            tasks,
-        };
+    #[test]
+    fn missing_input_paths_absolute_output() {
+        // An absolute output path is written to the output directory under its file name.
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  generate:
+    output_paths:
+      - /usr/local/bin/tool
+  build:
+    dependencies:
+      - generate
+    input_paths:
+      - tool
+      - usr/local/bin/tool
+",
+        )
+        .unwrap();
 
         assert_eq!(
-            command(&toastfile, &toastfile.tasks["foo"]),
-            "echo hello".to_owned(),
+            missing_input_path_strings(&toastfile, &["generate", "build"], Some(""), &[]),
+            vec![("build", vec!["usr/local/bin/tool".to_owned()])],
         );
     }
 
     #[test]
-    fn command_default_prefix_override() {
-        let mut tasks = HashMap::new();
-        tasks.insert(
-            "foo".to_owned(),
-            Task {
-                description: None,
-                dependencies: vec![],
-                cache: true,
-                environment: HashMap::new(),
-                input_paths: vec![],
-                excluded_input_paths: vec![],
-                output_paths: vec![],
-                output_paths_on_failure: vec![],
-                mount_paths: vec![],
-                mount_readonly: false,
-                ports: vec![],
-                location: None,
-                user: None,
-                command: String::new(),
-                command_prefix: Some("set -euxo pipefail".to_owned()),
-                extra_docker_arguments: vec![],
-            },
-        );
+    fn missing_input_paths_output_dir() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  generate:
+    output_paths:
+      - gen
+  build:
+    dependencies:
+      - generate
+    input_paths:
+      - out/gen/schema.rs
+      - gen/schema.rs
+",
+        )
+        .unwrap();
 
-        let toastfile = Toastfile {
-            image: "encom:os-12".to_owned(),
-            default: None,
-            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
-            user: DEFAULT_USER.to_owned(),
-            command_prefix: String::new(),
-            tasks,
-        };
+        // The outputs are written under the output directory.
+        assert_eq!(
+            missing_input_path_strings(&toastfile, &["generate", "build"], Some("out"), &[]),
+            vec![("build", vec!["gen/schema.rs".to_owned()])],
+        );
 
+        // If the output directory is outside the toastfile directory, no input path is produced.
         assert_eq!(
-            command(&toastfile, &toastfile.tasks["foo"]),
-            "set -euxo pipefail".to_owned(),
+            missing_input_path_strings(&toastfile, &["generate", "build"], None, &[]),
+            vec![(
+                "build",
+                vec!["out/gen/schema.rs".to_owned(), "gen/schema.rs".to_owned()],
+            )],
         );
     }
 
     #[test]
-    fn command_override_prefix_override() {
-        let mut tasks = HashMap::new();
-        tasks.insert(
-            "foo".to_owned(),
-            Task {
-                description: None,
-                dependencies: vec![],
-                cache: true,
-                environment: HashMap::new(),
-                input_paths: vec![],
-                excluded_input_paths: vec![],
-                output_paths: vec![],
-                output_paths_on_failure: vec![],
-                mount_paths: vec![],
-                mount_readonly: false,
-                ports: vec![],
-                location: None,
-                user: None,
-                command: "echo hello".to_owned(),
-                command_prefix: Some("set -euxo pipefail".to_owned()),
-                extra_docker_arguments: vec![],
-            },
+    fn missing_input_paths_not_produced_by_non_dependency() {
+        // Only dependencies count, even if another task happens to be scheduled first, since it
+        // wouldn't run if the user asked for just the task.
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  generate:
+    output_paths:
+      - gen
+  build:
+    input_paths:
+      - gen
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            missing_input_path_strings(&toastfile, &["generate", "build"], Some(""), &[]),
+            vec![("build", vec!["gen".to_owned()])],
         );
+    }
 
-        let toastfile = Toastfile {
-            image: "encom:os-12".to_owned(),
-            default: None,
-            location: UnixPath::new(DEFAULT_LOCATION).to_owned(),
-            user: DEFAULT_USER.to_owned(),
-            command_prefix: String::new(),
-            tasks,
-        };
+    #[test]
+    fn missing_input_paths_not_produced_by_own_outputs() {
+        let toastfile = parse(
+            r"
+image: encom:os-12
+tasks:
+  build:
+    input_paths:
+      - gen
+    output_paths:
+      - gen
+",
+        )
+        .unwrap();
 
         assert_eq!(
-            command(&toastfile, &toastfile.tasks["foo"]),
-            "set -euxo pipefail\necho hello".to_owned(),
+            missing_input_path_strings(&toastfile, &["build"], Some(""), &[]),
+            vec![("build", vec!["gen".to_owned()])],
         );
     }
 }
@@ -0,0 +1,268 @@
+use {
+    crate::{failure, failure::Failure, format::CodeStr, progress::Progress},
+    std::{
+        fmt::Write as _,
+        fs,
+        path::Path,
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+};
+
+// The category of an event which covers a whole task
+pub const TASK: &str = "task";
+
+// The category of an event which covers one step of a task (e.g., creating the container)
+pub const PHASE: &str = "phase";
+
+// The process and thread IDs determine which row an event appears on in the trace viewer. Tasks
+// currently run one at a time, so all the events go on a single row.
+const PROCESS_ID: u32 = 1;
+const THREAD_ID: u32 = 1;
+
+// A "complete" event in the Chrome Trace Event Format, which has a start time and a duration
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Event {
+    name: String,
+    category: &'static str,
+    start: Duration,
+    duration: Duration,
+    thread: u32,
+}
+
+// This struct records how long each task and phase of a run took, so the run can be viewed on a
+// timeline in `chrome://tracing` or Perfetto. If it's disabled, nothing is recorded. Independently,
+// the tasks and phases are reported to `progress` as they start and finish.
+pub struct Trace {
+    enabled: bool,
+    start: Instant,
+    events: Mutex<Vec<Event>>,
+    progress: Progress,
+}
+
+impl Trace {
+    pub fn new(enabled: bool, progress: Progress) -> Self {
+        Self {
+            enabled,
+            start: Instant::now(),
+            events: Mutex::new(vec![]),
+            progress,
+        }
+    }
+
+    // Start an event. It ends when the returned span is dropped.
+    pub fn span(&self, name: &str, category: &'static str) -> Span<'_> {
+        if category == TASK {
+            self.progress.task_started(name);
+        } else {
+            self.progress.phase_started(name);
+        }
+
+        Span {
+            trace: self,
+            name: name.to_owned(),
+            category,
+            start: Instant::now(),
+            success: false,
+        }
+    }
+
+    // Fetch the destination for progress events which don't correspond to spans.
+    pub fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    // Render the recorded events as JSON in the Chrome Trace Event Format.
+    pub fn to_json(&self) -> String {
+        // Sort the events by when they started, putting enclosing events first. Events are recorded
+        // when they end, so otherwise phases would come before the tasks which contain them. The
+        // `unwrap` will only fail if a panic already occurred.
+        let mut events = self.events.lock().unwrap().clone();
+        events.sort_by(|x, y| x.start.cmp(&y.start).then(y.duration.cmp(&x.duration)));
+
+        let mut json = "{\"traceEvents\":[".to_owned();
+        for (i, event) in events.iter().enumerate() {
+            if i != 0 {
+                json.push(',');
+            }
+
+            // Timestamps and durations are in microseconds.
+            let _ = write!(
+                json,
+                "\n{{\"name\":{},\"cat\":{},\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":{},\
+                 \"tid\":{}}}",
+                json_string(&event.name),
+                json_string(event.category),
+                event.start.as_micros(),
+                event.duration.as_micros(),
+                PROCESS_ID,
+                event.thread,
+            );
+        }
+        json.push_str("\n],\"displayTimeUnit\":\"ms\"}\n");
+
+        json
+    }
+
+    // Write the recorded events to a file.
+    pub fn write(&self, path: &Path) -> Result<(), Failure> {
+        fs::write(path, self.to_json()).map_err(failure::user(format!(
+            "Unable to write the trace to {}.",
+            path.to_string_lossy().code_str(),
+        )))
+    }
+}
+
+// An event which is in progress. The event is recorded when this is dropped.
+pub struct Span<'a> {
+    trace: &'a Trace,
+    name: String,
+    category: &'static str,
+    start: Instant,
+
+    // Whether the task succeeded, for the progress events. Phases don't report this.
+    success: bool,
+}
+
+impl Span<'_> {
+    // End the span of a task, noting whether the task succeeded. Otherwise, a task is reported as
+    // having failed.
+    pub fn finish(mut self, success: bool) {
+        self.success = success;
+    }
+}
+
+impl Drop for Span<'_> {
+    fn drop(&mut self) {
+        if self.category == TASK {
+            self.trace
+                .progress
+                .task_finished(&self.name, self.start.elapsed(), self.success);
+        } else {
+            self.trace
+                .progress
+                .phase_finished(&self.name, self.start.elapsed());
+        }
+
+        if self.trace.enabled {
+            let event = Event {
+                name: std::mem::take(&mut self.name),
+                category: self.category,
+                start: self.start.saturating_duration_since(self.trace.start),
+                duration: self.start.elapsed(),
+                thread: THREAD_ID,
+            };
+
+            // The `unwrap` will only fail if a panic already occurred.
+            self.trace.events.lock().unwrap().push(event);
+        }
+    }
+}
+
+// Encode a string as a JSON string literal.
+pub fn json_string(value: &str) -> String {
+    let mut json = "\"".to_owned();
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", u32::from(c));
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{
+            progress::Progress,
+            trace::{json_string, Event, Trace, PHASE, TASK},
+        },
+        std::time::Duration,
+    };
+
+    #[test]
+    fn trace_to_json() {
+        let trace = Trace::new(true, Progress::new(None));
+        trace.events.lock().unwrap().extend(vec![
+            Event {
+                name: "create".to_owned(),
+                category: PHASE,
+                start: Duration::from_micros(1500),
+                duration: Duration::from_micros(250),
+                thread: 1,
+            },
+            Event {
+                name: "build".to_owned(),
+                category: TASK,
+                start: Duration::from_micros(1500),
+                duration: Duration::from_millis(3),
+                thread: 1,
+            },
+        ]);
+
+        assert_eq!(
+            trace.to_json(),
+            "{\"traceEvents\":[\n\
+             {\"name\":\"build\",\"cat\":\"task\",\"ph\":\"X\",\"ts\":1500,\"dur\":3000,\"pid\":1,\
+             \"tid\":1},\n\
+             {\"name\":\"create\",\"cat\":\"phase\",\"ph\":\"X\",\"ts\":1500,\"dur\":250,\"pid\":1,\
+             \"tid\":1}\n\
+             ],\"displayTimeUnit\":\"ms\"}\n",
+        );
+    }
+
+    #[test]
+    fn trace_to_json_empty() {
+        assert_eq!(
+            Trace::new(true, Progress::new(None)).to_json(),
+            "{\"traceEvents\":[\n],\"displayTimeUnit\":\"ms\"}\n",
+        );
+    }
+
+    #[test]
+    fn trace_span_records_event() {
+        let trace = Trace::new(true, Progress::new(None));
+
+        {
+            let _task = trace.span("build", TASK);
+            let _phase = trace.span("run", PHASE);
+        }
+
+        let events = trace.events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].name, "run");
+        assert_eq!(events[0].category, PHASE);
+        assert_eq!(events[1].name, "build");
+        assert_eq!(events[1].category, TASK);
+        assert!(events[1].start <= events[0].start);
+        assert!(events[1].duration >= events[0].duration);
+    }
+
+    #[test]
+    fn trace_disabled() {
+        let trace = Trace::new(false, Progress::new(None));
+
+        {
+            let _task = trace.span("build", TASK);
+        }
+
+        assert!(trace.events.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn json_string_escapes() {
+        assert_eq!(json_string("build"), "\"build\"");
+        assert_eq!(json_string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(json_string("a\nb\u{1}"), "\"a\\nb\\u0001\"");
+        assert_eq!(json_string("caf\u{e9}"), "\"caf\u{e9}\"");
+    }
+}
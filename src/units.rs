@@ -0,0 +1,402 @@
+use {
+    crate::{failure::Failure, format::CodeStr},
+    std::time::Duration,
+};
+
+// The units of a duration, in nanoseconds. Longer units must come before their prefixes (e.g., `ms`
+// before `m`), since units are matched in order.
+const DURATION_UNITS: &[(&str, u128)] = &[
+    ("ms", 1_000_000),
+    ("s", 1_000_000_000),
+    ("m", 60 * 1_000_000_000),
+    ("h", 60 * 60 * 1_000_000_000),
+    ("d", 24 * 60 * 60 * 1_000_000_000),
+    ("w", 7 * 24 * 60 * 60 * 1_000_000_000),
+];
+
+// The units of a size, in bytes. These are matched case-insensitively. Single letters are binary
+// units, as in Docker (e.g., `512m`), and two-letter units are decimal units.
+const BYTE_UNITS: &[(&str, u128)] = &[
+    ("", 1),
+    ("b", 1),
+    ("k", 1 << 10),
+    ("kib", 1 << 10),
+    ("kb", 1_000),
+    ("m", 1 << 20),
+    ("mib", 1 << 20),
+    ("mb", 1_000_000),
+    ("g", 1 << 30),
+    ("gib", 1 << 30),
+    ("gb", 1_000_000_000),
+    ("t", 1 << 40),
+    ("tib", 1 << 40),
+    ("tb", 1_000_000_000_000),
+];
+
+// A nonnegative decimal number, split into its whole and fractional parts. For example, `1.25` is
+// represented as `whole: 1, fraction: 25, fraction_digits: 2`.
+struct Number {
+    whole: u128,
+    fraction: u128,
+    fraction_digits: u32,
+}
+
+impl Number {
+    // Multiply the number by a unit, rounding down. Also returns whether the result is exact.
+    fn scale(&self, unit: u128) -> Option<(u128, bool)> {
+        let denominator = 10_u128.checked_pow(self.fraction_digits)?;
+        let fraction = self.fraction.checked_mul(unit)?;
+        let value = self
+            .whole
+            .checked_mul(unit)?
+            .checked_add(fraction / denominator)?;
+        Some((value, fraction % denominator == 0))
+    }
+}
+
+// Split a number off the front of a string. Returns `None` if the string doesn't start with a digit
+// or the number is too large.
+fn split_number(value: &str) -> Option<(Number, &str)> {
+    let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+
+    let whole_digits = digits(value);
+    if whole_digits == 0 {
+        return None;
+    }
+    let whole = value[..whole_digits].parse().ok()?;
+    let rest = &value[whole_digits..];
+
+    if let Some(rest) = rest.strip_prefix('.') {
+        let fraction_digits = digits(rest);
+        if fraction_digits == 0 {
+            return None;
+        }
+
+        Some((
+            Number {
+                whole,
+                fraction: rest[..fraction_digits].parse().ok()?,
+                fraction_digits: u32::try_from(fraction_digits).ok()?,
+            },
+            &rest[fraction_digits..],
+        ))
+    } else {
+        Some((
+            Number {
+                whole,
+                fraction: 0,
+                fraction_digits: 0,
+            },
+            rest,
+        ))
+    }
+}
+
+// Construct the error for an invalid value. `field` describes what the value is for, e.g.,
+// "The `cache_ttl` of task `foo`".
+fn invalid(field: &str, value: &str, kind: &str, reason: &str, examples: [&str; 3]) -> Failure {
+    Failure::User(
+        format!(
+            "{} is {}, which isn't a valid {}. {} It should be a number followed by a unit, as in \
+             {}, {}, or {}.",
+            field,
+            value.code_str(),
+            kind,
+            reason,
+            examples[0].code_str(),
+            examples[1].code_str(),
+            examples[2].code_str(),
+        ),
+        None,
+    )
+}
+
+// Parse a duration given as a sequence of numbers with units, e.g., `90s`, `1.5h`, or `1d12h`. The
+// units are `ms`, `s`, `m` (minutes), `h`, `d`, and `w`. A number without a unit is ambiguous, so
+// it's rejected, except for `0`. `field` describes what the duration is for, for error messages.
+pub fn parse_duration(value: &str, field: &str) -> Result<Duration, Failure> {
+    const EXAMPLES: [&str; 3] = ["30s", "12h", "1d12h"];
+    let error = |reason: &str| invalid(field, value, "duration", reason, EXAMPLES);
+
+    let trimmed = value.trim();
+    if trimmed.is_empty() {
+        return Err(error("It's empty."));
+    }
+    if trimmed.starts_with('-') {
+        return Err(error("Durations can't be negative."));
+    }
+    if trimmed == "0" {
+        return Ok(Duration::ZERO);
+    }
+
+    let mut nanoseconds = 0_u128;
+    let mut rest = trimmed;
+    while !rest.is_empty() {
+        let (number, after_number) =
+            split_number(rest).ok_or_else(|| error("It doesn't start with a number."))?;
+        let unit_length = after_number
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(after_number.len());
+        if unit_length == 0 {
+            return Err(error("It's missing a unit."));
+        }
+        let unit = &after_number[..unit_length];
+        let (_, unit_nanoseconds) = DURATION_UNITS
+            .iter()
+            .find(|(name, _)| *name == unit)
+            .ok_or_else(|| error(&format!("{} isn't a unit of time.", unit.code_str())))?;
+
+        let (term, _) = number
+            .scale(*unit_nanoseconds)
+            .ok_or_else(|| error("It's too large."))?;
+        nanoseconds = nanoseconds
+            .checked_add(term)
+            .ok_or_else(|| error("It's too large."))?;
+        rest = &after_number[unit_length..];
+    }
+
+    let seconds =
+        u64::try_from(nanoseconds / 1_000_000_000).map_err(|_| error("It's too large."))?;
+
+    // The `unwrap` is safe because the remainder is less than a billion.
+    Ok(Duration::new(
+        seconds,
+        u32::try_from(nanoseconds % 1_000_000_000).unwrap(),
+    ))
+}
+
+// Parse a size given as a number with an optional unit, e.g., `512`, `512m`, `1.5GiB`, or `2 GB`.
+// Single-letter units (`k`, `m`, `g`, and `t`) and IEC units (`KiB`, etc.) are binary, and
+// two-letter units (`kB`, etc.) are decimal. Units are case-insensitive. `field` describes what the
+// size is for, for error messages.
+pub fn parse_bytes(value: &str, field: &str) -> Result<u64, Failure> {
+    const EXAMPLES: [&str; 3] = ["4096", "512m", "2GiB"];
+    let error = |reason: &str| invalid(field, value, "size", reason, EXAMPLES);
+
+    let trimmed = value.trim();
+    if trimmed.is_empty() {
+        return Err(error("It's empty."));
+    }
+    if trimmed.starts_with('-') {
+        return Err(error("Sizes can't be negative."));
+    }
+
+    let (number, rest) =
+        split_number(trimmed).ok_or_else(|| error("It doesn't start with a number."))?;
+    let unit = rest.trim_start().to_ascii_lowercase();
+    let (_, unit_bytes) = BYTE_UNITS
+        .iter()
+        .find(|(name, _)| *name == unit)
+        .ok_or_else(|| error(&format!("{} isn't a unit of size.", rest.trim().code_str())))?;
+
+    let (bytes, exact) = number
+        .scale(*unit_bytes)
+        .ok_or_else(|| error("It's too large."))?;
+    if !exact {
+        return Err(error("It isn't a whole number of bytes."));
+    }
+
+    u64::try_from(bytes).map_err(|_| error("It's too large."))
+}
+
+// Format a duration in whole seconds, e.g., "1h 2m 3s", "1m 42s", or "7s".
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{hours}h {minutes}m {seconds}s")
+    } else if minutes > 0 {
+        format!("{minutes}m {seconds}s")
+    } else {
+        format!("{seconds}s")
+    }
+}
+
+// Format a number of bytes in human-readable binary units, e.g., "512 B" or "1.5 MiB".
+#[allow(clippy::cast_precision_loss)]
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0_f64 && unit + 1 < UNITS.len() {
+        value /= 1024.0_f64;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::units::{format_bytes, format_duration, parse_bytes, parse_duration},
+        std::time::Duration,
+    };
+
+    #[test]
+    fn parse_duration_valid() {
+        for (value, expected) in [
+            ("0", Duration::ZERO),
+            ("0s", Duration::ZERO),
+            ("0.0h", Duration::ZERO),
+            ("250ms", Duration::from_millis(250)),
+            ("90s", Duration::from_secs(90)),
+            ("30m", Duration::from_mins(30)),
+            ("12h", Duration::from_hours(12)),
+            ("7d", Duration::from_hours(7 * 24)),
+            ("2w", Duration::from_hours(14 * 24)),
+            ("1d12h", Duration::from_hours(36)),
+            ("1m30s500ms", Duration::from_millis(90_500)),
+            (" 1h30m ", Duration::from_mins(90)),
+            ("1.5h", Duration::from_mins(90)),
+            ("0.25s", Duration::from_millis(250)),
+            ("1.5ms", Duration::from_micros(1500)),
+            ("0.0000000001s", Duration::ZERO),
+            ("007s", Duration::from_secs(7)),
+        ] {
+            assert_eq!(parse_duration(value, "It").unwrap(), expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn parse_duration_invalid() {
+        for value in [
+            "",
+            "  ",
+            "7",
+            "1.5",
+            "d",
+            "h7",
+            "7x",
+            "7mm",
+            "7 d",
+            "7d 12h",
+            "7D",
+            "-1d",
+            "-0",
+            ".5h",
+            "5.h",
+            "1.2.3s",
+            "1h-30m",
+            "99999999999999999999w",
+            "999999999999999999999999999999999999999999s",
+        ] {
+            assert!(parse_duration(value, "It").is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn parse_duration_error_message() {
+        assert_eq!(
+            parse_duration("7", "The `cache_ttl` of task `foo`")
+                .unwrap_err()
+                .to_string(),
+            "The `cache_ttl` of task `foo` is `7`, which isn't a valid duration. It's missing a \
+             unit. It should be a number followed by a unit, as in `30s`, `12h`, or `1d12h`.",
+        );
+        assert_eq!(
+            parse_duration("-1d", "It").unwrap_err().to_string(),
+            "It is `-1d`, which isn't a valid duration. Durations can't be negative. It should be \
+             a number followed by a unit, as in `30s`, `12h`, or `1d12h`.",
+        );
+        assert!(parse_duration("3y", "It")
+            .unwrap_err()
+            .to_string()
+            .contains("`y` isn't a unit of time."));
+    }
+
+    #[test]
+    fn parse_bytes_valid() {
+        for (value, expected) in [
+            ("0", 0),
+            ("0b", 0),
+            ("4096", 4096),
+            ("4096B", 4096),
+            ("1k", 1024),
+            ("1K", 1024),
+            ("1KiB", 1024),
+            ("1kB", 1000),
+            ("512m", 512 * 1024 * 1024),
+            ("512 MiB", 512 * 1024 * 1024),
+            ("3MB", 3_000_000),
+            ("2GiB", 2 * 1024 * 1024 * 1024),
+            ("2g", 2 * 1024 * 1024 * 1024),
+            ("5GB", 5_000_000_000),
+            ("1TiB", 1024 * 1024 * 1024 * 1024),
+            ("1tb", 1_000_000_000_000),
+            ("1.5k", 1536),
+            ("0.5GiB", 512 * 1024 * 1024),
+            ("1.000", 1),
+            (" 10 ", 10),
+        ] {
+            assert_eq!(parse_bytes(value, "It").unwrap(), expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn parse_bytes_invalid() {
+        for value in [
+            "",
+            " ",
+            "k",
+            "-1",
+            "-1k",
+            "1.5",
+            "0.1k",
+            "1.k",
+            ".5m",
+            "1x",
+            "1 k b",
+            "1pb",
+            "1e3",
+            "20000000000000000000",
+            "16777216TiB",
+            "999999999999999999999999999999999999999999",
+        ] {
+            assert!(parse_bytes(value, "It").is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn parse_bytes_error_message() {
+        assert_eq!(
+            parse_bytes("1.5", "The `input_size_warning_bytes` setting")
+                .unwrap_err()
+                .to_string(),
+            "The `input_size_warning_bytes` setting is `1.5`, which isn't a valid size. It isn't a \
+             whole number of bytes. It should be a number followed by a unit, as in `4096`, \
+             `512m`, or `2GiB`.",
+        );
+    }
+
+    #[test]
+    fn format_duration_seconds() {
+        assert_eq!(format_duration(Duration::from_millis(7900)), "7s");
+    }
+
+    #[test]
+    fn format_duration_minutes() {
+        assert_eq!(format_duration(Duration::from_secs(102)), "1m 42s");
+    }
+
+    #[test]
+    fn format_duration_hours() {
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1h 2m 5s");
+    }
+
+    #[test]
+    fn format_bytes_small() {
+        assert_eq!(format_bytes(512), "512 B");
+    }
+
+    #[test]
+    fn format_bytes_large() {
+        assert_eq!(format_bytes(3 * 1024 * 1024 / 2), "1.5 MiB");
+        assert_eq!(format_bytes(2 * 1024 * 1024 * 1024), "2.0 GiB");
+    }
+}
@@ -0,0 +1,278 @@
+use {
+    crate::paths,
+    serde::Deserialize,
+    std::{
+        fs::{create_dir_all, read_to_string, write},
+        path::{Path, PathBuf},
+        process::{Command, Stdio},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+};
+
+// Where the time of the last update check is stored, relative to the cache directory
+const STATE_FILE: &str = "update-check";
+
+// How often to check for a new release
+const CHECK_INTERVAL: Duration = Duration::from_hours(24);
+
+// Where to find the latest release
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/stepchowfun/toast/releases/latest";
+
+// How long to wait for the latest release, in seconds. The check shouldn't noticeably slow down
+// the run.
+const FETCH_TIMEOUT_SECS: &str = "3";
+
+// The program used to fetch the latest release
+const CURL_CLI: &str = "curl";
+
+// The part of a release, as reported by the GitHub API, that we care about
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+}
+
+// Let the user know if there's a newer release than the given version. This checks at most once
+// per `CHECK_INTERVAL`, and it never fails the run: if anything goes wrong (e.g., the network is
+// down), it silently gives up until the next check is due.
+pub fn check(current_version: &str) {
+    if let Some(state_path) = state_path() {
+        if let Some(latest_version) = newer_release(
+            current_version,
+            &state_path,
+            SystemTime::now(),
+            fetch_latest_release,
+        ) {
+            info!(
+                "Toast {latest_version} is available (you have {current_version}). See \
+                 https://github.com/stepchowfun/toast/releases for what changed.",
+            );
+        }
+    }
+}
+
+// Determine where the time of the last update check is stored.
+fn state_path() -> Option<PathBuf> {
+    paths::cache_dir().map(|dir| dir.join(STATE_FILE))
+}
+
+// Fetch the latest release from GitHub, if possible.
+fn fetch_latest_release() -> Option<String> {
+    let output = Command::new(CURL_CLI)
+        .args([
+            "--silent",
+            "--fail",
+            "--location",
+            "--max-time",
+            FETCH_TIMEOUT_SECS,
+            LATEST_RELEASE_URL,
+        ])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+
+    if output.status.success() {
+        String::from_utf8(output.stdout).ok()
+    } else {
+        None
+    }
+}
+
+// Return the latest version if it's newer than the current one and a check is due. The time of
+// the check is recorded before `fetch` is called, so a failed check isn't retried on every run.
+fn newer_release(
+    current_version: &str,
+    state_path: &Path,
+    now: SystemTime,
+    fetch: impl FnOnce() -> Option<String>,
+) -> Option<String> {
+    if !check_due(state_path, now) {
+        return None;
+    }
+
+    record_check(state_path, now);
+
+    // The GitHub API responds with JSON, which is also YAML.
+    let release = serde_yaml::from_str::<Release>(&fetch()?).ok()?;
+    let latest_version = release
+        .tag_name
+        .strip_prefix('v')
+        .unwrap_or(&release.tag_name);
+
+    if parse_version(latest_version)? > parse_version(current_version)? {
+        Some(latest_version.to_owned())
+    } else {
+        None
+    }
+}
+
+// Determine whether it's been long enough since the last check. A check time in the future (e.g.,
+// due to clock skew) doesn't count, so it can't suppress checks indefinitely.
+fn check_due(state_path: &Path, now: SystemTime) -> bool {
+    read_to_string(state_path)
+        .ok()
+        .and_then(|contents| contents.trim().parse::<u64>().ok())
+        .is_none_or(|last_check| {
+            now.duration_since(UNIX_EPOCH + Duration::from_secs(last_check))
+                .map_or(true, |elapsed| elapsed >= CHECK_INTERVAL)
+        })
+}
+
+// Remember when the last check happened. Failures are ignored, since the worst case is that we
+// check again on the next run.
+fn record_check(state_path: &Path, now: SystemTime) {
+    if let Ok(since_epoch) = now.duration_since(UNIX_EPOCH) {
+        if let Some(parent) = state_path.parent() {
+            let _ = create_dir_all(parent);
+        }
+        let _ = write(state_path, since_epoch.as_secs().to_string());
+    }
+}
+
+// Parse a version of the form `MAJOR.MINOR.PATCH`. Anything else (e.g., a pre-release) is ignored.
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = version
+        .trim()
+        .split('.')
+        .map(|part| part.parse::<u64>().ok());
+    let version = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() {
+        None
+    } else {
+        Some(version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::update::{newer_release, parse_version, CHECK_INTERVAL},
+        std::{
+            cell::Cell,
+            fs::{read_to_string, write},
+            time::{Duration, UNIX_EPOCH},
+        },
+        tempfile::tempdir,
+    };
+
+    const RELEASE: &str = r#"{"tag_name": "v0.48.0", "name": "0.48.0", "draft": false}"#;
+
+    #[test]
+    fn parse_version_valid() {
+        assert_eq!(parse_version("0.47.6"), Some((0, 47, 6)));
+        assert_eq!(parse_version("10.0.12\n"), Some((10, 0, 12)));
+    }
+
+    #[test]
+    fn parse_version_invalid() {
+        assert_eq!(parse_version("0.47"), None);
+        assert_eq!(parse_version("0.47.6.1"), None);
+        assert_eq!(parse_version("0.48.0-rc.1"), None);
+        assert_eq!(parse_version("latest"), None);
+    }
+
+    #[test]
+    fn newer_release_newer() {
+        let dir = tempdir().unwrap();
+        let state_path = dir.path().join("toast/update-check");
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        assert_eq!(
+            newer_release("0.47.6", &state_path, now, || Some(RELEASE.to_owned())),
+            Some("0.48.0".to_owned()),
+        );
+        assert_eq!(read_to_string(&state_path).unwrap(), "1000000");
+    }
+
+    #[test]
+    fn newer_release_same_or_older() {
+        let dir = tempdir().unwrap();
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        assert_eq!(
+            newer_release("0.48.0", &dir.path().join("a"), now, || Some(
+                RELEASE.to_owned()
+            )),
+            None,
+        );
+        assert_eq!(
+            newer_release("0.100.0", &dir.path().join("b"), now, || Some(
+                RELEASE.to_owned()
+            )),
+            None,
+        );
+    }
+
+    #[test]
+    fn newer_release_throttled() {
+        let dir = tempdir().unwrap();
+        let state_path = dir.path().join("update-check");
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        write(&state_path, "1000000").unwrap();
+        let fetches = Cell::new(0_usize);
+        let fetch = || {
+            fetches.set(fetches.get() + 1);
+            Some(RELEASE.to_owned())
+        };
+
+        assert_eq!(
+            newer_release("0.47.6", &state_path, now + CHECK_INTERVAL / 2, fetch),
+            None,
+        );
+        assert_eq!(fetches.get(), 0);
+
+        assert_eq!(
+            newer_release("0.47.6", &state_path, now + CHECK_INTERVAL, fetch),
+            Some("0.48.0".to_owned()),
+        );
+        assert_eq!(fetches.get(), 1);
+    }
+
+    #[test]
+    fn newer_release_future_check() {
+        let dir = tempdir().unwrap();
+        let state_path = dir.path().join("update-check");
+        write(&state_path, "2000000").unwrap();
+
+        assert_eq!(
+            newer_release(
+                "0.47.6",
+                &state_path,
+                UNIX_EPOCH + Duration::from_secs(1_000_000),
+                || Some(RELEASE.to_owned()),
+            ),
+            Some("0.48.0".to_owned()),
+        );
+    }
+
+    #[test]
+    fn newer_release_fetch_failed() {
+        let dir = tempdir().unwrap();
+        let state_path = dir.path().join("update-check");
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        assert_eq!(newer_release("0.47.6", &state_path, now, || None), None);
+
+        // The failed check still counts, so it isn't retried right away.
+        assert_eq!(read_to_string(&state_path).unwrap(), "1000000");
+    }
+
+    #[test]
+    fn newer_release_invalid_response() {
+        let dir = tempdir().unwrap();
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        assert_eq!(
+            newer_release("0.47.6", &dir.path().join("a"), now, || Some(
+                "<html>rate limited</html>".to_owned()
+            )),
+            None,
+        );
+        assert_eq!(
+            newer_release("0.47.6", &dir.path().join("b"), now, || Some(
+                r#"{"tag_name": "nightly"}"#.to_owned()
+            )),
+            None,
+        );
+    }
+}
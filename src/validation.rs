@@ -0,0 +1,130 @@
+use {
+    crate::{failure, failure::Failure, format::CodeStr, paths, VERSION},
+    serde::{Deserialize, Serialize},
+    std::{
+        fs::{create_dir_all, read_to_string, write},
+        path::{Path, PathBuf},
+    },
+};
+
+// Where the record of the last version of the toastfile which passed validation is stored, relative
+// to the state directory for the toastfile
+const STATE_FILE: &str = "validated.yml";
+
+// The toastfile which passed validation, identified by the hash of its contents. The checks can
+// change between versions of Toast, so the version is recorded too.
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct State {
+    version: String,
+    hash: String,
+}
+
+// Determine where the record is stored.
+fn state_path(toastfile_path: &Path) -> Option<PathBuf> {
+    paths::project_state_dir(toastfile_path).map(|dir| dir.join(STATE_FILE))
+}
+
+// Check whether a toastfile with the given content hash already passed validation. A record which
+// is missing, can't be read, or is from another version of Toast doesn't count.
+pub fn validated(toastfile_path: &Path, hash: &str) -> bool {
+    state_path(toastfile_path).is_some_and(|state_path| validated_at(&state_path, hash))
+}
+
+fn validated_at(state_path: &Path, hash: &str) -> bool {
+    read_to_string(state_path)
+        .ok()
+        .and_then(|contents| serde_yaml::from_str::<State>(&contents).ok())
+        .is_some_and(|state| state.version == VERSION && state.hash == hash)
+}
+
+// Remember that a toastfile with the given content hash passed validation.
+pub fn record(toastfile_path: &Path, hash: &str) -> Result<(), Failure> {
+    state_path(toastfile_path).map_or(Ok(()), |state_path| record_at(&state_path, hash))
+}
+
+fn record_at(state_path: &Path, hash: &str) -> Result<(), Failure> {
+    let state = State {
+        version: VERSION.to_owned(),
+        hash: hash.to_owned(),
+    };
+
+    // The `unwrap` is safe since the path is in the state directory.
+    let state_dir = state_path.parent().unwrap();
+    create_dir_all(state_dir).map_err(failure::system(format!(
+        "Unable to create directory {}.",
+        state_dir.to_string_lossy().code_str(),
+    )))?;
+
+    write(
+        state_path,
+        serde_yaml::to_string(&state).map_err(failure::system(
+            "Unable to serialize the validation record.",
+        ))?,
+    )
+    .map_err(failure::system(format!(
+        "Unable to write file {}.",
+        state_path.to_string_lossy().code_str(),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::validation::{record_at, validated_at},
+        std::fs::write,
+        tempfile::tempdir,
+    };
+
+    #[test]
+    fn validated_hit() {
+        let dir = tempdir().unwrap();
+        let state_path = dir
+            .path()
+            .join("projects")
+            .join("foo")
+            .join("validated.yml");
+
+        record_at(&state_path, "abc").unwrap();
+
+        assert!(validated_at(&state_path, "abc"));
+    }
+
+    #[test]
+    fn validated_miss() {
+        let dir = tempdir().unwrap();
+        let state_path = dir.path().join("validated.yml");
+
+        assert!(!validated_at(&state_path, "abc"));
+
+        record_at(&state_path, "abc").unwrap();
+        record_at(&state_path, "def").unwrap();
+
+        assert!(!validated_at(&state_path, "abc"));
+        assert!(validated_at(&state_path, "def"));
+    }
+
+    #[test]
+    fn validated_corrupt() {
+        let dir = tempdir().unwrap();
+        let state_path = dir.path().join("validated.yml");
+
+        write(&state_path, "\u{0}hash: [abc").unwrap();
+        assert!(!validated_at(&state_path, "abc"));
+
+        write(&state_path, "hash: abc\n").unwrap();
+        assert!(!validated_at(&state_path, "abc"));
+
+        record_at(&state_path, "abc").unwrap();
+        assert!(validated_at(&state_path, "abc"));
+    }
+
+    #[test]
+    fn validated_other_version() {
+        let dir = tempdir().unwrap();
+        let state_path = dir.path().join("validated.yml");
+
+        write(&state_path, "version: 0.0.0\nhash: abc\n").unwrap();
+
+        assert!(!validated_at(&state_path, "abc"));
+    }
+}
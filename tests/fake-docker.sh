@@ -0,0 +1,105 @@
+#!/usr/bin/env bash
+set -euo pipefail
+
+# A fake Docker CLI for the integration tests. It records each invocation and responds according to
+# a scenario file, so the tests can check how Toast talks to Docker without a Docker daemon. It's
+# configured with these environment variables:
+#
+# - `FAKE_DOCKER_SCENARIO`: the path to the scenario file
+# - `FAKE_DOCKER_LOG`: the path to the file where the invocations are recorded
+#
+# A scenario is a list of rules. Each rule starts with a glob pattern in brackets (e.g.,
+# `[container create *]`) on its own line, which is matched against the arguments joined by spaces.
+# The first rule which matches determines how the fake responds, as given by the lines after it:
+#
+# - `exit CODE`: exit with the given code (by default, 0)
+# - `stdout TEXT`: print the text and a newline to standard output (this can be repeated)
+# - `stderr TEXT`: print the text and a newline to standard error (this can be repeated)
+# - `stdout-file PATH`: copy the contents of a file to standard output
+# - `copy PATH`: copy a file or directory to the path given by the last argument, like
+#   `container cp` does when copying out of a container
+# - `delay SECONDS`: wait before responding
+#
+# Blank lines and lines starting with `#` are ignored. If no rule matches, the fake succeeds without
+# printing anything.
+#
+# Each invocation is recorded as a line in the log, with the arguments separated by the ASCII unit
+# separator (`\x1f`). Backslashes and newlines in the arguments are escaped as `\\` and `\n`.
+
+# Record the invocation.
+RECORD=''
+for ARG in "$@"; do
+  ARG="${ARG//\\/\\\\}"
+  ARG="${ARG//$'\n'/\\n}"
+  if [ -n "$RECORD" ]; then
+    RECORD+=$'\x1f'
+  fi
+  RECORD+="$ARG"
+done
+printf '%s\n' "$RECORD" >> "$FAKE_DOCKER_LOG"
+
+# Consume anything Toast sends on standard input (e.g., the input files for `container cp`), so it
+# doesn't fail to write to a closed pipe.
+if [ ! -t 0 ]; then
+  cat > /dev/null
+fi
+
+# Find the first rule which matches the invocation.
+MATCHED=0
+DIRECTIVES=()
+while IFS= read -r LINE || [ -n "$LINE" ]; do
+  case "$LINE" in
+    '' | '#'*)
+      ;;
+    '['*']')
+      if [ "$MATCHED" = 1 ]; then
+        break
+      fi
+      PATTERN="${LINE:1:${#LINE}-2}"
+      # shellcheck disable=SC2053
+      if [[ "$*" == $PATTERN ]]; then
+        MATCHED=1
+      fi
+      ;;
+    *)
+      if [ "$MATCHED" = 1 ]; then
+        DIRECTIVES+=("$LINE")
+      fi
+      ;;
+  esac
+done < "$FAKE_DOCKER_SCENARIO"
+
+if [ "$MATCHED" = 0 ]; then
+  exit 0
+fi
+
+# Respond as the rule says.
+CODE=0
+for DIRECTIVE in "${DIRECTIVES[@]+"${DIRECTIVES[@]}"}"; do
+  case "$DIRECTIVE" in
+    'exit '*)
+      CODE="${DIRECTIVE#exit }"
+      ;;
+    'stdout '*)
+      printf '%s\n' "${DIRECTIVE#stdout }"
+      ;;
+    'stderr '*)
+      printf '%s\n' "${DIRECTIVE#stderr }" >&2
+      ;;
+    'stdout-file '*)
+      cat "${DIRECTIVE#stdout-file }"
+      ;;
+    'copy '*)
+      cp -R "${DIRECTIVE#copy }" "${!#}"
+      ;;
+    'delay '*)
+      sleep "${DIRECTIVE#delay }"
+      ;;
+    *)
+      echo "Unknown directive in the scenario: $DIRECTIVE" >&2
+      exit 125
+      ;;
+  esac
+done
+
+exit "$CODE"
@@ -0,0 +1,1157 @@
+// These tests run the Toast binary against a fake Docker CLI (`tests/fake-docker.sh`), which
+// responds according to a scenario and records how it was called. This covers the interaction with
+// Docker (the arguments Toast passes, how it interprets failures, etc.) without needing a Docker
+// daemon. The fake is a Bash script, so these tests only run on Unix-like platforms.
+#![cfg(unix)]
+
+use {
+    std::{
+        fs,
+        io::{BufRead, BufReader},
+        os::unix::fs::PermissionsExt,
+        path::PathBuf,
+        process::{Command, Output, Stdio},
+    },
+    tempfile::{tempdir, TempDir},
+};
+
+// The fake Docker CLI
+const FAKE_DOCKER: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fake-docker.sh");
+
+// The exit code for user failures, such as an invalid command-line argument
+const USER_EXIT_CODE: i32 = 1;
+
+// The exit code for system failures, such as not being able to reach the Docker daemon
+const SYSTEM_EXIT_CODE: i32 = 70;
+
+// A project with a toastfile and a scenario for the fake Docker CLI. Everything Toast reads or
+// writes outside the project (e.g., the configuration file and the cache directory) is kept in the
+// same temporary directory, so the tests don't depend on the user's environment.
+struct Project {
+    dir: TempDir,
+}
+
+impl Project {
+    fn new(toastfile: &str, scenario: &str) -> Self {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("toast.yml"), toastfile).unwrap();
+        fs::write(dir.path().join("scenario"), scenario).unwrap();
+        fs::write(dir.path().join("config.yml"), "{}").unwrap();
+        Self { dir }
+    }
+
+    // Determine the path to a file in the project.
+    fn path(&self, path: &str) -> PathBuf {
+        self.dir.path().join(path)
+    }
+
+    // Prepare to run Toast in the project with the given arguments.
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_toast"));
+        command
+            .current_dir(self.dir.path())
+            .args(["--config-file", "config.yml", "--docker-cli", FAKE_DOCKER])
+            .args(args)
+            .env("FAKE_DOCKER_SCENARIO", self.path("scenario"))
+            .env("FAKE_DOCKER_LOG", self.path("docker.log"))
+            .env("HOME", self.dir.path())
+            .env("XDG_CACHE_HOME", self.path("cache"))
+            .env("XDG_CONFIG_HOME", self.path("config"))
+            .env("XDG_STATE_HOME", self.path("state"))
+            .env("NO_COLOR", "true")
+            .env("TOAST_NO_SPINNER", "true")
+            .stdin(Stdio::null());
+        command
+    }
+
+    // Run Toast in the project with the given arguments.
+    fn run(&self, args: &[&str]) -> Output {
+        self.command(args).output().unwrap()
+    }
+
+    // Read the arguments of each invocation of the fake Docker CLI, in order.
+    fn calls(&self) -> Vec<Vec<String>> {
+        fs::read_to_string(self.path("docker.log"))
+            .unwrap_or_default()
+            .lines()
+            .map(|line| line.split('\u{1f}').map(unescape).collect())
+            .collect()
+    }
+
+    // Summarize each invocation of the fake Docker CLI as its first two arguments (e.g.,
+    // `container create`), which is enough to tell what Toast asked Docker to do.
+    fn commands(&self) -> Vec<String> {
+        self.calls()
+            .iter()
+            .map(|call| call.iter().take(2).cloned().collect::<Vec<_>>().join(" "))
+            .collect()
+    }
+}
+
+// Undo the escaping of an argument in the log of the fake Docker CLI.
+fn unescape(arg: &str) -> String {
+    let mut result = String::new();
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some(c) => result.push(c),
+                None => result.push('\\'),
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+// Fetch what Toast logged.
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+// Fetch what Toast printed to standard output, such as the summary of the run.
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+const TWO_TASKS: &str = r"
+image: alpine
+tasks:
+  build:
+    command: make
+  test:
+    dependencies:
+      - build
+    command: make test
+";
+
+#[test]
+fn cache_hit_chain() {
+    // Every image exists, so both tasks are in the local cache.
+    let project = Project::new(
+        TWO_TASKS,
+        r"
+[version --format *]
+stdout amd64
+",
+    );
+
+    let output = project.run(&[]);
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        project.commands(),
+        vec!["version --format", "image inspect", "image inspect"],
+    );
+    let calls = project.calls();
+    assert!(calls[1][2].starts_with("toast:toast-"));
+    assert!(calls[2][2].starts_with("toast:toast-"));
+    assert_ne!(calls[1][2], calls[2][2]);
+    assert!(!stderr(&output).contains("Creating container"));
+    assert!(stdout(&output).starts_with("toast: 2 tasks, 2 cached, 0 ran, 0 failed, "));
+}
+
+#[test]
+fn outputs_up_to_date_without_docker() {
+    let project = Project::new(
+        r"
+image: alpine
+tasks:
+  build:
+    input_paths:
+      - Makefile
+    command: make
+  test:
+    dependencies:
+      - build
+    command: make test
+",
+        r"
+[version --format *]
+stdout amd64
+",
+    );
+    fs::write(project.path("Makefile"), "all:\n").unwrap();
+
+    // Nothing was recorded yet, so the input files are only read once for each task.
+    let output = project.run(&[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stderr(&output).matches("Reading files").count(), 2);
+
+    // The architecture of the daemon is stored with the record, so Docker isn't needed to tell
+    // that the outputs are up to date.
+    fs::remove_file(project.path("docker.log")).unwrap();
+    let output = project.run(&[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Everything up to date."));
+    assert!(project.commands().is_empty());
+
+    // A change to the input files is noticed.
+    fs::write(project.path("Makefile"), "all:\n\ttrue\n").unwrap();
+    let output = project.run(&[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("Everything up to date."));
+}
+
+#[test]
+fn architecture_per_docker_cli() {
+    let project = Project::new(
+        r"
+image: alpine
+tasks:
+  build:
+    command: make
+  deploy:
+    dependencies:
+      - build
+    image: alpine
+    docker_cli: ./other-docker.sh
+    command: make deploy
+",
+        r"
+[version --format *]
+stdout amd64
+",
+    );
+    fs::write(
+        project.path("other-docker.sh"),
+        format!("#!/bin/sh\nFAKE_DOCKER_SCENARIO=other-scenario exec {FAKE_DOCKER} \"$@\"\n"),
+    )
+    .unwrap();
+    fs::set_permissions(
+        project.path("other-docker.sh"),
+        fs::Permissions::from_mode(0o755),
+    )
+    .unwrap();
+    fs::write(
+        project.path("other-scenario"),
+        "[version --format *]\nstdout arm64\n",
+    )
+    .unwrap();
+
+    // Each Docker daemon is asked for its architecture once.
+    let output = project.run(&[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        project
+            .commands()
+            .iter()
+            .filter(|command| *command == "version --format")
+            .count(),
+        2,
+    );
+}
+
+#[test]
+fn cold_run_with_commit_and_push() {
+    // The base image exists, but the image for the task doesn't, so the task runs and the result is
+    // committed and pushed.
+    let project = Project::new(
+        r"
+image: alpine
+tasks:
+  build:
+    environment:
+      MODE: release
+    command: make
+",
+        r"
+[version --format *]
+stdout amd64
+
+[image inspect --format {{.Size}} *]
+stdout 1000
+
+[image inspect alpine]
+
+[image inspect *]
+stderr Error: No such image
+exit 1
+
+[container create *]
+stdout f3a1
+",
+    );
+
+    let output = project.run(&[
+        "--docker-repo",
+        "registry.example.com/toast",
+        "--write-remote-cache",
+        "true",
+    ]);
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        project.commands(),
+        vec![
+            "version --format",
+            "image inspect",
+            "image inspect",
+            "container create",
+            "container cp",
+            "container start",
+            "container commit",
+            "image push",
+            "image inspect",
+            "container rm",
+        ],
+    );
+
+    let calls = project.calls();
+    let image = &calls[1][2];
+    assert!(image.starts_with("registry.example.com/toast:toast-"));
+    assert!(calls[3]
+        .windows(2)
+        .any(|pair| pair == ["--env", "MODE=release"]));
+    assert!(calls[3].ends_with(&[
+        "alpine".to_owned(),
+        "/bin/su".to_owned(),
+        "-c".to_owned(),
+        "make".to_owned(),
+        "root".to_owned(),
+    ]));
+    assert_eq!(calls[4], vec!["container", "cp", "-", "f3a1:/"]);
+    assert_eq!(calls[5], vec!["container", "start", "--attach", "f3a1"]);
+    assert_eq!(calls[6], vec!["container", "commit", "f3a1", image]);
+    assert_eq!(calls[7], vec!["image", "push", image]);
+    assert_eq!(
+        calls[9],
+        vec!["container", "rm", "--force", "--volumes", "f3a1"]
+    );
+}
+
+#[test]
+fn task_failure_with_output_paths_on_failure() {
+    let project = Project::new(
+        r"
+image: alpine
+tasks:
+  test:
+    cache: false
+    command: make test
+    output_paths_on_failure:
+      - report.txt
+",
+        r"
+[version --format *]
+stdout amd64
+
+[container create *]
+stdout f3a1
+
+[container start *]
+stderr 1 test failed
+exit 3
+
+[container cp f3a1:/scratch/report.txt *]
+copy report-in-container.txt
+",
+    );
+    fs::write(project.path("report-in-container.txt"), "1 test failed\n").unwrap();
+
+    let output = project.run(&[]);
+
+    // The exit code of the command is passed along.
+    assert_eq!(output.status.code(), Some(3_i32));
+    assert!(stderr(&output).contains("Task `test` failed during command execution"));
+    assert!(stdout(&output).contains("1 failed (test during command execution)"));
+    assert_eq!(
+        fs::read_to_string(project.path("report.txt")).unwrap(),
+        "1 test failed\n",
+    );
+
+    // The container is deleted even though the task failed, and nothing is committed.
+    let commands = project.commands();
+    assert_eq!(commands.last().unwrap(), "container rm");
+    assert!(!commands.contains(&"container commit".to_owned()));
+}
+
+#[test]
+fn copy_into_container_failure() {
+    let project = Project::new(
+        r"
+image: busybox
+tasks:
+  build:
+    input_paths:
+      - src
+    command: make
+",
+        r"
+[version --format *]
+stdout amd64
+
+[image inspect *]
+stderr Error: No such image
+exit 1
+
+[image inspect busybox]
+
+[container create *]
+stdout f3a1
+
+[container cp - *]
+stderr Error response from daemon: not a directory
+exit 1
+",
+    );
+    fs::create_dir(project.path("src")).unwrap();
+    fs::write(project.path("src/main.c"), "int main() {}\n").unwrap();
+
+    let output = project.run(&[]);
+
+    // The error says which entries were being copied and suggests the fallback.
+    assert_eq!(output.status.code(), Some(SYSTEM_EXIT_CODE));
+    let stderr = stderr(&output);
+    assert!(stderr.contains("not a directory"), "{stderr}");
+    assert!(
+        stderr.contains("- `scratch` (directory)\n- `scratch/src` (directory)"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("- `scratch/src/main.c` (file)"), "{stderr}");
+    assert!(stderr.contains("copy_via_exec: true"), "{stderr}");
+    assert!(!project.commands().contains(&"container start".to_owned()));
+}
+
+#[test]
+fn copy_via_exec() {
+    let project = Project::new(
+        r"
+image: busybox
+tasks:
+  build:
+    input_paths:
+      - src
+    output_paths:
+      - out
+    command: make
+",
+        r"
+[version --format *]
+stdout amd64
+
+[image inspect --format {{.Size}} *]
+stdout 1000
+
+[image inspect busybox]
+
+[image inspect *]
+stderr Error: No such image
+exit 1
+
+[container create *]
+stdout f3a1
+
+[container cp f3a1:/scratch/out *]
+copy out-in-container
+",
+    );
+    fs::create_dir(project.path("src")).unwrap();
+    fs::write(project.path("src/main.c"), "int main() {}\n").unwrap();
+    fs::write(project.path("out-in-container"), "built\n").unwrap();
+    let toastfile = fs::read_to_string(project.path("toast.yml")).unwrap();
+    fs::write(
+        project.path("toast.yml"),
+        toastfile.replace("command: make", "copy_via_exec: true\n    command: make"),
+    )
+    .unwrap();
+
+    let output = project.run(&[]);
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        project.commands(),
+        vec![
+            "version --format",
+            "image inspect",
+            "image inspect",
+            "container create",
+            "container start",
+            "container exec",
+            "container exec",
+            "container stop",
+            "container cp",
+            "container commit",
+            "container rm",
+        ],
+    );
+
+    // The container just stays up, and the input files are piped to `tar` in it.
+    let calls = project.calls();
+    assert!(calls[3].ends_with(&[
+        "busybox".to_owned(),
+        "/bin/su".to_owned(),
+        "-c".to_owned(),
+        "while true; do sleep 3600; done".to_owned(),
+        "root".to_owned(),
+    ]));
+    assert_eq!(calls[4], vec!["container", "start", "f3a1"]);
+    assert_eq!(
+        calls[5],
+        vec![
+            "container",
+            "exec",
+            "--interactive",
+            "--user",
+            "root",
+            "f3a1",
+            "tar",
+            "-x",
+            "-f",
+            "-",
+            "-C",
+            "/",
+        ],
+    );
+
+    // Then the command runs via `docker exec`, and the container is stopped before it's committed.
+    assert!(calls[6].ends_with(&[
+        "f3a1".to_owned(),
+        "/bin/su".to_owned(),
+        "-c".to_owned(),
+        "make".to_owned(),
+        "root".to_owned(),
+    ]));
+    assert_eq!(calls[7], vec!["container", "stop", "f3a1"]);
+    assert_eq!(fs::read_to_string(project.path("out")).unwrap(), "built\n");
+}
+
+#[test]
+fn environment_overrides() {
+    let project = Project::new(
+        r"
+image: alpine
+tasks:
+  build:
+    environment:
+      PROFILE: debug
+    command: make
+",
+        r"
+[version --format *]
+stdout amd64
+",
+    );
+
+    // A value given on the command line is part of the cache key, like one from the environment.
+    let output = project.run(&[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = project.run(&["--env", "PROFILE=release"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let images = project
+        .calls()
+        .into_iter()
+        .filter(|call| call[..2] == ["image", "inspect"])
+        .map(|call| call[2].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(images.len(), 2);
+    assert_ne!(images[0], images[1]);
+
+    // Giving the default value explicitly arrives back at the first cache key.
+    let output = project.run(&["--env", "PROFILE=debug"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Everything up to date."));
+
+    // A variable which no task uses is probably a mistake.
+    let output = project.run(&["--env", "PROFLE=release"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("None of the tasks use the environment variable `PROFLE`"));
+
+    let output = project.run(&["--env", "=release"]);
+    assert_eq!(output.status.code(), Some(USER_EXIT_CODE));
+    assert!(stderr(&output).contains("Invalid environment variable `=release`."));
+}
+
+#[test]
+fn env_file() {
+    let project = Project::new(
+        r"
+image: alpine
+env_file: .env
+tasks:
+  build:
+    environment:
+      REGION: us-east-1
+    command: make
+",
+        r"
+[version --format *]
+stdout amd64
+",
+    );
+
+    // A file which is referenced explicitly must exist.
+    let output = project.run(&[]);
+    assert_eq!(output.status.code(), Some(USER_EXIT_CODE));
+    assert!(stderr(&output).contains("Unable to read the `env_file` of the toastfile: `.env`."));
+
+    // The values from the file are part of the cache key, like the ones from the environment.
+    fs::write(project.path(".env"), "# Deployment\n\nREGION=us-west-2\n").unwrap();
+    let output = project.run(&[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    fs::write(project.path(".env"), "REGION=eu-west-1\n").unwrap();
+    let output = project.run(&[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let images = project
+        .calls()
+        .into_iter()
+        .filter(|call| call[..2] == ["image", "inspect"])
+        .map(|call| call[2].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(images.len(), 2);
+    assert_ne!(images[0], images[1]);
+
+    // A value from the command line takes precedence over the file, so this arrives back at the
+    // first cache key.
+    let output = project.run(&["--env", "REGION=us-west-2"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Everything up to date."));
+
+    // A one-off command gets the values from the top-level file too.
+    fs::write(
+        project.path("toast.yml"),
+        "image: alpine\nenv_file: .env\nenvironment:\n  REGION: null\n",
+    )
+    .unwrap();
+    let output = project.run(&["run", "--", "env"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(project
+        .calls()
+        .iter()
+        .any(|call| call[..2] == ["container", "create"]
+            && call.contains(&"REGION=eu-west-1".to_owned())));
+}
+
+#[test]
+fn secret_variables() {
+    let project = Project::new(
+        r"
+image: alpine
+tasks:
+  deploy:
+    environment:
+      API_KEY: null
+    secret_variables:
+      - API_KEY
+    cache: false
+    command: deploy
+",
+        r"
+[version --format *]
+stdout amd64
+
+[container create *]
+stderr invalid reference format: s3cr3t-key
+exit 1
+",
+    );
+
+    let output = project.run(&["--env", "API_KEY=s3cr3t-key"]);
+    assert_eq!(output.status.code(), Some(SYSTEM_EXIT_CODE));
+
+    // The value reaches Docker, but it's masked in the error.
+    assert!(project
+        .calls()
+        .iter()
+        .any(|call| call[..2] == ["container", "create"]
+            && call.contains(&"API_KEY=s3cr3t-key".to_owned())));
+    assert!(stderr(&output).contains("invalid reference format: ********"));
+    assert!(!stderr(&output).contains("s3cr3t-key"));
+}
+
+#[test]
+fn secrets() {
+    let project = Project::new(
+        r"
+image: alpine
+tasks:
+  publish:
+    secrets:
+      npm_token:
+        file: .npmrc
+      api_key:
+        env: API_KEY
+    command: npm publish
+",
+        r"
+[version --format *]
+stdout amd64
+
+[image inspect alpine]
+
+[image inspect *]
+stderr Error: No such image
+exit 1
+
+[container create *]
+stdout f3a1
+",
+    );
+    fs::write(
+        project.path(".npmrc"),
+        "//registry.npmjs.org/:_authToken=abc123\n",
+    )
+    .unwrap();
+
+    // Each secret is mounted read-only from a file, so it isn't committed with the container.
+    let output = project
+        .command(&[])
+        .env("API_KEY", "first-key")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let calls = project.calls();
+    let create = calls
+        .iter()
+        .find(|call| call[..2] == ["container", "create"])
+        .unwrap();
+    for name in ["api_key", "npm_token"] {
+        assert!(create.iter().any(|arg| arg.starts_with("type=bind,source=")
+            && arg.ends_with(&format!("/{name},target=/run/secrets/{name},readonly"))));
+    }
+    assert!(!create.iter().any(|arg| arg.contains("first-key")));
+    assert!(calls
+        .iter()
+        .any(|call| call[..2] == ["container", "commit"]));
+
+    // The secrets aren't part of the cache key, so changing one doesn't cause the task to run
+    // again.
+    let output = project
+        .command(&[])
+        .env("API_KEY", "second-key")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Everything up to date."));
+
+    // A missing secret is reported before the task runs.
+    let output = project.run(&["--force-all"]);
+    assert_eq!(output.status.code(), Some(USER_EXIT_CODE));
+    assert!(stderr(&output)
+        .contains("Unable to read secret `api_key` from environment variable `API_KEY`."));
+}
+
+#[test]
+fn shell_image() {
+    let project = Project::new(
+        r"
+image: alpine
+tasks:
+  serve:
+    cache: false
+    mount_paths:
+      - logs
+    ports:
+      - 3000
+    command: serve
+",
+        r"
+[version --format *]
+stdout amd64
+
+[container create *]
+stdout f3a1
+",
+    );
+    let shell_call = |project: &Project| {
+        project
+            .calls()
+            .into_iter()
+            .find(|call| call[..2] == ["container", "run"])
+            .unwrap()
+    };
+
+    // By default, the shell runs in the image from the last task.
+    let output = project.run(&["--shell"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let default_call = shell_call(&project);
+    assert!(default_call.iter().any(|arg| arg.starts_with("toast:")));
+    fs::remove_file(project.path("docker.log")).unwrap();
+
+    // With `--shell-image`, the image is pulled if necessary and everything else stays the same.
+    let output = project.run(&["--shell", "--shell-image", "nicolaka/netshoot"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("The shell runs in `nicolaka/netshoot`"));
+    assert!(project.calls().contains(&vec![
+        "image".to_owned(),
+        "inspect".to_owned(),
+        "nicolaka/netshoot".to_owned()
+    ]));
+    assert_eq!(
+        shell_call(&project),
+        default_call
+            .iter()
+            .map(|arg| if arg.starts_with("toast:") {
+                "nicolaka/netshoot".to_owned()
+            } else {
+                arg.clone()
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    // The image must be a valid reference.
+    let output = project.run(&["--shell", "--shell-image", "Netshoot"]);
+    assert_eq!(output.status.code(), Some(USER_EXIT_CODE));
+    assert!(stderr(&output).contains("Invalid image `Netshoot`"));
+}
+
+#[test]
+fn force_all() {
+    let project = Project::new(
+        r"
+image: alpine
+tasks:
+  build:
+    command: make
+",
+        r"
+[version --format *]
+stdout amd64
+
+[container create *]
+stdout f3a1
+",
+    );
+    let cache_reads = |project: &Project| {
+        project
+            .calls()
+            .iter()
+            .filter(|call| call[..2] == ["image", "inspect"] && call[2].starts_with("toast:"))
+            .count()
+    };
+
+    // Nothing is read from or written to the cache.
+    let output = project.run(&["--force-all"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Caching is disabled for this run because of `--force-all`."));
+    assert_eq!(cache_reads(&project), 0);
+    assert!(!project.commands().contains(&"container commit".to_owned()));
+    fs::remove_file(project.path("docker.log")).unwrap();
+
+    // Writing to the cache can be enabled explicitly, but it's still not read.
+    let output = project.run(&["--force-all", "--write-local-cache", "true"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Reading from the cache is disabled for this run"));
+    assert_eq!(cache_reads(&project), 0);
+    assert!(project
+        .calls()
+        .iter()
+        .any(|call| call[..2] == ["container", "commit"] && call[3].starts_with("toast:")));
+}
+
+#[test]
+fn no_deps() {
+    let project = Project::new(
+        TWO_TASKS,
+        r"
+[version --format *]
+stdout amd64
+
+[container create *]
+stdout f3a1
+",
+    );
+
+    // The dependency isn't run, and the task starts from the image it would have produced.
+    let output = project.run(&["--no-deps", "--force", "test", "test"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Skipping 1 task because of `--no-deps`: `build`."));
+    let calls = project.calls();
+    assert_eq!(calls[1][..2], ["image", "inspect"]);
+    let build_image = &calls[1][2];
+    assert!(build_image.starts_with("toast:toast-"));
+    let creates = calls
+        .iter()
+        .filter(|call| call[..2] == ["container", "create"])
+        .collect::<Vec<_>>();
+    assert_eq!(creates.len(), 1);
+    assert!(creates[0].contains(build_image));
+    assert!(!stderr(&output).contains("Running task `build`"));
+}
+
+#[test]
+fn no_deps_missing_image() {
+    let project = Project::new(
+        TWO_TASKS,
+        r"
+[version --format *]
+stdout amd64
+
+[image inspect *]
+stderr Error: No such image
+exit 1
+",
+    );
+
+    let output = project.run(&["--no-deps", "test"]);
+
+    assert_eq!(output.status.code(), Some(USER_EXIT_CODE));
+    assert!(stderr(&output).contains(
+        "Unable to run task `test` without its dependencies, since the image task `build` would \
+         produce"
+    ));
+    assert!(stderr(&output).contains("Run `toast build` first"));
+    assert!(!project.commands().contains(&"container create".to_owned()));
+}
+
+#[test]
+fn time_limit() {
+    // The command takes longer than the time limit, and it's killed once the container is stopped.
+    let project = Project::new(
+        r"
+image: alpine
+tasks:
+  build:
+    cache: false
+    command: make
+",
+        r"
+[version --format *]
+stdout amd64
+
+[container create *]
+stdout f3a1
+
+[container start *]
+delay 3
+exit 137
+",
+    );
+
+    let output = project.run(&["--time-limit", "1s"]);
+
+    assert_eq!(output.status.code(), Some(USER_EXIT_CODE));
+    assert!(stderr(&output).contains("left before the time limit."));
+    assert!(stderr(&output).contains("The time limit of 1s ran out."));
+    assert!(stderr(&output).contains("The time limit of 1s given by `--time-limit` was exceeded."));
+
+    // The container is stopped by the watchdog and then cleaned up as usual.
+    let commands = project.commands();
+    assert!(commands.contains(&"container stop".to_owned()));
+    assert_eq!(commands.last().unwrap(), "container rm");
+}
+
+#[test]
+fn missing_input_paths() {
+    let project = Project::new(
+        r"
+image: alpine
+tasks:
+  generate:
+    input_paths:
+      - schema.yml
+    output_paths:
+      - gen
+    command: generate
+  build:
+    dependencies:
+      - generate
+    input_paths:
+      - gen/schema.rs
+      - src
+      - Makefile
+    excluded_input_paths:
+      - target
+    command: make
+",
+        r"
+[version --format *]
+stdout amd64
+",
+    );
+
+    // All the missing paths are reported before anything runs, except the ones a dependency
+    // produces.
+    let output = project.run(&[]);
+    assert_eq!(output.status.code(), Some(USER_EXIT_CODE));
+    assert!(stderr(&output).contains(
+        "Some input paths don't exist:\n\
+         - Task `generate`: `schema.yml`\n\
+         - Task `build`: `src`, `Makefile`, and `target`\n\
+         If they're created some other way, use `--allow-missing-inputs`.",
+    ));
+    assert!(project.calls().is_empty());
+
+    // The run still ends with a summary, which says that no task started.
+    assert!(stdout(&output).starts_with("toast: 2 tasks, failed before any task started, "));
+
+    // The check can be skipped, so the task fails when it tries to read the files instead.
+    let output = project.run(&["--allow-missing-inputs"]);
+    assert!(!output.status.success());
+    assert!(!stderr(&output).contains("don't exist"));
+    assert!(stderr(&output).contains("schema.yml"));
+}
+
+#[test]
+fn progress_events() {
+    let project = Project::new(
+        TWO_TASKS,
+        r"
+[version --format *]
+stdout amd64
+
+[image inspect --format {{.Size}} *]
+stdout 1000
+
+[image inspect alpine]
+
+[image inspect *]
+stderr Error: No such image
+exit 1
+
+[container create *]
+stdout f3a1
+
+[container start *]
+delay 1
+",
+    );
+
+    // The events are written to a pipe (standard output, in this case) while Toast runs.
+    let mut child = project
+        .command(&["--progress-fd", "1", "build"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let mut events = vec![];
+    for line in &mut lines {
+        let line = line.unwrap();
+        let done = line.contains("\"phase_started\"") && line.contains("\"phase\":\"run\"");
+        events.push(line);
+        if done {
+            break;
+        }
+    }
+    assert!(child.try_wait().unwrap().is_none());
+    events.extend(lines.map(Result::unwrap));
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    // Leave out the times and durations, since they vary.
+    let events = events
+        .iter()
+        .map(|event| {
+            let fields = event
+                .strip_prefix('{')
+                .and_then(|event| event.strip_suffix('}'))
+                .unwrap();
+            let fields = fields
+                .split(',')
+                .filter(|field| {
+                    !field.starts_with("\"time\":") && !field.starts_with("\"duration_ms\":")
+                })
+                .collect::<Vec<_>>();
+            format!("{{{}}}", fields.join(","))
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        events,
+        vec![
+            r#"{"event":"task_started","task":"build"}"#,
+            r#"{"event":"phase_started","task":"build","phase":"hash"}"#,
+            r#"{"event":"phase_finished","task":"build","phase":"hash"}"#,
+            r#"{"event":"phase_started","task":"build","phase":"cache"}"#,
+            r#"{"event":"phase_finished","task":"build","phase":"cache"}"#,
+            r#"{"event":"phase_started","task":"build","phase":"pull"}"#,
+            r#"{"event":"phase_finished","task":"build","phase":"pull"}"#,
+            r#"{"event":"phase_started","task":"build","phase":"create"}"#,
+            r#"{"event":"phase_finished","task":"build","phase":"create"}"#,
+            r#"{"event":"phase_started","task":"build","phase":"run"}"#,
+            r#"{"event":"phase_finished","task":"build","phase":"run"}"#,
+            r#"{"event":"phase_started","task":"build","phase":"copy-out"}"#,
+            r#"{"event":"phase_finished","task":"build","phase":"copy-out"}"#,
+            r#"{"event":"phase_started","task":"build","phase":"commit"}"#,
+            r#"{"event":"phase_finished","task":"build","phase":"commit"}"#,
+            r#"{"event":"task_finished","task":"build","success":true}"#,
+            r#"{"event":"run_finished","success":true}"#,
+        ],
+    );
+
+    // The human-readable logs are unaffected.
+    assert!(stderr(&output).contains("Running task `build`"));
+}
+
+#[test]
+fn only_cached_divergence() {
+    // None of the images exist, so the cache keys diverge at the first task.
+    let project = Project::new(
+        TWO_TASKS,
+        r"
+[version --format *]
+stdout amd64
+
+[image inspect *]
+stderr Error: No such image
+exit 1
+",
+    );
+
+    let output = project.run(&["--only-cached"]);
+
+    assert_eq!(output.status.code(), Some(1_i32));
+    assert!(stderr(&output).contains("2 tasks would run"));
+    assert!(stderr(&output).contains("`build` (the cache keys diverge here"));
+
+    // The final image is checked first, and nothing is created.
+    assert_eq!(
+        project.commands(),
+        vec!["version --format", "image inspect", "image inspect"],
+    );
+    let calls = project.calls();
+    assert_ne!(calls[1][2], calls[2][2]);
+}
+
+#[test]
+fn daemon_unreachable() {
+    let project = Project::new(
+        TWO_TASKS,
+        r"
+[*]
+stderr Cannot connect to the Docker daemon at unix:///var/run/docker.sock.
+exit 1
+",
+    );
+
+    let output = project.run(&[]);
+
+    assert_eq!(output.status.code(), Some(SYSTEM_EXIT_CODE));
+    assert!(stderr(&output).contains("Cannot connect to the Docker daemon"));
+
+    // Toast gives up before running anything.
+    assert_eq!(project.commands(), vec!["version --format"]);
+}
+
+#[test]
+fn command_ambiguous_with_tasks() {
+    let project = Project::new(
+        r"
+image: alpine
+tasks:
+  prune:
+    command: make prune
+  cache:
+    command: make cache
+  report:
+    command: make report
+",
+        "",
+    );
+
+    // The commands aren't mistaken for the tasks, or vice versa.
+    let output = project.run(&["prune"]);
+    assert_eq!(output.status.code(), Some(USER_EXIT_CODE));
+    assert!(stderr(&output).contains("`toast prune` is ambiguous"));
+    assert!(
+        stderr(&output).contains("has a task named `prune`. To run it, use `toast prune prune`.")
+    );
+    let output = project.run(&["cache", "report"]);
+    assert_eq!(output.status.code(), Some(USER_EXIT_CODE));
+    assert!(stderr(&output).contains("`toast cache report` is ambiguous"));
+    assert!(stderr(&output)
+        .contains("has tasks named `cache` and `report`. To run them, use `toast report cache`.",));
+    assert!(project.calls().is_empty());
+}